@@ -0,0 +1,53 @@
+//! Client for the server's HTTP control API
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::time::Duration;
+
+/// How long one request may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The control API at one server
+pub struct ApiClient {
+    base: String,
+    http: reqwest::Client,
+}
+
+impl ApiClient {
+    /// `base` is the API's root URL, e.g. `http://127.0.0.1:8090`
+    pub fn new(base: &str) -> Result<Self> {
+        let http = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self {
+            base: base.trim_end_matches('/').to_string(),
+            http,
+        })
+    }
+
+    pub async fn get(&self, path: &str) -> Result<Value> {
+        let response = self.http.get(self.url(path)).send().await?;
+        Self::answer(response).await
+    }
+
+    pub async fn post(&self, path: &str, body: &Value) -> Result<Value> {
+        let response = self.http.post(self.url(path)).json(body).send().await?;
+        Self::answer(response).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    /// The JSON body of a successful response, or the server's `error`
+    async fn answer(response: reqwest::Response) -> Result<Value> {
+        let status = response.status();
+        let text = response.text().await?;
+        if status.is_success() {
+            return Ok(serde_json::from_str(&text)?);
+        }
+        let body: Value = serde_json::from_str(&text).unwrap_or_default();
+        match body["error"].as_str() {
+            Some(error) => Err(anyhow!("{}: {}", status, error)),
+            None => Err(anyhow!("{}", status)),
+        }
+    }
+}
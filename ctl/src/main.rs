@@ -0,0 +1,363 @@
+//! ResQTerra operator CLI
+//!
+//! Drives the fleet through the server's HTTP control API, so field operators
+//! can list drones, read telemetry and send commands without scripts of their
+//! own. Commands go through the server's dispatcher like any other; with
+//! `--wait` the CLI follows one until the drone reports its outcome.
+//!
+//! The server is `RESQTERRA_CTL_SERVER` (default `http://127.0.0.1:8090`, the
+//! API's default listen address), or `--server` on the command line.
+
+mod client;
+
+use anyhow::{anyhow, bail, Context, Result};
+use client::ApiClient;
+use resqterra_shared::mission::MissionPlan;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// The control API unless `RESQTERRA_CTL_SERVER` or `--server` say otherwise
+const DEFAULT_SERVER: &str = "http://127.0.0.1:8090";
+
+/// How often `--wait` asks for a command's status
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long `--wait` follows a command before giving up
+const WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+
+const USAGE: &str = "\
+Usage: resqterra-ctl [--server URL] <command>
+
+Commands:
+  list                                  Connected drones and relays
+  telemetry <id>                        A drone's latest telemetry
+  rth <id> [--altitude M] [--speed M/S] Return to home
+  estop <id>                            Emergency stop
+  mission start <id> --file FILE        Start the mission in FILE (YAML or JSON: mission_id,
+                                        pattern, altitude_m, boundary, geofence, rth, ...)
+  status <command_id>                   A command's progress and outcome
+  cancel <command_id>                   Drop a queued command or abort one in progress
+
+Commands sent to a drone take --wait to follow them until the drone is done.
+The server is RESQTERRA_CTL_SERVER (default http://127.0.0.1:8090).";
+
+/// What to do
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Help,
+    List,
+    Telemetry { device_id: String },
+    Send {
+        device_id: String,
+        command: CommandSpec,
+        wait: bool,
+    },
+    Status { command_id: u64 },
+    Cancel { command_id: u64 },
+}
+
+/// A command for a drone, as given on the command line
+#[derive(Debug, Clone, PartialEq)]
+enum CommandSpec {
+    ReturnToHome {
+        altitude_m: Option<f32>,
+        speed_mps: Option<f32>,
+    },
+    EmergencyStop,
+    /// The mission is read from the file and validated when sent
+    MissionStart { file: PathBuf },
+}
+
+impl CommandSpec {
+    /// The body of `POST /drones/{id}/commands`
+    fn request(&self) -> Result<Value> {
+        let request = match self {
+            CommandSpec::ReturnToHome {
+                altitude_m,
+                speed_mps,
+            } => json!({
+                "type": "return_to_home",
+                "altitude_m": altitude_m.unwrap_or_default(),
+                "speed_mps": speed_mps.unwrap_or_default(),
+            }),
+            CommandSpec::EmergencyStop => json!({ "type": "emergency_stop" }),
+            CommandSpec::MissionStart { file } => {
+                let plan = MissionPlan::load(file).with_context(|| file.display().to_string())?;
+                let mut mission = serde_json::to_value(plan)?;
+                mission["type"] = "mission_start".into();
+                mission
+            }
+        };
+        Ok(request)
+    }
+}
+
+/// The server URL and action from the command line
+fn parse_args(args: &[String], default_server: &str) -> Result<(String, Action)> {
+    let mut server = default_server.to_string();
+    let mut words = Vec::new();
+    let mut flags = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--server" => server = value_of(arg, args.next())?.to_string(),
+            "--altitude" | "--speed" | "--file" => {
+                flags.push((arg.as_str(), Some(value_of(arg, args.next())?)));
+            }
+            "--wait" => flags.push((arg.as_str(), None)),
+            "-h" | "--help" => return Ok((server, Action::Help)),
+            flag if flag.starts_with("--") => bail!("Unknown option {}", flag),
+            word => words.push(word),
+        }
+    }
+
+    let flag = |name: &str| flags.iter().find(|(flag, _)| *flag == name).map(|(_, v)| *v);
+    let number = |name: &str| -> Result<Option<f32>> {
+        flag(name)
+            .flatten()
+            .map(|v| v.parse().map_err(|_| anyhow!("{} takes a number, not {:?}", name, v)))
+            .transpose()
+    };
+    let wait = flag("--wait").is_some();
+    let send = |device_id: &str, command| Action::Send {
+        device_id: device_id.to_string(),
+        command,
+        wait,
+    };
+
+    let action = match words.as_slice() {
+        [] | ["help"] => Action::Help,
+        ["list"] => Action::List,
+        ["telemetry", id] => Action::Telemetry {
+            device_id: id.to_string(),
+        },
+        ["rth", id] => send(
+            id,
+            CommandSpec::ReturnToHome {
+                altitude_m: number("--altitude")?,
+                speed_mps: number("--speed")?,
+            },
+        ),
+        ["estop", id] => send(id, CommandSpec::EmergencyStop),
+        ["mission", "start", id] => {
+            let Some(file) = flag("--file").flatten() else {
+                bail!("mission start needs --file");
+            };
+            send(id, CommandSpec::MissionStart { file: file.into() })
+        }
+        ["status", id] => Action::Status {
+            command_id: id.parse().map_err(|_| anyhow!("Bad command ID {:?}", id))?,
+        },
+        ["cancel", id] => Action::Cancel {
+            command_id: id.parse().map_err(|_| anyhow!("Bad command ID {:?}", id))?,
+        },
+        _ => bail!("Unknown command: {}\n\n{}", words.join(" "), USAGE),
+    };
+    Ok((server, action))
+}
+
+fn value_of<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a str> {
+    value.map(String::as_str).ok_or_else(|| anyhow!("{} needs a value", flag))
+}
+
+/// `prefix`-less proto enum name, e.g. `IN_MISSION` for `DRONE_IN_MISSION`
+fn short_name<'a>(value: &'a Value, prefix: &str) -> &'a str {
+    let name = value.as_str().unwrap_or("?");
+    name.strip_prefix(prefix).unwrap_or(name)
+}
+
+/// `GET /drones` as a table
+fn format_drones(drones: &Value) -> String {
+    let Some(drones) = drones.as_array().filter(|d| !d.is_empty()) else {
+        return "No drones connected".into();
+    };
+    let mut table = format!(
+        "{:<16} {:<6} {:<16} {:<10} {:>10} {:>8}",
+        "DEVICE", "TYPE", "STATE", "LINK", "HEARTBEAT", "PENDING"
+    );
+    for drone in drones {
+        let heartbeat = drone["last_heartbeat_ms_ago"].as_u64().unwrap_or_default();
+        table.push_str(&format!(
+            "\n{:<16} {:<6} {:<16} {:<10} {:>9.1}s {:>8}",
+            drone["device_id"].as_str().unwrap_or("?"),
+            short_name(&drone["device_type"], "DEVICE_"),
+            short_name(&drone["state"], "DRONE_"),
+            short_name(&drone["transport"], "TRANSPORT_"),
+            heartbeat as f64 / 1000.0,
+            drone["pending_commands"],
+        ));
+    }
+    table
+}
+
+/// Whether a command status from `GET /commands/{id}` is final
+fn is_finished(status: &Value) -> bool {
+    !matches!(status["status"].as_str(), Some("QUEUED" | "SENT"))
+}
+
+/// Follow a command until it finishes; an error unless it completed
+async fn wait_for(api: &ApiClient, command_id: u64) -> Result<()> {
+    let started = Instant::now();
+    let mut last = String::new();
+    loop {
+        let status = api.get(&format!("/commands/{}", command_id)).await?;
+        let name = status["status"].as_str().unwrap_or("?").to_string();
+        if name != last {
+            println!("Command {}: {}", command_id, name);
+            last = name;
+        }
+        if is_finished(&status) {
+            return match status["status"].as_str() {
+                Some("COMPLETED") => Ok(()),
+                _ => match status["message"].as_str().filter(|m| !m.is_empty()) {
+                    Some(message) => Err(anyhow!("Command {} {}: {}", command_id, last, message)),
+                    None => Err(anyhow!("Command {} {}", command_id, last)),
+                },
+            };
+        }
+        if started.elapsed() > WAIT_TIMEOUT {
+            bail!("Command {} still {} after {:?}", command_id, last, WAIT_TIMEOUT);
+        }
+        tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+    }
+}
+
+async fn run(api: &ApiClient, action: Action) -> Result<()> {
+    match action {
+        Action::Help => println!("{}", USAGE),
+        Action::List => println!("{}", format_drones(&api.get("/drones").await?)),
+        Action::Telemetry { device_id } => {
+            let telemetry = api.get(&format!("/drones/{}/telemetry", device_id)).await?;
+            println!("{}", serde_json::to_string_pretty(&telemetry)?);
+        }
+        Action::Status { command_id } => {
+            let status = api.get(&format!("/commands/{}", command_id)).await?;
+            println!("{}", serde_json::to_string_pretty(&status)?);
+        }
+        Action::Cancel { command_id } => {
+            let sent = api.post(&format!("/commands/{}/cancel", command_id), &json!({})).await?;
+            match sent["command_id"].as_u64() {
+                Some(id) if id == command_id => println!("Command {} dropped from the queue", id),
+                Some(id) => println!("Cancel sent as command {}", id),
+                None => bail!("Server sent no command ID"),
+            }
+        }
+        Action::Send {
+            device_id,
+            command,
+            wait,
+        } => {
+            let path = format!("/drones/{}/commands", device_id);
+            let sent = api.post(&path, &command.request()?).await?;
+            let command_id = sent["command_id"]
+                .as_u64()
+                .ok_or_else(|| anyhow!("Server sent no command ID"))?;
+            println!("Command {} sent to {}", command_id, device_id);
+            if let Some(uploads) = sent["upload_command_ids"].as_array().filter(|u| !u.is_empty()) {
+                println!("Waypoints uploading in {} chunks", uploads.len());
+            }
+            if wait {
+                wait_for(api, command_id).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let default_server =
+        std::env::var("RESQTERRA_CTL_SERVER").unwrap_or_else(|_| DEFAULT_SERVER.into());
+    let (server, action) = parse_args(&args, &default_server)?;
+    let api = ApiClient::new(&server)?;
+    run(&api, action).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<(String, Action)> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        parse_args(&args, DEFAULT_SERVER)
+    }
+
+    #[test]
+    fn test_commands_parse() {
+        assert_eq!(parse("").unwrap().1, Action::Help);
+        assert_eq!(parse("list").unwrap(), (DEFAULT_SERVER.into(), Action::List));
+        let (server, action) = parse("--server http://gcs:8090 rth edge-001 --altitude 50 --wait")
+            .unwrap();
+        assert_eq!(server, "http://gcs:8090");
+        assert_eq!(
+            action,
+            Action::Send {
+                device_id: "edge-001".into(),
+                command: CommandSpec::ReturnToHome {
+                    altitude_m: Some(50.0),
+                    speed_mps: None,
+                },
+                wait: true,
+            }
+        );
+        let (_, mission) = parse("mission start edge-002 --file m1.json").unwrap();
+        assert_eq!(
+            mission,
+            Action::Send {
+                device_id: "edge-002".into(),
+                command: CommandSpec::MissionStart {
+                    file: "m1.json".into()
+                },
+                wait: false,
+            }
+        );
+        assert_eq!(parse("status 42").unwrap().1, Action::Status { command_id: 42 });
+        assert_eq!(parse("cancel 42").unwrap().1, Action::Cancel { command_id: 42 });
+
+        assert!(parse("mission start edge-002").is_err());
+        assert!(parse("rth edge-001 --altitude high").is_err());
+        assert!(parse("status latest").is_err());
+        assert!(parse("land edge-001").is_err());
+        assert!(parse("list --server").is_err());
+    }
+
+    #[test]
+    fn test_mission_file_becomes_mission_start() {
+        let path = std::env::temp_dir().join(format!("resqterra-ctl-{}.json", std::process::id()));
+        let mission = r#"{"mission_id": "m1", "pattern": "custom", "altitude_m": 40,
+            "waypoints": [{"latitude": 47.5, "longitude": 8.5}]}"#;
+        std::fs::write(&path, mission).unwrap();
+        let request = CommandSpec::MissionStart { file: path.clone() }.request().unwrap();
+        assert_eq!(request["type"], "mission_start");
+        assert_eq!(request["mission_id"], "m1");
+        assert_eq!(request["waypoints"][0]["latitude"], 47.5);
+
+        std::fs::write(&path, "[]").unwrap();
+        assert!(CommandSpec::MissionStart { file: path.clone() }.request().is_err());
+        // Checked before anything is sent
+        std::fs::write(&path, mission.replace("40", "400")).unwrap();
+        assert!(CommandSpec::MissionStart { file: path.clone() }.request().is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_drone_table() {
+        assert_eq!(format_drones(&json!([])), "No drones connected");
+        let drones = json!([{
+            "device_id": "edge-001",
+            "device_type": "DEVICE_DRONE",
+            "state": "DRONE_IN_MISSION",
+            "transport": "TRANSPORT_5G",
+            "last_heartbeat_ms_ago": 1500,
+            "pending_commands": 2,
+        }]);
+        let table = format_drones(&drones);
+        let row = table.lines().nth(1).unwrap();
+        let columns: Vec<_> = row.split_whitespace().collect();
+        assert_eq!(columns, ["edge-001", "DRONE", "IN_MISSION", "5G", "1.5s", "2"]);
+        assert!(is_finished(&json!({ "status": "COMPLETED" })));
+        assert!(!is_finished(&json!({ "status": "SENT" })));
+    }
+}
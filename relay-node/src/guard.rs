@@ -0,0 +1,215 @@
+//! Checks on what each edge client sends upstream
+//!
+//! A relay's Bluetooth side is open to anything in radio range, so each
+//! plaintext link is checked frame by frame before anything reaches the
+//! server:
+//!
+//! - **Length**: a length prefix over `max_frame_len` drops the link as soon
+//!   as it arrives, before the frame is buffered.
+//! - **Decoding**: frames that don't decode, or carry no header, are dropped.
+//! - **Device ID**: a device outside `allowed_devices`, or a link switching
+//!   device IDs after its first frame, drops the link.
+//! - **Rate**: each link has token buckets for frames and bytes. Past its
+//!   burst, the relay stops reading the link until tokens refill, so a
+//!   flooding peer is held back by its own Bluetooth flow control rather than
+//!   buffered, and a drone replaying its outbox is slowed down, not cut off.
+//!
+//! Noise links can't be read: only their bytes are rate limited.
+
+use resqterra_shared::codec::{self, FrameDecoder};
+use resqterra_shared::Envelope;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// How many seconds of traffic a link may send at once
+const BURST_SECS: f64 = 4.0;
+
+/// What edge clients may send
+#[derive(Debug, Clone)]
+pub struct GuardConfig {
+    /// Longest frame body accepted from an edge
+    pub max_frame_len: u32,
+    /// Device IDs allowed through (None = any); include neighbouring relays'
+    /// `RELAY_ID`s when relays chain
+    pub allowed_devices: Option<HashSet<String>>,
+    /// Sustained frames per second per link
+    pub frames_per_sec: u32,
+    /// Sustained bytes per second per link
+    pub bytes_per_sec: u32,
+}
+
+impl Default for GuardConfig {
+    fn default() -> Self {
+        Self {
+            max_frame_len: 256 * 1024,
+            allowed_devices: None,
+            frames_per_sec: 50,
+            bytes_per_sec: 64 * 1024,
+        }
+    }
+}
+
+impl GuardConfig {
+    /// Parse a comma-separated list of device IDs, e.g. `"edge-001,relay-002"`
+    pub fn parse_devices(list: &str) -> HashSet<String> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// A frame decoder refusing frames over `max_frame_len`
+    pub fn decoder(&self) -> FrameDecoder {
+        FrameDecoder::new().with_max_len(self.max_frame_len)
+    }
+}
+
+/// What to do with a frame from the edge
+#[derive(Debug)]
+pub enum Verdict {
+    /// Forward it
+    Pass(Box<Envelope>),
+    /// Drop the frame, for this reason
+    Drop(String),
+    /// Drop the link, for this reason
+    Close(String),
+}
+
+/// The checks for one edge link
+#[derive(Debug)]
+pub struct Guard {
+    allowed_devices: Option<HashSet<String>>,
+    /// The device ID of the link's first frame
+    device_id: Option<String>,
+    frames: TokenBucket,
+    bytes: TokenBucket,
+}
+
+impl Guard {
+    pub fn new(config: &GuardConfig) -> Self {
+        Self {
+            allowed_devices: config.allowed_devices.clone(),
+            device_id: None,
+            frames: TokenBucket::new(config.frames_per_sec),
+            bytes: TokenBucket::new(config.bytes_per_sec),
+        }
+    }
+
+    /// Decode `frame` and check who sent it
+    pub fn check(&mut self, frame: &[u8]) -> Verdict {
+        let envelope = match codec::decode(&mut frame.into()) {
+            Ok(Some(envelope)) => envelope,
+            Ok(None) => return Verdict::Drop("truncated frame".into()),
+            Err(e) => return Verdict::Drop(e.to_string()),
+        };
+        let Some(device_id) = envelope.header.as_ref().map(|h| h.device_id.as_str()) else {
+            return Verdict::Drop("frame without a header".into());
+        };
+        if let Some(allowed) = &self.allowed_devices {
+            if !allowed.contains(device_id) {
+                return Verdict::Close(format!("device {:?} is not allowed", device_id));
+            }
+        }
+        match &self.device_id {
+            Some(first) if first != device_id => {
+                return Verdict::Close(format!("{} switched device ID to {:?}", first, device_id));
+            }
+            Some(_) => {}
+            None => self.device_id = Some(device_id.to_string()),
+        }
+        Verdict::Pass(Box::new(envelope))
+    }
+
+    /// Take `frames` and `bytes` from the link's buckets, returning how long
+    /// to wait before reading more from it
+    pub fn throttle(&mut self, frames: usize, bytes: usize, now: Instant) -> Duration {
+        let frames = self.frames.take(frames as f64, now);
+        let bytes = self.bytes.take(bytes as f64, now);
+        frames.max(bytes)
+    }
+}
+
+/// Tokens refilling at `rate` per second up to `BURST_SECS` worth
+///
+/// Taking more than is left goes into debt, paid off before the next take
+/// can go through, so a frame bigger than the burst still gets through.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn new(rate: u32) -> Self {
+        let rate = f64::from(rate.max(1));
+        Self {
+            rate,
+            tokens: rate * BURST_SECS,
+            refilled: None,
+        }
+    }
+
+    /// Take `amount` tokens, returning how long until the bucket is out of debt
+    fn take(&mut self, amount: f64, now: Instant) -> Duration {
+        if let Some(refilled) = self.refilled {
+            let elapsed = now.saturating_duration_since(refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.rate * BURST_SECS);
+        }
+        self.refilled = Some(now);
+        self.tokens -= amount;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::{Header, MessageType};
+
+    fn frame(device_id: &str) -> Vec<u8> {
+        let envelope = Envelope {
+            header: Some(Header::new(device_id, MessageType::MsgHeartbeat, 1)),
+            payload: None,
+            signature: Vec::new(),
+        };
+        codec::encode(&envelope).unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_frames_checked_against_allowlist_and_first_device() {
+        let config = GuardConfig {
+            allowed_devices: Some(GuardConfig::parse_devices("edge-001, edge-002,")),
+            ..Default::default()
+        };
+        let mut guard = Guard::new(&config);
+        assert!(matches!(guard.check(&frame("edge-001")), Verdict::Pass(_)));
+        assert!(matches!(guard.check(&[0, 0, 0, 2, 0xff, 0xff]), Verdict::Drop(_)));
+        // Allowed, but not who this link started as
+        assert!(matches!(guard.check(&frame("edge-002")), Verdict::Close(_)));
+        assert!(matches!(Guard::new(&config).check(&frame("spoof")), Verdict::Close(_)));
+    }
+
+    #[test]
+    fn test_throttled_past_the_burst() {
+        let config = GuardConfig {
+            frames_per_sec: 10,
+            ..Default::default()
+        };
+        let mut guard = Guard::new(&config);
+        let start = Instant::now();
+        // 4 s worth of frames go straight through
+        for _ in 0..40 {
+            assert_eq!(guard.throttle(1, 100, start), Duration::ZERO);
+        }
+        assert_eq!(guard.throttle(1, 100, start), Duration::from_millis(100));
+        // Paid off and refilled by one more frame's worth
+        let later = start + Duration::from_millis(200);
+        assert_eq!(guard.throttle(1, 100, later), Duration::ZERO);
+    }
+}
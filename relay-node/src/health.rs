@@ -0,0 +1,318 @@
+//! The relay's own health, reported to the server
+//!
+//! The relay opens a session of its own upstream, along the same routes as
+//! its drones, introduces itself as a relay in its hello and then sends a
+//! heartbeat every `interval` carrying a `RelayStatus`: the drones connected
+//! to it, the bytes held in store-and-forward and each drone's Bluetooth
+//! RSSI. The relay hardware's own battery, when it has one, goes in
+//! `Heartbeat.battery`, and a `StatusUpdate` warns the operator once when it
+//! drops to `BATTERY_WARNING_PERCENT`. The server lists relays alongside its
+//! drones and never sends them commands.
+
+use crate::route::Routes;
+use crate::store::ForwardStore;
+use bluer::Address;
+use resqterra_shared::{
+    codec, envelope, safety, AutonomousAction, BatteryStatus, DroneState, Envelope, Header,
+    Heartbeat, Hello, MessageType, RelayLink, RelayStatus, StatusUpdate,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// Where the kernel lists batteries and chargers
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Pause before reconnecting after the upstream link fails
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Read buffer for the server's replies, which are discarded
+const READ_BUF_LEN: usize = 1024;
+
+/// Who the relay reports as, and how often
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    /// Device ID of the relay's own session
+    pub device_id: String,
+    /// Heartbeat period; keep it well under the server's heartbeat timeout
+    pub interval: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            device_id: "relay-001".into(),
+            interval: Duration::from_millis(safety::HEARTBEAT_INTERVAL_MS),
+        }
+    }
+}
+
+/// Edge links open to this relay
+#[derive(Debug, Default)]
+pub struct Links {
+    next_id: AtomicU64,
+    links: std::sync::Mutex<HashMap<u64, LinkInfo>>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct LinkInfo {
+    /// Empty until the drone's hello
+    device_id: String,
+    /// Bluetooth address, for RFCOMM links
+    bt_address: Option<Address>,
+    rssi_dbm: Option<i32>,
+}
+
+impl Links {
+    /// Count a new edge link until the returned handle is dropped
+    pub fn open(self: &Arc<Self>, bt_address: Option<Address>) -> LinkHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let info = LinkInfo {
+            bt_address,
+            ..Default::default()
+        };
+        self.links.lock().unwrap().insert(id, info);
+        LinkHandle {
+            links: self.clone(),
+            id,
+        }
+    }
+
+    /// Addresses of the Bluetooth links, for polling their RSSI
+    pub fn bt_addresses(&self) -> Vec<Address> {
+        let links = self.links.lock().unwrap();
+        links.values().filter_map(|link| link.bt_address).collect()
+    }
+
+    /// Record the signal strength BlueZ reports for `address`
+    pub fn set_rssi(&self, address: Address, rssi_dbm: Option<i32>) {
+        let mut links = self.links.lock().unwrap();
+        for link in links.values_mut().filter(|link| link.bt_address == Some(address)) {
+            link.rssi_dbm = rssi_dbm;
+        }
+    }
+
+    fn snapshot(&self) -> Vec<LinkInfo> {
+        self.links.lock().unwrap().values().cloned().collect()
+    }
+}
+
+/// An open edge link; dropping it stops counting the link
+#[derive(Debug)]
+pub struct LinkHandle {
+    links: Arc<Links>,
+    id: u64,
+}
+
+impl LinkHandle {
+    /// Name the drone on this link, once its hello arrives
+    pub fn set_device_id(&self, device_id: &str) {
+        if let Some(link) = self.links.links.lock().unwrap().get_mut(&self.id) {
+            link.device_id = device_id.to_string();
+        }
+    }
+}
+
+impl Drop for LinkHandle {
+    fn drop(&mut self) {
+        self.links.links.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Report the relay's health upstream for as long as the relay runs
+pub async fn report(
+    config: HealthConfig,
+    routes: Arc<Routes>,
+    store: Arc<Mutex<ForwardStore>>,
+    links: Arc<Links>,
+) {
+    let mut reporter = Reporter {
+        config,
+        store,
+        links,
+        started: Instant::now(),
+        sequence_id: 0,
+        battery_warned: false,
+    };
+    loop {
+        match routes.connect().await {
+            Ok((server, addr)) => {
+                println!("[HEALTH] Reporting to {} as {}", addr, reporter.config.device_id);
+                if let Err(e) = reporter.run(server).await {
+                    eprintln!("[HEALTH] Upstream link lost ({}), reconnecting", e);
+                }
+            }
+            Err(e) => eprintln!("[HEALTH] No upstream reachable: {}", e),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+struct Reporter {
+    config: HealthConfig,
+    store: Arc<Mutex<ForwardStore>>,
+    links: Arc<Links>,
+    started: Instant,
+    sequence_id: u64,
+    /// The low battery warning was sent and the battery hasn't recovered since
+    battery_warned: bool,
+}
+
+impl Reporter {
+    /// Report over one upstream connection until it fails
+    async fn run(&mut self, server: TcpStream) -> io::Result<()> {
+        let (mut server_read, mut server_write) = server.into_split();
+        let hello = envelope::Payload::Hello(Hello::relay());
+        self.send(&mut server_write, MessageType::MsgHello, hello).await?;
+
+        let mut ticker = tokio::time::interval(self.config.interval);
+        let mut buf = vec![0u8; READ_BUF_LEN];
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.send_status(&mut server_write).await?,
+                // The server's hello and heartbeat ACKs aren't needed, only
+                // noticing when the link closes
+                read = server_read.read(&mut buf) => {
+                    if read? == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send_status(&mut self, server: &mut OwnedWriteHalf) -> io::Result<()> {
+        let uptime_ms = self.started.elapsed().as_millis() as u64;
+        let mut heartbeat = Heartbeat::new(uptime_ms, DroneState::DroneUnknown, 0, true)
+            .with_relay_status(self.status().await);
+        let battery = read_battery(Path::new(POWER_SUPPLY_DIR));
+        if let Some(battery) = battery {
+            heartbeat = heartbeat.with_battery(battery);
+        }
+        let payload = envelope::Payload::Heartbeat(heartbeat);
+        self.send(server, MessageType::MsgHeartbeat, payload).await?;
+
+        let Some(percent) = battery.map(|b| b.remaining_percent) else {
+            return Ok(());
+        };
+        if percent > safety::BATTERY_WARNING_PERCENT {
+            self.battery_warned = false;
+        } else if !self.battery_warned {
+            let update = StatusUpdate {
+                action: AutonomousAction::AutonomousWarning.into(),
+                reason: format!("Relay battery low ({}%)", percent),
+                ..Default::default()
+            };
+            let payload = envelope::Payload::StatusUpdate(update);
+            self.send(server, MessageType::MsgStatusUpdate, payload).await?;
+            self.battery_warned = true;
+        }
+        Ok(())
+    }
+
+    async fn status(&self) -> RelayStatus {
+        let store = self.store.lock().await;
+        let links = self.links.snapshot();
+        RelayStatus {
+            connected_drones: links.len() as u32,
+            buffered_bytes: store.total_bytes() as u64,
+            links: links
+                .into_iter()
+                .map(|link| RelayLink {
+                    buffered_bytes: store.bytes_for(&link.device_id) as u64,
+                    device_id: link.device_id,
+                    rssi_dbm: link.rssi_dbm,
+                })
+                .collect(),
+        }
+    }
+
+    async fn send(
+        &mut self,
+        server: &mut OwnedWriteHalf,
+        msg_type: MessageType,
+        payload: envelope::Payload,
+    ) -> io::Result<()> {
+        self.sequence_id += 1;
+        let envelope = Envelope {
+            header: Some(Header::new(&self.config.device_id, msg_type, self.sequence_id)),
+            payload: Some(payload),
+            signature: Vec::new(),
+        };
+        let frame = codec::encode(&envelope).map_err(io::Error::other)?;
+        server.write_all(&frame).await
+    }
+}
+
+/// The first battery the kernel lists under `power_supply_dir`, if any
+fn read_battery(power_supply_dir: &Path) -> Option<BatteryStatus> {
+    let read = |dir: &Path, name: &str| fs::read_to_string(dir.join(name)).ok();
+    let number = |dir: &Path, name: &str| read(dir, name)?.trim().parse::<i64>().ok();
+
+    let mut supplies: Vec<_> = fs::read_dir(power_supply_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    supplies.sort();
+    let dir = supplies
+        .into_iter()
+        .find(|dir| read(dir, "type").is_some_and(|t| t.trim() == "Battery"))?;
+    Some(BatteryStatus {
+        // The kernel reports microvolts and microamps
+        voltage: number(&dir, "voltage_now").map_or(0.0, |uv| uv as f32 / 1e6),
+        current: number(&dir, "current_now").map_or(0.0, |ua| ua as f32 / 1e6),
+        remaining_percent: number(&dir, "capacity")?.clamp(0, 100) as u32,
+        remaining_seconds: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_battery_read_from_sysfs() {
+        let root = std::env::temp_dir().join(format!("resqterra-power-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (name, kind) in [("AC", "Mains"), ("BAT0", "Battery")] {
+            fs::create_dir_all(root.join(name)).unwrap();
+            fs::write(root.join(name).join("type"), format!("{}\n", kind)).unwrap();
+        }
+        fs::write(root.join("BAT0/capacity"), "42\n").unwrap();
+        fs::write(root.join("BAT0/voltage_now"), "7400000\n").unwrap();
+
+        let battery = read_battery(&root).unwrap();
+        assert_eq!(battery.remaining_percent, 42);
+        assert!((battery.voltage - 7.4).abs() < 1e-6);
+        assert_eq!(battery.current, 0.0);
+        let _ = fs::remove_dir_all(&root);
+
+        // Mains-powered relays report no battery
+        assert_eq!(read_battery(&root), None);
+    }
+
+    #[test]
+    fn test_links_counted_while_open() {
+        let links = Arc::new(Links::default());
+        let address = Address::new([0, 1, 2, 3, 4, 5]);
+        let tcp = links.open(None);
+        let bluetooth = links.open(Some(address));
+        bluetooth.set_device_id("edge-001");
+        links.set_rssi(address, Some(-60));
+
+        assert_eq!(links.bt_addresses(), vec![address]);
+        drop(tcp);
+        let snapshot = links.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].device_id, "edge-001");
+        assert_eq!(snapshot[0].rssi_dbm, Some(-60));
+    }
+}
@@ -1,14 +1,32 @@
 //! ResQTerra Relay Node
 //!
 //! Accepts connections from edge devices (via TCP or Bluetooth RFCOMM)
-//! and forwards them to the ground control server.
+//! and proxies them to the ground control server (see `proxy`), directly or
+//! through neighbouring relays (see `route`), holding drone telemetry while
+//! neither is reachable (see `store`). What each edge sends is checked and
+//! rate limited first (see `guard`). It reports its own health to the server
+//! as a device of its own (see `health`).
 
-use anyhow::Result;
-use bluer::rfcomm::{Listener as RfcommListener, SocketAddr as RfcommAddr, Stream as RfcommStream};
+mod guard;
+mod health;
+mod proxy;
+mod route;
+mod store;
+
+use anyhow::{anyhow, Result};
+use bluer::rfcomm::{Profile, Role};
 use futures::StreamExt;
+use resqterra_shared::BT_SERVICE_UUID;
 use std::env;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use guard::GuardConfig;
+use health::{HealthConfig, Links};
+use route::Routes;
+use store::{ForwardStore, StoreConfig};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
 
 /// Default RFCOMM channel for ResQTerra relay service
 const DEFAULT_RFCOMM_CHANNEL: u8 = 1;
@@ -19,25 +37,52 @@ const DEFAULT_SERVER: &str = "127.0.0.1:8080";
 /// TCP listen address
 const DEFAULT_TCP_LISTEN: &str = "0.0.0.0:9000";
 
+/// Bluetooth name drones look for (`BtDiscoveryConfig::name_prefix`)
+const DEFAULT_BT_NAME: &str = "ResQTerra-Relay";
+
+/// How often BlueZ is asked for the signal strength of connected drones
+const RSSI_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Journal for frames held while the server is unreachable
+const DEFAULT_STORE_PATH: &str = "/var/lib/resqterra/relay-store";
+
 /// Relay configuration
 struct RelayConfig {
     /// Server address to forward to
     server_addr: String,
+    /// Neighbouring relays to forward through when the server is unreachable
+    peers: Vec<String>,
     /// TCP listen address (for development)
     tcp_listen: String,
     /// RFCOMM channel
     rfcomm_channel: u8,
     /// Enable real Bluetooth RFCOMM
     enable_rfcomm: bool,
+    /// Bluetooth name to advertise
+    bt_name: String,
+    /// Store-and-forward limits and journal
+    store: StoreConfig,
+    /// The relay's own session with the server
+    health: HealthConfig,
+    /// What edge clients may send upstream
+    guard: GuardConfig,
 }
 
 impl Default for RelayConfig {
     fn default() -> Self {
         Self {
             server_addr: DEFAULT_SERVER.into(),
+            peers: Vec::new(),
             tcp_listen: DEFAULT_TCP_LISTEN.into(),
             rfcomm_channel: DEFAULT_RFCOMM_CHANNEL,
             enable_rfcomm: false,
+            bt_name: DEFAULT_BT_NAME.into(),
+            store: StoreConfig {
+                path: Some(DEFAULT_STORE_PATH.into()),
+                ..Default::default()
+            },
+            health: HealthConfig::default(),
+            guard: GuardConfig::default(),
         }
     }
 }
@@ -46,6 +91,9 @@ impl RelayConfig {
     fn from_env() -> Self {
         Self {
             server_addr: env::var("RELAY_SERVER").unwrap_or_else(|_| DEFAULT_SERVER.into()),
+            peers: env::var("RELAY_PEERS")
+                .map(|list| Routes::parse_peers(&list))
+                .unwrap_or_default(),
             tcp_listen: env::var("RELAY_TCP_LISTEN").unwrap_or_else(|_| DEFAULT_TCP_LISTEN.into()),
             rfcomm_channel: env::var("RELAY_RFCOMM_CHANNEL")
                 .ok()
@@ -54,6 +102,39 @@ impl RelayConfig {
             enable_rfcomm: env::var("RELAY_ENABLE_RFCOMM")
                 .map(|v| v == "1" || v.to_lowercase() == "true")
                 .unwrap_or(false),
+            bt_name: env::var("RELAY_BT_NAME").unwrap_or_else(|_| DEFAULT_BT_NAME.into()),
+            store: StoreConfig {
+                path: Some(PathBuf::from(
+                    env::var("RELAY_STORE_PATH").unwrap_or_else(|_| DEFAULT_STORE_PATH.into()),
+                )),
+                max_bytes: env::var("RELAY_STORE_MAX_BYTES")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(StoreConfig::default().max_bytes),
+                ..Default::default()
+            },
+            health: HealthConfig {
+                device_id: env::var("RELAY_ID")
+                    .unwrap_or_else(|_| HealthConfig::default().device_id),
+                ..Default::default()
+            },
+            guard: GuardConfig {
+                allowed_devices: env::var("RELAY_ALLOWED_DEVICES")
+                    .ok()
+                    .map(|list| GuardConfig::parse_devices(&list)),
+                max_frame_len: env::var("RELAY_MAX_FRAME_LEN")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(GuardConfig::default().max_frame_len),
+                frames_per_sec: env::var("RELAY_RATE_FRAMES")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(GuardConfig::default().frames_per_sec),
+                bytes_per_sec: env::var("RELAY_RATE_BYTES")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(GuardConfig::default().bytes_per_sec),
+            },
         }
     }
 }
@@ -63,9 +144,37 @@ async fn main() -> Result<()> {
     let config = RelayConfig::from_env();
 
     println!("ResQTerra Relay Node");
+    println!("  Relay ID: {}", config.health.device_id);
     println!("  Server: {}", config.server_addr);
+    if !config.peers.is_empty() {
+        println!("  Peer relays: {}", config.peers.join(", "));
+    }
     println!("  TCP listen: {}", config.tcp_listen);
     println!("  RFCOMM enabled: {}", config.enable_rfcomm);
+    if let Some(allowed) = &config.guard.allowed_devices {
+        println!("  Allowed devices: {}", allowed.len());
+    }
+
+    let store = match ForwardStore::open(config.store.clone()) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("[STORE] Can't open journal ({}), holding frames in memory only", e);
+            ForwardStore::open(StoreConfig {
+                path: None,
+                ..config.store.clone()
+            })?
+        }
+    };
+    let store = Arc::new(Mutex::new(store));
+    let routes = Arc::new(Routes::new(config.server_addr.clone(), config.peers.clone()));
+    let links = Arc::new(Links::default());
+    let guard = Arc::new(config.guard.clone());
+    tokio::spawn(health::report(
+        config.health.clone(),
+        routes.clone(),
+        store.clone(),
+        links.clone(),
+    ));
 
     // Start TCP listener
     let tcp_listener = TcpListener::bind(&config.tcp_listen).await?;
@@ -74,10 +183,15 @@ async fn main() -> Result<()> {
     // Start RFCOMM listener if enabled
     let rfcomm_task = if config.enable_rfcomm {
         println!("Starting RFCOMM listener on channel {}", config.rfcomm_channel);
-        let server_addr = config.server_addr.clone();
+        let routes = routes.clone();
         let channel = config.rfcomm_channel;
+        let bt_name = config.bt_name.clone();
+        let store = store.clone();
+        let guard = guard.clone();
+        let links = links.clone();
         Some(tokio::spawn(async move {
-            if let Err(e) = run_rfcomm_listener(channel, &server_addr).await {
+            let result = run_rfcomm_listener(channel, &bt_name, routes, store, guard, links).await;
+            if let Err(e) = result {
                 eprintln!("[RFCOMM] Listener error: {}", e);
             }
         }))
@@ -86,14 +200,17 @@ async fn main() -> Result<()> {
     };
 
     // Main TCP accept loop
-    let server_addr = config.server_addr.clone();
     loop {
         match tcp_listener.accept().await {
             Ok((socket, addr)) => {
                 println!("[TCP] Connection from {}", addr);
-                let server = server_addr.clone();
+                let routes = routes.clone();
+                let store = store.clone();
+                let guard = guard.clone();
+                let link = links.open(None);
                 tokio::spawn(async move {
-                    if let Err(e) = handle_connection(socket, &server).await {
+                    let result = proxy::relay(socket, &routes, store, &guard, link, "TCP").await;
+                    if let Err(e) = result {
                         eprintln!("[TCP] Connection error: {}", e);
                     }
                 });
@@ -105,112 +222,80 @@ async fn main() -> Result<()> {
     }
 }
 
-/// Run the RFCOMM Bluetooth listener
-async fn run_rfcomm_listener(channel: u8, server_addr: &str) -> Result<()> {
-    let addr = RfcommAddr::new(bluer::Address::any(), channel);
-    let listener = RfcommListener::bind(addr).await?;
+/// Advertise the relay service over SDP and accept drones over RFCOMM
+///
+/// BlueZ owns the RFCOMM socket: registering the profile publishes an SDP
+/// record for `BT_SERVICE_UUID` on `channel` and hands each incoming
+/// connection over as a request.
+async fn run_rfcomm_listener(
+    channel: u8,
+    bt_name: &str,
+    routes: Arc<Routes>,
+    store: Arc<Mutex<ForwardStore>>,
+    guard: Arc<GuardConfig>,
+    links: Arc<Links>,
+) -> Result<()> {
+    let session = bluer::Session::new().await?;
+    let adapter = session.default_adapter().await?;
+    adapter.set_powered(true).await?;
+    // Drones pick relays out of a scan by name
+    adapter.set_alias(bt_name.to_string()).await?;
+    adapter.set_discoverable_timeout(0).await?;
+    adapter.set_discoverable(true).await?;
 
-    let local_addr = listener.as_ref().local_addr()?;
-    println!("[RFCOMM] Listening on channel {}", local_addr.channel);
+    let profile = Profile {
+        uuid: bluer::Uuid::from_u128(BT_SERVICE_UUID),
+        name: Some("ResQTerra Relay".into()),
+        role: Some(Role::Server),
+        channel: Some(channel.into()),
+        // Drones can't pair interactively, so any device may connect: frames
+        // are forwarded unauthenticated unless drone and server both enable Noise
+        require_authentication: Some(false),
+        require_authorization: Some(false),
+        ..Default::default()
+    };
+    let mut requests = session.register_profile(profile).await?;
+    tokio::spawn(poll_rssi(adapter.clone(), links.clone()));
+    println!(
+        "[RFCOMM] Advertising as {} on {}, channel {}",
+        bt_name,
+        adapter.name(),
+        channel
+    );
 
-    loop {
-        match listener.accept().await {
-            Ok((stream, addr)) => {
-                println!("[RFCOMM] Connection from {}", addr);
-                let server = server_addr.to_string();
+    while let Some(request) = requests.next().await {
+        let device = request.device();
+        match request.accept() {
+            Ok(stream) => {
+                println!("[RFCOMM] Connection from {}", device);
+                let routes = routes.clone();
+                let store = store.clone();
+                let guard = guard.clone();
+                let link = links.open(Some(device));
                 tokio::spawn(async move {
-                    if let Err(e) = handle_rfcomm_connection(stream, &server).await {
+                    let result = proxy::relay(stream, &routes, store, &guard, link, "RFCOMM").await;
+                    if let Err(e) = result {
                         eprintln!("[RFCOMM] Connection error: {}", e);
                     }
                 });
             }
-            Err(e) => {
-                eprintln!("[RFCOMM] Accept error: {}", e);
-            }
-        }
-    }
-}
-
-/// Handle a TCP connection from edge device
-async fn handle_connection(mut edge: TcpStream, server_addr: &str) -> Result<()> {
-    let mut server = TcpStream::connect(server_addr).await?;
-    println!("[TCP] Connected to server {}", server_addr);
-
-    // Bidirectional forwarding
-    let (mut edge_read, mut edge_write) = edge.split();
-    let (mut server_read, mut server_write) = server.split();
-
-    let edge_to_server = async {
-        let mut buf = vec![0u8; 4096];
-        loop {
-            let n = edge_read.read(&mut buf).await?;
-            if n == 0 {
-                break;
-            }
-            server_write.write_all(&buf[..n]).await?;
-        }
-        Ok::<_, anyhow::Error>(())
-    };
-
-    let server_to_edge = async {
-        let mut buf = vec![0u8; 4096];
-        loop {
-            let n = server_read.read(&mut buf).await?;
-            if n == 0 {
-                break;
-            }
-            edge_write.write_all(&buf[..n]).await?;
+            Err(e) => eprintln!("[RFCOMM] Accepting {} failed: {}", device, e),
         }
-        Ok::<_, anyhow::Error>(())
-    };
-
-    tokio::select! {
-        r = edge_to_server => r?,
-        r = server_to_edge => r?,
     }
-
-    println!("[TCP] Connection closed");
-    Ok(())
+    Err(anyhow!("BlueZ dropped the RFCOMM profile"))
 }
 
-/// Handle an RFCOMM connection from edge device
-async fn handle_rfcomm_connection(mut edge: RfcommStream, server_addr: &str) -> Result<()> {
-    let mut server = TcpStream::connect(server_addr).await?;
-    println!("[RFCOMM] Connected to server {}", server_addr);
-
-    // Bidirectional forwarding
-    let (mut edge_read, mut edge_write) = edge.split();
-    let (mut server_read, mut server_write) = server.split();
-
-    let edge_to_server = async {
-        let mut buf = vec![0u8; 4096];
-        loop {
-            let n = edge_read.read(&mut buf).await?;
-            if n == 0 {
-                break;
-            }
-            server_write.write_all(&buf[..n]).await?;
-        }
-        Ok::<_, anyhow::Error>(())
-    };
-
-    let server_to_edge = async {
-        let mut buf = vec![0u8; 4096];
-        loop {
-            let n = server_read.read(&mut buf).await?;
-            if n == 0 {
-                break;
-            }
-            edge_write.write_all(&buf[..n]).await?;
+/// Keep the RSSI of each connected drone current for the health reports
+async fn poll_rssi(adapter: bluer::Adapter, links: Arc<Links>) {
+    let mut ticker = tokio::time::interval(RSSI_POLL_INTERVAL);
+    loop {
+        ticker.tick().await;
+        for address in links.bt_addresses() {
+            let rssi = match adapter.device(address) {
+                Ok(device) => device.rssi().await.ok().flatten(),
+                Err(_) => None,
+            };
+            links.set_rssi(address, rssi.map(i32::from));
         }
-        Ok::<_, anyhow::Error>(())
-    };
-
-    tokio::select! {
-        r = edge_to_server => r?,
-        r = server_to_edge => r?,
     }
-
-    println!("[RFCOMM] Connection closed");
-    Ok(())
 }
@@ -0,0 +1,543 @@
+//! Proxying between one edge device and the server
+//!
+//! Plaintext links are pumped frame by frame with [`FrameDecoder`], checking
+//! each upstream frame (see `guard`) and counting this relay in its hop count
+//! (see `route`). Each edge client gets its own upstream connection, to the
+//! server or a neighbouring relay, which is reconnected on its own when the
+//! upstream link drops: the edge stays connected, its telemetry and
+//! ACKs go to the [`ForwardStore`] until the server is back, and the edge's
+//! hello is sent again first, followed by what was stored for it, so the
+//! server picks its session up. Only whole frames are forwarded either way,
+//! so a dropped server link never leaves half a frame on the edge link.
+//!
+//! Noise links are end to end: the relay can't read them, and the handshake
+//! can't survive a new upstream connection. They are forwarded byte for byte,
+//! rate limited only, and end with either side.
+
+use crate::guard::{Guard, GuardConfig, Verdict};
+use crate::health::LinkHandle;
+use crate::route::{self, Hop, Routes};
+use crate::store::ForwardStore;
+use anyhow::{anyhow, Context, Result};
+use bytes::{Bytes, BytesMut};
+use resqterra_shared::codec::{self, FrameDecoder};
+use resqterra_shared::envelope;
+use resqterra_shared::schema::{
+    FRAME_COMPRESSION_SHIFT, FRAME_CRC_FLAG, FRAME_LENGTH_MASK, FRAME_PREFIX_LEN,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+
+/// Frames from the edge queued while the server link is (re)connecting
+const FRAME_QUEUE_LEN: usize = 64;
+
+/// Largest first frame taken for a hello; Noise's first message reads as a
+/// frame of 2 MiB or more
+const MAX_HELLO_FRAME_LEN: u32 = 64 * 1024;
+
+/// First and longest pause between upstream reconnect attempts
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// Read buffer size for each direction
+const READ_BUF_LEN: usize = 4096;
+
+/// Proxy `edge` upstream along `routes` until the edge disconnects
+///
+/// `store` is shared by every edge, so frames stored for a drone are replayed
+/// even if it comes back on a new connection. `guard` says what the edge may
+/// send, and `link` counts it in the relay's health reports. `tag` prefixes
+/// log lines (`TCP`, `RFCOMM`).
+pub async fn relay<S>(
+    edge: S,
+    routes: &Routes,
+    store: Arc<Mutex<ForwardStore>>,
+    guard: &GuardConfig,
+    link: LinkHandle,
+    tag: &str,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut edge_read, edge_write) = tokio::io::split(edge);
+
+    // The first bytes tell plaintext frames from Noise
+    let mut first = BytesMut::with_capacity(READ_BUF_LEN);
+    while first.len() < FRAME_PREFIX_LEN {
+        if edge_read.read_buf(&mut first).await? == 0 {
+            return Ok(());
+        }
+    }
+    if !starts_with_hello_frame(&first) {
+        println!("[{}] Encrypted link, forwarding bytes", tag);
+        return forward_bytes(edge_read, edge_write, first, routes, Guard::new(guard)).await;
+    }
+
+    let (frames_tx, frames_rx) = mpsc::channel(FRAME_QUEUE_LEN);
+    let upstream = pump_upstream(routes, frames_rx, edge_write, &store, &link, tag);
+    tokio::pin!(upstream);
+    tokio::select! {
+        result = &mut upstream => return result,
+        result = read_edge_frames(edge_read, first, frames_tx, guard, tag) => result?,
+    }
+    // The edge is gone; deliver what it sent before leaving
+    upstream.await
+}
+
+/// Whether `buf` starts with the prefix of a plaintext hello frame
+fn starts_with_hello_frame(buf: &[u8]) -> bool {
+    let prefix = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let flags = (prefix >> FRAME_COMPRESSION_SHIFT) as u8;
+    // Compression is only agreed in the hello, so it's never compressed
+    flags & !FRAME_CRC_FLAG == 0 && prefix & FRAME_LENGTH_MASK <= MAX_HELLO_FRAME_LEN
+}
+
+/// The edge's latest hello, sent first on every server connection
+struct EdgeHello {
+    frame: Bytes,
+    device_id: String,
+}
+
+impl EdgeHello {
+    /// `frame` as a hello, if it is one
+    fn from_frame(frame: &Bytes) -> Option<Self> {
+        let mut buf = BytesMut::from(&frame[..]);
+        let envelope = codec::decode(&mut buf).ok()??;
+        if !matches!(envelope.payload, Some(envelope::Payload::Hello(_))) {
+            return None;
+        }
+        Some(Self {
+            frame: frame.clone(),
+            device_id: envelope.header?.device_id,
+        })
+    }
+}
+
+/// Remember `frame` if it's a hello, returning whether it was
+fn note_hello(frame: &Bytes, hello: &mut Option<EdgeHello>, link: &LinkHandle) -> bool {
+    let Some(new_hello) = EdgeHello::from_frame(frame) else {
+        return false;
+    };
+    link.set_device_id(&new_hello.device_id);
+    *hello = Some(new_hello);
+    true
+}
+
+/// Keep a frame the server can't take yet: hellos are remembered, telemetry
+/// and ACKs stored, and anything else dropped
+async fn hold(
+    frame: Bytes,
+    hello: &mut Option<EdgeHello>,
+    store: &Mutex<ForwardStore>,
+    link: &LinkHandle,
+    tag: &str,
+) {
+    if note_hello(&frame, hello, link) {
+        return;
+    }
+    if let Err(e) = store.lock().await.push(frame) {
+        eprintln!("[{}] Store journal write failed: {}", tag, e);
+    }
+}
+
+/// Split the edge's bytes into frames for the upstream pump, checking each
+/// against `guard` and counting this relay in its hop count
+///
+/// Fails when the guard closes the link, or a frame has already made
+/// `MAX_HOPS` hops: the link is looping through relays.
+async fn read_edge_frames<R>(
+    mut edge: R,
+    first: BytesMut,
+    frames: mpsc::Sender<Bytes>,
+    config: &GuardConfig,
+    tag: &str,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut guard = Guard::new(config);
+    let mut throttled = false;
+    let mut decoder = config.decoder();
+    decoder.extend(&first);
+    let mut buf = vec![0u8; READ_BUF_LEN];
+    loop {
+        while let Some(frame) = decoder.next_raw_frame().context("edge frame refused")? {
+            let wait = guard.throttle(1, frame.len(), Instant::now());
+            if !wait.is_zero() {
+                if !throttled {
+                    println!("[{}] Edge over its rate limit, throttling", tag);
+                    throttled = true;
+                }
+                tokio::time::sleep(wait).await;
+            }
+            let envelope = match guard.check(&frame) {
+                Verdict::Pass(envelope) => envelope,
+                Verdict::Drop(reason) => {
+                    eprintln!("[{}] Dropped frame from edge: {}", tag, reason);
+                    continue;
+                }
+                Verdict::Close(reason) => return Err(anyhow!(reason)),
+            };
+            let frame = match route::add_hop(&frame, *envelope)? {
+                Hop::Forward(frame) => frame,
+                Hop::TooFar(hops) => {
+                    return Err(anyhow!("frame already passed {} relays, routing loop?", hops));
+                }
+            };
+            if frames.send(frame).await.is_err() {
+                return Ok(());
+            }
+        }
+        let n = edge.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        decoder.extend(&buf[..n]);
+    }
+}
+
+/// Why a connection to the server ended
+enum PumpEnd {
+    /// The edge is gone and everything it sent was delivered
+    Done,
+    /// The server link failed; reconnect
+    Upstream(std::io::Error),
+}
+
+/// Keep an upstream connection up and pump frames both ways over it
+async fn pump_upstream<W>(
+    routes: &Routes,
+    mut frames: mpsc::Receiver<Bytes>,
+    mut edge: W,
+    store: &Mutex<ForwardStore>,
+    link: &LinkHandle,
+    tag: &str,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut hello: Option<EdgeHello> = None;
+    let mut delay = RECONNECT_MIN;
+    loop {
+        let (server, upstream_addr) = match routes.connect().await {
+            Ok(connected) => connected,
+            Err(e) => {
+                let held = match &hello {
+                    Some(hello) => store.lock().await.len_for(&hello.device_id),
+                    None => 0,
+                };
+                eprintln!("[{}] No upstream reachable: {} ({} frames held)", tag, e, held);
+                // Hold what the edge sends until the next attempt
+                let backoff = tokio::time::sleep(delay);
+                tokio::pin!(backoff);
+                loop {
+                    tokio::select! {
+                        _ = &mut backoff => break,
+                        frame = frames.recv() => match frame {
+                            Some(frame) => hold(frame, &mut hello, store, link, tag).await,
+                            // What the edge stored waits for it to come back
+                            None => return Ok(()),
+                        },
+                    }
+                }
+                delay = (delay * 2).min(RECONNECT_MAX);
+                continue;
+            }
+        };
+        delay = RECONNECT_MIN;
+        println!("[{}] Connected upstream to {}", tag, upstream_addr);
+
+        match pump(server, &mut frames, &mut edge, &mut hello, store, link, tag).await? {
+            PumpEnd::Done => return Ok(()),
+            PumpEnd::Upstream(e) => {
+                eprintln!("[{}] Upstream link lost ({}), reconnecting", tag, e);
+            }
+        }
+    }
+}
+
+/// Pump frames over one server connection; errors only when the edge fails
+async fn pump<W>(
+    server: TcpStream,
+    frames: &mut mpsc::Receiver<Bytes>,
+    edge: &mut W,
+    hello: &mut Option<EdgeHello>,
+    store: &Mutex<ForwardStore>,
+    link: &LinkHandle,
+    tag: &str,
+) -> Result<PumpEnd>
+where
+    W: AsyncWrite + Unpin,
+{
+    let (mut server_read, mut server_write) = server.into_split();
+
+    // A new session starts with the edge's hello, then what was stored for it
+    if let Some(hello) = hello {
+        if let Err(e) = server_write.write_all(&hello.frame).await {
+            return Ok(PumpEnd::Upstream(e));
+        }
+        if let Err(e) = replay_stored(&mut server_write, &hello.device_id, store, tag).await {
+            return Ok(PumpEnd::Upstream(e));
+        }
+    }
+
+    let mut decoder = FrameDecoder::new();
+    let mut buf = vec![0u8; READ_BUF_LEN];
+    loop {
+        tokio::select! {
+            frame = frames.recv() => {
+                let Some(frame) = frame else {
+                    return Ok(PumpEnd::Done);
+                };
+                note_hello(&frame, hello, link);
+                if let Err(e) = server_write.write_all(&frame).await {
+                    hold(frame, hello, store, link, tag).await;
+                    return Ok(PumpEnd::Upstream(e));
+                }
+            }
+            read = server_read.read(&mut buf) => {
+                let n = match read {
+                    Ok(0) => return Ok(PumpEnd::Upstream(std::io::ErrorKind::UnexpectedEof.into())),
+                    Ok(n) => n,
+                    Err(e) => return Ok(PumpEnd::Upstream(e)),
+                };
+                decoder.extend(&buf[..n]);
+                while let Some(frame) = decoder.next_raw_frame()? {
+                    edge.write_all(&frame).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Send the frames stored for `device_id`, removing each once written
+async fn replay_stored<W>(
+    server: &mut W,
+    device_id: &str,
+    store: &Mutex<ForwardStore>,
+    tag: &str,
+) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut replayed = 0;
+    let result = loop {
+        let Some(frame) = store.lock().await.front(device_id) else {
+            break Ok(());
+        };
+        if let Err(e) = server.write_all(&frame).await {
+            break Err(e);
+        }
+        store.lock().await.pop_front(device_id);
+        replayed += 1;
+    };
+    if replayed > 0 {
+        println!("[{}] Replayed {} stored frames for {}", tag, replayed, device_id);
+        if let Err(e) = store.lock().await.commit() {
+            eprintln!("[{}] Store journal write failed: {}", tag, e);
+        }
+    }
+    result
+}
+
+/// Forward bytes both ways until either side closes, rate limiting the edge
+async fn forward_bytes<R, W>(
+    mut edge_read: R,
+    mut edge_write: W,
+    first: BytesMut,
+    routes: &Routes,
+    guard: Guard,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (mut server, _) = routes.connect().await?;
+    server.write_all(&first).await?;
+    let (mut server_read, mut server_write) = server.split();
+    tokio::select! {
+        r = copy_throttled(&mut edge_read, &mut server_write, guard) => r?,
+        r = tokio::io::copy(&mut server_read, &mut edge_write) => {
+            r?;
+        }
+    };
+    Ok(())
+}
+
+/// Copy `edge` to `server` at no more than the guard's byte rate
+async fn copy_throttled<R, W>(
+    edge: &mut R,
+    server: &mut W,
+    mut guard: Guard,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; READ_BUF_LEN];
+    loop {
+        let n = edge.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        server.write_all(&buf[..n]).await?;
+        let wait = guard.throttle(0, n, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::Links;
+    use crate::store::StoreConfig;
+    use resqterra_shared::{Envelope, Header, Hello, MessageType, Telemetry};
+    use tokio::net::TcpListener;
+
+    fn frame(msg_type: MessageType, payload: Option<envelope::Payload>) -> Bytes {
+        frame_with_seq(msg_type, 1, payload)
+    }
+
+    fn frame_with_seq(
+        msg_type: MessageType,
+        seq: u64,
+        payload: Option<envelope::Payload>,
+    ) -> Bytes {
+        codec::encode(&Envelope {
+            header: Some(Header::new("edge-001", msg_type, seq)),
+            payload,
+            signature: Vec::new(),
+        })
+        .unwrap()
+    }
+
+    async fn read_frame(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    /// `frame` as the relay forwards it, one hop further
+    fn relayed(frame: &Bytes) -> Bytes {
+        let envelope = codec::decode(&mut BytesMut::from(&frame[..])).unwrap().unwrap();
+        match route::add_hop(frame, envelope).unwrap() {
+            Hop::Forward(frame) => frame,
+            Hop::TooFar(hops) => panic!("{} hops is too far", hops),
+        }
+    }
+
+    fn memory_store() -> Arc<Mutex<ForwardStore>> {
+        Arc::new(Mutex::new(ForwardStore::open(StoreConfig::default()).unwrap()))
+    }
+
+    fn routes(server_addr: impl ToString) -> Routes {
+        Routes::new(server_addr.to_string(), Vec::new())
+    }
+
+    fn guard() -> GuardConfig {
+        GuardConfig::default()
+    }
+
+    fn link() -> LinkHandle {
+        Arc::new(Links::default()).open(None)
+    }
+
+    #[tokio::test]
+    async fn test_upstream_reconnects_and_replays_hello() {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let routes = routes(server.local_addr().unwrap());
+        let (mut edge, relay_end) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            relay(relay_end, &routes, memory_store(), &guard(), link(), "TEST").await
+        });
+
+        let hello = frame(MessageType::MsgHello, Some(envelope::Payload::Hello(Hello::default())));
+        let heartbeat = frame(MessageType::MsgHeartbeat, None);
+        let (hello_up, heartbeat_up) = (relayed(&hello), relayed(&heartbeat));
+        edge.write_all(&hello).await.unwrap();
+        let (mut upstream, _) = server.accept().await.unwrap();
+        assert_eq!(read_frame(&mut upstream, hello_up.len()).await, hello_up);
+
+        // The server drops the link: the edge stays up and the relay reconnects
+        drop(upstream);
+        let (mut upstream, _) = server.accept().await.unwrap();
+        assert_eq!(read_frame(&mut upstream, hello_up.len()).await, hello_up);
+        edge.write_all(&heartbeat).await.unwrap();
+        assert_eq!(read_frame(&mut upstream, heartbeat_up.len()).await, heartbeat_up);
+
+        // Commands reach the edge over the new connection
+        let command = frame(MessageType::MsgCommand, None);
+        upstream.write_all(&command).await.unwrap();
+        let mut received = vec![0; command.len()];
+        edge.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, command);
+    }
+
+    #[tokio::test]
+    async fn test_telemetry_stored_while_server_down() {
+        // Find a free port, then leave it closed until the edge has sent
+        let server_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let store = memory_store();
+        let (mut edge, relay_end) = tokio::io::duplex(4096);
+        let relay_store = store.clone();
+        let routes = routes(server_addr);
+        tokio::spawn(async move {
+            relay(relay_end, &routes, relay_store, &guard(), link(), "TEST").await
+        });
+
+        let hello = frame(MessageType::MsgHello, Some(envelope::Payload::Hello(Hello::default())));
+        let telemetry = |seq| {
+            let payload = envelope::Payload::Telemetry(Telemetry::default());
+            frame_with_seq(MessageType::MsgTelemetry, seq, Some(payload))
+        };
+        let heartbeat = frame(MessageType::MsgHeartbeat, None);
+        let (first, second) = (telemetry(1), telemetry(2));
+        edge.write_all(&[hello.clone(), first.clone(), heartbeat].concat()).await.unwrap();
+        while store.lock().await.len_for("edge-001") == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Stored telemetry follows the hello; the stale heartbeat was dropped
+        let server = TcpListener::bind(server_addr).await.unwrap();
+        let (mut upstream, _) = server.accept().await.unwrap();
+        let (hello, first, second_up) = (relayed(&hello), relayed(&first), relayed(&second));
+        assert_eq!(read_frame(&mut upstream, hello.len()).await, hello);
+        assert_eq!(read_frame(&mut upstream, first.len()).await, first);
+        edge.write_all(&second).await.unwrap();
+        assert_eq!(read_frame(&mut upstream, second_up.len()).await, second_up);
+        assert_eq!(store.lock().await.len_for("edge-001"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_looping_link_dropped() {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let routes = routes(server.local_addr().unwrap());
+        let (mut edge, relay_end) = tokio::io::duplex(4096);
+
+        // A hello that has already been around the relays MAX_HOPS times
+        let mut header = Header::new("edge-001", MessageType::MsgHello, 1);
+        header.hop_count = resqterra_shared::MAX_HOPS;
+        let hello = codec::encode(&Envelope {
+            header: Some(header),
+            payload: Some(envelope::Payload::Hello(Hello::default())),
+            signature: Vec::new(),
+        })
+        .unwrap();
+        edge.write_all(&hello).await.unwrap();
+        let result = relay(relay_end, &routes, memory_store(), &guard(), link(), "TEST").await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_noise_handshake_is_not_a_hello_frame() {
+        let hello = frame(MessageType::MsgHello, Some(envelope::Payload::Hello(Hello::default())));
+        assert!(starts_with_hello_frame(&hello));
+        // Noise_XX's first message: 2-byte length 32, then an ephemeral key
+        assert!(!starts_with_hello_frame(&[0x00, 0x20, 0x9c, 0x41]));
+    }
+}
@@ -0,0 +1,163 @@
+//! Multi-hop routing between relays
+//!
+//! A relay forwards upstream to the server or, when the server is out of its
+//! reach, to a neighbouring relay that is closer to it (relay → relay →
+//! server), so a chain of relays can cover a canyon or forest the server
+//! can't see into. Neighbours are plain relays: to them, an upstream relay
+//! is just another edge client.
+//!
+//! Every plaintext frame a relay forwards upstream has its
+//! `Header.hop_count` bumped (signatures don't cover it). A link whose
+//! frames have already made `MAX_HOPS` hops is dropped, so relays that end
+//! up forwarding to each other in a loop tear the loop down instead of
+//! circling a drone's hello forever. Noise links can't be read, so they are
+//! neither counted nor limited.
+
+use bytes::{Bytes, BytesMut};
+use resqterra_shared::codec::{self, CodecError};
+use resqterra_shared::schema::{FRAME_COMPRESSION_SHIFT, FRAME_CRC_FLAG};
+use resqterra_shared::{Compression, Envelope, MAX_HOPS};
+use std::io;
+use tokio::net::TcpStream;
+
+/// Where a relay forwards to, in order of preference
+#[derive(Debug, Clone)]
+pub struct Routes {
+    /// The server, tried first
+    server: String,
+    /// Neighbouring relays, tried in order while the server is unreachable
+    peers: Vec<String>,
+}
+
+impl Routes {
+    pub fn new(server: impl Into<String>, peers: Vec<String>) -> Self {
+        Self {
+            server: server.into(),
+            peers,
+        }
+    }
+
+    /// Parse a comma-separated list of neighbour addresses, e.g.
+    /// `"10.0.0.2:9000,10.0.0.3:9000"`
+    pub fn parse_peers(list: &str) -> Vec<String> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|addr| !addr.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// Connect to the first upstream that answers: the server, then each peer
+    ///
+    /// Returns the connection and its address. The error is the last
+    /// upstream's.
+    pub async fn connect(&self) -> io::Result<(TcpStream, &str)> {
+        let mut last_err = None;
+        for addr in std::iter::once(&self.server).chain(&self.peers) {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return Ok((stream, addr)),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.expect("routes always include the server"))
+    }
+}
+
+/// A plaintext frame after this relay counted itself in
+#[derive(Debug, PartialEq)]
+pub enum Hop {
+    /// Forward this frame
+    Forward(Bytes),
+    /// The frame has already made `MAX_HOPS` hops, carrying this many
+    TooFar(u32),
+}
+
+/// Bump the hop count of `frame`, decoded as `envelope`, keeping its
+/// compression and CRC
+///
+/// The count is rewritten in the frame's own bytes: re-encoding `envelope`
+/// would drop fields this relay doesn't know, and break the signature over
+/// them. Frames without a header are forwarded as they are; the server
+/// drops them.
+pub fn add_hop(frame: &Bytes, envelope: Envelope) -> Result<Hop, CodecError> {
+    let prefix = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+    let flags = (prefix >> FRAME_COMPRESSION_SHIFT) as u8;
+    let Some(header) = envelope.header else {
+        return Ok(Hop::Forward(frame.clone()));
+    };
+    if header.hop_count >= MAX_HOPS {
+        return Ok(Hop::TooFar(header.hop_count));
+    }
+    let Some((_, raw)) = codec::decode_raw(&mut BytesMut::from(&frame[..]))? else {
+        return Ok(Hop::Forward(frame.clone()));
+    };
+    let raw = codec::set_hop_count(&raw, header.hop_count + 1)?;
+
+    let compression = Compression::try_from((flags & !FRAME_CRC_FLAG) as i32)
+        .expect("decoded frames use a known compression");
+    let checksum = flags & FRAME_CRC_FLAG != 0;
+    Ok(Hop::Forward(codec::encode_raw_frame(&raw, compression, checksum)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::{envelope, Header, MessageType, Telemetry};
+    use tokio::net::TcpListener;
+
+    fn decode(frame: &Bytes) -> Envelope {
+        codec::decode(&mut BytesMut::from(&frame[..])).unwrap().unwrap()
+    }
+
+    fn telemetry(hop_count: u32) -> Envelope {
+        let mut header = Header::new("edge-001", MessageType::MsgTelemetry, 1);
+        header.hop_count = hop_count;
+        Envelope {
+            header: Some(header),
+            payload: Some(envelope::Payload::Telemetry(Telemetry::default())),
+            signature: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_hops_counted_until_the_limit() {
+        let frame = codec::encode_frame(&telemetry(0), Compression::None, true).unwrap();
+        let Hop::Forward(relayed) = add_hop(&frame, decode(&frame)).unwrap() else {
+            panic!("first hop refused");
+        };
+        assert_eq!(decode(&relayed).header.unwrap().hop_count, 1);
+        // The CRC flag survives the rewrite
+        assert_eq!(relayed[0] & FRAME_CRC_FLAG, FRAME_CRC_FLAG);
+
+        // Apart from the count the envelope passes byte for byte, fields this
+        // relay doesn't know included, so a signature over them still holds
+        let mut raw = codec::encode(&telemetry(0)).unwrap()[4..].to_vec();
+        raw.extend_from_slice(&[0xa0, 0x06, 0x01]); // field 100 = 1, from a newer edge
+        let frame = codec::encode_raw_frame(&raw, Compression::None, false).unwrap();
+        let Hop::Forward(relayed) = add_hop(&frame, decode(&frame)).unwrap() else {
+            panic!("first hop refused");
+        };
+        let (envelope, relayed_raw) = codec::decode_raw(&mut BytesMut::from(&relayed[..]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(envelope.header.unwrap().hop_count, 1);
+        assert_eq!(codec::set_hop_count(&relayed_raw, 0).unwrap(), raw);
+
+        let looping = codec::encode(&telemetry(MAX_HOPS)).unwrap();
+        assert_eq!(add_hop(&looping, decode(&looping)).unwrap(), Hop::TooFar(MAX_HOPS));
+    }
+
+    #[tokio::test]
+    async fn test_connect_falls_back_to_peers() {
+        let peer = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap().to_string();
+        // Nothing listens on the server's port
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap().to_string();
+        drop(server);
+
+        let routes = Routes::new(server_addr, Routes::parse_peers(&format!(" {}, ", peer_addr)));
+        let (_, addr) = routes.connect().await.unwrap();
+        assert_eq!(addr, peer_addr);
+    }
+}
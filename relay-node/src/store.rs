@@ -0,0 +1,360 @@
+//! Store-and-forward for drone frames while the server is unreachable
+//!
+//! While a drone's upstream connection is down, its telemetry and ACK frames
+//! go here instead of backing up into its Bluetooth link. When the upstream
+//! is back they are replayed oldest first, right after the hello; anything
+//! else sent meanwhile (heartbeats in particular) would be stale and is
+//! dropped.
+//!
+//! Frames are kept exactly as the drone sent them, so signatures still
+//! verify, and decoded only to learn their drone, priority and age. With a
+//! `path` set they are journaled to disk and survive a relay restart; a torn
+//! tail is dropped on load. All drones share `max_bytes`: when it's exceeded
+//! the drone holding the most bytes loses its oldest lowest-priority frame,
+//! so one chatty drone can't push out everyone else's ACKs. Frames older
+//! than `max_age` (by header `timestamp_ms`) are dropped.
+
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use resqterra_shared::codec::{self, FrameDecoder};
+use resqterra_shared::{envelope, now_ms};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Store limits and where to journal it
+#[derive(Debug, Clone)]
+pub struct StoreConfig {
+    /// Journal file (None = kept in memory only, lost on restart)
+    pub path: Option<PathBuf>,
+    /// Frame bytes kept across all drones before evicting
+    pub max_bytes: usize,
+    /// Frames older than this are dropped instead of replayed
+    pub max_age: Duration,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_bytes: 4 * 1024 * 1024,
+            max_age: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// A stored frame and what was learned from decoding it
+#[derive(Debug)]
+struct Entry {
+    device_id: String,
+    priority: u32,
+    timestamp_ms: u64,
+    frame: Bytes,
+}
+
+impl Entry {
+    /// Decode `frame`, keeping it only if it's telemetry or an ACK
+    fn from_frame(frame: Bytes) -> Option<Self> {
+        let mut buf = BytesMut::from(&frame[..]);
+        let envelope = codec::decode(&mut buf).ok()??;
+        let storable = matches!(
+            envelope.payload,
+            Some(
+                envelope::Payload::Telemetry(_)
+                    | envelope::Payload::TelemetryDelta(_)
+                    | envelope::Payload::TelemetryBatch(_)
+                    | envelope::Payload::Ack(_)
+            )
+        );
+        let header = envelope.header.as_ref().filter(|_| storable)?;
+        Some(Self {
+            device_id: header.device_id.clone(),
+            priority: envelope.priority(),
+            timestamp_ms: header.timestamp_ms,
+            frame,
+        })
+    }
+}
+
+/// Frames waiting for their drone's upstream, oldest first
+#[derive(Debug)]
+pub struct ForwardStore {
+    config: StoreConfig,
+    entries: VecDeque<Entry>,
+    /// Frame bytes held per drone
+    bytes: HashMap<String, usize>,
+    /// Open journal, appended to on push
+    journal: Option<File>,
+    /// Entries were removed since the journal was last rewritten
+    dirty: bool,
+}
+
+impl ForwardStore {
+    /// Open the store, reloading frames journaled before a restart
+    pub fn open(config: StoreConfig) -> Result<Self> {
+        let mut store = Self {
+            config,
+            entries: VecDeque::new(),
+            bytes: HashMap::new(),
+            journal: None,
+            dirty: false,
+        };
+        let Some(path) = store.config.path.clone() else {
+            return Ok(store);
+        };
+
+        if let Ok(data) = fs::read(&path) {
+            let mut decoder = FrameDecoder::new();
+            decoder.extend(&data);
+            // Stop at the first frame that doesn't decode: a write torn by power loss
+            while let Ok(Some(frame)) = decoder.next_raw_frame() {
+                let Some(entry) = Entry::from_frame(frame) else {
+                    break;
+                };
+                store.insert(entry);
+            }
+            if decoder.buffer_len() > 0 {
+                println!("[STORE] Dropped a damaged tail from {}", path.display());
+            }
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        store.expire(now_ms());
+        store.rewrite()?;
+        if !store.entries.is_empty() {
+            println!("[STORE] {} frames waiting from before restart", store.entries.len());
+        }
+        Ok(store)
+    }
+
+    /// Keep `frame` if it's telemetry or an ACK, returning whether it was kept
+    pub fn push(&mut self, frame: Bytes) -> Result<bool> {
+        let Some(entry) = Entry::from_frame(frame) else {
+            return Ok(false);
+        };
+        let frame = entry.frame.clone();
+        self.insert(entry);
+
+        self.expire(now_ms());
+        let evicted = self.evict();
+        if evicted > 0 {
+            println!("[STORE] Full, dropped {} frames from the busiest drones", evicted);
+        }
+        if self.dirty {
+            self.rewrite()?;
+            return Ok(true);
+        }
+        if let Some(journal) = &mut self.journal {
+            if let Err(e) = journal.write_all(&frame) {
+                // Kept in memory; the next commit retries the whole journal
+                self.dirty = true;
+                return Err(e.into());
+            }
+        }
+        Ok(true)
+    }
+
+    /// Oldest frame waiting for `device_id`, still stored
+    pub fn front(&self, device_id: &str) -> Option<Bytes> {
+        self.entries
+            .iter()
+            .find(|e| e.device_id == device_id)
+            .map(|e| e.frame.clone())
+    }
+
+    /// Remove the oldest frame for `device_id` once it has been sent
+    ///
+    /// The journal catches up on the next `commit`.
+    pub fn pop_front(&mut self, device_id: &str) {
+        if let Some(index) = self.entries.iter().position(|e| e.device_id == device_id) {
+            self.remove(index);
+        }
+    }
+
+    /// Drop frames older than `max_age`, returning how many went
+    pub fn expire(&mut self, now_ms: u64) -> usize {
+        let max_age_ms = self.config.max_age.as_millis() as u64;
+        let mut expired = 0;
+        while let Some(index) = self
+            .entries
+            .iter()
+            .position(|e| now_ms.saturating_sub(e.timestamp_ms) > max_age_ms)
+        {
+            self.remove(index);
+            expired += 1;
+        }
+        expired
+    }
+
+    /// Bring the journal in line with the store after removals
+    pub fn commit(&mut self) -> Result<()> {
+        if self.dirty {
+            self.rewrite()?;
+        }
+        Ok(())
+    }
+
+    /// Frames waiting for `device_id`
+    pub fn len_for(&self, device_id: &str) -> usize {
+        self.entries.iter().filter(|e| e.device_id == device_id).count()
+    }
+
+    /// Frame bytes waiting for `device_id`
+    pub fn bytes_for(&self, device_id: &str) -> usize {
+        self.bytes.get(device_id).copied().unwrap_or(0)
+    }
+
+    /// Frame bytes waiting across all drones
+    pub fn total_bytes(&self) -> usize {
+        self.bytes.values().sum()
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        *self.bytes.entry(entry.device_id.clone()).or_default() += entry.frame.len();
+        self.entries.push_back(entry);
+    }
+
+    fn remove(&mut self, index: usize) {
+        let entry = self.entries.remove(index).expect("index in range");
+        if let Some(bytes) = self.bytes.get_mut(&entry.device_id) {
+            *bytes -= entry.frame.len();
+            if *bytes == 0 {
+                self.bytes.remove(&entry.device_id);
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Evict until within `max_bytes`, returning how many went
+    fn evict(&mut self) -> usize {
+        let mut evicted = 0;
+        while self.total_bytes() > self.config.max_bytes {
+            let Some(busiest) = self
+                .bytes
+                .iter()
+                .max_by_key(|(_, &bytes)| bytes)
+                .map(|(device_id, _)| device_id.clone())
+            else {
+                break;
+            };
+            let lowest = self
+                .entries
+                .iter()
+                .filter(|e| e.device_id == busiest)
+                .map(|e| e.priority)
+                .min()
+                .expect("busiest drone has frames");
+            let index = self
+                .entries
+                .iter()
+                .position(|e| e.device_id == busiest && e.priority == lowest)
+                .expect("lowest priority is present");
+            self.remove(index);
+            evicted += 1;
+        }
+        evicted
+    }
+
+    /// Replace the journal with the current store
+    fn rewrite(&mut self) -> Result<()> {
+        self.dirty = false;
+        let Some(path) = &self.config.path else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        for entry in &self.entries {
+            file.write_all(&entry.frame)?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        self.journal = Some(OpenOptions::new().append(true).open(path)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::{
+        priority, Ack, DroneState, Envelope, Header, Heartbeat, MessageType, Telemetry,
+    };
+
+    fn frame(device_id: &str, seq: u64, timestamp_ms: u64, payload: envelope::Payload) -> Bytes {
+        let priority = match payload {
+            envelope::Payload::Ack(_) => priority::HIGH,
+            _ => priority::LOW,
+        };
+        let header = Header::with_timestamp(device_id, MessageType::MsgUnknown, seq, timestamp_ms);
+        codec::encode(&Envelope {
+            header: Some(header.with_priority(priority)),
+            payload: Some(payload),
+            signature: Vec::new(),
+        })
+        .unwrap()
+    }
+
+    fn telemetry(device_id: &str, seq: u64, timestamp_ms: u64) -> Bytes {
+        frame(device_id, seq, timestamp_ms, envelope::Payload::Telemetry(Telemetry::default()))
+    }
+
+    fn drain(store: &mut ForwardStore, device_id: &str) -> Vec<Bytes> {
+        let mut frames = Vec::new();
+        while let Some(frame) = store.front(device_id) {
+            store.pop_front(device_id);
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn test_busiest_drone_evicted_first() {
+        let now = now_ms();
+        let ack = frame("edge-002", 1, now, envelope::Payload::Ack(Ack::default()));
+        let size = telemetry("edge-001", 1, now).len();
+        let mut store = ForwardStore::open(StoreConfig {
+            max_bytes: ack.len() + 3 * size,
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(store.push(ack.clone()).unwrap());
+        for seq in 1..=4 {
+            assert!(store.push(telemetry("edge-001", seq, now)).unwrap());
+        }
+        // edge-001 went over the shared limit, so its oldest frame went
+        assert_eq!(store.len_for("edge-001"), 3);
+        assert_eq!(drain(&mut store, "edge-002"), vec![ack]);
+        assert_eq!(store.front("edge-001"), Some(telemetry("edge-001", 2, now)));
+
+        // Heartbeats are stale by the time they'd be replayed
+        let heartbeat = Heartbeat::new(1000, DroneState::DroneIdle, 0, true);
+        let heartbeat = frame("edge-001", 9, now, envelope::Payload::Heartbeat(heartbeat));
+        assert!(!store.push(heartbeat).unwrap());
+    }
+
+    #[test]
+    fn test_survives_restart_and_expires_old_frames() {
+        let name = format!("resqterra-relay-store-{}", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        let config = StoreConfig {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+        let now = now_ms();
+
+        let mut store = ForwardStore::open(config.clone()).unwrap();
+        store.push(telemetry("edge-001", 1, now - 11 * 60 * 1000)).unwrap();
+        store.push(telemetry("edge-001", 2, now)).unwrap();
+        drop(store);
+
+        // The first frame had already expired when the second was pushed
+        let mut store = ForwardStore::open(config).unwrap();
+        assert_eq!(drain(&mut store, "edge-001"), vec![telemetry("edge-001", 2, now)]);
+        let _ = fs::remove_file(&path);
+    }
+}
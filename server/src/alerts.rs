@@ -0,0 +1,224 @@
+//! Dead-drone and detection alerts
+//!
+//! The heartbeat monitor takes drones that stopped sending heartbeats off the
+//! fleet and publishes each as `DroneLost`, with its last state and position
+//! for the search hand-off. The alert reaches operators three ways: the
+//! server log, dashboards on `GET /events` (a `lost` message), and, with
+//! `SERVER_ALERT_WEBHOOK` set, a JSON `POST` to that URL. Webhook deliveries
+//! are retried a few times and never hold up the next alert.
+//!
+//! Detections from onboard perception take the same paths (a `detection`
+//! message); the webhook only gets those at or above
+//! `SERVER_ALERT_DETECTION_CONFIDENCE`.
+
+use crate::events::{EventBus, ServerEvent};
+use resqterra_shared::{DetectionReport, DroneState, GpsPosition};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Attempts per webhook delivery
+const WEBHOOK_ATTEMPTS: u32 = 3;
+
+/// Wait before the second attempt, doubled for each one after
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How long one attempt may take
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Detections below this confidence aren't posted unless
+/// `SERVER_ALERT_DETECTION_CONFIDENCE` is set
+pub const DEFAULT_DETECTION_ALERT_CONFIDENCE: f32 = 0.5;
+
+/// Where to post alerts
+#[derive(Debug, Clone)]
+pub struct AlertConfig {
+    pub webhook: String,
+    /// Detections less certain than this (0-1) aren't posted
+    pub min_detection_confidence: f32,
+}
+
+impl AlertConfig {
+    /// Read `SERVER_ALERT_WEBHOOK` (an `http://` or `https://` URL) and
+    /// `SERVER_ALERT_DETECTION_CONFIDENCE`; None when the webhook isn't set
+    pub fn from_env() -> Option<Self> {
+        let webhook = std::env::var("SERVER_ALERT_WEBHOOK").ok()?;
+        let min_detection_confidence = std::env::var("SERVER_ALERT_DETECTION_CONFIDENCE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_DETECTION_ALERT_CONFIDENCE);
+        Some(Self {
+            webhook,
+            min_detection_confidence,
+        })
+    }
+}
+
+/// A lost drone as posted to the webhook and sent to dashboards
+pub fn lost_json(
+    device_id: &str,
+    state: DroneState,
+    last_seen_ms: u64,
+    last_position: Option<&GpsPosition>,
+) -> Value {
+    let position = last_position.map(|p| {
+        json!({
+            "latitude": p.latitude,
+            "longitude": p.longitude,
+            "altitude_m": p.altitude_m,
+        })
+    });
+    json!({
+        "type": "lost",
+        "device_id": device_id,
+        "state": state.as_str_name(),
+        "last_seen_ms": last_seen_ms,
+        "last_position": position,
+    })
+}
+
+/// A detection as posted to the webhook and sent to dashboards
+pub fn detection_json(device_id: &str, report: &DetectionReport) -> Value {
+    let position = report.position.map(|p| {
+        json!({
+            "latitude": p.latitude,
+            "longitude": p.longitude,
+            "altitude_m": p.altitude_m,
+            "heading_deg": p.heading_deg,
+        })
+    });
+    json!({
+        "type": "detection",
+        "device_id": device_id,
+        "detection_id": report.detection_id,
+        "mission_id": report.mission_id,
+        "label": report.label,
+        "confidence": report.confidence,
+        "sensor_type": report.sensor_type,
+        "position": position,
+        "detected_at_ms": report.detected_at_ms,
+        "thumbnail_ref": report.thumbnail_ref,
+    })
+}
+
+/// Post every `DroneLost`, and every detection certain enough, published on
+/// `events` to the webhook, in the background
+pub fn spawn(config: AlertConfig, events: &EventBus) -> anyhow::Result<()> {
+    let client = reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build()?;
+    let mut events = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let alert = match events.recv().await {
+                Ok(ServerEvent::DroneLost {
+                    device_id,
+                    state,
+                    last_seen_ms,
+                    last_position,
+                }) => lost_json(&device_id, state, last_seen_ms, last_position.as_ref()),
+                Ok(ServerEvent::DetectionReceived { device_id, report })
+                    if report.confidence >= config.min_detection_confidence =>
+                {
+                    detection_json(&device_id, &report)
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(n)) => {
+                    eprintln!("[ALERT] Webhook sender lagged, skipped {} events", n);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            tokio::spawn(deliver(client.clone(), config.webhook.clone(), alert));
+        }
+    });
+    Ok(())
+}
+
+/// Post `alert`, retrying with backoff until it is taken or attempts run out
+async fn deliver(client: reqwest::Client, url: String, alert: Value) {
+    let mut delay = WEBHOOK_RETRY_DELAY;
+    for attempt in 1..=WEBHOOK_ATTEMPTS {
+        let sent = client.post(&url).json(&alert).send().await;
+        match sent.and_then(|response| response.error_for_status()) {
+            Ok(_) => {
+                println!("[ALERT] Posted {} alert for {}", alert["type"], alert["device_id"]);
+                return;
+            }
+            Err(e) if attempt < WEBHOOK_ATTEMPTS => {
+                eprintln!("[ALERT] Webhook attempt {} failed: {}", attempt, e);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => {
+                let device_id = &alert["device_id"];
+                eprintln!("[ALERT] Webhook failed, alert for {} not delivered: {}", device_id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    async fn receive(State(tx): State<mpsc::Sender<Value>>, Json(body): Json<Value>) {
+        tx.send(body).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lost_drone_posted_to_webhook() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let hook = Router::new().route("/hook", post(receive)).with_state(tx);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, hook).await });
+
+        let events = EventBus::new();
+        let config = AlertConfig {
+            webhook: format!("http://{}/hook", addr),
+            min_detection_confidence: DEFAULT_DETECTION_ALERT_CONFIDENCE,
+        };
+        spawn(config, &events).unwrap();
+        let position = GpsPosition {
+            latitude: 47.1,
+            longitude: 8.5,
+            altitude_m: 60.0,
+            ..Default::default()
+        };
+        events.publish(ServerEvent::DroneLost {
+            device_id: "edge-001".into(),
+            state: DroneState::DroneInMission,
+            last_seen_ms: 5000,
+            last_position: Some(position),
+        });
+
+        let alert = rx.recv().await.unwrap();
+        assert_eq!(alert["type"], "lost");
+        assert_eq!(alert["device_id"], "edge-001");
+        assert_eq!(alert["state"], "DRONE_IN_MISSION");
+        assert_eq!(alert["last_position"]["latitude"], 47.1);
+        assert_eq!(alert["last_seen_ms"], 5000);
+
+        // Only detections the detector is sure enough of are posted
+        let detection = |detection_id: &str, confidence| ServerEvent::DetectionReceived {
+            device_id: "edge-001".into(),
+            report: DetectionReport {
+                detection_id: detection_id.into(),
+                label: "person".into(),
+                confidence,
+                position: Some(position),
+                ..Default::default()
+            },
+        };
+        events.publish(detection("d1", 0.2));
+        events.publish(detection("d2", 0.9));
+        let alert = rx.recv().await.unwrap();
+        assert_eq!(alert["type"], "detection");
+        assert_eq!(alert["detection_id"], "d2");
+        assert_eq!(alert["position"]["latitude"], 47.1);
+    }
+}
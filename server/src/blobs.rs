@@ -0,0 +1,212 @@
+//! Blobs uploaded by drones: captured images and map tiles
+//!
+//! A drone sends one blob at a time (see `resqterra_shared::blob`). Its
+//! chunks are held in memory until the `BlobEnd` checks out, then the blob is
+//! written to `{dir}/{device_id}/{blob_id}` and published as `BlobReceived`.
+//! A drone whose link dropped mid-transfer re-sends the `BlobStart` and is
+//! told how far it got; a transfer it abandons is dropped with its next one,
+//! or after `PARTIAL_TTL` once any drone starts another.
+
+use resqterra_shared::{BlobAssembler, BlobChunk, BlobStart, BlobState, BlobStatus};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Where blobs go unless `SERVER_BLOB_DIR` is set
+pub const DEFAULT_BLOB_DIR: &str = "/var/lib/resqterra/blobs";
+
+/// How long an unfinished transfer is kept for its drone to resume
+const PARTIAL_TTL: Duration = Duration::from_secs(3600);
+
+/// Where to store blobs
+#[derive(Debug, Clone)]
+pub struct BlobConfig {
+    pub dir: PathBuf,
+}
+
+impl BlobConfig {
+    /// Read `SERVER_BLOB_DIR`; None when it is `off`
+    pub fn from_env() -> Option<Self> {
+        let dir = std::env::var("SERVER_BLOB_DIR").unwrap_or_else(|_| DEFAULT_BLOB_DIR.into());
+        if dir == "off" {
+            return None;
+        }
+        Some(Self { dir: dir.into() })
+    }
+}
+
+/// A blob written to disk
+#[derive(Debug, Clone)]
+pub struct StoredBlob {
+    pub start: BlobStart,
+    pub path: PathBuf,
+}
+
+/// Reassembles blobs from drones and stores the finished ones
+pub struct BlobStore {
+    dir: PathBuf,
+    /// Transfer in progress per drone, and when it last moved
+    partial: Mutex<HashMap<String, (BlobAssembler, Instant)>>,
+}
+
+impl BlobStore {
+    pub fn new(config: BlobConfig) -> Self {
+        Self {
+            dir: config.dir,
+            partial: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Open or resume `device_id`'s transfer of the blob `start` announces
+    pub fn start(&self, device_id: &str, start: &BlobStart) -> BlobStatus {
+        // Device IDs name directories too, and may contain dots
+        if device_id.starts_with('.') {
+            let error = format!("Device ID {:?} can't name a directory", device_id);
+            return failed(&start.blob_id, error);
+        }
+        let mut partial = self.partial.lock().unwrap();
+        partial.retain(|_, (_, touched)| touched.elapsed() < PARTIAL_TTL);
+        if let Some((assembler, touched)) = partial.get_mut(device_id) {
+            if assembler.resumes(start) {
+                *touched = Instant::now();
+                return status(&start.blob_id, BlobState::BlobReceiving, assembler.received());
+            }
+        }
+        match BlobAssembler::new(start.clone()) {
+            Ok(assembler) => {
+                partial.insert(device_id.to_string(), (assembler, Instant::now()));
+                status(&start.blob_id, BlobState::BlobReceiving, 0)
+            }
+            Err(e) => {
+                partial.remove(device_id);
+                failed(&start.blob_id, e.to_string())
+            }
+        }
+    }
+
+    /// Add a chunk of `device_id`'s transfer; Some(failure) when it has to
+    /// start over
+    pub fn chunk(&self, device_id: &str, chunk: &BlobChunk) -> Option<BlobStatus> {
+        let mut partial = self.partial.lock().unwrap();
+        let (assembler, touched) = partial.get_mut(device_id)?;
+        // Left over from an earlier blob (replayed after a reconnect)
+        if assembler.start().blob_id != chunk.blob_id {
+            return None;
+        }
+        match assembler.push(chunk) {
+            Ok(_) => {
+                *touched = Instant::now();
+                None
+            }
+            Err(e) => {
+                partial.remove(device_id);
+                Some(failed(&chunk.blob_id, e.to_string()))
+            }
+        }
+    }
+
+    /// Finish `device_id`'s transfer of `blob_id`: verified and written to
+    /// disk, or failed
+    pub async fn end(&self, device_id: &str, blob_id: &str) -> (BlobStatus, Option<StoredBlob>) {
+        let assembler = {
+            let mut partial = self.partial.lock().unwrap();
+            match partial.get(device_id) {
+                Some((assembler, _)) if assembler.start().blob_id == blob_id => {
+                    partial.remove(device_id).map(|(assembler, _)| assembler)
+                }
+                _ => None,
+            }
+        };
+        let Some(assembler) = assembler else {
+            return (failed(blob_id, "No transfer in progress".into()), None);
+        };
+        let start = assembler.start().clone();
+        let data = match assembler.finish() {
+            Ok(data) => data,
+            Err(e) => return (failed(blob_id, e.to_string()), None),
+        };
+
+        let path = self.dir.join(device_id).join(blob_id);
+        if let Err(e) = write(&path, &data).await {
+            eprintln!("[BLOB] Can't write {}: {}", path.display(), e);
+            return (failed(blob_id, "Server could not store the blob".into()), None);
+        }
+        let done = status(blob_id, BlobState::BlobComplete, data.len() as u64);
+        (done, Some(StoredBlob { start, path }))
+    }
+}
+
+/// Write `data` under a temporary name first, so readers never see half a file
+async fn write(path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    tokio::fs::write(&partial, data).await?;
+    tokio::fs::rename(&partial, path).await
+}
+
+fn status(blob_id: &str, state: BlobState, received_bytes: u64) -> BlobStatus {
+    BlobStatus {
+        blob_id: blob_id.to_string(),
+        state: state.into(),
+        received_bytes,
+        error: String::new(),
+    }
+}
+
+fn failed(blob_id: &str, error: String) -> BlobStatus {
+    BlobStatus {
+        error,
+        ..status(blob_id, BlobState::BlobFailed, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::blob::{blob_checksum, chunk_blob};
+
+    #[tokio::test]
+    async fn test_blob_resumed_and_stored() {
+        let dir = std::env::temp_dir().join(format!("resqterra-blobs-{}", std::process::id()));
+        let store = BlobStore::new(BlobConfig { dir: dir.clone() });
+        let data: Vec<u8> = (0..40_000).map(|i| (i % 7) as u8).collect();
+        let start = BlobStart {
+            blob_id: "frame-1.jpg".into(),
+            content_type: "image/jpeg".into(),
+            total_size: data.len() as u64,
+            sha256: blob_checksum(&data),
+            name: "d1".into(),
+        };
+        let chunks: Vec<BlobChunk> = chunk_blob("frame-1.jpg", &data, 0).collect();
+
+        assert_eq!(store.start("edge-001", &start).received_bytes, 0);
+        assert!(store.chunk("edge-001", &chunks[0]).is_none());
+        // Link dropped; on reconnect the drone asks how far it got
+        let resumed = store.start("edge-001", &start);
+        assert_eq!(resumed.state(), BlobState::BlobReceiving);
+        assert_eq!(resumed.received_bytes, chunks[0].data.len() as u64);
+        for chunk in &chunks[1..] {
+            assert!(store.chunk("edge-001", chunk).is_none());
+        }
+
+        // Another drone's end doesn't finish it
+        let (other, _) = store.end("edge-002", "frame-1.jpg").await;
+        assert_eq!(other.state(), BlobState::BlobFailed);
+        let (done, stored) = store.end("edge-001", "frame-1.jpg").await;
+        assert_eq!(done.state(), BlobState::BlobComplete);
+        let stored = stored.unwrap();
+        assert_eq!(stored.path, dir.join("edge-001").join("frame-1.jpg"));
+        assert_eq!(std::fs::read(&stored.path).unwrap(), data);
+
+        let bad = BlobStart {
+            blob_id: "../escape".into(),
+            ..start
+        };
+        assert_eq!(store.start("edge-001", &bad).state(), BlobState::BlobFailed);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,139 @@
+//! Per-drone command audit trail for incident review
+
+use resqterra_shared::{AckStatus, Command, CommandType};
+use std::collections::{HashMap, VecDeque};
+
+/// Audit records kept per drone; the oldest are dropped first
+pub const COMMAND_HISTORY_PER_DEVICE: usize = 256;
+
+/// What happened to one command sent to a drone
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandAudit {
+    pub command_id: u64,
+    pub cmd_type: CommandType,
+    pub priority: u32,
+    /// When the operator issued it (ms since epoch)
+    pub queued_at: u64,
+    /// When it was first written to the drone (`None` while queued)
+    pub sent_at: Option<u64>,
+    pub retries: u32,
+    /// Final status (`None` while still in flight)
+    pub outcome: Option<AckStatus>,
+    pub outcome_message: String,
+    /// When the final status was recorded
+    pub finished_at: Option<u64>,
+}
+
+/// Bounded ring buffer of command audits per drone
+#[derive(Debug, Default)]
+pub struct CommandHistory {
+    devices: HashMap<String, VecDeque<CommandAudit>>,
+}
+
+impl CommandHistory {
+    /// Create an empty history
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a command issued to a drone
+    pub fn record_queued(&mut self, device_id: &str, command: &Command, now: u64) {
+        let log = self.devices.entry(device_id.to_string()).or_default();
+        if log.len() == COMMAND_HISTORY_PER_DEVICE {
+            log.pop_front();
+        }
+        log.push_back(CommandAudit {
+            command_id: command.command_id,
+            cmd_type: CommandType::try_from(command.cmd_type).unwrap_or(CommandType::CmdUnknown),
+            priority: command.effective_priority(),
+            queued_at: now,
+            sent_at: None,
+            retries: 0,
+            outcome: None,
+            outcome_message: String::new(),
+            finished_at: None,
+        });
+    }
+
+    /// Record that a command was written to the drone
+    pub fn record_sent(&mut self, device_id: &str, command_id: u64, now: u64) {
+        if let Some(audit) = self.find(device_id, command_id) {
+            audit.sent_at.get_or_insert(now);
+        }
+    }
+
+    /// Record a retry attempt
+    pub fn record_retry(&mut self, device_id: &str, command_id: u64, retries: u32) {
+        if let Some(audit) = self.find(device_id, command_id) {
+            audit.retries = retries;
+        }
+    }
+
+    /// Record the final outcome of a command
+    pub fn record_outcome(
+        &mut self,
+        device_id: &str,
+        command_id: u64,
+        status: AckStatus,
+        message: &str,
+        now: u64,
+    ) {
+        if let Some(audit) = self.find(device_id, command_id) {
+            audit.outcome = Some(status);
+            audit.outcome_message = message.to_string();
+            audit.finished_at = Some(now);
+        }
+    }
+
+    /// All retained audits for a drone, oldest first
+    pub fn for_device(&self, device_id: &str) -> Vec<CommandAudit> {
+        self.devices
+            .get(device_id)
+            .map(|log| log.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The audit of `command_id` and the drone it was sent to, if retained
+    pub fn find_command(&self, command_id: u64) -> Option<(String, CommandAudit)> {
+        self.devices.iter().find_map(|(device_id, log)| {
+            let audit = log.iter().rev().find(|a| a.command_id == command_id)?;
+            Some((device_id.clone(), audit.clone()))
+        })
+    }
+
+    fn find(&mut self, device_id: &str, command_id: u64) -> Option<&mut CommandAudit> {
+        self.devices
+            .get_mut(device_id)?
+            .iter_mut()
+            .rev()
+            .find(|a| a.command_id == command_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_is_bounded_per_device() {
+        let mut history = CommandHistory::new();
+        for id in 0..(COMMAND_HISTORY_PER_DEVICE as u64 + 5) {
+            let command = Command {
+                command_id: id,
+                ..Default::default()
+            };
+            history.record_queued("edge-001", &command, id);
+        }
+        history.record_queued("edge-002", &Command::default(), 0);
+
+        let audits = history.for_device("edge-001");
+        assert_eq!(audits.len(), COMMAND_HISTORY_PER_DEVICE);
+        assert_eq!(audits[0].command_id, 5);
+        assert_eq!(history.for_device("edge-002").len(), 1);
+        assert!(history.for_device("edge-003").is_empty());
+
+        let (device_id, audit) = history.find_command(7).unwrap();
+        assert_eq!((device_id.as_str(), audit.queued_at), ("edge-001", 7));
+        assert!(history.find_command(4).is_none());
+    }
+}
@@ -0,0 +1,264 @@
+//! Durable command audit log for SAR incident review
+//!
+//! Unlike the bounded in-memory `CommandHistory`, the log keeps every step of
+//! every command for good: queued, sent, each ACK from the drone, retries,
+//! replays over a new link and the final outcome. It is an append-only file
+//! of JSON lines, one `AuditEntry` each, synced as it is written so a crash
+//! or power loss right after a command doesn't lose it. Enums are their proto
+//! names, so the file reads well with `grep` and `jq`.
+//!
+//! Queries scan the file. A torn last line (a write cut short, or one still
+//! in progress) is skipped. The log is never pruned; rotate it offline.
+
+use resqterra_shared::{now_ms, AckStatus, CommandType};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Where the log goes unless `SERVER_COMMAND_AUDIT_LOG` is set
+pub const DEFAULT_AUDIT_LOG_PATH: &str = "/var/lib/resqterra/command-audit.jsonl";
+
+/// Most entries one query returns
+pub const MAX_AUDIT_ROWS: usize = 10_000;
+
+/// Where to keep the audit log
+#[derive(Debug, Clone)]
+pub struct AuditLogConfig {
+    pub path: PathBuf,
+}
+
+impl AuditLogConfig {
+    /// Read `SERVER_COMMAND_AUDIT_LOG`; None when it is `off`
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("SERVER_COMMAND_AUDIT_LOG")
+            .unwrap_or_else(|_| DEFAULT_AUDIT_LOG_PATH.into());
+        if path == "off" {
+            return None;
+        }
+        Some(Self { path: path.into() })
+    }
+}
+
+/// One step in the life of a command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the server recorded it (ms since epoch)
+    pub at_ms: u64,
+    pub device_id: String,
+    pub command_id: u64,
+    /// Proto name, e.g. `CMD_RTH`
+    pub cmd_type: String,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+impl AuditEntry {
+    pub fn new(device_id: &str, command_id: u64, cmd_type: CommandType, event: AuditEvent) -> Self {
+        Self {
+            at_ms: now_ms(),
+            device_id: device_id.to_string(),
+            command_id,
+            cmd_type: cmd_type.as_str_name().to_string(),
+            event,
+        }
+    }
+}
+
+/// What happened, in an entry's `event` field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// Issued by an operator or the server
+    Queued { priority: u32 },
+    /// First written to the drone
+    Sent,
+    /// Resent after an ACK timeout; `attempt` counts the resends
+    Retry { attempt: u32 },
+    /// Resent over a new link after the drone resumed its session
+    Replayed,
+    /// An ACK from the drone, in progress or final
+    Ack {
+        /// Proto name, e.g. `ACK_ACCEPTED`
+        status: String,
+        message: String,
+        processing_time_ms: u64,
+    },
+    /// The server's final verdict: the drone's, or a timeout, expiry or
+    /// cancellation of its own
+    Outcome { status: String, message: String },
+}
+
+impl AuditEvent {
+    pub fn ack(status: AckStatus, message: &str, processing_time_ms: u64) -> Self {
+        Self::Ack {
+            status: status.as_str_name().to_string(),
+            message: message.to_string(),
+            processing_time_ms,
+        }
+    }
+
+    pub fn outcome(status: AckStatus, message: &str) -> Self {
+        Self::Outcome {
+            status: status.as_str_name().to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Entries within `[from_ms, to_ms]`, optionally for one drone or command type
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditQuery {
+    pub device_id: Option<String>,
+    pub cmd_type: Option<CommandType>,
+    pub from_ms: u64,
+    pub to_ms: u64,
+    /// The oldest `limit` matching entries are returned
+    pub limit: usize,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        (self.from_ms..=self.to_ms).contains(&entry.at_ms)
+            && self.device_id.as_ref().is_none_or(|id| *id == entry.device_id)
+            && self
+                .cmd_type
+                .is_none_or(|cmd_type| cmd_type.as_str_name() == entry.cmd_type)
+    }
+}
+
+/// The append-only audit log file
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Open (or create) the log at `path`, appending to what is there
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Write `entry` and sync it to disk
+    pub fn append(&self, entry: &AuditEntry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Entries matching `query`, oldest first, at most `MAX_AUDIT_ROWS`
+    pub fn query(&self, query: &AuditQuery) -> anyhow::Result<Vec<AuditEntry>> {
+        let limit = query.limit.min(MAX_AUDIT_ROWS);
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            if entries.len() == limit {
+                break;
+            }
+            let Ok(entry) = serde_json::from_str::<AuditEntry>(&line?) else {
+                continue;
+            };
+            if query.matches(&entry) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// `query` off the async runtime
+    pub async fn search(self: &Arc<Self>, query: AuditQuery) -> anyhow::Result<Vec<AuditEntry>> {
+        let log = self.clone();
+        tokio::task::spawn_blocking(move || log.query(&query)).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_path(name: &str) -> PathBuf {
+        let name = format!("resqterra-audit-{}-{}.jsonl", name, std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn entry(at_ms: u64, device_id: &str, cmd_type: CommandType, event: AuditEvent) -> AuditEntry {
+        AuditEntry {
+            at_ms,
+            ..AuditEntry::new(device_id, 7, cmd_type, event)
+        }
+    }
+
+    #[test]
+    fn test_entries_survive_reopen_and_filter() {
+        let path = log_path("reopen");
+        let entries = [
+            entry(1000, "edge-001", CommandType::CmdRth, AuditEvent::Queued { priority: 2 }),
+            entry(1100, "edge-001", CommandType::CmdRth, AuditEvent::Sent),
+            entry(1200, "edge-002", CommandType::CmdRth, AuditEvent::Sent),
+            entry(
+                1300,
+                "edge-001",
+                CommandType::CmdRth,
+                AuditEvent::ack(AckStatus::AckAccepted, "", 0),
+            ),
+            entry(
+                2000,
+                "edge-001",
+                CommandType::CmdEmergencyStop,
+                AuditEvent::outcome(AckStatus::AckCompleted, "Stopped"),
+            ),
+        ];
+        {
+            let log = AuditLog::open(&path).unwrap();
+            for entry in &entries[..3] {
+                log.append(entry).unwrap();
+            }
+        }
+        let log = AuditLog::open(&path).unwrap();
+        for entry in &entries[3..] {
+            log.append(entry).unwrap();
+        }
+        // A write cut short by a crash
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"at_ms\":3000,\"dev")
+            .unwrap();
+
+        let query = AuditQuery {
+            device_id: Some("edge-001".into()),
+            cmd_type: Some(CommandType::CmdRth),
+            from_ms: 1050,
+            to_ms: 5000,
+            limit: 10,
+        };
+        assert_eq!(log.query(&query).unwrap(), [entries[1].clone(), entries[3].clone()]);
+        let all = AuditQuery {
+            device_id: None,
+            cmd_type: None,
+            from_ms: 0,
+            to_ms: u64::MAX,
+            limit: 10,
+        };
+        assert_eq!(log.query(&all).unwrap(), entries);
+        assert_eq!(log.query(&AuditQuery { limit: 2, ..all }).unwrap().len(), 2);
+
+        let line = fs::read_to_string(&path).unwrap();
+        let first: serde_json::Value = serde_json::from_str(line.lines().next().unwrap()).unwrap();
+        assert_eq!((&first["event"], &first["cmd_type"]), (&"queued".into(), &"CMD_RTH".into()));
+        let _ = fs::remove_file(&path);
+    }
+}
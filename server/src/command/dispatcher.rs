@@ -1,13 +1,28 @@
 //! Command dispatcher for sending commands to drones
 
+use super::audit::{CommandAudit, CommandHistory};
+use super::audit_log::{AuditEntry, AuditEvent, AuditLog};
+use super::queue::{CommandLimits, CommandQueue};
+use super::upload::{self, UploadLimiter, UploadLimits};
+use crate::events::{EventBus, ServerEvent};
 use crate::session::SessionManager;
 use resqterra_shared::{
-    envelope, Command, CommandType, Envelope, Header, MessageType, now_ms, safety,
+    command, envelope, mission_upload, priority, AckStatus, CancelCommand, Command, CommandType,
+    DeviceType, Envelope, GpsCoordinate, Header, MessageType, MissionProgress, MissionStart,
+    now_ms, safety,
 };
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
+
+/// The mission last sent to a drone, with the waypoints uploaded for it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AssignedMission {
+    pub mission: MissionStart,
+    /// Empty when the drone generates its path from the survey area
+    pub waypoints: Vec<GpsCoordinate>,
+}
 
 /// Tracks a sent command awaiting response
 #[derive(Debug, Clone)]
@@ -20,6 +35,10 @@ pub struct PendingCommand {
     pub expires_at: u64,
     pub retries: u32,
     pub max_retries: u32,
+    /// Drone acknowledged receipt (it may still be executing)
+    pub acked: bool,
+    /// The command as sent, for retries
+    pub command: Command,
 }
 
 impl PendingCommand {
@@ -47,17 +66,150 @@ pub struct CommandDispatcher {
     command_id: Arc<AtomicU64>,
     /// Pending commands by command_id
     pending: Arc<RwLock<HashMap<u64, PendingCommand>>>,
+    /// Commands waiting for the drone to ACK the previous one
+    queue: Arc<Mutex<CommandQueue>>,
+    /// Bounded audit trail per drone
+    history: Arc<RwLock<CommandHistory>>,
+    /// Fleet-wide slots for mission uploads and other large commands
+    uploads: Arc<Mutex<UploadLimiter>>,
+    /// Command outcomes are published here
+    events: EventBus,
+    /// Durable record of every command step, if one is kept
+    audit_log: Option<Arc<AuditLog>>,
+    /// Latest progress each drone reported for the mission it flies
+    mission_progress: Arc<RwLock<HashMap<String, MissionProgress>>>,
+    /// Mission last sent to each drone
+    missions: Arc<RwLock<HashMap<String, AssignedMission>>>,
 }
 
 impl CommandDispatcher {
     /// Create a new command dispatcher
     pub fn new(session_manager: Arc<SessionManager>, sequence_id: Arc<AtomicU64>) -> Self {
+        let events = session_manager.events();
         Self {
             session_manager,
             sequence_id,
             command_id: Arc::new(AtomicU64::new(0)),
             pending: Arc::new(RwLock::new(HashMap::new())),
+            queue: Arc::new(Mutex::new(CommandQueue::new())),
+            history: Arc::new(RwLock::new(CommandHistory::new())),
+            uploads: Arc::new(Mutex::new(UploadLimiter::new(UploadLimits::default()))),
+            events,
+            audit_log: None,
+            mission_progress: Arc::new(RwLock::new(HashMap::new())),
+            missions: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Limit how many large commands may be in flight per transport
+    pub fn with_upload_limits(mut self, limits: UploadLimits) -> Self {
+        self.uploads = Arc::new(Mutex::new(UploadLimiter::new(limits)));
+        self
+    }
+
+    /// Limit each drone's in-flight commands, command rate and reported backlog
+    pub fn with_command_limits(mut self, limits: CommandLimits) -> Self {
+        self.queue = Arc::new(Mutex::new(CommandQueue::new().with_limits(limits)));
+        self
+    }
+
+    /// Append every command step to `log` as well as the in-memory history
+    pub fn with_audit_log(mut self, log: Arc<AuditLog>) -> Self {
+        self.audit_log = Some(log);
+        self
+    }
+
+    /// The durable audit log, if one is kept
+    pub fn audit_log(&self) -> Option<Arc<AuditLog>> {
+        self.audit_log.clone()
+    }
+
+    /// Append a command step to the durable audit log, if one is kept
+    fn log_audit(
+        &self,
+        device_id: &str,
+        command_id: u64,
+        cmd_type: CommandType,
+        event: AuditEvent,
+    ) {
+        let Some(log) = &self.audit_log else {
+            return;
+        };
+        let entry = AuditEntry::new(device_id, command_id, cmd_type, event);
+        if let Err(e) = log.append(&entry) {
+            eprintln!("[AUDIT] Failed to log command {} for {}: {}", command_id, device_id, e);
+        }
+    }
+
+    /// Record that a command was issued to a drone
+    async fn record_queued(&self, device_id: &str, command: &Command) {
+        self.history
+            .write()
+            .await
+            .record_queued(device_id, command, now_ms());
+        let event = AuditEvent::Queued {
+            priority: command.effective_priority(),
+        };
+        self.log_audit(device_id, command.command_id, command.cmd_type(), event);
+    }
+
+    /// Record and publish the final outcome of a command
+    async fn publish_outcome(
+        &self,
+        device_id: &str,
+        command_id: u64,
+        cmd_type: CommandType,
+        status: AckStatus,
+        message: impl Into<String>,
+    ) {
+        let message = message.into();
+        if status == AckStatus::AckCompleted {
+            self.session_manager.expect_command_state(device_id, cmd_type).await;
         }
+        self.history
+            .write()
+            .await
+            .record_outcome(device_id, command_id, status, &message, now_ms());
+        self.log_audit(device_id, command_id, cmd_type, AuditEvent::outcome(status, &message));
+        self.events.publish(ServerEvent::CommandOutcome {
+            device_id: device_id.to_string(),
+            command_id,
+            cmd_type,
+            status,
+            message,
+        });
+    }
+
+    /// Every retained command sent to a drone with its outcome, oldest first
+    pub async fn command_history(&self, device_id: &str) -> Vec<CommandAudit> {
+        self.history.read().await.for_device(device_id)
+    }
+
+    /// A retained command's audit and the drone it was sent to
+    pub async fn command_status(&self, command_id: u64) -> Option<(String, CommandAudit)> {
+        self.history.read().await.find_command(command_id)
+    }
+
+    /// Store a drone's mission progress, returning what it reported before
+    pub async fn record_mission_progress(
+        &self,
+        device_id: &str,
+        progress: MissionProgress,
+    ) -> Option<MissionProgress> {
+        self.mission_progress
+            .write()
+            .await
+            .insert(device_id.to_string(), progress)
+    }
+
+    /// Latest progress of the mission a drone flies (or last flew)
+    pub async fn mission_progress(&self, device_id: &str) -> Option<MissionProgress> {
+        self.mission_progress.read().await.get(device_id).cloned()
+    }
+
+    /// The mission last sent to a drone, whether or not it is still flying it
+    pub async fn assigned_mission(&self, device_id: &str) -> Option<AssignedMission> {
+        self.missions.read().await.get(device_id).cloned()
     }
 
     /// Get the next command ID
@@ -71,19 +223,162 @@ impl CommandDispatcher {
     }
 
     /// Send a command to a specific drone
+    ///
+    /// The command is queued by priority (then FIFO) behind commands the drone
+    /// has not acknowledged yet. Emergency stops bypass the queue. Refused
+    /// while the drone reports more pending commands than `CommandLimits`
+    /// allows, unless it gets through a backlog (see [`bypasses_backlog`]).
     pub async fn send_command(
         &self,
         device_id: &str,
         command: Command,
     ) -> anyhow::Result<u64> {
+        let Some(info) = self.session_manager.get_info(device_id).await else {
+            return Err(anyhow::anyhow!("Drone not connected: {}", device_id));
+        };
+        if info.device_type == DeviceType::DeviceRelay {
+            return Err(anyhow::anyhow!("{} is a relay and takes no commands", device_id));
+        }
+
+        let emergency = command.effective_priority() >= priority::EMERGENCY;
+        let max_pending = self.queue.lock().await.limits().max_drone_pending;
+        if !bypasses_backlog(&command) && info.pending_commands >= max_pending {
+            return Err(anyhow::anyhow!(
+                "Drone {} busy: {} commands pending",
+                device_id,
+                info.pending_commands
+            ));
+        }
+
+        let cmd_id = command.command_id;
+        self.record_queued(device_id, &command).await;
+        if let Some(command::Params::MissionStart(mission)) = &command.params {
+            let mut missions = self.missions.write().await;
+            let assigned = missions.entry(device_id.to_string()).or_default();
+            // Waypoints uploaded ahead of this start stay with it
+            if assigned.mission.mission_id != mission.mission_id {
+                assigned.waypoints.clear();
+            }
+            assigned.mission = mission.clone();
+        }
+
+        if emergency {
+            self.transmit(device_id, command).await?;
+        } else {
+            let mut queue = self.queue.lock().await;
+            queue.push(device_id, command);
+            self.dispatch_queued(device_id, &mut queue).await;
+        }
+
+        Ok(cmd_id)
+    }
+
+    /// Send queued commands for a drone while it has capacity for unacked ones
+    ///
+    /// Uploads also need a free slot on the drone's transport; without one the
+    /// drone's queue holds until another upload finishes. Commands over the
+    /// rate limit wait for `dispatch_ready`.
+    async fn dispatch_queued(&self, device_id: &str, queue: &mut CommandQueue) {
+        let max_in_flight = queue.limits().max_in_flight;
+        while self.unacked_count_for(device_id).await < max_in_flight {
+            if !queue.rate_allows(device_id, now_ms()) {
+                break;
+            }
+            let Some(next) = queue.peek(device_id) else {
+                break;
+            };
+            if upload::is_upload(next) && !next.is_expired() {
+                let cmd_id = next.command_id;
+                let transport = self.session_manager.transport_of(device_id).await;
+                let mut uploads = self.uploads.lock().await;
+                if !uploads.try_acquire(device_id, cmd_id, transport) {
+                    println!(
+                        "Command {} for {} waiting for a {:?} upload slot ({} in flight)",
+                        cmd_id,
+                        device_id,
+                        transport,
+                        uploads.in_flight()
+                    );
+                    break;
+                }
+            }
+
+            let Some(command) = queue.pop(device_id) else {
+                break;
+            };
+
+            if command.is_expired() {
+                println!("Command {} expired in queue", command.command_id);
+                let cmd_type =
+                    CommandType::try_from(command.cmd_type).unwrap_or(CommandType::CmdUnknown);
+                self.publish_outcome(
+                    device_id,
+                    command.command_id,
+                    cmd_type,
+                    AckStatus::AckExpired,
+                    "Expired in queue",
+                )
+                .await;
+                continue;
+            }
+
+            let cmd_id = command.command_id;
+            queue.record_sent(device_id, now_ms());
+            if let Err(e) = self.transmit(device_id, command).await {
+                // Still tracked as pending, so the retry/timeout path takes over
+                eprintln!("Failed to send command {} to {}: {}", cmd_id, device_id, e);
+            }
+        }
+    }
+
+    /// Send queued commands for a drone after one of its commands was ACKed or dropped
+    async fn advance_queue(&self, device_id: &str) {
+        let mut queue = self.queue.lock().await;
+        self.dispatch_queued(device_id, &mut queue).await;
+    }
+
+    /// Send queued commands for every drone that has room again
+    pub async fn dispatch_ready(&self) {
+        let mut queue = self.queue.lock().await;
+        for device_id in queue.devices() {
+            self.dispatch_queued(&device_id, &mut queue).await;
+        }
+    }
+
+    /// Free the upload slot of a finished command and let waiting drones use it
+    async fn release_upload_slot(&self, command_id: u64) {
+        let waiting = {
+            let mut uploads = self.uploads.lock().await;
+            if !uploads.release(command_id) {
+                return;
+            }
+            uploads.take_waiting()
+        };
+        for device_id in waiting {
+            self.advance_queue(&device_id).await;
+        }
+    }
+
+    /// Wrap a command in an envelope, marking resends with their retry count
+    fn command_envelope(command: &Command, seq: u64, retries: u32) -> Envelope {
+        Envelope {
+            header: Some(
+                Header::new("server", MessageType::MsgCommand, seq)
+                    .with_priority(command.effective_priority())
+                    .with_retry_count(retries),
+            ),
+            payload: Some(envelope::Payload::Command(command.clone())),
+            signature: Vec::new(),
+        }
+    }
+
+    /// Track a command as pending and write it to the drone
+    async fn transmit(&self, device_id: &str, command: Command) -> anyhow::Result<()> {
         let seq = self.next_sequence_id();
         let cmd_id = command.command_id;
         let cmd_type = CommandType::try_from(command.cmd_type).unwrap_or(CommandType::CmdUnknown);
 
-        let envelope = Envelope {
-            header: Some(Header::new("server", MessageType::MsgCommand, seq)),
-            payload: Some(envelope::Payload::Command(command.clone())),
-        };
+        let envelope = Self::command_envelope(&command, seq, 0);
 
         // Track pending command
         let pending = PendingCommand {
@@ -95,9 +390,16 @@ impl CommandDispatcher {
             expires_at: command.expires_at_ms,
             retries: 0,
             max_retries: safety::COMMAND_MAX_RETRIES,
+            acked: false,
+            command,
         };
 
         self.pending.write().await.insert(cmd_id, pending);
+        self.history
+            .write()
+            .await
+            .record_sent(device_id, cmd_id, now_ms());
+        self.log_audit(device_id, cmd_id, cmd_type, AuditEvent::Sent);
 
         // Send to drone
         self.session_manager.send_to(device_id, &envelope).await?;
@@ -107,7 +409,90 @@ impl CommandDispatcher {
             cmd_id, cmd_type, device_id, seq
         );
 
-        Ok(cmd_id)
+        Ok(())
+    }
+
+    /// Cancel a command
+    ///
+    /// A command still queued is dropped without reaching the drone. One
+    /// already sent gets a `CMD_CANCEL`, bypassing the queue so it doesn't wait
+    /// behind the command it cancels; the drone then NAKs the target with
+    /// `ACK_CANCELLED`. Returns the command ID of the cancel, or of the dropped
+    /// command itself.
+    pub async fn cancel(&self, command_id: u64) -> anyhow::Result<u64> {
+        let removed = self.queue.lock().await.remove(command_id);
+        if let Some((device_id, command)) = removed {
+            println!("Command {} cancelled before sending", command_id);
+            let cmd_type =
+                CommandType::try_from(command.cmd_type).unwrap_or(CommandType::CmdUnknown);
+            self.publish_outcome(
+                &device_id,
+                command_id,
+                cmd_type,
+                AckStatus::AckCancelled,
+                "Cancelled in queue",
+            )
+            .await;
+            return Ok(command_id);
+        }
+
+        let device_id = self
+            .pending
+            .read()
+            .await
+            .get(&command_id)
+            .map(|c| c.device_id.clone())
+            .ok_or_else(|| anyhow::anyhow!("Command {} is not pending", command_id))?;
+
+        let cancel = Command {
+            command_id: self.next_command_id(),
+            cmd_type: CommandType::CmdCancel.into(),
+            priority: priority::HIGH,
+            params: Some(command::Params::Cancel(CancelCommand {
+                target_command_id: command_id,
+            })),
+            ..Default::default()
+        };
+        let cancel_id = cancel.command_id;
+        self.record_queued(&device_id, &cancel).await;
+        self.transmit(&device_id, cancel).await?;
+        Ok(cancel_id)
+    }
+
+    /// Stream a mission's waypoints to a drone ahead of its mission start
+    ///
+    /// Queues one `CMD_MISSION_UPLOAD` per chunk; the queue sends each once the
+    /// drone has ACKed the one before. The mission start that follows sets
+    /// `MissionStart.uploaded_waypoints` to `waypoints.len()`. Returns the
+    /// command IDs of the chunks.
+    pub async fn upload_mission(
+        &self,
+        device_id: &str,
+        mission_id: &str,
+        waypoints: &[GpsCoordinate],
+    ) -> anyhow::Result<Vec<u64>> {
+        let mut command_ids = Vec::new();
+        for chunk in mission_upload::chunk_mission(mission_id, waypoints) {
+            let command = Command {
+                command_id: self.next_command_id(),
+                cmd_type: CommandType::CmdMissionUpload.into(),
+                priority: priority::NORMAL,
+                params: Some(command::Params::MissionUpload(chunk)),
+                ..Default::default()
+            };
+            command_ids.push(self.send_command(device_id, command).await?);
+        }
+        self.missions.write().await.insert(
+            device_id.to_string(),
+            AssignedMission {
+                mission: MissionStart {
+                    mission_id: mission_id.to_string(),
+                    ..Default::default()
+                },
+                waypoints: waypoints.to_vec(),
+            },
+        );
+        Ok(command_ids)
     }
 
     /// Broadcast a command to all connected drones
@@ -116,6 +501,12 @@ impl CommandDispatcher {
         let mut command_ids = Vec::new();
 
         for device_id in devices {
+            // Relays take no commands
+            if let Some(info) = self.session_manager.get_info(&device_id).await {
+                if info.device_type == DeviceType::DeviceRelay {
+                    continue;
+                }
+            }
             // Each drone gets a unique command_id
             command.command_id = self.next_command_id();
 
@@ -135,24 +526,53 @@ impl CommandDispatcher {
 
         let mut pending = self.pending.write().await;
 
-        if let Some(cmd) = pending.get(&ack.command_id) {
+        if let Some(cmd) = pending.get_mut(&ack.command_id) {
             println!(
                 "<<< ACK for command {} from {}: {:?} ({}ms)",
                 ack.command_id, device_id, status, ack.processing_time_ms
             );
+            let event = AuditEvent::ack(status, &ack.message, ack.processing_time_ms);
+            self.log_audit(device_id, ack.command_id, cmd.cmd_type, event);
 
+            let mut finished = false;
             match status {
                 resqterra_shared::AckStatus::AckCompleted
                 | resqterra_shared::AckStatus::AckFailed
                 | resqterra_shared::AckStatus::AckRejected
-                | resqterra_shared::AckStatus::AckExpired => {
+                | resqterra_shared::AckStatus::AckExpired
+                | resqterra_shared::AckStatus::AckCancelled => {
                     // Command is done, remove from pending
-                    pending.remove(&ack.command_id);
+                    finished = true;
+                    if let Some(cmd) = pending.remove(&ack.command_id) {
+                        self.publish_outcome(
+                            &cmd.device_id,
+                            cmd.command_id,
+                            cmd.cmd_type,
+                            status,
+                            ack.message.clone(),
+                        )
+                        .await;
+                    }
                 }
                 resqterra_shared::AckStatus::AckReceived
                 | resqterra_shared::AckStatus::AckAccepted => {
                     // Command is being processed, keep tracking
-                    println!("    Command {} is being processed", ack.command_id);
+                    cmd.acked = true;
+                    match &ack.progress {
+                        // A transfer still making progress is not timed out
+                        Some(progress) => {
+                            cmd.sent_at = now_ms();
+                            println!(
+                                "    Command {} transferring: chunk {}/{} ({}/{} bytes)",
+                                ack.command_id,
+                                progress.chunks_sent,
+                                progress.total_chunks,
+                                progress.bytes_sent,
+                                progress.total_bytes
+                            );
+                        }
+                        None => println!("    Command {} is being processed", ack.command_id),
+                    }
                 }
                 _ => {}
             }
@@ -160,6 +580,13 @@ impl CommandDispatcher {
             if !ack.message.is_empty() {
                 println!("    Message: {}", ack.message);
             }
+
+            // Any ACK frees the drone's slot for the next queued command
+            drop(pending);
+            if finished {
+                self.release_upload_slot(ack.command_id).await;
+            }
+            self.advance_queue(device_id).await;
         } else {
             println!(
                 "<<< ACK for unknown command {} from {}",
@@ -178,13 +605,29 @@ impl CommandDispatcher {
             .collect()
     }
 
-    /// Retry a timed out command
+    /// Retry a timed out command, re-sending it to the drone
+    ///
+    /// The resend gets a fresh sequence ID (the drone drops repeated ones) and
+    /// its retry count in the header; the drone answers a command it already
+    /// ran with the same ACK instead of running it again.
     pub async fn retry_command(&self, command_id: u64) -> anyhow::Result<()> {
         let mut pending = self.pending.write().await;
 
         if let Some(cmd) = pending.get_mut(&command_id) {
             if !cmd.can_retry() {
-                pending.remove(&command_id);
+                if let Some(cmd) = pending.remove(&command_id) {
+                    self.publish_outcome(
+                        &cmd.device_id,
+                        cmd.command_id,
+                        cmd.cmd_type,
+                        AckStatus::AckFailed,
+                        "No ACK after max retries",
+                    )
+                    .await;
+                    drop(pending);
+                    self.release_upload_slot(command_id).await;
+                    self.advance_queue(&cmd.device_id).await;
+                }
                 return Err(anyhow::anyhow!(
                     "Command {} exceeded max retries or expired",
                     command_id
@@ -193,6 +636,15 @@ impl CommandDispatcher {
 
             cmd.retries += 1;
             cmd.sent_at = now_ms();
+            cmd.sequence_id = self.next_sequence_id();
+            self.history
+                .write()
+                .await
+                .record_retry(&cmd.device_id, command_id, cmd.retries);
+            let event = AuditEvent::Retry {
+                attempt: cmd.retries,
+            };
+            self.log_audit(&cmd.device_id, command_id, cmd.cmd_type, event);
 
             println!(
                 ">>> Retrying command {} (attempt {}/{})",
@@ -201,13 +653,51 @@ impl CommandDispatcher {
                 cmd.max_retries + 1
             );
 
-            // TODO: Re-send the actual command
-            // For now, we just update the tracking
+            let envelope = Self::command_envelope(&cmd.command, cmd.sequence_id, cmd.retries);
+            let device_id = cmd.device_id.clone();
+            drop(pending);
+            self.session_manager.send_to(&device_id, &envelope).await?;
         }
 
         Ok(())
     }
 
+    /// Resend a drone's unacknowledged commands after it resumed its session
+    ///
+    /// They may have been lost with the old link, so they go out again right
+    /// away instead of waiting out the ACK timeout; this doesn't use up a
+    /// retry. The drone answers one it already got with the same ACK. Then
+    /// queued commands follow. Returns the IDs of the resent commands.
+    pub async fn replay_pending(&self, device_id: &str) -> Vec<u64> {
+        let mut pending = self.pending.write().await;
+        let mut resend: Vec<(u64, Envelope)> = Vec::new();
+        for cmd in pending
+            .values_mut()
+            .filter(|c| c.device_id == device_id && !c.acked && !c.is_expired())
+        {
+            cmd.sent_at = now_ms();
+            cmd.sequence_id = self.next_sequence_id();
+            let envelope = Self::command_envelope(&cmd.command, cmd.sequence_id, cmd.retries);
+            self.log_audit(device_id, cmd.command_id, cmd.cmd_type, AuditEvent::Replayed);
+            resend.push((cmd.command_id, envelope));
+        }
+        drop(pending);
+
+        // In the order they were first sent, as one write
+        resend.sort_by_key(|(command_id, _)| *command_id);
+        let (command_ids, envelopes): (Vec<u64>, Vec<Envelope>) = resend.into_iter().unzip();
+        if !envelopes.is_empty() {
+            match self.session_manager.send_batch_to(device_id, &envelopes).await {
+                Ok(()) => println!(">>> Replayed commands {:?} to {}", command_ids, device_id),
+                Err(e) => {
+                    eprintln!("Failed to replay commands {:?} to {}: {}", command_ids, device_id, e)
+                }
+            }
+        }
+        self.advance_queue(device_id).await;
+        command_ids
+    }
+
     /// Remove expired commands
     pub async fn cleanup_expired(&self) -> Vec<u64> {
         let mut pending = self.pending.write().await;
@@ -217,15 +707,37 @@ impl CommandDispatcher {
             .map(|(id, _)| *id)
             .collect();
 
+        let mut devices = Vec::new();
         for id in &expired {
-            pending.remove(id);
+            if let Some(cmd) = pending.remove(id) {
+                self.publish_outcome(
+                    &cmd.device_id,
+                    cmd.command_id,
+                    cmd.cmd_type,
+                    AckStatus::AckExpired,
+                    "Expired awaiting ACK",
+                )
+                .await;
+                devices.push(cmd.device_id);
+            }
             println!("Command {} expired and removed", id);
         }
+        drop(pending);
+
+        for id in &expired {
+            self.release_upload_slot(*id).await;
+        }
+        devices.sort();
+        devices.dedup();
+        for device_id in devices {
+            self.advance_queue(&device_id).await;
+        }
 
         expired
     }
 
     /// Get count of pending commands
+    #[cfg(test)]
     pub async fn pending_count(&self) -> usize {
         self.pending.read().await.len()
     }
@@ -239,4 +751,455 @@ impl CommandDispatcher {
             .filter(|c| c.device_id == device_id)
             .count()
     }
+
+    /// Get count of commands sent to a drone that it has not ACKed yet
+    async fn unacked_count_for(&self, device_id: &str) -> usize {
+        self.pending
+            .read()
+            .await
+            .values()
+            .filter(|c| c.device_id == device_id && !c.acked)
+            .count()
+    }
+
+    /// Get count of commands queued for a drone but not sent yet
+    pub async fn queued_count_for(&self, device_id: &str) -> usize {
+        self.queue.lock().await.len_for(device_id)
+    }
+}
+
+/// Commands sent even while the drone reports a backlog: anything HIGH or
+/// above, and RTH and emergency stops whatever priority they were sent at
+///
+/// A backlogged drone is often one in trouble, which is when the operator
+/// most needs to bring it home.
+fn bypasses_backlog(command: &Command) -> bool {
+    command.effective_priority() >= priority::HIGH
+        || [CommandType::CmdRth, CommandType::CmdEmergencyStop]
+            .iter()
+            .any(|&t| command.cmd_type == t as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::DroneSession;
+    use futures::StreamExt;
+    use resqterra_shared::codec::EnvelopeCodec;
+    use resqterra_shared::{Ack, ConnectionQuality, Transport};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::codec::FramedRead;
+
+    /// Register a loopback session for `device_id`; keep the client alive
+    async fn connect(session_manager: &SessionManager, device_id: &str) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+
+        let mut session = DroneSession::new(stream, addr);
+        session.handle.device_id = device_id.into();
+        session_manager.register(session.get_handle()).await;
+        client
+    }
+
+    fn command(command_id: u64, cmd_type: CommandType, priority: u32) -> Command {
+        Command {
+            command_id,
+            cmd_type: cmd_type.into(),
+            priority,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_higher_priority_command_jumps_queue() {
+        let session_manager = Arc::new(SessionManager::new());
+        let _client = connect(&session_manager, "edge-001").await;
+        let dispatcher = CommandDispatcher::new(session_manager, Arc::new(AtomicU64::new(0)));
+
+        // The first command goes straight out; the rest wait for its ACK
+        for (id, prio) in [(1, priority::NORMAL), (2, priority::LOW), (3, priority::LOW), (4, priority::HIGH)] {
+            dispatcher
+                .send_command("edge-001", command(id, CommandType::CmdStatusRequest, prio))
+                .await
+                .unwrap();
+        }
+        assert_eq!(dispatcher.pending_count_for("edge-001").await, 1);
+        assert_eq!(dispatcher.queued_count_for("edge-001").await, 3);
+
+        // Emergency stops never wait in the queue
+        dispatcher
+            .send_command("edge-001", command(5, CommandType::CmdEmergencyStop, priority::LOW))
+            .await
+            .unwrap();
+        assert!(dispatcher.pending.read().await.contains_key(&5));
+        assert_eq!(dispatcher.queued_count_for("edge-001").await, 3);
+
+        dispatcher.handle_ack("edge-001", &Ack::completed(0, 5, 1)).await;
+        let mut sent_order = Vec::new();
+        for acked in [1, 4, 2] {
+            dispatcher.handle_ack("edge-001", &Ack::completed(0, acked, 1)).await;
+            sent_order.extend(dispatcher.pending.read().await.keys().copied());
+        }
+        assert_eq!(sent_order, [4, 2, 3]);
+        assert_eq!(dispatcher.queued_count_for("edge-001").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_command_completion_publishes_outcome() {
+        let session_manager = Arc::new(SessionManager::new());
+        let dispatcher = CommandDispatcher::new(session_manager.clone(), Arc::new(AtomicU64::new(0)));
+        let mut events = session_manager.events().subscribe();
+
+        dispatcher.pending.write().await.insert(
+            7,
+            PendingCommand {
+                command_id: 7,
+                sequence_id: 1,
+                device_id: "edge-001".into(),
+                cmd_type: CommandType::CmdRth,
+                sent_at: now_ms(),
+                expires_at: 0,
+                retries: 0,
+                max_retries: safety::COMMAND_MAX_RETRIES,
+                acked: false,
+                command: command(7, CommandType::CmdRth, priority::NORMAL),
+            },
+        );
+
+        // In-progress ACKs are not outcomes
+        dispatcher.handle_ack("edge-001", &Ack::received(1, 7)).await;
+        dispatcher.handle_ack("edge-001", &Ack::completed(1, 7, 25)).await;
+
+        match events.try_recv().expect("outcome event") {
+            ServerEvent::CommandOutcome { device_id, command_id, cmd_type, status, .. } => {
+                assert_eq!(device_id, "edge-001");
+                assert_eq!(command_id, 7);
+                assert_eq!(cmd_type, CommandType::CmdRth);
+                assert_eq!(status, AckStatus::AckCompleted);
+            }
+            other => panic!("expected CommandOutcome, got {:?}", other),
+        }
+        assert!(events.try_recv().is_err());
+        assert_eq!(dispatcher.pending_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_command_history_records_outcomes() {
+        let session_manager = Arc::new(SessionManager::new());
+        let _client = connect(&session_manager, "edge-001").await;
+        let dispatcher = CommandDispatcher::new(session_manager, Arc::new(AtomicU64::new(0)));
+
+        for (id, cmd_type) in [(1, CommandType::CmdRth), (2, CommandType::CmdMissionAbort), (3, CommandType::CmdStatusRequest)] {
+            dispatcher
+                .send_command("edge-001", command(id, cmd_type, priority::NORMAL))
+                .await
+                .unwrap();
+        }
+        dispatcher.handle_ack("edge-001", &Ack::completed(0, 1, 5)).await;
+        dispatcher.handle_ack("edge-001", &Ack::rejected(0, 2, "not in mission")).await;
+        dispatcher.retry_command(3).await.unwrap();
+
+        let history = dispatcher.command_history("edge-001").await;
+        assert_eq!(history.len(), 3);
+
+        assert_eq!(history[0].cmd_type, CommandType::CmdRth);
+        assert_eq!(history[0].outcome, Some(AckStatus::AckCompleted));
+        assert!(history[0].sent_at.is_some() && history[0].finished_at.is_some());
+
+        assert_eq!(history[1].outcome, Some(AckStatus::AckRejected));
+        assert_eq!(history[1].outcome_message, "not in mission");
+
+        // Still in flight after one retry
+        assert_eq!(history[2].outcome, None);
+        assert_eq!(history[2].retries, 1);
+        assert!(dispatcher.command_history("edge-002").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mission_progress_kept_per_drone() {
+        let session_manager = Arc::new(SessionManager::new());
+        let dispatcher = CommandDispatcher::new(session_manager, Arc::new(AtomicU64::new(0)));
+        let progress = |current_waypoint| MissionProgress {
+            mission_id: "survey-7".into(),
+            current_waypoint,
+            total_waypoints: 10,
+            percent_complete: current_waypoint as f32 * 10.0,
+            ..Default::default()
+        };
+
+        assert!(dispatcher.record_mission_progress("edge-001", progress(2)).await.is_none());
+        let previous = dispatcher.record_mission_progress("edge-001", progress(3)).await;
+        assert_eq!(previous.map(|p| p.current_waypoint), Some(2));
+
+        let latest = dispatcher.mission_progress("edge-001").await.unwrap();
+        assert_eq!(latest.current_waypoint, 3);
+        assert_eq!(latest.percent_complete, 30.0);
+        assert!(dispatcher.mission_progress("edge-002").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_records_every_step() {
+        use crate::command::audit_log::AuditQuery;
+
+        let path = std::env::temp_dir()
+            .join(format!("resqterra-dispatch-audit-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = Arc::new(AuditLog::open(&path).unwrap());
+        let session_manager = Arc::new(SessionManager::new());
+        let _client = connect(&session_manager, "edge-001").await;
+        let dispatcher = CommandDispatcher::new(session_manager, Arc::new(AtomicU64::new(0)))
+            .with_audit_log(log.clone());
+
+        dispatcher
+            .send_command("edge-001", command(1, CommandType::CmdRth, priority::HIGH))
+            .await
+            .unwrap();
+        dispatcher.retry_command(1).await.unwrap();
+        dispatcher.handle_ack("edge-001", &Ack::received(0, 1)).await;
+        dispatcher.handle_ack("edge-001", &Ack::completed(0, 1, 40)).await;
+
+        let query = AuditQuery {
+            device_id: Some("edge-001".into()),
+            cmd_type: Some(CommandType::CmdRth),
+            from_ms: 0,
+            to_ms: u64::MAX,
+            limit: 100,
+        };
+        let events: Vec<_> = log.query(&query).unwrap().into_iter().map(|e| e.event).collect();
+        assert_eq!(
+            events,
+            [
+                AuditEvent::Queued {
+                    priority: priority::HIGH
+                },
+                AuditEvent::Sent,
+                AuditEvent::Retry { attempt: 1 },
+                AuditEvent::ack(AckStatus::AckReceived, "", 0),
+                AuditEvent::ack(AckStatus::AckCompleted, "", 40),
+                AuditEvent::outcome(AckStatus::AckCompleted, ""),
+            ]
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_replay_pending_over_new_link() {
+        let session_manager = Arc::new(SessionManager::new());
+        let _old_link = connect(&session_manager, "edge-001").await;
+        let dispatcher = CommandDispatcher::new(session_manager.clone(), Arc::new(AtomicU64::new(0)));
+
+        for id in [1, 2] {
+            dispatcher
+                .send_command("edge-001", command(id, CommandType::CmdStatusRequest, priority::NORMAL))
+                .await
+                .unwrap();
+        }
+        dispatcher.handle_ack("edge-001", &Ack::received(0, 1)).await;
+
+        // The drone switches transport: only the unacknowledged command goes out again
+        let new_link = connect(&session_manager, "edge-001").await;
+        let mut new_link = FramedRead::new(new_link, EnvelopeCodec::new());
+        assert_eq!(dispatcher.replay_pending("edge-001").await, [2]);
+
+        let replayed = new_link.next().await.unwrap().unwrap();
+        match replayed.payload {
+            Some(envelope::Payload::Command(cmd)) => assert_eq!(cmd.command_id, 2),
+            other => panic!("expected command, got {:?}", other),
+        }
+        assert_eq!(dispatcher.pending.read().await[&2].retries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_retry_resends_command() {
+        let session_manager = Arc::new(SessionManager::new());
+        let client = connect(&session_manager, "edge-001").await;
+        let mut client = FramedRead::new(client, EnvelopeCodec::new());
+        let dispatcher = CommandDispatcher::new(session_manager, Arc::new(AtomicU64::new(0)));
+
+        dispatcher
+            .send_command("edge-001", command(1, CommandType::CmdRth, priority::HIGH))
+            .await
+            .unwrap();
+        dispatcher.retry_command(1).await.unwrap();
+
+        let first = client.next().await.unwrap().unwrap();
+        let resent = client.next().await.unwrap().unwrap();
+        assert_eq!(first.payload, resent.payload);
+        let (first, resent) = (first.header.unwrap(), resent.header.unwrap());
+        assert_eq!((first.retry_count, resent.retry_count), (0, 1));
+        assert!(resent.sequence_id > first.sequence_id);
+        assert_eq!(resent.priority, priority::HIGH);
+        assert_eq!(dispatcher.pending.read().await[&1].sequence_id, resent.sequence_id);
+    }
+
+    #[tokio::test]
+    async fn test_command_limits() {
+        let session_manager = Arc::new(SessionManager::new());
+        let _client = connect(&session_manager, "edge-001").await;
+        let dispatcher = CommandDispatcher::new(session_manager.clone(), Arc::new(AtomicU64::new(0)))
+            .with_command_limits(CommandLimits {
+                max_in_flight: 4,
+                max_per_second: 2,
+                max_drone_pending: 3,
+            });
+
+        // Rate limited to two a second even with room in flight
+        for id in 1..=3 {
+            dispatcher
+                .send_command("edge-001", command(id, CommandType::CmdStatusRequest, priority::NORMAL))
+                .await
+                .unwrap();
+        }
+        assert_eq!(dispatcher.pending_count_for("edge-001").await, 2);
+        assert_eq!(dispatcher.queued_count_for("edge-001").await, 1);
+
+        // The drone reports a backlog: routine commands are refused, while
+        // RTH, emergency stops and anything HIGH still get through
+        session_manager.update_pending_commands("edge-001", 3).await;
+        let refused = dispatcher
+            .send_command("edge-001", command(4, CommandType::CmdStatusRequest, priority::NORMAL))
+            .await;
+        assert!(refused.is_err());
+        for (id, cmd_type, priority) in [
+            (5, CommandType::CmdRth, priority::HIGH),
+            (6, CommandType::CmdRth, priority::NORMAL),
+            (7, CommandType::CmdMissionAbort, priority::HIGH),
+            (8, CommandType::CmdEmergencyStop, priority::LOW),
+        ] {
+            dispatcher
+                .send_command("edge-001", command(id, cmd_type, priority))
+                .await
+                .unwrap();
+        }
+        assert!(dispatcher.pending.read().await.contains_key(&8));
+        assert_eq!(dispatcher.command_history("edge-001").await.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_queued_and_sent_commands() {
+        let session_manager = Arc::new(SessionManager::new());
+        let client = connect(&session_manager, "edge-001").await;
+        let mut client = FramedRead::new(client, EnvelopeCodec::new());
+        let dispatcher = CommandDispatcher::new(session_manager, Arc::new(AtomicU64::new(100)));
+
+        for id in [11, 12] {
+            dispatcher
+                .send_command("edge-001", command(id, CommandType::CmdDownloadLog, priority::NORMAL))
+                .await
+                .unwrap();
+        }
+
+        // Still queued: dropped on the server
+        assert_eq!(dispatcher.cancel(12).await.unwrap(), 12);
+        assert_eq!(dispatcher.queued_count_for("edge-001").await, 0);
+
+        // Already sent: the drone gets a cancel
+        let cancel_id = dispatcher.cancel(11).await.unwrap();
+        client.next().await.unwrap().unwrap();
+        let sent = client.next().await.unwrap().unwrap();
+        let Some(envelope::Payload::Command(cancel)) = sent.payload else {
+            panic!("expected cancel command");
+        };
+        assert_eq!(cancel.command_id, cancel_id);
+        assert_eq!(cancel.cmd_type(), CommandType::CmdCancel);
+        assert_eq!(
+            cancel.params,
+            Some(command::Params::Cancel(CancelCommand { target_command_id: 11 }))
+        );
+
+        dispatcher
+            .handle_ack("edge-001", &Ack { status: AckStatus::AckCancelled.into(), ..Ack::completed(0, 11, 5) })
+            .await;
+        let history = dispatcher.command_history("edge-001").await;
+        let outcome = |id| history.iter().find(|c| c.command_id == id).unwrap().outcome;
+        assert_eq!(outcome(11), Some(AckStatus::AckCancelled));
+        assert_eq!(outcome(12), Some(AckStatus::AckCancelled));
+        assert!(dispatcher.cancel(11).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mission_upload_streams_chunks_in_order() {
+        let session_manager = Arc::new(SessionManager::new());
+        let _client = connect(&session_manager, "edge-001").await;
+        let dispatcher = CommandDispatcher::new(session_manager, Arc::new(AtomicU64::new(0)));
+        let waypoints = vec![GpsCoordinate::default(); 2 * resqterra_shared::MISSION_CHUNK_WAYPOINTS + 1];
+
+        let ids = dispatcher
+            .upload_mission("edge-001", "survey-1", &waypoints)
+            .await
+            .unwrap();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(dispatcher.queued_count_for("edge-001").await, 2);
+
+        for (acked, next) in ids.iter().zip(&ids[1..]) {
+            dispatcher.handle_ack("edge-001", &Ack::completed(0, *acked, 1)).await;
+            let pending = dispatcher.pending.read().await;
+            let Some(command::Params::MissionUpload(chunk)) = &pending[next].command.params else {
+                panic!("expected mission upload chunk");
+            };
+            assert_eq!(chunk.chunk_index as usize, ids.iter().position(|id| id == next).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_assigned_mission_keeps_its_waypoints() {
+        let session_manager = Arc::new(SessionManager::new());
+        let _client = connect(&session_manager, "edge-001").await;
+        let dispatcher = CommandDispatcher::new(session_manager, Arc::new(AtomicU64::new(0)));
+        let start = |mission_id: &str| Command {
+            command_id: dispatcher.next_command_id(),
+            cmd_type: CommandType::CmdMissionStart.into(),
+            params: Some(command::Params::MissionStart(MissionStart {
+                mission_id: mission_id.into(),
+                altitude_m: 40.0,
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        assert!(dispatcher.assigned_mission("edge-001").await.is_none());
+
+        let waypoints = vec![GpsCoordinate::default(); 3];
+        dispatcher.upload_mission("edge-001", "survey-1", &waypoints).await.unwrap();
+        dispatcher.send_command("edge-001", start("survey-1")).await.unwrap();
+        let assigned = dispatcher.assigned_mission("edge-001").await.unwrap();
+        assert_eq!((assigned.mission.altitude_m, assigned.waypoints.len()), (40.0, 3));
+
+        // A later mission flown from its survey area has no waypoints of its own
+        dispatcher.send_command("edge-001", start("survey-2")).await.unwrap();
+        let assigned = dispatcher.assigned_mission("edge-001").await.unwrap();
+        assert_eq!(assigned.mission.mission_id, "survey-2");
+        assert!(assigned.waypoints.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_third_upload_waits_for_slot() {
+        let session_manager = Arc::new(SessionManager::new());
+        let mut clients = Vec::new();
+        for device_id in ["edge-001", "edge-002", "edge-003"] {
+            clients.push(connect(&session_manager, device_id).await);
+            let quality = ConnectionQuality {
+                active_transport: Transport::Transport5g.into(),
+                ..Default::default()
+            };
+            session_manager.record_quality(device_id, now_ms(), quality).await;
+        }
+        let dispatcher = CommandDispatcher::new(session_manager, Arc::new(AtomicU64::new(0)))
+            .with_upload_limits(UploadLimits { five_g: 2, bluetooth: 1 });
+
+        for (id, device_id) in [(1, "edge-001"), (2, "edge-002"), (3, "edge-003")] {
+            dispatcher
+                .send_command(device_id, command(id, CommandType::CmdMissionStart, priority::NORMAL))
+                .await
+                .unwrap();
+        }
+        assert_eq!(dispatcher.pending_count().await, 2);
+        assert_eq!(dispatcher.queued_count_for("edge-003").await, 1);
+
+        // An upload finishing hands its slot to the waiting drone
+        dispatcher.handle_ack("edge-001", &Ack::completed(0, 1, 5)).await;
+        assert!(dispatcher.pending.read().await.contains_key(&3));
+        assert_eq!(dispatcher.queued_count_for("edge-003").await, 0);
+        assert_eq!(dispatcher.uploads.lock().await.in_flight(), 2);
+    }
 }
@@ -1,13 +1,27 @@
 //! Command dispatch and timeout tracking for the server
 //!
 //! This module handles:
-//! - Queuing commands for specific drones
+//! - Queuing commands for specific drones (by priority, then FIFO), rate limited
 //! - Tracking pending commands and their timeouts
 //! - Retry logic for failed commands
 //! - Command completion/failure handling
+//! - A bounded per-drone audit trail of commands and outcomes
+//! - A durable, append-only audit log of every command step on disk
+//! - A fleet-wide, per-transport limit on concurrent mission uploads
+//! - The mission each drone was last sent, for re-tasking
 
+mod audit;
+mod audit_log;
 mod dispatcher;
+mod queue;
 mod timeout;
+mod upload;
 
-pub use dispatcher::CommandDispatcher;
+pub use audit::CommandAudit;
+#[cfg(test)]
+pub use audit_log::{AuditEntry, AuditEvent};
+pub use audit_log::{AuditLog, AuditLogConfig, AuditQuery, MAX_AUDIT_ROWS};
+pub use dispatcher::{AssignedMission, CommandDispatcher};
+pub use queue::CommandLimits;
 pub use timeout::TimeoutTracker;
+pub use upload::UploadLimits;
@@ -0,0 +1,251 @@
+//! Per-device priority queue for outbound commands
+//!
+//! Commands to one drone go out one at a time by default, at most
+//! `max_per_second` of them, and a drone reporting a backlog in its
+//! heartbeats gets no more queued (see `CommandLimits`).
+
+use resqterra_shared::Command;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::env;
+
+/// Commands sent to a drone but not yet acknowledged before the queue holds back
+pub const MAX_UNACKED_PER_DEVICE: usize = 1;
+
+/// Default commands sent to one drone per second
+pub const DEFAULT_COMMANDS_PER_SECOND: usize = 5;
+
+/// Default pending commands a drone may report before new ones are refused
+pub const DEFAULT_MAX_DRONE_PENDING: u32 = 8;
+
+/// Rate and backlog limits applied to each drone's commands
+///
+/// Emergency stops are exempt from all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandLimits {
+    /// Commands sent but not yet acknowledged
+    pub max_in_flight: usize,
+    /// Commands sent in any one-second window
+    pub max_per_second: usize,
+    /// Pending commands reported in the drone's heartbeat before enqueue is refused
+    pub max_drone_pending: u32,
+}
+
+impl Default for CommandLimits {
+    fn default() -> Self {
+        Self {
+            max_in_flight: MAX_UNACKED_PER_DEVICE,
+            max_per_second: DEFAULT_COMMANDS_PER_SECOND,
+            max_drone_pending: DEFAULT_MAX_DRONE_PENDING,
+        }
+    }
+}
+
+impl CommandLimits {
+    /// Read limits from `SERVER_MAX_IN_FLIGHT` / `SERVER_COMMANDS_PER_SEC` /
+    /// `SERVER_MAX_DRONE_PENDING`
+    pub fn from_env() -> Self {
+        fn read<T: std::str::FromStr + PartialOrd + Default>(name: &str, default: T) -> T {
+            env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|n| *n > T::default())
+                .unwrap_or(default)
+        }
+        Self {
+            max_in_flight: read("SERVER_MAX_IN_FLIGHT", MAX_UNACKED_PER_DEVICE),
+            max_per_second: read("SERVER_COMMANDS_PER_SEC", DEFAULT_COMMANDS_PER_SECOND),
+            max_drone_pending: read("SERVER_MAX_DRONE_PENDING", DEFAULT_MAX_DRONE_PENDING),
+        }
+    }
+}
+
+/// A command waiting to be sent
+#[derive(Debug, Clone)]
+struct QueuedCommand {
+    priority: u32,
+    /// Insertion order (FIFO within a priority level)
+    order: Reverse<u64>,
+    command: Command,
+}
+
+impl PartialEq for QueuedCommand {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedCommand {}
+
+impl PartialOrd for QueuedCommand {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedCommand {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.priority, self.order).cmp(&(other.priority, other.order))
+    }
+}
+
+/// Outbound commands per device, ordered by priority then FIFO
+#[derive(Debug, Default)]
+pub struct CommandQueue {
+    queues: HashMap<String, BinaryHeap<QueuedCommand>>,
+    next_order: u64,
+    limits: CommandLimits,
+    /// Send times (ms) within the last second, per device
+    sent: HashMap<String, VecDeque<u64>>,
+}
+
+impl CommandQueue {
+    /// Create an empty queue
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply rate and backlog limits
+    pub fn with_limits(mut self, limits: CommandLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Limits in force
+    pub fn limits(&self) -> CommandLimits {
+        self.limits
+    }
+
+    /// Whether another command may go to a device without exceeding its rate
+    pub fn rate_allows(&mut self, device_id: &str, now_ms: u64) -> bool {
+        let Some(sent) = self.sent.get_mut(device_id) else {
+            return true;
+        };
+        while sent.front().is_some_and(|&at| now_ms.saturating_sub(at) >= 1000) {
+            sent.pop_front();
+        }
+        if sent.is_empty() {
+            self.sent.remove(device_id);
+            return true;
+        }
+        sent.len() < self.limits.max_per_second
+    }
+
+    /// Count a command sent to a device against its rate
+    pub fn record_sent(&mut self, device_id: &str, now_ms: u64) {
+        self.sent
+            .entry(device_id.to_string())
+            .or_default()
+            .push_back(now_ms);
+    }
+
+    /// Devices with commands waiting
+    pub fn devices(&self) -> Vec<String> {
+        self.queues.keys().cloned().collect()
+    }
+
+    /// Queue a command for a device at its effective priority
+    pub fn push(&mut self, device_id: &str, command: Command) {
+        let queued = QueuedCommand {
+            priority: command.effective_priority(),
+            order: Reverse(self.next_order),
+            command,
+        };
+        self.next_order += 1;
+        self.queues
+            .entry(device_id.to_string())
+            .or_default()
+            .push(queued);
+    }
+
+    /// The next command that would be sent to a device
+    pub fn peek(&self, device_id: &str) -> Option<&Command> {
+        self.queues.get(device_id)?.peek().map(|q| &q.command)
+    }
+
+    /// Take the next command to send to a device
+    pub fn pop(&mut self, device_id: &str) -> Option<Command> {
+        let queue = self.queues.get_mut(device_id)?;
+        let next = queue.pop().map(|q| q.command);
+        if queue.is_empty() {
+            self.queues.remove(device_id);
+        }
+        next
+    }
+
+    /// Take a queued command out before it is sent, returning its device
+    pub fn remove(&mut self, command_id: u64) -> Option<(String, Command)> {
+        let device_id = self
+            .queues
+            .iter()
+            .find(|(_, q)| q.iter().any(|c| c.command.command_id == command_id))
+            .map(|(device_id, _)| device_id.clone())?;
+        let queue = self.queues.get_mut(&device_id)?;
+        let mut removed = None;
+        queue.retain(|c| {
+            if c.command.command_id == command_id {
+                removed = Some(c.command.clone());
+                return false;
+            }
+            true
+        });
+        if queue.is_empty() {
+            self.queues.remove(&device_id);
+        }
+        removed.map(|command| (device_id, command))
+    }
+
+    /// Number of commands queued for a device
+    pub fn len_for(&self, device_id: &str) -> usize {
+        self.queues.get(device_id).map_or(0, |q| q.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::{priority, CommandType};
+
+    fn command(command_id: u64, priority: u32) -> Command {
+        Command {
+            command_id,
+            cmd_type: CommandType::CmdStatusRequest.into(),
+            priority,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_priority_then_fifo() {
+        let mut queue = CommandQueue::new();
+        queue.push("edge-001", command(1, priority::LOW));
+        queue.push("edge-001", command(2, priority::NORMAL));
+        queue.push("edge-001", command(3, priority::LOW));
+        queue.push("edge-001", command(4, priority::HIGH));
+        queue.push("edge-002", command(5, priority::HIGH));
+
+        let order: Vec<u64> = std::iter::from_fn(|| queue.pop("edge-001"))
+            .map(|c| c.command_id)
+            .collect();
+        assert_eq!(order, [4, 2, 1, 3]);
+        assert_eq!(queue.len_for("edge-002"), 1);
+    }
+
+    #[test]
+    fn test_rate_window_slides() {
+        let mut queue = CommandQueue::new().with_limits(CommandLimits {
+            max_per_second: 2,
+            ..Default::default()
+        });
+        queue.record_sent("edge-001", 1_000);
+        queue.record_sent("edge-001", 1_400);
+        assert!(!queue.rate_allows("edge-001", 1_900));
+        assert!(queue.rate_allows("edge-002", 1_900));
+
+        // The first send has left the window
+        assert!(queue.rate_allows("edge-001", 2_000));
+        queue.record_sent("edge-001", 2_000);
+        assert!(!queue.rate_allows("edge-001", 2_100));
+        assert!(queue.rate_allows("edge-001", 3_000));
+    }
+}
@@ -49,6 +49,9 @@ impl TimeoutTracker {
                 }
             }
 
+            // Send commands the rate limit held back
+            self.dispatcher.dispatch_ready().await;
+
             // Cleanup expired commands
             let expired = self.dispatcher.cleanup_expired().await;
             if !expired.is_empty() {
@@ -0,0 +1,144 @@
+//! Fleet-wide limit on concurrent mission uploads and other large commands
+//!
+//! Each large command holds a slot on the drone's transport until its final
+//! ACK, so launching missions on the whole fleet at once cannot saturate the
+//! relay/Bluetooth link. Commands without a free slot wait in the drone's queue.
+
+use resqterra_shared::{Command, CommandType, Transport};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Default concurrent uploads over 5G
+pub const DEFAULT_MAX_UPLOADS_5G: usize = 8;
+
+/// Default concurrent uploads over the Bluetooth relay
+pub const DEFAULT_MAX_UPLOADS_BLUETOOTH: usize = 2;
+
+/// Whether a command is large enough to need an upload slot
+pub fn is_upload(command: &Command) -> bool {
+    matches!(
+        CommandType::try_from(command.cmd_type),
+        Ok(CommandType::CmdMissionStart
+            | CommandType::CmdConfigUpdate
+            | CommandType::CmdMissionUpload)
+    )
+}
+
+/// Concurrent upload slots per transport
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadLimits {
+    pub five_g: usize,
+    /// Also used for drones that have not reported a transport yet
+    pub bluetooth: usize,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        Self {
+            five_g: DEFAULT_MAX_UPLOADS_5G,
+            bluetooth: DEFAULT_MAX_UPLOADS_BLUETOOTH,
+        }
+    }
+}
+
+impl UploadLimits {
+    /// Read limits from `SERVER_MAX_UPLOADS_5G` / `SERVER_MAX_UPLOADS_BT`
+    pub fn from_env() -> Self {
+        let read = |name: &str, default: usize| {
+            env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|&n| n > 0)
+                .unwrap_or(default)
+        };
+        Self {
+            five_g: read("SERVER_MAX_UPLOADS_5G", DEFAULT_MAX_UPLOADS_5G),
+            bluetooth: read("SERVER_MAX_UPLOADS_BT", DEFAULT_MAX_UPLOADS_BLUETOOTH),
+        }
+    }
+}
+
+/// Upload slots per transport and the commands holding them
+#[derive(Debug)]
+pub struct UploadLimiter {
+    five_g: Arc<Semaphore>,
+    bluetooth: Arc<Semaphore>,
+    /// Slots held by in-flight uploads, by command_id
+    in_flight: HashMap<u64, OwnedSemaphorePermit>,
+    /// Drones holding back an upload until a slot frees
+    waiting: BTreeSet<String>,
+}
+
+impl UploadLimiter {
+    /// Create a limiter with the given slots per transport
+    pub fn new(limits: UploadLimits) -> Self {
+        Self {
+            five_g: Arc::new(Semaphore::new(limits.five_g)),
+            bluetooth: Arc::new(Semaphore::new(limits.bluetooth)),
+            in_flight: HashMap::new(),
+            waiting: BTreeSet::new(),
+        }
+    }
+
+    fn slots(&self, transport: Transport) -> &Arc<Semaphore> {
+        match transport {
+            Transport::Transport5g => &self.five_g,
+            Transport::Bluetooth | Transport::Unknown => &self.bluetooth,
+        }
+    }
+
+    /// Take a slot for `command_id`, or remember the drone as waiting
+    pub fn try_acquire(&mut self, device_id: &str, command_id: u64, transport: Transport) -> bool {
+        match self.slots(transport).clone().try_acquire_owned() {
+            Ok(permit) => {
+                self.in_flight.insert(command_id, permit);
+                self.waiting.remove(device_id);
+                true
+            }
+            Err(_) => {
+                self.waiting.insert(device_id.to_string());
+                false
+            }
+        }
+    }
+
+    /// Free the slot held by `command_id`; returns whether it held one
+    pub fn release(&mut self, command_id: u64) -> bool {
+        self.in_flight.remove(&command_id).is_some()
+    }
+
+    /// Drones that were waiting for a slot (they should retry their queue)
+    pub fn take_waiting(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.waiting).into_iter().collect()
+    }
+
+    /// Number of uploads in flight
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_are_per_transport() {
+        let mut limiter = UploadLimiter::new(UploadLimits {
+            five_g: 1,
+            bluetooth: 1,
+        });
+
+        assert!(limiter.try_acquire("edge-001", 1, Transport::Transport5g));
+        assert!(!limiter.try_acquire("edge-002", 2, Transport::Transport5g));
+        assert!(limiter.try_acquire("edge-003", 3, Transport::Bluetooth));
+        assert_eq!(limiter.in_flight(), 2);
+
+        assert!(limiter.release(1));
+        assert!(!limiter.release(1));
+        assert_eq!(limiter.take_waiting(), ["edge-002"]);
+        assert!(limiter.try_acquire("edge-002", 2, Transport::Transport5g));
+    }
+}
@@ -0,0 +1,378 @@
+//! Live event feed for dashboards
+//!
+//! `GET /events` on the HTTP API upgrades to a WebSocket streaming telemetry
+//! and state changes as JSON text messages, for some drones or the whole
+//! fleet:
+//!
+//! - `?devices=edge-001,edge-002` limits the feed to those drones (default:
+//!   every drone)
+//! - `?events=telemetry,state,detections` picks what to send (default: all)
+//!
+//! Each message has a `type`: `telemetry` (shaped like
+//! `GET /drones/{id}/telemetry`), `state` (`from` and `to` drone states),
+//! `connected`, `disconnected` and `lost` (heartbeats stopped; see `alerts`),
+//! which also count as state, or `detection` (onboard perception spotted
+//! something).
+//!
+//! Every dashboard has its own queue of `DASHBOARD_QUEUE_LEN` messages. A
+//! dashboard that reads too slowly loses the oldest of them rather than
+//! holding anything up, and is told so by a `dropped` message carrying the
+//! count ahead of the next message it gets.
+//!
+//! `GET /drones/{id}/telemetry/live` is the same for one drone's telemetry
+//! only, fed from its own stream instead of the fleet-wide bus.
+
+use crate::alerts::{detection_json, lost_json};
+use crate::events::{EventBus, ServerEvent};
+use crate::http::{telemetry_json, ApiError};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use futures::stream::SplitSink;
+use futures::{SinkExt, StreamExt};
+use resqterra_shared::{now_ms, DeviceId, Telemetry};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Notify;
+
+/// Messages queued per dashboard before the oldest are dropped
+pub const DASHBOARD_QUEUE_LEN: usize = 256;
+
+/// The `/events` route, to merge into the HTTP API
+pub fn router(events: EventBus) -> Router {
+    Router::new()
+        .route("/events", get(subscribe))
+        .with_state(events)
+}
+
+/// `GET /events` query: comma-separated lists
+#[derive(Debug, Default, Deserialize)]
+struct FilterQuery {
+    devices: Option<String>,
+    events: Option<String>,
+}
+
+/// What one dashboard is sent
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    /// Empty = every drone
+    devices: HashSet<String>,
+    telemetry: bool,
+    state: bool,
+    detections: bool,
+}
+
+impl Filter {
+    fn parse(query: &FilterQuery) -> Result<Self, String> {
+        let list = |list: &Option<String>| -> Vec<String> {
+            let list = list.as_deref().unwrap_or_default();
+            list.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        };
+        let devices = list(&query.devices)
+            .into_iter()
+            .map(|raw| match raw.parse::<DeviceId>() {
+                Ok(id) => Ok(id.as_str().to_string()),
+                Err(e) => Err(format!("Bad device ID {:?}: {}", raw, e)),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut filter = Filter {
+            devices,
+            telemetry: false,
+            state: false,
+            detections: false,
+        };
+        for name in list(&query.events) {
+            match name.as_str() {
+                "telemetry" => filter.telemetry = true,
+                "state" => filter.state = true,
+                "detections" => filter.detections = true,
+                _ => {
+                    let expected = "expected telemetry, state or detections";
+                    return Err(format!("Unknown event {:?}, {}", name, expected));
+                }
+            }
+        }
+        if !filter.telemetry && !filter.state && !filter.detections {
+            filter.telemetry = true;
+            filter.state = true;
+            filter.detections = true;
+        }
+        Ok(filter)
+    }
+
+    fn wants(&self, device_id: &str) -> bool {
+        self.devices.is_empty() || self.devices.contains(device_id)
+    }
+
+    /// The message for `event`, if the dashboard wants it
+    fn message(&self, event: &ServerEvent) -> Option<Value> {
+        let message = match event {
+            ServerEvent::TelemetryReceived {
+                device_id,
+                timestamp_ms,
+                telemetry,
+            } if self.telemetry && self.wants(device_id) => {
+                let mut message = telemetry_json(device_id, *timestamp_ms, telemetry);
+                message["type"] = "telemetry".into();
+                message
+            }
+            ServerEvent::StateChanged { device_id, from, to }
+                if self.state && self.wants(device_id) =>
+            {
+                json!({
+                    "type": "state",
+                    "device_id": device_id,
+                    "from": from.as_str_name(),
+                    "to": to.as_str_name(),
+                })
+            }
+            ServerEvent::SessionConnected { device_id, .. }
+                if self.state && self.wants(device_id) =>
+            {
+                json!({ "type": "connected", "device_id": device_id })
+            }
+            ServerEvent::SessionDisconnected { device_id, reason }
+                if self.state && self.wants(device_id) =>
+            {
+                json!({ "type": "disconnected", "device_id": device_id, "reason": reason })
+            }
+            ServerEvent::DroneLost {
+                device_id,
+                state,
+                last_seen_ms,
+                last_position,
+            } if self.state && self.wants(device_id) => {
+                lost_json(device_id, *state, *last_seen_ms, last_position.as_ref())
+            }
+            ServerEvent::DetectionReceived { device_id, report }
+                if self.detections && self.wants(device_id) =>
+            {
+                detection_json(device_id, report)
+            }
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+async fn subscribe(
+    ws: WebSocketUpgrade,
+    State(events): State<EventBus>,
+    Query(query): Query<FilterQuery>,
+) -> Result<Response, ApiError> {
+    let filter = Filter::parse(&query).map_err(|e| ApiError(StatusCode::BAD_REQUEST, e))?;
+    let events = events.subscribe();
+    Ok(ws.on_upgrade(move |socket| stream_events(socket, events, filter)))
+}
+
+/// Feed one dashboard until it hangs up
+async fn stream_events(
+    socket: WebSocket,
+    mut events: broadcast::Receiver<ServerEvent>,
+    filter: Filter,
+) {
+    let (sink, mut incoming) = socket.split();
+    let outbox = Arc::new(Outbox::new(DASHBOARD_QUEUE_LEN));
+    let writer = tokio::spawn(write_outbox(sink, outbox.clone()));
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if let Some(message) = filter.message(&event) {
+                        outbox.push(message);
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    eprintln!("[HTTP] Dashboard lagged, skipped {} events", n);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Dashboards only listen; anything but a close is ignored
+            message = incoming.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    writer.abort();
+}
+
+/// Feed one dashboard a single drone's telemetry
+///
+/// Frames come from `SessionManager::subscribe_device_telemetry`, which
+/// carries no capture time, so `timestamp_ms` is when the server got them.
+/// The socket is closed when the drone disconnects; reconnect to follow it
+/// again.
+pub async fn stream_drone_telemetry(
+    socket: WebSocket,
+    device_id: String,
+    mut telemetry: broadcast::Receiver<Telemetry>,
+) {
+    let (sink, mut incoming) = socket.split();
+    let outbox = Arc::new(Outbox::new(DASHBOARD_QUEUE_LEN));
+    let writer = tokio::spawn(write_outbox(sink, outbox.clone()));
+
+    loop {
+        tokio::select! {
+            frame = telemetry.recv() => match frame {
+                Ok(frame) => {
+                    let mut message = telemetry_json(&device_id, now_ms(), &frame);
+                    message["type"] = "telemetry".into();
+                    outbox.push(message);
+                }
+                Err(RecvError::Lagged(n)) => {
+                    eprintln!("[HTTP] Dashboard of {} lagged, skipped {} frames", device_id, n);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = incoming.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    writer.abort();
+}
+
+/// Send a dashboard its queued messages until the socket fails
+async fn write_outbox(mut sink: SplitSink<WebSocket, Message>, outbox: Arc<Outbox>) {
+    loop {
+        let (message, dropped) = outbox.pop().await;
+        if dropped > 0 {
+            let notice = json!({ "type": "dropped", "count": dropped });
+            if sink.send(Message::Text(notice.to_string().into())).await.is_err() {
+                return;
+            }
+        }
+        if sink.send(Message::Text(message.to_string().into())).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Messages waiting for one dashboard; when full, the oldest go
+struct Outbox {
+    queued: Mutex<Queued>,
+    ready: Notify,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct Queued {
+    messages: VecDeque<Value>,
+    /// Dropped since the last message taken
+    dropped: u64,
+}
+
+impl Outbox {
+    fn new(capacity: usize) -> Self {
+        Self {
+            queued: Mutex::new(Queued::default()),
+            ready: Notify::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    fn push(&self, message: Value) {
+        {
+            let mut queued = self.queued.lock().unwrap();
+            if queued.messages.len() >= self.capacity {
+                queued.messages.pop_front();
+                queued.dropped += 1;
+            }
+            queued.messages.push_back(message);
+        }
+        self.ready.notify_one();
+    }
+
+    /// Wait for the oldest message, with how many were dropped before it
+    async fn pop(&self) -> (Value, u64) {
+        loop {
+            {
+                let mut queued = self.queued.lock().unwrap();
+                if let Some(message) = queued.messages.pop_front() {
+                    return (message, std::mem::take(&mut queued.dropped));
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::{DetectionReport, DroneState, Telemetry};
+
+    fn filter(devices: Option<&str>, events: Option<&str>) -> Result<Filter, String> {
+        Filter::parse(&FilterQuery {
+            devices: devices.map(String::from),
+            events: events.map(String::from),
+        })
+    }
+
+    #[test]
+    fn test_filter_from_query() {
+        let all = filter(None, None).unwrap();
+        assert!(all.devices.is_empty() && all.telemetry && all.state && all.detections);
+        let some = filter(Some("edge-001, edge-002,"), Some("state")).unwrap();
+        assert_eq!(some.devices.len(), 2);
+        assert!(!some.telemetry && some.state && !some.detections);
+        assert!(filter(None, Some("battery")).is_err());
+        assert!(filter(Some("edge\u{1b}"), None).is_err());
+    }
+
+    #[test]
+    fn test_events_filtered_per_dashboard() {
+        let filter = filter(Some("edge-001"), Some("telemetry")).unwrap();
+        let telemetry = |device_id: &str| ServerEvent::TelemetryReceived {
+            device_id: device_id.into(),
+            timestamp_ms: 1000,
+            telemetry: Telemetry::default(),
+        };
+        let message = filter.message(&telemetry("edge-001")).unwrap();
+        assert_eq!(message["type"], "telemetry");
+        assert_eq!(message["timestamp_ms"], 1000);
+        assert!(filter.message(&telemetry("edge-002")).is_none());
+
+        let state = ServerEvent::StateChanged {
+            device_id: "edge-001".into(),
+            from: DroneState::DroneIdle,
+            to: DroneState::DroneInMission,
+        };
+        assert!(filter.message(&state).is_none());
+
+        let detection = ServerEvent::DetectionReceived {
+            device_id: "edge-001".into(),
+            report: DetectionReport::default(),
+        };
+        assert!(filter.message(&detection).is_none());
+        let detections = Filter { detections: true, ..filter.clone() };
+        assert_eq!(detections.message(&detection).unwrap()["type"], "detection");
+
+        let message = Filter { telemetry: false, state: true, ..filter }.message(&state).unwrap();
+        assert_eq!(message["to"], "DRONE_IN_MISSION");
+    }
+
+    #[tokio::test]
+    async fn test_outbox_drops_oldest() {
+        let outbox = Outbox::new(2);
+        for n in 1..=3 {
+            outbox.push(json!(n));
+        }
+        assert_eq!(outbox.pop().await, (json!(2), 1));
+        assert_eq!(outbox.pop().await, (json!(3), 0));
+    }
+}
@@ -0,0 +1,122 @@
+//! Server event bus
+//!
+//! A single typed stream of everything interesting that happens on the server
+//! (sessions, telemetry, command outcomes, alerts) so integrators subscribe in
+//! one place instead of wiring per-subsystem channels.
+//!
+//! Backpressure: publishing never blocks. Each subscriber has its own window of
+//! `EVENT_BUS_CAPACITY` events; a subscriber that falls further behind gets
+//! `RecvError::Lagged(n)` on its next `recv` and resumes from the oldest event
+//! still buffered (the `n` oldest are lost to it). Slow consumers should drain
+//! quickly or hand events off to their own queue.
+
+use resqterra_shared::{
+    AckStatus, CommandType, DetectionReport, DroneState, GpsPosition, Heartbeat, Telemetry,
+};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber before it starts lagging
+pub const EVENT_BUS_CAPACITY: usize = 1024;
+
+/// Events published by server subsystems
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    /// A drone registered a session
+    SessionConnected { device_id: String, addr: SocketAddr },
+    /// A drone session ended (disconnect or heartbeat timeout)
+    SessionDisconnected { device_id: String, reason: String },
+    /// A drone's heartbeats stopped for `HEARTBEAT_TIMEOUT_MS`; it is presumed
+    /// lost until it reconnects. Carries what search teams need to find it.
+    DroneLost {
+        device_id: String,
+        /// Last state it reported
+        state: DroneState,
+        /// When its last heartbeat arrived (ms since epoch)
+        last_seen_ms: u64,
+        /// From its latest telemetry, if it had a fix
+        last_position: Option<GpsPosition>,
+    },
+    /// Telemetry frame received from a drone, taken at `timestamp_ms`
+    TelemetryReceived {
+        device_id: String,
+        timestamp_ms: u64,
+        telemetry: Telemetry,
+    },
+    /// A drone's reported state changed
+    StateChanged {
+        device_id: String,
+        from: DroneState,
+        to: DroneState,
+    },
+    /// Heartbeat received from a drone
+    HeartbeatReceived { device_id: String, heartbeat: Heartbeat },
+    /// A command reached a final state (completed, failed, rejected, expired)
+    CommandOutcome {
+        device_id: String,
+        command_id: u64,
+        cmd_type: CommandType,
+        status: AckStatus,
+        message: String,
+    },
+    /// A drone's send queue reached `BACKPRESSURE_THRESHOLD` writes; its
+    /// link is struggling and may drop
+    Backpressure { device_id: String, depth: usize },
+    /// Sequence ids `first..=last` from a drone never arrived in order;
+    /// frames were lost or reordered on its link
+    SequenceGap { device_id: String, first: u64, last: u64 },
+    /// A drone sent a sequence id twice; the copy was dropped
+    DuplicateDropped { device_id: String, sequence_id: u64 },
+    /// Onboard perception spotted something (a person, a vehicle, a fire)
+    DetectionReceived { device_id: String, report: DetectionReport },
+    /// A drone finished uploading a blob (an image or map tile), now at `path`
+    BlobReceived {
+        device_id: String,
+        blob_id: String,
+        content_type: String,
+        /// What it shows, e.g. a detection ID (may be empty)
+        name: String,
+        size: u64,
+        path: PathBuf,
+    },
+    /// Something needs operator attention
+    Alert { device_id: String, message: String },
+    /// A drone reports a state that can't follow from the one a completed
+    /// command put it in (see `SessionManager::expect_command_state`)
+    StateDivergence {
+        device_id: String,
+        expected: DroneState,
+        reported: DroneState,
+    },
+}
+
+/// Cloneable handle to the server event bus
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<ServerEvent>,
+}
+
+impl EventBus {
+    /// Create a new event bus
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { tx }
+    }
+
+    /// Publish an event to all current subscribers (dropped if there are none)
+    pub fn publish(&self, event: ServerEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    /// Subscribe to events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
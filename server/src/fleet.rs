@@ -0,0 +1,327 @@
+//! In-memory view of the fleet, kept current from the event bus
+//!
+//! `FleetState` holds the latest known telemetry, state, link transport and
+//! pending-command count of every drone seen since the server started, and
+//! publishes typed `FleetEvent`s when something worth reacting to changes.
+//! Subsystems read it or subscribe to it instead of polling
+//! `SessionManager`. Drones that disconnect stay in the view with their last
+//! known values.
+//!
+//! Like any event bus subscriber, the view can fall behind under load; it
+//! skips what it missed and catches up with the next frames.
+
+use crate::events::{EventBus, ServerEvent};
+use resqterra_shared::{safety, DroneState, GpsPosition, Telemetry, Transport};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Changes buffered per subscriber before it starts lagging
+pub const FLEET_EVENT_CAPACITY: usize = 256;
+
+/// A drone that reported low battery is warned about again only after its
+/// battery is back this far above `BATTERY_WARNING_PERCENT` (a swap)
+const BATTERY_RECHARGED_MARGIN: u32 = 10;
+
+/// The latest known state of one drone
+#[derive(Debug, Clone, PartialEq)]
+pub struct DroneView {
+    pub device_id: String,
+    pub connected: bool,
+    /// Heartbeats stopped; cleared when it reconnects
+    pub lost: bool,
+    pub state: DroneState,
+    /// From the link quality in its telemetry
+    pub transport: Transport,
+    /// As reported in its heartbeats
+    pub pending_commands: u32,
+    /// From its telemetry or heartbeats, whichever came last
+    pub battery_percent: Option<u32>,
+    /// Latest telemetry frame and when it was taken (ms since epoch)
+    pub telemetry: Option<(u64, Telemetry)>,
+    /// `BatteryLow` was published and not re-armed since
+    battery_low: bool,
+}
+
+impl DroneView {
+    fn new(device_id: &str) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            connected: false,
+            lost: false,
+            state: DroneState::DroneUnknown,
+            transport: Transport::Unknown,
+            pending_commands: 0,
+            battery_percent: None,
+            telemetry: None,
+            battery_low: false,
+        }
+    }
+}
+
+/// A change in the fleet
+#[derive(Debug, Clone, PartialEq)]
+pub enum FleetEvent {
+    /// A drone connected, or came back after a disconnect
+    DroneConnected { device_id: String },
+    /// A drone's session ended
+    DroneDisconnected { device_id: String, reason: String },
+    /// A drone's heartbeats stopped; where it was last seen, if known
+    DroneLost {
+        device_id: String,
+        last_position: Option<GpsPosition>,
+    },
+    /// A drone's reported state changed
+    StateChanged {
+        device_id: String,
+        from: DroneState,
+        to: DroneState,
+    },
+    /// A drone's battery fell to `BATTERY_WARNING_PERCENT` or below; sent
+    /// once until it recovers
+    BatteryLow { device_id: String, percent: u32 },
+}
+
+/// Latest known state per drone, with change notifications
+pub struct FleetState {
+    drones: RwLock<HashMap<String, DroneView>>,
+    changes: broadcast::Sender<FleetEvent>,
+}
+
+impl FleetState {
+    pub fn new() -> Self {
+        let (changes, _) = broadcast::channel(FLEET_EVENT_CAPACITY);
+        Self {
+            drones: RwLock::new(HashMap::new()),
+            changes,
+        }
+    }
+
+    /// A view kept current from `events` in the background
+    pub fn spawn(events: &EventBus) -> Arc<Self> {
+        let fleet = Arc::new(Self::new());
+        let mut events = events.subscribe();
+        tokio::spawn({
+            let fleet = fleet.clone();
+            async move {
+                loop {
+                    match events.recv().await {
+                        Ok(event) => fleet.apply(&event),
+                        Err(RecvError::Lagged(n)) => {
+                            eprintln!("[FLEET] View lagged, skipped {} events", n);
+                        }
+                        Err(RecvError::Closed) => return,
+                    }
+                }
+            }
+        });
+        fleet
+    }
+
+    /// Subscribe to changes published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<FleetEvent> {
+        self.changes.subscribe()
+    }
+
+    /// The latest known state of a drone
+    pub fn drone(&self, device_id: &str) -> Option<DroneView> {
+        self.drones.read().unwrap().get(device_id).cloned()
+    }
+
+    /// Every drone seen so far, in no particular order
+    pub fn drones(&self) -> Vec<DroneView> {
+        self.drones.read().unwrap().values().cloned().collect()
+    }
+
+    /// Fold one server event into the view
+    pub fn apply(&self, event: &ServerEvent) {
+        let mut drones = self.drones.write().unwrap();
+        match event {
+            ServerEvent::SessionConnected { device_id, .. } => {
+                let drone = view(&mut drones, device_id);
+                if !drone.connected {
+                    drone.connected = true;
+                    drone.lost = false;
+                    drone.battery_low = false;
+                    self.publish(FleetEvent::DroneConnected {
+                        device_id: device_id.clone(),
+                    });
+                }
+            }
+            ServerEvent::SessionDisconnected { device_id, reason } => {
+                view(&mut drones, device_id).connected = false;
+                self.publish(FleetEvent::DroneDisconnected {
+                    device_id: device_id.clone(),
+                    reason: reason.clone(),
+                });
+            }
+            ServerEvent::DroneLost {
+                device_id,
+                last_position,
+                ..
+            } => {
+                view(&mut drones, device_id).lost = true;
+                self.publish(FleetEvent::DroneLost {
+                    device_id: device_id.clone(),
+                    last_position: *last_position,
+                });
+            }
+            ServerEvent::StateChanged { device_id, from, to } => {
+                view(&mut drones, device_id).state = *to;
+                self.publish(FleetEvent::StateChanged {
+                    device_id: device_id.clone(),
+                    from: *from,
+                    to: *to,
+                });
+            }
+            ServerEvent::TelemetryReceived {
+                device_id,
+                timestamp_ms,
+                telemetry,
+            } => {
+                let drone = view(&mut drones, device_id);
+                drone.state = telemetry.state();
+                if let Some(quality) = &telemetry.conn_quality {
+                    drone.transport = quality.active_transport();
+                }
+                drone.telemetry = Some((*timestamp_ms, telemetry.clone()));
+                if let Some(battery) = &telemetry.battery {
+                    self.update_battery(drone, battery.remaining_percent);
+                }
+            }
+            ServerEvent::HeartbeatReceived {
+                device_id,
+                heartbeat,
+            } => {
+                let drone = view(&mut drones, device_id);
+                drone.pending_commands = heartbeat.pending_commands;
+                if let Some(percent) = heartbeat.battery_percent() {
+                    self.update_battery(drone, percent);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn update_battery(&self, drone: &mut DroneView, percent: u32) {
+        drone.battery_percent = Some(percent);
+        if percent <= safety::BATTERY_WARNING_PERCENT {
+            if !drone.battery_low {
+                drone.battery_low = true;
+                self.publish(FleetEvent::BatteryLow {
+                    device_id: drone.device_id.clone(),
+                    percent,
+                });
+            }
+        } else if percent >= safety::BATTERY_WARNING_PERCENT + BATTERY_RECHARGED_MARGIN {
+            drone.battery_low = false;
+        }
+    }
+
+    fn publish(&self, event: FleetEvent) {
+        let _ = self.changes.send(event);
+    }
+}
+
+/// The view of `device_id`, added if it is new
+fn view<'a>(drones: &'a mut HashMap<String, DroneView>, device_id: &str) -> &'a mut DroneView {
+    drones
+        .entry(device_id.to_string())
+        .or_insert_with(|| DroneView::new(device_id))
+}
+
+impl Default for FleetState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::{BatteryStatus, ConnectionQuality, Heartbeat};
+
+    fn telemetry(device_id: &str, state: DroneState, percent: u32) -> ServerEvent {
+        ServerEvent::TelemetryReceived {
+            device_id: device_id.into(),
+            timestamp_ms: 1000,
+            telemetry: Telemetry {
+                state: state.into(),
+                battery: Some(BatteryStatus {
+                    remaining_percent: percent,
+                    ..Default::default()
+                }),
+                conn_quality: Some(ConnectionQuality {
+                    active_transport: Transport::Transport5g.into(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_view_follows_events() {
+        let fleet = FleetState::new();
+        let mut changes = fleet.subscribe();
+        let connected = ServerEvent::SessionConnected {
+            device_id: "edge-001".into(),
+            addr: "127.0.0.1:9000".parse().unwrap(),
+        };
+        fleet.apply(&connected);
+        // A new address for a drone still connected is no change
+        fleet.apply(&connected);
+        fleet.apply(&telemetry("edge-001", DroneState::DroneInMission, 80));
+        fleet.apply(&ServerEvent::HeartbeatReceived {
+            device_id: "edge-001".into(),
+            heartbeat: Heartbeat {
+                pending_commands: 2,
+                ..Default::default()
+            },
+        });
+
+        let drone = fleet.drone("edge-001").unwrap();
+        assert!(drone.connected);
+        assert_eq!((drone.state, drone.transport), (DroneState::DroneInMission, Transport::Transport5g));
+        assert_eq!((drone.pending_commands, drone.battery_percent), (2, Some(80)));
+        assert_eq!(drone.telemetry.unwrap().0, 1000);
+        assert_eq!(
+            changes.try_recv().unwrap(),
+            FleetEvent::DroneConnected {
+                device_id: "edge-001".into()
+            }
+        );
+        assert!(changes.try_recv().is_err());
+
+        fleet.apply(&ServerEvent::SessionDisconnected {
+            device_id: "edge-001".into(),
+            reason: "timeout".into(),
+        });
+        fleet.apply(&ServerEvent::DroneLost {
+            device_id: "edge-001".into(),
+            state: DroneState::DroneInMission,
+            last_seen_ms: 900,
+            last_position: None,
+        });
+        let drone = fleet.drone("edge-001").unwrap();
+        assert!(!drone.connected && drone.lost);
+        fleet.apply(&connected);
+        assert!(!fleet.drone("edge-001").unwrap().lost);
+        assert!(fleet.drone("edge-002").is_none());
+    }
+
+    #[test]
+    fn test_battery_low_published_once_until_recharged() {
+        let fleet = FleetState::new();
+        let mut changes = fleet.subscribe();
+        for percent in [35, 30, 25, 38, 60, 29] {
+            fleet.apply(&telemetry("edge-001", DroneState::DroneInMission, percent));
+        }
+        let low: Vec<_> = std::iter::from_fn(|| changes.try_recv().ok()).collect();
+        let low_at = |percent| FleetEvent::BatteryLow {
+            device_id: "edge-001".into(),
+            percent,
+        };
+        assert_eq!(low, [low_at(30), low_at(29)]);
+    }
+}
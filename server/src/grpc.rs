@@ -0,0 +1,326 @@
+//! gRPC control API for operations tooling
+//!
+//! `FleetService` (see `shared/proto/fleet.proto`) offers what the HTTP API
+//! does to tooling that already speaks protobuf: requests and replies carry
+//! the protocol's own `Command`, `Telemetry` and `RelayStatus` messages, and
+//! telemetry is streamed to subscribers as it arrives instead of polled.
+//!
+//! Commands go through the `CommandDispatcher` like any other; mission starts
+//! are first validated as mission plans (see `resqterra_shared::mission`). A
+//! subscriber that falls more than `EVENT_BUS_CAPACITY` events behind skips
+//! the oldest frames rather than holding up the server. The API has no
+//! authentication, so it listens on loopback unless `SERVER_GRPC_LISTEN` says
+//! otherwise.
+
+use crate::command::{CommandAudit, CommandDispatcher};
+use crate::events::ServerEvent;
+use crate::session::SessionManager;
+use resqterra_shared::fleet::fleet_service_server::{FleetService, FleetServiceServer};
+use resqterra_shared::fleet::{
+    CommandState, CommandStatus, DroneInfo, GetCommandStatusRequest, ListDronesRequest,
+    ListDronesResponse, SendCommandRequest, SendCommandResponse, SubscribeTelemetryRequest,
+    TelemetryUpdate,
+};
+use resqterra_shared::{command, DeviceId};
+use resqterra_shared::mission::MissionPlan;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+
+/// Where the API listens unless `SERVER_GRPC_LISTEN` is set
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:50051";
+
+/// Telemetry updates queued per subscriber on top of its event bus window
+const SUBSCRIBER_QUEUE_LEN: usize = 16;
+
+/// Where to serve the API
+#[derive(Debug, Clone)]
+pub struct GrpcConfig {
+    pub listen: String,
+}
+
+impl GrpcConfig {
+    /// Read `SERVER_GRPC_LISTEN` (`host:port`); None when it is `off`
+    pub fn from_env() -> Option<Self> {
+        let listen = std::env::var("SERVER_GRPC_LISTEN").unwrap_or_else(|_| DEFAULT_LISTEN.into());
+        if listen == "off" {
+            return None;
+        }
+        Some(Self { listen })
+    }
+}
+
+/// Bind the API and serve it in the background
+pub async fn spawn(
+    config: GrpcConfig,
+    sessions: Arc<SessionManager>,
+    dispatcher: Arc<CommandDispatcher>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(&config.listen).await?;
+    tokio::spawn(serve(
+        listener,
+        Fleet {
+            sessions,
+            dispatcher,
+        },
+    ));
+    Ok(())
+}
+
+async fn serve(listener: TcpListener, fleet: Fleet) {
+    let result = tonic::transport::Server::builder()
+        .add_service(FleetServiceServer::new(fleet))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await;
+    if let Err(e) = result {
+        eprintln!("[GRPC] API server failed: {}", e);
+    }
+}
+
+struct Fleet {
+    sessions: Arc<SessionManager>,
+    dispatcher: Arc<CommandDispatcher>,
+}
+
+/// A device ID from a request, normalized like the drone's own
+fn device_id(raw: &str) -> Result<DeviceId, String> {
+    raw.parse().map_err(|e| format!("Bad device ID: {}", e))
+}
+
+#[tonic::async_trait]
+impl FleetService for Fleet {
+    async fn list_drones(
+        &self,
+        _request: Request<ListDronesRequest>,
+    ) -> Result<Response<ListDronesResponse>, Status> {
+        let drones = self
+            .sessions
+            .fleet_snapshot()
+            .await
+            .into_iter()
+            .map(|info| DroneInfo {
+                device_id: info.device_id,
+                device_type: info.device_type.into(),
+                addr: info.addr.to_string(),
+                state: info.state.into(),
+                transport: info.transport.into(),
+                connected_secs: info.connected_at.elapsed().as_secs(),
+                last_heartbeat_ms_ago: info.last_heartbeat.elapsed().as_millis() as u64,
+                pending_commands: info.pending_commands,
+                send_queue_depth: info.send_queue_depth as u32,
+                relay: info.relay_status,
+            })
+            .collect();
+        Ok(Response::new(ListDronesResponse { drones }))
+    }
+
+    type SubscribeTelemetryStream = ReceiverStream<Result<TelemetryUpdate, Status>>;
+
+    async fn subscribe_telemetry(
+        &self,
+        request: Request<SubscribeTelemetryRequest>,
+    ) -> Result<Response<Self::SubscribeTelemetryStream>, Status> {
+        let devices = request
+            .into_inner()
+            .device_ids
+            .iter()
+            .map(|raw| device_id(raw).map(|id| id.as_str().to_string()))
+            .collect::<Result<HashSet<_>, _>>()
+            .map_err(Status::invalid_argument)?;
+        // Subscribed before answering, so no frame after the reply is missed
+        let mut events = self.sessions.events().subscribe();
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_QUEUE_LEN);
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = events.recv() => event,
+                    // The client hung up
+                    _ = tx.closed() => return,
+                };
+                let update = match event {
+                    Ok(ServerEvent::TelemetryReceived {
+                        device_id,
+                        timestamp_ms,
+                        telemetry,
+                    }) if devices.is_empty() || devices.contains(&device_id) => TelemetryUpdate {
+                        device_id,
+                        timestamp_ms,
+                        telemetry: Some(telemetry),
+                    },
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(n)) => {
+                        eprintln!("[GRPC] Telemetry subscriber lagged, skipped {} events", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                if tx.send(Ok(update)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn send_command(
+        &self,
+        request: Request<SendCommandRequest>,
+    ) -> Result<Response<SendCommandResponse>, Status> {
+        let request = request.into_inner();
+        let device_id = device_id(&request.device_id).map_err(Status::invalid_argument)?;
+        let device_id = device_id.as_str();
+        let Some(mut command) = request.command else {
+            return Err(Status::invalid_argument("No command given"));
+        };
+        if self.sessions.get_info(device_id).await.is_none() {
+            return Err(Status::not_found(format!("Drone {} not found", device_id)));
+        }
+        let refused = |e: anyhow::Error| Status::failed_precondition(e.to_string());
+        if let Some(command::Params::MissionStart(mission)) = &command.params {
+            MissionPlan::from_mission_start(mission, &request.waypoints)
+                .validate()
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        }
+
+        let mut upload_command_ids = Vec::new();
+        if !request.waypoints.is_empty() {
+            let Some(command::Params::MissionStart(mission)) = &command.params else {
+                return Err(Status::invalid_argument("Waypoints go with a mission start only"));
+            };
+            upload_command_ids = self
+                .dispatcher
+                .upload_mission(device_id, &mission.mission_id, &request.waypoints)
+                .await
+                .map_err(refused)?;
+        }
+        command.command_id = self.dispatcher.next_command_id();
+        let cmd_type = command.cmd_type();
+        let command_id = self
+            .dispatcher
+            .send_command(device_id, command)
+            .await
+            .map_err(refused)?;
+        println!("[GRPC] Command {} ({:?}) sent to {}", command_id, cmd_type, device_id);
+        Ok(Response::new(SendCommandResponse {
+            command_id,
+            upload_command_ids,
+        }))
+    }
+
+    async fn get_command_status(
+        &self,
+        request: Request<GetCommandStatusRequest>,
+    ) -> Result<Response<CommandStatus>, Status> {
+        let command_id = request.into_inner().command_id;
+        match self.dispatcher.command_status(command_id).await {
+            Some((device_id, audit)) => Ok(Response::new(command_status(device_id, &audit))),
+            None => Err(Status::not_found(format!("Command {} not found", command_id))),
+        }
+    }
+}
+
+fn command_status(device_id: String, audit: &CommandAudit) -> CommandStatus {
+    let state = match (audit.outcome, audit.sent_at) {
+        (Some(_), _) => CommandState::CommandFinished,
+        (None, Some(_)) => CommandState::CommandSent,
+        (None, None) => CommandState::CommandQueued,
+    };
+    CommandStatus {
+        command_id: audit.command_id,
+        device_id,
+        cmd_type: audit.cmd_type.into(),
+        priority: audit.priority,
+        state: state.into(),
+        outcome: audit.outcome.map_or(0, Into::into),
+        message: audit.outcome_message.clone(),
+        queued_at_ms: audit.queued_at,
+        sent_at_ms: audit.sent_at,
+        finished_at_ms: audit.finished_at,
+        retries: audit.retries,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::fleet::fleet_service_client::FleetServiceClient;
+    use resqterra_shared::{priority, AckStatus, CommandType, Telemetry};
+    use std::sync::atomic::AtomicU64;
+    use tonic::transport::Channel;
+    use tonic::Code;
+
+    #[test]
+    fn test_command_states() {
+        let mut audit = CommandAudit {
+            command_id: 3,
+            cmd_type: CommandType::CmdRth,
+            priority: priority::HIGH,
+            queued_at: 100,
+            sent_at: None,
+            retries: 0,
+            outcome: None,
+            outcome_message: String::new(),
+            finished_at: None,
+        };
+        assert_eq!(command_status("edge-001".into(), &audit).state(), CommandState::CommandQueued);
+        audit.sent_at = Some(110);
+        assert_eq!(command_status("edge-001".into(), &audit).state(), CommandState::CommandSent);
+        audit.outcome = Some(AckStatus::AckCompleted);
+        let status = command_status("edge-001".into(), &audit);
+        assert_eq!(status.state(), CommandState::CommandFinished);
+        assert_eq!(status.outcome(), AckStatus::AckCompleted);
+    }
+
+    #[tokio::test]
+    async fn test_telemetry_streamed_to_subscribers() {
+        let sessions = Arc::new(SessionManager::new());
+        let dispatcher = CommandDispatcher::new(sessions.clone(), Arc::new(AtomicU64::new(0)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            Fleet {
+                sessions: sessions.clone(),
+                dispatcher: Arc::new(dispatcher),
+            },
+        ));
+        let channel = Channel::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = FleetServiceClient::new(channel);
+
+        let request = SubscribeTelemetryRequest {
+            device_ids: vec!["edge-001".into()],
+        };
+        let mut stream = client.subscribe_telemetry(request).await.unwrap().into_inner();
+        let telemetry = |uptime_seconds| Telemetry {
+            uptime_seconds,
+            ..Default::default()
+        };
+        sessions.publish_telemetry("edge-002", 1000, &telemetry(7)).await;
+        sessions.publish_telemetry("edge-001", 2000, &telemetry(42)).await;
+        let update = stream.message().await.unwrap().expect("telemetry update");
+        assert_eq!((update.device_id.as_str(), update.timestamp_ms), ("edge-001", 2000));
+        assert_eq!(update.telemetry.unwrap().uptime_seconds, 42);
+
+        let drones = client.list_drones(ListDronesRequest {}).await.unwrap();
+        assert!(drones.into_inner().drones.is_empty());
+        let status = client
+            .get_command_status(GetCommandStatusRequest { command_id: 1 })
+            .await;
+        assert_eq!(status.unwrap_err().code(), Code::NotFound);
+        let request = SendCommandRequest {
+            device_id: "edge-001".into(),
+            command: Some(Default::default()),
+            waypoints: Vec::new(),
+        };
+        let sent = client.send_command(request).await;
+        assert_eq!(sent.unwrap_err().code(), Code::NotFound);
+    }
+}
@@ -0,0 +1,1013 @@
+//! HTTP control API for operations tooling
+//!
+//! JSON over HTTP, so tooling can watch and drive the fleet without linking
+//! against the Rust crates:
+//!
+//! - `GET /drones`: every connected drone and relay
+//! - `GET /drones/{id}/telemetry`: a drone's latest telemetry;
+//!   `GET /drones/{id}/telemetry/live` is a WebSocket feed of it (see
+//!   `dashboard`)
+//! - `GET /drones/{id}/mission`: progress of the mission a drone flies
+//! - `GET /drones/{id}/history`, `GET /drones/{id}/track`: stored telemetry
+//!   over a time range (see `storage`), as samples or as a GeoJSON flight path
+//! - `POST /drones/{id}/commands`: a mission start (the fields of a mission
+//!   file, validated first), return to home or emergency stop; answered with
+//!   `202 Accepted` and the command ID
+//! - `GET /commands/{id}`: a command's progress and outcome;
+//!   `POST /commands/{id}/cancel` drops it while queued or has the drone
+//!   abort it (answered with the command ID of the cancel)
+//! - `GET /retask`: missions proposed for hand-off from drones that dropped
+//!   out; `POST /retask/{id}/approve` sends one, `POST /retask/{id}/reject`
+//!   drops it (see `retask`)
+//! - `GET /detections`: stored detection reports, filtered by `device_id`,
+//!   `mission_id`, `from_ms`, `to_ms` and `min_confidence` (0-1)
+//! - `GET /audit`: the durable command audit log, filtered by `device_id`,
+//!   `type` (`CMD_RTH`), `from_ms` and `to_ms` (see `command::audit_log`)
+//! - `GET /events`: a WebSocket feed of telemetry, state changes and
+//!   detections (see `dashboard`)
+//!
+//! Commands go through the `CommandDispatcher` like any other: queued by
+//! priority, retried and audited. Enum values are their proto names
+//! (`DRONE_IN_MISSION`, `CMD_RTH`). The API has no authentication, so it
+//! listens on loopback unless `SERVER_HTTP_LISTEN` says otherwise; expose it
+//! only behind something that does.
+
+use crate::command::{AuditQuery, CommandAudit, CommandDispatcher, MAX_AUDIT_ROWS};
+use crate::dashboard;
+use crate::retask::{RetaskProposal, Retasker};
+use crate::session::SessionManager;
+use crate::alerts::detection_json;
+use crate::storage::{DetectionQuery, HistoryQuery, Storage, TelemetryRecord, MAX_QUERY_ROWS};
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use resqterra_shared::{
+    command, now_ms, priority, Command, CommandType, DeviceId, DroneState, EmergencyStop,
+    GpsCoordinate, ParamGet, ParamSet, ReturnToHome, Telemetry, VideoProtocol, VideoStart,
+    VideoStop,
+};
+use resqterra_shared::mission::MissionPlan;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Where the API listens unless `SERVER_HTTP_LISTEN` is set
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8090";
+
+/// History returned when a query gives no `from_ms`
+const DEFAULT_HISTORY_MS: u64 = 3600 * 1000;
+
+/// Where to serve the API
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub listen: String,
+}
+
+impl HttpConfig {
+    /// Read `SERVER_HTTP_LISTEN` (`host:port`); None when it is `off`
+    pub fn from_env() -> Option<Self> {
+        let listen = std::env::var("SERVER_HTTP_LISTEN").unwrap_or_else(|_| DEFAULT_LISTEN.into());
+        if listen == "off" {
+            return None;
+        }
+        Some(Self { listen })
+    }
+}
+
+/// Bind the API and serve it in the background
+pub async fn spawn(
+    config: HttpConfig,
+    sessions: Arc<SessionManager>,
+    dispatcher: Arc<CommandDispatcher>,
+    storage: Option<Arc<Storage>>,
+    retasker: Option<Arc<Retasker>>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(&config.listen).await?;
+    let app = router(ApiState {
+        sessions,
+        dispatcher,
+        storage,
+        retasker,
+    });
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            eprintln!("[HTTP] API server failed: {}", e);
+        }
+    });
+    Ok(())
+}
+
+#[derive(Clone)]
+struct ApiState {
+    sessions: Arc<SessionManager>,
+    dispatcher: Arc<CommandDispatcher>,
+    /// None when telemetry storage is off
+    storage: Option<Arc<Storage>>,
+    /// None when re-tasking is off
+    retasker: Option<Arc<Retasker>>,
+}
+
+fn router(state: ApiState) -> Router {
+    let events = state.sessions.events();
+    Router::new()
+        .route("/drones", get(list_drones))
+        .route("/drones/{id}/telemetry", get(drone_telemetry))
+        .route("/drones/{id}/telemetry/live", get(drone_telemetry_live))
+        .route("/drones/{id}/mission", get(drone_mission))
+        .route("/drones/{id}/history", get(drone_history))
+        .route("/drones/{id}/track", get(drone_track))
+        .route("/drones/{id}/commands", post(send_command))
+        .route("/commands/{id}", get(command_status))
+        .route("/commands/{id}/cancel", post(cancel_command))
+        .route("/detections", get(list_detections))
+        .route("/audit", get(command_audit))
+        .route("/retask", get(retask_proposals))
+        .route("/retask/{id}/approve", post(approve_retask))
+        .route("/retask/{id}/reject", post(reject_retask))
+        .with_state(state)
+        .merge(dashboard::router(events))
+}
+
+/// An error answered as `{"error": "..."}`
+#[derive(Debug)]
+pub struct ApiError(pub StatusCode, pub String);
+
+impl ApiError {
+    fn not_found(what: impl std::fmt::Display) -> Self {
+        Self(StatusCode::NOT_FOUND, format!("{} not found", what))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// The device ID in a request path, normalized like the drone's own
+fn device_id(raw: &str) -> Result<DeviceId, ApiError> {
+    raw.parse()
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("Bad device ID: {}", e)))
+}
+
+async fn list_drones(State(state): State<ApiState>) -> Json<Value> {
+    let drones: Vec<Value> = state
+        .sessions
+        .fleet_snapshot()
+        .await
+        .into_iter()
+        .map(|info| {
+            let relay = info.relay_status.map(|relay| {
+                let links: Vec<Value> = relay
+                    .links
+                    .iter()
+                    .map(|link| {
+                        json!({
+                            "device_id": link.device_id,
+                            "rssi_dbm": link.rssi_dbm,
+                            "buffered_bytes": link.buffered_bytes,
+                        })
+                    })
+                    .collect();
+                json!({
+                    "connected_drones": relay.connected_drones,
+                    "buffered_bytes": relay.buffered_bytes,
+                    "links": links,
+                })
+            });
+            json!({
+                "device_id": info.device_id,
+                "device_type": info.device_type.as_str_name(),
+                "addr": info.addr.to_string(),
+                "state": info.state.as_str_name(),
+                "transport": info.transport.as_str_name(),
+                "connected_secs": info.connected_at.elapsed().as_secs(),
+                "last_heartbeat_ms_ago": info.last_heartbeat.elapsed().as_millis() as u64,
+                "pending_commands": info.pending_commands,
+                "send_queue_depth": info.send_queue_depth,
+                "relay": relay,
+            })
+        })
+        .collect();
+    Json(Value::Array(drones))
+}
+
+async fn drone_telemetry(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let device_id = device_id(&id)?;
+    let Some((at_ms, telemetry)) = state.sessions.latest_telemetry(device_id.as_str()).await
+    else {
+        return Err(ApiError::not_found(format_args!("Telemetry for {}", device_id)));
+    };
+    Ok(Json(telemetry_json(device_id.as_str(), at_ms, &telemetry)))
+}
+
+/// A WebSocket feed of one drone's telemetry (see `dashboard`)
+async fn drone_telemetry_live(
+    ws: WebSocketUpgrade,
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+) -> Result<Response, ApiError> {
+    let device_id = device_id(&id)?.as_str().to_string();
+    let telemetry = state.sessions.subscribe_device_telemetry(&device_id).await;
+    Ok(ws.on_upgrade(move |socket| {
+        dashboard::stream_drone_telemetry(socket, device_id, telemetry)
+    }))
+}
+
+pub fn telemetry_json(device_id: &str, at_ms: u64, tel: &Telemetry) -> Value {
+    let state = DroneState::try_from(tel.state).unwrap_or(DroneState::DroneUnknown);
+    json!({
+        "device_id": device_id,
+        "timestamp_ms": at_ms,
+        "state": state.as_str_name(),
+        "mission_id": tel.mission_id,
+        "uptime_seconds": tel.uptime_seconds,
+        "position": tel.position.map(|pos| json!({
+            "latitude": pos.latitude,
+            "longitude": pos.longitude,
+            "altitude_m": pos.altitude_m,
+            "heading_deg": pos.heading_deg,
+            "ground_speed_mps": pos.ground_speed_mps,
+            "satellites": pos.satellites,
+            "hdop": pos.hdop,
+        })),
+        "battery": tel.battery.map(|bat| json!({
+            "voltage": bat.voltage,
+            "current": bat.current,
+            "remaining_percent": bat.remaining_percent,
+            "remaining_seconds": bat.remaining_seconds,
+        })),
+        "fc_status": tel.fc_status.as_ref().map(|fc| json!({
+            "armed": fc.armed,
+            "gps_lock": fc.gps_lock,
+            "mode": fc.mode,
+            "error_count": fc.error_count,
+            "active_faults": fc.active_faults,
+        })),
+        "conn_quality": tel.conn_quality.map(|quality| json!({
+            "active_transport": quality.active_transport().as_str_name(),
+            "rssi_dbm": quality.rssi_dbm,
+            "latency_ms": quality.latency_ms,
+            "packet_loss_percent": quality.packet_loss_percent,
+            "jitter_ms": quality.jitter_ms,
+        })),
+        "payload_values": tel.payload_values,
+        "video": tel.video.as_ref().map(|video| json!({
+            "stream_id": video.stream_id,
+            "protocol": video.protocol().as_str_name(),
+            "url": video.url,
+            "camera": video.camera,
+            "codec": video.codec,
+            "width": video.width,
+            "height": video.height,
+            "framerate": video.framerate,
+            "bitrate_kbps": video.bitrate_kbps,
+            "state": video.state().as_str_name(),
+            "started_at_ms": video.started_at_ms,
+            "error": video.error,
+        })),
+    })
+}
+
+async fn drone_mission(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let device_id = device_id(&id)?;
+    let Some(progress) = state.dispatcher.mission_progress(device_id.as_str()).await else {
+        return Err(ApiError::not_found(format_args!("Mission progress for {}", device_id)));
+    };
+    Ok(Json(json!({
+        "device_id": device_id.as_str(),
+        "mission_id": progress.mission_id,
+        "current_waypoint": progress.current_waypoint,
+        "total_waypoints": progress.total_waypoints,
+        "last_reached": progress.last_reached,
+        "percent_complete": progress.percent_complete,
+        "distance_remaining_m": progress.distance_remaining_m,
+        "eta_seconds": progress.eta_seconds,
+    })))
+}
+
+/// `GET /drones/{id}/history` and `/track` query; times in ms since epoch
+#[derive(Debug, Default, Deserialize)]
+struct HistoryParams {
+    /// Default: an hour before `to_ms`
+    from_ms: Option<u64>,
+    /// Default: now
+    to_ms: Option<u64>,
+    /// Oldest samples first, at most `MAX_QUERY_ROWS`
+    limit: Option<usize>,
+}
+
+impl HistoryParams {
+    fn query(&self, device_id: &str, now_ms: u64) -> HistoryQuery {
+        let to_ms = self.to_ms.unwrap_or(now_ms);
+        HistoryQuery {
+            device_id: device_id.to_string(),
+            from_ms: self.from_ms.unwrap_or(to_ms.saturating_sub(DEFAULT_HISTORY_MS)),
+            to_ms,
+            limit: self.limit.unwrap_or(MAX_QUERY_ROWS),
+        }
+    }
+}
+
+/// Stored samples for `GET /drones/{id}/history` and `/track`; drones that
+/// have since disconnected still have them
+async fn stored_history(
+    state: &ApiState,
+    id: &str,
+    params: &HistoryParams,
+) -> Result<(DeviceId, Vec<TelemetryRecord>), ApiError> {
+    let device_id = device_id(id)?;
+    let Some(storage) = &state.storage else {
+        let off = "Telemetry storage is off (SERVER_TELEMETRY_DB=off)";
+        return Err(ApiError(StatusCode::SERVICE_UNAVAILABLE, off.into()));
+    };
+    let records = storage
+        .history(params.query(device_id.as_str(), now_ms()))
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok((device_id, records))
+}
+
+async fn drone_history(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<Value>, ApiError> {
+    let (_, records) = stored_history(&state, &id, &params).await?;
+    let samples = records
+        .iter()
+        .map(|record| telemetry_json(&record.device_id, record.timestamp_ms, &record.telemetry))
+        .collect();
+    Ok(Json(Value::Array(samples)))
+}
+
+async fn drone_track(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<Value>, ApiError> {
+    let (device_id, records) = stored_history(&state, &id, &params).await?;
+    Ok(Json(track_geojson(device_id.as_str(), &records)))
+}
+
+/// A flight path as a GeoJSON `LineString` feature, with the time of each
+/// point in `timestamps_ms`
+fn track_geojson(device_id: &str, records: &[TelemetryRecord]) -> Value {
+    // Samples taken before a GPS fix have no position, or one at 0,0
+    let points: Vec<_> = records
+        .iter()
+        .filter_map(|record| Some((record.timestamp_ms, record.telemetry.position?)))
+        .filter(|(_, pos)| pos.latitude != 0.0 || pos.longitude != 0.0)
+        .collect();
+    let coordinates: Vec<Value> = points
+        .iter()
+        .map(|(_, pos)| json!([pos.longitude, pos.latitude, pos.altitude_m]))
+        .collect();
+    let timestamps: Vec<u64> = points.iter().map(|(at_ms, _)| *at_ms).collect();
+    json!({
+        "type": "Feature",
+        "geometry": { "type": "LineString", "coordinates": coordinates },
+        "properties": { "device_id": device_id, "timestamps_ms": timestamps },
+    })
+}
+
+/// `GET /detections` query; times in ms since epoch, every detection by default
+#[derive(Debug, Default, Deserialize)]
+struct DetectionParams {
+    device_id: Option<String>,
+    mission_id: Option<String>,
+    from_ms: Option<u64>,
+    to_ms: Option<u64>,
+    min_confidence: Option<f32>,
+    /// Oldest detections first, at most `MAX_QUERY_ROWS`
+    limit: Option<usize>,
+}
+
+impl DetectionParams {
+    fn query(&self) -> Result<DetectionQuery, ApiError> {
+        let device_id = match &self.device_id {
+            Some(raw) => Some(device_id(raw)?.as_str().to_string()),
+            None => None,
+        };
+        Ok(DetectionQuery {
+            device_id,
+            mission_id: self.mission_id.clone(),
+            from_ms: self.from_ms.unwrap_or(0),
+            // SQLite integers are signed
+            to_ms: self.to_ms.unwrap_or(i64::MAX as u64),
+            min_confidence: self.min_confidence.unwrap_or(0.0),
+            limit: self.limit.unwrap_or(MAX_QUERY_ROWS),
+        })
+    }
+}
+
+async fn list_detections(
+    State(state): State<ApiState>,
+    Query(params): Query<DetectionParams>,
+) -> Result<Json<Value>, ApiError> {
+    let query = params.query()?;
+    let Some(storage) = &state.storage else {
+        let off = "Telemetry storage is off (SERVER_TELEMETRY_DB=off)";
+        return Err(ApiError(StatusCode::SERVICE_UNAVAILABLE, off.into()));
+    };
+    let records = storage
+        .detections(query)
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let detections = records
+        .iter()
+        .map(|record| detection_json(&record.device_id, &record.report))
+        .collect();
+    Ok(Json(Value::Array(detections)))
+}
+
+/// `POST /drones/{id}/commands` body
+#[derive(Debug, Deserialize)]
+struct CommandRequest {
+    #[serde(flatten)]
+    kind: CommandKind,
+    /// Overrides the command's usual priority (emergency stops ignore it)
+    priority: Option<u32>,
+    /// Drop the command if the drone hasn't run it by then
+    expires_in_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CommandKind {
+    /// A mission file's fields (see `resqterra_shared::mission`)
+    MissionStart(MissionPlan),
+    ReturnToHome {
+        #[serde(default)]
+        altitude_m: f32,
+        #[serde(default)]
+        speed_mps: f32,
+    },
+    EmergencyStop,
+    /// Fields left out are chosen by the drone
+    VideoStart {
+        #[serde(default)]
+        camera: String,
+        #[serde(default)]
+        protocol: StreamProtocol,
+        #[serde(default)]
+        codec: String,
+        #[serde(default)]
+        width: u32,
+        #[serde(default)]
+        height: u32,
+        #[serde(default)]
+        framerate: u32,
+        #[serde(default)]
+        bitrate_kbps: u32,
+    },
+    VideoStop,
+    /// Flight controller parameters by name, e.g. `RTL_ALT`
+    ParamGet {
+        names: Vec<String>,
+    },
+    ParamSet {
+        name: String,
+        value: f32,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum StreamProtocol {
+    #[default]
+    Rtsp,
+    Webrtc,
+}
+
+impl CommandRequest {
+    /// The command to dispatch (without its ID) and the waypoints to upload
+    /// ahead of it
+    fn into_command(self, now_ms: u64) -> Result<(Command, Vec<GpsCoordinate>), String> {
+        let (cmd_type, default_priority, params, waypoints) = match self.kind {
+            CommandKind::MissionStart(plan) => {
+                plan.validate().map_err(|e| e.to_string())?;
+                let mission = plan.mission_start();
+                let waypoints = plan.waypoints();
+                (
+                    CommandType::CmdMissionStart,
+                    priority::NORMAL,
+                    command::Params::MissionStart(mission),
+                    waypoints,
+                )
+            }
+            CommandKind::ReturnToHome {
+                altitude_m,
+                speed_mps,
+            } => (
+                CommandType::CmdRth,
+                priority::HIGH,
+                command::Params::Rth(ReturnToHome {
+                    altitude_m,
+                    speed_mps,
+                }),
+                Vec::new(),
+            ),
+            CommandKind::EmergencyStop => (
+                CommandType::CmdEmergencyStop,
+                priority::EMERGENCY,
+                command::Params::EmergencyStop(EmergencyStop {}),
+                Vec::new(),
+            ),
+            CommandKind::VideoStart {
+                camera,
+                protocol,
+                codec,
+                width,
+                height,
+                framerate,
+                bitrate_kbps,
+            } => {
+                let protocol = match protocol {
+                    StreamProtocol::Rtsp => VideoProtocol::VideoRtsp,
+                    StreamProtocol::Webrtc => VideoProtocol::VideoWebrtc,
+                };
+                let start = VideoStart {
+                    camera,
+                    protocol: protocol.into(),
+                    codec,
+                    width,
+                    height,
+                    framerate,
+                    bitrate_kbps,
+                };
+                (
+                    CommandType::CmdVideoStart,
+                    priority::NORMAL,
+                    command::Params::VideoStart(start),
+                    Vec::new(),
+                )
+            }
+            CommandKind::VideoStop => (
+                CommandType::CmdVideoStop,
+                priority::NORMAL,
+                command::Params::VideoStop(VideoStop {}),
+                Vec::new(),
+            ),
+            CommandKind::ParamGet { names } => (
+                CommandType::CmdParamGet,
+                priority::NORMAL,
+                command::Params::ParamGet(ParamGet { names }),
+                Vec::new(),
+            ),
+            CommandKind::ParamSet { name, value } => (
+                CommandType::CmdParamSet,
+                priority::NORMAL,
+                command::Params::ParamSet(ParamSet { name, value }),
+                Vec::new(),
+            ),
+        };
+        let command = Command {
+            cmd_type: cmd_type.into(),
+            expires_at_ms: self.expires_in_ms.map_or(0, |ms| now_ms + ms),
+            priority: self.priority.unwrap_or(default_priority),
+            params: Some(params),
+            ..Default::default()
+        };
+        Ok((command, waypoints))
+    }
+}
+
+async fn send_command(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    Json(request): Json<CommandRequest>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let device_id = device_id(&id)?;
+    let device_id = device_id.as_str();
+    if state.sessions.get_info(device_id).await.is_none() {
+        return Err(ApiError::not_found(format_args!("Drone {}", device_id)));
+    }
+    let (mut command, waypoints) = request
+        .into_command(now_ms())
+        .map_err(|e| ApiError(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    let refused = |e: anyhow::Error| ApiError(StatusCode::CONFLICT, e.to_string());
+
+    let mut upload_ids = Vec::new();
+    if let Some(command::Params::MissionStart(mission)) = &command.params {
+        if !waypoints.is_empty() {
+            upload_ids = state
+                .dispatcher
+                .upload_mission(device_id, &mission.mission_id, &waypoints)
+                .await
+                .map_err(refused)?;
+        }
+    }
+    command.command_id = state.dispatcher.next_command_id();
+    let cmd_type = command.cmd_type();
+    let command_id = state
+        .dispatcher
+        .send_command(device_id, command)
+        .await
+        .map_err(refused)?;
+    println!("[HTTP] Command {} ({:?}) sent to {}", command_id, cmd_type, device_id);
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({ "command_id": command_id, "upload_command_ids": upload_ids })),
+    ))
+}
+
+async fn command_status(
+    State(state): State<ApiState>,
+    Path(command_id): Path<u64>,
+) -> Result<Json<Value>, ApiError> {
+    match state.dispatcher.command_status(command_id).await {
+        Some((device_id, audit)) => Ok(Json(command_json(&device_id, &audit))),
+        None => Err(ApiError::not_found(format_args!("Command {}", command_id))),
+    }
+}
+
+async fn cancel_command(
+    State(state): State<ApiState>,
+    Path(command_id): Path<u64>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let cancel_id = state
+        .dispatcher
+        .cancel(command_id)
+        .await
+        .map_err(|e| ApiError(StatusCode::CONFLICT, e.to_string()))?;
+    println!("[HTTP] Command {} cancelled (command {})", command_id, cancel_id);
+    Ok((StatusCode::ACCEPTED, Json(json!({ "command_id": cancel_id }))))
+}
+
+fn retasker(state: &ApiState) -> Result<&Retasker, ApiError> {
+    state
+        .retasker
+        .as_deref()
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "Re-tasking is off".into()))
+}
+
+async fn retask_proposals(State(state): State<ApiState>) -> Result<Json<Value>, ApiError> {
+    let proposals = retasker(&state)?.pending().await;
+    Ok(Json(Value::Array(proposals.iter().map(proposal_json).collect())))
+}
+
+async fn approve_retask(
+    State(state): State<ApiState>,
+    Path(id): Path<u64>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let retasker = retasker(&state)?;
+    let Some(proposal) = retasker.take(id).await else {
+        return Err(ApiError::not_found(format_args!("Re-task proposal {}", id)));
+    };
+    let command_id = retasker
+        .dispatch(&proposal)
+        .await
+        .map_err(|e| ApiError(StatusCode::CONFLICT, e.to_string()))?;
+    Ok((StatusCode::ACCEPTED, Json(json!({ "command_id": command_id }))))
+}
+
+async fn reject_retask(
+    State(state): State<ApiState>,
+    Path(id): Path<u64>,
+) -> Result<Json<Value>, ApiError> {
+    match retasker(&state)?.take(id).await {
+        Some(proposal) => {
+            println!("[HTTP] Re-task proposal {} rejected", id);
+            Ok(Json(proposal_json(&proposal)))
+        }
+        None => Err(ApiError::not_found(format_args!("Re-task proposal {}", id))),
+    }
+}
+
+fn proposal_json(proposal: &RetaskProposal) -> Value {
+    json!({
+        "id": proposal.id,
+        "from_device": proposal.from_device,
+        "reason": proposal.reason,
+        "to_device": proposal.to_device,
+        "distance_m": proposal.distance_m,
+        "mission_id": proposal.mission.mission_id,
+        "waypoints": proposal.waypoints.len(),
+        "created_at_ms": proposal.created_at_ms,
+    })
+}
+
+/// `GET /audit` query; times in ms since epoch, every entry by default
+#[derive(Debug, Default, Deserialize)]
+struct AuditParams {
+    device_id: Option<String>,
+    /// A command type by proto name, e.g. `CMD_RTH`
+    #[serde(rename = "type")]
+    cmd_type: Option<String>,
+    from_ms: Option<u64>,
+    to_ms: Option<u64>,
+    /// Oldest entries first, at most `MAX_AUDIT_ROWS`
+    limit: Option<usize>,
+}
+
+impl AuditParams {
+    fn query(&self) -> Result<AuditQuery, ApiError> {
+        let device_id = match &self.device_id {
+            Some(raw) => Some(device_id(raw)?.as_str().to_string()),
+            None => None,
+        };
+        let cmd_type = match &self.cmd_type {
+            Some(name) => Some(CommandType::from_str_name(name).ok_or_else(|| {
+                ApiError(StatusCode::BAD_REQUEST, format!("Unknown command type {:?}", name))
+            })?),
+            None => None,
+        };
+        Ok(AuditQuery {
+            device_id,
+            cmd_type,
+            from_ms: self.from_ms.unwrap_or(0),
+            to_ms: self.to_ms.unwrap_or(u64::MAX),
+            limit: self.limit.unwrap_or(MAX_AUDIT_ROWS),
+        })
+    }
+}
+
+async fn command_audit(
+    State(state): State<ApiState>,
+    Query(params): Query<AuditParams>,
+) -> Result<Json<Value>, ApiError> {
+    let query = params.query()?;
+    let Some(log) = state.dispatcher.audit_log() else {
+        let off = "Command audit log is off (SERVER_COMMAND_AUDIT_LOG=off)";
+        return Err(ApiError(StatusCode::SERVICE_UNAVAILABLE, off.into()));
+    };
+    let entries = log
+        .search(query)
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(json!(entries)))
+}
+
+fn command_json(device_id: &str, audit: &CommandAudit) -> Value {
+    // Final outcomes by their ACK status, without the `ACK_` prefix
+    let status = match (audit.outcome, audit.sent_at) {
+        (Some(outcome), _) => outcome.as_str_name().trim_start_matches("ACK_"),
+        (None, Some(_)) => "SENT",
+        (None, None) => "QUEUED",
+    };
+    json!({
+        "command_id": audit.command_id,
+        "device_id": device_id,
+        "type": audit.cmd_type.as_str_name(),
+        "priority": audit.priority,
+        "status": status,
+        "message": audit.outcome_message,
+        "queued_at_ms": audit.queued_at,
+        "sent_at_ms": audit.sent_at,
+        "finished_at_ms": audit.finished_at,
+        "retries": audit.retries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{AuditEntry, AuditEvent, AuditLog};
+    use crate::storage::{DetectionRecord, SqliteStore};
+    use resqterra_shared::{AckStatus, DetectionReport, GpsPosition, ScanPattern};
+    use std::sync::atomic::AtomicU64;
+
+    fn request(body: &str) -> Result<(Command, Vec<GpsCoordinate>), String> {
+        let request: CommandRequest = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        request.into_command(1000)
+    }
+
+    #[test]
+    fn test_command_requests_map_to_commands() {
+        let (rth, _) = request(r#"{"type": "return_to_home", "altitude_m": 50}"#).unwrap();
+        assert_eq!(rth.cmd_type(), CommandType::CmdRth);
+        assert_eq!((rth.priority, rth.expires_at_ms), (priority::HIGH, 0));
+
+        let body = r#"{"type": "emergency_stop", "expires_in_ms": 500}"#;
+        let (stop, _) = request(body).unwrap();
+        assert_eq!(stop.effective_priority(), priority::EMERGENCY);
+        assert_eq!(stop.expires_at_ms, 1500);
+
+        let body = r#"{"type": "mission_start", "mission_id": "m1", "altitude_m": 40,
+            "speed_mps": 5, "waypoints": [{"latitude": 1, "longitude": 2}]}"#;
+        let (start, waypoints) = request(body).unwrap();
+        let Some(command::Params::MissionStart(mission)) = start.params else {
+            panic!("not a mission start");
+        };
+        assert_eq!(mission.scan_pattern(), ScanPattern::PatternCustom);
+        assert_eq!(mission.uploaded_waypoints, 1);
+        assert_eq!(waypoints[0].longitude, 2.0);
+
+        let body = r#"{"type": "mission_start", "mission_id": "m1", "altitude_m": 40,
+            "speed_mps": 5, "pattern": "zigzag"}"#;
+        assert!(request(body).is_err());
+        let body = r#"{"type": "mission_start", "mission_id": "m1", "altitude_m": 400,
+            "speed_mps": 5, "waypoints": [{"latitude": 1, "longitude": 2}]}"#;
+        assert!(request(body).unwrap_err().contains("altitude"));
+
+        let body = r#"{"type": "video_start", "protocol": "webrtc", "width": 640, "height": 480}"#;
+        let (video, _) = request(body).unwrap();
+        let Some(command::Params::VideoStart(start)) = video.params else {
+            panic!("not a video start");
+        };
+        assert_eq!(start.protocol(), VideoProtocol::VideoWebrtc);
+        assert_eq!((start.width, start.height, start.codec.as_str()), (640, 480, ""));
+        let (stop, _) = request(r#"{"type": "video_stop"}"#).unwrap();
+        assert_eq!(stop.cmd_type(), CommandType::CmdVideoStop);
+        assert!(request(r#"{"type": "video_start", "protocol": "hls"}"#).is_err());
+
+        let body = r#"{"type": "param_set", "name": "RTL_ALT", "value": 1500}"#;
+        let (set, _) = request(body).unwrap();
+        assert_eq!(set.cmd_type(), CommandType::CmdParamSet);
+        assert!(set.validate_params().is_ok());
+        let (get, _) = request(r#"{"type": "param_get", "names": []}"#).unwrap();
+        assert!(get.validate_params().is_err());
+    }
+
+    #[test]
+    fn test_command_status_names() {
+        let mut audit = CommandAudit {
+            command_id: 3,
+            cmd_type: CommandType::CmdRth,
+            priority: priority::HIGH,
+            queued_at: 100,
+            sent_at: None,
+            retries: 0,
+            outcome: None,
+            outcome_message: String::new(),
+            finished_at: None,
+        };
+        assert_eq!(command_json("edge-001", &audit)["status"], "QUEUED");
+        audit.sent_at = Some(110);
+        assert_eq!(command_json("edge-001", &audit)["status"], "SENT");
+        audit.outcome = Some(AckStatus::AckCompleted);
+        let json = command_json("edge-001", &audit);
+        assert_eq!((&json["status"], &json["type"]), (&json!("COMPLETED"), &json!("CMD_RTH")));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_drones_and_commands_not_found() {
+        let sessions = Arc::new(SessionManager::new());
+        let dispatcher = CommandDispatcher::new(sessions.clone(), Arc::new(AtomicU64::new(0)));
+        let state = ApiState {
+            sessions,
+            dispatcher: Arc::new(dispatcher),
+            storage: None,
+            retasker: None,
+        };
+
+        let telemetry = drone_telemetry(State(state.clone()), Path("edge-001".into())).await;
+        assert_eq!(telemetry.unwrap_err().0, StatusCode::NOT_FOUND);
+        let mission = drone_mission(State(state.clone()), Path("edge-001".into())).await;
+        assert_eq!(mission.unwrap_err().0, StatusCode::NOT_FOUND);
+        let status = command_status(State(state.clone()), Path(1)).await;
+        assert_eq!(status.unwrap_err().0, StatusCode::NOT_FOUND);
+        let cancel = cancel_command(State(state.clone()), Path(1)).await;
+        assert_eq!(cancel.unwrap_err().0, StatusCode::CONFLICT);
+        let retask = approve_retask(State(state.clone()), Path(1)).await;
+        assert_eq!(retask.unwrap_err().0, StatusCode::NOT_FOUND);
+        let bad_id = drone_telemetry(State(state.clone()), Path("edge\u{1b}".into())).await;
+        assert_eq!(bad_id.unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(list_drones(State(state)).await.0, json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_queries() {
+        let path = std::env::temp_dir()
+            .join(format!("resqterra-http-audit-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = Arc::new(AuditLog::open(&path).unwrap());
+        for device_id in ["edge-001", "edge-002"] {
+            let entry = AuditEntry::new(device_id, 1, CommandType::CmdRth, AuditEvent::Sent);
+            log.append(&entry).unwrap();
+        }
+        let sessions = Arc::new(SessionManager::new());
+        let dispatcher = CommandDispatcher::new(sessions.clone(), Arc::new(AtomicU64::new(0)));
+        let state = ApiState {
+            sessions,
+            dispatcher: Arc::new(dispatcher.with_audit_log(log)),
+            storage: None,
+            retasker: None,
+        };
+
+        let params = |device_id: &str, cmd_type: &str| AuditParams {
+            device_id: Some(device_id.into()),
+            cmd_type: Some(cmd_type.into()),
+            ..Default::default()
+        };
+        let audit = command_audit(State(state.clone()), Query(params("edge-002", "CMD_RTH")));
+        let entries = audit.await.unwrap().0;
+        assert_eq!(entries.as_array().unwrap().len(), 1);
+        assert_eq!(entries[0]["device_id"], "edge-002");
+        assert_eq!(entries[0]["event"], "sent");
+        let params_abort = params("edge-002", "CMD_MISSION_ABORT");
+        let audit = command_audit(State(state.clone()), Query(params_abort));
+        assert_eq!(audit.await.unwrap().0, json!([]));
+        let audit = command_audit(State(state), Query(params("edge-002", "RTH")));
+        assert_eq!(audit.await.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        let sessions = Arc::new(SessionManager::new());
+        let dispatcher = CommandDispatcher::new(sessions.clone(), Arc::new(AtomicU64::new(0)));
+        let off = ApiState {
+            sessions,
+            dispatcher: Arc::new(dispatcher),
+            storage: None,
+            retasker: None,
+        };
+        let audit = command_audit(State(off), Query(Default::default())).await;
+        assert_eq!(audit.unwrap_err().0, StatusCode::SERVICE_UNAVAILABLE);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_track_from_stored_telemetry() {
+        let sessions = Arc::new(SessionManager::new());
+        let dispatcher = CommandDispatcher::new(sessions.clone(), Arc::new(AtomicU64::new(0)));
+        let storage = Storage::new(SqliteStore::open_in_memory().unwrap(), None);
+        let sample = |latitude| Telemetry {
+            position: Some(GpsPosition {
+                latitude,
+                longitude: 8.5,
+                altitude_m: 40.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let records = vec![
+            TelemetryRecord::new("edge-001", 1000, &Telemetry::default()),
+            TelemetryRecord::new("edge-001", 2000, &sample(47.0)),
+            TelemetryRecord::new("edge-001", 3000, &sample(47.5)),
+        ];
+        storage.append(records).await.unwrap();
+        let state = ApiState {
+            sessions,
+            dispatcher: Arc::new(dispatcher),
+            storage: Some(Arc::new(storage)),
+            retasker: None,
+        };
+
+        let params = HistoryParams {
+            from_ms: Some(0),
+            to_ms: Some(2500),
+            limit: None,
+        };
+        let track = drone_track(State(state.clone()), Path("edge-001".into()), Query(params)).await;
+        let track = track.unwrap().0;
+        assert_eq!(track["geometry"]["coordinates"], json!([[8.5, 47.0, 40.0]]));
+        assert_eq!(track["properties"]["timestamps_ms"], json!([2000]));
+
+        let off = ApiState { storage: None, ..state };
+        let history = drone_history(State(off), Path("edge-001".into()), Query(Default::default()));
+        assert_eq!(history.await.unwrap_err().0, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_detections_from_storage() {
+        let sessions = Arc::new(SessionManager::new());
+        let dispatcher = CommandDispatcher::new(sessions.clone(), Arc::new(AtomicU64::new(0)));
+        let storage = Storage::new(SqliteStore::open_in_memory().unwrap(), None);
+        for (detection_id, confidence) in [("d1", 0.4), ("d2", 0.95)] {
+            let report = DetectionReport {
+                detection_id: detection_id.into(),
+                mission_id: "m1".into(),
+                label: "person".into(),
+                confidence,
+                detected_at_ms: 1000,
+                ..Default::default()
+            };
+            let record = DetectionRecord {
+                device_id: "edge-001".into(),
+                report,
+            };
+            storage.append_detection(record).await.unwrap();
+        }
+        let state = ApiState {
+            sessions,
+            dispatcher: Arc::new(dispatcher),
+            storage: Some(Arc::new(storage)),
+            retasker: None,
+        };
+
+        let params = DetectionParams {
+            mission_id: Some("m1".into()),
+            min_confidence: Some(0.9),
+            ..Default::default()
+        };
+        let found = list_detections(State(state.clone()), Query(params)).await.unwrap().0;
+        assert_eq!(found.as_array().unwrap().len(), 1);
+        assert_eq!(found[0]["detection_id"], "d2");
+        assert_eq!(found[0]["device_id"], "edge-001");
+        let all = list_detections(State(state.clone()), Query(Default::default())).await;
+        assert_eq!(all.unwrap().0.as_array().unwrap().len(), 2);
+
+        let off = ApiState { storage: None, ..state };
+        let found = list_detections(State(off), Query(Default::default())).await;
+        assert_eq!(found.unwrap_err().0, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
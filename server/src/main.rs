@@ -1,30 +1,209 @@
+mod alerts;
+mod blobs;
 mod command;
+mod dashboard;
+mod events;
+mod fleet;
+mod grpc;
+mod http;
+mod mqtt;
+mod retask;
 mod session;
+mod storage;
 
-use command::{CommandDispatcher, TimeoutTracker};
+use alerts::AlertConfig;
+use blobs::{BlobConfig, BlobStore};
+use command::{
+    AuditLog, AuditLogConfig, CommandAudit, CommandDispatcher, CommandLimits, TimeoutTracker,
+    UploadLimits,
+};
+use events::ServerEvent;
+use fleet::{FleetEvent, FleetState};
+use grpc::GrpcConfig;
+use http::HttpConfig;
+use mqtt::MqttConfig;
+use retask::{RetaskConfig, Retasker};
 use resqterra_shared::{
-    envelope, AckStatus, Command, CommandType, DroneState, Envelope, Header,
-    Heartbeat, MessageType, now_ms,
+    compression, envelope, Command, CommandType, DeltaDecoder, DeviceType, DroneState,
+    Envelope, Header, HeartbeatAck, Hello, MessageType, Telemetry, now_ms, priority,
+    BlobState, BlobStatus,
+    noise::{NoiseConfig, NoiseStream},
+    quic::{self, QuicListener},
+    udp::UdpListener,
+    websocket::WebSocketListener,
+    DeviceId, DeviceKeys, PROTOCOL_VERSION, QUALITY_HISTORY_LEN,
 };
-use session::{DroneSession, SessionManager};
+use session::{DroneSession, SessionManager, SessionStream};
+use storage::{SqliteStore, Storage, StorageConfig};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::time::{interval, Duration};
 
+/// Time a connecting drone gets to complete the Noise handshake
+const NOISE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// UDP port for QUIC links
+const QUIC_PORT: u16 = 8443;
+
+/// TCP port for WebSocket links (browsers and gateways)
+const WEBSOCKET_PORT: u16 = 8081;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let listener = TcpListener::bind("0.0.0.0:8080").await?;
-    let session_manager = Arc::new(SessionManager::new());
+    let mut udp_listener = UdpListener::bind("0.0.0.0:8080").await?;
+    let mut quic_listener = quic_listener_from_env()?;
+    let mut session_manager =
+        SessionManager::new().with_quality_history_len(quality_history_len_from_env());
+    // Images and map tiles uploaded by drones
+    match BlobConfig::from_env() {
+        Some(config) => {
+            println!("Blob uploads: {}", config.dir.display());
+            session_manager = session_manager.with_blob_store(Arc::new(BlobStore::new(config)));
+        }
+        None => println!("Blob uploads: off (SERVER_BLOB_DIR=off)"),
+    }
+    let session_manager = Arc::new(session_manager);
     let sequence_id = Arc::new(AtomicU64::new(0));
 
     // Create command dispatcher
-    let dispatcher = Arc::new(CommandDispatcher::new(
-        session_manager.clone(),
-        sequence_id.clone(),
-    ));
+    let upload_limits = UploadLimits::from_env();
+    let command_limits = CommandLimits::from_env();
+    let mut dispatcher = CommandDispatcher::new(session_manager.clone(), sequence_id.clone())
+        .with_upload_limits(upload_limits)
+        .with_command_limits(command_limits);
+    // Every command step on disk, for incident review
+    match AuditLogConfig::from_env() {
+        Some(config) => match AuditLog::open(&config.path) {
+            Ok(log) => {
+                println!("Command audit log: {}", config.path.display());
+                dispatcher = dispatcher.with_audit_log(Arc::new(log));
+            }
+            Err(e) => {
+                let path = config.path.display();
+                eprintln!("[AUDIT] Can't open {} ({}), commands are kept in memory only", path, e);
+            }
+        },
+        None => println!("Command audit log: off (SERVER_COMMAND_AUDIT_LOG=off)"),
+    }
+    let dispatcher = Arc::new(dispatcher);
+
+    println!("Server listening on :8080 (TCP and UDP)");
+    println!(
+        "Concurrent uploads: 5G={} Bluetooth={}",
+        upload_limits.five_g, upload_limits.bluetooth
+    );
+    println!(
+        "Per-drone commands: {} in flight, {}/s, refused at {} pending",
+        command_limits.max_in_flight, command_limits.max_per_second, command_limits.max_drone_pending
+    );
+    let device_keys = DeviceKeys::from_env()?.map(Arc::new);
+    match &device_keys {
+        Some(keys) => println!("Envelope signing: {:?}", keys),
+        None => println!(
+            "Envelope signing: off (RESQTERRA_SIGNING_KEY / RESQTERRA_DEVICE_KEYS not set)"
+        ),
+    }
+    let noise = NoiseConfig::from_env()?.map(Arc::new);
+    match &noise {
+        Some(noise) => println!("Noise encryption: {:?}", noise),
+        None => println!("Noise encryption: off (RESQTERRA_NOISE_KEY not set)"),
+    }
+    // WebSocket clients send bare envelopes, so they can't do the Noise handshake
+    let mut ws_listener = match &noise {
+        Some(_) => {
+            println!("WebSocket: off (Noise encryption is on)");
+            None
+        }
+        None => {
+            println!("WebSocket: listening on :{}", WEBSOCKET_PORT);
+            Some(WebSocketListener::bind(("0.0.0.0", WEBSOCKET_PORT)).await?)
+        }
+    };
+    match &quic_listener {
+        Some(_) => println!("QUIC: listening on :{}", QUIC_PORT),
+        None => println!("QUIC: off (RESQTERRA_QUIC_CERT / RESQTERRA_QUIC_KEY not set)"),
+    }
+    // Republish telemetry to fleet software over MQTT
+    match MqttConfig::from_env()? {
+        Some(config) => {
+            println!(
+                "MQTT: bridging to {}:{} (commands {})",
+                config.host,
+                config.port,
+                if config.accept_commands { "accepted" } else { "ignored" }
+            );
+            mqtt::spawn(config, &session_manager.events(), dispatcher.clone());
+        }
+        None => println!("MQTT: off (SERVER_MQTT_BROKER not set)"),
+    }
+    // Telemetry history for flight-path reconstruction and incident analysis
+    let storage = match StorageConfig::from_env() {
+        Some(config) => match SqliteStore::open(&config.path) {
+            Ok(store) => {
+                println!("Telemetry storage: {}", config.path.display());
+                let storage = Arc::new(Storage::new(store, config.retention));
+                storage.spawn_writer(&session_manager.events());
+                Some(storage)
+            }
+            Err(e) => {
+                let path = config.path.display();
+                eprintln!("[STORAGE] Can't open {} ({}), telemetry is not stored", path, e);
+                None
+            }
+        },
+        None => {
+            println!("Telemetry storage: off (SERVER_TELEMETRY_DB=off)");
+            None
+        }
+    };
+    // Fleet view for subsystems
+    let fleet = FleetState::spawn(&session_manager.events());
+
+    // Missions of drones that drop out are handed to the nearest healthy one
+    let retasker = match RetaskConfig::from_env() {
+        Some(config) => {
+            println!(
+                "Re-tasking: {:?}, takeover battery >= {}%",
+                config.mode, config.min_battery_percent
+            );
+            let events = session_manager.events();
+            Some(Retasker::new(config, fleet.clone(), dispatcher.clone(), events).spawn())
+        }
+        None => {
+            println!("Re-tasking: off (SERVER_RETASK=off)");
+            None
+        }
+    };
+
+    // JSON control API for operations tooling
+    match HttpConfig::from_env() {
+        Some(config) => {
+            println!("HTTP API: listening on {}", config.listen);
+            let sessions = session_manager.clone();
+            http::spawn(config, sessions, dispatcher.clone(), storage.clone(), retasker).await?;
+        }
+        None => println!("HTTP API: off (SERVER_HTTP_LISTEN=off)"),
+    }
+    // The same over gRPC, with streamed telemetry
+    match GrpcConfig::from_env() {
+        Some(config) => {
+            println!("gRPC API: listening on {}", config.listen);
+            grpc::spawn(config, session_manager.clone(), dispatcher.clone()).await?;
+        }
+        None => println!("gRPC API: off (SERVER_GRPC_LISTEN=off)"),
+    }
+
+    // Lost drones are posted to a webhook as well as logged and streamed
+    match AlertConfig::from_env() {
+        Some(config) => {
+            println!("Alert webhook: {}", config.webhook);
+            alerts::spawn(config, &session_manager.events())?;
+        }
+        None => println!("Alert webhook: off (SERVER_ALERT_WEBHOOK not set)"),
+    }
 
-    println!("Server listening on :8080");
     println!("Waiting for drone connections...");
 
     // Spawn heartbeat monitor
@@ -33,6 +212,43 @@ async fn main() -> anyhow::Result<()> {
         heartbeat_monitor(sm_clone).await;
     });
 
+    // Spawn event logger (operator-facing events only)
+    let mut events = session_manager.events().subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => log_event(&event),
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    eprintln!("[EVENT] Logger lagged, skipped {} events", n);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    // Low batteries get an operator's attention
+    let mut changes = fleet.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match changes.recv().await {
+                Ok(FleetEvent::BatteryLow { device_id, percent }) => {
+                    let Some(drone) = fleet.drone(&device_id) else {
+                        continue;
+                    };
+                    println!(
+                        "[FLEET] [{}] battery low: {}% ({:?}, {} commands pending)",
+                        device_id, percent, drone.state, drone.pending_commands
+                    );
+                }
+                Ok(_) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    eprintln!("[FLEET] Battery watch lagged, skipped {} changes", n);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
     // Spawn command timeout tracker
     let disp_clone = dispatcher.clone();
     tokio::spawn(async move {
@@ -46,6 +262,46 @@ async fn main() -> anyhow::Result<()> {
         demo_command_sender(disp_clone).await;
     });
 
+    // Drones on 5G over UDP or QUIC, and WebSocket clients, get the same
+    // sessions as TCP ones
+    let sm_clone = session_manager.clone();
+    let seq_clone = sequence_id.clone();
+    let disp_clone = dispatcher.clone();
+    let keys_clone = device_keys.clone();
+    let noise_clone = noise.clone();
+    tokio::spawn(async move {
+        loop {
+            let quic_accept = async {
+                match &mut quic_listener {
+                    Some(listener) => listener.accept().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let ws_accept = async {
+                match &mut ws_listener {
+                    Some(listener) => listener.accept().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let (link, addr, kind) = tokio::select! {
+                Ok((link, addr)) = udp_listener.accept() => (link, addr, "UDP"),
+                Ok((link, addr)) = quic_accept => (link, addr, "QUIC"),
+                Ok((link, addr)) = ws_accept => (link, addr, "WebSocket"),
+                else => break,
+            };
+            println!("New {} link from: {}", kind, addr);
+            tokio::spawn(handle_drone_session(
+                link,
+                addr,
+                sm_clone.clone(),
+                seq_clone.clone(),
+                disp_clone.clone(),
+                keys_clone.clone(),
+                noise_clone.clone(),
+            ));
+        }
+    });
+
     loop {
         let (stream, addr) = listener.accept().await?;
         println!("New connection from: {}", addr);
@@ -53,21 +309,49 @@ async fn main() -> anyhow::Result<()> {
         let sm = session_manager.clone();
         let seq = sequence_id.clone();
         let disp = dispatcher.clone();
+        let keys = device_keys.clone();
+        let noise = noise.clone();
 
         tokio::spawn(async move {
-            handle_drone_session(stream, addr, sm, seq, disp).await;
+            handle_drone_session(stream, addr, sm, seq, disp, keys, noise).await;
         });
     }
 }
 
+/// QUIC listener with the certificate in `RESQTERRA_QUIC_CERT` and key in
+/// `RESQTERRA_QUIC_KEY` (PEM files); None when they aren't set
+fn quic_listener_from_env() -> anyhow::Result<Option<QuicListener>> {
+    let (Ok(cert), Ok(key)) = (
+        std::env::var("RESQTERRA_QUIC_CERT"),
+        std::env::var("RESQTERRA_QUIC_KEY"),
+    ) else {
+        return Ok(None);
+    };
+    let config = quic::server_config(cert.as_ref(), key.as_ref())?;
+    Ok(Some(QuicListener::bind(([0, 0, 0, 0], QUIC_PORT).into(), config)?))
+}
+
 async fn handle_drone_session(
-    stream: tokio::net::TcpStream,
+    stream: impl SessionStream,
     addr: std::net::SocketAddr,
     session_manager: Arc<SessionManager>,
     sequence_id: Arc<AtomicU64>,
     dispatcher: Arc<CommandDispatcher>,
+    device_keys: Option<Arc<DeviceKeys>>,
+    noise: Option<Arc<NoiseConfig>>,
 ) {
-    let mut session = DroneSession::new(stream, addr);
+    let session = match noise {
+        Some(noise) => match accept_encrypted(stream, addr, &noise).await {
+            Some(session) => session,
+            None => return,
+        },
+        None => DroneSession::new(stream, addr),
+    };
+    let mut session = session.with_events(session_manager.events());
+    if let Some(keys) = device_keys {
+        session = session.with_device_keys(keys);
+    }
+    let mut telemetry_decoder = DeltaDecoder::new();
 
     // Read messages until disconnect
     while let Some(envelope) = session.recv().await {
@@ -82,6 +366,7 @@ async fn handle_drone_session(
             &session_manager,
             &sequence_id,
             &dispatcher,
+            &mut telemetry_decoder,
         )
         .await;
     }
@@ -90,10 +375,43 @@ async fn handle_drone_session(
     let device_id = session.device_id();
     if !device_id.is_empty() {
         println!("Drone disconnected: {} ({})", device_id, addr);
-        session_manager.unregister(device_id).await;
+        session_manager.unregister(device_id, addr).await;
+        log_command_history(device_id, &dispatcher.command_history(device_id).await);
     } else {
         println!("Client disconnected: {}", addr);
     }
+    if session.rejected_frames() > 0 {
+        println!("  {} frames failed verification", session.rejected_frames());
+    }
+}
+
+/// Run the Noise handshake and open a session bound to the drone it proved to be
+async fn accept_encrypted(
+    stream: impl SessionStream,
+    addr: std::net::SocketAddr,
+    noise: &NoiseConfig,
+) -> Option<DroneSession> {
+    let stream = match tokio::time::timeout(NOISE_HANDSHAKE_TIMEOUT, NoiseStream::accept(stream, noise)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            eprintln!("Noise handshake with {} failed: {}", addr, e);
+            return None;
+        }
+        Err(_) => {
+            eprintln!("Noise handshake with {} timed out", addr);
+            return None;
+        }
+    };
+    match DeviceId::parse(stream.peer()) {
+        Ok(device_id) => {
+            println!("Encrypted link from {} ({})", device_id, addr);
+            Some(DroneSession::new(stream, addr).with_authenticated_device(device_id))
+        }
+        Err(e) => {
+            eprintln!("Noise peer {:?} is not a device ID: {}", stream.peer(), e);
+            None
+        }
+    }
 }
 
 async fn handle_envelope(
@@ -102,6 +420,7 @@ async fn handle_envelope(
     session_manager: &SessionManager,
     sequence_id: &AtomicU64,
     dispatcher: &CommandDispatcher,
+    telemetry_decoder: &mut DeltaDecoder,
 ) {
     let header = match &envelope.header {
         Some(h) => h,
@@ -117,25 +436,61 @@ async fn handle_envelope(
     match &envelope.payload {
         Some(envelope::Payload::Heartbeat(hb)) => {
             session_manager.update_heartbeat(device_id).await;
+            session_manager.events().publish(ServerEvent::HeartbeatReceived {
+                device_id: device_id.clone(),
+                heartbeat: hb.clone(),
+            });
 
             let state = DroneState::try_from(hb.state).unwrap_or(DroneState::DroneUnknown);
             session_manager.update_state(device_id, state).await;
+            session_manager.update_pending_commands(device_id, hb.pending_commands).await;
+
+            let battery = hb.battery_percent().map_or("?".to_string(), |p| format!("{}%", p));
+            if let Some(relay) = &hb.relay {
+                let links: Vec<String> = relay
+                    .links
+                    .iter()
+                    .map(|link| {
+                        let device = if link.device_id.is_empty() { "?" } else { &link.device_id };
+                        match link.rssi_dbm {
+                            Some(rssi) => format!("{} {}dBm", device, rssi),
+                            None => device.to_string(),
+                        }
+                    })
+                    .collect();
+                println!(
+                    "[{}] RELAY: drones={} buffered={}B battery={} links=[{}]",
+                    device_id,
+                    relay.connected_drones,
+                    relay.buffered_bytes,
+                    battery,
+                    links.join(", ")
+                );
+                session_manager.update_relay_status(device_id, relay.clone()).await;
+            } else {
+                println!(
+                    "[{}] HEARTBEAT: uptime={}ms state={:?} healthy={} pending={} battery={} gps_fix={} queued={}",
+                    device_id,
+                    hb.uptime_ms,
+                    state,
+                    hb.healthy,
+                    hb.pending_commands,
+                    battery,
+                    hb.gps_fix,
+                    dispatcher.queued_count_for(device_id).await
+                );
+            }
 
-            println!(
-                "[{}] HEARTBEAT: uptime={}ms state={:?} healthy={} pending={}",
-                device_id, hb.uptime_ms, state, hb.healthy, hb.pending_commands
-            );
-
-            // Send heartbeat response
+            // Acknowledge it: the edge returns home if these stop arriving
             let seq = sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
             let response = Envelope {
-                header: Some(Header::new("server", MessageType::MsgHeartbeat, seq)),
-                payload: Some(envelope::Payload::Heartbeat(Heartbeat::new(
-                    0,
-                    DroneState::DroneUnknown,
-                    dispatcher.pending_count_for(device_id).await as u32,
-                    true,
-                ))),
+                header: Some(Header::new("server", MessageType::MsgHeartbeatAck, seq)),
+                payload: Some(envelope::Payload::HeartbeatAck(HeartbeatAck {
+                    heartbeat_sequence_id: header.sequence_id,
+                    queued_commands: dispatcher.pending_count_for(device_id).await as u32,
+                    heartbeat_timestamp_ms: header.timestamp_ms,
+                })),
+                signature: Vec::new(),
             };
 
             if let Err(e) = session.get_handle().send(&response).await {
@@ -143,43 +498,234 @@ async fn handle_envelope(
             }
         }
 
-        Some(envelope::Payload::Telemetry(tel)) => {
-            let state = DroneState::try_from(tel.state).unwrap_or(DroneState::DroneUnknown);
-            session_manager.update_state(device_id, state).await;
+        Some(envelope::Payload::Hello(hello)) if hello.device_type() == DeviceType::DeviceRelay => {
+            println!("[{}] HELLO: relay, protocol=v{}", device_id, hello.protocol_version);
+            session_manager.update_device_type(device_id, DeviceType::DeviceRelay).await;
+            let seq = sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
+            let reply = Envelope {
+                header: Some(Header::new("server", MessageType::MsgHello, seq)),
+                payload: Some(envelope::Payload::Hello(Hello {
+                    protocol_version: PROTOCOL_VERSION,
+                    ..Default::default()
+                })),
+                signature: Vec::new(),
+            };
+            if let Err(e) = session.get_handle().send(&reply).await {
+                eprintln!("Failed to send hello to {}: {}", device_id, e);
+            }
+        }
 
+        Some(envelope::Payload::Hello(hello)) => {
+            let config = hello.safety_config.unwrap_or_default();
             println!(
-                "[{}] TELEMETRY: state={:?} uptime={}s",
-                device_id, state, tel.uptime_seconds
+                "[{}] HELLO: protocol=v{} battery_critical={}% heartbeat_timeout={}ms battery_action={:?} link_loss_action={:?}",
+                device_id,
+                hello.protocol_version,
+                config.battery_critical_percent,
+                config.heartbeat_timeout_ms,
+                config.battery_critical_action(),
+                config.heartbeat_loss_action()
             );
-
-            if let Some(ref pos) = tel.position {
+            if header.hop_count > 0 {
+                println!("[{}] Reached through {} relays", device_id, header.hop_count);
+            }
+            if hello.protocol_version > PROTOCOL_VERSION {
                 println!(
-                    "  Position: lat={:.6} lon={:.6} alt={:.1}m",
-                    pos.latitude, pos.longitude, pos.altitude_m
+                    "[{}] Drone speaks protocol v{} (server v{}); newer fields will be ignored",
+                    device_id, hello.protocol_version, PROTOCOL_VERSION
                 );
             }
+            // Same drone over another link: carry its session over
+            let (token, resumed) = session_manager
+                .resume_session(device_id, &hello.session_token)
+                .await;
+            session_manager.update_safety_config(device_id, config).await;
+
+            // Reply with our own capabilities, then switch to the common compression
+            let handle = session.get_handle();
+            let supported = compression::supported();
+            let seq = sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
+            let reply = Envelope {
+                header: Some(Header::new("server", MessageType::MsgHello, seq)),
+                payload: Some(envelope::Payload::Hello(
+                    Hello {
+                        protocol_version: PROTOCOL_VERSION,
+                        session_resumed: resumed,
+                        ..Default::default()
+                    }
+                    .with_compression(&supported)
+                    .with_session_token(token),
+                )),
+                signature: Vec::new(),
+            };
+            if let Err(e) = handle.send(&reply).await {
+                eprintln!("Failed to send hello to {}: {}", device_id, e);
+            }
+
+            let chosen = compression::negotiate(&supported, &hello.compression);
+            handle.set_compression(chosen);
+            println!("[{}] Frame compression: {:?}", device_id, chosen);
 
-            if let Some(ref bat) = tel.battery {
+            if resumed {
+                let replayed = dispatcher.replay_pending(device_id).await;
                 println!(
-                    "  Battery: {}% ({:.1}V, {:.1}A)",
-                    bat.remaining_percent, bat.voltage, bat.current
+                    "[{}] Session resumed, {} unacknowledged command(s) replayed",
+                    device_id,
+                    replayed.len()
                 );
             }
         }
 
+        Some(envelope::Payload::Telemetry(tel)) => {
+            let tel = telemetry_decoder.apply_keyframe(tel.clone());
+            handle_telemetry(device_id, now_ms(), &tel, session_manager).await;
+        }
+
+        Some(envelope::Payload::TelemetryDelta(delta)) => {
+            match telemetry_decoder.apply_delta(delta) {
+                Some(tel) => handle_telemetry(device_id, now_ms(), &tel, session_manager).await,
+                None => println!(
+                    "[{}] TELEMETRY_DELTA: waiting for keyframe {}",
+                    device_id, delta.keyframe_id
+                ),
+            }
+        }
+
+        Some(envelope::Payload::TelemetryBatch(batch)) => {
+            let samples = batch.unpack();
+            println!("[{}] TELEMETRY_BATCH: {} samples", device_id, samples.len());
+            for (timestamp_ms, tel) in &samples {
+                handle_telemetry(device_id, *timestamp_ms, tel, session_manager).await;
+            }
+        }
+
+        Some(envelope::Payload::StatusUpdate(update)) => {
+            let state = DroneState::try_from(update.state).unwrap_or(DroneState::DroneUnknown);
+            session_manager.update_state(device_id, state).await;
+            let outcome = if update.error.is_empty() {
+                String::new()
+            } else {
+                format!(" FAILED: {}", update.error)
+            };
+            println!(
+                "[{}] STATUS_UPDATE: {:?} ({}) state={:?}{}",
+                device_id,
+                update.action(),
+                update.reason,
+                state,
+                outcome
+            );
+        }
+
         Some(envelope::Payload::SensorData(data)) => {
             println!(
-                "[{}] SENSOR_DATA: type={} mission={} chunk={}/{}  size={}",
+                "[{}] SENSOR_DATA: type={} mission={} chunk={}/{}  size={} command={}",
                 device_id,
                 data.sensor_type,
                 data.mission_id,
                 data.chunk_index,
                 data.total_chunks,
-                data.data.len()
+                data.data.len(),
+                data.command_id
+            );
+        }
+
+        Some(envelope::Payload::MissionProgress(progress)) => {
+            let previous = dispatcher
+                .record_mission_progress(device_id, progress.clone())
+                .await;
+            // Reported every few seconds; log only when a new item is flown
+            let advanced = previous.is_none_or(|p| {
+                p.mission_id != progress.mission_id
+                    || p.current_waypoint != progress.current_waypoint
+            });
+            if advanced {
+                println!(
+                    "[{}] MISSION_PROGRESS: mission={} waypoint {}/{} {:.0}%",
+                    device_id,
+                    progress.mission_id,
+                    progress.current_waypoint,
+                    progress.total_waypoints,
+                    progress.percent_complete
+                );
+            }
+        }
+
+        Some(envelope::Payload::DetectionReport(report)) => {
+            println!(
+                "[{}] DETECTION: {} {} ({:.0}%, {}) mission={}",
+                device_id,
+                report.detection_id,
+                report.label,
+                report.confidence * 100.0,
+                report.sensor_type,
+                report.mission_id
+            );
+            session_manager.events().publish(ServerEvent::DetectionReceived {
+                device_id: device_id.clone(),
+                report: report.clone(),
+            });
+        }
+
+        Some(envelope::Payload::BlobStart(start)) => {
+            println!(
+                "[{}] BLOB_START: {} ({}, {} bytes)",
+                device_id, start.blob_id, start.content_type, start.total_size
             );
+            let status = match session_manager.blob_store() {
+                Some(store) => store.start(device_id, start),
+                None => BlobStatus {
+                    blob_id: start.blob_id.clone(),
+                    state: BlobState::BlobFailed.into(),
+                    received_bytes: 0,
+                    error: "Blob uploads are off".into(),
+                },
+            };
+            send_blob_status(session, sequence_id, status).await;
+        }
+
+        Some(envelope::Payload::BlobChunk(chunk)) => {
+            let failure = session_manager
+                .blob_store()
+                .and_then(|store| store.chunk(device_id, chunk));
+            if let Some(status) = failure {
+                eprintln!("[{}] BLOB {} refused: {}", device_id, chunk.blob_id, status.error);
+                send_blob_status(session, sequence_id, status).await;
+            }
+        }
+
+        Some(envelope::Payload::BlobEnd(end)) => {
+            let Some(store) = session_manager.blob_store() else {
+                return;
+            };
+            let (status, stored) = store.end(device_id, &end.blob_id).await;
+            match stored {
+                Some(stored) => {
+                    session_manager.events().publish(ServerEvent::BlobReceived {
+                        device_id: device_id.clone(),
+                        blob_id: stored.start.blob_id,
+                        content_type: stored.start.content_type,
+                        name: stored.start.name,
+                        size: stored.start.total_size,
+                        path: stored.path,
+                    });
+                }
+                None => eprintln!("[{}] BLOB {} failed: {}", device_id, end.blob_id, status.error),
+            }
+            send_blob_status(session, sequence_id, status).await;
+        }
+
+        Some(envelope::Payload::BlobStatus(_)) => {
+            println!("[{}] WARNING: Received BLOB_STATUS from drone (unexpected)", device_id);
         }
 
         Some(envelope::Payload::Ack(ack)) => {
+            if let Some(stream) = &ack.video_stream {
+                println!(
+                    "[{}] VIDEO: {} {}x{} at {}",
+                    device_id, stream.codec, stream.width, stream.height, stream.url
+                );
+            }
             // Forward ACK to dispatcher for tracking
             dispatcher.handle_ack(device_id, ack).await;
         }
@@ -191,12 +737,207 @@ async fn handle_envelope(
             );
         }
 
+        Some(envelope::Payload::HeartbeatAck(_)) => {
+            println!(
+                "[{}] WARNING: Received HEARTBEAT_ACK from drone (unexpected)",
+                device_id
+            );
+        }
+
         None => {
             println!("[{}] {:?}: (no payload)", device_id, msg_type);
         }
     }
 }
 
+/// Answer a drone's blob transfer
+async fn send_blob_status(session: &DroneSession, sequence_id: &AtomicU64, status: BlobStatus) {
+    let seq = sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
+    let reply = Envelope {
+        header: Some(Header::new("server", MessageType::MsgBlobStatus, seq)),
+        payload: Some(envelope::Payload::BlobStatus(status)),
+        signature: Vec::new(),
+    };
+    if let Err(e) = session.get_handle().send(&reply).await {
+        eprintln!("Failed to send blob status to {}: {}", session.device_id(), e);
+    }
+}
+
+/// Record a full (keyframe, reconstructed or batched) telemetry frame taken at `timestamp_ms`
+async fn handle_telemetry(
+    device_id: &str,
+    timestamp_ms: u64,
+    tel: &Telemetry,
+    session_manager: &SessionManager,
+) {
+    let state = DroneState::try_from(tel.state).unwrap_or(DroneState::DroneUnknown);
+    session_manager.update_state(device_id, state).await;
+    if let Some(quality) = tel.conn_quality {
+        session_manager.record_quality(device_id, timestamp_ms, quality).await;
+    }
+    session_manager.record_telemetry(device_id, timestamp_ms, tel).await;
+    session_manager.publish_telemetry(device_id, timestamp_ms, tel).await;
+
+    println!(
+        "[{}] TELEMETRY: state={:?} uptime={}s",
+        device_id, state, tel.uptime_seconds
+    );
+
+    if !tel.mission_id.is_empty() {
+        println!("  Mission: {}", tel.mission_id);
+    }
+
+    if let Some(ref pos) = tel.position {
+        println!(
+            "  Position: lat={:.6} lon={:.6} alt={:.1}m",
+            pos.latitude, pos.longitude, pos.altitude_m
+        );
+    }
+
+    if let Some(ref bat) = tel.battery {
+        println!(
+            "  Battery: {}% ({:.1}V, {:.1}A)",
+            bat.remaining_percent, bat.voltage, bat.current
+        );
+    }
+
+    if let Some(ref quality) = tel.conn_quality {
+        let trend = session_manager
+            .quality_history(device_id)
+            .await
+            .and_then(|h| h.trend(|q| q.latency_ms as f64))
+            .unwrap_or(0.0);
+        println!(
+            "  Link: {:?} latency={}ms ({:+.1}ms/s) jitter={}ms loss={:.0}%",
+            quality.active_transport(),
+            quality.latency_ms,
+            trend,
+            quality.jitter_ms,
+            quality.packet_loss_percent
+        );
+    }
+}
+
+/// Link quality samples kept per drone (`SERVER_QUALITY_HISTORY_LEN`)
+fn quality_history_len_from_env() -> usize {
+    std::env::var("SERVER_QUALITY_HISTORY_LEN")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(QUALITY_HISTORY_LEN)
+}
+
+/// Print commands a drone left unresolved when it disconnected
+fn log_command_history(device_id: &str, history: &[CommandAudit]) {
+    let unresolved: Vec<&CommandAudit> = history.iter().filter(|a| a.outcome.is_none()).collect();
+    println!(
+        "[{}] Command history: {} command(s), {} without outcome",
+        device_id,
+        history.len(),
+        unresolved.len()
+    );
+    for audit in unresolved {
+        println!(
+            "  {} {:?} sent={} retries={}",
+            audit.command_id,
+            audit.cmd_type,
+            audit.sent_at.is_some(),
+            audit.retries
+        );
+    }
+}
+
+/// Print operator-facing server events
+fn log_event(event: &ServerEvent) {
+    match event {
+        ServerEvent::CommandOutcome {
+            device_id,
+            command_id,
+            cmd_type,
+            status,
+            message,
+        } => {
+            println!(
+                "[EVENT] [{}] command {} ({:?}) -> {:?} {}",
+                device_id, command_id, cmd_type, status, message
+            );
+        }
+        ServerEvent::Alert { device_id, message } => {
+            println!("[EVENT] [{}] ALERT: {}", device_id, message);
+        }
+        ServerEvent::BlobReceived {
+            device_id,
+            blob_id,
+            content_type,
+            name,
+            size,
+            path,
+        } => {
+            println!(
+                "[EVENT] [{}] blob {} ({}, {} bytes, {:?}) stored at {}",
+                device_id,
+                blob_id,
+                content_type,
+                size,
+                name,
+                path.display()
+            );
+        }
+        ServerEvent::Backpressure { device_id, depth } => {
+            println!("[EVENT] [{}] link backing up: {} writes queued", device_id, depth);
+        }
+        ServerEvent::SequenceGap {
+            device_id,
+            first,
+            last,
+        } => {
+            println!("[EVENT] [{}] sequence gap: {}..={} missing", device_id, first, last);
+        }
+        ServerEvent::DuplicateDropped {
+            device_id,
+            sequence_id,
+        } => {
+            println!("[EVENT] [{}] duplicate sequence {} dropped", device_id, sequence_id);
+        }
+        ServerEvent::StateDivergence {
+            device_id,
+            expected,
+            reported,
+        } => {
+            println!(
+                "[EVENT] [{}] state divergence: expected {:?}, drone reports {:?}",
+                device_id, expected, reported
+            );
+        }
+        ServerEvent::SessionConnected { device_id, addr } => {
+            println!("[EVENT] [{}] session started from {}", device_id, addr);
+        }
+        ServerEvent::SessionDisconnected { device_id, reason } => {
+            println!("[EVENT] [{}] session ended: {}", device_id, reason);
+        }
+        ServerEvent::StateChanged { device_id, from, to } => {
+            println!("[EVENT] [{}] state {:?} -> {:?}", device_id, from, to);
+        }
+        ServerEvent::DroneLost {
+            device_id,
+            state,
+            last_seen_ms,
+            last_position,
+        } => {
+            let position = last_position.map_or("unknown".to_string(), |p| {
+                format!("{:.6},{:.6} at {:.0}m", p.latitude, p.longitude, p.altitude_m)
+            });
+            let silent_secs = now_ms().saturating_sub(*last_seen_ms) / 1000;
+            println!(
+                "[EVENT] [{}] LOST: no heartbeat for {}s, last {:?}, last position {}",
+                device_id, silent_secs, state, position
+            );
+        }
+        ServerEvent::TelemetryReceived { .. }
+        | ServerEvent::HeartbeatReceived { .. }
+        | ServerEvent::DetectionReceived { .. } => {}
+    }
+}
+
 /// Monitor for dead drone sessions
 async fn heartbeat_monitor(session_manager: Arc<SessionManager>) {
     let mut check_interval = interval(Duration::from_secs(5));
@@ -226,7 +967,7 @@ async fn demo_command_sender(dispatcher: Arc<CommandDispatcher>) {
             command_id: dispatcher.next_command_id(),
             cmd_type: CommandType::CmdStatusRequest.into(),
             expires_at_ms: now_ms() + 10000, // 10 second expiry
-            priority: 1,
+            priority: priority::LOW,
             params: Some(resqterra_shared::command::Params::StatusRequest(
                 resqterra_shared::StatusRequest {
                     requested_fields: vec![],
@@ -0,0 +1,182 @@
+//! MQTT bridge for fleet software
+//!
+//! Republishes every telemetry frame and heartbeat from the event bus to an
+//! MQTT broker, as protobuf `Telemetry` / `Heartbeat` messages on
+//! `resqterra/{device_id}/telemetry` and `resqterra/{device_id}/heartbeat`.
+//! Telemetry is published after delta decoding and batch splitting, so every
+//! message is a full frame.
+//!
+//! With `accept_commands`, protobuf `Command`s published to
+//! `resqterra/{device_id}/command` are dispatched to that drone like any
+//! other command. The server assigns the command ID. Anyone who can publish
+//! to the broker can then command the fleet, so only turn it on for a broker
+//! with access control.
+
+use crate::command::CommandDispatcher;
+use crate::events::{EventBus, ServerEvent};
+use prost::Message;
+use resqterra_shared::{Command, DeviceId};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Root of every topic the bridge uses
+pub const TOPIC_PREFIX: &str = "resqterra";
+
+/// Requests queued for the broker before publishing waits
+const CLIENT_QUEUE_LEN: usize = 256;
+
+/// Pause before reconnecting after the broker connection fails
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Where and how to bridge
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    /// Dispatch commands published to `resqterra/{device_id}/command`
+    pub accept_commands: bool,
+}
+
+impl MqttConfig {
+    /// Read `SERVER_MQTT_BROKER` (`host:port`, port 1883 if left out) and
+    /// `SERVER_MQTT_COMMANDS`; None when no broker is set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(broker) = std::env::var("SERVER_MQTT_BROKER") else {
+            return Ok(None);
+        };
+        let (host, port) = match broker.rsplit_once(':') {
+            Some((host, port)) => (host.to_string(), port.parse()?),
+            None => (broker, 1883),
+        };
+        let accept_commands = matches!(
+            std::env::var("SERVER_MQTT_COMMANDS").as_deref(),
+            Ok("1") | Ok("true")
+        );
+        Ok(Some(Self {
+            host,
+            port,
+            client_id: "resqterra-server".into(),
+            accept_commands,
+        }))
+    }
+}
+
+/// Topic a drone's messages of `kind` go to
+fn topic(device_id: &str, kind: &str) -> String {
+    format!("{}/{}/{}", TOPIC_PREFIX, device_id, kind)
+}
+
+/// Drone a command topic addresses, if `topic` is one
+fn command_target(topic: &str) -> Option<DeviceId> {
+    let rest = topic.strip_prefix(TOPIC_PREFIX)?.strip_prefix('/')?;
+    let device_id = rest.strip_suffix("/command")?;
+    device_id.parse().ok()
+}
+
+/// Start bridging the event bus to the broker in the background
+pub fn spawn(config: MqttConfig, events: &EventBus, dispatcher: Arc<CommandDispatcher>) {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    let (client, event_loop) = AsyncClient::new(options, CLIENT_QUEUE_LEN);
+
+    tokio::spawn(publish_events(client.clone(), events.subscribe()));
+    tokio::spawn(run_connection(client, event_loop, config.accept_commands, dispatcher));
+}
+
+async fn publish_events(
+    client: AsyncClient,
+    mut events: tokio::sync::broadcast::Receiver<ServerEvent>,
+) {
+    loop {
+        let (topic, payload) = match events.recv().await {
+            Ok(ServerEvent::TelemetryReceived {
+                device_id, telemetry, ..
+            }) => {
+                (topic(&device_id, "telemetry"), telemetry.encode_to_vec())
+            }
+            Ok(ServerEvent::HeartbeatReceived { device_id, heartbeat }) => {
+                (topic(&device_id, "heartbeat"), heartbeat.encode_to_vec())
+            }
+            Ok(_) => continue,
+            Err(RecvError::Lagged(n)) => {
+                eprintln!("[MQTT] Bridge lagged, skipped {} events", n);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        // Fresher frames follow, so a lost one isn't worth redelivering
+        if client.publish(topic, QoS::AtMostOnce, false, payload).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Drive the broker connection, reconnecting as needed, and dispatch commands
+async fn run_connection(
+    client: AsyncClient,
+    mut event_loop: EventLoop,
+    accept_commands: bool,
+    dispatcher: Arc<CommandDispatcher>,
+) {
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                println!("[MQTT] Connected to broker");
+                // Subscriptions don't outlive a clean session, so renew them
+                if accept_commands {
+                    let filter = topic("+", "command");
+                    if let Err(e) = client.try_subscribe(filter, QoS::AtLeastOnce) {
+                        eprintln!("[MQTT] Failed to subscribe to commands: {}", e);
+                    }
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) if accept_commands => {
+                dispatch_command(&dispatcher, &publish.topic, &publish.payload).await;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("[MQTT] Broker connection failed: {}", e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+async fn dispatch_command(dispatcher: &CommandDispatcher, topic: &str, payload: &[u8]) {
+    let Some(device_id) = command_target(topic) else {
+        eprintln!("[MQTT] Ignoring publish on {}", topic);
+        return;
+    };
+    let mut command = match Command::decode(payload) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("[MQTT] Bad command for {}: {}", device_id, e);
+            return;
+        }
+    };
+    command.command_id = dispatcher.next_command_id();
+    match dispatcher.send_command(device_id.as_str(), command).await {
+        Ok(command_id) => println!("[MQTT] Command {} sent to {}", command_id, device_id),
+        Err(e) => eprintln!("[MQTT] Command for {} refused: {}", device_id, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_topics_name_one_drone() {
+        assert_eq!(topic("edge-001", "telemetry"), "resqterra/edge-001/telemetry");
+        assert_eq!(
+            command_target("resqterra/edge-001/command").map(|id| id.to_string()),
+            Some("edge-001".to_string())
+        );
+        assert!(command_target("resqterra/edge-001/telemetry").is_none());
+        assert!(command_target("other/edge-001/command").is_none());
+        assert!(command_target("resqterra/a/b/command").is_none());
+    }
+}
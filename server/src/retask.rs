@@ -0,0 +1,461 @@
+//! Re-tasking missions a drone drops out of
+//!
+//! A drone in a mission that is lost (heartbeats stopped) or turns back on its
+//! own (RTH or emergency the operator did not command) leaves its survey
+//! unfinished. Its remaining work is proposed to the nearest healthy drone:
+//! connected, idle or armed on the ground, with a GPS fix and at least
+//! `min_battery_percent`. Uploaded waypoints already flown (by the drone's
+//! last `MissionProgress`) are dropped; a mission the drone planned from its
+//! survey area is flown again in full, as the server never sees that path.
+//!
+//! `SERVER_RETASK` gates the hand-off: `approve` (the default) holds each
+//! proposal until an operator approves or rejects it over the HTTP API,
+//! `auto` sends it at once and `off` disables re-tasking. Proposals are
+//! announced as `Alert` events. A hand-off that fails to send is not retried.
+
+use crate::command::{AssignedMission, CommandDispatcher};
+use crate::events::{EventBus, ServerEvent};
+use crate::fleet::{DroneView, FleetEvent, FleetState};
+use resqterra_shared::mission::MissionPlan;
+use resqterra_shared::{
+    command, now_ms, priority, Command, CommandType, DroneState, GpsCoordinate, MissionProgress,
+    MissionStart,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+
+/// Battery a drone needs to take over a mission unless
+/// `SERVER_RETASK_MIN_BATTERY` says otherwise (percent)
+pub const DEFAULT_RETASK_MIN_BATTERY_PERCENT: u32 = 50;
+
+/// Mean Earth radius (m)
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Whether a hand-off waits for an operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetaskMode {
+    /// Hold proposals until approved or rejected
+    Approve,
+    /// Send proposals as soon as they are made
+    Auto,
+}
+
+/// How missions are re-tasked
+#[derive(Debug, Clone)]
+pub struct RetaskConfig {
+    pub mode: RetaskMode,
+    pub min_battery_percent: u32,
+}
+
+impl RetaskConfig {
+    /// Read `SERVER_RETASK` (`approve`, `auto` or `off`) and
+    /// `SERVER_RETASK_MIN_BATTERY` (percent); None when it is `off`
+    pub fn from_env() -> Option<Self> {
+        let mode = match std::env::var("SERVER_RETASK").as_deref() {
+            Ok("off") => return None,
+            Ok("auto") => RetaskMode::Auto,
+            _ => RetaskMode::Approve,
+        };
+        let min_battery_percent = std::env::var("SERVER_RETASK_MIN_BATTERY")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|&percent| percent <= 100)
+            .unwrap_or(DEFAULT_RETASK_MIN_BATTERY_PERCENT);
+        Some(Self {
+            mode,
+            min_battery_percent,
+        })
+    }
+}
+
+/// A dropped-out drone's remaining work and the drone to take it over
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetaskProposal {
+    pub id: u64,
+    /// The drone that dropped out
+    pub from_device: String,
+    /// Why, e.g. "lost" or "DRONE_RETURNING_HOME mid-mission"
+    pub reason: String,
+    pub to_device: String,
+    /// From `to_device` to where the work resumes (m)
+    pub distance_m: f64,
+    /// The mission for `to_device`, under a new ID
+    pub mission: MissionStart,
+    /// Waypoints left to fly; empty when the survey area is flown again
+    pub waypoints: Vec<GpsCoordinate>,
+    /// When it was proposed (ms since epoch)
+    pub created_at_ms: u64,
+}
+
+#[derive(Debug, Default)]
+struct Proposals {
+    next_id: u64,
+    pending: BTreeMap<u64, RetaskProposal>,
+    /// Missions already re-tasked, so a drone lost after turning back is
+    /// handed off only once
+    handled: HashSet<String>,
+}
+
+/// Watches the fleet for drones dropping out of missions and re-tasks them
+pub struct Retasker {
+    config: RetaskConfig,
+    fleet: Arc<FleetState>,
+    dispatcher: Arc<CommandDispatcher>,
+    events: EventBus,
+    proposals: Mutex<Proposals>,
+}
+
+impl Retasker {
+    pub fn new(
+        config: RetaskConfig,
+        fleet: Arc<FleetState>,
+        dispatcher: Arc<CommandDispatcher>,
+        events: EventBus,
+    ) -> Self {
+        Self {
+            config,
+            fleet,
+            dispatcher,
+            events,
+            proposals: Mutex::new(Proposals::default()),
+        }
+    }
+
+    /// React to fleet changes in the background
+    pub fn spawn(self) -> Arc<Self> {
+        let retasker = Arc::new(self);
+        let mut changes = retasker.fleet.subscribe();
+        tokio::spawn({
+            let retasker = retasker.clone();
+            async move {
+                loop {
+                    match changes.recv().await {
+                        Ok(change) => {
+                            retasker.handle(&change).await;
+                        }
+                        Err(RecvError::Lagged(n)) => {
+                            eprintln!("[RETASK] Fleet watch lagged, skipped {} changes", n);
+                        }
+                        Err(RecvError::Closed) => return,
+                    }
+                }
+            }
+        });
+        retasker
+    }
+
+    /// Propose a hand-off if `change` is a drone dropping out of its mission
+    ///
+    /// In `Auto` mode the proposal is sent before it is returned.
+    pub async fn handle(&self, change: &FleetEvent) -> Option<RetaskProposal> {
+        let (device_id, reason) = match change {
+            FleetEvent::DroneLost { device_id, .. } => {
+                let drone = self.fleet.drone(device_id)?;
+                if drone.state != DroneState::DroneInMission {
+                    return None;
+                }
+                (device_id, "lost".to_string())
+            }
+            FleetEvent::StateChanged {
+                device_id,
+                from: DroneState::DroneInMission,
+                to: to @ (DroneState::DroneReturningHome | DroneState::DroneEmergency),
+            } => {
+                if self.operator_recalled(device_id).await {
+                    return None;
+                }
+                (device_id, format!("{} mid-mission", to.as_str_name()))
+            }
+            _ => return None,
+        };
+
+        let assigned = self.dispatcher.assigned_mission(device_id).await?;
+        let progress = self
+            .dispatcher
+            .mission_progress(device_id)
+            .await
+            .filter(|p| p.mission_id == assigned.mission.mission_id);
+        let proposal = self.propose(device_id, &reason, &assigned, progress.as_ref()).await?;
+
+        self.events.publish(ServerEvent::Alert {
+            device_id: device_id.clone(),
+            message: format!(
+                "{}: re-task {} to {} ({:.0} m away), proposal {}",
+                reason,
+                assigned.mission.mission_id,
+                proposal.to_device,
+                proposal.distance_m,
+                proposal.id
+            ),
+        });
+        if self.config.mode == RetaskMode::Auto {
+            self.take(proposal.id).await;
+            if let Err(e) = self.dispatch(&proposal).await {
+                eprintln!("[RETASK] Hand-off {} failed: {}", proposal.id, e);
+            }
+        }
+        Some(proposal)
+    }
+
+    /// Work out and hold a hand-off of `assigned`
+    async fn propose(
+        &self,
+        device_id: &str,
+        reason: &str,
+        assigned: &AssignedMission,
+        progress: Option<&MissionProgress>,
+    ) -> Option<RetaskProposal> {
+        let mission_id = &assigned.mission.mission_id;
+        let mut proposals = self.proposals.lock().await;
+        if proposals.handled.contains(mission_id) {
+            return None;
+        }
+
+        let waypoints = remaining_waypoints(assigned, progress);
+        if !assigned.waypoints.is_empty() && waypoints.is_empty() {
+            println!("[RETASK] {} left {} with nothing left to fly", device_id, mission_id);
+            return None;
+        }
+
+        // The work resumes where the drone was last seen, or at its next waypoint
+        let dropped = self.fleet.drone(device_id);
+        let last_position = dropped
+            .as_ref()
+            .and_then(|drone| drone.telemetry.as_ref())
+            .and_then(|(_, telemetry)| telemetry.position);
+        let origin = last_position
+            .map(|p| (p.latitude, p.longitude))
+            .or_else(|| waypoints.first().map(|p| (p.latitude, p.longitude)))
+            .or_else(|| {
+                let area = assigned.mission.survey_area.as_ref()?;
+                area.boundary.first().map(|p| (p.latitude, p.longitude))
+            });
+        let Some(origin) = origin else {
+            eprintln!("[RETASK] {} left {}, no location to re-task from", device_id, mission_id);
+            return None;
+        };
+
+        // A drone already proposed for another hand-off is not offered twice
+        let taken: HashSet<&str> =
+            proposals.pending.values().map(|p| p.to_device.as_str()).collect();
+        let drones = self.fleet.drones();
+        let candidates = drones.iter().filter(|drone| {
+            drone.device_id != device_id && !taken.contains(drone.device_id.as_str())
+        });
+        let min_battery = self.config.min_battery_percent;
+        let Some((to, distance_m)) = nearest_healthy(candidates, origin, min_battery) else {
+            eprintln!("[RETASK] {} left {}, no healthy drone to take over", device_id, mission_id);
+            return None;
+        };
+
+        let id = proposals.next_id + 1;
+        let mut mission = assigned.mission.clone();
+        mission.mission_id = format!("{}-retask-{}", mission_id, id);
+        mission.uploaded_waypoints = waypoints.len() as u32;
+        mission.replace = false;
+        if let Err(e) = MissionPlan::from_mission_start(&mission, &waypoints).validate() {
+            eprintln!("[RETASK] {} not re-tasked: {}", mission_id, e);
+            return None;
+        }
+
+        let proposal = RetaskProposal {
+            id,
+            from_device: device_id.to_string(),
+            reason: reason.to_string(),
+            to_device: to.device_id.clone(),
+            distance_m,
+            mission,
+            waypoints,
+            created_at_ms: now_ms(),
+        };
+        println!(
+            "[RETASK] Proposal {}: {} ({}) -> {}, {} waypoints left",
+            id,
+            mission_id,
+            reason,
+            proposal.to_device,
+            proposal.waypoints.len()
+        );
+        proposals.next_id = id;
+        proposals.handled.insert(mission_id.clone());
+        proposals.pending.insert(id, proposal.clone());
+        Some(proposal)
+    }
+
+    /// Proposals waiting for an operator, oldest first
+    pub async fn pending(&self) -> Vec<RetaskProposal> {
+        self.proposals.lock().await.pending.values().cloned().collect()
+    }
+
+    /// Remove a pending proposal, to send or drop it
+    pub async fn take(&self, id: u64) -> Option<RetaskProposal> {
+        self.proposals.lock().await.pending.remove(&id)
+    }
+
+    /// Send a proposal's mission to its drone; returns the mission start's command ID
+    pub async fn dispatch(&self, proposal: &RetaskProposal) -> anyhow::Result<u64> {
+        let device_id = &proposal.to_device;
+        if !proposal.waypoints.is_empty() {
+            self.dispatcher
+                .upload_mission(device_id, &proposal.mission.mission_id, &proposal.waypoints)
+                .await?;
+        }
+        let command = Command {
+            command_id: self.dispatcher.next_command_id(),
+            cmd_type: CommandType::CmdMissionStart.into(),
+            priority: priority::NORMAL,
+            params: Some(command::Params::MissionStart(proposal.mission.clone())),
+            ..Default::default()
+        };
+        let command_id = self.dispatcher.send_command(device_id, command).await?;
+        println!(
+            "[RETASK] {} sent to {} (command {}), taking over from {}",
+            proposal.mission.mission_id, device_id, command_id, proposal.from_device
+        );
+        Ok(command_id)
+    }
+
+    /// Whether the operator called the drone back: its latest mission command
+    /// was not a start
+    async fn operator_recalled(&self, device_id: &str) -> bool {
+        let history = self.dispatcher.command_history(device_id).await;
+        let last = history.iter().rev().find(|audit| {
+            matches!(
+                audit.cmd_type,
+                CommandType::CmdMissionStart
+                    | CommandType::CmdMissionAbort
+                    | CommandType::CmdRth
+                    | CommandType::CmdEmergencyStop
+            )
+        });
+        last.is_some_and(|audit| audit.cmd_type != CommandType::CmdMissionStart)
+    }
+}
+
+/// Uploaded waypoints the drone has not flown to yet
+///
+/// The FC's item numbers count the takeoff and speed items the drone puts
+/// in front of the waypoints; the item it is flying to is still left.
+fn remaining_waypoints(
+    assigned: &AssignedMission,
+    progress: Option<&MissionProgress>,
+) -> Vec<GpsCoordinate> {
+    let Some(progress) = progress else {
+        return assigned.waypoints.clone();
+    };
+    let offset = (progress.total_waypoints as usize).saturating_sub(assigned.waypoints.len());
+    let next = (progress.current_waypoint as usize).saturating_sub(offset);
+    assigned.waypoints.get(next..).unwrap_or_default().to_vec()
+}
+
+/// The closest drone able to take over a mission, and how far it is (m)
+fn nearest_healthy<'a>(
+    drones: impl Iterator<Item = &'a DroneView>,
+    origin: (f64, f64),
+    min_battery_percent: u32,
+) -> Option<(&'a DroneView, f64)> {
+    drones
+        .filter(|drone| {
+            drone.connected
+                && !drone.lost
+                && matches!(drone.state, DroneState::DroneIdle | DroneState::DroneArmed)
+                && drone.battery_percent.is_some_and(|p| p >= min_battery_percent)
+        })
+        .filter_map(|drone| {
+            let position = drone.telemetry.as_ref()?.1.position?;
+            let distance = distance_m(origin, (position.latitude, position.longitude));
+            Some((drone, distance))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Great-circle distance between two (latitude, longitude) points in degrees (m)
+fn distance_m(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (phi1, phi2) = (a.0.to_radians(), b.0.to_radians());
+    let d_phi = (b.0 - a.0).to_radians();
+    let d_lambda = (b.1 - a.1).to_radians();
+    let h = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * h.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::{GpsPosition, Telemetry};
+
+    fn waypoint(latitude: f64) -> GpsCoordinate {
+        GpsCoordinate {
+            latitude,
+            longitude: 8.5,
+            altitude_m: 40.0,
+        }
+    }
+
+    fn drone(device_id: &str, state: DroneState, battery: u32, latitude: f64) -> DroneView {
+        let fleet = FleetState::new();
+        fleet.apply(&ServerEvent::SessionConnected {
+            device_id: device_id.into(),
+            addr: "127.0.0.1:9000".parse().unwrap(),
+        });
+        fleet.apply(&ServerEvent::TelemetryReceived {
+            device_id: device_id.into(),
+            timestamp_ms: 1000,
+            telemetry: Telemetry {
+                state: state.into(),
+                position: Some(GpsPosition {
+                    latitude,
+                    longitude: 8.5,
+                    ..Default::default()
+                }),
+                battery: Some(resqterra_shared::BatteryStatus {
+                    remaining_percent: battery,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        });
+        fleet.drone(device_id).unwrap()
+    }
+
+    #[test]
+    fn test_remaining_waypoints_skip_flown_ones() {
+        let assigned = AssignedMission {
+            mission: MissionStart::default(),
+            waypoints: (0..5).map(|i| waypoint(47.0 + i as f64 * 0.001)).collect(),
+        };
+        // Takeoff and speed items ahead of the five waypoints; flying to the third
+        let progress = MissionProgress {
+            current_waypoint: 4,
+            total_waypoints: 7,
+            ..Default::default()
+        };
+        let left = remaining_waypoints(&assigned, Some(&progress));
+        assert_eq!(left, assigned.waypoints[2..]);
+        assert_eq!(remaining_waypoints(&assigned, None).len(), 5);
+
+        // Past the last waypoint, on the way home
+        let done = MissionProgress {
+            current_waypoint: 7,
+            ..progress
+        };
+        assert!(remaining_waypoints(&assigned, Some(&done)).is_empty());
+    }
+
+    #[test]
+    fn test_nearest_healthy_drone_chosen() {
+        let drones = [
+            drone("edge-002", DroneState::DroneIdle, 90, 47.01),
+            // Closer, but busy or low on battery
+            drone("edge-003", DroneState::DroneInMission, 90, 47.0),
+            drone("edge-004", DroneState::DroneIdle, 30, 47.0),
+            drone("edge-005", DroneState::DroneArmed, 60, 47.005),
+        ];
+        let (to, distance) = nearest_healthy(drones.iter(), (47.0, 8.5), 50).unwrap();
+        assert_eq!(to.device_id, "edge-005");
+        assert!((distance - 556.0).abs() < 1.0, "{distance}");
+
+        assert!(nearest_healthy(drones.iter(), (47.0, 8.5), 95).is_none());
+    }
+}
@@ -1,36 +1,137 @@
 //! Individual drone session handling
 
-use anyhow::Result;
+use crate::events::{EventBus, ServerEvent};
+use anyhow::{anyhow, Result};
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
 use resqterra_shared::{
-    codec::{self, FrameDecoder},
-    safety, Envelope, DroneState,
+    codec::{self, CodecError, EnvelopeCodec},
+    safety, Compression, DeviceId, DeviceKeys, DeviceType, Envelope, EnvelopeSigner, DroneState,
+    RelayStatus, SafetyConfig, SequenceEvent, SequenceTracker, Transport,
 };
+use std::borrow::Cow;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::sync::{mpsc, Mutex};
+use tokio_util::codec::FramedRead;
+
+/// Encoded writes queued per session before senders wait
+const OUTBOUND_QUEUE_CAPACITY: usize = 256;
+
+/// Queued writes at which a session reports backpressure
+pub const BACKPRESSURE_THRESHOLD: usize = OUTBOUND_QUEUE_CAPACITY / 2;
+
+/// Frames failing verification before a session is closed
+pub const MAX_REJECTED_FRAMES: u32 = 10;
+
+/// Byte stream a session runs over: a TCP socket or UDP link, possibly
+/// encrypted with Noise
+pub trait SessionStream: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static> SessionStream for T {}
 
 /// Handle to send messages to a specific drone
+///
+/// All writes for a connection go through a single writer task, so frames
+/// hit the wire in the order they were queued and are never interleaved.
 #[derive(Clone)]
 pub struct SessionHandle {
     pub device_id: String,
     pub addr: SocketAddr,
-    writer: Arc<Mutex<WriteHalf<TcpStream>>>,
+    outbound_tx: mpsc::Sender<Bytes>,
+    /// Frame compression negotiated in the hello (shared by all handles)
+    compression: Arc<AtomicI32>,
+    /// Signs outgoing envelopes the signing policy covers
+    signer: Option<Arc<EnvelopeSigner>>,
+    /// Where backpressure is reported
+    events: Option<EventBus>,
+    /// Whether the queue is over the threshold (shared by all handles)
+    backpressured: Arc<AtomicBool>,
     pub connected_at: Instant,
     pub last_heartbeat: Arc<Mutex<Instant>>,
 }
 
 impl SessionHandle {
     /// Send an envelope to this drone
+    ///
+    /// Returns once the frame is queued; frames from one caller keep their order.
     pub async fn send(&self, envelope: &Envelope) -> Result<()> {
-        let encoded = codec::encode(envelope)?;
-        let mut writer = self.writer.lock().await;
-        writer.write_all(&encoded).await?;
+        let encoded = codec::encode_compressed(&self.signed(envelope), self.compression())?;
+        self.enqueue(encoded).await
+    }
+
+    /// Send several envelopes back-to-back (e.g. commands replayed after a reconnect)
+    ///
+    /// The frames are queued as one write, so no other sender's frames can
+    /// land between them.
+    pub async fn send_batch(&self, envelopes: &[Envelope]) -> Result<()> {
+        let mut buf = BytesMut::new();
+        let compression = self.compression();
+        for envelope in envelopes {
+            codec::encode_compressed_into(&self.signed(envelope), compression, &mut buf)?;
+        }
+        self.enqueue(buf.freeze()).await
+    }
+
+    /// The envelope as sent, signed if the signing policy covers it
+    fn signed<'a>(&self, envelope: &'a Envelope) -> Cow<'a, Envelope> {
+        match &self.signer {
+            Some(signer) if signer.policy().requires_signature(envelope) => {
+                let mut signed = envelope.clone();
+                signer.sign(&mut signed);
+                Cow::Owned(signed)
+            }
+            _ => Cow::Borrowed(envelope),
+        }
+    }
+
+    /// Compression applied to frames sent from now on
+    pub fn compression(&self) -> Compression {
+        Compression::try_from(self.compression.load(Ordering::Relaxed)).unwrap_or_default()
+    }
+
+    /// Switch frame compression for this connection
+    pub fn set_compression(&self, compression: Compression) {
+        self.compression.store(compression as i32, Ordering::Relaxed);
+    }
+
+    async fn enqueue(&self, frames: Bytes) -> Result<()> {
+        self.outbound_tx
+            .send(frames)
+            .await
+            .map_err(|_| anyhow!("Session writer closed: {}", self.addr))?;
+        self.check_backpressure();
         Ok(())
     }
 
+    /// Writes queued and not yet handed to the socket
+    ///
+    /// A queue that keeps growing means the link can't keep up, which usually
+    /// comes before a disconnect.
+    pub fn queue_depth(&self) -> usize {
+        self.outbound_tx.max_capacity() - self.outbound_tx.capacity()
+    }
+
+    /// Publish `Backpressure` once each time the queue reaches the threshold
+    fn check_backpressure(&self) {
+        let depth = self.queue_depth();
+        if depth < BACKPRESSURE_THRESHOLD {
+            self.backpressured.store(false, Ordering::Relaxed);
+            return;
+        }
+        if !self.backpressured.swap(true, Ordering::Relaxed) {
+            if let Some(events) = &self.events {
+                events.publish(ServerEvent::Backpressure {
+                    device_id: self.device_id.clone(),
+                    depth,
+                });
+            }
+        }
+    }
+
     /// Check if the session is still alive (heartbeat not timed out)
     pub async fn is_alive(&self) -> bool {
         let last = *self.last_heartbeat.lock().await;
@@ -51,33 +152,103 @@ impl SessionHandle {
 /// Active drone session
 pub struct DroneSession {
     pub handle: SessionHandle,
-    reader: ReadHalf<TcpStream>,
-    decoder: FrameDecoder,
-    read_buf: Vec<u8>,
+    reader: FramedRead<ReadHalf<Box<dyn SessionStream>>, EnvelopeCodec>,
+    /// Whether unknown fields from this peer have been logged
+    unknown_reported: bool,
+    /// Gaps and duplicates in the drone's sequence ids
+    sequence: SequenceTracker,
+    /// Verifies incoming envelopes the signing policy covers
+    signer: Option<Arc<EnvelopeSigner>>,
+    /// Keys to pick `signer` from once the drone's ID is known
+    device_keys: Option<Arc<DeviceKeys>>,
+    /// Device `signer` was picked for; frames claiming another are rejected
+    signer_id: Option<DeviceId>,
+    /// Frames that failed verification so far
+    rejected_frames: u32,
+    /// Rejected frames after which the session is closed
+    reject_limit: u32,
+    /// Device the link's Noise key belongs to; other device IDs are refused
+    authenticated_id: Option<DeviceId>,
 }
 
 impl DroneSession {
-    /// Create a new drone session from a TCP stream
-    pub fn new(stream: TcpStream, addr: SocketAddr) -> Self {
+    /// Create a new drone session from a TCP (or encrypted) stream
+    pub fn new(stream: impl SessionStream, addr: SocketAddr) -> Self {
+        let stream: Box<dyn SessionStream> = Box::new(stream);
         let (reader, writer) = tokio::io::split(stream);
         let now = Instant::now();
 
+        let (outbound_tx, outbound_rx) = mpsc::channel(OUTBOUND_QUEUE_CAPACITY);
+        tokio::spawn(writer_task(writer, outbound_rx, addr));
+
         let handle = SessionHandle {
             device_id: String::new(), // Will be set on first message
             addr,
-            writer: Arc::new(Mutex::new(writer)),
+            outbound_tx,
+            compression: Arc::new(AtomicI32::new(Compression::None as i32)),
+            signer: None,
+            events: None,
+            backpressured: Arc::new(AtomicBool::new(false)),
             connected_at: now,
             last_heartbeat: Arc::new(Mutex::new(now)),
         };
 
         Self {
             handle,
-            reader,
-            decoder: FrameDecoder::new(),
-            read_buf: vec![0u8; 4096],
+            reader: FramedRead::new(reader, EnvelopeCodec::new()),
+            unknown_reported: false,
+            sequence: SequenceTracker::new(),
+            signer: None,
+            device_keys: None,
+            signer_id: None,
+            rejected_frames: 0,
+            reject_limit: MAX_REJECTED_FRAMES,
+            authenticated_id: None,
         }
     }
 
+    /// Sign outgoing and verify incoming envelopes per the signer's policy
+    #[cfg(test)]
+    pub fn with_signer(mut self, signer: Arc<EnvelopeSigner>) -> Self {
+        self.handle.signer = Some(signer.clone());
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Sign and verify with the drone's own key from `keys`
+    ///
+    /// The key is chosen from the first frame's device ID; a drone without one
+    /// (and no fallback key) is disconnected. Frames claiming any other device
+    /// ID are then rejected, so one drone's key can't speak for another.
+    pub fn with_device_keys(mut self, keys: Arc<DeviceKeys>) -> Self {
+        self.device_keys = Some(keys);
+        self
+    }
+
+    /// Accept only `device_id` on this link, as proven by its Noise handshake
+    pub fn with_authenticated_device(mut self, device_id: DeviceId) -> Self {
+        self.authenticated_id = Some(device_id);
+        self
+    }
+
+    /// Close the session after `limit` frames fail verification
+    #[cfg(test)]
+    pub fn with_reject_limit(mut self, limit: u32) -> Self {
+        self.reject_limit = limit.max(1);
+        self
+    }
+
+    /// Frames from this drone that failed verification
+    pub fn rejected_frames(&self) -> u32 {
+        self.rejected_frames
+    }
+
+    /// Report backpressure and sequence gaps on this session to `events`
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.handle.events = Some(events);
+        self
+    }
+
     /// Get a cloneable handle for sending messages
     pub fn get_handle(&self) -> SessionHandle {
         self.handle.clone()
@@ -87,43 +258,138 @@ impl DroneSession {
     /// Returns None if the connection is closed
     pub async fn recv(&mut self) -> Option<Envelope> {
         loop {
-            // First try to decode from existing buffer
-            match self.decoder.decode_next() {
-                Ok(Some(envelope)) => {
-                    // Update device ID from header if not set
-                    if self.handle.device_id.is_empty() {
-                        if let Some(ref header) = envelope.header {
-                            self.handle.device_id = header.device_id.clone();
-                        }
-                    }
-
-                    // Update heartbeat time for heartbeat messages
-                    if let Some(resqterra_shared::envelope::Payload::Heartbeat(_)) = &envelope.payload {
-                        self.handle.update_heartbeat().await;
-                    }
-
-                    return Some(envelope);
-                }
-                Ok(None) => {
-                    // Need more data
+            let mut envelope = match self.reader.next().await? {
+                Ok(envelope) => envelope,
+                Err(CodecError::Io(e)) => {
+                    eprintln!("Read error from {}: {}", self.handle.addr, e);
+                    return None;
                 }
                 Err(e) => {
                     eprintln!("Decode error from {}: {}", self.handle.addr, e);
                     return None;
                 }
+            };
+
+            // Newer peers may add fields; they're skipped, logged once
+            if !self.unknown_reported && self.reader.decoder().unknown_bytes() > 0 {
+                self.unknown_reported = true;
+                println!(
+                    "Peer {} sent fields this server doesn't know (newer protocol?), ignoring them",
+                    self.handle.addr
+                );
             }
 
-            // Read more data
-            match self.reader.read(&mut self.read_buf).await {
-                Ok(0) => return None, // Connection closed
-                Ok(n) => {
-                    self.decoder.extend(&self.read_buf[..n]);
-                }
+            // Drop envelopes with nothing to route rather than pass them on
+            if let Err(e) = envelope.validate() {
+                eprintln!("Rejected invalid envelope from {}: {}", self.handle.addr, e);
+                continue;
+            }
+
+            // Reject malformed device IDs before they become map keys or hit logs
+            let raw_id = envelope.header.as_ref().map_or("", |h| h.device_id.as_str());
+            let device_id = match DeviceId::parse(raw_id) {
+                Ok(id) => id,
                 Err(e) => {
-                    eprintln!("Read error from {}: {}", self.handle.addr, e);
+                    eprintln!("Protocol error from {}: {}", self.handle.addr, e);
                     return None;
                 }
+            };
+
+            // An encrypted link speaks for one drone only
+            if let Some(expected) = &self.authenticated_id {
+                if device_id != *expected {
+                    eprintln!(
+                        "{} sent frames as {} but its Noise key belongs to {}, closing session",
+                        self.handle.addr, device_id, expected
+                    );
+                    return None;
+                }
+            }
+
+            // Pick the drone's own key once it has identified itself
+            if self.signer.is_none() {
+                if let Some(keys) = &self.device_keys {
+                    let Some(signer) = keys.signer_for(device_id.as_str()) else {
+                        eprintln!("No signing key for {} ({}), closing session", device_id, self.handle.addr);
+                        return None;
+                    };
+                    let signer = Arc::new(signer);
+                    self.handle.signer = Some(signer.clone());
+                    self.signer = Some(signer);
+                    self.signer_id = Some(device_id.clone());
+                }
+            }
+
+            // Drop messages the signing policy covers that fail verification,
+            // or that claim a device other than the one the key belongs to,
+            // and give up on a peer that keeps sending them
+            if let Some(signer) = &self.signer {
+                let verified = match &self.signer_id {
+                    Some(signer_id) if *signer_id != device_id => {
+                        Err(format!("signed with the key of {}, claims {}", signer_id, device_id))
+                    }
+                    _ => signer
+                        .verify_encoded(&envelope, self.reader.decoder().last_envelope())
+                        .map_err(|e| e.to_string()),
+                };
+                if let Err(e) = verified {
+                    self.rejected_frames += 1;
+                    eprintln!(
+                        "Rejected message from {} ({}/{}): {}",
+                        self.handle.addr, self.rejected_frames, self.reject_limit, e
+                    );
+                    if self.rejected_frames >= self.reject_limit {
+                        eprintln!("Too many rejected frames from {}, closing session", self.handle.addr);
+                        self.publish(ServerEvent::Alert {
+                            device_id: String::from(&device_id),
+                            message: format!("session closed after {} rejected frames", self.rejected_frames),
+                        });
+                        return None;
+                    }
+                    continue;
+                }
+            }
+
+            if let Some(ref mut header) = envelope.header {
+                header.device_id = String::from(&device_id);
+
+                // Update device ID from header if not set
+                if self.handle.device_id.is_empty() {
+                    self.handle.device_id = header.device_id.clone();
+                }
+
+                // Drop duplicates; gaps are reported for link monitoring
+                match self.sequence.observe(header.sequence_id) {
+                    SequenceEvent::Duplicate => {
+                        self.publish(ServerEvent::DuplicateDropped {
+                            device_id: self.handle.device_id.clone(),
+                            sequence_id: header.sequence_id,
+                        });
+                        continue;
+                    }
+                    SequenceEvent::Gap { first, last } => {
+                        self.publish(ServerEvent::SequenceGap {
+                            device_id: self.handle.device_id.clone(),
+                            first,
+                            last,
+                        });
+                    }
+                    SequenceEvent::InOrder | SequenceEvent::Reordered | SequenceEvent::Reset => {}
+                }
+            }
+
+            // Update heartbeat time for heartbeat messages
+            if let Some(resqterra_shared::envelope::Payload::Heartbeat(_)) = &envelope.payload {
+                self.handle.update_heartbeat().await;
             }
+
+            return Some(envelope);
+        }
+    }
+
+    fn publish(&self, event: ServerEvent) {
+        if let Some(events) = &self.handle.events {
+            events.publish(event);
         }
     }
 
@@ -138,6 +404,27 @@ impl DroneSession {
     }
 }
 
+/// Single writer per connection: drains queued frames in order
+///
+/// Exits when every handle is dropped or the socket fails.
+async fn writer_task(
+    mut writer: WriteHalf<Box<dyn SessionStream>>,
+    mut outbound_rx: mpsc::Receiver<Bytes>,
+    addr: SocketAddr,
+) {
+    while let Some(frames) = outbound_rx.recv().await {
+        // Flush so encrypting streams don't sit on buffered ciphertext
+        let written = match writer.write_all(&frames).await {
+            Ok(()) => writer.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            eprintln!("Write error to {}: {}", addr, e);
+            break;
+        }
+    }
+}
+
 /// Drone state tracked by the server
 #[derive(Debug, Clone)]
 pub struct DroneInfo {
@@ -147,6 +434,16 @@ pub struct DroneInfo {
     pub last_heartbeat: Instant,
     pub connected_at: Instant,
     pub pending_commands: u32,
+    /// Failsafe configuration reported by the drone in its hello
+    pub safety_config: Option<SafetyConfig>,
+    /// Link the drone last reported in its telemetry
+    pub transport: Transport,
+    /// Writes queued for the drone (filled in by `fleet_snapshot`)
+    pub send_queue_depth: usize,
+    /// Drone or relay, from the hello
+    pub device_type: DeviceType,
+    /// Health a relay reported in its last heartbeat
+    pub relay_status: Option<RelayStatus>,
 }
 
 impl DroneInfo {
@@ -159,6 +456,297 @@ impl DroneInfo {
             last_heartbeat: now,
             connected_at: now,
             pending_commands: 0,
+            safety_config: None,
+            transport: Transport::Unknown,
+            send_queue_depth: 0,
+            device_type: DeviceType::DeviceDrone,
+            relay_status: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::codec::FrameDecoder;
+    use resqterra_shared::{Header, Heartbeat, MessageType, SigningPolicy};
+    use resqterra_shared::noise::{NoiseConfig, NoiseStream};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn session_pair() -> (DroneSession, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+        (DroneSession::new(stream, addr), client)
+    }
+
+    fn frame(device_id: &str, sequence_id: u64) -> Envelope {
+        Envelope {
+            header: Some(Header::new(device_id, MessageType::MsgHeartbeat, sequence_id)),
+            payload: Some(resqterra_shared::envelope::Payload::Heartbeat(Heartbeat::new(
+                sequence_id,
+                DroneState::DroneIdle,
+                0,
+                true,
+            ))),
+            signature: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_sends_stay_ordered() {
+        const BATCHES: u64 = 50;
+        const BATCH_LEN: u64 = 4;
+
+        let (session, mut client) = session_pair().await;
+
+        // Two tasks interleave multi-frame batches on the same connection
+        let senders: Vec<_> = ["task-a", "task-b"]
+            .into_iter()
+            .map(|name| {
+                let handle = session.get_handle();
+                tokio::spawn(async move {
+                    for batch in 0..BATCHES {
+                        let frames: Vec<Envelope> = (0..BATCH_LEN)
+                            .map(|i| frame(name, batch * BATCH_LEN + i))
+                            .collect();
+                        handle.send_batch(&frames).await.unwrap();
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.await.unwrap();
+        }
+
+        let total = 2 * BATCHES * BATCH_LEN;
+        let mut decoder = FrameDecoder::new();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 4096];
+        while (received.len() as u64) < total {
+            let n = client.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed early");
+            decoder.extend(&buf[..n]);
+            while let Some(envelope) = decoder.decode_next().expect("corrupted frame") {
+                let header = envelope.header.unwrap();
+                received.push((header.device_id, header.sequence_id));
+            }
+        }
+
+        // Per-sender order is preserved and every batch arrives contiguously
+        for name in ["task-a", "task-b"] {
+            let seqs: Vec<u64> = received
+                .iter()
+                .filter(|(id, _)| id == name)
+                .map(|(_, seq)| *seq)
+                .collect();
+            assert_eq!(seqs, (0..BATCHES * BATCH_LEN).collect::<Vec<_>>());
+        }
+        for chunk in received.chunks(BATCH_LEN as usize) {
+            assert!(chunk.iter().all(|(id, _)| id == &chunk[0].0), "batch interleaved");
+            assert_eq!(chunk[0].1 % BATCH_LEN, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_non_draining_peer_reports_backpressure() {
+        // The client never reads, so the socket buffers and then the queue fill
+        let (session, _client) = session_pair().await;
+        let events = EventBus::new();
+        let mut rx = events.subscribe();
+        let mut sender = session.with_events(events).get_handle();
+        sender.device_id = "edge-001".into();
+
+        let bulk = Envelope {
+            payload: Some(resqterra_shared::envelope::Payload::SensorData(
+                resqterra_shared::SensorData {
+                    data: vec![0xA5; 64 * 1024],
+                    ..Default::default()
+                },
+            )),
+            ..frame("edge-001", 1)
+        };
+        let flood = tokio::spawn(async move {
+            loop {
+                if sender.send(&bulk).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let event = tokio::time::timeout(std::time::Duration::from_secs(10), rx.recv())
+            .await
+            .expect("no backpressure event")
+            .unwrap();
+        flood.abort();
+        match event {
+            ServerEvent::Backpressure { device_id, depth } => {
+                assert_eq!(device_id, "edge-001");
+                assert!(depth >= BACKPRESSURE_THRESHOLD);
+            }
+            other => panic!("expected Backpressure, got {:?}", other),
         }
     }
+
+    #[tokio::test]
+    async fn test_device_id_normalized_on_recv() {
+        let (mut session, mut client) = session_pair().await;
+        client.write_all(&codec::encode(&frame("EDGE-001", 1)).unwrap()).await.unwrap();
+
+        let envelope = session.recv().await.expect("valid envelope");
+        assert_eq!(envelope.header.unwrap().device_id, "edge-001");
+        assert_eq!(session.device_id(), "edge-001");
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_message_dropped_when_policy_requires_signature() {
+        let (session, mut client) = session_pair().await;
+        let signer = EnvelopeSigner::new("secret", SigningPolicy::default().with_type(MessageType::MsgHeartbeat));
+        let mut session = session.with_signer(Arc::new(signer.clone()));
+
+        // The unsigned heartbeat is skipped; the signed one after it comes through
+        let mut signed = frame("edge-001", 2);
+        signer.sign(&mut signed);
+        client.write_all(&codec::encode(&frame("edge-001", 1)).unwrap()).await.unwrap();
+        client.write_all(&codec::encode(&signed).unwrap()).await.unwrap();
+
+        let envelope = session.recv().await.expect("signed envelope");
+        assert_eq!(envelope.header.unwrap().sequence_id, 2);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_rejected_frames_close_session() {
+        let (session, mut client) = session_pair().await;
+        let events = EventBus::new();
+        let mut rx = events.subscribe();
+        let keys = DeviceKeys::new(SigningPolicy::default().with_type(MessageType::MsgHeartbeat))
+            .with_key(&DeviceId::parse("edge-001").unwrap(), "right");
+        let mut session = session
+            .with_events(events)
+            .with_device_keys(Arc::new(keys))
+            .with_reject_limit(2);
+
+        // Signed with someone else's key, so neither verifies
+        let wrong = EnvelopeSigner::new("wrong", SigningPolicy::default().with_type(MessageType::MsgHeartbeat));
+        for seq in [1, 2] {
+            let mut envelope = frame("edge-001", seq);
+            wrong.sign(&mut envelope);
+            client.write_all(&codec::encode(&envelope).unwrap()).await.unwrap();
+        }
+
+        assert!(session.recv().await.is_none());
+        assert_eq!(session.rejected_frames(), 2);
+        assert!(matches!(rx.recv().await.unwrap(), ServerEvent::Alert { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_device_key_cannot_sign_for_another_device() {
+        let (session, mut client) = session_pair().await;
+        let policy = SigningPolicy::default().with_type(MessageType::MsgHeartbeat);
+        let keys = DeviceKeys::new(policy.clone())
+            .with_key(&DeviceId::parse("edge-001").unwrap(), "key-a")
+            .with_key(&DeviceId::parse("edge-002").unwrap(), "key-b");
+        let mut session = session.with_device_keys(Arc::new(keys));
+
+        // Device A identifies itself, then signs a frame claiming to be B
+        // with its own key, and another as B with B's key it shouldn't have
+        let key_a = EnvelopeSigner::new("key-a", policy.clone());
+        let key_b = EnvelopeSigner::new("key-b", policy);
+        let mut own = frame("edge-001", 1);
+        key_a.sign(&mut own);
+        let mut as_b = frame("edge-002", 2);
+        key_a.sign(&mut as_b);
+        let mut as_b_with_b = frame("edge-002", 3);
+        key_b.sign(&mut as_b_with_b);
+        let mut next = frame("edge-001", 4);
+        key_a.sign(&mut next);
+        for envelope in [own, as_b, as_b_with_b, next] {
+            client.write_all(&codec::encode(&envelope).unwrap()).await.unwrap();
+        }
+
+        assert_eq!(session.recv().await.unwrap().header.unwrap().sequence_id, 1);
+        assert_eq!(session.recv().await.unwrap().header.unwrap().sequence_id, 4);
+        assert_eq!(session.rejected_frames(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_noise_session_refuses_other_device_ids() {
+        let edge = NoiseConfig::generate().unwrap();
+        let server = NoiseConfig::generate().unwrap().with_peer("edge-001", edge.public_key());
+        let edge = edge.with_peer("server", server.public_key());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+        let (client, accepted) = tokio::join!(
+            NoiseStream::initiate(client, &edge),
+            NoiseStream::accept(stream, &server)
+        );
+        let (mut client, accepted) = (client.unwrap(), accepted.unwrap());
+        let device_id = DeviceId::parse(accepted.peer()).unwrap();
+        let mut session = DroneSession::new(accepted, addr).with_authenticated_device(device_id);
+
+        client.write_all(&codec::encode(&frame("edge-001", 1)).unwrap()).await.unwrap();
+        client.write_all(&codec::encode(&frame("edge-002", 2)).unwrap()).await.unwrap();
+        client.flush().await.unwrap();
+
+        assert_eq!(session.recv().await.unwrap().header.unwrap().sequence_id, 1);
+        assert!(session.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_headerless_and_payloadless_envelopes_dropped() {
+        let (mut session, mut client) = session_pair().await;
+        let headerless = Envelope {
+            header: None,
+            ..frame("edge-001", 1)
+        };
+        let header_only = Envelope {
+            payload: None,
+            ..frame("edge-001", 2)
+        };
+        for envelope in [headerless, header_only, frame("edge-001", 3)] {
+            client.write_all(&codec::encode(&envelope).unwrap()).await.unwrap();
+        }
+
+        let envelope = session.recv().await.expect("valid envelope");
+        assert_eq!(envelope.header.unwrap().sequence_id, 3);
+        assert_eq!(session.device_id(), "edge-001");
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_sequence_dropped_and_gap_reported() {
+        let (session, mut client) = session_pair().await;
+        let events = EventBus::new();
+        let mut rx = events.subscribe();
+        let mut session = session.with_events(events);
+        for seq in [1, 1, 4] {
+            client.write_all(&codec::encode(&frame("edge-001", seq)).unwrap()).await.unwrap();
+        }
+
+        assert_eq!(session.recv().await.unwrap().header.unwrap().sequence_id, 1);
+        assert_eq!(session.recv().await.unwrap().header.unwrap().sequence_id, 4);
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            ServerEvent::DuplicateDropped { sequence_id: 1, .. }
+        ));
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            ServerEvent::SequenceGap { first: 2, last: 3, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_device_id_closes_session() {
+        let (mut session, mut client) = session_pair().await;
+        client
+            .write_all(&codec::encode(&frame("edge-001\n[server] fake", 1)).unwrap())
+            .await
+            .unwrap();
+
+        assert!(session.recv().await.is_none());
+        assert!(session.device_id().is_empty());
+    }
 }
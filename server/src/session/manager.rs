@@ -1,21 +1,70 @@
 //! Session manager for tracking all connected drones
 
 use super::connection::{DroneInfo, SessionHandle};
-use resqterra_shared::{safety, Envelope};
+use crate::blobs::BlobStore;
+use crate::events::{EventBus, ServerEvent};
+use resqterra_shared::{
+    now_ms, safety, state_machine, CommandType, ConnectionQuality, DeviceId, DeviceType, DroneState,
+    Envelope, QualityHistory, RelayStatus, SafetyConfig, Telemetry, Transport, QUALITY_HISTORY_LEN,
+};
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, RwLock};
+
+/// Buffered telemetry frames per device subscription before slow readers lag
+const TELEMETRY_SUBSCRIPTION_CAPACITY: usize = 64;
+
+/// How long a disconnected drone can resume its session with its token
+pub const SESSION_RESUME_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long a drone has to reach the state a completed command puts it in
+pub const STATE_DIVERGENCE_GRACE: Duration = Duration::from_secs(5);
 
 /// Manages all active drone sessions
 pub struct SessionManager {
     /// Map of device_id -> session handle
     sessions: Arc<RwLock<HashMap<String, SessionEntry>>>,
+    /// Per-device telemetry streams (device_id -> broadcast sender)
+    telemetry_subs: Arc<RwLock<HashMap<String, broadcast::Sender<Telemetry>>>>,
+    /// Server-wide event bus
+    events: EventBus,
+    /// Link quality samples kept per drone
+    quality_history_len: usize,
+    /// Sessions of disconnected drones, kept for `SESSION_RESUME_WINDOW`
+    detached: Arc<RwLock<HashMap<String, DetachedSession>>>,
+    /// Keys session tokens; random per server run
+    token_keys: RandomState,
+    tokens_issued: AtomicU64,
+    /// Takes the images and tiles drones upload (None = uploads refused)
+    blobs: Option<Arc<BlobStore>>,
+    /// How long reports may lag a completed command before they diverge
+    divergence_grace: Duration,
 }
 
 struct SessionEntry {
     handle: SessionHandle,
     info: DroneInfo,
+    /// Link quality reported in the drone's telemetry
+    quality: QualityHistory,
+    /// Token the drone resumes this session with (empty until its hello)
+    token: String,
+    /// Latest full telemetry frame and when it was taken (ms since epoch)
+    telemetry: Option<(u64, Telemetry)>,
+    /// State a completed command should put the drone in, and since when
+    expected_state: Option<(DroneState, Instant)>,
+}
+
+/// What a disconnected drone's session leaves behind for it to resume
+struct DetachedSession {
+    token: String,
+    info: DroneInfo,
+    quality: QualityHistory,
+    detached_at: Instant,
 }
 
 impl SessionManager {
@@ -23,27 +72,213 @@ impl SessionManager {
     pub fn new() -> Self {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            telemetry_subs: Arc::new(RwLock::new(HashMap::new())),
+            events: EventBus::new(),
+            quality_history_len: QUALITY_HISTORY_LEN,
+            detached: Arc::new(RwLock::new(HashMap::new())),
+            token_keys: RandomState::new(),
+            tokens_issued: AtomicU64::new(0),
+            blobs: None,
+            divergence_grace: STATE_DIVERGENCE_GRACE,
         }
     }
 
+    /// Keep `len` link quality samples per drone
+    pub fn with_quality_history_len(mut self, len: usize) -> Self {
+        self.quality_history_len = len;
+        self
+    }
+
+    /// Give drones `grace` to follow a completed command
+    #[cfg(test)]
+    pub fn with_divergence_grace(mut self, grace: Duration) -> Self {
+        self.divergence_grace = grace;
+        self
+    }
+
+    /// Store blobs drones upload in `store`
+    pub fn with_blob_store(mut self, store: Arc<BlobStore>) -> Self {
+        self.blobs = Some(store);
+        self
+    }
+
+    /// Where uploaded blobs go, if uploads are accepted
+    pub fn blob_store(&self) -> Option<&BlobStore> {
+        self.blobs.as_deref()
+    }
+
+    /// Get a handle to the server event bus
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
     /// Register a new drone session
+    ///
+    /// Re-registering an already known device (e.g. after a transport switch)
+    /// replaces its handle but keeps the tracked drone info.
     pub async fn register(&self, handle: SessionHandle) {
-        let device_id = handle.device_id.clone();
-        if device_id.is_empty() {
-            return; // Can't register without device ID
+        let device_id = match DeviceId::parse(&handle.device_id) {
+            Ok(id) if id.as_str() == handle.device_id => handle.device_id.clone(),
+            _ => return, // Can't register without a valid, normalized device ID
+        };
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(entry) = sessions.get_mut(&device_id) {
+            if entry.handle.addr != handle.addr {
+                println!("Drone {} reconnected from {}", device_id, handle.addr);
+                entry.info.addr = handle.addr;
+                entry.info.connected_at = handle.connected_at;
+                self.events.publish(ServerEvent::SessionConnected {
+                    device_id: device_id.clone(),
+                    addr: handle.addr,
+                });
+            }
+            entry.handle = handle;
+            return;
         }
 
-        let info = DroneInfo::new(device_id.clone(), handle.addr);
-        let entry = SessionEntry { handle, info };
+        let addr = handle.addr;
+        let info = DroneInfo::new(device_id.clone(), addr);
+        let quality = QualityHistory::new(self.quality_history_len);
+        let token = String::new();
+        let entry = SessionEntry {
+            handle,
+            info,
+            quality,
+            token,
+            telemetry: None,
+            expected_state: None,
+        };
+        sessions.insert(device_id.clone(), entry);
+        self.events
+            .publish(ServerEvent::SessionConnected { device_id, addr });
+    }
 
+    /// Unregister a drone session
+    ///
+    /// Only removes the session if it still belongs to `addr`, so a stale
+    /// connection closing after the drone already reconnected is a no-op.
+    pub async fn unregister(&self, device_id: &str, addr: SocketAddr) {
         let mut sessions = self.sessions.write().await;
-        sessions.insert(device_id, entry);
+        let entry = match sessions.get(device_id) {
+            Some(entry) if entry.handle.addr == addr => sessions.remove(device_id),
+            _ => return,
+        };
+        drop(sessions);
+        if let Some(entry) = entry {
+            self.detach(device_id, entry).await;
+        }
+
+        self.close_telemetry_subscriptions(device_id).await;
+        self.events.publish(ServerEvent::SessionDisconnected {
+            device_id: device_id.to_string(),
+            reason: "connection closed".into(),
+        });
     }
 
-    /// Unregister a drone session
-    pub async fn unregister(&self, device_id: &str) {
+    /// Keep a closed session around for the drone to resume
+    async fn detach(&self, device_id: &str, entry: SessionEntry) {
+        if entry.token.is_empty() {
+            return;
+        }
+        self.detached.write().await.insert(
+            device_id.to_string(),
+            DetachedSession {
+                token: entry.token,
+                info: entry.info,
+                quality: entry.quality,
+                detached_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Match the session token a drone sent in its hello
+    ///
+    /// A token naming the drone's live session, or one that closed less than
+    /// `SESSION_RESUME_WINDOW` ago, resumes it: the drone info and link quality
+    /// tracked so far carry over to the new connection. Anything else starts a
+    /// new session under a fresh token. Returns the session's token and
+    /// whether it was resumed.
+    pub async fn resume_session(&self, device_id: &str, token: &str) -> (String, bool) {
         let mut sessions = self.sessions.write().await;
-        sessions.remove(device_id);
+        let Some(entry) = sessions.get_mut(device_id) else {
+            return (String::new(), false);
+        };
+        if !token.is_empty() && entry.token == token {
+            return (entry.token.clone(), true);
+        }
+
+        let mut detached = self.detached.write().await;
+        detached.retain(|_, d| d.detached_at.elapsed() < SESSION_RESUME_WINDOW);
+        match detached.remove(device_id) {
+            Some(old) if !token.is_empty() && old.token == token => {
+                entry.info = DroneInfo {
+                    addr: entry.info.addr,
+                    connected_at: entry.info.connected_at,
+                    last_heartbeat: entry.info.last_heartbeat,
+                    ..old.info
+                };
+                entry.quality = old.quality;
+                entry.token = old.token;
+                (entry.token.clone(), true)
+            }
+            _ => {
+                entry.token = self.new_token();
+                (entry.token.clone(), false)
+            }
+        }
+    }
+
+    /// A token no other session has or had
+    fn new_token(&self) -> String {
+        let issued = self.tokens_issued.fetch_add(1, Ordering::Relaxed);
+        let mut high = self.token_keys.build_hasher();
+        high.write_u64(issued);
+        let mut low = self.token_keys.build_hasher();
+        low.write_u64(!issued);
+        format!("{:016x}{:016x}", high.finish(), low.finish())
+    }
+
+    /// Subscribe to the telemetry stream of a single drone
+    ///
+    /// The subscription may be created before the drone connects; frames are
+    /// delivered once it starts reporting. When the drone disconnects the
+    /// stream is closed (`RecvError::Closed`) and callers should resubscribe.
+    pub async fn subscribe_device_telemetry(
+        &self,
+        device_id: &str,
+    ) -> broadcast::Receiver<Telemetry> {
+        let mut subs = self.telemetry_subs.write().await;
+        subs.entry(device_id.to_string())
+            .or_insert_with(|| broadcast::channel(TELEMETRY_SUBSCRIPTION_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Publish a telemetry frame taken at `timestamp_ms` to subscribers of
+    /// that drone and the event bus
+    pub async fn publish_telemetry(
+        &self,
+        device_id: &str,
+        timestamp_ms: u64,
+        telemetry: &Telemetry,
+    ) {
+        self.events.publish(ServerEvent::TelemetryReceived {
+            device_id: device_id.to_string(),
+            timestamp_ms,
+            telemetry: telemetry.clone(),
+        });
+
+        let subs = self.telemetry_subs.read().await;
+        if let Some(tx) = subs.get(device_id) {
+            if tx.receiver_count() > 0 {
+                let _ = tx.send(telemetry.clone());
+            }
+        }
+    }
+
+    /// Drop the telemetry stream of a drone, closing all of its subscribers
+    async fn close_telemetry_subscriptions(&self, device_id: &str) {
+        self.telemetry_subs.write().await.remove(device_id);
     }
 
     /// Get a session handle for a specific drone
@@ -59,6 +294,17 @@ impl SessionManager {
         handle.send(envelope).await
     }
 
+    /// Send several envelopes to a drone as one write (see `SessionHandle::send_batch`)
+    pub async fn send_batch_to(
+        &self,
+        device_id: &str,
+        envelopes: &[Envelope],
+    ) -> anyhow::Result<()> {
+        let handle = self.get(device_id).await
+            .ok_or_else(|| anyhow::anyhow!("Drone not connected: {}", device_id))?;
+        handle.send_batch(envelopes).await
+    }
+
     /// Broadcast a message to all connected drones
     pub async fn broadcast(&self, envelope: &Envelope) {
         let sessions = self.sessions.read().await;
@@ -89,14 +335,137 @@ impl SessionManager {
         }
     }
 
-    /// Update drone state
-    pub async fn update_state(&self, device_id: &str, state: resqterra_shared::DroneState) {
+    /// Update drone state, publishing `StateChanged` when it differs
+    ///
+    /// A state that can't follow from the one a completed command put the
+    /// drone in publishes `StateDivergence`, once the drone had
+    /// `STATE_DIVERGENCE_GRACE` to get there.
+    pub async fn update_state(&self, device_id: &str, state: DroneState) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(entry) = sessions.get_mut(device_id) {
+            if let Some((expected, since)) = entry.expected_state {
+                if state_machine::is_valid_transition(expected, state) {
+                    entry.expected_state = None;
+                } else if since.elapsed() >= self.divergence_grace {
+                    entry.expected_state = None;
+                    self.events.publish(ServerEvent::StateDivergence {
+                        device_id: device_id.to_string(),
+                        expected,
+                        reported: state,
+                    });
+                }
+            }
+            let from = std::mem::replace(&mut entry.info.state, state);
+            if from != state {
+                self.events.publish(ServerEvent::StateChanged {
+                    device_id: device_id.to_string(),
+                    from,
+                    to: state,
+                });
+            }
+        }
+    }
+
+    /// Expect a drone to follow a command it completed
+    ///
+    /// Only a return to home puts the drone in a known state (returning, or
+    /// landing when it is near home); other commands leave it where it is.
+    pub async fn expect_command_state(&self, device_id: &str, cmd_type: CommandType) {
+        let expected = match cmd_type {
+            CommandType::CmdRth => DroneState::DroneReturningHome,
+            _ => return,
+        };
+        let mut sessions = self.sessions.write().await;
+        if let Some(entry) = sessions.get_mut(device_id) {
+            entry.expected_state = Some((expected, Instant::now()));
+        }
+    }
+
+    /// Record the pending command count a drone reported in its heartbeat
+    pub async fn update_pending_commands(&self, device_id: &str, pending_commands: u32) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(entry) = sessions.get_mut(device_id) {
+            entry.info.pending_commands = pending_commands;
+        }
+    }
+
+    /// Record whether a device is a drone or a relay, from its hello
+    pub async fn update_device_type(&self, device_id: &str, device_type: DeviceType) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(entry) = sessions.get_mut(device_id) {
+            entry.info.device_type = device_type;
+        }
+    }
+
+    /// Record the health a relay reported in its heartbeat
+    pub async fn update_relay_status(&self, device_id: &str, status: RelayStatus) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(entry) = sessions.get_mut(device_id) {
+            entry.info.relay_status = Some(status);
+        }
+    }
+
+    /// Record the failsafe configuration a drone reported in its hello
+    pub async fn update_safety_config(&self, device_id: &str, config: SafetyConfig) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(entry) = sessions.get_mut(device_id) {
+            entry.info.safety_config = Some(config);
+        }
+    }
+
+    /// Record the link quality a drone reported in its telemetry
+    pub async fn record_quality(&self, device_id: &str, at_ms: u64, quality: ConnectionQuality) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(entry) = sessions.get_mut(device_id) {
+            entry.info.transport = quality.active_transport();
+            entry.quality.push(at_ms, quality);
+        }
+    }
+
+    /// Keep a drone's telemetry taken at `at_ms` if it's the latest so far
+    pub async fn record_telemetry(&self, device_id: &str, at_ms: u64, telemetry: &Telemetry) {
         let mut sessions = self.sessions.write().await;
         if let Some(entry) = sessions.get_mut(device_id) {
-            entry.info.state = state;
+            if entry.telemetry.as_ref().is_none_or(|(latest, _)| at_ms >= *latest) {
+                entry.telemetry = Some((at_ms, telemetry.clone()));
+            }
         }
     }
 
+    /// A drone's latest telemetry and when it was taken (ms since epoch)
+    pub async fn latest_telemetry(&self, device_id: &str) -> Option<(u64, Telemetry)> {
+        let sessions = self.sessions.read().await;
+        sessions.get(device_id)?.telemetry.clone()
+    }
+
+    /// Snapshot of a drone's recent link quality (for plotting and trends)
+    pub async fn quality_history(&self, device_id: &str) -> Option<QualityHistory> {
+        let sessions = self.sessions.read().await;
+        sessions.get(device_id).map(|e| e.quality.clone())
+    }
+
+    /// Link a drone is connected over (`Unknown` until its first telemetry)
+    pub async fn transport_of(&self, device_id: &str) -> Transport {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(device_id)
+            .map_or(Transport::Unknown, |e| e.info.transport)
+    }
+
+    /// Snapshot of all connected drones, ordered by device ID
+    pub async fn fleet_snapshot(&self) -> Vec<DroneInfo> {
+        let sessions = self.sessions.read().await;
+        let mut fleet: Vec<DroneInfo> = sessions
+            .values()
+            .map(|e| DroneInfo {
+                send_queue_depth: e.handle.queue_depth(),
+                ..e.info.clone()
+            })
+            .collect();
+        fleet.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        fleet
+    }
+
     /// Check for dead sessions (heartbeat timeout)
     pub async fn check_dead_sessions(&self) -> Vec<String> {
         let sessions = self.sessions.read().await;
@@ -110,12 +479,38 @@ impl SessionManager {
     }
 
     /// Remove dead sessions and return their IDs
+    ///
+    /// Each is published as `SessionDisconnected`, then as `DroneLost` with its
+    /// last known state and position.
     pub async fn remove_dead_sessions(&self) -> Vec<String> {
         let dead = self.check_dead_sessions().await;
         if !dead.is_empty() {
             let mut sessions = self.sessions.write().await;
-            for id in &dead {
-                sessions.remove(id);
+            let removed: Vec<(String, SessionEntry)> = dead
+                .iter()
+                .filter_map(|id| sessions.remove(id).map(|entry| (id.clone(), entry)))
+                .collect();
+            drop(sessions);
+
+            for (id, entry) in removed {
+                let last_seen = entry.info.last_heartbeat.elapsed().as_millis() as u64;
+                let lost = ServerEvent::DroneLost {
+                    device_id: id.clone(),
+                    state: entry.info.state,
+                    last_seen_ms: now_ms().saturating_sub(last_seen),
+                    last_position: entry
+                        .telemetry
+                        .as_ref()
+                        .and_then(|(_, telemetry)| telemetry.position)
+                        .filter(|p| p.latitude != 0.0 || p.longitude != 0.0),
+                };
+                self.detach(&id, entry).await;
+                self.close_telemetry_subscriptions(&id).await;
+                self.events.publish(ServerEvent::SessionDisconnected {
+                    device_id: id,
+                    reason: "heartbeat timeout".into(),
+                });
+                self.events.publish(lost);
             }
         }
         dead
@@ -132,3 +527,287 @@ impl Default for SessionManager {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::DroneSession;
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Open a loopback connection and wrap the server side in a session
+    async fn test_session(device_id: &str) -> (DroneSession, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+
+        let mut session = DroneSession::new(stream, addr);
+        session.handle.device_id = device_id.into();
+        (session, client)
+    }
+
+    fn test_telemetry(uptime_seconds: u64) -> Telemetry {
+        Telemetry {
+            uptime_seconds,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_safety_config_in_fleet_snapshot() {
+        let manager = SessionManager::new();
+        let (session_a, _client_a) = test_session("edge-002").await;
+        let (session_b, _client_b) = test_session("edge-001").await;
+        manager.register(session_a.get_handle()).await;
+        manager.register(session_b.get_handle()).await;
+
+        let config = SafetyConfig {
+            battery_critical_percent: 30,
+            ..SafetyConfig::from_defaults()
+        };
+        manager.update_safety_config("edge-001", config).await;
+
+        let fleet = manager.fleet_snapshot().await;
+        assert_eq!(fleet.len(), 2);
+        assert_eq!(fleet[0].device_id, "edge-001");
+        assert_eq!(fleet[0].safety_config, Some(config));
+        assert_eq!(fleet[1].safety_config, None);
+        assert_eq!(fleet[0].send_queue_depth, 0);
+    }
+
+    #[tokio::test]
+    async fn test_relay_status_in_fleet_snapshot() {
+        let manager = SessionManager::new();
+        let (drone, _drone_client) = test_session("edge-001").await;
+        let (relay, _relay_client) = test_session("relay-001").await;
+        manager.register(drone.get_handle()).await;
+        manager.register(relay.get_handle()).await;
+
+        manager.update_device_type("relay-001", DeviceType::DeviceRelay).await;
+        let status = RelayStatus {
+            connected_drones: 1,
+            buffered_bytes: 512,
+            ..Default::default()
+        };
+        manager.update_relay_status("relay-001", status.clone()).await;
+
+        let fleet = manager.fleet_snapshot().await;
+        assert_eq!(fleet[1].device_type, DeviceType::DeviceRelay);
+        assert_eq!(fleet[1].relay_status, Some(status));
+        assert_eq!(fleet[0].relay_status, None);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_device_telemetry() {
+        let manager = SessionManager::new();
+        let (session, _client) = test_session("edge-001").await;
+        manager.register(session.get_handle()).await;
+
+        let mut rx = manager.subscribe_device_telemetry("edge-001").await;
+        manager.publish_telemetry("edge-002", 0, &test_telemetry(7)).await;
+        manager.publish_telemetry("edge-001", 0, &test_telemetry(42)).await;
+
+        let received = rx.recv().await.expect("telemetry frame");
+        assert_eq!(received.uptime_seconds, 42);
+        assert!(rx.try_recv().is_err(), "other devices must not leak in");
+    }
+
+    #[tokio::test]
+    async fn test_subscription_before_connect_and_closed_on_disconnect() {
+        let manager = SessionManager::new();
+        let mut rx = manager.subscribe_device_telemetry("edge-001").await;
+
+        let (session, _client) = test_session("edge-001").await;
+        manager.register(session.get_handle()).await;
+        manager.publish_telemetry("edge-001", 0, &test_telemetry(1)).await;
+        assert_eq!(rx.recv().await.unwrap().uptime_seconds, 1);
+
+        manager.unregister("edge-001", session.addr()).await;
+        assert!(matches!(
+            rx.recv().await,
+            Err(broadcast::error::RecvError::Closed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stale_disconnect_after_reconnect_keeps_subscription() {
+        let manager = SessionManager::new();
+        let (old, _old_client) = test_session("edge-001").await;
+        manager.register(old.get_handle()).await;
+        let mut rx = manager.subscribe_device_telemetry("edge-001").await;
+
+        // Drone reconnects over another link before the old socket is torn down
+        let (new, _new_client) = test_session("edge-001").await;
+        manager.register(new.get_handle()).await;
+        manager.unregister("edge-001", old.addr()).await;
+
+        assert!(manager.get("edge-001").await.is_some());
+        manager.publish_telemetry("edge-001", 0, &test_telemetry(5)).await;
+        assert_eq!(rx.recv().await.unwrap().uptime_seconds, 5);
+    }
+
+    #[tokio::test]
+    async fn test_session_resumed_with_token() {
+        let manager = SessionManager::new();
+        let (old, _old_client) = test_session("edge-001").await;
+        manager.register(old.get_handle()).await;
+        let (token, resumed) = manager.resume_session("edge-001", "").await;
+        assert!(!resumed);
+        assert_eq!(token.len(), 32);
+        manager.update_state("edge-001", DroneState::DroneInMission).await;
+        manager.unregister("edge-001", old.addr()).await;
+
+        // Back over another link with the token: state carries over
+        let (new, _new_client) = test_session("edge-001").await;
+        manager.register(new.get_handle()).await;
+        assert_eq!(manager.resume_session("edge-001", &token).await, (token.clone(), true));
+        let info = manager.get_info("edge-001").await.unwrap();
+        assert_eq!(info.state, DroneState::DroneInMission);
+        assert_eq!(info.addr, new.addr());
+
+        // A restarted drone has no token and starts over
+        manager.unregister("edge-001", new.addr()).await;
+        let (fresh, _fresh_client) = test_session("edge-001").await;
+        manager.register(fresh.get_handle()).await;
+        let (new_token, resumed) = manager.resume_session("edge-001", "stale").await;
+        assert!(!resumed);
+        assert_ne!(new_token, token);
+        assert_eq!(manager.get_info("edge-001").await.unwrap().state, DroneState::DroneUnknown);
+    }
+
+    #[tokio::test]
+    async fn test_latest_telemetry_kept_per_session() {
+        let manager = SessionManager::new();
+        let (session, _client) = test_session("edge-001").await;
+        manager.register(session.get_handle()).await;
+        assert!(manager.latest_telemetry("edge-001").await.is_none());
+
+        manager.record_telemetry("edge-001", 2000, &test_telemetry(2)).await;
+        // A late sample from a replayed batch doesn't replace a newer one
+        manager.record_telemetry("edge-001", 1000, &test_telemetry(1)).await;
+        let (at_ms, telemetry) = manager.latest_telemetry("edge-001").await.unwrap();
+        assert_eq!((at_ms, telemetry.uptime_seconds), (2000, 2));
+        assert!(manager.latest_telemetry("edge-002").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_state_change_published_once() {
+        let manager = SessionManager::new();
+        let (session, _client) = test_session("edge-001").await;
+        manager.register(session.get_handle()).await;
+        let mut events = manager.events().subscribe();
+
+        manager.update_state("edge-001", DroneState::DroneInMission).await;
+        manager.update_state("edge-001", DroneState::DroneInMission).await;
+        assert!(matches!(
+            events.try_recv(),
+            Ok(ServerEvent::StateChanged {
+                from: DroneState::DroneUnknown,
+                to: DroneState::DroneInMission,
+                ..
+            })
+        ));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_state_divergence_after_completed_rth() {
+        let manager = SessionManager::new().with_divergence_grace(Duration::ZERO);
+        let (session, _client) = test_session("edge-001").await;
+        manager.register(session.get_handle()).await;
+        let divergences = |events: &mut broadcast::Receiver<ServerEvent>| {
+            std::iter::from_fn(|| events.try_recv().ok())
+                .filter(|event| matches!(event, ServerEvent::StateDivergence { .. }))
+                .count()
+        };
+        let mut events = manager.events().subscribe();
+
+        // Landing in place follows a return to home
+        manager.expect_command_state("edge-001", CommandType::CmdRth).await;
+        manager.update_state("edge-001", DroneState::DroneLanding).await;
+        manager.update_state("edge-001", DroneState::DroneInMission).await;
+        assert_eq!(divergences(&mut events), 0);
+
+        // Still flying the mission once its grace is up
+        manager.expect_command_state("edge-001", CommandType::CmdRth).await;
+        manager.update_state("edge-001", DroneState::DroneInMission).await;
+        assert!(matches!(
+            events.try_recv(),
+            Ok(ServerEvent::StateDivergence {
+                expected: DroneState::DroneReturningHome,
+                reported: DroneState::DroneInMission,
+                ..
+            })
+        ));
+        manager.update_state("edge-001", DroneState::DroneInMission).await;
+        assert_eq!(divergences(&mut events), 0);
+
+        // Within the grace the drone may still report its old state
+        let manager = SessionManager::new();
+        let (session, _client) = test_session("edge-001").await;
+        manager.register(session.get_handle()).await;
+        let mut events = manager.events().subscribe();
+        manager.expect_command_state("edge-001", CommandType::CmdRth).await;
+        manager.update_state("edge-001", DroneState::DroneInMission).await;
+        assert_eq!(divergences(&mut events), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dead_drone_published_lost_with_last_position() {
+        let manager = SessionManager::new();
+        let (session, _client) = test_session("edge-001").await;
+        manager.register(session.get_handle()).await;
+        manager.update_state("edge-001", DroneState::DroneInMission).await;
+        let telemetry = Telemetry {
+            position: Some(resqterra_shared::GpsPosition {
+                latitude: 47.1,
+                longitude: 8.5,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        manager.record_telemetry("edge-001", 1000, &telemetry).await;
+        let silent = Duration::from_millis(safety::HEARTBEAT_TIMEOUT_MS + 1000);
+        manager.sessions.write().await.get_mut("edge-001").unwrap().info.last_heartbeat =
+            Instant::now() - silent;
+        let mut events = manager.events().subscribe();
+
+        assert_eq!(manager.remove_dead_sessions().await, ["edge-001"]);
+        assert!(matches!(events.try_recv(), Ok(ServerEvent::SessionDisconnected { .. })));
+        match events.try_recv().unwrap() {
+            ServerEvent::DroneLost {
+                device_id,
+                state,
+                last_seen_ms,
+                last_position,
+            } => {
+                assert_eq!((device_id.as_str(), state), ("edge-001", DroneState::DroneInMission));
+                assert!(last_seen_ms <= now_ms() - silent.as_millis() as u64);
+                assert_eq!(last_position.unwrap().latitude, 47.1);
+            }
+            other => panic!("expected DroneLost, got {:?}", other),
+        }
+        assert!(manager.get_info("edge-001").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_quality_history_per_session() {
+        let manager = SessionManager::new().with_quality_history_len(2);
+        let (session, _client) = test_session("edge-001").await;
+        manager.register(session.get_handle()).await;
+
+        for (at_ms, latency_ms) in [(1000, 40), (2000, 55), (3000, 70)] {
+            let quality = ConnectionQuality {
+                active_transport: Transport::Bluetooth.into(),
+                latency_ms,
+                ..Default::default()
+            };
+            manager.record_quality("edge-001", at_ms, quality).await;
+        }
+
+        let history = manager.quality_history("edge-001").await.unwrap();
+        let latencies: Vec<u32> = history.samples().iter().map(|s| s.quality.latency_ms).collect();
+        assert_eq!(latencies, [55, 70]);
+        assert_eq!(manager.transport_of("edge-001").await, Transport::Bluetooth);
+        assert!(manager.quality_history("edge-002").await.is_none());
+    }
+}
@@ -10,4 +10,4 @@ mod manager;
 mod connection;
 
 pub use manager::SessionManager;
-pub use connection::{DroneSession, SessionHandle};
+pub use connection::{DroneSession, SessionStream};
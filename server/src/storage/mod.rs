@@ -0,0 +1,261 @@
+//! Telemetry history for flight-path reconstruction and incident analysis
+//!
+//! This module handles:
+//! - Writing every telemetry frame from the event bus (state, mission,
+//!   position, battery, link quality) to a `TelemetryStore`, in batches
+//! - Dropping samples older than the retention period
+//! - Reading a drone's samples back by time range
+//! - Keeping every detection report (no retention: they are the search
+//!   record), with replays from the drone's outbox stored once
+//!
+//! SQLite is the built-in store. Time-series databases (InfluxDB,
+//! Timescale) plug in by implementing `TelemetryStore`.
+
+mod sqlite;
+
+pub use sqlite::SqliteStore;
+
+use crate::events::{EventBus, ServerEvent};
+use resqterra_shared::{now_ms, DetectionReport, Telemetry};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Where the database goes unless `SERVER_TELEMETRY_DB` is set
+pub const DEFAULT_DB_PATH: &str = "/var/lib/resqterra/telemetry.db";
+
+/// Samples kept unless `SERVER_TELEMETRY_RETENTION_DAYS` is set
+const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+/// Samples are written at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// ... or as soon as this many are waiting
+const FLUSH_BATCH_LEN: usize = 500;
+
+/// How often samples past the retention period are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Most samples one history query returns
+pub const MAX_QUERY_ROWS: usize = 10_000;
+
+/// Where to store telemetry, and for how long
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    pub path: PathBuf,
+    /// None = keep everything
+    pub retention: Option<Duration>,
+}
+
+impl StorageConfig {
+    /// Read `SERVER_TELEMETRY_DB` and `SERVER_TELEMETRY_RETENTION_DAYS`
+    /// (0 = keep everything); None when the database is `off`
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("SERVER_TELEMETRY_DB").unwrap_or_else(|_| DEFAULT_DB_PATH.into());
+        if path == "off" {
+            return None;
+        }
+        let days = std::env::var("SERVER_TELEMETRY_RETENTION_DAYS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_RETENTION_DAYS);
+        Some(Self {
+            path: path.into(),
+            retention: (days > 0).then(|| Duration::from_secs(days * 86_400)),
+        })
+    }
+}
+
+/// One stored sample: the telemetry fields worth keeping, as received
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelemetryRecord {
+    pub device_id: String,
+    /// When the drone took the sample (ms since epoch)
+    pub timestamp_ms: u64,
+    /// State, mission, position, battery and link quality; the rest unset
+    pub telemetry: Telemetry,
+}
+
+impl TelemetryRecord {
+    pub fn new(device_id: &str, timestamp_ms: u64, telemetry: &Telemetry) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            timestamp_ms,
+            telemetry: Telemetry {
+                position: telemetry.position,
+                battery: telemetry.battery,
+                state: telemetry.state,
+                conn_quality: telemetry.conn_quality,
+                mission_id: telemetry.mission_id.clone(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Samples of one drone within `[from_ms, to_ms]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryQuery {
+    pub device_id: String,
+    pub from_ms: u64,
+    pub to_ms: u64,
+    /// The oldest `limit` samples in the range are returned
+    pub limit: usize,
+}
+
+/// One stored detection, as the drone reported it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectionRecord {
+    pub device_id: String,
+    pub report: DetectionReport,
+}
+
+/// Detections made within `[from_ms, to_ms]`, optionally of one drone or mission
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectionQuery {
+    pub device_id: Option<String>,
+    pub mission_id: Option<String>,
+    pub from_ms: u64,
+    pub to_ms: u64,
+    pub min_confidence: f32,
+    /// The oldest `limit` detections in the range are returned
+    pub limit: usize,
+}
+
+/// A telemetry backend
+///
+/// Calls block; `Storage` runs them off the async runtime.
+pub trait TelemetryStore: Send {
+    /// Store `records`, all or none
+    fn append(&mut self, records: &[TelemetryRecord]) -> anyhow::Result<()>;
+
+    /// Samples matching `query`, oldest first
+    fn query(&self, query: &HistoryQuery) -> anyhow::Result<Vec<TelemetryRecord>>;
+
+    /// Delete samples taken before `before_ms`, returning how many went
+    fn prune(&mut self, before_ms: u64) -> anyhow::Result<usize>;
+
+    /// Store a detection; false if the drone already sent one with its ID
+    fn append_detection(&mut self, record: &DetectionRecord) -> anyhow::Result<bool>;
+
+    /// Detections matching `query`, oldest first
+    fn detections(&self, query: &DetectionQuery) -> anyhow::Result<Vec<DetectionRecord>>;
+}
+
+/// A `TelemetryStore` shared by the writer and the query API
+pub struct Storage {
+    store: Arc<Mutex<Box<dyn TelemetryStore>>>,
+    retention: Option<Duration>,
+}
+
+impl Storage {
+    pub fn new(store: impl TelemetryStore + 'static, retention: Option<Duration>) -> Self {
+        Self {
+            store: Arc::new(Mutex::new(Box::new(store))),
+            retention,
+        }
+    }
+
+    /// Store the telemetry and detections published on `events` from now on, in the background
+    pub fn spawn_writer(self: &Arc<Self>, events: &EventBus) {
+        tokio::spawn(write_events(self.clone(), events.subscribe()));
+    }
+
+    pub async fn append(&self, records: Vec<TelemetryRecord>) -> anyhow::Result<()> {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || store.lock().unwrap().append(&records)).await?
+    }
+
+    /// Samples matching `query`, oldest first, at most `MAX_QUERY_ROWS`
+    pub async fn history(&self, mut query: HistoryQuery) -> anyhow::Result<Vec<TelemetryRecord>> {
+        query.limit = query.limit.min(MAX_QUERY_ROWS);
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || store.lock().unwrap().query(&query)).await?
+    }
+
+    pub async fn append_detection(&self, record: DetectionRecord) -> anyhow::Result<bool> {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || store.lock().unwrap().append_detection(&record)).await?
+    }
+
+    /// Detections matching `query`, oldest first, at most `MAX_QUERY_ROWS`
+    pub async fn detections(
+        &self,
+        mut query: DetectionQuery,
+    ) -> anyhow::Result<Vec<DetectionRecord>> {
+        query.limit = query.limit.min(MAX_QUERY_ROWS);
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || store.lock().unwrap().detections(&query)).await?
+    }
+
+    async fn prune(&self) -> anyhow::Result<usize> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        let before_ms = now_ms().saturating_sub(retention.as_millis() as u64);
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || store.lock().unwrap().prune(before_ms)).await?
+    }
+}
+
+async fn write_events(
+    storage: Arc<Storage>,
+    mut events: tokio::sync::broadcast::Receiver<ServerEvent>,
+) {
+    let mut batch = Vec::new();
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(ServerEvent::TelemetryReceived {
+                    device_id,
+                    timestamp_ms,
+                    telemetry,
+                }) => {
+                    batch.push(TelemetryRecord::new(&device_id, timestamp_ms, &telemetry));
+                    if batch.len() < FLUSH_BATCH_LEN {
+                        continue;
+                    }
+                }
+                // Rare and precious: stored right away rather than batched
+                Ok(ServerEvent::DetectionReceived { device_id, report }) => {
+                    let detection_id = report.detection_id.clone();
+                    let record = DetectionRecord { device_id, report };
+                    if let Err(e) = storage.append_detection(record).await {
+                        eprintln!("[STORAGE] Failed to store detection {}: {}", detection_id, e);
+                    }
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(n)) => {
+                    eprintln!("[STORAGE] Writer lagged, {} events not stored", n);
+                    continue;
+                }
+                Err(RecvError::Closed) => {
+                    write_batch(&storage, &mut batch).await;
+                    return;
+                }
+            },
+            _ = flush.tick() => {}
+            _ = prune.tick() => match storage.prune().await {
+                Ok(0) => {}
+                Ok(n) => println!("[STORAGE] Pruned {} samples past retention", n),
+                Err(e) => eprintln!("[STORAGE] Pruning failed: {}", e),
+            },
+        }
+        write_batch(&storage, &mut batch).await;
+    }
+}
+
+async fn write_batch(storage: &Storage, batch: &mut Vec<TelemetryRecord>) {
+    if batch.is_empty() {
+        return;
+    }
+    let records = std::mem::take(batch);
+    let len = records.len();
+    if let Err(e) = storage.append(records).await {
+        eprintln!("[STORAGE] Failed to store {} samples: {}", len, e);
+    }
+}
@@ -0,0 +1,424 @@
+//! SQLite telemetry store
+//!
+//! One row per sample in a `telemetry` table, indexed by drone and time,
+//! and one per detection in `detections`, keyed by drone and detection ID.
+//! Enums are stored by their proto names so the file reads well in any
+//! SQLite tool.
+
+use super::{DetectionQuery, DetectionRecord, HistoryQuery, TelemetryRecord, TelemetryStore};
+use resqterra_shared::{
+    BatteryStatus, ConnectionQuality, DetectionReport, DroneState, GpsPosition, Telemetry,
+    Transport,
+};
+use rusqlite::{params, Connection, Row};
+use std::fs;
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS telemetry (
+        device_id TEXT NOT NULL,
+        timestamp_ms INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        mission_id TEXT NOT NULL,
+        latitude REAL,
+        longitude REAL,
+        altitude_m REAL,
+        heading_deg REAL,
+        ground_speed_mps REAL,
+        satellites INTEGER,
+        hdop REAL,
+        battery_voltage REAL,
+        battery_current REAL,
+        battery_percent INTEGER,
+        battery_seconds INTEGER,
+        transport TEXT,
+        rssi_dbm INTEGER,
+        latency_ms INTEGER,
+        packet_loss_percent REAL,
+        jitter_ms INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS telemetry_device_time ON telemetry (device_id, timestamp_ms);
+    CREATE TABLE IF NOT EXISTS detections (
+        device_id TEXT NOT NULL,
+        detection_id TEXT NOT NULL,
+        mission_id TEXT NOT NULL,
+        label TEXT NOT NULL,
+        confidence REAL NOT NULL,
+        sensor_type TEXT NOT NULL,
+        latitude REAL,
+        longitude REAL,
+        altitude_m REAL,
+        heading_deg REAL,
+        detected_at_ms INTEGER NOT NULL,
+        thumbnail_ref TEXT NOT NULL,
+        PRIMARY KEY (device_id, detection_id)
+    );
+    CREATE INDEX IF NOT EXISTS detections_time ON detections (detected_at_ms);
+";
+
+const DETECTION_COLUMNS: &str = "device_id, detection_id, mission_id, label, confidence, \
+    sensor_type, latitude, longitude, altitude_m, heading_deg, detected_at_ms, thumbnail_ref";
+
+const COLUMNS: &str = "device_id, timestamp_ms, state, mission_id, \
+    latitude, longitude, altitude_m, heading_deg, ground_speed_mps, satellites, hdop, \
+    battery_voltage, battery_current, battery_percent, battery_seconds, \
+    transport, rssi_dbm, latency_ms, packet_loss_percent, jitter_ms";
+
+/// Telemetry in a SQLite database file
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Open (or create) the database at `path`
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        // Readers (the query API) don't block the writer
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::init(conn)
+    }
+
+    /// A database that lives as long as the store, for tests
+    #[cfg(test)]
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> anyhow::Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+}
+
+impl TelemetryStore for SqliteStore {
+    fn append(&mut self, records: &[TelemetryRecord]) -> anyhow::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let sql = format!(
+                "INSERT INTO telemetry ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, \
+                 ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
+                COLUMNS
+            );
+            let mut insert = tx.prepare_cached(&sql)?;
+            for record in records {
+                let tel = &record.telemetry;
+                let pos = tel.position;
+                let bat = tel.battery;
+                let quality = tel.conn_quality;
+                insert.execute(params![
+                    record.device_id,
+                    record.timestamp_ms,
+                    tel.state().as_str_name(),
+                    tel.mission_id,
+                    pos.map(|p| p.latitude),
+                    pos.map(|p| p.longitude),
+                    pos.map(|p| p.altitude_m),
+                    pos.map(|p| p.heading_deg),
+                    pos.map(|p| p.ground_speed_mps),
+                    pos.map(|p| p.satellites),
+                    pos.map(|p| p.hdop),
+                    bat.map(|b| b.voltage),
+                    bat.map(|b| b.current),
+                    bat.map(|b| b.remaining_percent),
+                    bat.map(|b| b.remaining_seconds),
+                    quality.map(|q| q.active_transport().as_str_name()),
+                    quality.map(|q| q.rssi_dbm),
+                    quality.map(|q| q.latency_ms),
+                    quality.map(|q| q.packet_loss_percent),
+                    quality.map(|q| q.jitter_ms),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn query(&self, query: &HistoryQuery) -> anyhow::Result<Vec<TelemetryRecord>> {
+        let sql = format!(
+            "SELECT {} FROM telemetry WHERE device_id = ?1 AND timestamp_ms BETWEEN ?2 AND ?3 \
+             ORDER BY timestamp_ms LIMIT ?4",
+            COLUMNS
+        );
+        let mut select = self.conn.prepare_cached(&sql)?;
+        let rows = select.query_map(
+            params![query.device_id, query.from_ms, query.to_ms, query.limit as u64],
+            record_from_row,
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn prune(&mut self, before_ms: u64) -> anyhow::Result<usize> {
+        let deleted = self
+            .conn
+            .execute("DELETE FROM telemetry WHERE timestamp_ms < ?1", params![before_ms])?;
+        Ok(deleted)
+    }
+
+    fn append_detection(&mut self, record: &DetectionRecord) -> anyhow::Result<bool> {
+        // A report resent from the drone's outbox is already here
+        let sql = format!(
+            "INSERT OR IGNORE INTO detections ({}) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            DETECTION_COLUMNS
+        );
+        let report = &record.report;
+        let pos = report.position;
+        let inserted = self.conn.prepare_cached(&sql)?.execute(params![
+            record.device_id,
+            report.detection_id,
+            report.mission_id,
+            report.label,
+            report.confidence,
+            report.sensor_type,
+            pos.map(|p| p.latitude),
+            pos.map(|p| p.longitude),
+            pos.map(|p| p.altitude_m),
+            pos.map(|p| p.heading_deg),
+            report.detected_at_ms,
+            report.thumbnail_ref,
+        ])?;
+        Ok(inserted > 0)
+    }
+
+    fn detections(&self, query: &DetectionQuery) -> anyhow::Result<Vec<DetectionRecord>> {
+        let sql = format!(
+            "SELECT {} FROM detections \
+             WHERE (?1 IS NULL OR device_id = ?1) AND (?2 IS NULL OR mission_id = ?2) \
+             AND detected_at_ms BETWEEN ?3 AND ?4 AND confidence >= ?5 \
+             ORDER BY detected_at_ms LIMIT ?6",
+            DETECTION_COLUMNS
+        );
+        let mut select = self.conn.prepare_cached(&sql)?;
+        let rows = select.query_map(
+            params![
+                query.device_id,
+                query.mission_id,
+                query.from_ms,
+                query.to_ms,
+                query.min_confidence,
+                query.limit as u64
+            ],
+            detection_from_row,
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+fn detection_from_row(row: &Row) -> rusqlite::Result<DetectionRecord> {
+    let position = match (row.get("latitude")?, row.get("longitude")?) {
+        (Some(latitude), Some(longitude)) => Some(GpsPosition {
+            latitude,
+            longitude,
+            altitude_m: row.get::<_, Option<_>>("altitude_m")?.unwrap_or_default(),
+            heading_deg: row.get::<_, Option<_>>("heading_deg")?.unwrap_or_default(),
+            ..Default::default()
+        }),
+        _ => None,
+    };
+    Ok(DetectionRecord {
+        device_id: row.get("device_id")?,
+        report: DetectionReport {
+            detection_id: row.get("detection_id")?,
+            mission_id: row.get("mission_id")?,
+            label: row.get("label")?,
+            confidence: row.get("confidence")?,
+            sensor_type: row.get("sensor_type")?,
+            position,
+            detected_at_ms: row.get("detected_at_ms")?,
+            thumbnail_ref: row.get("thumbnail_ref")?,
+        },
+    })
+}
+
+fn record_from_row(row: &Row) -> rusqlite::Result<TelemetryRecord> {
+    let state: String = row.get("state")?;
+    let position = match (row.get("latitude")?, row.get("longitude")?) {
+        (Some(latitude), Some(longitude)) => Some(GpsPosition {
+            latitude,
+            longitude,
+            altitude_m: row.get::<_, Option<_>>("altitude_m")?.unwrap_or_default(),
+            heading_deg: row.get::<_, Option<_>>("heading_deg")?.unwrap_or_default(),
+            ground_speed_mps: row.get::<_, Option<_>>("ground_speed_mps")?.unwrap_or_default(),
+            satellites: row.get::<_, Option<_>>("satellites")?.unwrap_or_default(),
+            hdop: row.get::<_, Option<_>>("hdop")?.unwrap_or_default(),
+        }),
+        _ => None,
+    };
+    let battery = row
+        .get::<_, Option<u32>>("battery_percent")?
+        .map(|remaining_percent| -> rusqlite::Result<_> {
+            Ok(BatteryStatus {
+                voltage: row.get::<_, Option<_>>("battery_voltage")?.unwrap_or_default(),
+                current: row.get::<_, Option<_>>("battery_current")?.unwrap_or_default(),
+                remaining_percent,
+                remaining_seconds: row.get::<_, Option<_>>("battery_seconds")?.unwrap_or_default(),
+            })
+        })
+        .transpose()?;
+    let conn_quality = row
+        .get::<_, Option<String>>("transport")?
+        .map(|transport| -> rusqlite::Result<_> {
+            let transport = Transport::from_str_name(&transport).unwrap_or(Transport::Unknown);
+            Ok(ConnectionQuality {
+                active_transport: transport.into(),
+                rssi_dbm: row.get::<_, Option<_>>("rssi_dbm")?.unwrap_or_default(),
+                latency_ms: row.get::<_, Option<_>>("latency_ms")?.unwrap_or_default(),
+                packet_loss_percent: row
+                    .get::<_, Option<_>>("packet_loss_percent")?
+                    .unwrap_or_default(),
+                jitter_ms: row.get::<_, Option<_>>("jitter_ms")?.unwrap_or_default(),
+            })
+        })
+        .transpose()?;
+    Ok(TelemetryRecord {
+        device_id: row.get("device_id")?,
+        timestamp_ms: row.get("timestamp_ms")?,
+        telemetry: Telemetry {
+            state: DroneState::from_str_name(&state)
+                .unwrap_or(DroneState::DroneUnknown)
+                .into(),
+            mission_id: row.get("mission_id")?,
+            position,
+            battery,
+            conn_quality,
+            ..Default::default()
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(device_id: &str, timestamp_ms: u64, latitude: f64) -> TelemetryRecord {
+        let telemetry = Telemetry {
+            state: DroneState::DroneInMission.into(),
+            mission_id: "m1".into(),
+            position: Some(GpsPosition {
+                latitude,
+                longitude: 8.5,
+                altitude_m: 40.0,
+                satellites: 12,
+                ..Default::default()
+            }),
+            battery: Some(BatteryStatus {
+                voltage: 15.2,
+                remaining_percent: 80,
+                ..Default::default()
+            }),
+            uptime_seconds: 99,
+            ..Default::default()
+        };
+        TelemetryRecord::new(device_id, timestamp_ms, &telemetry)
+    }
+
+    #[test]
+    fn test_records_round_trip_by_device_and_time() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let records = [
+            record("edge-001", 1000, 47.0),
+            record("edge-002", 1500, 46.0),
+            record("edge-001", 2000, 47.1),
+            record("edge-001", 3000, 47.2),
+        ];
+        store.append(&records).unwrap();
+
+        let query = HistoryQuery {
+            device_id: "edge-001".into(),
+            from_ms: 1000,
+            to_ms: 2500,
+            limit: 10,
+        };
+        let found = store.query(&query).unwrap();
+        assert_eq!(found, [records[0].clone(), records[2].clone()]);
+        // Only the stored fields come back
+        assert_eq!(found[0].telemetry.uptime_seconds, 0);
+        assert!(found[0].telemetry.conn_quality.is_none());
+
+        let first = store.query(&HistoryQuery { limit: 1, ..query }).unwrap();
+        assert_eq!(first.len(), 1);
+    }
+
+    #[test]
+    fn test_prune_drops_old_samples() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let records = [record("edge-001", 1000, 47.0), record("edge-001", 2000, 47.1)];
+        store.append(&records).unwrap();
+
+        assert_eq!(store.prune(1500).unwrap(), 1);
+        let query = HistoryQuery {
+            device_id: "edge-001".into(),
+            from_ms: 0,
+            to_ms: u64::MAX >> 1,
+            limit: 10,
+        };
+        assert_eq!(store.query(&query).unwrap(), [records[1].clone()]);
+    }
+
+    fn detection(
+        device_id: &str,
+        detection_id: &str,
+        detected_at_ms: u64,
+        confidence: f32,
+    ) -> DetectionRecord {
+        DetectionRecord {
+            device_id: device_id.into(),
+            report: DetectionReport {
+                detection_id: detection_id.into(),
+                mission_id: "m1".into(),
+                label: "person".into(),
+                confidence,
+                sensor_type: "THERMAL".into(),
+                position: Some(GpsPosition {
+                    latitude: 47.1,
+                    longitude: 8.5,
+                    altitude_m: 40.0,
+                    heading_deg: 90.0,
+                    ..Default::default()
+                }),
+                detected_at_ms,
+                thumbnail_ref: format!("s3://frames/{}.jpg", detection_id),
+            },
+        }
+    }
+
+    #[test]
+    fn test_detections_stored_once_and_filtered() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let seen = detection("edge-001", "d1", 1000, 0.9);
+        assert!(store.append_detection(&seen).unwrap());
+        // Replayed from the outbox after a reconnect
+        assert!(!store.append_detection(&seen).unwrap());
+        store.append_detection(&detection("edge-001", "d2", 2000, 0.3)).unwrap();
+        store.append_detection(&detection("edge-002", "d1", 3000, 0.8)).unwrap();
+
+        let query = DetectionQuery {
+            device_id: None,
+            mission_id: Some("m1".into()),
+            from_ms: 0,
+            to_ms: 5000,
+            min_confidence: 0.5,
+            limit: 10,
+        };
+        let found = store.detections(&query).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0], seen);
+        assert_eq!(found[1].device_id, "edge-002");
+
+        let one_drone = DetectionQuery {
+            device_id: Some("edge-001".into()),
+            min_confidence: 0.0,
+            ..query
+        };
+        let ids: Vec<_> = store
+            .detections(&one_drone)
+            .unwrap()
+            .into_iter()
+            .map(|d| d.report.detection_id)
+            .collect();
+        assert_eq!(ids, ["d1", "d2"]);
+    }
+}
@@ -1,6 +1,27 @@
 use std::io::Result;
+use std::path::PathBuf;
 
 fn main() -> Result<()> {
-    prost_build::compile_protos(&["proto/resqterra.proto"], &["proto/"])?;
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").expect("OUT_DIR set by cargo"));
+    let mut config = prost_build::Config::new();
+    config
+        // Exposed as `schema::proto_descriptor()` for non-Rust clients
+        .file_descriptor_set_path(out_dir.join("resqterra_descriptor.bin"))
+        // Every proto map is a BTreeMap, std or not: no_std + alloc has no
+        // HashMap, and a map type that changed with the `std` feature would
+        // break no_std users as soon as anything in the build enabled std
+        .btree_map(["."])
+        // Keep the envelope small; deltas and batches are the largest payloads
+        .boxed(".resqterra.Envelope.payload.telemetry_delta")
+        .boxed(".resqterra.Envelope.payload.telemetry_batch");
+    let protos = ["proto/resqterra.proto", "proto/fleet.proto"];
+
+    // `FleetService` server and client, on top of the same messages
+    #[cfg(feature = "grpc")]
+    tonic_build::configure()
+        .build_transport(false)
+        .compile_protos_with_config(config, &protos, &["proto/"])?;
+    #[cfg(not(feature = "grpc"))]
+    config.compile_protos(&protos, &["proto/"])?;
     Ok(())
 }
@@ -0,0 +1,541 @@
+//! Envelope signing and verification
+//!
+//! Envelopes are signed with HMAC-SHA256 over a pre-shared key; the tag goes
+//! in `Envelope.signature` and covers the encoded envelope as it is on the
+//! wire, except the signature itself and the header's `hop_count`, which
+//! relays bump on the way. Fields the receiver doesn't know are covered as
+//! they are, so a newer peer's signed messages still verify on an older one.
+//!
+//! Signing every frame costs CPU on the edge, so a [`SigningPolicy`] picks the
+//! message types that are signed and verified. Safety-critical commands are
+//! covered whatever the policy says.
+//!
+//! Each drone can have its own key: the server keeps them in [`DeviceKeys`]
+//! and picks the session's signer once the drone has identified itself.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use prost::encoding::{encode_key, encode_varint, WireType};
+use prost::Message;
+use thiserror::Error;
+
+use crate::codec::{next_field, ENVELOPE_HEADER_TAG, HEADER_HOP_COUNT_TAG};
+use crate::{envelope::Payload, CommandType, DeviceId, Envelope, MessageType};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// `Envelope.signature`
+const ENVELOPE_SIGNATURE_TAG: u32 = 9;
+
+/// Commands that are always signed and verified
+///
+/// `CMD_MISSION_START` is included because it arms the drone.
+pub const ALWAYS_SIGNED_COMMANDS: [CommandType; 3] = [
+    CommandType::CmdEmergencyStop,
+    CommandType::CmdRth,
+    CommandType::CmdMissionStart,
+];
+
+/// Errors from signing configuration and verification
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    #[error("Unsigned {0:?} rejected")]
+    Unsigned(MessageType),
+
+    #[error("Bad signature on {0:?}")]
+    BadSignature(MessageType),
+
+    #[error("Unknown message type in signing policy: {0}")]
+    UnknownMessageType(String),
+
+    #[error("Invalid device key entry (expected device_id=key): {0}")]
+    InvalidKeyEntry(String),
+}
+
+/// Message type of an envelope, taken from its payload
+///
+/// The header's `msg_type` is not covered by the policy check, so a sender
+/// can't dodge verification by mislabelling a command.
+pub fn payload_type(envelope: &Envelope) -> MessageType {
+    match envelope.payload {
+        Some(Payload::Telemetry(_)) => MessageType::MsgTelemetry,
+        Some(Payload::Command(_)) => MessageType::MsgCommand,
+        Some(Payload::Ack(_)) => MessageType::MsgAck,
+        Some(Payload::Heartbeat(_)) => MessageType::MsgHeartbeat,
+        Some(Payload::SensorData(_)) => MessageType::MsgSensorData,
+        Some(Payload::Hello(_)) => MessageType::MsgHello,
+        Some(Payload::TelemetryDelta(_)) => MessageType::MsgTelemetryDelta,
+        Some(Payload::TelemetryBatch(_)) => MessageType::MsgTelemetryBatch,
+        Some(Payload::StatusUpdate(_)) => MessageType::MsgStatusUpdate,
+        Some(Payload::HeartbeatAck(_)) => MessageType::MsgHeartbeatAck,
+        Some(Payload::MissionProgress(_)) => MessageType::MsgMissionProgress,
+        Some(Payload::DetectionReport(_)) => MessageType::MsgDetectionReport,
+        Some(Payload::BlobStart(_)) => MessageType::MsgBlobStart,
+        Some(Payload::BlobChunk(_)) => MessageType::MsgBlobChunk,
+        Some(Payload::BlobEnd(_)) => MessageType::MsgBlobEnd,
+        Some(Payload::BlobStatus(_)) => MessageType::MsgBlobStatus,
+        None => MessageType::MsgUnknown,
+    }
+}
+
+/// Which messages must carry a valid signature
+///
+/// The default covers only [`ALWAYS_SIGNED_COMMANDS`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SigningPolicy {
+    message_types: Vec<MessageType>,
+}
+
+impl SigningPolicy {
+    /// Sign and verify every message
+    pub fn all() -> Self {
+        Self {
+            message_types: Vec::from([
+                MessageType::MsgTelemetry,
+                MessageType::MsgCommand,
+                MessageType::MsgAck,
+                MessageType::MsgHeartbeat,
+                MessageType::MsgSensorData,
+                MessageType::MsgHello,
+                MessageType::MsgTelemetryDelta,
+                MessageType::MsgTelemetryBatch,
+                MessageType::MsgStatusUpdate,
+                MessageType::MsgHeartbeatAck,
+                MessageType::MsgMissionProgress,
+                MessageType::MsgDetectionReport,
+                MessageType::MsgBlobStart,
+                MessageType::MsgBlobChunk,
+                MessageType::MsgBlobEnd,
+                MessageType::MsgBlobStatus,
+            ]),
+        }
+    }
+
+    /// Also sign and verify every message of `message_type`
+    pub fn with_type(mut self, message_type: MessageType) -> Self {
+        if !self.message_types.contains(&message_type) {
+            self.message_types.push(message_type);
+        }
+        self
+    }
+
+    /// Parse a comma-separated list of message types, e.g. `"command,ack"`
+    ///
+    /// Names are `MessageType` values without the `MSG_` prefix, in any case;
+    /// `"all"` selects every type and an empty list only the safety commands.
+    pub fn parse(list: &str) -> Result<Self, AuthError> {
+        let mut policy = Self::default();
+        for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            if name.eq_ignore_ascii_case("all") {
+                return Ok(Self::all());
+            }
+            let full = alloc::format!("MSG_{}", name.to_ascii_uppercase());
+            match MessageType::from_str_name(&full) {
+                Some(t) if t != MessageType::MsgUnknown => policy = policy.with_type(t),
+                _ => return Err(AuthError::UnknownMessageType(name.to_string())),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Whether `envelope` must be signed
+    pub fn requires_signature(&self, envelope: &Envelope) -> bool {
+        if let Some(Payload::Command(command)) = &envelope.payload {
+            if ALWAYS_SIGNED_COMMANDS
+                .iter()
+                .any(|&t| command.cmd_type == t as i32)
+            {
+                return true;
+            }
+        }
+        self.message_types.contains(&payload_type(envelope))
+    }
+}
+
+/// Signs and verifies envelopes with a pre-shared key, per a [`SigningPolicy`]
+#[derive(Clone)]
+pub struct EnvelopeSigner {
+    key: Vec<u8>,
+    policy: SigningPolicy,
+}
+
+impl core::fmt::Debug for EnvelopeSigner {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // Never print the key
+        f.debug_struct("EnvelopeSigner")
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EnvelopeSigner {
+    /// Create a signer for `key` enforcing `policy`
+    pub fn new(key: impl Into<Vec<u8>>, policy: SigningPolicy) -> Self {
+        Self {
+            key: key.into(),
+            policy,
+        }
+    }
+
+    /// Read the key from `RESQTERRA_SIGNING_KEY` and the signed message types
+    /// from `RESQTERRA_SIGNED_TYPES` (see [`SigningPolicy::parse`])
+    ///
+    /// `Ok(None)` when no key is set: signing is off.
+    #[cfg(feature = "std")]
+    pub fn from_env() -> Result<Option<Self>, AuthError> {
+        let Some(key) = env_var("RESQTERRA_SIGNING_KEY") else {
+            return Ok(None);
+        };
+        Ok(Some(Self::new(key, policy_from_env()?)))
+    }
+
+    /// The policy this signer enforces
+    pub fn policy(&self) -> &SigningPolicy {
+        &self.policy
+    }
+
+    /// MAC over the bytes of an encoded envelope a signature covers, or
+    /// `None` if they don't parse
+    fn mac(&self, encoded: &[u8]) -> Option<HmacSha256> {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(&signed_bytes(encoded)?);
+        Some(mac)
+    }
+
+    /// Sign `envelope` if the policy covers it; returns whether it was signed
+    pub fn sign(&self, envelope: &mut Envelope) -> bool {
+        if !self.policy.requires_signature(envelope) {
+            return false;
+        }
+        let mac = self
+            .mac(&envelope.encode_to_vec())
+            .expect("prost output parses");
+        envelope.signature = mac.finalize().into_bytes().to_vec();
+        true
+    }
+
+    /// Check the signature of an envelope the policy covers
+    ///
+    /// Envelopes outside the policy pass without being checked. The envelope
+    /// is re-encoded, which loses fields this build doesn't know: for one
+    /// received from a peer use [`Self::verify_encoded`].
+    pub fn verify(&self, envelope: &Envelope) -> Result<(), AuthError> {
+        self.verify_encoded(envelope, &envelope.encode_to_vec())
+    }
+
+    /// Check the signature of `envelope`, decoded from `encoded`, against the
+    /// bytes as they came off the wire (see [`crate::codec::decode_raw`])
+    pub fn verify_encoded(&self, envelope: &Envelope, encoded: &[u8]) -> Result<(), AuthError> {
+        if !self.policy.requires_signature(envelope) {
+            return Ok(());
+        }
+        if envelope.signature.is_empty() {
+            return Err(AuthError::Unsigned(payload_type(envelope)));
+        }
+        self.mac(encoded)
+            .and_then(|mac| mac.verify_slice(&envelope.signature).ok())
+            .ok_or(AuthError::BadSignature(payload_type(envelope)))
+    }
+}
+
+/// What a signature covers: the encoded envelope without its signature and
+/// without the header's `hop_count`, every other field kept as it is
+///
+/// For an envelope with only the fields this build knows, this is the same as
+/// the encoded header (hop count 0) followed by the payload.
+fn signed_bytes(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut signed = Vec::with_capacity(encoded.len());
+    let mut buf = encoded;
+    while !buf.is_empty() {
+        let field = next_field(&mut buf).ok()?;
+        match field.tag {
+            ENVELOPE_SIGNATURE_TAG => {}
+            ENVELOPE_HEADER_TAG if field.wire_type == WireType::LengthDelimited => {
+                let mut header = Vec::with_capacity(field.value.len());
+                let mut buf = field.value;
+                while !buf.is_empty() {
+                    let field = next_field(&mut buf).ok()?;
+                    if field.tag != HEADER_HOP_COUNT_TAG {
+                        header.extend_from_slice(field.raw);
+                    }
+                }
+                encode_key(ENVELOPE_HEADER_TAG, WireType::LengthDelimited, &mut signed);
+                encode_varint(header.len() as u64, &mut signed);
+                signed.extend_from_slice(&header);
+            }
+            _ => signed.extend_from_slice(field.raw),
+        }
+    }
+    Some(signed)
+}
+
+/// Pre-shared keys per device, for the receiving side of many drones
+///
+/// Devices without their own key use the fallback key, if there is one.
+#[derive(Clone, Default)]
+pub struct DeviceKeys {
+    keys: BTreeMap<String, Vec<u8>>,
+    fallback: Option<Vec<u8>>,
+    policy: SigningPolicy,
+}
+
+impl core::fmt::Debug for DeviceKeys {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // Never print the keys
+        f.debug_struct("DeviceKeys")
+            .field("devices", &self.keys.keys().collect::<Vec<_>>())
+            .field("fallback", &self.fallback.is_some())
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl DeviceKeys {
+    /// No keys yet; signers will enforce `policy`
+    pub fn new(policy: SigningPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Key for one device (IDs are normalized, so case doesn't matter)
+    pub fn with_key(mut self, device_id: &DeviceId, key: impl Into<Vec<u8>>) -> Self {
+        self.keys.insert(String::from(device_id), key.into());
+        self
+    }
+
+    /// Key for devices that have none of their own
+    pub fn with_fallback(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.fallback = Some(key.into());
+        self
+    }
+
+    /// Parse a comma-separated list of `device_id=key` entries
+    pub fn parse(list: &str, policy: SigningPolicy) -> Result<Self, AuthError> {
+        let mut keys = Self::new(policy);
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let invalid = || AuthError::InvalidKeyEntry(entry.to_string());
+            let (device_id, key) = entry.split_once('=').ok_or_else(invalid)?;
+            let device_id = DeviceId::parse(device_id.trim()).map_err(|_| invalid())?;
+            if key.is_empty() {
+                return Err(invalid());
+            }
+            keys = keys.with_key(&device_id, key);
+        }
+        Ok(keys)
+    }
+
+    /// Read per-device keys from `RESQTERRA_DEVICE_KEYS` (see [`Self::parse`]),
+    /// the fallback from `RESQTERRA_SIGNING_KEY` and the signed message types
+    /// from `RESQTERRA_SIGNED_TYPES`
+    ///
+    /// `Ok(None)` when neither key variable is set: signing is off.
+    #[cfg(feature = "std")]
+    pub fn from_env() -> Result<Option<Self>, AuthError> {
+        let device_keys = env_var("RESQTERRA_DEVICE_KEYS");
+        let fallback = env_var("RESQTERRA_SIGNING_KEY");
+        if device_keys.is_none() && fallback.is_none() {
+            return Ok(None);
+        }
+        let mut keys = Self::parse(device_keys.as_deref().unwrap_or(""), policy_from_env()?)?;
+        if let Some(fallback) = fallback {
+            keys = keys.with_fallback(fallback);
+        }
+        Ok(Some(keys))
+    }
+
+    /// The policy every signer enforces
+    pub fn policy(&self) -> &SigningPolicy {
+        &self.policy
+    }
+
+    /// Signer for `device_id`, or `None` if it has no key and there is no fallback
+    pub fn signer_for(&self, device_id: &str) -> Option<EnvelopeSigner> {
+        let key = DeviceId::parse(device_id)
+            .ok()
+            .and_then(|id| self.keys.get(id.as_str()))
+            .or(self.fallback.as_ref())?;
+        Some(EnvelopeSigner::new(key.clone(), self.policy.clone()))
+    }
+}
+
+/// Non-empty value of an environment variable
+#[cfg(feature = "std")]
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// Signed message types from `RESQTERRA_SIGNED_TYPES` (default policy if unset)
+#[cfg(feature = "std")]
+fn policy_from_env() -> Result<SigningPolicy, AuthError> {
+    match std::env::var("RESQTERRA_SIGNED_TYPES") {
+        Ok(list) => SigningPolicy::parse(&list),
+        Err(_) => Ok(SigningPolicy::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codec, Command, Header, Telemetry};
+    use bytes::BytesMut;
+
+    fn command(cmd_type: CommandType) -> Envelope {
+        Envelope {
+            header: Some(Header::new("server", MessageType::MsgCommand, 1)),
+            payload: Some(Payload::Command(Command {
+                command_id: 42,
+                cmd_type: cmd_type.into(),
+                ..Default::default()
+            })),
+            signature: Vec::new(),
+        }
+    }
+
+    fn telemetry() -> Envelope {
+        Envelope {
+            header: Some(Header::new("edge-001", MessageType::MsgTelemetry, 2)),
+            payload: Some(Payload::Telemetry(Telemetry::default())),
+            signature: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_unsigned_emergency_stop_rejected_unsigned_telemetry_passes() {
+        let signer = EnvelopeSigner::new("secret", SigningPolicy::default());
+
+        assert_eq!(
+            signer.verify(&command(CommandType::CmdEmergencyStop)),
+            Err(AuthError::Unsigned(MessageType::MsgCommand))
+        );
+        assert_eq!(signer.verify(&telemetry()), Ok(()));
+        // Other commands are only covered when the policy lists them
+        assert_eq!(signer.verify(&command(CommandType::CmdStatusRequest)), Ok(()));
+        let strict = EnvelopeSigner::new("secret", SigningPolicy::parse("command").unwrap());
+        assert!(strict.verify(&command(CommandType::CmdStatusRequest)).is_err());
+    }
+
+    #[test]
+    fn test_signed_command_survives_codec_and_tampering_is_caught() {
+        let signer = EnvelopeSigner::new("secret", SigningPolicy::default());
+        let mut envelope = command(CommandType::CmdRth);
+        assert!(signer.sign(&mut envelope));
+
+        let encoded = codec::encode(&envelope).unwrap();
+        let decoded = codec::decode(&mut BytesMut::from(&encoded[..]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(signer.verify(&decoded), Ok(()));
+
+        // Relays count themselves in without breaking the signature
+        let mut relayed = decoded.clone();
+        relayed.header.as_mut().unwrap().hop_count = 2;
+        assert_eq!(signer.verify(&relayed), Ok(()));
+
+        // A different key, or a changed command, fails
+        let other = EnvelopeSigner::new("other", SigningPolicy::default());
+        assert_eq!(
+            other.verify(&decoded),
+            Err(AuthError::BadSignature(MessageType::MsgCommand))
+        );
+        let mut tampered = decoded;
+        if let Some(Payload::Command(cmd)) = &mut tampered.payload {
+            cmd.cmd_type = CommandType::CmdEmergencyStop.into();
+        }
+        assert!(signer.verify(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_signature_covers_fields_this_build_does_not_know() {
+        let signer = EnvelopeSigner::new("secret", SigningPolicy::default());
+        let mut envelope = command(CommandType::CmdEmergencyStop);
+        envelope.header.as_mut().unwrap().hop_count = 1;
+
+        // Same bytes signed as before wire-level signing: header with hop
+        // count 0, then payload
+        let mut legacy = Vec::new();
+        let header = Header {
+            hop_count: 0,
+            ..envelope.header.clone().unwrap()
+        };
+        prost::encoding::message::encode(ENVELOPE_HEADER_TAG, &header, &mut legacy);
+        envelope.payload.as_ref().unwrap().encode(&mut legacy);
+        assert_eq!(signed_bytes(&envelope.encode_to_vec()).unwrap(), legacy);
+
+        // A newer peer adds field 100 and signs what it sends
+        let with_new_field = |envelope: &Envelope| {
+            let mut wire = envelope.encode_to_vec();
+            encode_key(100, WireType::LengthDelimited, &mut wire);
+            encode_varint(3, &mut wire);
+            wire.extend_from_slice(b"new");
+            wire
+        };
+        let tag = signer.mac(&with_new_field(&envelope)).unwrap().finalize().into_bytes();
+        let signed = |envelope: &Envelope| {
+            let mut wire = with_new_field(envelope);
+            encode_key(ENVELOPE_SIGNATURE_TAG, WireType::LengthDelimited, &mut wire);
+            encode_varint(tag.len() as u64, &mut wire);
+            wire.extend_from_slice(&tag);
+            wire
+        };
+        let wire = signed(&envelope);
+
+        // Decoding drops the field; verifying the wire bytes still covers it
+        let decoded = Envelope::decode(&wire[..]).unwrap();
+        assert!(decoded.encoded_len() < wire.len());
+        assert_eq!(signer.verify_encoded(&decoded, &wire), Ok(()));
+        assert!(signer.verify(&decoded).is_err());
+
+        // The unknown field can't be changed, but relays can count hops
+        let mut tampered = wire.clone();
+        let at = tampered.windows(3).position(|w| w == b"new").unwrap();
+        tampered[at] = b'N';
+        assert!(signer.verify_encoded(&decoded, &tampered).is_err());
+        envelope.header.as_mut().unwrap().hop_count = 2;
+        assert_eq!(signer.verify_encoded(&decoded, &signed(&envelope)), Ok(()));
+    }
+
+    #[test]
+    fn test_policy_parse() {
+        let policy = SigningPolicy::parse(" Command, telemetry_delta ").unwrap();
+        assert_eq!(
+            policy,
+            SigningPolicy::default()
+                .with_type(MessageType::MsgCommand)
+                .with_type(MessageType::MsgTelemetryDelta)
+        );
+        assert_eq!(SigningPolicy::parse("all").unwrap(), SigningPolicy::all());
+        assert_eq!(SigningPolicy::parse("").unwrap(), SigningPolicy::default());
+        assert!(matches!(
+            SigningPolicy::parse("command,bogus"),
+            Err(AuthError::UnknownMessageType(name)) if name == "bogus"
+        ));
+    }
+
+    #[test]
+    fn test_device_keys_pick_each_drones_key() {
+        let keys = DeviceKeys::parse("edge-001=alpha, EDGE-002=bravo", SigningPolicy::default())
+            .unwrap();
+        let mut envelope = command(CommandType::CmdRth);
+        EnvelopeSigner::new("alpha", SigningPolicy::default()).sign(&mut envelope);
+
+        assert_eq!(keys.signer_for("edge-001").unwrap().verify(&envelope), Ok(()));
+        assert!(keys.signer_for("edge-002").unwrap().verify(&envelope).is_err());
+        assert!(keys.signer_for("edge-003").is_none());
+
+        // Unlisted devices fall back to the shared key
+        let keys = keys.with_fallback("alpha");
+        assert_eq!(keys.signer_for("edge-003").unwrap().verify(&envelope), Ok(()));
+
+        for bad in ["edge-001", "edge-001=", "bad id=key"] {
+            assert!(matches!(
+                DeviceKeys::parse(bad, SigningPolicy::default()),
+                Err(AuthError::InvalidKeyEntry(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Chunked blob transfer
+//!
+//! Captured images and map tiles don't fit in one frame, so the edge sends
+//! each as a `BlobStart` (size and SHA-256), `BlobChunk`s in order and a
+//! `BlobEnd`. The server answers the start with a `BlobStatus` saying how many
+//! bytes it already holds, so a transfer cut off by a link drop resumes where
+//! it stopped, and answers the end with whether the blob checked out.
+
+use crate::{BlobChunk, BlobStart};
+use alloc::string::String;
+use alloc::vec::Vec;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Bytes per chunk
+pub const BLOB_CHUNK_LEN: usize = 16 * 1024;
+
+/// Largest blob accepted
+pub const MAX_BLOB_LEN: u64 = 16 * 1024 * 1024;
+
+/// Longest blob ID, in bytes
+pub const MAX_BLOB_ID_LEN: usize = 128;
+
+/// Reasons a blob is refused
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BlobError {
+    #[error("Invalid blob ID {0:?} (allowed: a-z, A-Z, 0-9, '-', '_', '.'; not leading '.')")]
+    InvalidId(String),
+
+    #[error("Blob too large: {0} bytes (max: {MAX_BLOB_LEN})")]
+    TooLarge(u64),
+
+    #[error("Checksum must be a 32-byte SHA-256, got {0} bytes")]
+    BadChecksumLen(usize),
+
+    #[error("Chunk runs past the end of the blob ({end} of {total_size} bytes)")]
+    Overrun { end: u64, total_size: u64 },
+
+    #[error("Blob incomplete: {received} of {total_size} bytes")]
+    Incomplete { received: u64, total_size: u64 },
+
+    #[error("Checksum mismatch")]
+    ChecksumMismatch,
+}
+
+/// SHA-256 of a whole blob, as carried in `BlobStart`
+pub fn blob_checksum(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+/// Check a blob ID; it names the stored file, so it must be a plain file name
+pub fn validate_blob_id(blob_id: &str) -> Result<(), BlobError> {
+    let valid = !blob_id.is_empty()
+        && blob_id.len() <= MAX_BLOB_ID_LEN
+        && !blob_id.starts_with('.')
+        && blob_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(BlobError::InvalidId(String::from(blob_id)))
+    }
+}
+
+/// Split `data` into chunks from `offset` on, in send order
+///
+/// Resuming at the end of the blob yields no chunks.
+pub fn chunk_blob<'a>(
+    blob_id: &'a str,
+    data: &'a [u8],
+    offset: u64,
+) -> impl Iterator<Item = BlobChunk> + 'a {
+    let start = (offset as usize).min(data.len());
+    data[start..]
+        .chunks(BLOB_CHUNK_LEN)
+        .enumerate()
+        .map(move |(i, chunk)| BlobChunk {
+            blob_id: String::from(blob_id),
+            offset: (start + i * BLOB_CHUNK_LEN) as u64,
+            data: chunk.to_vec(),
+        })
+}
+
+/// Reassembles one blob from its chunks
+#[derive(Debug, Clone)]
+pub struct BlobAssembler {
+    start: BlobStart,
+    data: Vec<u8>,
+}
+
+impl BlobAssembler {
+    /// Begin receiving the blob `start` announces
+    pub fn new(start: BlobStart) -> Result<Self, BlobError> {
+        validate_blob_id(&start.blob_id)?;
+        if start.total_size > MAX_BLOB_LEN {
+            return Err(BlobError::TooLarge(start.total_size));
+        }
+        if start.sha256.len() != 32 {
+            return Err(BlobError::BadChecksumLen(start.sha256.len()));
+        }
+        Ok(Self {
+            start,
+            data: Vec::new(),
+        })
+    }
+
+    /// The announcement this blob was started with
+    pub fn start(&self) -> &BlobStart {
+        &self.start
+    }
+
+    /// Whether `start` announces this same blob again (a resume)
+    pub fn resumes(&self, start: &BlobStart) -> bool {
+        start.blob_id == self.start.blob_id
+            && start.total_size == self.start.total_size
+            && start.sha256 == self.start.sha256
+    }
+
+    /// Bytes received so far, all from the start of the blob
+    pub fn received(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    /// Append `chunk` if it continues the blob
+    ///
+    /// A chunk anywhere else (a resend, or one after a lost chunk) is ignored
+    /// and false returned; the sender resumes from `received` after its next
+    /// `BlobStart`.
+    pub fn push(&mut self, chunk: &BlobChunk) -> Result<bool, BlobError> {
+        if chunk.offset != self.received() {
+            return Ok(false);
+        }
+        let end = chunk.offset + chunk.data.len() as u64;
+        if end > self.start.total_size {
+            return Err(BlobError::Overrun {
+                end,
+                total_size: self.start.total_size,
+            });
+        }
+        self.data.extend_from_slice(&chunk.data);
+        Ok(true)
+    }
+
+    /// The whole blob, once every byte arrived and the checksum matches
+    pub fn finish(self) -> Result<Vec<u8>, BlobError> {
+        if self.received() != self.start.total_size {
+            return Err(BlobError::Incomplete {
+                received: self.received(),
+                total_size: self.start.total_size,
+            });
+        }
+        if blob_checksum(&self.data) != self.start.sha256 {
+            return Err(BlobError::ChecksumMismatch);
+        }
+        Ok(self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn start(blob_id: &str, data: &[u8]) -> BlobStart {
+        BlobStart {
+            blob_id: String::from(blob_id),
+            content_type: String::from("image/jpeg"),
+            total_size: data.len() as u64,
+            sha256: blob_checksum(data),
+            name: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_blob_reassembled_after_resume() {
+        let data = blob(2 * BLOB_CHUNK_LEN + 100);
+        let mut assembler = BlobAssembler::new(start("frame-1.jpg", &data)).unwrap();
+        let chunks: Vec<BlobChunk> = chunk_blob("frame-1.jpg", &data, 0).collect();
+        assert_eq!(chunks.len(), 3);
+        assert!(assembler.push(&chunks[0]).unwrap());
+
+        // The link dropped: the second chunk got lost, the third arrives alone
+        assert!(!assembler.push(&chunks[2]).unwrap());
+        assert!(assembler.resumes(&start("frame-1.jpg", &data)));
+        let resumed: Vec<BlobChunk> =
+            chunk_blob("frame-1.jpg", &data, assembler.received()).collect();
+        assert_eq!(resumed[0].offset, BLOB_CHUNK_LEN as u64);
+        for chunk in &resumed {
+            assert!(assembler.push(chunk).unwrap());
+        }
+        assert_eq!(assembler.finish().unwrap(), data);
+    }
+
+    #[test]
+    fn test_bad_blobs_refused() {
+        let data = blob(100);
+        for id in ["", "../etc/passwd", ".hidden", "a/b"] {
+            assert!(BlobAssembler::new(start(id, &data)).is_err(), "{id:?}");
+        }
+        let huge = BlobStart {
+            total_size: MAX_BLOB_LEN + 1,
+            ..start("big", &data)
+        };
+        assert_eq!(
+            BlobAssembler::new(huge).unwrap_err(),
+            BlobError::TooLarge(MAX_BLOB_LEN + 1)
+        );
+
+        // Same size, different bytes
+        let mut assembler = BlobAssembler::new(start("tile", &data)).unwrap();
+        let mut corrupt = data.clone();
+        corrupt[7] ^= 1;
+        for chunk in chunk_blob("tile", &corrupt, 0) {
+            assembler.push(&chunk).unwrap();
+        }
+        assert_eq!(assembler.finish().unwrap_err(), BlobError::ChecksumMismatch);
+
+        let mut assembler = BlobAssembler::new(start("tile", &data)).unwrap();
+        let long = BlobChunk {
+            blob_id: String::from("tile"),
+            offset: 0,
+            data: blob(101),
+        };
+        assert!(matches!(assembler.push(&long), Err(BlobError::Overrun { .. })));
+        assert!(matches!(assembler.finish(), Err(BlobError::Incomplete { .. })));
+    }
+}
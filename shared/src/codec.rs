@@ -2,20 +2,37 @@
 //!
 //! All messages are framed as:
 //! ```text
-//! [ 4 bytes: length (u32, big-endian) ][ N bytes: protobuf Envelope ]
+//! [ 1 byte: compression ][ 3 bytes: length (big-endian) ][ N bytes: protobuf Envelope ][ CRC32 ]
 //! ```
 //!
-//! This ensures message boundaries are preserved over TCP streams.
+//! This ensures message boundaries are preserved over TCP streams. The
+//! compression byte is a `Compression` value (0 = none, so uncompressed frames
+//! read as a plain big-endian u32 length); the length is of the bytes on the
+//! wire. The CRC32 trailer is optional and flagged in the compression byte;
+//! frames that fail it are dropped. The full rules, as constants, are in
+//! [`crate::schema`].
 
+use alloc::{string::String, vec::Vec};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use prost::encoding::{
+    decode_key, decode_varint, encode_key, encode_varint, skip_field, DecodeContext, WireType,
+};
 use prost::Message;
 use thiserror::Error;
 
-use crate::Envelope;
+use crate::schema::{
+    FRAME_COMPRESSION_SHIFT, FRAME_CRC_FLAG, FRAME_CRC_LEN, FRAME_LENGTH_MASK, FRAME_PREFIX_LEN,
+};
+use crate::{compression, Compression, Envelope};
 
 /// Maximum message size (10 MB) to prevent memory exhaustion
 pub const MAX_MESSAGE_SIZE: u32 = 10 * 1024 * 1024;
 
+/// `Envelope.header`
+pub(crate) const ENVELOPE_HEADER_TAG: u32 = 1;
+/// `Header.hop_count`
+pub(crate) const HEADER_HOP_COUNT_TAG: u32 = 7;
+
 /// Errors that can occur during encoding/decoding
 #[derive(Error, Debug)]
 pub enum CodecError {
@@ -28,11 +45,37 @@ pub enum CodecError {
     #[error("Not enough data: need {needed} bytes, have {available}")]
     NotEnoughData { needed: usize, available: usize },
 
+    // prost errors only implement `Error` with std, so they are a source only there
     #[error("Protobuf decode error: {0}")]
-    DecodeError(#[from] prost::DecodeError),
+    DecodeError(#[cfg_attr(feature = "std", source)] prost::DecodeError),
 
     #[error("Protobuf encode error: {0}")]
-    EncodeError(#[from] prost::EncodeError),
+    EncodeError(#[cfg_attr(feature = "std", source)] prost::EncodeError),
+
+    #[error("Unsupported frame compression: {0}")]
+    UnsupportedCompression(u8),
+
+    #[error("Compression error: {0}")]
+    Compression(String),
+
+    #[error("Frame checksum mismatch: expected {expected:#010x}, got {actual:#010x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+
+    #[cfg(feature = "std")]
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<prost::DecodeError> for CodecError {
+    fn from(e: prost::DecodeError) -> Self {
+        CodecError::DecodeError(e)
+    }
+}
+
+impl From<prost::EncodeError> for CodecError {
+    fn from(e: prost::EncodeError) -> Self {
+        CodecError::EncodeError(e)
+    }
 }
 
 /// Encode an Envelope into a length-prefixed byte buffer
@@ -43,8 +86,8 @@ pub fn encode(envelope: &Envelope) -> Result<Bytes, CodecError> {
         return Err(CodecError::MessageTooLarge(msg_len));
     }
 
-    // 4 bytes for length prefix + message bytes
-    let mut buf = BytesMut::with_capacity(4 + msg_len);
+    // Length prefix + message bytes
+    let mut buf = BytesMut::with_capacity(FRAME_PREFIX_LEN + msg_len);
 
     // Write length prefix (big-endian u32)
     buf.put_u32(msg_len as u32);
@@ -64,7 +107,7 @@ pub fn encode_into(envelope: &Envelope, buf: &mut BytesMut) -> Result<(), CodecE
     }
 
     // Reserve space
-    buf.reserve(4 + msg_len);
+    buf.reserve(FRAME_PREFIX_LEN + msg_len);
 
     // Write length prefix (big-endian u32)
     buf.put_u32(msg_len as u32);
@@ -75,50 +118,320 @@ pub fn encode_into(envelope: &Envelope, buf: &mut BytesMut) -> Result<(), CodecE
     Ok(())
 }
 
+/// Encode an Envelope into a length-prefixed frame, compressed if it helps
+///
+/// Frames that don't shrink are sent uncompressed.
+pub fn encode_compressed(envelope: &Envelope, compression: Compression) -> Result<Bytes, CodecError> {
+    let mut buf = BytesMut::new();
+    encode_compressed_into(envelope, compression, &mut buf)?;
+    Ok(buf.freeze())
+}
+
+/// Encode an Envelope into a provided buffer, compressed if it helps
+pub fn encode_compressed_into(
+    envelope: &Envelope,
+    compression: Compression,
+    buf: &mut BytesMut,
+) -> Result<(), CodecError> {
+    if compression == Compression::None {
+        return encode_into(envelope, buf);
+    }
+
+    encode_raw_into(&envelope.encode_to_vec(), compression, buf)
+}
+
+/// Frame already encoded Envelope bytes, compressed if it helps
+fn encode_raw_into(
+    raw: &[u8],
+    compression: Compression,
+    buf: &mut BytesMut,
+) -> Result<(), CodecError> {
+    if raw.len() > MAX_MESSAGE_SIZE as usize {
+        return Err(CodecError::MessageTooLarge(raw.len()));
+    }
+
+    let compressed = match compression {
+        Compression::None => None,
+        _ => Some(compression::compress(compression, raw)?),
+    };
+    match compressed {
+        Some(compressed) if compressed.len() < raw.len() => {
+            buf.reserve(FRAME_PREFIX_LEN + compressed.len());
+            let prefix =
+                ((compression as u32) << FRAME_COMPRESSION_SHIFT) | compressed.len() as u32;
+            buf.put_u32(prefix);
+            buf.put_slice(&compressed);
+        }
+        _ => {
+            buf.reserve(FRAME_PREFIX_LEN + raw.len());
+            buf.put_u32(raw.len() as u32);
+            buf.put_slice(raw);
+        }
+    }
+    Ok(())
+}
+
+/// Encode an Envelope into a length-prefixed frame, optionally with a CRC32
+///
+/// Only enable `checksum` towards peers that understand the CRC flag; older
+/// ones reject the frame as using an unknown compression.
+pub fn encode_frame(
+    envelope: &Envelope,
+    compression: Compression,
+    checksum: bool,
+) -> Result<Bytes, CodecError> {
+    let mut buf = BytesMut::new();
+    encode_frame_into(envelope, compression, checksum, &mut buf)?;
+    Ok(buf.freeze())
+}
+
+/// Encode an Envelope into a provided buffer, optionally with a CRC32
+pub fn encode_frame_into(
+    envelope: &Envelope,
+    compression: Compression,
+    checksum: bool,
+    buf: &mut BytesMut,
+) -> Result<(), CodecError> {
+    let start = buf.len();
+    encode_compressed_into(envelope, compression, buf)?;
+    if checksum {
+        buf[start] |= FRAME_CRC_FLAG;
+        let crc = crc32fast::hash(&buf[start..]);
+        buf.put_u32(crc);
+    }
+    Ok(())
+}
+
+/// Frame Envelope bytes as they are, e.g. from [`decode_raw`], optionally
+/// with a CRC32
+///
+/// Unlike re-encoding a decoded envelope, this keeps fields this build
+/// doesn't know, and with them any signature over them.
+pub fn encode_raw_frame(
+    raw: &[u8],
+    compression: Compression,
+    checksum: bool,
+) -> Result<Bytes, CodecError> {
+    let mut buf = BytesMut::new();
+    encode_raw_into(raw, compression, &mut buf)?;
+    if checksum {
+        buf[0] |= FRAME_CRC_FLAG;
+        let crc = crc32fast::hash(&buf);
+        buf.put_u32(crc);
+    }
+    Ok(buf.freeze())
+}
+
+/// Set `Header.hop_count` in encoded Envelope bytes, leaving every other
+/// field as it is (unknown ones included)
+///
+/// Envelopes without a header come back unchanged.
+pub fn set_hop_count(raw: &[u8], hop_count: u32) -> Result<Vec<u8>, CodecError> {
+    let mut out = Vec::with_capacity(raw.len() + 2);
+    let mut buf = raw;
+    while !buf.is_empty() {
+        let field = next_field(&mut buf)?;
+        if field.tag != ENVELOPE_HEADER_TAG || field.wire_type != WireType::LengthDelimited {
+            out.extend_from_slice(field.raw);
+            continue;
+        }
+        let mut header = Vec::with_capacity(field.value.len() + 2);
+        let mut fields = field.value;
+        while !fields.is_empty() {
+            let field = next_field(&mut fields)?;
+            if field.tag != HEADER_HOP_COUNT_TAG {
+                header.extend_from_slice(field.raw);
+            }
+        }
+        if hop_count != 0 {
+            prost::encoding::uint32::encode(HEADER_HOP_COUNT_TAG, &hop_count, &mut header);
+        }
+        encode_key(ENVELOPE_HEADER_TAG, WireType::LengthDelimited, &mut out);
+        encode_varint(header.len() as u64, &mut out);
+        out.extend_from_slice(&header);
+    }
+    Ok(out)
+}
+
 /// Try to decode a length-prefixed Envelope from a buffer
 ///
 /// Returns:
 /// - `Ok(Some(envelope))` if a complete message was decoded
 /// - `Ok(None)` if more data is needed
 /// - `Err(...)` if the data is invalid
+///
+/// A frame failing its CRC is consumed and reported as
+/// [`CodecError::ChecksumMismatch`]; decoding can continue after it.
 pub fn decode(buf: &mut BytesMut) -> Result<Option<Envelope>, CodecError> {
-    // Need at least 4 bytes for the length prefix
-    if buf.len() < 4 {
+    Ok(decode_reporting(buf)?.map(|(envelope, _)| envelope))
+}
+
+/// Like [`decode`], also returning how many bytes of the frame were unknown
+///
+/// Fields added by a newer peer are skipped by prost rather than failing the
+/// decode, but not kept. Since prost never writes default values, the wire
+/// size minus the re-encoded size is exactly what was skipped.
+pub fn decode_reporting(buf: &mut BytesMut) -> Result<Option<(Envelope, usize)>, CodecError> {
+    Ok(decode_raw(buf)?.map(|(envelope, raw)| {
+        let unknown = raw.len().saturating_sub(envelope.encoded_len());
+        (envelope, unknown)
+    }))
+}
+
+/// Like [`decode`], also returning the (decompressed) Envelope bytes
+///
+/// Unlike the decoded envelope, these keep fields this build doesn't know,
+/// which is what signatures are checked against
+/// (see [`crate::EnvelopeSigner::verify_encoded`]).
+pub fn decode_raw(buf: &mut BytesMut) -> Result<Option<(Envelope, Bytes)>, CodecError> {
+    // Need the whole length prefix
+    if buf.len() < FRAME_PREFIX_LEN {
         return Ok(None);
     }
 
     // Peek at the length prefix without consuming
-    let msg_len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let prefix = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let msg_len = prefix & FRAME_LENGTH_MASK;
 
     // Validate length
     if msg_len > MAX_MESSAGE_SIZE {
         return Err(CodecError::InvalidLength(msg_len));
     }
 
-    let total_len = 4 + msg_len as usize;
+    // Refuse algorithms we can't undo before waiting for the whole frame
+    let flags = (prefix >> FRAME_COMPRESSION_SHIFT) as u8;
+    let checksummed = flags & FRAME_CRC_FLAG != 0;
+    let compression_byte = flags & !FRAME_CRC_FLAG;
+    let compression = match Compression::try_from(compression_byte as i32) {
+        Ok(c) if compression::is_supported(c) => c,
+        _ => return Err(CodecError::UnsupportedCompression(compression_byte)),
+    };
+
+    let body_end = FRAME_PREFIX_LEN + msg_len as usize;
+    let total_len = body_end + if checksummed { FRAME_CRC_LEN } else { 0 };
 
     // Check if we have the complete message
     if buf.len() < total_len {
         return Ok(None);
     }
 
+    // Drop corrupted frames before they reach the protobuf decoder
+    if checksummed {
+        let trailer = &buf[body_end..total_len];
+        let expected = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let actual = crc32fast::hash(&buf[..body_end]);
+        if actual != expected {
+            buf.advance(total_len);
+            return Err(CodecError::ChecksumMismatch { expected, actual });
+        }
+    }
+
     // Consume the length prefix
-    buf.advance(4);
+    buf.advance(FRAME_PREFIX_LEN);
 
     // Split off the message bytes
-    let msg_bytes = buf.split_to(msg_len as usize);
+    let msg_bytes = buf.split_to(msg_len as usize).freeze();
+    if checksummed {
+        buf.advance(FRAME_CRC_LEN);
+    }
 
     // Decode the protobuf message
-    let envelope = Envelope::decode(msg_bytes)?;
+    let raw = match compression {
+        Compression::None => msg_bytes,
+        _ => Bytes::from(compression::decompress(compression, &msg_bytes)?),
+    };
+    let envelope = Envelope::decode(&raw[..])?;
+
+    Ok(Some((envelope, raw)))
+}
+
+/// Decode the next frame, skipping and counting ones that fail their CRC
+///
+/// The Envelope bytes of the decoded frame are left in `last_envelope`.
+fn decode_counting(
+    buf: &mut BytesMut,
+    unknown_bytes: &mut u64,
+    corrupt_frames: &mut u64,
+    last_envelope: &mut Bytes,
+) -> Result<Option<Envelope>, CodecError> {
+    loop {
+        match decode_raw(buf) {
+            Err(CodecError::ChecksumMismatch { .. }) => *corrupt_frames += 1,
+            result => {
+                return Ok(result?.map(|(envelope, raw)| {
+                    *unknown_bytes += raw.len().saturating_sub(envelope.encoded_len()) as u64;
+                    *last_envelope = raw;
+                    envelope
+                }));
+            }
+        }
+    }
+}
+
+/// One field of an encoded protobuf message, as it is on the wire
+pub(crate) struct RawField<'a> {
+    pub tag: u32,
+    pub wire_type: WireType,
+    /// Key and value
+    pub raw: &'a [u8],
+    /// Contents of a length-delimited field (empty for other wire types)
+    pub value: &'a [u8],
+}
+
+/// Split the next field off the front of an encoded message
+///
+/// Works on any field, known to this build or not, so a message can be
+/// taken apart and put back together without losing what a newer peer added.
+pub(crate) fn next_field<'a>(buf: &mut &'a [u8]) -> Result<RawField<'a>, prost::DecodeError> {
+    let start = *buf;
+    let (tag, wire_type) = decode_key(buf)?;
+    let mut value = *buf;
+    skip_field(wire_type, tag, buf, DecodeContext::default())?;
+    let raw = &start[..start.len() - buf.len()];
+    if wire_type == WireType::LengthDelimited {
+        let len = decode_varint(&mut value)? as usize;
+        value = &value[..len];
+    } else {
+        value = &[];
+    }
+    Ok(RawField {
+        tag,
+        wire_type,
+        raw,
+        value,
+    })
+}
 
-    Ok(Some(envelope))
+/// How outgoing frames are built, agreed with the peer at connect time
+///
+/// Decoding needs no config: each frame names its own compression and CRC.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodecConfig {
+    /// Compression negotiated in the hello
+    pub compression: Compression,
+    /// Append a CRC32 to each frame (only towards peers that understand it)
+    pub checksum: bool,
 }
 
 /// Decoder state machine for streaming decoding
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct FrameDecoder {
     /// Partial frame data being accumulated
     buffer: BytesMut,
+    /// Bytes of unknown fields skipped so far
+    unknown_bytes: u64,
+    /// Frames dropped for failing their CRC
+    corrupt_frames: u64,
+    /// Envelope bytes of the frame decoded last
+    last_envelope: Bytes,
+    /// Longest frame body accepted
+    max_len: u32,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FrameDecoder {
@@ -126,9 +439,22 @@ impl FrameDecoder {
     pub fn new() -> Self {
         Self {
             buffer: BytesMut::with_capacity(4096),
+            unknown_bytes: 0,
+            corrupt_frames: 0,
+            last_envelope: Bytes::new(),
+            max_len: MAX_MESSAGE_SIZE,
         }
     }
 
+    /// Refuse frame bodies longer than `max_len` (capped at `MAX_MESSAGE_SIZE`)
+    ///
+    /// The length prefix is checked as soon as it arrives, so an oversized
+    /// frame fails with [`CodecError::InvalidLength`] before it is buffered.
+    pub fn with_max_len(mut self, max_len: u32) -> Self {
+        self.max_len = max_len.min(MAX_MESSAGE_SIZE);
+        self
+    }
+
     /// Add data to the decoder buffer
     pub fn extend(&mut self, data: &[u8]) {
         self.buffer.extend_from_slice(data);
@@ -136,9 +462,66 @@ impl FrameDecoder {
 
     /// Try to decode the next frame from the buffer
     ///
-    /// Call this repeatedly until it returns `Ok(None)` to drain all complete frames
+    /// Call this repeatedly until it returns `Ok(None)` to drain all complete frames.
+    /// Frames failing their CRC are skipped and counted in [`Self::corrupt_frames`].
     pub fn decode_next(&mut self) -> Result<Option<Envelope>, CodecError> {
-        decode(&mut self.buffer)
+        self.check_len()?;
+        decode_counting(
+            &mut self.buffer,
+            &mut self.unknown_bytes,
+            &mut self.corrupt_frames,
+            &mut self.last_envelope,
+        )
+    }
+
+    /// Split off the next complete frame exactly as it was on the wire
+    ///
+    /// For forwarding frames unchanged: nothing is decompressed, decoded or
+    /// checked against its CRC.
+    pub fn next_raw_frame(&mut self) -> Result<Option<Bytes>, CodecError> {
+        self.check_len()?;
+        if self.buffer.len() < FRAME_PREFIX_LEN {
+            return Ok(None);
+        }
+        let buf = &self.buffer;
+        let prefix = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let msg_len = prefix & FRAME_LENGTH_MASK;
+        let checksummed = (prefix >> FRAME_COMPRESSION_SHIFT) as u8 & FRAME_CRC_FLAG != 0;
+        let total_len =
+            FRAME_PREFIX_LEN + msg_len as usize + if checksummed { FRAME_CRC_LEN } else { 0 };
+        if self.buffer.len() < total_len {
+            return Ok(None);
+        }
+        Ok(Some(self.buffer.split_to(total_len).freeze()))
+    }
+
+    /// Fail if the next frame's length prefix is over `max_len`
+    fn check_len(&self) -> Result<(), CodecError> {
+        let buf = &self.buffer;
+        if buf.len() < FRAME_PREFIX_LEN {
+            return Ok(());
+        }
+        let msg_len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) & FRAME_LENGTH_MASK;
+        if msg_len > self.max_len {
+            return Err(CodecError::InvalidLength(msg_len));
+        }
+        Ok(())
+    }
+
+    /// Total bytes of unknown fields skipped (non-zero means a newer peer)
+    pub fn unknown_bytes(&self) -> u64 {
+        self.unknown_bytes
+    }
+
+    /// Frames dropped so far because their CRC didn't match
+    pub fn corrupt_frames(&self) -> u64 {
+        self.corrupt_frames
+    }
+
+    /// Envelope bytes of the envelope [`Self::decode_next`] returned last,
+    /// unknown fields included (see [`decode_raw`])
+    pub fn last_envelope(&self) -> &[u8] {
+        &self.last_envelope
     }
 
     /// Get the current buffer length (for debugging)
@@ -152,19 +535,43 @@ impl FrameDecoder {
 pub struct FrameEncoder {
     /// Output buffer
     buffer: BytesMut,
+    /// Framing agreed for this connection
+    config: CodecConfig,
 }
 
 impl FrameEncoder {
     /// Create a new frame encoder
     pub fn new() -> Self {
+        Self::with_config(CodecConfig::default())
+    }
+
+    /// Create a frame encoder with framing agreed at connect time
+    pub fn with_config(config: CodecConfig) -> Self {
         Self {
             buffer: BytesMut::with_capacity(4096),
+            config,
         }
     }
 
+    /// Framing applied to frames encoded from now on
+    pub fn config(&self) -> CodecConfig {
+        self.config
+    }
+
+    /// Compress frames encoded from now on
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.config.compression = compression;
+    }
+
+    /// Append a CRC32 to frames encoded from now on (off by default, for
+    /// peers that predate it)
+    pub fn set_checksum(&mut self, checksum: bool) {
+        self.config.checksum = checksum;
+    }
+
     /// Encode an envelope and add to the output buffer
     pub fn encode(&mut self, envelope: &Envelope) -> Result<(), CodecError> {
-        encode_into(envelope, &mut self.buffer)
+        encode_frame_into(envelope, self.config.compression, self.config.checksum, &mut self.buffer)
     }
 
     /// Take the encoded bytes, leaving an empty buffer
@@ -178,6 +585,98 @@ impl FrameEncoder {
     }
 }
 
+/// Envelope framing for `tokio_util::codec::{Framed, FramedRead, FramedWrite}`
+///
+/// Same frames and counters as [`FrameDecoder`] and [`FrameEncoder`], with
+/// the buffering left to the `Framed` wrapper.
+#[cfg(feature = "tokio-codec")]
+#[derive(Debug, Default)]
+pub struct EnvelopeCodec {
+    /// Framing agreed for this connection
+    config: CodecConfig,
+    /// Bytes of unknown fields skipped so far
+    unknown_bytes: u64,
+    /// Frames dropped for failing their CRC
+    corrupt_frames: u64,
+    /// Envelope bytes of the frame decoded last
+    last_envelope: Bytes,
+}
+
+#[cfg(feature = "tokio-codec")]
+impl EnvelopeCodec {
+    /// Create a codec sending uncompressed frames without a CRC
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a codec with framing agreed at connect time
+    pub fn with_config(config: CodecConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Framing applied to frames encoded from now on
+    pub fn config(&self) -> CodecConfig {
+        self.config
+    }
+
+    /// Compress frames encoded from now on
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.config.compression = compression;
+    }
+
+    /// Append a CRC32 to frames encoded from now on (off by default, for
+    /// peers that predate it)
+    pub fn set_checksum(&mut self, checksum: bool) {
+        self.config.checksum = checksum;
+    }
+
+    /// Total bytes of unknown fields skipped (non-zero means a newer peer)
+    pub fn unknown_bytes(&self) -> u64 {
+        self.unknown_bytes
+    }
+
+    /// Frames dropped so far because their CRC didn't match
+    pub fn corrupt_frames(&self) -> u64 {
+        self.corrupt_frames
+    }
+
+    /// Envelope bytes of the envelope decoded last, unknown fields included
+    /// (see [`decode_raw`])
+    ///
+    /// `FramedRead` decodes one frame per item, so this belongs to the item
+    /// it returned last.
+    pub fn last_envelope(&self) -> &[u8] {
+        &self.last_envelope
+    }
+}
+
+#[cfg(feature = "tokio-codec")]
+impl tokio_util::codec::Decoder for EnvelopeCodec {
+    type Item = Envelope;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Envelope>, CodecError> {
+        decode_counting(
+            src,
+            &mut self.unknown_bytes,
+            &mut self.corrupt_frames,
+            &mut self.last_envelope,
+        )
+    }
+}
+
+#[cfg(feature = "tokio-codec")]
+impl tokio_util::codec::Encoder<Envelope> for EnvelopeCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, envelope: Envelope, dst: &mut BytesMut) -> Result<(), CodecError> {
+        encode_frame_into(&envelope, self.config.compression, self.config.checksum, dst)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -192,6 +691,7 @@ mod tests {
                 0,
                 true,
             ))),
+            signature: Vec::new(),
         }
     }
 
@@ -218,6 +718,33 @@ mod tests {
         assert!(buf.is_empty(), "buffer should be empty after decode");
     }
 
+    #[test]
+    fn test_hello_safety_config_roundtrip() {
+        let config = crate::SafetyConfig {
+            battery_critical_percent: 25,
+            heartbeat_timeout_ms: 8000,
+            battery_critical_action: crate::FailsafeAction::FailsafeLand.into(),
+            heartbeat_loss_action: crate::FailsafeAction::FailsafeRth.into(),
+            geofence_breach_action: crate::FailsafeAction::FailsafeHover.into(),
+        };
+        let original = Envelope {
+            header: Some(Header::new("test-device", MessageType::MsgHello, 1)),
+            payload: Some(crate::envelope::Payload::Hello(crate::Hello::new(config))),
+            signature: Vec::new(),
+        };
+
+        let encoded = encode(&original).expect("encode failed");
+        let mut buf = BytesMut::from(&encoded[..]);
+        let decoded = decode(&mut buf).expect("decode failed").expect("no message");
+
+        match decoded.payload {
+            Some(crate::envelope::Payload::Hello(hello)) => {
+                assert_eq!(hello.safety_config, Some(config));
+            }
+            other => panic!("expected hello, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_partial_decode() {
         let envelope = create_test_envelope();
@@ -273,6 +800,68 @@ mod tests {
         assert!(decoder.decode_next().expect("decode error").is_none());
     }
 
+    /// Heartbeat as a future peer might send it, with fields 14 and 15 added
+    #[derive(Clone, PartialEq, Message)]
+    struct FutureHeartbeat {
+        #[prost(uint64, tag = "1")]
+        uptime_ms: u64,
+        #[prost(int32, tag = "2")]
+        state: i32,
+        #[prost(bool, tag = "4")]
+        healthy: bool,
+        #[prost(bool, tag = "14")]
+        charging: bool,
+        #[prost(string, tag = "15")]
+        fc_firmware: String,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    struct FutureEnvelope {
+        #[prost(message, optional, tag = "1")]
+        header: Option<Header>,
+        #[prost(message, optional, tag = "5")]
+        heartbeat: Option<FutureHeartbeat>,
+    }
+
+    #[test]
+    fn test_heartbeat_with_unknown_fields_decodes() {
+        let future = FutureEnvelope {
+            header: Some(Header::new("edge-001", MessageType::MsgHeartbeat, 7)),
+            heartbeat: Some(FutureHeartbeat {
+                uptime_ms: 1000,
+                state: crate::DroneState::DroneInMission as i32,
+                healthy: true,
+                charging: true,
+                fc_firmware: "4.5.1".into(),
+            }),
+        };
+        let bytes = future.encode_to_vec();
+        let mut frame = BytesMut::new();
+        frame.put_u32(bytes.len() as u32);
+        frame.put_slice(&bytes);
+
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&frame);
+        let decoded = decoder.decode_next().expect("decode error").expect("no message");
+
+        match decoded.payload {
+            Some(crate::envelope::Payload::Heartbeat(hb)) => {
+                assert_eq!(hb.uptime_ms, 1000);
+                assert_eq!(hb.state(), crate::DroneState::DroneInMission);
+                assert!(hb.healthy);
+            }
+            other => panic!("expected heartbeat, got {:?}", other),
+        }
+        assert_eq!(decoded.header.unwrap().sequence_id, 7);
+        // charging (2 bytes) + fc_firmware (7 bytes)
+        assert_eq!(decoder.unknown_bytes(), 9);
+
+        // Frames from a peer on our own revision report nothing
+        decoder.extend(&encode(&create_test_envelope()).unwrap());
+        decoder.decode_next().expect("decode error").expect("no message");
+        assert_eq!(decoder.unknown_bytes(), 9);
+    }
+
     #[test]
     fn test_message_too_large() {
         let mut buf = BytesMut::new();
@@ -282,4 +871,451 @@ mod tests {
         let result = decode(&mut buf);
         assert!(matches!(result, Err(CodecError::InvalidLength(_))));
     }
+
+    #[test]
+    fn test_unsupported_compression_rejected() {
+        let mut buf = BytesMut::new();
+        buf.put_u32((0x7F << 24) | 10);
+        buf.put_bytes(0, 10);
+
+        assert!(matches!(decode(&mut buf), Err(CodecError::UnsupportedCompression(0x7F))));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_frame_roundtrip() {
+        let mut original = create_test_envelope();
+        original.payload = Some(crate::envelope::Payload::Telemetry(crate::Telemetry {
+            payload_values: (0..32).map(|i| (alloc::format!("SENSOR_{}", i), 1.5)).collect(),
+            ..Default::default()
+        }));
+
+        let plain = encode(&original).expect("encode failed");
+        let compressed = encode_compressed(&original, Compression::Zstd).expect("encode failed");
+        assert_eq!(compressed[0], Compression::Zstd as u8);
+        assert!(compressed.len() < plain.len());
+
+        // Compressed and plain frames can share a stream
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&compressed);
+        decoder.extend(&plain);
+        assert_eq!(decoder.decode_next().unwrap(), Some(original.clone()));
+        assert_eq!(decoder.decode_next().unwrap(), Some(original));
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4_frame_with_config() {
+        let mut original = create_test_envelope();
+        original.payload = Some(crate::envelope::Payload::Telemetry(crate::Telemetry {
+            payload_values: (0..32).map(|i| (alloc::format!("SENSOR_{}", i), 1.5)).collect(),
+            ..Default::default()
+        }));
+
+        let mut encoder = FrameEncoder::with_config(CodecConfig {
+            compression: Compression::Lz4,
+            checksum: true,
+        });
+        encoder.encode(&original).unwrap();
+        let frame = encoder.take();
+        assert_eq!(frame[0], Compression::Lz4 as u8 | FRAME_CRC_FLAG);
+        assert!(frame.len() < encode(&original).unwrap().len());
+
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&frame);
+        assert_eq!(decoder.decode_next().unwrap(), Some(original));
+    }
+
+    #[test]
+    fn test_incompressible_frame_sent_plain() {
+        let envelope = create_test_envelope();
+        let plain = encode(&envelope).expect("encode failed");
+        for compression in compression::supported() {
+            assert_eq!(encode_compressed(&envelope, compression).unwrap(), plain);
+        }
+    }
+
+    /// Feed `stream` to a fresh decoder in chunks sized by `pattern` (cycled),
+    /// draining decoded frames after every chunk
+    ///
+    /// Replays a split deterministically, e.g. one a property test shrank to.
+    fn replay_split(stream: &[u8], pattern: &[usize]) -> Result<Vec<Envelope>, CodecError> {
+        let mut decoder = FrameDecoder::new();
+        let mut decoded = Vec::new();
+        let mut rest = stream;
+        for &size in pattern.iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at(size.clamp(1, rest.len()));
+            decoder.extend(chunk);
+            rest = tail;
+            while let Some(envelope) = decoder.decode_next()? {
+                decoded.push(envelope);
+            }
+        }
+        assert_eq!(decoder.buffer_len(), 0, "trailing bytes left in decoder");
+        Ok(decoded)
+    }
+
+    /// Encode `envelopes` back to back as one stream
+    fn encode_stream(envelopes: &[(Envelope, Compression)]) -> Vec<u8> {
+        let mut stream = BytesMut::new();
+        for (envelope, compression) in envelopes {
+            encode_compressed_into(envelope, *compression, &mut stream).expect("encode failed");
+        }
+        stream.to_vec()
+    }
+
+    #[test]
+    fn test_replay_split_inside_length_prefix() {
+        let envelopes = [
+            (create_test_envelope(), Compression::None),
+            (create_test_envelope(), Compression::None),
+        ];
+        let stream = encode_stream(&envelopes);
+
+        // Byte by byte, and splits landing inside the 4-byte prefix
+        for pattern in [&[1][..], &[3, 2], &[2, 5, 1], &[stream.len()]] {
+            let decoded = replay_split(&stream, pattern).unwrap();
+            assert_eq!(decoded, [envelopes[0].0.clone(), envelopes[1].0.clone()]);
+        }
+    }
+
+    /// Envelopes of the golden vectors in `tests/vectors/`, with their frames
+    ///
+    /// Every field is fixed (no wall clock), so the encoding is canonical.
+    fn golden_vectors() -> [(&'static str, Envelope, &'static [u8]); 3] {
+        use crate::command::Params;
+        use crate::envelope::Payload;
+        use crate::{
+            priority, Command, CommandType, DroneState, EmergencyStop, GpsCoordinate,
+            MissionStart, ScanPattern, SurveyArea,
+        };
+
+        const TIMESTAMP_MS: u64 = 1_700_000_000_000;
+        let command = |sequence_id, command_id, cmd_type: CommandType, priority, params| Envelope {
+            header: Some(Header::with_timestamp(
+                "server-main",
+                MessageType::MsgCommand,
+                sequence_id,
+                TIMESTAMP_MS,
+            )),
+            payload: Some(Payload::Command(Command {
+                command_id,
+                cmd_type: cmd_type.into(),
+                expires_at_ms: 0,
+                priority,
+                params: Some(params),
+            })),
+            signature: Vec::new(),
+        };
+        let corner = |latitude, longitude| GpsCoordinate {
+            latitude,
+            longitude,
+            altitude_m: 0.0,
+        };
+
+        [
+            (
+                "emergency_stop",
+                command(
+                    42,
+                    7,
+                    CommandType::CmdEmergencyStop,
+                    priority::EMERGENCY,
+                    Params::EmergencyStop(EmergencyStop {}),
+                ),
+                include_bytes!("../tests/vectors/emergency_stop.bin"),
+            ),
+            (
+                "heartbeat",
+                Envelope {
+                    header: Some(Header::with_timestamp(
+                        "edge-001",
+                        MessageType::MsgHeartbeat,
+                        1,
+                        TIMESTAMP_MS,
+                    )),
+                    payload: Some(Payload::Heartbeat(Heartbeat::new(
+                        60_000,
+                        DroneState::DroneInMission,
+                        2,
+                        true,
+                    ))),
+                    signature: Vec::new(),
+                },
+                include_bytes!("../tests/vectors/heartbeat.bin"),
+            ),
+            (
+                "mission_start",
+                command(
+                    43,
+                    8,
+                    CommandType::CmdMissionStart,
+                    priority::NORMAL,
+                    Params::MissionStart(MissionStart {
+                        mission_id: "survey-1".into(),
+                        survey_area: Some(SurveyArea {
+                            boundary: vec![
+                                corner(47.5, 8.5),
+                                corner(47.5, 8.75),
+                                corner(47.25, 8.75),
+                            ],
+                            home_position: Some(corner(47.25, 8.5)),
+                        }),
+                        scan_pattern: ScanPattern::PatternLawnmower.into(),
+                        altitude_m: 40.0,
+                        speed_mps: 5.5,
+                        sensors: Vec::new(),
+                        replace: false,
+                        uploaded_waypoints: 0,
+                        geofence: None,
+                        rth: None,
+                    }),
+                ),
+                include_bytes!("../tests/vectors/mission_start.bin"),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_golden_vectors() {
+        for (name, envelope, frame) in golden_vectors() {
+            let encoded = encode(&envelope).unwrap();
+            assert_eq!(&encoded[..], frame, "{} encodes differently", name);
+
+            let mut buf = BytesMut::from(frame);
+            let decoded = decode(&mut buf).unwrap().unwrap();
+            assert_eq!(decoded, envelope, "{} decodes differently", name);
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[cfg(feature = "tokio-codec")]
+    #[test]
+    fn test_envelope_codec_across_partial_reads() {
+        use tokio_util::codec::{Decoder, Encoder};
+
+        let envelope = create_test_envelope();
+        let mut codec = EnvelopeCodec::new();
+        codec.set_checksum(true);
+        let mut wire = BytesMut::new();
+        codec.encode(envelope.clone(), &mut wire).unwrap();
+        codec.set_checksum(false);
+        codec.encode(envelope.clone(), &mut wire).unwrap();
+
+        // Frames arrive a few bytes at a time, as from a socket
+        let mut src = BytesMut::new();
+        let mut decoded = Vec::new();
+        for chunk in wire.chunks(5) {
+            src.extend_from_slice(chunk);
+            while let Some(envelope) = codec.decode(&mut src).unwrap() {
+                decoded.push(envelope);
+            }
+        }
+        assert_eq!(decoded, vec![envelope.clone(), envelope]);
+        assert!(src.is_empty());
+        assert_eq!(codec.corrupt_frames(), 0);
+    }
+
+    #[test]
+    fn test_golden_crc_vector() {
+        let (_, envelope, _) = &golden_vectors()[1];
+        let frame = include_bytes!("../tests/vectors/heartbeat_crc.bin");
+
+        let encoded = encode_frame(envelope, Compression::None, true).unwrap();
+        assert_eq!(&encoded[..], frame);
+
+        let mut buf = BytesMut::from(&frame[..]);
+        assert_eq!(decode(&mut buf).unwrap().as_ref(), Some(envelope));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_checksummed_frames_mix_with_plain() {
+        let first = create_test_envelope();
+        let mut second = create_test_envelope();
+        second.header.as_mut().unwrap().sequence_id = 2;
+
+        let mut encoder = FrameEncoder::new();
+        encoder.set_checksum(true);
+        encoder.encode(&first).unwrap();
+        encoder.set_checksum(false);
+        encoder.encode(&second).unwrap();
+
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&encoder.take());
+        assert_eq!(decoder.decode_next().unwrap(), Some(first));
+        assert_eq!(decoder.decode_next().unwrap(), Some(second));
+        assert_eq!(decoder.corrupt_frames(), 0);
+    }
+
+    #[test]
+    fn test_corrupt_frame_dropped_and_counted() {
+        let envelope = create_test_envelope();
+        let mut corrupt =
+            BytesMut::from(&encode_frame(&envelope, Compression::None, true).unwrap()[..]);
+        let last = corrupt.len() - FRAME_CRC_LEN - 1;
+        corrupt[last] ^= 0xFF;
+
+        let mut buf = corrupt.clone();
+        assert!(matches!(decode(&mut buf), Err(CodecError::ChecksumMismatch { .. })));
+        assert!(buf.is_empty());
+
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&corrupt);
+        decoder.extend(&encode_frame(&envelope, Compression::None, true).unwrap());
+        assert_eq!(decoder.decode_next().unwrap(), Some(envelope));
+        assert_eq!(decoder.decode_next().unwrap(), None);
+        assert_eq!(decoder.corrupt_frames(), 1);
+    }
+
+    #[test]
+    fn test_raw_frames_split_unchanged() {
+        let envelope = create_test_envelope();
+        let checksummed = encode_frame(&envelope, Compression::None, true).unwrap();
+        let plain = encode(&envelope).unwrap();
+
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&checksummed);
+        decoder.extend(&plain[..3]);
+        assert_eq!(decoder.next_raw_frame().unwrap(), Some(checksummed));
+        assert_eq!(decoder.next_raw_frame().unwrap(), None);
+        decoder.extend(&plain[3..]);
+        assert_eq!(decoder.next_raw_frame().unwrap(), Some(plain));
+        assert_eq!(decoder.buffer_len(), 0);
+    }
+
+    #[test]
+    fn test_decoder_max_len_checked_on_prefix() {
+        let frame = encode(&create_test_envelope()).unwrap();
+        let body_len = (frame.len() - FRAME_PREFIX_LEN) as u32;
+
+        let mut decoder = FrameDecoder::new().with_max_len(body_len);
+        decoder.extend(&frame);
+        assert!(decoder.decode_next().unwrap().is_some());
+
+        // Refused from the prefix alone, without waiting for the body
+        let mut decoder = FrameDecoder::new().with_max_len(body_len - 1);
+        decoder.extend(&frame[..FRAME_PREFIX_LEN]);
+        let result = decoder.next_raw_frame();
+        assert!(matches!(result, Err(CodecError::InvalidLength(len)) if len == body_len));
+    }
+
+    mod proptests {
+        use super::*;
+        use crate::envelope::Payload;
+        use crate::{Ack, Command, DroneState, SensorData, Telemetry};
+        use alloc::collections::BTreeMap;
+        use proptest::prelude::*;
+
+        fn arb_header() -> impl Strategy<Value = Header> {
+            (
+                "[a-z0-9-]{1,16}",
+                any::<u64>(),
+                any::<u64>(),
+                0..8i32,
+                0..4u32,
+                0..4u32,
+                0..=crate::MAX_HOPS,
+            )
+                .prop_map(
+                    |(device_id, sequence_id, timestamp_ms, msg_type, priority, retry_count, hops)| {
+                        Header {
+                            device_id,
+                            sequence_id,
+                            timestamp_ms,
+                            msg_type,
+                            priority,
+                            retry_count,
+                            hop_count: hops,
+                        }
+                    },
+                )
+        }
+
+        /// Payloads from simplest to largest, so failures shrink toward heartbeats
+        fn arb_payload() -> impl Strategy<Value = Payload> {
+            prop_oneof![
+                (any::<u64>(), 0..10i32, any::<u32>(), any::<bool>()).prop_map(
+                    |(uptime_ms, state, pending, healthy)| {
+                        Payload::Heartbeat(Heartbeat {
+                            uptime_ms,
+                            state,
+                            pending_commands: pending,
+                            healthy,
+                            battery: None,
+                            gps_fix: healthy,
+                            relay: None,
+                        })
+                    }
+                ),
+                (any::<u64>(), 0..7i32, ".{0,40}").prop_map(|(command_id, status, message)| {
+                    Payload::Ack(Ack {
+                        command_id,
+                        status,
+                        message,
+                        ..Default::default()
+                    })
+                }),
+                (any::<u64>(), 0..11i32, 0..4u32).prop_map(|(command_id, cmd_type, priority)| {
+                    Payload::Command(Command {
+                        command_id,
+                        cmd_type,
+                        priority,
+                        ..Default::default()
+                    })
+                }),
+                (
+                    any::<u64>(),
+                    prop::collection::btree_map("[A-Z_]{1,12}", -1e6f32..1e6f32, 0..8),
+                )
+                    .prop_map(|(uptime_seconds, payload_values)| {
+                        Payload::Telemetry(Telemetry {
+                            uptime_seconds,
+                            state: DroneState::DroneInMission.into(),
+                            payload_values: payload_values.into_iter().collect::<BTreeMap<_, _>>(),
+                            ..Default::default()
+                        })
+                    }),
+                (prop::collection::vec(any::<u8>(), 0..4096), any::<u32>()).prop_map(
+                    |(data, chunk_index)| {
+                        Payload::SensorData(SensorData {
+                            sensor_type: "GPR".into(),
+                            data,
+                            chunk_index,
+                            ..Default::default()
+                        })
+                    }
+                ),
+            ]
+        }
+
+        fn arb_envelope() -> impl Strategy<Value = Envelope> {
+            (proptest::option::of(arb_header()), proptest::option::of(arb_payload())).prop_map(
+                |(header, payload)| Envelope {
+                    header,
+                    payload,
+                    signature: Vec::new(),
+                },
+            )
+        }
+
+        proptest! {
+            #[test]
+            fn prop_any_split_decodes_same_sequence(
+                envelopes in prop::collection::vec(
+                    (arb_envelope(), prop::sample::select(compression::supported())),
+                    0..12,
+                ),
+                pattern in prop::collection::vec(1usize..600, 1..16),
+            ) {
+                let stream = encode_stream(&envelopes);
+                let decoded = replay_split(&stream, &pattern).expect("decode error");
+                let expected: Vec<Envelope> = envelopes.into_iter().map(|(e, _)| e).collect();
+                prop_assert_eq!(decoded, expected);
+            }
+        }
+    }
 }
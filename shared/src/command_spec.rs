@@ -0,0 +1,272 @@
+//! Command registry
+//!
+//! One entry per command type: the `Command.params` field carrying its
+//! parameters and the fields of it that must be set. The edge validates
+//! incoming commands against it and reports it to ground stations in reply to
+//! `CMD_CAPABILITIES`, so a GCS can adapt to what the drone supports.
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use thiserror::Error;
+
+use crate::{command::Params, Command, CommandCapability, CommandType};
+
+/// Parameters a command type takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandSpec {
+    pub cmd_type: CommandType,
+    /// `Command.params` field carrying the parameters (`None` = takes none)
+    pub params: Option<&'static str>,
+    /// Whether the command is rejected without its parameters
+    pub params_required: bool,
+    /// Fields of the parameters that must be set (non-empty)
+    pub required_fields: &'static [&'static str],
+}
+
+impl CommandSpec {
+    const fn new(cmd_type: CommandType, params: &'static str) -> Self {
+        Self {
+            cmd_type,
+            params: Some(params),
+            params_required: false,
+            required_fields: &[],
+        }
+    }
+
+    const fn required(mut self, required_fields: &'static [&'static str]) -> Self {
+        self.params_required = true;
+        self.required_fields = required_fields;
+        self
+    }
+
+    /// The spec as reported to ground stations
+    pub fn to_capability(&self) -> CommandCapability {
+        CommandCapability {
+            cmd_type: self.cmd_type.into(),
+            params: self.params.unwrap_or_default().to_string(),
+            params_required: self.params_required,
+            required_fields: self.required_fields.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+}
+
+/// Every command type this build supports
+pub const COMMAND_SPECS: [CommandSpec; 17] = [
+    CommandSpec::new(CommandType::CmdMissionStart, "mission_start").required(&["mission_id"]),
+    CommandSpec::new(CommandType::CmdMissionAbort, "mission_abort").required(&[]),
+    CommandSpec::new(CommandType::CmdRth, "rth"),
+    CommandSpec::new(CommandType::CmdStatusRequest, "status_request"),
+    CommandSpec::new(CommandType::CmdConfigUpdate, "config_update").required(&[]),
+    CommandSpec::new(CommandType::CmdEmergencyStop, "emergency_stop"),
+    CommandSpec::new(CommandType::CmdGetFaults, "get_faults"),
+    CommandSpec::new(CommandType::CmdClearFaults, "clear_faults"),
+    CommandSpec::new(CommandType::CmdSkipWaypoint, "skip_waypoint"),
+    CommandSpec::new(CommandType::CmdDownloadLog, "download_log"),
+    CommandSpec {
+        cmd_type: CommandType::CmdCapabilities,
+        params: None,
+        params_required: false,
+        required_fields: &[],
+    },
+    CommandSpec::new(CommandType::CmdCancel, "cancel").required(&["target_command_id"]),
+    CommandSpec::new(CommandType::CmdMissionUpload, "mission_upload").required(&["mission_id"]),
+    CommandSpec::new(CommandType::CmdVideoStart, "video_start"),
+    CommandSpec::new(CommandType::CmdVideoStop, "video_stop"),
+    CommandSpec::new(CommandType::CmdParamGet, "param_get").required(&["names"]),
+    CommandSpec::new(CommandType::CmdParamSet, "param_set").required(&["name"]),
+];
+
+/// Why a command doesn't match its spec
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CommandSpecError {
+    #[error("Unsupported command type: {0}")]
+    Unsupported(i32),
+
+    #[error("Missing {0} parameters")]
+    MissingParams(&'static str),
+
+    #[error("Wrong parameters: expected {expected}, got {got}")]
+    WrongParams {
+        expected: &'static str,
+        got: &'static str,
+    },
+
+    #[error("Missing required parameter {0}")]
+    MissingField(&'static str),
+}
+
+/// Spec of `cmd_type`, if this build supports it
+pub fn spec(cmd_type: CommandType) -> Option<&'static CommandSpec> {
+    COMMAND_SPECS.iter().find(|s| s.cmd_type == cmd_type)
+}
+
+/// Capabilities of every supported command, for the `CMD_CAPABILITIES` reply
+pub fn capabilities() -> Vec<CommandCapability> {
+    COMMAND_SPECS.iter().map(CommandSpec::to_capability).collect()
+}
+
+/// `Command.params` field name of `params`
+pub fn params_name(params: &Params) -> &'static str {
+    match params {
+        Params::MissionStart(_) => "mission_start",
+        Params::MissionAbort(_) => "mission_abort",
+        Params::Rth(_) => "rth",
+        Params::StatusRequest(_) => "status_request",
+        Params::ConfigUpdate(_) => "config_update",
+        Params::EmergencyStop(_) => "emergency_stop",
+        Params::GetFaults(_) => "get_faults",
+        Params::ClearFaults(_) => "clear_faults",
+        Params::SkipWaypoint(_) => "skip_waypoint",
+        Params::DownloadLog(_) => "download_log",
+        Params::Cancel(_) => "cancel",
+        Params::MissionUpload(_) => "mission_upload",
+        Params::VideoStart(_) => "video_start",
+        Params::VideoStop(_) => "video_stop",
+        Params::ParamGet(_) => "param_get",
+        Params::ParamSet(_) => "param_set",
+    }
+}
+
+/// Whether `field` of `params` is unset
+fn field_missing(params: &Params, field: &str) -> bool {
+    match (params, field) {
+        (Params::MissionStart(m), "mission_id") => m.mission_id.is_empty(),
+        (Params::Cancel(c), "target_command_id") => c.target_command_id == 0,
+        (Params::MissionUpload(m), "mission_id") => m.mission_id.is_empty(),
+        (Params::ParamGet(p), "names") => p.names.is_empty(),
+        (Params::ParamSet(p), "name") => p.name.is_empty(),
+        _ => false,
+    }
+}
+
+impl Command {
+    /// Check the command's type and parameters against [`COMMAND_SPECS`]
+    pub fn validate_params(&self) -> Result<(), CommandSpecError> {
+        let spec = CommandType::try_from(self.cmd_type)
+            .ok()
+            .and_then(spec)
+            .ok_or(CommandSpecError::Unsupported(self.cmd_type))?;
+
+        let Some(params) = &self.params else {
+            return match spec.params {
+                Some(name) if spec.params_required => Err(CommandSpecError::MissingParams(name)),
+                _ => Ok(()),
+            };
+        };
+
+        let got = params_name(params);
+        match spec.params {
+            Some(expected) if expected != got => {
+                Err(CommandSpecError::WrongParams { expected, got })
+            }
+            None => Err(CommandSpecError::WrongParams {
+                expected: "none",
+                got,
+            }),
+            Some(_) => match spec.required_fields.iter().find(|f| field_missing(params, f)) {
+                Some(field) => Err(CommandSpecError::MissingField(field)),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codec, envelope::Payload, Ack, Envelope, Header, MessageType, MissionStart, ReturnToHome,
+    };
+    use bytes::BytesMut;
+
+    fn mission_start(mission_id: &str) -> Command {
+        Command {
+            cmd_type: CommandType::CmdMissionStart.into(),
+            params: Some(Params::MissionStart(MissionStart {
+                mission_id: mission_id.into(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_every_command_type_has_a_spec() {
+        for value in 1.. {
+            let Ok(cmd_type) = CommandType::try_from(value) else {
+                break;
+            };
+            assert!(spec(cmd_type).is_some(), "{:?} missing from registry", cmd_type);
+        }
+        assert!(spec(CommandType::CmdUnknown).is_none());
+    }
+
+    #[test]
+    fn test_validate_params() {
+        assert_eq!(mission_start("survey-1").validate_params(), Ok(()));
+        assert_eq!(
+            mission_start("").validate_params(),
+            Err(CommandSpecError::MissingField("mission_id"))
+        );
+
+        let no_params = Command {
+            cmd_type: CommandType::CmdMissionStart.into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            no_params.validate_params(),
+            Err(CommandSpecError::MissingParams("mission_start"))
+        );
+        // Optional parameters may be left out
+        let rth = Command {
+            cmd_type: CommandType::CmdRth.into(),
+            ..no_params.clone()
+        };
+        assert_eq!(rth.validate_params(), Ok(()));
+
+        let mismatched = Command {
+            params: Some(Params::Rth(ReturnToHome::default())),
+            ..no_params
+        };
+        assert_eq!(
+            mismatched.validate_params(),
+            Err(CommandSpecError::WrongParams {
+                expected: "mission_start",
+                got: "rth",
+            })
+        );
+        let unknown = Command::default();
+        assert_eq!(unknown.validate_params(), Err(CommandSpecError::Unsupported(0)));
+    }
+
+    #[test]
+    fn test_capabilities_roundtrip() {
+        let envelope = Envelope {
+            header: Some(Header::new("edge-001", MessageType::MsgAck, 1)),
+            payload: Some(Payload::Ack(Ack {
+                capabilities: capabilities(),
+                ..Ack::completed(7, 42, 0)
+            })),
+            signature: Vec::new(),
+        };
+
+        let encoded = codec::encode(&envelope).unwrap();
+        let decoded = codec::decode(&mut BytesMut::from(&encoded[..]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, envelope);
+
+        let Some(Payload::Ack(ack)) = decoded.payload else {
+            panic!("expected ack");
+        };
+        assert_eq!(ack.capabilities.len(), COMMAND_SPECS.len());
+        let mission = ack
+            .capabilities
+            .iter()
+            .find(|c| c.cmd_type() == CommandType::CmdMissionStart)
+            .unwrap();
+        assert_eq!(mission.params, "mission_start");
+        assert!(mission.params_required);
+        assert_eq!(mission.required_fields, ["mission_id"]);
+    }
+}
@@ -0,0 +1,177 @@
+//! Per-connection frame compression
+//!
+//! Both peers list the algorithms they support in their `Hello`; each side
+//! then compresses outgoing frames with `negotiate(local, remote)`. Frames
+//! carry their algorithm (see `codec`), so the switch needs no extra round
+//! trip and a peer that never advertises anything just gets uncompressed
+//! frames.
+//!
+//! Algorithms are opt-in crate features: `zstd` and `deflate` (both need
+//! `std`) and `lz4` (cheapest on CPU, works on `no_std`).
+
+#[cfg(feature = "lz4")]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use crate::codec::CodecError;
+#[cfg(any(feature = "zstd", feature = "deflate", feature = "lz4"))]
+use crate::codec::MAX_MESSAGE_SIZE;
+use crate::Compression;
+
+/// Algorithms from best to worst ratio; both sides pick the first one they share
+const PREFERENCE: [Compression; 4] = [
+    Compression::Zstd,
+    Compression::Deflate,
+    Compression::Lz4,
+    Compression::None,
+];
+
+/// zstd level: fast enough for the Pi, most of the gain on protobuf frames
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// Algorithms compiled into this build, best first
+pub fn supported() -> Vec<Compression> {
+    PREFERENCE
+        .into_iter()
+        .filter(|c| is_supported(*c))
+        .collect()
+}
+
+/// Whether this build can compress and decompress with `compression`
+pub fn is_supported(compression: Compression) -> bool {
+    match compression {
+        Compression::None => true,
+        Compression::Zstd => cfg!(feature = "zstd"),
+        Compression::Deflate => cfg!(feature = "deflate"),
+        Compression::Lz4 => cfg!(feature = "lz4"),
+    }
+}
+
+/// Best algorithm both peers support (`None` if there is no overlap)
+///
+/// Unknown values from a newer peer are ignored.
+pub fn negotiate(local: &[Compression], remote: &[i32]) -> Compression {
+    PREFERENCE
+        .into_iter()
+        .find(|c| local.contains(c) && remote.contains(&(*c as i32)))
+        .unwrap_or(Compression::None)
+}
+
+/// Compress a serialized envelope
+pub(crate) fn compress(compression: Compression, data: &[u8]) -> Result<Vec<u8>, CodecError> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)
+            .map_err(|e| CodecError::Compression(e.to_string())),
+        #[cfg(feature = "deflate")]
+        Compression::Deflate => {
+            use std::io::Write;
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder
+                .write_all(data)
+                .and_then(|_| encoder.finish())
+                .map_err(|e| CodecError::Compression(e.to_string()))
+        }
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => Ok(lz4_flex::block::compress_prepend_size(data)),
+        #[allow(unreachable_patterns)]
+        other => Err(CodecError::UnsupportedCompression(other as u8)),
+    }
+}
+
+/// Decompress a frame body, refusing output larger than `MAX_MESSAGE_SIZE`
+pub(crate) fn decompress(compression: Compression, data: &[u8]) -> Result<Vec<u8>, CodecError> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::bulk::decompress(data, MAX_MESSAGE_SIZE as usize)
+            .map_err(|e| CodecError::Compression(e.to_string())),
+        #[cfg(feature = "deflate")]
+        Compression::Deflate => {
+            use std::io::Read;
+            let mut out = Vec::new();
+            flate2::read::DeflateDecoder::new(data)
+                .take(MAX_MESSAGE_SIZE as u64 + 1)
+                .read_to_end(&mut out)
+                .map_err(|e| CodecError::Compression(e.to_string()))?;
+            if out.len() > MAX_MESSAGE_SIZE as usize {
+                return Err(CodecError::MessageTooLarge(out.len()));
+            }
+            Ok(out)
+        }
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => {
+            // Check the prepended size before allocating for it
+            let (size, block) = lz4_flex::block::uncompressed_size(data)
+                .map_err(|e| CodecError::Compression(e.to_string()))?;
+            if size > MAX_MESSAGE_SIZE as usize {
+                return Err(CodecError::MessageTooLarge(size));
+            }
+            lz4_flex::block::decompress(block, size)
+                .map_err(|e| CodecError::Compression(e.to_string()))
+        }
+        #[allow(unreachable_patterns)]
+        other => Err(CodecError::UnsupportedCompression(other as u8)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advertised(list: &[Compression]) -> Vec<i32> {
+        list.iter().map(|c| *c as i32).collect()
+    }
+
+    #[test]
+    fn test_negotiate_zstd_between_capable_peers() {
+        let local = [Compression::Deflate, Compression::Zstd, Compression::None];
+        let remote = advertised(&[Compression::Zstd, Compression::Deflate]);
+        assert_eq!(negotiate(&local, &remote), Compression::Zstd);
+        // Both sides reach the same answer
+        assert_eq!(negotiate(&[Compression::Zstd, Compression::Deflate], &advertised(&local)), Compression::Zstd);
+    }
+
+    #[test]
+    fn test_negotiate_none_with_incapable_peer() {
+        let local = [Compression::Zstd, Compression::Deflate, Compression::None];
+        assert_eq!(negotiate(&local, &[]), Compression::None);
+        assert_eq!(negotiate(&local, &[42]), Compression::None);
+        assert_eq!(negotiate(&[Compression::None], &advertised(&local)), Compression::None);
+    }
+
+    #[test]
+    fn test_negotiate_partial_overlap() {
+        let local = [Compression::Zstd, Compression::Deflate];
+        let remote = advertised(&[Compression::Deflate]);
+        assert_eq!(negotiate(&local, &remote), Compression::Deflate);
+    }
+
+    #[test]
+    fn test_negotiate_lz4_between_light_peers() {
+        // e.g. a relay built without zstd/deflate
+        let local = [Compression::Lz4, Compression::None];
+        let remote = advertised(&[Compression::Zstd, Compression::Lz4]);
+        assert_eq!(negotiate(&local, &remote), Compression::Lz4);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4_rejects_oversized_frame() {
+        let mut data = (MAX_MESSAGE_SIZE + 1).to_le_bytes().to_vec();
+        data.extend_from_slice(&[0; 8]);
+        assert!(matches!(
+            decompress(Compression::Lz4, &data),
+            Err(CodecError::MessageTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_supported_always_includes_none() {
+        assert_eq!(supported().last(), Some(&Compression::None));
+        assert!(supported().iter().all(|c| is_supported(*c)));
+    }
+}
@@ -0,0 +1,130 @@
+//! Validated device identifiers
+//!
+//! Device IDs end up as session map keys and in logs, so anything crossing the
+//! protocol boundary is checked and normalized here first.
+
+use alloc::string::String;
+use core::fmt;
+use core::str::FromStr;
+use thiserror::Error;
+
+/// Maximum device ID length in bytes
+pub const MAX_DEVICE_ID_LEN: usize = 64;
+
+/// Reasons a device ID is rejected
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DeviceIdError {
+    #[error("Device ID is empty")]
+    Empty,
+
+    #[error("Device ID too long: {0} bytes (max: {MAX_DEVICE_ID_LEN})")]
+    TooLong(usize),
+
+    #[error("Device ID contains invalid character {0:?} (allowed: a-z, 0-9, '-', '_', '.')")]
+    InvalidChar(char),
+}
+
+/// A validated, normalized device ID (e.g. "edge-001")
+///
+/// Normalization trims surrounding whitespace and lowercases ASCII letters,
+/// so "EDGE-001" and "edge-001" name the same drone.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceId(String);
+
+impl DeviceId {
+    /// Validate and normalize a raw device ID
+    pub fn parse(raw: &str) -> Result<Self, DeviceIdError> {
+        let trimmed = raw.trim();
+        if trimmed.is_empty() {
+            return Err(DeviceIdError::Empty);
+        }
+        if trimmed.len() > MAX_DEVICE_ID_LEN {
+            return Err(DeviceIdError::TooLong(trimmed.len()));
+        }
+        if let Some(c) = trimmed
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        {
+            return Err(DeviceIdError::InvalidChar(c));
+        }
+        Ok(Self(trimmed.to_ascii_lowercase()))
+    }
+
+    /// Get the normalized ID
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for DeviceId {
+    type Err = DeviceIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for DeviceId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<DeviceId> for String {
+    fn from(id: DeviceId) -> Self {
+        id.0
+    }
+}
+
+impl From<&DeviceId> for String {
+    fn from(id: &DeviceId) -> Self {
+        id.0.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_device_ids() {
+        for raw in ["edge-001", "relay_2", "server", "drone.alpha-7"] {
+            assert_eq!(DeviceId::parse(raw).unwrap().as_str(), raw);
+        }
+    }
+
+    #[test]
+    fn test_device_id_normalized() {
+        let id = DeviceId::parse("  EDGE-001 ").unwrap();
+        assert_eq!(id.as_str(), "edge-001");
+        assert_eq!(id, "edge-001".parse().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_device_ids() {
+        assert_eq!(DeviceId::parse(""), Err(DeviceIdError::Empty));
+        assert_eq!(DeviceId::parse("   "), Err(DeviceIdError::Empty));
+        assert_eq!(
+            DeviceId::parse(&"a".repeat(MAX_DEVICE_ID_LEN + 1)),
+            Err(DeviceIdError::TooLong(MAX_DEVICE_ID_LEN + 1))
+        );
+        assert_eq!(
+            DeviceId::parse("edge-001\n[FAKE] log line"),
+            Err(DeviceIdError::InvalidChar('\n'))
+        );
+        assert_eq!(DeviceId::parse("edge 001"), Err(DeviceIdError::InvalidChar(' ')));
+        assert_eq!(DeviceId::parse("edge/001"), Err(DeviceIdError::InvalidChar('/')));
+        assert_eq!(DeviceId::parse("édge"), Err(DeviceIdError::InvalidChar('é')));
+    }
+
+    #[test]
+    fn test_max_length_accepted() {
+        assert!(DeviceId::parse(&"a".repeat(MAX_DEVICE_ID_LEN)).is_ok());
+    }
+}
@@ -2,21 +2,86 @@
 //!
 //! This crate provides the shared protocol types and codec for communication
 //! between drone edge devices, relay nodes, and the server.
+//!
+//! The protocol types, codec and state machine build with `no_std` + `alloc`
+//! when the default `std` feature is disabled; wall-clock helpers require `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
+pub mod auth;
+pub mod blob;
 pub mod codec;
+pub mod command_spec;
+pub mod compression;
+pub mod device_id;
+pub mod link_quality;
+#[cfg(feature = "mission")]
+pub mod mission;
+#[cfg(any(feature = "udp", feature = "quic", feature = "websocket"))]
+pub mod message_link;
+pub mod mission_upload;
+#[cfg(feature = "noise")]
+pub mod noise;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod schema;
+pub mod sequence;
 pub mod state_machine;
+pub mod telemetry_batch;
+pub mod telemetry_delta;
+#[cfg(feature = "udp")]
+pub mod udp;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
+use alloc::{string::String, vec::Vec};
+#[cfg(feature = "std")]
 use std::time::{SystemTime, UNIX_EPOCH};
 
 // Include the generated protobuf types
 pub mod proto {
     include!(concat!(env!("OUT_DIR"), "/resqterra.rs"));
+
+    /// The server's `FleetService` control plane; its server and client need
+    /// the `grpc` feature
+    pub mod fleet {
+        include!(concat!(env!("OUT_DIR"), "/resqterra.fleet.rs"));
+    }
 }
 
 // Re-export commonly used types at crate root
+pub use auth::{DeviceKeys, EnvelopeSigner, SigningPolicy};
+pub use blob::{BlobAssembler, BlobError, BLOB_CHUNK_LEN, MAX_BLOB_LEN};
+pub use command_spec::{CommandSpec, CommandSpecError, COMMAND_SPECS};
+pub use device_id::{DeviceId, DeviceIdError};
+pub use link_quality::{LinkStats, QualityHistory, QualitySample, LOSS_WINDOW_LEN, QUALITY_HISTORY_LEN};
+pub use mission_upload::{MISSION_CHUNK_WAYPOINTS, MAX_MISSION_WAYPOINTS};
+pub use schema::proto_descriptor;
+pub use sequence::{SequenceEvent, SequenceStats, SequenceTracker, SEQUENCE_WINDOW};
+pub use telemetry_batch::{TelemetryBatcher, TELEMETRY_BATCH_LEN};
+pub use telemetry_delta::{DeltaDecoder, DeltaEncoder, TELEMETRY_KEYFRAME_INTERVAL};
 pub use proto::*;
 
+/// Wire protocol revision advertised in the hello
+///
+/// Bumped whenever fields are added to the proto. Changes are additive only
+/// (see `docs/PROTOCOL.md`), so peers on different revisions interoperate and
+/// this is for diagnostics, not negotiation.
+pub const PROTOCOL_VERSION: u32 = 27;
+
+/// Most relays a message may pass through (`Header.hop_count`)
+///
+/// Relays drop links whose frames have already made this many hops, so a
+/// loop of relays forwarding to each other dies out instead of circling.
+pub const MAX_HOPS: u32 = 8;
+
+/// Bluetooth service UUID relays advertise over SDP and drones look for
+pub const BT_SERVICE_UUID: u128 = 0x00001101_0000_1000_8000_00805F9B34FB;
+
 /// Get current timestamp in milliseconds since Unix epoch
+#[cfg(feature = "std")]
 pub fn now_ms() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -32,6 +97,9 @@ pub mod safety {
     /// Heartbeat timeout - triggers RTH if no heartbeat received
     pub const HEARTBEAT_TIMEOUT_MS: u64 = 10000;
 
+    /// Flight controller heartbeat timeout - warns that the FC link is lost
+    pub const FC_HEARTBEAT_TIMEOUT_MS: u64 = 3000;
+
     /// Command ACK timeout in milliseconds
     pub const COMMAND_ACK_TIMEOUT_MS: u64 = 3000;
 
@@ -41,21 +109,116 @@ pub mod safety {
     /// Maximum age for a command before it's considered expired
     pub const COMMAND_MAX_AGE_MS: u64 = 30000;
 
+    /// Low battery percentage - warns the operator once
+    pub const BATTERY_WARNING_PERCENT: u32 = 30;
+
     /// Critical battery percentage - triggers forced RTH
     pub const BATTERY_CRITICAL_PERCENT: u32 = 20;
+
+    /// Depleted battery percentage - forces a landing wherever the drone is
+    pub const BATTERY_LAND_PERCENT: u32 = 10;
+}
+
+/// Command priority levels (`Command.priority`)
+///
+/// The server sends queued commands highest priority first, FIFO within a
+/// level. `EMERGENCY` is reserved: only emergency stops run at that level and
+/// they bypass the queue entirely.
+pub mod priority {
+    /// Routine work (status requests, survey configuration)
+    pub const LOW: u32 = 0;
+
+    /// Default for operator commands
+    pub const NORMAL: u32 = 1;
+
+    /// Manual control (goto, RTH); preempts pending lower-priority commands
+    pub const HIGH: u32 = 2;
+
+    /// Reserved for emergency stop
+    pub const EMERGENCY: u32 = 3;
+}
+
+/// Why an envelope can't be routed
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeError {
+    #[error("Envelope has no header")]
+    MissingHeader,
+
+    /// Also what a payload type added by a newer peer decodes to
+    #[error("Envelope has no payload (empty or unknown payload type)")]
+    MissingPayload,
+}
+
+impl Envelope {
+    /// Check the envelope has a header and a payload
+    ///
+    /// Received envelopes are validated before routing, so handlers can rely
+    /// on both being present.
+    pub fn validate(&self) -> Result<(), EnvelopeError> {
+        if self.header.is_none() {
+            return Err(EnvelopeError::MissingHeader);
+        }
+        if self.payload.is_none() {
+            return Err(EnvelopeError::MissingPayload);
+        }
+        Ok(())
+    }
+
+    /// Delivery priority from the header, clamped to `priority::EMERGENCY`
+    ///
+    /// Envelopes without a header (or from peers predating the field) are
+    /// `priority::LOW`.
+    pub fn priority(&self) -> u32 {
+        self.header
+            .as_ref()
+            .map_or(priority::LOW, |h| h.priority.min(priority::EMERGENCY))
+    }
 }
 
 /// Builder helpers for creating messages
 impl Header {
     /// Create a new header with the given device ID and message type
+    #[cfg(feature = "std")]
     pub fn new(device_id: impl Into<String>, msg_type: MessageType, sequence_id: u64) -> Self {
+        Self::with_timestamp(device_id, msg_type, sequence_id, now_ms())
+    }
+
+    /// Validate the sender's device ID and normalize it in place
+    pub fn normalize_device_id(&mut self) -> Result<DeviceId, DeviceIdError> {
+        let id = DeviceId::parse(&self.device_id)?;
+        self.device_id = String::from(&id);
+        Ok(id)
+    }
+
+    /// Create a header with an explicit timestamp (for targets without a wall clock)
+    pub fn with_timestamp(
+        device_id: impl Into<String>,
+        msg_type: MessageType,
+        sequence_id: u64,
+        timestamp_ms: u64,
+    ) -> Self {
         Self {
             device_id: device_id.into(),
             sequence_id,
-            timestamp_ms: now_ms(),
+            timestamp_ms,
             msg_type: msg_type.into(),
+            priority: priority::LOW,
+            retry_count: 0,
+            hop_count: 0,
         }
     }
+
+    /// Set the delivery priority (see [`priority`])
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Mark the message as the `retry_count`-th resend
+    pub fn with_retry_count(mut self, retry_count: u32) -> Self {
+        self.retry_count = retry_count;
+        self
+    }
 }
 
 impl Heartbeat {
@@ -66,6 +229,80 @@ impl Heartbeat {
             state: state.into(),
             pending_commands,
             healthy,
+            battery: None,
+            gps_fix: false,
+            relay: None,
+        }
+    }
+
+    /// Report the FC's battery status
+    pub fn with_battery(mut self, battery: BatteryStatus) -> Self {
+        self.battery = Some(battery);
+        self
+    }
+
+    /// Report whether the GPS has a 3D fix
+    pub fn with_gps_fix(mut self, gps_fix: bool) -> Self {
+        self.gps_fix = gps_fix;
+        self
+    }
+
+    /// Report a relay's own health
+    pub fn with_relay_status(mut self, relay: RelayStatus) -> Self {
+        self.relay = Some(relay);
+        self
+    }
+
+    /// Battery level in percent, if the FC has reported one
+    pub fn battery_percent(&self) -> Option<u32> {
+        self.battery.as_ref().map(|battery| battery.remaining_percent)
+    }
+}
+
+impl Hello {
+    /// Create a hello message reporting this drone's safety configuration
+    pub fn new(safety_config: SafetyConfig) -> Self {
+        Self {
+            safety_config: Some(safety_config),
+            compression: Vec::new(),
+            protocol_version: PROTOCOL_VERSION,
+            session_token: String::new(),
+            session_resumed: false,
+            device_type: DeviceType::DeviceDrone.into(),
+        }
+    }
+
+    /// Create a hello for a relay opening a session of its own (no safety config)
+    pub fn relay() -> Self {
+        Self {
+            safety_config: None,
+            device_type: DeviceType::DeviceRelay.into(),
+            ..Self::new(SafetyConfig::default())
+        }
+    }
+
+    /// Ask to resume the session the server issued `token` for
+    pub fn with_session_token(mut self, token: impl Into<String>) -> Self {
+        self.session_token = token.into();
+        self
+    }
+
+    /// Advertise the frame compression this peer supports
+    pub fn with_compression(mut self, compression: &[Compression]) -> Self {
+        self.compression = compression.iter().map(|c| *c as i32).collect();
+        self
+    }
+}
+
+impl SafetyConfig {
+    /// The failsafe configuration enforced by the shared safety state machine
+    pub fn from_defaults() -> Self {
+        Self {
+            battery_critical_percent: safety::BATTERY_CRITICAL_PERCENT,
+            heartbeat_timeout_ms: safety::HEARTBEAT_TIMEOUT_MS,
+            battery_critical_action: FailsafeAction::FailsafeRth.into(),
+            heartbeat_loss_action: FailsafeAction::FailsafeRth.into(),
+            geofence_breach_action: FailsafeAction::FailsafeRth.into(),
         }
     }
 }
@@ -79,6 +316,12 @@ impl Ack {
             status: AckStatus::AckReceived.into(),
             message: String::new(),
             processing_time_ms: 0,
+            faults: Vec::new(),
+            current_waypoint: None,
+            progress: None,
+            capabilities: Vec::new(),
+            video_stream: None,
+            fc_params: Vec::new(),
         }
     }
 
@@ -90,6 +333,12 @@ impl Ack {
             status: AckStatus::AckCompleted.into(),
             message: String::new(),
             processing_time_ms,
+            faults: Vec::new(),
+            current_waypoint: None,
+            progress: None,
+            capabilities: Vec::new(),
+            video_stream: None,
+            fc_params: Vec::new(),
         }
     }
 
@@ -101,6 +350,12 @@ impl Ack {
             status: AckStatus::AckFailed.into(),
             message: message.into(),
             processing_time_ms: 0,
+            faults: Vec::new(),
+            current_waypoint: None,
+            progress: None,
+            capabilities: Vec::new(),
+            video_stream: None,
+            fc_params: Vec::new(),
         }
     }
 
@@ -112,6 +367,12 @@ impl Ack {
             status: AckStatus::AckRejected.into(),
             message: message.into(),
             processing_time_ms: 0,
+            faults: Vec::new(),
+            current_waypoint: None,
+            progress: None,
+            capabilities: Vec::new(),
+            video_stream: None,
+            fc_params: Vec::new(),
         }
     }
 
@@ -123,17 +384,41 @@ impl Ack {
             status: AckStatus::AckExpired.into(),
             message: "Command expired".into(),
             processing_time_ms: 0,
+            faults: Vec::new(),
+            current_waypoint: None,
+            progress: None,
+            capabilities: Vec::new(),
+            video_stream: None,
+            fc_params: Vec::new(),
         }
     }
 }
 
 impl Command {
     /// Check if this command has expired
+    #[cfg(feature = "std")]
     pub fn is_expired(&self) -> bool {
+        self.is_expired_at(now_ms())
+    }
+
+    /// Check if this command has expired at the given time
+    pub fn is_expired_at(&self, current_time_ms: u64) -> bool {
         if self.expires_at_ms == 0 {
             return false; // No expiry set
         }
-        now_ms() > self.expires_at_ms
+        current_time_ms > self.expires_at_ms
+    }
+
+    /// Priority used for queueing and preemption
+    ///
+    /// Emergency stops always run at `priority::EMERGENCY`; any other command
+    /// is capped at `priority::HIGH` so it can't claim the reserved level.
+    pub fn effective_priority(&self) -> u32 {
+        if self.cmd_type == CommandType::CmdEmergencyStop as i32 {
+            priority::EMERGENCY
+        } else {
+            self.priority.min(priority::HIGH)
+        }
     }
 }
 
@@ -149,6 +434,46 @@ mod tests {
         assert!(header.timestamp_ms > 0);
     }
 
+    #[test]
+    fn test_header_normalize_device_id() {
+        let mut header = Header::new(" Edge-001", MessageType::MsgHeartbeat, 1);
+        assert_eq!(header.normalize_device_id().unwrap().as_str(), "edge-001");
+        assert_eq!(header.device_id, "edge-001");
+
+        let mut bad = Header::new("edge\u{1b}[2J", MessageType::MsgHeartbeat, 1);
+        assert_eq!(bad.normalize_device_id(), Err(DeviceIdError::InvalidChar('\u{1b}')));
+    }
+
+    #[test]
+    fn test_envelope_validate() {
+        let heartbeat = Envelope {
+            header: Some(Header::new("edge-001", MessageType::MsgHeartbeat, 1)),
+            payload: Some(envelope::Payload::Heartbeat(Heartbeat::new(
+                1000,
+                DroneState::DroneIdle,
+                0,
+                true,
+            ))),
+            signature: Vec::new(),
+        };
+        assert_eq!(heartbeat.validate(), Ok(()));
+
+        let headerless = Envelope {
+            header: None,
+            ..heartbeat.clone()
+        };
+        assert_eq!(headerless.validate(), Err(EnvelopeError::MissingHeader));
+
+        let header_only = Envelope {
+            payload: None,
+            ..heartbeat
+        };
+        assert_eq!(header_only.validate(), Err(EnvelopeError::MissingPayload));
+
+        // A zero-length frame decodes to an empty envelope
+        assert_eq!(Envelope::default().validate(), Err(EnvelopeError::MissingHeader));
+    }
+
     #[test]
     fn test_heartbeat_creation() {
         let hb = Heartbeat::new(1000, DroneState::DroneIdle, 0, true);
@@ -156,6 +481,14 @@ mod tests {
         assert!(hb.healthy);
     }
 
+    #[test]
+    fn test_safety_config_matches_constants() {
+        let config = SafetyConfig::from_defaults();
+        assert_eq!(config.battery_critical_percent, safety::BATTERY_CRITICAL_PERCENT);
+        assert_eq!(config.heartbeat_timeout_ms, safety::HEARTBEAT_TIMEOUT_MS);
+        assert_eq!(config.heartbeat_loss_action(), FailsafeAction::FailsafeRth);
+    }
+
     #[test]
     fn test_ack_creation() {
         let ack = Ack::completed(1, 100, 50);
@@ -163,4 +496,37 @@ mod tests {
         assert_eq!(ack.command_id, 100);
         assert_eq!(ack.processing_time_ms, 50);
     }
+
+    #[test]
+    fn test_effective_priority_reserves_emergency() {
+        let goto = Command {
+            cmd_type: CommandType::CmdRth.into(),
+            priority: priority::EMERGENCY,
+            ..Default::default()
+        };
+        assert_eq!(goto.effective_priority(), priority::HIGH);
+
+        let stop = Command {
+            cmd_type: CommandType::CmdEmergencyStop.into(),
+            priority: priority::LOW,
+            ..Default::default()
+        };
+        assert_eq!(stop.effective_priority(), priority::EMERGENCY);
+    }
+
+    #[test]
+    fn test_envelope_priority_from_header() {
+        let mut envelope = Envelope::default();
+        assert_eq!(envelope.priority(), priority::LOW);
+
+        envelope.header = Some(
+            Header::with_timestamp("edge-001", MessageType::MsgAck, 1, 0)
+                .with_priority(priority::HIGH),
+        );
+        assert_eq!(envelope.priority(), priority::HIGH);
+
+        // Out-of-range values from a misbehaving peer don't outrank emergencies
+        envelope.header.as_mut().unwrap().priority = 99;
+        assert_eq!(envelope.priority(), priority::EMERGENCY);
+    }
 }
@@ -0,0 +1,251 @@
+//! Connection quality history
+//!
+//! A bounded ring buffer of timestamped `ConnectionQuality` samples, kept by
+//! the edge for its own link and by the server per session, so link trends
+//! (improving or degrading) can be plotted and acted on, not just the latest level.
+//!
+//! The edge measures the samples with `LinkStats`, from its heartbeats and
+//! the server's acks.
+
+use crate::ConnectionQuality;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// Default number of samples kept (about two minutes at one sample per second)
+pub const QUALITY_HISTORY_LEN: usize = 120;
+
+/// Default number of heartbeats packet loss is measured over
+pub const LOSS_WINDOW_LEN: usize = 30;
+
+/// A connection quality reading and when it was taken
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualitySample {
+    /// Milliseconds since Unix epoch
+    pub at_ms: u64,
+    pub quality: ConnectionQuality,
+}
+
+/// Most recent quality samples, oldest evicted first
+#[derive(Debug, Clone)]
+pub struct QualityHistory {
+    capacity: usize,
+    samples: VecDeque<QualitySample>,
+}
+
+impl QualityHistory {
+    /// Keep up to `capacity` samples (at least one)
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Record a sample, evicting the oldest when full
+    pub fn push(&mut self, at_ms: u64, quality: ConnectionQuality) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(QualitySample { at_ms, quality });
+    }
+
+    /// Retained samples, oldest first
+    pub fn samples(&self) -> Vec<QualitySample> {
+        self.samples.iter().cloned().collect()
+    }
+
+    /// Most recent sample
+    pub fn latest(&self) -> Option<&QualitySample> {
+        self.samples.back()
+    }
+
+    /// Number of retained samples
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Whether no samples have been recorded
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Drop all samples (e.g. after switching transport)
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Least-squares slope of `metric` per second over the retained samples
+    ///
+    /// `None` with fewer than two samples or when they share one timestamp.
+    pub fn trend(&self, metric: impl Fn(&ConnectionQuality) -> f64) -> Option<f64> {
+        let first = self.samples.front()?.at_ms;
+        let points: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .map(|s| (s.at_ms.saturating_sub(first) as f64 / 1000.0, metric(&s.quality)))
+            .collect();
+        if points.len() < 2 {
+            return None;
+        }
+
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_v = points.iter().map(|(_, v)| v).sum::<f64>() / n;
+        let (cov, var) = points.iter().fold((0.0, 0.0), |(cov, var), (t, v)| {
+            let dt = t - mean_t;
+            (cov + dt * (v - mean_v), var + dt * dt)
+        });
+        (var > 0.0).then(|| cov / var)
+    }
+}
+
+impl Default for QualityHistory {
+    fn default() -> Self {
+        Self::new(QUALITY_HISTORY_LEN)
+    }
+}
+
+/// Round trip, jitter and loss of one transport, from heartbeat acks
+///
+/// Jitter is the smoothed difference between successive round trips (as in
+/// RFC 3550). Loss counts unacked heartbeats among the last `window_len`,
+/// up to the newest acked one; later heartbeats may still be in flight.
+#[derive(Debug, Clone)]
+pub struct LinkStats {
+    window_len: usize,
+    /// Sequence IDs of recent heartbeats, oldest first, and whether acked
+    sent: VecDeque<(u64, bool)>,
+    last_rtt_ms: Option<u32>,
+    jitter_ms: f64,
+}
+
+impl LinkStats {
+    /// Measure loss over the last `window_len` heartbeats (at least one)
+    pub fn new(window_len: usize) -> Self {
+        Self {
+            window_len: window_len.max(1),
+            sent: VecDeque::new(),
+            last_rtt_ms: None,
+            jitter_ms: 0.0,
+        }
+    }
+
+    /// Record a heartbeat sent with `sequence_id`
+    pub fn sent(&mut self, sequence_id: u64) {
+        if self.sent.len() == self.window_len {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((sequence_id, false));
+    }
+
+    /// Record the ack of heartbeat `sequence_id` and its round trip, if known
+    ///
+    /// Returns false for a heartbeat not in the window (too old, or not ours).
+    pub fn acked(&mut self, sequence_id: u64, rtt_ms: Option<u32>) -> bool {
+        let Some(entry) = self.sent.iter_mut().find(|(seq, _)| *seq == sequence_id) else {
+            return false;
+        };
+        entry.1 = true;
+
+        if let Some(rtt_ms) = rtt_ms {
+            if let Some(last) = self.last_rtt_ms {
+                let delta = (rtt_ms as i64 - last as i64).unsigned_abs() as f64;
+                self.jitter_ms += (delta - self.jitter_ms) / 16.0;
+            }
+            self.last_rtt_ms = Some(rtt_ms);
+        }
+        true
+    }
+
+    /// Latest round trip
+    pub fn rtt_ms(&self) -> Option<u32> {
+        self.last_rtt_ms
+    }
+
+    /// Smoothed round-trip variation
+    pub fn jitter_ms(&self) -> u32 {
+        self.jitter_ms as u32
+    }
+
+    /// Share of heartbeats up to the newest acked one that were not acked
+    pub fn loss_percent(&self) -> f32 {
+        let Some(newest_acked) = self.sent.iter().rposition(|(_, acked)| *acked) else {
+            return 0.0;
+        };
+        let counted = newest_acked + 1;
+        let lost = self.sent.iter().take(counted).filter(|(_, acked)| !acked).count();
+        lost as f32 * 100.0 / counted as f32
+    }
+
+    /// The measurements as a quality sample for `transport`
+    pub fn quality(&self, transport: crate::Transport) -> ConnectionQuality {
+        ConnectionQuality {
+            active_transport: transport.into(),
+            rssi_dbm: 0,
+            latency_ms: self.last_rtt_ms.unwrap_or(0),
+            packet_loss_percent: self.loss_percent(),
+            jitter_ms: self.jitter_ms(),
+        }
+    }
+}
+
+impl Default for LinkStats {
+    fn default() -> Self {
+        Self::new(LOSS_WINDOW_LEN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latency(latency_ms: u32) -> ConnectionQuality {
+        ConnectionQuality {
+            latency_ms,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_samples_accumulate_and_evict_oldest() {
+        let mut history = QualityHistory::new(3);
+        assert!(history.is_empty());
+        assert_eq!(history.trend(|q| q.latency_ms as f64), None);
+
+        for (i, ms) in [40, 50, 60, 80].into_iter().enumerate() {
+            history.push(i as u64 * 1000, latency(ms));
+        }
+
+        let samples = history.samples();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0].at_ms, 1000);
+        assert_eq!(samples[0].quality.latency_ms, 50);
+        assert_eq!(history.latest().unwrap().quality.latency_ms, 80);
+
+        // 50, 60, 80 ms over two seconds: degrading by 15 ms/s
+        let slope = history.trend(|q| q.latency_ms as f64).unwrap();
+        assert!((slope - 15.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_link_stats_rtt_jitter_and_loss() {
+        let mut stats = LinkStats::new(4);
+        assert_eq!(stats.loss_percent(), 0.0);
+
+        for seq in 1..=5 {
+            stats.sent(seq);
+        }
+        // Heartbeat 1 fell out of the window; 3 was lost, 5 is in flight
+        assert!(!stats.acked(1, Some(40)));
+        assert!(stats.acked(2, Some(40)));
+        assert!(stats.acked(4, Some(56)));
+        assert_eq!(stats.rtt_ms(), Some(56));
+        assert_eq!(stats.jitter_ms(), 1); // 16 ms change, smoothed by 1/16
+
+        let quality = stats.quality(crate::Transport::Bluetooth);
+        assert_eq!(quality.latency_ms, 56);
+        assert!((quality.packet_loss_percent - 100.0 / 3.0).abs() < 1e-3);
+        assert_eq!(quality.active_transport(), crate::Transport::Bluetooth);
+    }
+}
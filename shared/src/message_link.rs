@@ -0,0 +1,151 @@
+//! Byte streams over message-oriented links
+//!
+//! UDP, QUIC and WebSocket links move whole messages, not bytes. A
+//! [`MessageLink`] hides that behind `AsyncRead` / `AsyncWrite`: whatever is
+//! written between two flushes is handed to the link's task as one message,
+//! and received messages are read back to back. Frame writers flush after
+//! whole frames, so a message always holds whole frames.
+
+use crate::{codec, envelope};
+use bytes::{Buf, Bytes, BytesMut};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use tokio_util::sync::PollSender;
+
+/// Messages queued each way between a link and its task
+const QUEUE_LEN: usize = 32;
+
+/// The task's ends of a link's queues
+pub(crate) struct LinkChannels {
+    /// Messages written to the link
+    pub outgoing: mpsc::Receiver<Bytes>,
+    /// Messages to read from the link; an error ends it
+    pub incoming: mpsc::Sender<io::Result<Bytes>>,
+}
+
+/// One end of a UDP, QUIC or WebSocket link, read and written as a byte stream
+///
+/// A background task does the network work; dropping the link closes it.
+pub struct MessageLink {
+    /// Bytes written since the last flush
+    pending: BytesMut,
+    outgoing: PollSender<Bytes>,
+    incoming: mpsc::Receiver<io::Result<Bytes>>,
+    /// Rest of the message being read
+    read_buf: Bytes,
+}
+
+impl MessageLink {
+    pub(crate) fn new() -> (Self, LinkChannels) {
+        let (outgoing_tx, outgoing) = mpsc::channel(QUEUE_LEN);
+        let (incoming, incoming_rx) = mpsc::channel(QUEUE_LEN);
+        let link = Self {
+            pending: BytesMut::new(),
+            outgoing: PollSender::new(outgoing_tx),
+            incoming: incoming_rx,
+            read_buf: Bytes::new(),
+        };
+        (link, LinkChannels { outgoing, incoming })
+    }
+}
+
+impl AsyncRead for MessageLink {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.read_buf.is_empty() {
+            match ready!(this.incoming.poll_recv(cx)) {
+                Some(message) => this.read_buf = message?,
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = buf.remaining().min(this.read_buf.len());
+        buf.put_slice(&this.read_buf[..n]);
+        this.read_buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MessageLink {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().pending.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pending.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        let closed = |_| io::Error::new(io::ErrorKind::BrokenPipe, "link closed");
+        ready!(this.outgoing.poll_reserve(cx)).map_err(closed)?;
+        let message = this.pending.split().freeze();
+        this.outgoing.send_item(message).map_err(closed)?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.get_mut().outgoing.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Whether `message` is exactly one telemetry frame
+///
+/// Such messages may be lost or reordered without harm: the next keyframe
+/// makes up for them. Anything else, Noise ciphertext included, is not.
+pub fn is_telemetry_frame(message: &[u8]) -> bool {
+    let mut buf = BytesMut::from(message);
+    match codec::decode(&mut buf) {
+        Ok(Some(envelope)) if buf.is_empty() => matches!(
+            envelope.payload,
+            Some(
+                envelope::Payload::Telemetry(_)
+                    | envelope::Payload::TelemetryDelta(_)
+                    | envelope::Payload::TelemetryBatch(_)
+            )
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Header, Heartbeat, MessageType, Telemetry};
+
+    fn frame(payload: envelope::Payload) -> Bytes {
+        let envelope = crate::Envelope {
+            header: Some(Header::new("edge-001", MessageType::MsgUnknown, 1)),
+            payload: Some(payload),
+            signature: Vec::new(),
+        };
+        codec::encode(&envelope).unwrap()
+    }
+
+    #[test]
+    fn test_only_single_telemetry_frames_qualify() {
+        let telemetry = frame(envelope::Payload::Telemetry(Telemetry::default()));
+        assert!(is_telemetry_frame(&telemetry));
+
+        let heartbeat = Heartbeat::new(1000, crate::DroneState::DroneIdle, 0, true);
+        assert!(!is_telemetry_frame(&frame(envelope::Payload::Heartbeat(heartbeat))));
+
+        // Two frames in one message, or bytes that aren't a frame, don't
+        let mut two = BytesMut::from(&telemetry[..]);
+        two.extend_from_slice(&telemetry);
+        assert!(!is_telemetry_frame(&two));
+        assert!(!is_telemetry_frame(b"\x00\x00\x00\x05noise"));
+    }
+}
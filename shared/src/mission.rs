@@ -0,0 +1,615 @@
+//! Mission definition files
+//!
+//! Operators plan missions as YAML or JSON files: the survey area, altitude,
+//! speed and pattern, plus an optional geofence and RTH settings for the
+//! flight. `MissionPlan` is that schema. It converts to the `MissionStart`
+//! sent to the drone (and back), and `validate` catches what the drone would
+//! only find out in the air: a boundary that crosses itself, an altitude
+//! outside the flight envelope, a mission above its own geofence ceiling.
+//!
+//! The HTTP control API takes the same fields as its `mission_start` body,
+//! so a file can be posted as is.
+//!
+//! ```yaml
+//! mission_id: flood-sector-7
+//! pattern: lawnmower
+//! altitude_m: 40
+//! speed_mps: 8
+//! boundary:
+//!   - { latitude: 47.500, longitude: 8.500 }
+//!   - { latitude: 47.500, longitude: 8.520 }
+//!   - { latitude: 47.520, longitude: 8.520 }
+//! geofence:
+//!   max_altitude_m: 100
+//! rth:
+//!   altitude_m: 60
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    GpsCoordinate, MissionGeofence, MissionStart, ReturnToHome, ScanPattern, SensorConfig,
+    SurveyArea, MAX_MISSION_WAYPOINTS,
+};
+
+/// Lowest mission, RTH or waypoint altitude above home (m)
+pub const MIN_MISSION_ALTITUDE_M: f32 = 2.0;
+
+/// Highest mission, RTH or waypoint altitude above home (m), the usual
+/// regulatory ceiling for drones
+pub const MAX_MISSION_ALTITUDE_M: f32 = 120.0;
+
+/// Fastest mission or RTH speed (m/s)
+pub const MAX_MISSION_SPEED_MPS: f32 = 20.0;
+
+/// A point as mission files give it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Waypoints only; 0 = the mission altitude
+    #[serde(default)]
+    pub altitude_m: f32,
+}
+
+impl From<Point> for GpsCoordinate {
+    fn from(point: Point) -> Self {
+        Self {
+            latitude: point.latitude,
+            longitude: point.longitude,
+            altitude_m: point.altitude_m,
+        }
+    }
+}
+
+impl From<&GpsCoordinate> for Point {
+    fn from(coordinate: &GpsCoordinate) -> Self {
+        Self {
+            latitude: coordinate.latitude,
+            longitude: coordinate.longitude,
+            altitude_m: coordinate.altitude_m,
+        }
+    }
+}
+
+/// How the drone covers the survey area (`ScanPattern`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    Lawnmower,
+    Spiral,
+    Grid,
+    Perimeter,
+    /// Flies the mission's `waypoints`
+    Custom,
+}
+
+impl From<Pattern> for ScanPattern {
+    fn from(pattern: Pattern) -> Self {
+        match pattern {
+            Pattern::Lawnmower => ScanPattern::PatternLawnmower,
+            Pattern::Spiral => ScanPattern::PatternSpiral,
+            Pattern::Grid => ScanPattern::PatternGrid,
+            Pattern::Perimeter => ScanPattern::PatternPerimeter,
+            Pattern::Custom => ScanPattern::PatternCustom,
+        }
+    }
+}
+
+impl Pattern {
+    /// None for `PATTERN_UNKNOWN`
+    fn from_proto(pattern: ScanPattern) -> Option<Self> {
+        match pattern {
+            ScanPattern::PatternUnknown => None,
+            ScanPattern::PatternLawnmower => Some(Pattern::Lawnmower),
+            ScanPattern::PatternSpiral => Some(Pattern::Spiral),
+            ScanPattern::PatternGrid => Some(Pattern::Grid),
+            ScanPattern::PatternPerimeter => Some(Pattern::Perimeter),
+            ScanPattern::PatternCustom => Some(Pattern::Custom),
+        }
+    }
+}
+
+/// A sensor to run during the mission (`SensorConfig`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorPlan {
+    /// `GPR`, `LIDAR`, ...
+    pub sensor_type: String,
+    #[serde(default)]
+    pub sample_rate_hz: u32,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
+}
+
+/// Where the mission may fly; unset limits are not enforced
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GeofencePlan {
+    /// Allowed area
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub polygon: Vec<Point>,
+    /// Ceiling above home
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_altitude_m: Option<f32>,
+    /// Radius around home
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_distance_m: Option<f64>,
+}
+
+/// Return-to-home settings for the mission; 0 = the drone's default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct RthPlan {
+    #[serde(default)]
+    pub altitude_m: f32,
+    #[serde(default)]
+    pub speed_mps: f32,
+}
+
+/// A mission as planned in a mission file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MissionPlan {
+    pub mission_id: String,
+    /// Omitted: `custom` with `waypoints`, else the boundary vertices as given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<Pattern>,
+    /// Above home
+    pub altitude_m: f32,
+    /// 0 = the drone's default
+    #[serde(default)]
+    pub speed_mps: f32,
+    /// The survey area polygon
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub boundary: Vec<Point>,
+    /// Where the drone returns to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home: Option<Point>,
+    /// Flown in order; uploaded ahead of the mission start
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub waypoints: Vec<Point>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sensors: Vec<SensorPlan>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geofence: Option<GeofencePlan>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rth: Option<RthPlan>,
+    /// Supersede a mission in progress instead of being rejected
+    #[serde(default)]
+    pub replace: bool,
+}
+
+/// Why a mission plan can't be flown
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MissionError {
+    #[error("Mission has no mission_id")]
+    MissingId,
+
+    #[error("A mission needs a pattern, waypoints or a boundary")]
+    NoPattern,
+
+    #[error("Pattern {0:?} needs a boundary")]
+    NoBoundary(Pattern),
+
+    #[error("A custom pattern needs waypoints")]
+    NoWaypoints,
+
+    #[error("{count} waypoints, at most {} fit a mission", MAX_MISSION_WAYPOINTS)]
+    TooManyWaypoints { count: usize },
+
+    /// `what` is `boundary` or `geofence polygon`
+    #[error("The {what} needs at least 3 vertices")]
+    TooFewVertices { what: &'static str },
+
+    #[error("The {what} crosses itself")]
+    SelfIntersecting { what: &'static str },
+
+    #[error("Coordinate {latitude},{longitude} is out of range")]
+    InvalidCoordinate { latitude: f64, longitude: f64 },
+
+    /// `what` is `Mission`, `Waypoint` or `RTH`
+    #[error(
+        "{what} altitude {altitude_m}m is outside {}-{}m",
+        MIN_MISSION_ALTITUDE_M,
+        MAX_MISSION_ALTITUDE_M
+    )]
+    AltitudeOutOfRange { what: &'static str, altitude_m: f32 },
+
+    #[error("{what} speed {speed_mps}m/s is outside 0-{}m/s", MAX_MISSION_SPEED_MPS)]
+    SpeedOutOfRange { what: &'static str, speed_mps: f32 },
+
+    #[error("Geofence limits must be positive")]
+    InvalidGeofenceLimit,
+
+    #[error("Mission altitude {altitude_m}m is above the geofence ceiling {ceiling_m}m")]
+    AboveGeofence { altitude_m: f32, ceiling_m: f32 },
+}
+
+/// Why a mission file can't be loaded
+#[derive(Error, Debug)]
+pub enum MissionFileError {
+    #[error("Cannot read mission file: {0}")]
+    Read(#[from] std::io::Error),
+
+    #[error("Invalid mission file: {0}")]
+    Parse(String),
+
+    #[error(transparent)]
+    Invalid(#[from] MissionError),
+}
+
+impl MissionPlan {
+    /// Parse a JSON mission file (not validated)
+    pub fn from_json(text: &str) -> Result<Self, MissionFileError> {
+        serde_json::from_str(text).map_err(|e| MissionFileError::Parse(e.to_string()))
+    }
+
+    /// Parse a YAML mission file (not validated)
+    pub fn from_yaml(text: &str) -> Result<Self, MissionFileError> {
+        serde_yaml::from_str(text).map_err(|e| MissionFileError::Parse(e.to_string()))
+    }
+
+    /// Read and validate a mission file: YAML for `.yaml` and `.yml`, JSON
+    /// otherwise
+    pub fn load(path: &Path) -> Result<Self, MissionFileError> {
+        let text = std::fs::read_to_string(path)?;
+        let plan = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&text)?,
+            _ => Self::from_json(&text)?,
+        };
+        plan.validate()?;
+        Ok(plan)
+    }
+
+    /// Rebuild the plan of a mission start and the waypoints uploaded for it
+    pub fn from_mission_start(mission: &MissionStart, waypoints: &[GpsCoordinate]) -> Self {
+        let area = mission.survey_area.as_ref();
+        let sensors = mission
+            .sensors
+            .iter()
+            .map(|sensor| SensorPlan {
+                sensor_type: sensor.sensor_type.clone(),
+                sample_rate_hz: sensor.sample_rate_hz,
+                params: sensor.params.clone(),
+            })
+            .collect();
+        let geofence = mission.geofence.as_ref().map(|fence| GeofencePlan {
+            polygon: fence.polygon.iter().map(Point::from).collect(),
+            max_altitude_m: Some(fence.max_altitude_m).filter(|limit| *limit != 0.0),
+            max_distance_m: Some(fence.max_distance_m).filter(|limit| *limit != 0.0),
+        });
+        Self {
+            mission_id: mission.mission_id.clone(),
+            pattern: Pattern::from_proto(mission.scan_pattern()),
+            altitude_m: mission.altitude_m,
+            speed_mps: mission.speed_mps,
+            boundary: area.map_or_else(Vec::new, |a| a.boundary.iter().map(Point::from).collect()),
+            home: area.and_then(|a| a.home_position.as_ref()).map(Point::from),
+            waypoints: waypoints.iter().map(Point::from).collect(),
+            sensors,
+            geofence,
+            rth: mission.rth.map(|rth| RthPlan {
+                altitude_m: rth.altitude_m,
+                speed_mps: rth.speed_mps,
+            }),
+            replace: mission.replace,
+        }
+    }
+
+    /// The pattern sent to the drone
+    pub fn scan_pattern(&self) -> ScanPattern {
+        match self.pattern {
+            Some(pattern) => pattern.into(),
+            None if !self.waypoints.is_empty() => ScanPattern::PatternCustom,
+            None => ScanPattern::PatternUnknown,
+        }
+    }
+
+    /// The mission start for the drone; `waypoints` are uploaded before it
+    pub fn mission_start(&self) -> MissionStart {
+        let sensors = self
+            .sensors
+            .iter()
+            .map(|sensor| SensorConfig {
+                sensor_type: sensor.sensor_type.clone(),
+                sample_rate_hz: sensor.sample_rate_hz,
+                params: sensor.params.clone(),
+            })
+            .collect();
+        let geofence = self.geofence.as_ref().map(|fence| MissionGeofence {
+            polygon: fence.polygon.iter().copied().map(Into::into).collect(),
+            max_altitude_m: fence.max_altitude_m.unwrap_or_default(),
+            max_distance_m: fence.max_distance_m.unwrap_or_default(),
+        });
+        MissionStart {
+            mission_id: self.mission_id.clone(),
+            survey_area: Some(SurveyArea {
+                boundary: self.boundary.iter().copied().map(Into::into).collect(),
+                home_position: self.home.map(Into::into),
+            }),
+            scan_pattern: self.scan_pattern().into(),
+            altitude_m: self.altitude_m,
+            speed_mps: self.speed_mps,
+            sensors,
+            replace: self.replace,
+            uploaded_waypoints: self.waypoints.len() as u32,
+            geofence,
+            rth: self.rth.map(|rth| ReturnToHome {
+                altitude_m: rth.altitude_m,
+                speed_mps: rth.speed_mps,
+            }),
+        }
+    }
+
+    /// The waypoints to upload ahead of the mission start
+    pub fn waypoints(&self) -> Vec<GpsCoordinate> {
+        self.waypoints.iter().copied().map(Into::into).collect()
+    }
+
+    /// Check the plan can be flown as given
+    pub fn validate(&self) -> Result<(), MissionError> {
+        if self.mission_id.trim().is_empty() {
+            return Err(MissionError::MissingId);
+        }
+        check_altitude("Mission", self.altitude_m)?;
+        check_speed("Mission", self.speed_mps)?;
+
+        let points = self.boundary.iter().chain(&self.home).chain(&self.waypoints);
+        for point in points {
+            check_coordinate(point)?;
+        }
+        if !self.boundary.is_empty() {
+            check_polygon("boundary", &self.boundary)?;
+        }
+
+        match self.pattern {
+            Some(Pattern::Custom) if self.waypoints.is_empty() => return Err(MissionError::NoWaypoints),
+            Some(Pattern::Custom) => {}
+            Some(pattern) if self.boundary.is_empty() => return Err(MissionError::NoBoundary(pattern)),
+            Some(_) => {}
+            None if self.waypoints.is_empty() && self.boundary.is_empty() => {
+                return Err(MissionError::NoPattern);
+            }
+            None => {}
+        }
+        if self.waypoints.len() > MAX_MISSION_WAYPOINTS {
+            return Err(MissionError::TooManyWaypoints {
+                count: self.waypoints.len(),
+            });
+        }
+        for waypoint in self.waypoints.iter().filter(|w| w.altitude_m != 0.0) {
+            check_altitude("Waypoint", waypoint.altitude_m)?;
+        }
+
+        if let Some(fence) = &self.geofence {
+            for point in &fence.polygon {
+                check_coordinate(point)?;
+            }
+            if !fence.polygon.is_empty() {
+                check_polygon("geofence polygon", &fence.polygon)?;
+            }
+            let altitude_ok = fence.max_altitude_m.is_none_or(|limit| limit > 0.0);
+            if !altitude_ok || !fence.max_distance_m.is_none_or(|limit| limit > 0.0) {
+                return Err(MissionError::InvalidGeofenceLimit);
+            }
+            if let Some(ceiling_m) = fence.max_altitude_m {
+                if self.altitude_m > ceiling_m {
+                    return Err(MissionError::AboveGeofence {
+                        altitude_m: self.altitude_m,
+                        ceiling_m,
+                    });
+                }
+            }
+        }
+
+        if let Some(rth) = &self.rth {
+            if rth.altitude_m != 0.0 {
+                check_altitude("RTH", rth.altitude_m)?;
+            }
+            check_speed("RTH", rth.speed_mps)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_altitude(what: &'static str, altitude_m: f32) -> Result<(), MissionError> {
+    if (MIN_MISSION_ALTITUDE_M..=MAX_MISSION_ALTITUDE_M).contains(&altitude_m) {
+        Ok(())
+    } else {
+        Err(MissionError::AltitudeOutOfRange { what, altitude_m })
+    }
+}
+
+fn check_speed(what: &'static str, speed_mps: f32) -> Result<(), MissionError> {
+    if (0.0..=MAX_MISSION_SPEED_MPS).contains(&speed_mps) {
+        Ok(())
+    } else {
+        Err(MissionError::SpeedOutOfRange { what, speed_mps })
+    }
+}
+
+fn check_coordinate(point: &Point) -> Result<(), MissionError> {
+    if (-90.0..=90.0).contains(&point.latitude) && (-180.0..=180.0).contains(&point.longitude) {
+        Ok(())
+    } else {
+        Err(MissionError::InvalidCoordinate {
+            latitude: point.latitude,
+            longitude: point.longitude,
+        })
+    }
+}
+
+/// A simple polygon: at least three vertices and no edges crossing
+fn check_polygon(what: &'static str, polygon: &[Point]) -> Result<(), MissionError> {
+    if polygon.len() < 3 {
+        return Err(MissionError::TooFewVertices { what });
+    }
+    let n = polygon.len();
+    let edge = |i: usize| (&polygon[i], &polygon[(i + 1) % n]);
+    for i in 0..n {
+        // Neighbouring edges share a vertex; only the others may not meet
+        for j in i + 2..n {
+            if i == 0 && j == n - 1 {
+                continue;
+            }
+            if segments_intersect(edge(i), edge(j)) {
+                return Err(MissionError::SelfIntersecting { what });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether two edges touch or cross, treating degrees as planar
+fn segments_intersect((a, b): (&Point, &Point), (c, d): (&Point, &Point)) -> bool {
+    // Sign of the turn from p to q to r
+    let turn = |p: &Point, q: &Point, r: &Point| {
+        let cross = (q.longitude - p.longitude) * (r.latitude - p.latitude)
+            - (q.latitude - p.latitude) * (r.longitude - p.longitude);
+        if cross > 0.0 {
+            1
+        } else if cross < 0.0 {
+            -1
+        } else {
+            0
+        }
+    };
+    // Whether r, collinear with p and q, lies between them
+    let on_segment = |p: &Point, q: &Point, r: &Point| {
+        r.longitude >= p.longitude.min(q.longitude)
+            && r.longitude <= p.longitude.max(q.longitude)
+            && r.latitude >= p.latitude.min(q.latitude)
+            && r.latitude <= p.latitude.max(q.latitude)
+    };
+    let (d1, d2) = (turn(a, b, c), turn(a, b, d));
+    let (d3, d4) = (turn(c, d, a), turn(c, d, b));
+    if d1 * d2 < 0 && d3 * d4 < 0 {
+        return true;
+    }
+    (d1 == 0 && on_segment(a, b, c))
+        || (d2 == 0 && on_segment(a, b, d))
+        || (d3 == 0 && on_segment(c, d, a))
+        || (d4 == 0 && on_segment(c, d, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = "
+mission_id: flood-sector-7
+pattern: lawnmower
+altitude_m: 40
+speed_mps: 8
+boundary:
+  - { latitude: 47.50, longitude: 8.50 }
+  - { latitude: 47.50, longitude: 8.52 }
+  - { latitude: 47.52, longitude: 8.52 }
+  - { latitude: 47.52, longitude: 8.50 }
+home: { latitude: 47.505, longitude: 8.505 }
+sensors:
+  - sensor_type: GPR
+    sample_rate_hz: 100
+    params: { depth_m: '3' }
+geofence:
+  max_altitude_m: 100
+  max_distance_m: 2000
+rth:
+  altitude_m: 60
+";
+
+    fn point(latitude: f64, longitude: f64) -> Point {
+        Point {
+            latitude,
+            longitude,
+            altitude_m: 0.0,
+        }
+    }
+
+    fn plan() -> MissionPlan {
+        MissionPlan::from_yaml(YAML).unwrap()
+    }
+
+    #[test]
+    fn test_yaml_and_json_load_the_same_plan() {
+        let plan = plan();
+        assert!(plan.validate().is_ok());
+        assert_eq!(plan.pattern, Some(Pattern::Lawnmower));
+        assert_eq!(plan.sensors[0].params["depth_m"], "3");
+        assert_eq!(plan.geofence.as_ref().unwrap().max_altitude_m, Some(100.0));
+
+        let json = serde_json::to_string(&plan).unwrap();
+        assert_eq!(MissionPlan::from_json(&json).unwrap(), plan);
+
+        let path = std::env::temp_dir().join(format!("resqterra-mission-{}.yml", std::process::id()));
+        std::fs::write(&path, YAML).unwrap();
+        assert_eq!(MissionPlan::load(&path).unwrap(), plan);
+        std::fs::write(&path, YAML.replace("altitude_m: 40", "altitude_m: 400")).unwrap();
+        assert!(matches!(MissionPlan::load(&path), Err(MissionFileError::Invalid(_))));
+        std::fs::write(&path, "mission_id: [").unwrap();
+        assert!(matches!(MissionPlan::load(&path), Err(MissionFileError::Parse(_))));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_round_trip_through_mission_start() {
+        let mut plan = plan();
+        plan.pattern = Some(Pattern::Custom);
+        plan.waypoints = vec![point(47.51, 8.51), point(47.515, 8.515)];
+
+        let start = plan.mission_start();
+        assert_eq!(start.scan_pattern(), ScanPattern::PatternCustom);
+        assert_eq!(start.uploaded_waypoints, 2);
+        assert_eq!(start.geofence.as_ref().unwrap().max_distance_m, 2000.0);
+        assert_eq!(start.rth.unwrap().altitude_m, 60.0);
+        assert_eq!(MissionPlan::from_mission_start(&start, &plan.waypoints()), plan);
+    }
+
+    #[test]
+    fn test_invalid_plans_rejected() {
+        let invalid = |change: fn(&mut MissionPlan)| {
+            let mut plan = plan();
+            change(&mut plan);
+            plan.validate().unwrap_err()
+        };
+
+        // A bow tie: the second and fourth edges cross
+        assert_eq!(
+            invalid(|p| p.boundary.swap(2, 3)),
+            MissionError::SelfIntersecting { what: "boundary" }
+        );
+        assert_eq!(
+            invalid(|p| p.boundary.truncate(2)),
+            MissionError::TooFewVertices { what: "boundary" }
+        );
+        assert!(matches!(
+            invalid(|p| p.altitude_m = 150.0),
+            MissionError::AltitudeOutOfRange { what: "Mission", .. }
+        ));
+        assert!(matches!(
+            invalid(|p| p.altitude_m = 110.0),
+            MissionError::AboveGeofence { .. }
+        ));
+        assert!(matches!(
+            invalid(|p| p.rth = Some(RthPlan { altitude_m: 0.5, speed_mps: 0.0 })),
+            MissionError::AltitudeOutOfRange { what: "RTH", .. }
+        ));
+        assert!(matches!(invalid(|p| p.speed_mps = -1.0), MissionError::SpeedOutOfRange { .. }));
+        assert_eq!(invalid(|p| p.boundary.clear()), MissionError::NoBoundary(Pattern::Lawnmower));
+        assert_eq!(invalid(|p| p.pattern = Some(Pattern::Custom)), MissionError::NoWaypoints);
+        assert!(matches!(
+            invalid(|p| p.home = Some(point(91.0, 8.5))),
+            MissionError::InvalidCoordinate { .. }
+        ));
+        assert_eq!(
+            invalid(|p| p.geofence.as_mut().unwrap().max_distance_m = Some(0.0)),
+            MissionError::InvalidGeofenceLimit
+        );
+
+        // A square's opposite edges don't meet, and a triangle has none
+        assert!(check_polygon("boundary", &plan().boundary).is_ok());
+        let triangle = [point(0.0, 0.0), point(0.0, 1.0), point(1.0, 0.0)];
+        assert!(check_polygon("boundary", &triangle).is_ok());
+    }
+}
@@ -0,0 +1,83 @@
+//! Chunked mission waypoint upload
+//!
+//! A mission with thousands of waypoints doesn't fit comfortably in one frame
+//! over RFCOMM, so the server splits it into `MissionUploadChunk`s sent in
+//! order as `CMD_MISSION_UPLOAD` commands. Every chunk repeats the total
+//! waypoint count and a CRC-32 over the whole list, so the receiver can verify
+//! the reassembled mission before `CMD_MISSION_START` flies it.
+
+use crate::{GpsCoordinate, MissionUploadChunk};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Waypoints per chunk (about 2.5 KB encoded)
+pub const MISSION_CHUNK_WAYPOINTS: usize = 100;
+
+/// Largest mission the edge reassembles
+pub const MAX_MISSION_WAYPOINTS: usize = 50_000;
+
+/// CRC-32 over the waypoints' coordinates, independent of protobuf encoding
+pub fn waypoint_checksum(waypoints: &[GpsCoordinate]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for waypoint in waypoints {
+        hasher.update(&waypoint.latitude.to_le_bytes());
+        hasher.update(&waypoint.longitude.to_le_bytes());
+        hasher.update(&waypoint.altitude_m.to_le_bytes());
+    }
+    hasher.finalize()
+}
+
+/// Split a mission's waypoints into upload chunks, in send order
+///
+/// An empty list still yields one (empty) chunk, so the upload completes.
+pub fn chunk_mission(mission_id: &str, waypoints: &[GpsCoordinate]) -> Vec<MissionUploadChunk> {
+    let checksum = waypoint_checksum(waypoints);
+    let total_chunks = waypoints.len().div_ceil(MISSION_CHUNK_WAYPOINTS).max(1) as u32;
+    (0..total_chunks)
+        .map(|chunk_index| {
+            let start = chunk_index as usize * MISSION_CHUNK_WAYPOINTS;
+            let end = (start + MISSION_CHUNK_WAYPOINTS).min(waypoints.len());
+            MissionUploadChunk {
+                mission_id: String::from(mission_id),
+                chunk_index,
+                total_chunks,
+                total_waypoints: waypoints.len() as u32,
+                checksum,
+                waypoints: waypoints[start..end].to_vec(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waypoints(count: usize) -> Vec<GpsCoordinate> {
+        (0..count)
+            .map(|i| GpsCoordinate {
+                latitude: 45.0 + i as f64 * 1e-5,
+                longitude: -122.0,
+                altitude_m: 0.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks_cover_mission_in_order() {
+        let mission = waypoints(2 * MISSION_CHUNK_WAYPOINTS + 1);
+        let chunks = chunk_mission("survey-1", &mission);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[2].waypoints.len(), 1);
+        assert!(chunks.iter().all(|c| c.total_chunks == 3 && c.total_waypoints == 201));
+
+        let rejoined: Vec<GpsCoordinate> = chunks.into_iter().flat_map(|c| c.waypoints).collect();
+        assert_eq!(rejoined, mission);
+
+        // Order matters to the checksum
+        let mut swapped = mission.clone();
+        swapped.swap(0, 1);
+        assert_ne!(waypoint_checksum(&swapped), waypoint_checksum(&mission));
+        assert_eq!(chunk_mission("empty", &[]).len(), 1);
+    }
+}
@@ -0,0 +1,448 @@
+//! End-to-end encryption for links through untrusted relays
+//!
+//! Relay nodes forward RFCOMM traffic byte for byte, so without this layer a
+//! relay can read and alter every envelope. [`NoiseStream`] runs a Noise_XX
+//! handshake over any async byte stream and encrypts everything written to it
+//! afterwards; the relay only ever sees ciphertext.
+//!
+//! Each end has a static X25519 key and a list of peers it trusts (the server
+//! trusts each drone's public key, a drone trusts the server's). The handshake
+//! fails as soon as the other end's static key isn't on that list.
+//!
+//! On the wire every Noise message, handshake or transport, is a 2-byte
+//! big-endian length followed by the message.
+
+use alloc::collections::BTreeMap;
+use bytes::{Buf, BufMut, BytesMut};
+use core::fmt;
+use snow::params::DHChoice;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::{Builder, HandshakeState, TransportState};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// Handshake pattern and primitives used on every link
+pub const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Length of static private and public keys
+pub const NOISE_KEY_LEN: usize = 32;
+
+/// Largest Noise message, including the length prefix's limit
+const MAX_MESSAGE_LEN: usize = u16::MAX as usize;
+
+/// Plaintext carried by one transport message (the rest is the AEAD tag)
+const MAX_PLAINTEXT_LEN: usize = MAX_MESSAGE_LEN - 16;
+
+/// Length prefix before each message
+const LEN_PREFIX: usize = 2;
+
+/// Bytes read from the underlying stream at a time
+const READ_CHUNK: usize = 4096;
+
+/// Errors setting up an encrypted link
+#[derive(Debug, thiserror::Error)]
+pub enum NoiseError {
+    #[error("I/O error during handshake: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Noise handshake failed: {0}")]
+    Handshake(#[from] snow::Error),
+
+    #[error("Peer key {0} is not trusted")]
+    UntrustedPeer(String),
+
+    #[error("Invalid Noise key: {0}")]
+    InvalidKey(String),
+}
+
+/// Static key and trusted peers for one end of a link
+#[derive(Clone)]
+pub struct NoiseConfig {
+    private_key: [u8; NOISE_KEY_LEN],
+    public_key: [u8; NOISE_KEY_LEN],
+    /// Trusted public keys and the peer each belongs to
+    peers: BTreeMap<[u8; NOISE_KEY_LEN], String>,
+}
+
+impl fmt::Debug for NoiseConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the private key
+        f.debug_struct("NoiseConfig")
+            .field("public_key", &to_hex(&self.public_key))
+            .field("peers", &self.peers.values().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl NoiseConfig {
+    /// Config for the static key `private_key`, trusting no peers yet
+    pub fn new(private_key: [u8; NOISE_KEY_LEN]) -> Self {
+        let mut dh = DefaultResolver
+            .resolve_dh(&DHChoice::Curve25519)
+            .expect("default resolver supports Curve25519");
+        dh.set(&private_key);
+        let mut public_key = [0u8; NOISE_KEY_LEN];
+        public_key.copy_from_slice(dh.pubkey());
+        Self {
+            private_key,
+            public_key,
+            peers: BTreeMap::new(),
+        }
+    }
+
+    /// Config with a freshly generated static key
+    pub fn generate() -> Result<Self, NoiseError> {
+        let keypair = Builder::new(params()).generate_keypair()?;
+        Ok(Self::new(to_key(&keypair.private)?))
+    }
+
+    /// Trust `public_key` as the peer `name` (a device ID, or `server`)
+    pub fn with_peer(mut self, name: impl Into<String>, public_key: [u8; NOISE_KEY_LEN]) -> Self {
+        self.peers.insert(public_key, name.into());
+        self
+    }
+
+    /// Trust the peers in a `name=hexkey,name=hexkey` list
+    pub fn with_peers(mut self, list: &str) -> Result<Self, NoiseError> {
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, key) = entry
+                .split_once('=')
+                .ok_or_else(|| NoiseError::InvalidKey(format!("expected name=key, got {:?}", entry)))?;
+            self = self.with_peer(name.trim(), parse_key(key.trim())?);
+        }
+        Ok(self)
+    }
+
+    /// Config from `RESQTERRA_NOISE_KEY` (hex private key) and
+    /// `RESQTERRA_NOISE_PEERS` (`name=hexkey,...`)
+    ///
+    /// Returns `None` when no key is set, i.e. links stay plaintext.
+    pub fn from_env() -> Result<Option<Self>, NoiseError> {
+        let Some(key) = std::env::var("RESQTERRA_NOISE_KEY").ok().filter(|k| !k.is_empty()) else {
+            return Ok(None);
+        };
+        let peers = std::env::var("RESQTERRA_NOISE_PEERS").unwrap_or_default();
+        let config = Self::new(parse_key(&key)?).with_peers(&peers)?;
+        if config.peers.is_empty() {
+            return Err(NoiseError::InvalidKey(
+                "RESQTERRA_NOISE_KEY is set but RESQTERRA_NOISE_PEERS trusts no peers".into(),
+            ));
+        }
+        Ok(Some(config))
+    }
+
+    /// This end's public key, to hand to the peers that should trust it
+    pub fn public_key(&self) -> [u8; NOISE_KEY_LEN] {
+        self.public_key
+    }
+
+    /// Public key as hex, the form `RESQTERRA_NOISE_PEERS` takes
+    pub fn public_key_hex(&self) -> String {
+        to_hex(&self.public_key)
+    }
+
+    /// Name of the trusted peer with `public_key`
+    fn peer_name(&self, public_key: &[u8]) -> Option<&str> {
+        let key: [u8; NOISE_KEY_LEN] = public_key.try_into().ok()?;
+        self.peers.get(&key).map(String::as_str)
+    }
+
+    fn builder(&self) -> Builder<'_> {
+        Builder::new(params()).local_private_key(&self.private_key)
+    }
+}
+
+fn params() -> snow::params::NoiseParams {
+    NOISE_PARAMS.parse().expect("valid Noise parameters")
+}
+
+/// An encrypted stream to an authenticated peer
+pub struct NoiseStream<S> {
+    inner: S,
+    transport: TransportState,
+    /// Name the peer's static key is trusted under
+    peer: String,
+    /// Ciphertext read from `inner` that doesn't make a whole message yet
+    read_buf: BytesMut,
+    /// Decrypted bytes not yet handed to the reader
+    plaintext: BytesMut,
+    /// Ciphertext not yet written to `inner`
+    write_buf: BytesMut,
+    /// Room for one decrypted or encrypted message
+    scratch: Vec<u8>,
+}
+
+impl<S> fmt::Debug for NoiseStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseStream").field("peer", &self.peer).finish_non_exhaustive()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> NoiseStream<S> {
+    /// Run the handshake as the connecting side (the edge device)
+    pub async fn initiate(stream: S, config: &NoiseConfig) -> Result<Self, NoiseError> {
+        let handshake = config.builder().build_initiator()?;
+        Self::handshake(stream, handshake, config).await
+    }
+
+    /// Run the handshake as the accepting side (the server)
+    pub async fn accept(stream: S, config: &NoiseConfig) -> Result<Self, NoiseError> {
+        let handshake = config.builder().build_responder()?;
+        Self::handshake(stream, handshake, config).await
+    }
+
+    async fn handshake(
+        mut inner: S,
+        mut handshake: HandshakeState,
+        config: &NoiseConfig,
+    ) -> Result<Self, NoiseError> {
+        let mut scratch = vec![0u8; MAX_MESSAGE_LEN];
+        while !handshake.is_handshake_finished() {
+            if handshake.is_my_turn() {
+                let len = handshake.write_message(&[], &mut scratch)?;
+                inner.write_all(&(len as u16).to_be_bytes()).await?;
+                inner.write_all(&scratch[..len]).await?;
+                inner.flush().await?;
+            } else {
+                let len = inner.read_u16().await? as usize;
+                let mut message = vec![0u8; len];
+                inner.read_exact(&mut message).await?;
+                handshake.read_message(&message, &mut scratch)?;
+
+                // Stop before revealing anything more to an unknown peer
+                if let Some(remote) = handshake.get_remote_static() {
+                    if config.peer_name(remote).is_none() {
+                        return Err(NoiseError::UntrustedPeer(to_hex(remote)));
+                    }
+                }
+            }
+        }
+
+        let remote = handshake
+            .get_remote_static()
+            .ok_or_else(|| NoiseError::UntrustedPeer("(none sent)".into()))?;
+        let peer = config
+            .peer_name(remote)
+            .ok_or_else(|| NoiseError::UntrustedPeer(to_hex(remote)))?
+            .to_owned();
+
+        Ok(Self {
+            inner,
+            transport: handshake.into_transport_mode()?,
+            peer,
+            read_buf: BytesMut::new(),
+            plaintext: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            scratch,
+        })
+    }
+}
+
+impl<S> NoiseStream<S> {
+    /// Name the peer is trusted under (its device ID, or `server`)
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    /// The underlying stream
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: AsyncWrite + Unpin> NoiseStream<S> {
+    /// Write out buffered ciphertext
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_buf.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_buf.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for NoiseStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.plaintext.is_empty() {
+                let n = buf.remaining().min(this.plaintext.len());
+                buf.put_slice(&this.plaintext[..n]);
+                this.plaintext.advance(n);
+                return Poll::Ready(Ok(()));
+            }
+
+            // Decrypt the next whole message, if one has arrived
+            if this.read_buf.len() >= LEN_PREFIX {
+                let len = u16::from_be_bytes([this.read_buf[0], this.read_buf[1]]) as usize;
+                if this.read_buf.len() >= LEN_PREFIX + len {
+                    let message = &this.read_buf[LEN_PREFIX..LEN_PREFIX + len];
+                    let n = this
+                        .transport
+                        .read_message(message, &mut this.scratch)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    this.plaintext.extend_from_slice(&this.scratch[..n]);
+                    this.read_buf.advance(LEN_PREFIX + len);
+                    continue;
+                }
+            }
+
+            let mut chunk = [0u8; READ_CHUNK];
+            let mut read = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                return Poll::Ready(if this.read_buf.is_empty() {
+                    Ok(())
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed mid-message",
+                    ))
+                });
+            }
+            this.read_buf.extend_from_slice(read.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for NoiseStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let n = buf.len().min(MAX_PLAINTEXT_LEN);
+        let len = this
+            .transport
+            .write_message(&buf[..n], &mut this.scratch)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        this.write_buf.put_u16(len as u16);
+        this.write_buf.extend_from_slice(&this.scratch[..len]);
+
+        // Start sending now; whatever doesn't fit goes out on the next write or flush
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Parse a hex-encoded key
+fn parse_key(hex: &str) -> Result<[u8; NOISE_KEY_LEN], NoiseError> {
+    if hex.len() != NOISE_KEY_LEN * 2 || !hex.is_ascii() {
+        return Err(NoiseError::InvalidKey(format!(
+            "expected {} hex digits, got {:?}",
+            NOISE_KEY_LEN * 2,
+            hex
+        )));
+    }
+    let mut key = [0u8; NOISE_KEY_LEN];
+    for (byte, pair) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let digits = core::str::from_utf8(pair).expect("ASCII");
+        *byte = u8::from_str_radix(digits, 16)
+            .map_err(|_| NoiseError::InvalidKey(format!("not hex: {:?}", hex)))?;
+    }
+    Ok(key)
+}
+
+fn to_key(bytes: &[u8]) -> Result<[u8; NOISE_KEY_LEN], NoiseError> {
+    bytes
+        .try_into()
+        .map_err(|_| NoiseError::InvalidKey(format!("expected {} bytes, got {}", NOISE_KEY_LEN, bytes.len())))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Edge and server configs that trust each other
+    fn pair() -> (NoiseConfig, NoiseConfig) {
+        let edge = NoiseConfig::generate().unwrap();
+        let server = NoiseConfig::generate().unwrap();
+        (
+            edge.clone().with_peer("server", server.public_key()),
+            server.with_peer("edge-001", edge.public_key()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_round_trip() {
+        let (edge, server) = pair();
+        let (a, b) = tokio::io::duplex(64);
+        let (client, accepted) = tokio::join!(
+            NoiseStream::initiate(a, &edge),
+            NoiseStream::accept(b, &server)
+        );
+        let (mut client, mut accepted) = (client.unwrap(), accepted.unwrap());
+        assert_eq!(client.peer(), "server");
+        assert_eq!(accepted.peer(), "edge-001");
+
+        // Larger than one Noise message, so it's split and reassembled
+        let payload: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let expected = payload.clone();
+        let writer = tokio::spawn(async move {
+            client.write_all(&payload).await.unwrap();
+            client.shutdown().await.unwrap();
+        });
+        let mut received = Vec::new();
+        accepted.read_to_end(&mut received).await.unwrap();
+        writer.await.unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_peer_rejected() {
+        let (edge, _) = pair();
+        let stranger = NoiseConfig::generate().unwrap().with_peer("server", edge.public_key());
+        let (a, b) = tokio::io::duplex(1024);
+        let (client, accepted) = tokio::join!(
+            NoiseStream::initiate(a, &edge),
+            NoiseStream::accept(b, &stranger)
+        );
+        assert!(matches!(client, Err(NoiseError::UntrustedPeer(_))));
+        assert!(accepted.is_err());
+    }
+
+    #[test]
+    fn test_peer_list_parsing() {
+        let key = NoiseConfig::generate().unwrap();
+        let config = NoiseConfig::generate()
+            .unwrap()
+            .with_peers(&format!("edge-001={}, ", key.public_key_hex()))
+            .unwrap();
+        assert_eq!(config.peer_name(&key.public_key()), Some("edge-001"));
+        assert!(config.clone().with_peers("edge-002=zz").is_err());
+        assert!(config.with_peers("edge-002").is_err());
+    }
+}
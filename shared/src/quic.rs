@@ -0,0 +1,342 @@
+//! QUIC links for the 5G path
+//!
+//! QUIC gives the 5G link TLS 1.3, independent streams and 0-RTT
+//! reconnection. Links are [`MessageLink`]s: control messages (commands,
+//! ACKs, hellos, heartbeats, and anything encrypted with Noise) travel in
+//! order on one bidirectional stream, each behind its u32 length; each
+//! telemetry frame gets a unidirectional stream of its own. A lost packet
+//! only holds up its own stream, so telemetry never waits behind a command
+//! or the other way round. The client opens the control stream with an
+//! empty message.
+//!
+//! A client endpoint keeps the server's session tickets, so reconnecting
+//! through the same endpoint sends the first messages as 0-RTT data, before
+//! the handshake completes. The server keeps resumption state itself and a
+//! ticket is accepted once, so a replayed 0-RTT flight is refused.
+
+use crate::message_link::{is_telemetry_frame, MessageLink};
+use bytes::Bytes;
+use quinn::crypto::rustls::{NoInitialCipherSuite, QuicClientConfig, QuicServerConfig};
+use quinn::{Connection, ConnectionError, ReadError, ReadExactError, RecvStream, SendStream};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+pub use quinn::Endpoint;
+
+/// ALPN protocol both ends speak
+pub const QUIC_ALPN: &[u8] = b"resqterra/1";
+
+/// Largest control message accepted (a batch of full-size frames)
+const MAX_CONTROL_MESSAGE_LEN: usize = 64 << 20;
+
+/// Largest telemetry frame accepted
+const MAX_TELEMETRY_LEN: usize = 1 << 20;
+
+/// Accepted links waiting for `QuicListener::accept`
+const ACCEPT_QUEUE_LEN: usize = 32;
+
+/// Errors setting up QUIC endpoints
+#[derive(Debug, thiserror::Error)]
+pub enum QuicError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid PEM file: {0}")]
+    Pem(#[from] rustls::pki_types::pem::Error),
+
+    #[error("TLS setup failed: {0}")]
+    Tls(#[from] rustls::Error),
+
+    #[error("TLS config unusable for QUIC: {0}")]
+    Crypto(#[from] NoInitialCipherSuite),
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Server config from a PEM certificate chain and private key
+pub fn server_config(cert_chain: &Path, key: &Path) -> Result<quinn::ServerConfig, QuicError> {
+    let certs = CertificateDer::pem_file_iter(cert_chain)?.collect::<Result<Vec<_>, _>>()?;
+    server_config_from_der(certs, PrivateKeyDer::from_pem_file(key)?)
+}
+
+/// Server config from a DER certificate chain and private key
+pub fn server_config_from_der(
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<quinn::ServerConfig, QuicError> {
+    let mut tls = rustls::ServerConfig::builder_with_provider(provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)?;
+    tls.alpn_protocols = vec![QUIC_ALPN.to_vec()];
+    // Lets returning drones send before the handshake completes
+    tls.max_early_data_size = u32::MAX;
+    let crypto = QuicServerConfig::try_from(tls)?;
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+/// Client endpoint trusting the CA certificates in a PEM file
+pub fn client_endpoint(ca_certs: &Path) -> Result<Endpoint, QuicError> {
+    let roots = CertificateDer::pem_file_iter(ca_certs)?.collect::<Result<Vec<_>, _>>()?;
+    client_endpoint_from_der(roots)
+}
+
+/// Client endpoint trusting the given DER CA certificates
+///
+/// Keep the endpoint across reconnects: it holds the session tickets that
+/// make 0-RTT possible.
+pub fn client_endpoint_from_der(roots: Vec<CertificateDer<'static>>) -> Result<Endpoint, QuicError> {
+    let mut store = rustls::RootCertStore::empty();
+    for root in roots {
+        store.add(root)?;
+    }
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_root_certificates(store)
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![QUIC_ALPN.to_vec()];
+    tls.enable_early_data = true;
+    let crypto = QuicClientConfig::try_from(tls)?;
+
+    let mut endpoint = Endpoint::client(([0, 0, 0, 0], 0).into())?;
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+    Ok(endpoint)
+}
+
+/// Open a link to the [`QuicListener`] at `addr`
+///
+/// Also returns whether the link was resumed with 0-RTT. If the server turns
+/// the 0-RTT data down, the link fails and the next attempt does a full
+/// handshake.
+pub async fn connect(
+    endpoint: &Endpoint,
+    addr: SocketAddr,
+    server_name: &str,
+) -> io::Result<(MessageLink, bool)> {
+    let connecting = endpoint.connect(addr, server_name).map_err(io::Error::other)?;
+    let (connection, zero_rtt) = match connecting.into_0rtt() {
+        Ok((connection, _accepted)) => (connection, true),
+        Err(connecting) => (connecting.await?, false),
+    };
+    let (mut control_send, control_recv) = connection.open_bi().await?;
+    // The server only learns of the stream once something is sent on it
+    control_send.write_all(&0u32.to_be_bytes()).await?;
+    Ok((spawn(connection, control_send, control_recv), zero_rtt))
+}
+
+fn spawn(connection: Connection, control_send: SendStream, control_recv: RecvStream) -> MessageLink {
+    let (link, channels) = MessageLink::new();
+    let incoming = channels.incoming;
+    tokio::spawn(read_control(control_recv, incoming.clone()));
+    tokio::spawn(read_telemetry(connection.clone(), incoming.clone()));
+    tokio::spawn(async move {
+        if let Err(e) = write(connection, control_send, channels.outgoing).await {
+            let _ = incoming.try_send(Err(e));
+        }
+    });
+    link
+}
+
+/// Send each message on the stream it belongs to, until the link or connection closes
+async fn write(
+    connection: Connection,
+    mut control: SendStream,
+    mut outgoing: mpsc::Receiver<Bytes>,
+) -> io::Result<()> {
+    loop {
+        let message = tokio::select! {
+            message = outgoing.recv() => message,
+            _ = connection.closed() => return Ok(()),
+        };
+        let Some(message) = message else {
+            let _ = control.finish();
+            connection.close(0u32.into(), b"closed");
+            return Ok(());
+        };
+        if is_telemetry_frame(&message) {
+            let mut stream = connection.open_uni().await?;
+            stream.write_all(&message).await?;
+            let _ = stream.finish();
+        } else {
+            control.write_all(&(message.len() as u32).to_be_bytes()).await?;
+            control.write_all(&message).await?;
+        }
+    }
+}
+
+/// Whether a read ended because the peer closed the link, not on an error
+fn closed_cleanly(e: &ReadExactError) -> bool {
+    matches!(
+        e,
+        ReadExactError::FinishedEarly(0)
+            | ReadExactError::ReadError(ReadError::ConnectionLost(ConnectionError::ApplicationClosed(_)))
+    )
+}
+
+async fn read_control(mut control: RecvStream, incoming: mpsc::Sender<io::Result<Bytes>>) {
+    loop {
+        let mut len = [0; 4];
+        if let Err(e) = control.read_exact(&mut len).await {
+            if !closed_cleanly(&e) {
+                let _ = incoming.send(Err(io::Error::other(e))).await;
+            }
+            return;
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_CONTROL_MESSAGE_LEN {
+            let error = io::Error::new(io::ErrorKind::InvalidData, "oversized QUIC control message");
+            let _ = incoming.send(Err(error)).await;
+            return;
+        }
+        let mut message = vec![0; len];
+        if let Err(e) = control.read_exact(&mut message).await {
+            let _ = incoming.send(Err(io::Error::other(e))).await;
+            return;
+        }
+        if incoming.send(Ok(message.into())).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_telemetry(connection: Connection, incoming: mpsc::Sender<io::Result<Bytes>>) {
+    while let Ok(mut stream) = connection.accept_uni().await {
+        let incoming = incoming.clone();
+        // Frames are read side by side, so a slow one doesn't hold up the rest
+        tokio::spawn(async move {
+            if let Ok(frame) = stream.read_to_end(MAX_TELEMETRY_LEN).await {
+                let _ = incoming.send(Ok(frame.into())).await;
+            }
+        });
+    }
+}
+
+/// Accepts QUIC links on one endpoint
+pub struct QuicListener {
+    accepted: mpsc::Receiver<(MessageLink, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl QuicListener {
+    pub fn bind(addr: SocketAddr, config: quinn::ServerConfig) -> io::Result<Self> {
+        let endpoint = Endpoint::server(config, addr)?;
+        let local_addr = endpoint.local_addr()?;
+        let (accepted_tx, accepted) = mpsc::channel(ACCEPT_QUEUE_LEN);
+        tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                let accepted = accepted_tx.clone();
+                // Handshakes run side by side
+                tokio::spawn(async move {
+                    let addr = incoming.remote_address();
+                    let Ok(connecting) = incoming.accept() else {
+                        return;
+                    };
+                    // Read 0-RTT data as soon as it arrives
+                    let connection = match connecting.into_0rtt() {
+                        Ok((connection, _)) => connection,
+                        Err(connecting) => match connecting.await {
+                            Ok(connection) => connection,
+                            Err(_) => return,
+                        },
+                    };
+                    if let Ok((send, recv)) = connection.accept_bi().await {
+                        let _ = accepted.send((spawn(connection, send, recv), addr)).await;
+                    }
+                });
+            }
+        });
+        Ok(Self { accepted, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Wait for the next peer to open a link
+    pub async fn accept(&mut self) -> io::Result<(MessageLink, SocketAddr)> {
+        self.accepted
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::other("QUIC listener stopped"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codec, envelope, Envelope, Header, MessageType, Telemetry};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn listener_and_endpoint() -> (QuicListener, Endpoint) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let cert_der = cert.cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+        let config = server_config_from_der(vec![cert_der.clone()], key).unwrap();
+        let listener = QuicListener::bind(([127, 0, 0, 1], 0).into(), config).unwrap();
+        (listener, client_endpoint_from_der(vec![cert_der]).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_link_separates_telemetry_and_resumes_with_0rtt() {
+        let (mut listener, endpoint) = listener_and_endpoint();
+        let addr = listener.local_addr();
+
+        let (connected, accepted) =
+            tokio::join!(connect(&endpoint, addr, "localhost"), listener.accept());
+        let (mut client, zero_rtt) = connected.unwrap();
+        let (mut server, _) = accepted.unwrap();
+        assert!(!zero_rtt);
+
+        let telemetry = codec::encode(&Envelope {
+            header: Some(Header::new("edge-001", MessageType::MsgTelemetry, 1)),
+            payload: Some(envelope::Payload::Telemetry(Telemetry::default())),
+            signature: Vec::new(),
+        })
+        .unwrap();
+        client.write_all(b"hello").await.unwrap();
+        client.flush().await.unwrap();
+        client.write_all(&telemetry).await.unwrap();
+        client.flush().await.unwrap();
+
+        // Either may arrive first; both arrive whole
+        let mut received = vec![0; 5 + telemetry.len()];
+        server.read_exact(&mut received).await.unwrap();
+        let hello_first = received.starts_with(b"hello");
+        let (first, second) = received.split_at(if hello_first { 5 } else { telemetry.len() });
+        if hello_first {
+            assert_eq!(second, &telemetry[..]);
+        } else {
+            assert_eq!((first, second), (&telemetry[..], &b"hello"[..]));
+        }
+
+        server.write_all(b"command").await.unwrap();
+        server.flush().await.unwrap();
+        let mut command = [0; 7];
+        client.read_exact(&mut command).await.unwrap();
+        assert_eq!(&command, b"command");
+
+        // Dropping one end closes the other
+        drop(client);
+        assert_eq!(server.read(&mut command).await.unwrap(), 0);
+
+        // The session ticket from the first link allows 0-RTT
+        let (connected, accepted) =
+            tokio::join!(connect(&endpoint, addr, "localhost"), listener.accept());
+        let (mut client, zero_rtt) = connected.unwrap();
+        let (mut server, _) = accepted.unwrap();
+        assert!(zero_rtt);
+        client.write_all(b"again").await.unwrap();
+        client.flush().await.unwrap();
+        let mut again = [0; 5];
+        server.read_exact(&mut again).await.unwrap();
+        assert_eq!(&again, b"again");
+    }
+}
@@ -0,0 +1,117 @@
+//! Protocol schema for external consumers
+//!
+//! Everything a non-Rust client (e.g. a Python GCS) needs to talk to
+//! ResQTerra nodes: the `.proto` source, its compiled `FileDescriptorSet`,
+//! and the framing rules as constants.
+//!
+//! # Framing
+//!
+//! Each message on a stream is one frame:
+//!
+//! ```text
+//! [ 1 byte: compression ][ 3 bytes: length (big-endian) ][ length bytes: Envelope ][ 4 bytes: CRC32, optional ]
+//! ```
+//!
+//! - Read the first [`FRAME_PREFIX_LEN`] bytes as a big-endian `u32`. The top
+//!   byte (`>> FRAME_COMPRESSION_SHIFT`) is a `Compression` value, the low
+//!   bits (`& FRAME_LENGTH_MASK`) the length of the Envelope bytes.
+//! - With compression `0` (none) the prefix reads as a plain `u32` length.
+//!   Other values are only sent once both peers offered them in the hello.
+//! - Lengths above [`MAX_MESSAGE_SIZE`] are a protocol error; close the link.
+//! - The (decompressed) bytes are a protobuf [`ENVELOPE_MESSAGE`].
+//! - If the compression byte has [`FRAME_CRC_FLAG`] set (mask it off before
+//!   reading the `Compression` value), the frame ends with a big-endian
+//!   CRC32 (IEEE) of the prefix and Envelope bytes. Frames whose CRC doesn't
+//!   match are dropped.
+//!
+//! The CRC is optional so older peers, which don't know the flag, keep
+//! working; enable it on lossy links (Bluetooth relays) where corruption can
+//! slip past the transport. Authenticity comes from the optional HMAC in
+//! `Envelope.signature` (see `auth`).
+//!
+//! # Versioning
+//!
+//! Proto changes are additive only, so decoders must skip unknown fields.
+//! [`PROTOCOL_VERSION`] is advertised in `Hello.protocol_version` for
+//! diagnostics, not negotiation.
+
+pub use crate::codec::MAX_MESSAGE_SIZE;
+pub use crate::PROTOCOL_VERSION;
+
+/// Bytes in the frame prefix (compression + length)
+pub const FRAME_PREFIX_LEN: usize = 4;
+
+/// Shift of the compression byte within the big-endian prefix
+pub const FRAME_COMPRESSION_SHIFT: u32 = 24;
+
+/// Low 24 bits of the prefix hold the length
+pub const FRAME_LENGTH_MASK: u32 = 0x00FF_FFFF;
+
+/// Bit of the compression byte marking a CRC32 trailer
+pub const FRAME_CRC_FLAG: u8 = 0x80;
+
+/// Bytes in the CRC32 trailer
+pub const FRAME_CRC_LEN: usize = 4;
+
+/// Protobuf package of all messages
+pub const PROTO_PACKAGE: &str = "resqterra";
+
+/// Fully qualified name of the message every frame carries
+pub const ENVELOPE_MESSAGE: &str = "resqterra.Envelope";
+
+/// Source of `resqterra.proto`
+pub const PROTO_SOURCE: &str = include_str!("../proto/resqterra.proto");
+
+/// Source of `fleet.proto`, the server's gRPC `FleetService`
+pub const FLEET_PROTO_SOURCE: &str = include_str!("../proto/fleet.proto");
+
+/// Encoded `google.protobuf.FileDescriptorSet` of both protos
+static DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/resqterra_descriptor.bin"));
+
+/// Compiled descriptor set of the protocol, for generating clients or
+/// decoding frames dynamically (e.g. `protoc --descriptor_set_in`)
+pub fn proto_descriptor() -> &'static [u8] {
+    DESCRIPTOR_SET
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+    use prost_types::FileDescriptorSet;
+
+    #[test]
+    fn test_descriptor_set_contains_envelope() {
+        let set = FileDescriptorSet::decode(proto_descriptor()).expect("valid descriptor set");
+        let file = set
+            .file
+            .iter()
+            .find(|f| f.package() == PROTO_PACKAGE)
+            .expect("resqterra.proto in set");
+
+        let (package, name) = ENVELOPE_MESSAGE.rsplit_once('.').unwrap();
+        assert_eq!(package, PROTO_PACKAGE);
+        let envelope = file
+            .message_type
+            .iter()
+            .find(|m| m.name() == name)
+            .expect("Envelope message");
+        let fields: Vec<&str> = envelope.field.iter().map(|f| f.name()).collect();
+        assert!(fields.contains(&"header"));
+        assert!(fields.contains(&"signature"));
+    }
+
+    #[test]
+    fn test_descriptor_set_contains_fleet_service() {
+        let set = FileDescriptorSet::decode(proto_descriptor()).expect("valid descriptor set");
+        let file = set
+            .file
+            .iter()
+            .find(|f| f.package() == "resqterra.fleet")
+            .expect("fleet.proto in set");
+        assert_eq!(file.dependency, ["resqterra.proto"]);
+        let methods: Vec<&str> = file.service[0].method.iter().map(|m| m.name()).collect();
+        assert!(methods.contains(&"SubscribeTelemetry"));
+        assert!(methods.contains(&"SendCommand"));
+    }
+}
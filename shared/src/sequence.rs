@@ -0,0 +1,153 @@
+//! Per-peer `sequence_id` tracking
+//!
+//! Receivers feed each header's `sequence_id` to a [`SequenceTracker`] to
+//! spot lost frames (gaps), frames overtaken by later ones (reordering, e.g.
+//! by the edge's priority lanes) and frames seen twice (duplicates, e.g. a
+//! relay resending after a reconnect). Duplicates should be dropped; the
+//! other events are for observing link quality.
+
+use alloc::collections::BTreeSet;
+
+/// Ids below the highest seen that are still tracked for late arrival
+pub const SEQUENCE_WINDOW: u64 = 256;
+
+/// What one received `sequence_id` says about the link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceEvent {
+    /// The next id (or the first one seen)
+    InOrder,
+    /// Ids `first..=last` were skipped; they may still arrive late
+    Gap { first: u64, last: u64 },
+    /// An id from an earlier gap arrived late
+    Reordered,
+    /// Already received; drop the envelope
+    Duplicate,
+    /// Far below everything seen: the peer restarted its counter
+    Reset,
+}
+
+/// Running totals of what a tracker has seen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequenceStats {
+    /// Ids reported in gaps (late arrivals included)
+    pub skipped: u64,
+    /// Late arrivals from earlier gaps
+    pub reordered: u64,
+    /// Copies of ids already received
+    pub duplicates: u64,
+}
+
+/// Tracks the `sequence_id`s received from one peer
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    /// Highest id received
+    highest: Option<u64>,
+    /// Ids within `SEQUENCE_WINDOW` of `highest` not received yet
+    missing: BTreeSet<u64>,
+    stats: SequenceStats,
+}
+
+impl SequenceTracker {
+    /// Create a tracker that hasn't seen any ids yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a received id and classify it
+    pub fn observe(&mut self, sequence_id: u64) -> SequenceEvent {
+        let Some(highest) = self.highest else {
+            self.highest = Some(sequence_id);
+            return SequenceEvent::InOrder;
+        };
+
+        if sequence_id > highest {
+            self.highest = Some(sequence_id);
+            let floor = sequence_id.saturating_sub(SEQUENCE_WINDOW);
+            self.missing = self.missing.split_off(&floor);
+            if sequence_id == highest + 1 {
+                return SequenceEvent::InOrder;
+            }
+            let (first, last) = (highest + 1, sequence_id - 1);
+            self.missing.extend(first.max(floor)..=last);
+            self.stats.skipped += last - first + 1;
+            return SequenceEvent::Gap { first, last };
+        }
+
+        if self.missing.remove(&sequence_id) {
+            self.stats.reordered += 1;
+            SequenceEvent::Reordered
+        } else if highest - sequence_id < SEQUENCE_WINDOW {
+            self.stats.duplicates += 1;
+            SequenceEvent::Duplicate
+        } else {
+            self.highest = Some(sequence_id);
+            self.missing.clear();
+            SequenceEvent::Reset
+        }
+    }
+
+    /// Highest id received so far
+    pub fn highest(&self) -> Option<u64> {
+        self.highest
+    }
+
+    /// Totals since the tracker was created
+    pub fn stats(&self) -> SequenceStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_order_and_duplicates() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.observe(5), SequenceEvent::InOrder);
+        assert_eq!(tracker.observe(6), SequenceEvent::InOrder);
+        assert_eq!(tracker.observe(6), SequenceEvent::Duplicate);
+        assert_eq!(tracker.observe(5), SequenceEvent::Duplicate);
+        assert_eq!(tracker.stats().duplicates, 2);
+        assert_eq!(tracker.highest(), Some(6));
+    }
+
+    #[test]
+    fn test_gap_filled_late_is_reordered_once() {
+        let mut tracker = SequenceTracker::new();
+        tracker.observe(1);
+        assert_eq!(tracker.observe(4), SequenceEvent::Gap { first: 2, last: 3 });
+        assert_eq!(tracker.observe(3), SequenceEvent::Reordered);
+        assert_eq!(tracker.observe(3), SequenceEvent::Duplicate);
+        assert_eq!(tracker.observe(5), SequenceEvent::InOrder);
+        assert_eq!(
+            tracker.stats(),
+            SequenceStats {
+                skipped: 2,
+                reordered: 1,
+                duplicates: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_huge_gap_only_tracks_window() {
+        let mut tracker = SequenceTracker::new();
+        tracker.observe(1);
+        assert_eq!(
+            tracker.observe(1_000_000),
+            SequenceEvent::Gap { first: 2, last: 999_999 }
+        );
+        assert_eq!(tracker.missing.len() as u64, SEQUENCE_WINDOW);
+        assert_eq!(tracker.observe(999_999), SequenceEvent::Reordered);
+    }
+
+    #[test]
+    fn test_counter_restart_detected() {
+        let mut tracker = SequenceTracker::new();
+        tracker.observe(10_000);
+        assert_eq!(tracker.observe(1), SequenceEvent::Reset);
+        assert_eq!(tracker.observe(2), SequenceEvent::InOrder);
+        assert_eq!(tracker.observe(1), SequenceEvent::Duplicate);
+    }
+}
@@ -3,6 +3,11 @@
 //! Defines valid state transitions and safety-critical event handling.
 
 use crate::{DroneState, safety};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 
 /// Events that can trigger state transitions
 #[derive(Debug, Clone, PartialEq)]
@@ -35,12 +40,22 @@ pub enum SafetyEvent {
     EmergencyCleared,
     /// Heartbeat timeout (server connection lost)
     HeartbeatTimeout,
+    /// Flight controller heartbeat timeout (FC link lost)
+    FcLinkLost,
+    /// Battery warning level reached
+    BatteryLow,
     /// Battery critical level reached
     BatteryCritical,
+    /// Battery too low to make it home
+    BatteryDepleted,
     /// Geofence breach
     GeofenceBreach,
     /// Command timeout
     CommandTimeout,
+    /// Onboard software/firmware update started (only while idle)
+    MaintenanceStarted,
+    /// Onboard update finished
+    MaintenanceComplete,
 }
 
 /// Result of a state transition attempt
@@ -50,18 +65,29 @@ pub enum TransitionResult {
     Success(DroneState),
     /// Transition was invalid from current state
     Invalid { from: DroneState, event: SafetyEvent },
+    /// Condition worth reporting, no state change
+    Warning { reason: String },
     /// Transition triggered emergency RTH
     EmergencyRth { reason: String },
+    /// Transition triggered a forced landing in place
+    ForcedLand { reason: String },
     /// Transition triggered emergency stop
     EmergencyStop { reason: String },
 }
 
 /// The safety state machine for drone operations
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SafetyStateMachine {
     current_state: DroneState,
     last_server_heartbeat_ms: u64,
+    /// When the link to the server went down (`None` while connected)
+    link_lost_at_ms: Option<u64>,
+    last_fc_heartbeat_ms: u64,
+    /// FC link loss raised since the last FC heartbeat
+    fc_link_warned: bool,
     battery_percent: u32,
+    /// Battery warning raised since the level last dropped below it
+    battery_warned: bool,
     is_geofenced: bool,
 }
 
@@ -77,7 +103,11 @@ impl SafetyStateMachine {
         Self {
             current_state: DroneState::DroneIdle,
             last_server_heartbeat_ms: 0,
+            link_lost_at_ms: None,
+            last_fc_heartbeat_ms: 0,
+            fc_link_warned: false,
             battery_percent: 100,
+            battery_warned: false,
             is_geofenced: false,
         }
     }
@@ -92,25 +122,68 @@ impl SafetyStateMachine {
         self.last_server_heartbeat_ms = timestamp_ms;
     }
 
+    /// Record that the link to the server went down
+    ///
+    /// Repeated calls during one outage keep the time it started.
+    pub fn link_lost(&mut self, timestamp_ms: u64) {
+        self.link_lost_at_ms.get_or_insert(timestamp_ms);
+    }
+
+    /// Record that the link to the server is back
+    pub fn link_restored(&mut self) {
+        self.link_lost_at_ms = None;
+    }
+
+    /// Update flight controller heartbeat timestamp
+    pub fn update_fc_heartbeat(&mut self, timestamp_ms: u64) {
+        self.last_fc_heartbeat_ms = timestamp_ms;
+        self.fc_link_warned = false;
+    }
+
     /// Update battery level
     pub fn update_battery(&mut self, percent: u32) {
         self.battery_percent = percent;
+        if percent > safety::BATTERY_WARNING_PERCENT {
+            self.battery_warned = false;
+        }
     }
 
     /// Check if we've lost connection to server
     pub fn is_heartbeat_timed_out(&self, current_time_ms: u64) -> bool {
-        if self.last_server_heartbeat_ms == 0 {
-            return false; // Never received heartbeat yet
-        }
-        let elapsed = current_time_ms.saturating_sub(self.last_server_heartbeat_ms);
+        // Without a heartbeat yet, only a known outage can time out
+        let since = match (self.last_server_heartbeat_ms, self.link_lost_at_ms) {
+            (0, None) => return false,
+            (0, Some(lost)) => lost,
+            (last, _) => last,
+        };
+        let elapsed = current_time_ms.saturating_sub(since);
         elapsed > safety::HEARTBEAT_TIMEOUT_MS
     }
 
+    /// Check if the flight controller has gone silent
+    ///
+    /// Never true before the first FC heartbeat.
+    pub fn is_fc_link_lost(&self, current_time_ms: u64) -> bool {
+        self.last_fc_heartbeat_ms != 0
+            && current_time_ms.saturating_sub(self.last_fc_heartbeat_ms)
+                > safety::FC_HEARTBEAT_TIMEOUT_MS
+    }
+
     /// Check if battery is at critical level
     pub fn is_battery_critical(&self) -> bool {
         self.battery_percent <= safety::BATTERY_CRITICAL_PERCENT
     }
 
+    /// The battery event for the current level, most severe stage first
+    pub fn battery_event(&self) -> Option<SafetyEvent> {
+        match self.battery_percent {
+            p if p <= safety::BATTERY_LAND_PERCENT => Some(SafetyEvent::BatteryDepleted),
+            p if p <= safety::BATTERY_CRITICAL_PERCENT => Some(SafetyEvent::BatteryCritical),
+            p if p <= safety::BATTERY_WARNING_PERCENT => Some(SafetyEvent::BatteryLow),
+            _ => None,
+        }
+    }
+
     /// Process an event and return the transition result
     pub fn process_event(&mut self, event: SafetyEvent) -> TransitionResult {
         // Safety-critical events always take priority
@@ -125,9 +198,31 @@ impl SafetyStateMachine {
             SafetyEvent::HeartbeatTimeout => {
                 return self.trigger_safety_rth("Server heartbeat timeout");
             }
+            SafetyEvent::FcLinkLost => {
+                // Nothing to command without the FC; its own failsafes fly
+                // the drone. Reported once per outage.
+                if core::mem::replace(&mut self.fc_link_warned, true) {
+                    return TransitionResult::Success(self.current_state);
+                }
+                return TransitionResult::Warning {
+                    reason: "Flight controller link lost".to_string(),
+                };
+            }
+            SafetyEvent::BatteryLow => {
+                // Reported once per drop below the warning level
+                if core::mem::replace(&mut self.battery_warned, true) {
+                    return TransitionResult::Success(self.current_state);
+                }
+                return TransitionResult::Warning {
+                    reason: format!("Battery low ({}%)", self.battery_percent),
+                };
+            }
             SafetyEvent::BatteryCritical => {
                 return self.trigger_safety_rth("Battery critical");
             }
+            SafetyEvent::BatteryDepleted => {
+                return self.trigger_forced_land("Battery depleted");
+            }
             SafetyEvent::GeofenceBreach => {
                 return self.trigger_safety_rth("Geofence breach");
             }
@@ -172,6 +267,7 @@ impl SafetyStateMachine {
             // From InMission
             (DroneInMission, MissionComplete) => Some(DroneIdle),
             (DroneInMission, RthTriggered) => Some(DroneReturningHome),
+            (DroneInMission, LandingStarted) => Some(DroneLanding),
 
             // From ReturningHome
             (DroneReturningHome, RthComplete) => Some(DroneLanding),
@@ -180,9 +276,18 @@ impl SafetyStateMachine {
             // From Landing
             (DroneLanding, Landed) => Some(DroneIdle),
 
+            // The FC disarmed before we got to land (touched down, crashed)
+            (DroneArmed | DroneTakingOff | DroneInMission | DroneReturningHome, Landed) => {
+                Some(DroneIdle)
+            }
+
             // From Emergency - can only be cleared explicitly
             (DroneEmergency, EmergencyCleared) => Some(DroneIdle),
 
+            // Maintenance - only entered on the ground
+            (DroneIdle, MaintenanceStarted) => Some(DroneMaintenance),
+            (DroneMaintenance, MaintenanceComplete) => Some(DroneIdle),
+
             // RTH can be triggered from most active states
             (DroneArmed | DroneTakingOff | DroneInMission, RthTriggered) => {
                 Some(DroneReturningHome)
@@ -197,7 +302,9 @@ impl SafetyStateMachine {
     fn trigger_safety_rth(&mut self, reason: &str) -> TransitionResult {
         match self.current_state {
             // Already safe states - no action needed
-            DroneState::DroneIdle | DroneState::DroneLanding => TransitionResult::Success(self.current_state),
+            DroneState::DroneIdle | DroneState::DroneLanding | DroneState::DroneMaintenance => {
+                TransitionResult::Success(self.current_state)
+            }
 
             // Already returning home
             DroneState::DroneReturningHome => TransitionResult::Success(self.current_state),
@@ -226,6 +333,30 @@ impl SafetyStateMachine {
         }
     }
 
+    /// Force a landing in place and return result
+    fn trigger_forced_land(&mut self, reason: &str) -> TransitionResult {
+        match self.current_state {
+            // On the ground, already landing, or beyond a landing
+            DroneState::DroneIdle
+            | DroneState::DroneLanding
+            | DroneState::DroneMaintenance
+            | DroneState::DroneEmergency => TransitionResult::Success(self.current_state),
+
+            // Anything else lands, including on the way home
+            DroneState::DronePreflight
+            | DroneState::DroneArmed
+            | DroneState::DroneTakingOff
+            | DroneState::DroneInMission
+            | DroneState::DroneReturningHome
+            | DroneState::DroneUnknown => {
+                self.current_state = DroneState::DroneLanding;
+                TransitionResult::ForcedLand {
+                    reason: reason.to_string(),
+                }
+            }
+        }
+    }
+
     /// Check all safety conditions and return any triggered events
     pub fn check_safety(&self, current_time_ms: u64) -> Vec<SafetyEvent> {
         let mut events = Vec::new();
@@ -234,10 +365,12 @@ impl SafetyStateMachine {
             events.push(SafetyEvent::HeartbeatTimeout);
         }
 
-        if self.is_battery_critical() {
-            events.push(SafetyEvent::BatteryCritical);
+        if self.is_fc_link_lost(current_time_ms) {
+            events.push(SafetyEvent::FcLinkLost);
         }
 
+        events.extend(self.battery_event());
+
         events
     }
 }
@@ -264,10 +397,18 @@ pub fn is_valid_transition(from: DroneState, to: DroneState) -> bool {
         (DroneReturningHome, DroneLanding) => true,
         (DroneLanding, DroneIdle) => true,
         (DroneEmergency, DroneIdle) => true, // Emergency cleared
+        (DroneIdle, DroneMaintenance) => true,
+        (DroneMaintenance, DroneIdle) => true,
 
         // RTH can be triggered from flight states
         (DroneArmed | DroneTakingOff, DroneReturningHome) => true,
 
+        // FC disarmed mid-flight
+        (DroneArmed | DroneReturningHome, DroneIdle) => true,
+
+        // Forced landing on a depleted battery
+        (DronePreflight | DroneArmed | DroneTakingOff | DroneInMission, DroneLanding) => true,
+
         _ => false,
     }
 }
@@ -333,6 +474,38 @@ mod tests {
         assert_eq!(fsm.state(), DroneState::DroneReturningHome);
     }
 
+    #[test]
+    fn test_staged_battery_policy() {
+        let mut fsm = SafetyStateMachine::new();
+        fsm.process_event(SafetyEvent::PreflightComplete);
+        fsm.process_event(SafetyEvent::Armed);
+        fsm.process_event(SafetyEvent::TakeoffStarted);
+        fsm.process_event(SafetyEvent::MissionStarted);
+        assert_eq!(fsm.check_safety(0), []);
+
+        // Warning once, without leaving the mission
+        fsm.update_battery(safety::BATTERY_WARNING_PERCENT);
+        assert_eq!(fsm.check_safety(0), [SafetyEvent::BatteryLow]);
+        let result = fsm.process_event(SafetyEvent::BatteryLow);
+        assert!(matches!(result, TransitionResult::Warning { .. }));
+        let result = fsm.process_event(SafetyEvent::BatteryLow);
+        assert!(matches!(result, TransitionResult::Success(DroneState::DroneInMission)));
+
+        fsm.update_battery(safety::BATTERY_CRITICAL_PERCENT);
+        assert_eq!(fsm.check_safety(0), [SafetyEvent::BatteryCritical]);
+        let result = fsm.process_event(SafetyEvent::BatteryCritical);
+        assert!(matches!(result, TransitionResult::EmergencyRth { .. }));
+
+        // On the way home the battery runs out: land where it is
+        fsm.update_battery(safety::BATTERY_LAND_PERCENT);
+        assert_eq!(fsm.check_safety(0), [SafetyEvent::BatteryDepleted]);
+        let result = fsm.process_event(SafetyEvent::BatteryDepleted);
+        assert!(matches!(result, TransitionResult::ForcedLand { .. }));
+        assert_eq!(fsm.state(), DroneState::DroneLanding);
+        let result = fsm.process_event(SafetyEvent::BatteryDepleted);
+        assert!(matches!(result, TransitionResult::Success(DroneState::DroneLanding)));
+    }
+
     #[test]
     fn test_emergency_from_any_state() {
         let mut fsm = SafetyStateMachine::new();
@@ -353,6 +526,19 @@ mod tests {
         assert_eq!(fsm.state(), DroneState::DroneIdle);
     }
 
+    #[test]
+    fn test_disarm_in_flight_lands() {
+        let mut fsm = SafetyStateMachine::new();
+        fsm.process_event(SafetyEvent::PreflightComplete);
+        fsm.process_event(SafetyEvent::Armed);
+        fsm.process_event(SafetyEvent::TakeoffStarted);
+        fsm.process_event(SafetyEvent::MissionStarted);
+
+        let result = fsm.process_event(SafetyEvent::Landed);
+        assert!(matches!(result, TransitionResult::Success(DroneState::DroneIdle)));
+        assert!(is_valid_transition(DroneState::DroneReturningHome, DroneState::DroneIdle));
+    }
+
     #[test]
     fn test_heartbeat_timeout_detection() {
         let mut fsm = SafetyStateMachine::new();
@@ -370,4 +556,66 @@ mod tests {
         let timeout_time = 1000 + safety::HEARTBEAT_TIMEOUT_MS + 1;
         assert!(fsm.is_heartbeat_timed_out(timeout_time));
     }
+
+    #[test]
+    fn test_link_loss_times_out_without_heartbeat() {
+        let mut fsm = SafetyStateMachine::new();
+
+        // Never connected: the grace window runs from the outage
+        fsm.link_lost(1000);
+        fsm.link_lost(5000);
+        assert!(!fsm.is_heartbeat_timed_out(1000 + safety::HEARTBEAT_TIMEOUT_MS));
+        let timeout_time = 1000 + safety::HEARTBEAT_TIMEOUT_MS + 1;
+        assert_eq!(fsm.check_safety(timeout_time), [SafetyEvent::HeartbeatTimeout]);
+
+        fsm.link_restored();
+        assert!(!fsm.is_heartbeat_timed_out(timeout_time));
+    }
+
+    #[test]
+    fn test_fc_link_loss_warns_once_per_outage() {
+        let mut fsm = SafetyStateMachine::new();
+        fsm.process_event(SafetyEvent::PreflightComplete);
+        fsm.process_event(SafetyEvent::Armed);
+
+        // Never heard from the FC: nothing to time out
+        assert!(fsm.check_safety(60_000).is_empty());
+
+        fsm.update_fc_heartbeat(1000);
+        assert!(fsm.check_safety(1000 + safety::FC_HEARTBEAT_TIMEOUT_MS).is_empty());
+        let silent = 1000 + safety::FC_HEARTBEAT_TIMEOUT_MS + 1;
+        assert_eq!(fsm.check_safety(silent), [SafetyEvent::FcLinkLost]);
+
+        // Warns without leaving the flight state, and only once
+        let result = fsm.process_event(SafetyEvent::FcLinkLost);
+        assert!(matches!(result, TransitionResult::Warning { .. }));
+        assert_eq!(fsm.state(), DroneState::DroneArmed);
+        let result = fsm.process_event(SafetyEvent::FcLinkLost);
+        assert!(matches!(result, TransitionResult::Success(DroneState::DroneArmed)));
+
+        // Back, then silent again: warned again
+        fsm.update_fc_heartbeat(silent);
+        assert!(fsm.check_safety(silent).is_empty());
+        let result = fsm.process_event(SafetyEvent::FcLinkLost);
+        assert!(matches!(result, TransitionResult::Warning { .. }));
+    }
+
+    #[test]
+    fn test_maintenance_only_from_idle() {
+        let mut fsm = SafetyStateMachine::new();
+        fsm.process_event(SafetyEvent::PreflightComplete);
+        let result = fsm.process_event(SafetyEvent::MaintenanceStarted);
+        assert!(matches!(result, TransitionResult::Invalid { .. }));
+
+        let mut fsm = SafetyStateMachine::new();
+        fsm.process_event(SafetyEvent::MaintenanceStarted);
+        assert_eq!(fsm.state(), DroneState::DroneMaintenance);
+
+        // Link loss on the ground doesn't interrupt the update
+        let result = fsm.process_event(SafetyEvent::HeartbeatTimeout);
+        assert!(matches!(result, TransitionResult::Success(DroneState::DroneMaintenance)));
+
+        fsm.process_event(SafetyEvent::MaintenanceComplete);
+        assert_eq!(fsm.state(), DroneState::DroneIdle);
+    }
 }
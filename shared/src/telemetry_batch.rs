@@ -0,0 +1,404 @@
+//! Telemetry batching for slow links
+//!
+//! Over Bluetooth the edge collects samples in a [`TelemetryBatcher`] and sends
+//! them as one `TelemetryBatch` instead of one envelope each. The first sample
+//! goes in full; each later one carries position and battery as small
+//! fixed-point deltas from the sample before it (a few bytes each as zigzag
+//! varints) and any other changed fields as a `TelemetryDelta`.
+//!
+//! Both ends track the fixed-point values rather than re-rounding decoded
+//! floats, so rounding never accumulates across a batch. Unpacked positions
+//! and battery readings are rounded to the units of `TelemetrySample`.
+
+use crate::{BatteryStatus, GpsPosition, Telemetry, TelemetryBatch, TelemetryDelta, TelemetrySample};
+use alloc::vec::Vec;
+
+/// Samples per batch before it is sent regardless of the flush interval
+pub const TELEMETRY_BATCH_LEN: usize = 10;
+
+/// Round to the nearest integer (`f64::round` needs `std`)
+fn fixed(value: f64, scale: f64) -> i64 {
+    let scaled = value * scale;
+    (if scaled < 0.0 { scaled - 0.5 } else { scaled + 0.5 }) as i64
+}
+
+/// Position in the units `TelemetrySample` carries deltas in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FixedPosition {
+    latitude_e7: i64,
+    longitude_e7: i64,
+    altitude_mm: i32,
+    heading_cdeg: i32,
+    ground_speed_cmps: i32,
+    satellites: i32,
+    hdop_centi: i32,
+}
+
+impl FixedPosition {
+    fn new(position: &GpsPosition) -> Self {
+        Self {
+            latitude_e7: fixed(position.latitude, 1e7),
+            longitude_e7: fixed(position.longitude, 1e7),
+            altitude_mm: fixed(position.altitude_m.into(), 1e3) as i32,
+            heading_cdeg: fixed(position.heading_deg.into(), 1e2) as i32,
+            ground_speed_cmps: fixed(position.ground_speed_mps.into(), 1e2) as i32,
+            satellites: position.satellites as i32,
+            hdop_centi: fixed(position.hdop.into(), 1e2) as i32,
+        }
+    }
+
+    fn position(&self) -> GpsPosition {
+        GpsPosition {
+            latitude: self.latitude_e7 as f64 / 1e7,
+            longitude: self.longitude_e7 as f64 / 1e7,
+            altitude_m: self.altitude_mm as f32 / 1e3,
+            heading_deg: self.heading_cdeg as f32 / 1e2,
+            ground_speed_mps: self.ground_speed_cmps as f32 / 1e2,
+            satellites: self.satellites.max(0) as u32,
+            hdop: self.hdop_centi as f32 / 1e2,
+        }
+    }
+
+    /// Store `self - previous` in `sample`
+    fn write_delta(&self, previous: &Self, sample: &mut TelemetrySample) {
+        sample.latitude_e7 = self.latitude_e7 - previous.latitude_e7;
+        sample.longitude_e7 = self.longitude_e7 - previous.longitude_e7;
+        sample.altitude_mm = self.altitude_mm.wrapping_sub(previous.altitude_mm);
+        sample.heading_cdeg = self.heading_cdeg.wrapping_sub(previous.heading_cdeg);
+        sample.ground_speed_cmps = self.ground_speed_cmps.wrapping_sub(previous.ground_speed_cmps);
+        sample.satellites = self.satellites.wrapping_sub(previous.satellites);
+        sample.hdop_centi = self.hdop_centi.wrapping_sub(previous.hdop_centi);
+    }
+
+    fn apply_delta(&self, sample: &TelemetrySample) -> Self {
+        Self {
+            latitude_e7: self.latitude_e7.wrapping_add(sample.latitude_e7),
+            longitude_e7: self.longitude_e7.wrapping_add(sample.longitude_e7),
+            altitude_mm: self.altitude_mm.wrapping_add(sample.altitude_mm),
+            heading_cdeg: self.heading_cdeg.wrapping_add(sample.heading_cdeg),
+            ground_speed_cmps: self.ground_speed_cmps.wrapping_add(sample.ground_speed_cmps),
+            satellites: self.satellites.wrapping_add(sample.satellites),
+            hdop_centi: self.hdop_centi.wrapping_add(sample.hdop_centi),
+        }
+    }
+}
+
+/// Battery status in the units `TelemetrySample` carries deltas in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FixedBattery {
+    voltage_mv: i32,
+    current_ma: i32,
+    remaining_percent: i32,
+    remaining_seconds: i32,
+}
+
+impl FixedBattery {
+    fn new(battery: &BatteryStatus) -> Self {
+        Self {
+            voltage_mv: fixed(battery.voltage.into(), 1e3) as i32,
+            current_ma: fixed(battery.current.into(), 1e3) as i32,
+            remaining_percent: battery.remaining_percent as i32,
+            remaining_seconds: battery.remaining_seconds as i32,
+        }
+    }
+
+    fn battery(&self) -> BatteryStatus {
+        BatteryStatus {
+            voltage: self.voltage_mv as f32 / 1e3,
+            current: self.current_ma as f32 / 1e3,
+            remaining_percent: self.remaining_percent.max(0) as u32,
+            remaining_seconds: self.remaining_seconds.max(0) as u32,
+        }
+    }
+
+    /// Store `self - previous` in `sample`
+    fn write_delta(&self, previous: &Self, sample: &mut TelemetrySample) {
+        sample.voltage_mv = self.voltage_mv.wrapping_sub(previous.voltage_mv);
+        sample.current_ma = self.current_ma.wrapping_sub(previous.current_ma);
+        sample.remaining_percent = self.remaining_percent.wrapping_sub(previous.remaining_percent);
+        sample.remaining_seconds = self.remaining_seconds.wrapping_sub(previous.remaining_seconds);
+    }
+
+    fn apply_delta(&self, sample: &TelemetrySample) -> Self {
+        Self {
+            voltage_mv: self.voltage_mv.wrapping_add(sample.voltage_mv),
+            current_ma: self.current_ma.wrapping_add(sample.current_ma),
+            remaining_percent: self.remaining_percent.wrapping_add(sample.remaining_percent),
+            remaining_seconds: self.remaining_seconds.wrapping_add(sample.remaining_seconds),
+        }
+    }
+}
+
+/// Sender side: collects samples into batches
+#[derive(Debug)]
+pub struct TelemetryBatcher {
+    capacity: usize,
+    batch: Option<TelemetryBatch>,
+    /// Last sample added and when it was taken
+    last: Option<(u64, Telemetry)>,
+    position: Option<FixedPosition>,
+    battery: Option<FixedBattery>,
+}
+
+impl TelemetryBatcher {
+    /// Create a batcher that fills up at `capacity` samples
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            batch: None,
+            last: None,
+            position: None,
+            battery: None,
+        }
+    }
+
+    /// Add a sample taken at `timestamp_ms`
+    ///
+    /// Returns a batch to send once it is full, or when this sample can't be
+    /// expressed relative to the last one (a field disappeared, or the clock
+    /// went backwards); the sample then starts the next batch.
+    pub fn push(&mut self, timestamp_ms: u64, telemetry: Telemetry) -> Option<TelemetryBatch> {
+        if self.last.is_some() {
+            match self.sample(timestamp_ms, &telemetry) {
+                Some(sample) => {
+                    if let Some(batch) = &mut self.batch {
+                        batch.samples.push(sample);
+                    }
+                    self.last = Some((timestamp_ms, telemetry));
+                }
+                None => {
+                    let done = self.flush();
+                    self.start(timestamp_ms, telemetry);
+                    return done;
+                }
+            }
+        } else {
+            self.start(timestamp_ms, telemetry);
+        }
+
+        if self.len() >= self.capacity {
+            self.flush()
+        } else {
+            None
+        }
+    }
+
+    /// Take the samples collected so far (on the flush interval, or when a
+    /// full-rate link comes up)
+    pub fn flush(&mut self) -> Option<TelemetryBatch> {
+        self.last = None;
+        self.position = None;
+        self.battery = None;
+        self.batch.take()
+    }
+
+    /// Samples waiting to be sent
+    pub fn len(&self) -> usize {
+        self.batch.as_ref().map_or(0, |b| 1 + b.samples.len())
+    }
+
+    /// Whether no samples are waiting
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_none()
+    }
+
+    fn start(&mut self, timestamp_ms: u64, telemetry: Telemetry) {
+        self.position = telemetry.position.as_ref().map(FixedPosition::new);
+        self.battery = telemetry.battery.as_ref().map(FixedBattery::new);
+        self.batch = Some(TelemetryBatch {
+            first_timestamp_ms: timestamp_ms,
+            first: Some(telemetry.clone()),
+            samples: Vec::new(),
+        });
+        self.last = Some((timestamp_ms, telemetry));
+    }
+
+    /// `telemetry` relative to the last sample, if it can be expressed that way
+    fn sample(&mut self, timestamp_ms: u64, telemetry: &Telemetry) -> Option<TelemetrySample> {
+        let (last_timestamp, last) = self.last.as_ref()?;
+        let interval_ms = u32::try_from(timestamp_ms.checked_sub(*last_timestamp)?).ok()?;
+        let mut changes = TelemetryDelta::diff(last, telemetry)?;
+        changes.keyframe_id = 0;
+
+        let mut sample = TelemetrySample {
+            interval_ms,
+            ..Default::default()
+        };
+        // `diff` rules out fields that disappeared, so a new value either
+        // follows a previous one (delta) or is the first (sent in full)
+        if let Some(position) = &telemetry.position {
+            let next = FixedPosition::new(position);
+            if let Some(previous) = &self.position {
+                next.write_delta(previous, &mut sample);
+                changes.position = None;
+            }
+            self.position = Some(next);
+        }
+        if let Some(battery) = &telemetry.battery {
+            let next = FixedBattery::new(battery);
+            if let Some(previous) = &self.battery {
+                next.write_delta(previous, &mut sample);
+                changes.battery = None;
+            }
+            self.battery = Some(next);
+        }
+
+        sample.changes = (changes != TelemetryDelta::default()).then_some(changes);
+        Some(sample)
+    }
+}
+
+impl Default for TelemetryBatcher {
+    fn default() -> Self {
+        Self::new(TELEMETRY_BATCH_LEN)
+    }
+}
+
+impl TelemetryBatch {
+    /// Every sample in the batch with the time it was taken, oldest first
+    pub fn unpack(&self) -> Vec<(u64, Telemetry)> {
+        let Some(first) = &self.first else {
+            return Vec::new();
+        };
+        let mut timestamp_ms = self.first_timestamp_ms;
+        let mut current = first.clone();
+        let mut position = first.position.as_ref().map(FixedPosition::new);
+        let mut battery = first.battery.as_ref().map(FixedBattery::new);
+
+        let mut samples = Vec::with_capacity(1 + self.samples.len());
+        samples.push((timestamp_ms, current.clone()));
+        for sample in &self.samples {
+            timestamp_ms += u64::from(sample.interval_ms);
+            let changes = sample.changes.as_ref();
+            if let Some(changes) = changes {
+                current = changes.apply(&current);
+            }
+
+            match changes.and_then(|c| c.position.as_ref()) {
+                Some(full) => position = Some(FixedPosition::new(full)),
+                None => {
+                    position = position.map(|p| p.apply_delta(sample));
+                    current.position = position.map(|p| p.position());
+                }
+            }
+            match changes.and_then(|c| c.battery.as_ref()) {
+                Some(full) => battery = Some(FixedBattery::new(full)),
+                None => {
+                    battery = battery.map(|b| b.apply_delta(sample));
+                    current.battery = battery.map(|b| b.battery());
+                }
+            }
+            samples.push((timestamp_ms, current.clone()));
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DroneState;
+    use prost::Message;
+
+    fn telemetry(lat: f64, voltage: f32, uptime_seconds: u64) -> Telemetry {
+        Telemetry {
+            position: Some(GpsPosition {
+                latitude: lat,
+                longitude: 8.5412345,
+                altitude_m: 120.25,
+                heading_deg: 271.5,
+                ground_speed_mps: 12.3,
+                satellites: 14,
+                hdop: 0.8,
+            }),
+            battery: Some(BatteryStatus {
+                voltage,
+                current: 18.5,
+                remaining_percent: 76,
+                remaining_seconds: 900,
+            }),
+            state: DroneState::DroneInMission.into(),
+            uptime_seconds,
+            ..Default::default()
+        }
+    }
+
+    fn assert_close(a: &Telemetry, b: &Telemetry) {
+        let (pa, pb) = (a.position.unwrap(), b.position.unwrap());
+        assert!((pa.latitude - pb.latitude).abs() < 1e-7);
+        assert!((pa.longitude - pb.longitude).abs() < 1e-7);
+        assert!((pa.altitude_m - pb.altitude_m).abs() < 1e-3);
+        assert_eq!(pa.satellites, pb.satellites);
+        let (ba, bb) = (a.battery.unwrap(), b.battery.unwrap());
+        assert!((ba.voltage - bb.voltage).abs() < 1e-3);
+        assert_eq!(ba.remaining_percent, bb.remaining_percent);
+        assert_eq!(a.state, b.state);
+        assert_eq!(a.uptime_seconds, b.uptime_seconds);
+        assert_eq!(a.mission_id, b.mission_id);
+    }
+
+    #[test]
+    fn test_batch_round_trip_and_size() {
+        let mut batcher = TelemetryBatcher::new(TELEMETRY_BATCH_LEN);
+        let mut sent = Vec::new();
+        let mut batch = None;
+        for i in 0..TELEMETRY_BATCH_LEN as u64 {
+            let mut sample = telemetry(47.0 + i as f64 * 1.3e-5, 15.2 - i as f32 * 0.01, 100 + i);
+            if i >= 5 {
+                sample.mission_id = "survey-7".into();
+            }
+            sent.push((1_000 + i * 1_000, sample.clone()));
+            batch = batcher.push(1_000 + i * 1_000, sample);
+        }
+        let batch = batch.expect("full batch is returned");
+        assert!(batcher.is_empty());
+
+        let unpacked = batch.unpack();
+        assert_eq!(unpacked.len(), sent.len());
+        for ((ts_a, a), (ts_b, b)) in unpacked.iter().zip(&sent) {
+            assert_eq!(ts_a, ts_b);
+            assert_close(a, b);
+        }
+
+        // Much smaller than the same samples sent one by one
+        let separate: usize = sent.iter().map(|(_, t)| t.encoded_len()).sum();
+        assert!(batch.encoded_len() * 3 < separate, "{} vs {}", batch.encoded_len(), separate);
+    }
+
+    #[test]
+    fn test_disappearing_field_starts_new_batch() {
+        let mut batcher = TelemetryBatcher::new(TELEMETRY_BATCH_LEN);
+        assert!(batcher.push(1_000, telemetry(47.0, 15.2, 1)).is_none());
+        assert!(batcher.push(2_000, telemetry(47.1, 15.1, 2)).is_none());
+
+        let lost_fix = Telemetry {
+            position: None,
+            ..telemetry(47.1, 15.0, 3)
+        };
+        let done = batcher.push(3_000, lost_fix.clone()).expect("previous batch flushed");
+        assert_eq!(done.unpack().len(), 2);
+        assert_eq!(batcher.len(), 1);
+
+        // The fix coming back is sent in full, then deltas resume
+        batcher.push(4_000, telemetry(47.2, 14.9, 4));
+        batcher.push(5_000, telemetry(47.3, 14.8, 5));
+        let unpacked = batcher.flush().unwrap().unpack();
+        assert_eq!(unpacked[0].1, lost_fix);
+        assert_close(&unpacked[1].1, &telemetry(47.2, 14.9, 4));
+        assert_close(&unpacked[2].1, &telemetry(47.3, 14.8, 5));
+        assert_eq!(unpacked[2].0, 5_000);
+    }
+
+    #[test]
+    fn test_rounding_does_not_drift() {
+        let mut batcher = TelemetryBatcher::new(usize::MAX);
+        let mut last = None;
+        for i in 0..200u64 {
+            // Steps smaller than the fixed-point unit would vanish if rounded per step
+            last = Some(telemetry(47.0 + i as f64 * 3.3e-8, 15.0 + i as f32 * 4e-4, i));
+            batcher.push(i * 100, last.clone().unwrap());
+        }
+        let unpacked = batcher.flush().unwrap().unpack();
+        assert_close(&unpacked.last().unwrap().1, &last.unwrap());
+    }
+}
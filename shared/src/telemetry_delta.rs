@@ -0,0 +1,291 @@
+//! Telemetry delta encoding
+//!
+//! The edge sends a full `Telemetry` keyframe every `TELEMETRY_KEYFRAME_INTERVAL`
+//! frames and `TelemetryDelta` frames in between, each carrying only the fields
+//! that differ from the last keyframe. Deltas are relative to the keyframe (not
+//! the previous delta), so a lost delta never corrupts later state.
+//!
+//! A receiver that joins mid-stream (or misses a keyframe) drops deltas until
+//! the next keyframe arrives.
+
+use crate::{envelope, Telemetry, TelemetryDelta};
+use alloc::boxed::Box;
+
+/// Frames between full keyframes (1 keyframe + N-1 deltas)
+pub const TELEMETRY_KEYFRAME_INTERVAL: u32 = 10;
+
+impl TelemetryDelta {
+    /// Fields of `current` that differ from `keyframe`
+    ///
+    /// Returns `None` when the change can't be expressed as a delta (a field
+    /// or payload value disappeared); send a keyframe instead.
+    pub fn diff(keyframe: &Telemetry, current: &Telemetry) -> Option<Self> {
+        let cleared = (keyframe.position.is_some() && current.position.is_none())
+            || (keyframe.battery.is_some() && current.battery.is_none())
+            || (keyframe.fc_status.is_some() && current.fc_status.is_none())
+            || (keyframe.conn_quality.is_some() && current.conn_quality.is_none())
+            || (keyframe.video.is_some() && current.video.is_none())
+            || keyframe
+                .payload_values
+                .keys()
+                .any(|k| !current.payload_values.contains_key(k));
+        if cleared {
+            return None;
+        }
+
+        Some(Self {
+            keyframe_id: keyframe.keyframe_id,
+            position: changed(&keyframe.position, &current.position),
+            battery: changed(&keyframe.battery, &current.battery),
+            state: (keyframe.state != current.state).then_some(current.state),
+            fc_status: changed(&keyframe.fc_status, &current.fc_status),
+            uptime_seconds: (keyframe.uptime_seconds != current.uptime_seconds)
+                .then_some(current.uptime_seconds),
+            conn_quality: changed(&keyframe.conn_quality, &current.conn_quality),
+            payload_values: current
+                .payload_values
+                .iter()
+                .filter(|(k, v)| keyframe.payload_values.get(*k) != Some(*v))
+                .map(|(k, v)| (k.clone(), *v))
+                .collect(),
+            mission_id: (keyframe.mission_id != current.mission_id)
+                .then(|| current.mission_id.clone()),
+            video: changed(&keyframe.video, &current.video),
+        })
+    }
+
+    /// Rebuild the full telemetry by applying this delta to its keyframe
+    pub fn apply(&self, keyframe: &Telemetry) -> Telemetry {
+        let mut full = keyframe.clone();
+        if self.position.is_some() {
+            full.position = self.position;
+        }
+        if self.battery.is_some() {
+            full.battery = self.battery;
+        }
+        if let Some(state) = self.state {
+            full.state = state;
+        }
+        if self.fc_status.is_some() {
+            full.fc_status = self.fc_status.clone();
+        }
+        if let Some(uptime_seconds) = self.uptime_seconds {
+            full.uptime_seconds = uptime_seconds;
+        }
+        if self.conn_quality.is_some() {
+            full.conn_quality = self.conn_quality;
+        }
+        for (k, v) in &self.payload_values {
+            full.payload_values.insert(k.clone(), *v);
+        }
+        if let Some(mission_id) = &self.mission_id {
+            full.mission_id = mission_id.clone();
+        }
+        if self.video.is_some() {
+            full.video = self.video.clone();
+        }
+        full
+    }
+}
+
+fn changed<T: Clone + PartialEq>(keyframe: &Option<T>, current: &Option<T>) -> Option<T> {
+    if keyframe != current {
+        current.clone()
+    } else {
+        None
+    }
+}
+
+/// Sender side: decides between keyframes and deltas
+#[derive(Debug)]
+pub struct DeltaEncoder {
+    interval: u32,
+    keyframe: Option<Telemetry>,
+    frames_since_keyframe: u32,
+    next_keyframe_id: u64,
+}
+
+impl DeltaEncoder {
+    /// Create an encoder sending a keyframe every `interval` frames
+    pub fn new(interval: u32) -> Self {
+        Self {
+            interval: interval.max(1),
+            keyframe: None,
+            frames_since_keyframe: 0,
+            next_keyframe_id: 1,
+        }
+    }
+
+    /// Send a keyframe next (e.g. after reconnecting)
+    pub fn force_keyframe(&mut self) {
+        self.keyframe = None;
+    }
+
+    /// Encode the next telemetry frame as a keyframe or delta payload
+    pub fn encode(&mut self, telemetry: Telemetry) -> envelope::Payload {
+        if let Some(keyframe) = &self.keyframe {
+            if self.frames_since_keyframe < self.interval {
+                if let Some(delta) = TelemetryDelta::diff(keyframe, &telemetry) {
+                    self.frames_since_keyframe += 1;
+                    return envelope::Payload::TelemetryDelta(Box::new(delta));
+                }
+            }
+        }
+
+        let mut keyframe = telemetry;
+        keyframe.keyframe_id = self.next_keyframe_id;
+        self.next_keyframe_id += 1;
+        self.frames_since_keyframe = 1;
+        self.keyframe = Some(keyframe.clone());
+        envelope::Payload::Telemetry(keyframe)
+    }
+}
+
+impl Default for DeltaEncoder {
+    fn default() -> Self {
+        Self::new(TELEMETRY_KEYFRAME_INTERVAL)
+    }
+}
+
+/// Receiver side: reconstructs full telemetry from keyframes and deltas
+#[derive(Debug, Default)]
+pub struct DeltaDecoder {
+    keyframe: Option<Telemetry>,
+}
+
+impl DeltaDecoder {
+    /// Create a decoder waiting for its first keyframe
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a keyframe and return it
+    pub fn apply_keyframe(&mut self, telemetry: Telemetry) -> Telemetry {
+        self.keyframe = Some(telemetry.clone());
+        telemetry
+    }
+
+    /// Reconstruct full telemetry, or `None` while waiting for the matching keyframe
+    pub fn apply_delta(&self, delta: &TelemetryDelta) -> Option<Telemetry> {
+        self.keyframe
+            .as_ref()
+            .filter(|k| k.keyframe_id == delta.keyframe_id)
+            .map(|k| delta.apply(k))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BatteryStatus, DroneState, GpsPosition, VideoStream};
+
+    fn telemetry(lat: f64, battery: u32, uptime_seconds: u64) -> Telemetry {
+        Telemetry {
+            position: Some(GpsPosition {
+                latitude: lat,
+                longitude: 8.5,
+                altitude_m: 40.0,
+                ..Default::default()
+            }),
+            battery: Some(BatteryStatus {
+                voltage: 15.2,
+                remaining_percent: battery,
+                ..Default::default()
+            }),
+            state: DroneState::DroneInMission.into(),
+            uptime_seconds,
+            ..Default::default()
+        }
+    }
+
+    fn expect_keyframe(payload: envelope::Payload) -> Telemetry {
+        match payload {
+            envelope::Payload::Telemetry(t) => t,
+            other => panic!("expected keyframe, got {:?}", other),
+        }
+    }
+
+    fn expect_delta(payload: envelope::Payload) -> TelemetryDelta {
+        match payload {
+            envelope::Payload::TelemetryDelta(d) => *d,
+            other => panic!("expected delta, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reconstruct_from_keyframe_and_deltas() {
+        let mut encoder = DeltaEncoder::new(TELEMETRY_KEYFRAME_INTERVAL);
+        let mut decoder = DeltaDecoder::new();
+
+        let keyframe = expect_keyframe(encoder.encode(telemetry(47.0, 90, 10)));
+        decoder.apply_keyframe(keyframe);
+
+        // Only position and uptime move; battery stays put
+        let frame = telemetry(47.001, 90, 11);
+        let delta = expect_delta(encoder.encode(frame.clone()));
+        assert!(delta.position.is_some());
+        assert!(delta.battery.is_none());
+        assert!(delta.state.is_none());
+        assert_eq!(delta.uptime_seconds, Some(11));
+        let rebuilt = decoder.apply_delta(&delta).expect("keyframe known");
+        assert_eq!(rebuilt.position, frame.position);
+        assert_eq!(rebuilt.uptime_seconds, 11);
+
+        // A later delta is still relative to the keyframe
+        let mut frame = telemetry(47.002, 85, 12);
+        frame.payload_values.insert("CO_PPM".into(), 3.5);
+        frame.mission_id = "survey-7".into();
+        frame.video = Some(VideoStream {
+            url: "rtsp://10.0.0.7:8554/edge-001".into(),
+            ..Default::default()
+        });
+        let delta = expect_delta(encoder.encode(frame.clone()));
+        let rebuilt = decoder.apply_delta(&delta).expect("keyframe known");
+        assert_eq!(Telemetry { keyframe_id: 0, ..rebuilt }, frame);
+    }
+
+    #[test]
+    fn test_keyframe_interval() {
+        let mut encoder = DeltaEncoder::new(3);
+        let kinds: Vec<bool> = (0..7)
+            .map(|i| matches!(encoder.encode(telemetry(47.0, 90, i)), envelope::Payload::Telemetry(_)))
+            .collect();
+        assert_eq!(kinds, [true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn test_receiver_joining_mid_stream_waits_for_keyframe() {
+        let mut encoder = DeltaEncoder::new(3);
+        let mut decoder = DeltaDecoder::new();
+
+        let _missed = encoder.encode(telemetry(47.0, 90, 1));
+        let delta = expect_delta(encoder.encode(telemetry(47.1, 90, 2)));
+        assert!(decoder.apply_delta(&delta).is_none());
+
+        let _ = encoder.encode(telemetry(47.2, 90, 3));
+        let keyframe = expect_keyframe(encoder.encode(telemetry(47.3, 90, 4)));
+        decoder.apply_keyframe(keyframe);
+        let delta = expect_delta(encoder.encode(telemetry(47.4, 90, 5)));
+        assert_eq!(decoder.apply_delta(&delta).unwrap().uptime_seconds, 5);
+
+        // Stale delta for an older keyframe is not applied
+        let stale = TelemetryDelta { keyframe_id: 1, ..delta };
+        assert!(decoder.apply_delta(&stale).is_none());
+    }
+
+    #[test]
+    fn test_cleared_field_forces_keyframe() {
+        let mut encoder = DeltaEncoder::default();
+        let _ = encoder.encode(telemetry(47.0, 90, 1));
+        let mut frame = telemetry(47.0, 90, 2);
+        frame.position = None;
+        assert_eq!(expect_keyframe(encoder.encode(frame)).position, None);
+
+        // A stream that stopped
+        let mut encoder = DeltaEncoder::default();
+        let mut frame = telemetry(47.0, 90, 3);
+        frame.video = Some(VideoStream::default());
+        let _ = encoder.encode(frame);
+        assert_eq!(expect_keyframe(encoder.encode(telemetry(47.0, 90, 4))).video, None);
+    }
+}
@@ -0,0 +1,569 @@
+//! UDP links with selective-repeat retransmission
+//!
+//! On a lossy 5G link one lost TCP segment stalls every frame behind it.
+//! Over a [`UdpLink`] each telemetry frame is a single fire-and-forget
+//! datagram, so a loss costs one sample and nothing waits on it. Everything
+//! else (commands, ACKs, hellos) is cut into segments that are acknowledged
+//! selectively, retransmitted when lost and delivered in order.
+//!
+//! Links are [`MessageLink`]s: whatever is written between two flushes is one
+//! message. A message goes fire-and-forget only if it is exactly one
+//! telemetry frame that fits in a datagram; anything else, Noise ciphertext
+//! included, is sent reliably.
+//!
+//! Each datagram starts with a kind byte:
+//!
+//! | Kind | Body |
+//! |------|------|
+//! | `OPEN`, `OPEN_ACK`, `CLOSE` | none |
+//! | `DATA` | sequence (u64), last segment of the message (u8), bytes |
+//! | `UNRELIABLE` | one whole message |
+//! | `ACK` | next sequence expected (u64), count (u8), that many sequences received past it (u64) |
+
+use crate::message_link::{is_telemetry_frame, MessageLink};
+use alloc::collections::BTreeMap;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{lookup_host, ToSocketAddrs, UdpSocket};
+use tokio::sync::mpsc;
+
+const KIND_OPEN: u8 = 1;
+const KIND_OPEN_ACK: u8 = 2;
+const KIND_CLOSE: u8 = 3;
+const KIND_DATA: u8 = 4;
+const KIND_UNRELIABLE: u8 = 5;
+const KIND_ACK: u8 = 6;
+
+/// Largest datagram sent, kept under common path MTUs to avoid IP fragmentation
+pub const MAX_DATAGRAM_LEN: usize = 1200;
+
+/// Kind, sequence and last-segment flag before each segment
+const DATA_HEADER_LEN: usize = 10;
+
+/// Kind, next expected sequence and count before the received sequences
+const ACK_HEADER_LEN: usize = 10;
+
+/// Segments sent but not yet acknowledged before new messages wait
+pub const SEND_WINDOW: usize = 256;
+
+/// How far past the next expected segment the receiver holds segments
+const RECEIVE_WINDOW: u64 = 4096;
+
+/// Received sequences listed in one ACK
+const MAX_SELECTIVE_ACKS: usize = 32;
+
+/// Retransmission timeout before any round trip is measured
+const INITIAL_RTO: Duration = Duration::from_millis(300);
+const MIN_RTO: Duration = Duration::from_millis(50);
+const MAX_RTO: Duration = Duration::from_secs(4);
+
+/// Retransmissions of one segment before the link is given up
+pub const MAX_RETRANSMISSIONS: u32 = 8;
+
+/// How often `OPEN` is repeated while connecting
+const OPEN_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Wake-up for the retransmission timer when nothing is in flight
+const IDLE_WAKEUP: Duration = Duration::from_secs(60);
+
+/// Datagrams queued between the listener and each link
+const QUEUE_LEN: usize = 32;
+
+/// Why the retransmission state gave up on a link
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ArqError {
+    #[error("Segment {sequence} unacknowledged after {retransmissions} retransmissions")]
+    Unacknowledged { sequence: u64, retransmissions: u32 },
+}
+
+impl From<ArqError> for io::Error {
+    fn from(e: ArqError) -> Self {
+        io::Error::new(io::ErrorKind::TimedOut, e)
+    }
+}
+
+#[derive(Debug)]
+struct InFlight {
+    datagram: Bytes,
+    sent_at: Instant,
+    retransmissions: u32,
+}
+
+/// Selective-repeat state for one end of a link, without any IO
+#[derive(Debug)]
+pub struct Arq {
+    next_sequence: u64,
+    in_flight: BTreeMap<u64, InFlight>,
+    /// Next segment to deliver
+    expected: u64,
+    /// Segments received past `expected`, with their last-segment flag
+    held: BTreeMap<u64, (bool, Bytes)>,
+    /// Delivered segments of a message not yet complete
+    partial: BytesMut,
+    srtt: Option<Duration>,
+}
+
+impl Default for Arq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arq {
+    pub fn new() -> Self {
+        Self {
+            next_sequence: 0,
+            in_flight: BTreeMap::new(),
+            expected: 0,
+            held: BTreeMap::new(),
+            partial: BytesMut::new(),
+            srtt: None,
+        }
+    }
+
+    /// Whether a new message may be sent
+    pub fn window_open(&self) -> bool {
+        self.in_flight.len() < SEND_WINDOW
+    }
+
+    /// Segments in flight
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Datagrams carrying `message` reliably
+    pub fn send(&mut self, message: &[u8], now: Instant) -> Vec<Bytes> {
+        let chunks: Vec<&[u8]> = message.chunks(MAX_DATAGRAM_LEN - DATA_HEADER_LEN).collect();
+        let last = chunks.len().saturating_sub(1);
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let sequence = self.next_sequence;
+                self.next_sequence += 1;
+                let mut datagram = BytesMut::with_capacity(DATA_HEADER_LEN + chunk.len());
+                datagram.put_u8(KIND_DATA);
+                datagram.put_u64(sequence);
+                datagram.put_u8((index == last) as u8);
+                datagram.put_slice(chunk);
+                let datagram = datagram.freeze();
+                self.in_flight.insert(
+                    sequence,
+                    InFlight {
+                        datagram: datagram.clone(),
+                        sent_at: now,
+                        retransmissions: 0,
+                    },
+                );
+                datagram
+            })
+            .collect()
+    }
+
+    /// Take in a `DATA` or `ACK` datagram
+    ///
+    /// Returns the messages it completes, in order, and the ACK to send back.
+    pub fn receive(&mut self, datagram: &[u8], now: Instant) -> (Vec<Bytes>, Option<Bytes>) {
+        match datagram.first() {
+            Some(&KIND_DATA) if datagram.len() >= DATA_HEADER_LEN => {
+                let mut buf = &datagram[1..];
+                let sequence = buf.get_u64();
+                let last = buf.get_u8() != 0;
+                if sequence >= self.expected && sequence - self.expected < RECEIVE_WINDOW {
+                    self.held
+                        .entry(sequence)
+                        .or_insert_with(|| (last, Bytes::copy_from_slice(buf)));
+                }
+
+                let mut messages = Vec::new();
+                while let Some((last, segment)) = self.held.remove(&self.expected) {
+                    self.expected += 1;
+                    self.partial.put_slice(&segment);
+                    if last {
+                        messages.push(self.partial.split().freeze());
+                    }
+                }
+                // Duplicates are acknowledged too: the first ACK may have been lost
+                (messages, Some(self.ack()))
+            }
+            Some(&KIND_ACK) if datagram.len() >= ACK_HEADER_LEN => {
+                let mut buf = &datagram[1..];
+                let expected = buf.get_u64();
+                let count = buf.get_u8() as usize;
+                let mut acked: Vec<u64> = self.in_flight.range(..expected).map(|(&s, _)| s).collect();
+                for _ in 0..count {
+                    if buf.remaining() < 8 {
+                        break;
+                    }
+                    acked.push(buf.get_u64());
+                }
+                for sequence in acked {
+                    if let Some(segment) = self.in_flight.remove(&sequence) {
+                        // Karn: a retransmitted segment's ACK can't be timed
+                        if segment.retransmissions == 0 {
+                            self.sample_rtt(now.saturating_duration_since(segment.sent_at));
+                        }
+                    }
+                }
+                (Vec::new(), None)
+            }
+            _ => (Vec::new(), None),
+        }
+    }
+
+    /// When the next retransmission is due
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let rto = self.rto();
+        self.in_flight.values().map(|segment| deadline(segment, rto)).min()
+    }
+
+    /// Datagrams due for retransmission
+    ///
+    /// Fails once a segment has been retransmitted `MAX_RETRANSMISSIONS` times.
+    pub fn retransmit(&mut self, now: Instant) -> Result<Vec<Bytes>, ArqError> {
+        let rto = self.rto();
+        let mut due = Vec::new();
+        for (&sequence, segment) in self.in_flight.iter_mut() {
+            if now < deadline(segment, rto) {
+                continue;
+            }
+            if segment.retransmissions >= MAX_RETRANSMISSIONS {
+                return Err(ArqError::Unacknowledged {
+                    sequence,
+                    retransmissions: segment.retransmissions,
+                });
+            }
+            segment.retransmissions += 1;
+            segment.sent_at = now;
+            due.push(segment.datagram.clone());
+        }
+        Ok(due)
+    }
+
+    fn ack(&self) -> Bytes {
+        let received: Vec<u64> = self.held.keys().take(MAX_SELECTIVE_ACKS).copied().collect();
+        let mut ack = BytesMut::with_capacity(ACK_HEADER_LEN + received.len() * 8);
+        ack.put_u8(KIND_ACK);
+        ack.put_u64(self.expected);
+        ack.put_u8(received.len() as u8);
+        for sequence in received {
+            ack.put_u64(sequence);
+        }
+        ack.freeze()
+    }
+
+    fn rto(&self) -> Duration {
+        match self.srtt {
+            Some(srtt) => (srtt * 2).clamp(MIN_RTO, MAX_RTO),
+            None => INITIAL_RTO,
+        }
+    }
+
+    fn sample_rtt(&mut self, sample: Duration) {
+        self.srtt = Some(match self.srtt {
+            Some(srtt) => (srtt * 7 + sample) / 8,
+            None => sample,
+        });
+    }
+}
+
+/// Retransmission time of `segment`, backing off with each retransmission
+fn deadline(segment: &InFlight, rto: Duration) -> Instant {
+    segment.sent_at + (rto * 2u32.pow(segment.retransmissions)).min(MAX_RTO)
+}
+
+/// Datagram side of a link
+enum Socket {
+    /// Our own socket, connected to the listener
+    Connected(UdpSocket),
+    /// One peer of a listener's socket; the listener hands over its datagrams
+    Peer {
+        socket: Arc<UdpSocket>,
+        addr: SocketAddr,
+        datagrams: mpsc::Receiver<Bytes>,
+    },
+}
+
+impl Socket {
+    async fn send(&self, datagram: &[u8]) -> io::Result<()> {
+        match self {
+            Socket::Connected(socket) => socket.send(datagram).await?,
+            Socket::Peer { socket, addr, .. } => socket.send_to(datagram, addr).await?,
+        };
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> io::Result<Bytes> {
+        match self {
+            Socket::Connected(socket) => {
+                let mut buf = vec![0; u16::MAX as usize];
+                let len = socket.recv(&mut buf).await?;
+                buf.truncate(len);
+                Ok(buf.into())
+            }
+            Socket::Peer { datagrams, .. } => datagrams
+                .recv()
+                .await
+                .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionAborted, "UDP listener closed")),
+        }
+    }
+}
+
+/// Open a link to the [`UdpListener`] at `addr`
+///
+/// Repeats the opening datagram until the listener answers, so callers bound
+/// the wait with a timeout.
+pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<MessageLink> {
+    let addr = lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"))?;
+    let local: SocketAddr = if addr.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(addr).await?;
+
+    let mut buf = [0; 16];
+    loop {
+        socket.send(&[KIND_OPEN]).await?;
+        match tokio::time::timeout(OPEN_INTERVAL, socket.recv(&mut buf)).await {
+            Ok(Ok(1)) if buf[0] == KIND_OPEN_ACK => break,
+            // Nothing listening answers with ICMP, reported here
+            Ok(Err(e)) => return Err(e),
+            Ok(Ok(_)) | Err(_) => {}
+        }
+    }
+    Ok(spawn(Socket::Connected(socket)))
+}
+
+fn spawn(socket: Socket) -> MessageLink {
+    let (link, channels) = MessageLink::new();
+    tokio::spawn(async move {
+        if let Err(e) = drive(socket, channels.outgoing, &channels.incoming).await {
+            let _ = channels.incoming.try_send(Err(e));
+        }
+    });
+    link
+}
+
+/// Move messages between a link and its socket until either closes
+async fn drive(
+    mut socket: Socket,
+    mut outgoing: mpsc::Receiver<Bytes>,
+    incoming: &mpsc::Sender<io::Result<Bytes>>,
+) -> io::Result<()> {
+    let mut arq = Arq::new();
+    loop {
+        let retransmit_at = arq
+            .next_deadline()
+            .unwrap_or_else(|| Instant::now() + IDLE_WAKEUP);
+        let mut delivered = Vec::new();
+        tokio::select! {
+            datagram = socket.recv() => {
+                let datagram = datagram?;
+                match datagram.first() {
+                    Some(&KIND_UNRELIABLE) => delivered.push(datagram.slice(1..)),
+                    // The peer retries until our OPEN_ACK gets through
+                    Some(&KIND_OPEN) => socket.send(&[KIND_OPEN_ACK]).await?,
+                    Some(&KIND_CLOSE) => return Ok(()),
+                    _ => {
+                        let (messages, ack) = arq.receive(&datagram, Instant::now());
+                        if let Some(ack) = ack {
+                            socket.send(&ack).await?;
+                        }
+                        delivered = messages;
+                    }
+                }
+            }
+            message = outgoing.recv(), if arq.window_open() => {
+                let Some(message) = message else {
+                    let _ = socket.send(&[KIND_CLOSE]).await;
+                    return Ok(());
+                };
+                if message.len() < MAX_DATAGRAM_LEN && is_telemetry_frame(&message) {
+                    let mut datagram = BytesMut::with_capacity(1 + message.len());
+                    datagram.put_u8(KIND_UNRELIABLE);
+                    datagram.put_slice(&message);
+                    socket.send(&datagram).await?;
+                } else {
+                    for datagram in arq.send(&message, Instant::now()) {
+                        socket.send(&datagram).await?;
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(retransmit_at.into()) => {
+                for datagram in arq.retransmit(Instant::now())? {
+                    socket.send(&datagram).await?;
+                }
+            }
+        }
+        for message in delivered {
+            if incoming.send(Ok(message)).await.is_err() {
+                // The link was dropped
+                let _ = socket.send(&[KIND_CLOSE]).await;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Accepts UDP links on one socket
+pub struct UdpListener {
+    accepted: mpsc::Receiver<(MessageLink, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl UdpListener {
+    pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = Arc::new(UdpSocket::bind(addr).await?);
+        let local_addr = socket.local_addr()?;
+        let (accepted_tx, accepted) = mpsc::channel(QUEUE_LEN);
+        tokio::spawn(demux(socket, accepted_tx));
+        Ok(Self { accepted, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Wait for the next peer to open a link
+    pub async fn accept(&mut self) -> io::Result<(MessageLink, SocketAddr)> {
+        self.accepted
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::other("UDP listener stopped"))
+    }
+}
+
+/// Hand each peer's datagrams to its link, opening links for new peers
+async fn demux(socket: Arc<UdpSocket>, accepted: mpsc::Sender<(MessageLink, SocketAddr)>) {
+    let mut peers: HashMap<SocketAddr, mpsc::Sender<Bytes>> = HashMap::new();
+    let mut buf = vec![0; u16::MAX as usize];
+    loop {
+        let Ok((len, addr)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let datagram = Bytes::copy_from_slice(&buf[..len]);
+        if let Some(link) = peers.get(&addr) {
+            match link.try_send(datagram.clone()) {
+                // A full queue drops the datagram like the network would
+                Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => continue,
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    peers.remove(&addr);
+                }
+            }
+        }
+        if datagram[..] != [KIND_OPEN] {
+            continue;
+        }
+
+        let (datagrams_tx, datagrams) = mpsc::channel(QUEUE_LEN);
+        let link = spawn(Socket::Peer {
+            socket: socket.clone(),
+            addr,
+            datagrams,
+        });
+        if accepted.send((link, addr)).await.is_err() {
+            return;
+        }
+        peers.insert(addr, datagrams_tx);
+        let _ = socket.send_to(&[KIND_OPEN_ACK], addr).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codec, envelope, Header, MessageType, Telemetry};
+    use alloc::vec;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn frame(payload: envelope::Payload) -> Bytes {
+        let envelope = crate::Envelope {
+            header: Some(Header::new("edge-001", MessageType::MsgUnknown, 1)),
+            payload: Some(payload),
+            signature: Vec::new(),
+        };
+        codec::encode(&envelope).unwrap()
+    }
+
+    #[test]
+    fn test_arq_recovers_lost_segments_in_order() {
+        let now = Instant::now();
+        let mut sender = Arq::new();
+        let mut receiver = Arq::new();
+
+        let message = vec![7u8; 3000];
+        let segments = sender.send(&message, now);
+        assert_eq!(segments.len(), 3);
+
+        // The middle segment is lost: nothing is delivered past the gap
+        let (delivered, _) = receiver.receive(&segments[0], now);
+        assert!(delivered.is_empty());
+        let (delivered, ack) = receiver.receive(&segments[2], now);
+        assert!(delivered.is_empty());
+        sender.receive(&ack.unwrap(), now);
+        assert_eq!(sender.in_flight(), 1);
+
+        // Only the lost segment is retransmitted
+        let later = now + INITIAL_RTO;
+        let resent = sender.retransmit(later).unwrap();
+        assert_eq!(resent, [segments[1].clone()]);
+        let (delivered, ack) = receiver.receive(&resent[0], later);
+        assert_eq!(delivered, [Bytes::from(message)]);
+        sender.receive(&ack.unwrap(), later);
+        assert_eq!(sender.in_flight(), 0);
+        assert!(sender.next_deadline().is_none());
+    }
+
+    #[test]
+    fn test_arq_gives_up_on_silent_peer() {
+        let mut now = Instant::now();
+        let mut arq = Arq::new();
+        arq.send(b"command", now);
+        for _ in 0..MAX_RETRANSMISSIONS {
+            now = arq.next_deadline().unwrap();
+            assert_eq!(arq.retransmit(now).unwrap().len(), 1);
+        }
+        now = arq.next_deadline().unwrap();
+        assert!(matches!(
+            arq.retransmit(now),
+            Err(ArqError::Unacknowledged { sequence: 0, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_link_carries_messages_both_ways() {
+        let mut listener = UdpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr();
+        let (connected, accepted) = tokio::join!(connect(addr), listener.accept());
+        let mut client = connected.unwrap();
+        let (mut server, _) = accepted.unwrap();
+
+        let telemetry = frame(envelope::Payload::Telemetry(Telemetry::default()));
+        client.write_all(&telemetry).await.unwrap();
+        client.flush().await.unwrap();
+        let mut received = vec![0; telemetry.len()];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, telemetry);
+
+        // Larger than a datagram: segmented and reassembled
+        let upload = vec![0x5a; 5000];
+        server.write_all(&upload).await.unwrap();
+        server.flush().await.unwrap();
+        let mut received = vec![0; upload.len()];
+        client.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, upload);
+
+        // Dropping one end closes the other
+        drop(client);
+        assert_eq!(server.read(&mut received).await.unwrap(), 0);
+    }
+}
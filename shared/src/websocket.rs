@@ -0,0 +1,214 @@
+//! WebSocket links for browsers and gateways
+//!
+//! Web dashboards and third-party gateways can't easily speak the raw
+//! length-prefixed TCP framing, so a WebSocket link carries the same
+//! protobuf [`Envelope`]s one per binary message, with no prefix, checksum
+//! or compression. Links are [`MessageLink`]s: the link's task turns the
+//! frames written to it into messages and received messages back into
+//! frames, so sessions read and write a WebSocket link like any other.
+//!
+//! Envelopes travel in the clear, so WebSocket links can't be combined with
+//! Noise; serve them behind a TLS proxy (`wss://`) on untrusted networks.
+
+use crate::message_link::{LinkChannels, MessageLink};
+use crate::{codec, Envelope};
+use bytes::{Bytes, BytesMut};
+use futures_util::{SinkExt, StreamExt};
+use prost::Message as _;
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::WebSocketStream;
+
+/// Handshaken links waiting for `accept`
+const ACCEPT_QUEUE_LEN: usize = 16;
+
+/// Open a link to the WebSocket server at `url` (`ws://host:port/`)
+pub async fn connect(url: &str) -> io::Result<MessageLink> {
+    let (ws, _) = tokio_tungstenite::connect_async(url).await.map_err(into_io)?;
+    Ok(spawn(ws))
+}
+
+/// Run a link over an open WebSocket in a background task
+fn spawn<S>(ws: WebSocketStream<S>) -> MessageLink
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (link, channels) = MessageLink::new();
+    tokio::spawn(drive(ws, channels));
+    link
+}
+
+async fn drive<S>(mut ws: WebSocketStream<S>, channels: LinkChannels)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let LinkChannels { mut outgoing, incoming } = channels;
+    let result = loop {
+        tokio::select! {
+            message = outgoing.recv() => {
+                let Some(message) = message else {
+                    let _ = ws.close(None).await;
+                    return;
+                };
+                if let Err(e) = send_frames(&mut ws, message).await {
+                    break Err(e);
+                }
+            }
+            message = ws.next() => match message {
+                Some(Ok(Message::Binary(data))) => match envelope_frame(&data) {
+                    Ok(frame) => {
+                        if incoming.send(Ok(frame)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => break Err(e),
+                },
+                Some(Ok(Message::Text(_))) => {
+                    break Err(invalid_data("WebSocket messages must be binary envelopes"));
+                }
+                // Pings are answered by tungstenite itself
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => {}
+                Some(Ok(Message::Close(_))) | None => break Ok(()),
+                Some(Err(e)) => break Err(into_io(e)),
+            },
+        }
+    };
+    if let Err(e) = result {
+        let _ = incoming.send(Err(e)).await;
+    }
+}
+
+/// Send each frame in `message` as one binary message holding its envelope
+async fn send_frames<S>(ws: &mut WebSocketStream<S>, message: Bytes) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = BytesMut::from(&message[..]);
+    while !buf.is_empty() {
+        let envelope = match codec::decode(&mut buf) {
+            Ok(Some(envelope)) => envelope,
+            Ok(None) => return Err(invalid_data("partial frame written to WebSocket link")),
+            // Noise ciphertext lands here too
+            Err(e) => return Err(invalid_data(&format!("not a frame: {}", e))),
+        };
+        ws.feed(Message::Binary(envelope.encode_to_vec().into()))
+            .await
+            .map_err(into_io)?;
+    }
+    ws.flush().await.map_err(into_io)
+}
+
+/// Frame a received envelope for the session reading the link
+fn envelope_frame(data: &[u8]) -> io::Result<Bytes> {
+    let envelope = Envelope::decode(data).map_err(|e| invalid_data(&format!("bad envelope: {}", e)))?;
+    codec::encode(&envelope).map_err(|e| invalid_data(&e.to_string()))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn into_io(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+/// Accepts WebSocket links on a TCP port
+pub struct WebSocketListener {
+    accepted: mpsc::Receiver<(MessageLink, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl WebSocketListener {
+    pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let (accepted_tx, accepted) = mpsc::channel(ACCEPT_QUEUE_LEN);
+        tokio::spawn(async move {
+            while let Ok((stream, addr)) = listener.accept().await {
+                let accepted = accepted_tx.clone();
+                // Handshakes run side by side, so a slow client doesn't block the rest
+                tokio::spawn(async move {
+                    if let Ok(ws) = tokio_tungstenite::accept_async(stream).await {
+                        let _ = accepted.send((spawn(ws), addr)).await;
+                    }
+                });
+            }
+        });
+        Ok(Self { accepted, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Wait for the next peer to open a link
+    pub async fn accept(&mut self) -> io::Result<(MessageLink, SocketAddr)> {
+        self.accepted
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::other("WebSocket listener stopped"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{envelope, Header, MessageType, Telemetry};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn telemetry() -> Envelope {
+        Envelope {
+            header: Some(Header::new("edge-001", MessageType::MsgTelemetry, 1)),
+            payload: Some(envelope::Payload::Telemetry(Telemetry::default())),
+            signature: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_link_carries_bare_envelopes() {
+        let mut listener = WebSocketListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr());
+
+        // A browser-style client sends bare protobuf; the server reads frames
+        let (client, accepted) = tokio::join!(tokio_tungstenite::connect_async(&url), listener.accept());
+        let (mut client, _) = client.unwrap();
+        let (mut server, _) = accepted.unwrap();
+        let telemetry = telemetry();
+        client.send(Message::Binary(telemetry.encode_to_vec().into())).await.unwrap();
+        let frame = codec::encode(&telemetry).unwrap();
+        let mut read = vec![0; frame.len()];
+        server.read_exact(&mut read).await.unwrap();
+        assert_eq!(read, frame);
+
+        // Frames written together arrive as one message each
+        server.write_all(&[frame.clone(), frame].concat()).await.unwrap();
+        server.flush().await.unwrap();
+        for _ in 0..2 {
+            let Some(Ok(Message::Binary(data))) = client.next().await else {
+                panic!("expected a binary message");
+            };
+            assert_eq!(Envelope::decode(&data[..]).unwrap(), telemetry);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_text_messages_end_the_link() {
+        let mut listener = WebSocketListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr());
+        let (client, accepted) = tokio::join!(tokio_tungstenite::connect_async(&url), listener.accept());
+        let (mut client, _) = client.unwrap();
+        let (mut server, _) = accepted.unwrap();
+
+        client.send(Message::Text("hello".into())).await.unwrap();
+        let mut buf = [0; 16];
+        let err = server.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
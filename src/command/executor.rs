@@ -1,12 +1,23 @@
 //! Command executor - validates and dispatches incoming commands
 
-use super::handlers::{self, HandlerContext};
+use super::handlers::{self, FcLink, HandlerContext};
+use super::log_transfer;
+use super::mission_upload::MissionUploads;
+use crate::connection::{HeartbeatProvider, OutboundSender};
+use crate::mavlink::{CommandAckError, FlightController, MavCommandSender, TelemetryReader};
+use crate::safety::Geofence;
+use crate::state::DroneStateStore;
+use crate::video::VideoStreamer;
+use async_trait::async_trait;
 use resqterra_shared::{
-    Ack, AckStatus, Command, CommandType, DroneState, Envelope, Header, MessageType,
-    now_ms, safety,
+    command, Ack, AckStatus, Command, CommandCapability, CommandType, DroneState, Envelope,
+    FcParam, Fault, Header, Heartbeat, MessageType, TransferProgress, VideoStream, now_ms,
+    priority, safety,
 };
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 /// Result of command execution
@@ -18,16 +29,197 @@ pub enum CommandResult {
     Failed { message: String },
     /// Command rejected (invalid state, expired, etc.)
     Rejected { message: String },
+    /// Command completed and reports the fault list
+    Faults { message: String, faults: Vec<Fault> },
+    /// Command completed and the FC is now flying this mission item
+    Waypoint { message: String, current_waypoint: u32 },
+    /// Command completed and reports the supported commands
+    Capabilities { message: String, capabilities: Vec<CommandCapability> },
+    /// Command completed and reports FC parameter values
+    Params { message: String, params: Vec<FcParam> },
+    /// Command completed and reports the negotiated video stream
+    Video { message: String, stream: VideoStream },
     /// Command is being executed asynchronously (ACK will come later)
     Pending,
 }
 
+impl CommandResult {
+    /// Result of a command the FC didn't carry out: `Rejected` when the FC
+    /// refused it outright (see [`CommandAckError::is_refusal`]), `Failed`
+    /// otherwise
+    pub fn from_fc_error(context: &str, error: &anyhow::Error) -> Self {
+        let message = format!("{}: {}", context, error);
+        match error.downcast_ref::<CommandAckError>() {
+            Some(e) if e.is_refusal() => CommandResult::Rejected { message },
+            _ => CommandResult::Failed { message },
+        }
+    }
+}
+
+/// NAK reason sent for pending commands cancelled by an emergency
+const EMERGENCY_SUPERSEDED_REASON: &str = "superseded by emergency";
+
+/// NAK reason sent for pending commands preempted by a higher-priority command
+const PREEMPTED_REASON: &str = "preempted by higher-priority command";
+
+/// NAK reason sent for the mission start of a mission replaced by a new one
+const MISSION_SUPERSEDED_REASON: &str = "superseded by new mission";
+
+/// NAK reason sent for pending commands aborted by a `CMD_CANCEL`
+const CANCELLED_REASON: &str = "cancelled by server";
+
+/// Rejection reason for commands received during maintenance
+const DEVICE_BUSY_REASON: &str = "device busy";
+
+/// How long an executed command's ACK is kept to answer retries of it
+pub const DEDUP_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Commands still honored while the drone is busy with an onboard update
+fn allowed_while_busy(cmd_type: CommandType) -> bool {
+    matches!(
+        cmd_type,
+        CommandType::CmdEmergencyStop | CommandType::CmdStatusRequest | CommandType::CmdGetFaults
+    )
+}
+
+/// Send an ACK at its command's priority (see `connection::OutboundSender`)
+fn with_priority(mut ack: Envelope, priority: u32) -> Envelope {
+    if let Some(header) = &mut ack.header {
+        header.priority = priority;
+    }
+    ack
+}
+
+/// What happens to pending commands when the drone enters emergency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmergencyPolicy {
+    /// Cancel and NAK with `AckFailed` (they were accepted but will not finish)
+    #[default]
+    FailPending,
+    /// Cancel and NAK with `AckRejected`
+    RejectPending,
+}
+
+impl EmergencyPolicy {
+    /// Policy from `RESQTERRA_EMERGENCY_NAK` (`failed`, the default, or
+    /// `rejected`)
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var("RESQTERRA_EMERGENCY_NAK") {
+            Ok(name) => Self::parse(&name),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        match name.trim() {
+            "failed" => Ok(EmergencyPolicy::FailPending),
+            "rejected" => Ok(EmergencyPolicy::RejectPending),
+            _ => Err(anyhow::anyhow!(
+                "Invalid RESQTERRA_EMERGENCY_NAK {:?} (failed or rejected)",
+                name
+            )),
+        }
+    }
+
+    fn nak_status(self) -> AckStatus {
+        match self {
+            EmergencyPolicy::FailPending => AckStatus::AckFailed,
+            EmergencyPolicy::RejectPending => AckStatus::AckRejected,
+        }
+    }
+}
+
+/// Remembers the ACKs of executed commands within a sliding window
+///
+/// Keyed on the sending device and command ID, so a command resent after a
+/// transport switch (or replayed) is answered with its ACK again instead of
+/// running twice.
+#[derive(Debug)]
+pub struct CommandDeduplicator {
+    window_ms: u64,
+    seen: HashMap<(String, u64), (u64, Ack)>,
+}
+
+impl CommandDeduplicator {
+    /// Remember ACKs for `window` after the command's last outcome
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window_ms: window.as_millis() as u64,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// The ACK last given for `command_id` from `device_id`, if still in the window
+    pub fn lookup(&mut self, device_id: &str, command_id: u64, now_ms: u64) -> Option<Ack> {
+        self.prune(now_ms);
+        self.seen
+            .get(&(device_id.to_string(), command_id))
+            .map(|(_, ack)| ack.clone())
+    }
+
+    /// Remember the outcome of a command that ran
+    pub fn record(&mut self, device_id: &str, command_id: u64, ack: Ack, now_ms: u64) {
+        self.prune(now_ms);
+        self.seen.insert((device_id.to_string(), command_id), (now_ms, ack));
+    }
+
+    /// Replace the ACK of a recorded command that finished asynchronously
+    pub fn complete(&mut self, command_id: u64, ack: &Ack, now_ms: u64) {
+        for ((_, id), entry) in self.seen.iter_mut() {
+            if *id == command_id {
+                *entry = (now_ms, ack.clone());
+            }
+        }
+    }
+
+    fn prune(&mut self, now_ms: u64) {
+        let window_ms = self.window_ms;
+        self.seen
+            .retain(|_, (at, _)| now_ms.saturating_sub(*at) <= window_ms);
+    }
+}
+
+/// Command a `CMD_CANCEL` targets (0 = none)
+fn cancel_target(command: &Command) -> u64 {
+    match &command.params {
+        Some(command::Params::Cancel(cancel)) => cancel.target_command_id,
+        _ => 0,
+    }
+}
+
+/// ACK payload of an envelope built by `create_ack`
+fn ack_payload(envelope: &Envelope) -> Option<&Ack> {
+    match &envelope.payload {
+        Some(resqterra_shared::envelope::Payload::Ack(ack)) => Some(ack),
+        _ => None,
+    }
+}
+
 /// Executes commands received from the server
+///
+/// Clones share state, so background work (log transfers) can finish commands.
+#[derive(Clone)]
 pub struct CommandExecutor {
     device_id: String,
     sequence_id: Arc<AtomicU64>,
-    current_state: Arc<RwLock<DroneState>>,
+    /// Drone state shared with the safety monitor
+    state: Arc<DroneStateStore>,
     pending_commands: Arc<RwLock<Vec<PendingCommand>>>,
+    emergency_policy: EmergencyPolicy,
+    telemetry: Option<Arc<TelemetryReader>>,
+    fc: Option<FcLink>,
+    /// Outbound queue for messages sent outside the command/ACK exchange
+    uplink: Option<OutboundSender>,
+    /// Mission-start command of the latest accepted mission
+    active_mission: Arc<RwLock<Option<PendingCommand>>>,
+    /// ACKs of executed commands, for answering retries
+    dedup: Arc<RwLock<CommandDeduplicator>>,
+    /// Missions being streamed in `CMD_MISSION_UPLOAD` chunks
+    mission_uploads: Arc<RwLock<MissionUploads>>,
+    /// Geofence updated by `CMD_CONFIG_UPDATE`
+    geofence: Option<Arc<RwLock<Geofence>>>,
+    /// Video pipeline run by `CMD_VIDEO_START`
+    video: Option<Arc<VideoStreamer>>,
 }
 
 /// A command that is being executed asynchronously
@@ -37,6 +229,8 @@ pub struct PendingCommand {
     pub sequence_id: u64,
     pub cmd_type: CommandType,
     pub started_at: u64,
+    /// Effective priority (see `resqterra_shared::priority`)
+    pub priority: u32,
 }
 
 impl CommandExecutor {
@@ -45,19 +239,74 @@ impl CommandExecutor {
         Self {
             device_id,
             sequence_id,
-            current_state: Arc::new(RwLock::new(DroneState::DroneIdle)),
+            state: Arc::new(DroneStateStore::new()),
             pending_commands: Arc::new(RwLock::new(Vec::new())),
+            emergency_policy: EmergencyPolicy::default(),
+            telemetry: None,
+            fc: None,
+            uplink: None,
+            active_mission: Arc::new(RwLock::new(None)),
+            dedup: Arc::new(RwLock::new(CommandDeduplicator::new(DEDUP_WINDOW))),
+            mission_uploads: Arc::new(RwLock::new(MissionUploads::default())),
+            geofence: None,
+            video: None,
         }
     }
 
+    /// Give handlers access to FC telemetry (faults, position, ...)
+    pub fn with_telemetry(mut self, telemetry: Arc<TelemetryReader>) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Let handlers command the flight controller directly (waypoint skip, ...)
+    pub fn with_flight_controller(
+        mut self,
+        controller: Arc<FlightController>,
+        commands: Arc<MavCommandSender>,
+    ) -> Self {
+        self.fc = Some(FcLink { controller, commands });
+        self
+    }
+
+    /// Let long-running commands stream data and progress to the server
+    pub fn with_uplink(mut self, uplink: OutboundSender) -> Self {
+        self.uplink = Some(uplink);
+        self
+    }
+
+    /// Let config updates set the geofence the safety monitor enforces
+    pub fn with_geofence(mut self, geofence: Arc<RwLock<Geofence>>) -> Self {
+        self.geofence = Some(geofence);
+        self
+    }
+
+    /// Let video commands start and stop the stream pipeline
+    pub fn with_video(mut self, video: Arc<VideoStreamer>) -> Self {
+        self.video = Some(video);
+        self
+    }
+
+    /// Validate commands against the state kept by the safety monitor
+    pub fn with_state_store(mut self, state: Arc<DroneStateStore>) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Set how pending commands are NAKed on emergency
+    pub fn with_emergency_policy(mut self, policy: EmergencyPolicy) -> Self {
+        self.emergency_policy = policy;
+        self
+    }
+
     /// Get the current drone state
     pub async fn get_state(&self) -> DroneState {
-        *self.current_state.read().await
+        self.state.state().await
     }
 
     /// Set the current drone state
     pub async fn set_state(&self, state: DroneState) {
-        *self.current_state.write().await = state;
+        self.state.set_state(state).await;
     }
 
     /// Get pending command count
@@ -66,7 +315,15 @@ impl CommandExecutor {
     }
 
     /// Execute a command and return the appropriate ACK envelope
+    ///
+    /// The ACK carries the command's priority, so an emergency stop's ACK
+    /// isn't queued behind telemetry.
     pub async fn execute(&self, command: &Command, header: &Header) -> Envelope {
+        let ack = self.run(command, header).await;
+        with_priority(ack, command.effective_priority())
+    }
+
+    async fn run(&self, command: &Command, header: &Header) -> Envelope {
         let start_time = now_ms();
         let cmd_type = CommandType::try_from(command.cmd_type).unwrap_or(CommandType::CmdUnknown);
 
@@ -75,6 +332,22 @@ impl CommandExecutor {
             command.command_id, cmd_type
         );
 
+        // A retry or replay of a command that already ran gets the same answer
+        let replayed = self
+            .dedup
+            .write()
+            .await
+            .lookup(&header.device_id, command.command_id, start_time);
+        if let Some(mut ack) = replayed {
+            println!("  Duplicate command, repeating its ACK");
+            ack.ack_sequence_id = header.sequence_id;
+            return Envelope {
+                header: Some(Header::new(&self.device_id, MessageType::MsgAck, self.next_sequence_id())),
+                payload: Some(resqterra_shared::envelope::Payload::Ack(ack)),
+                signature: Vec::new(),
+            };
+        }
+
         // Check if command has expired
         if command.expires_at_ms > 0 && now_ms() > command.expires_at_ms {
             println!("  Command expired");
@@ -87,11 +360,59 @@ impl CommandExecutor {
             );
         }
 
+        // A retry of a command still running must not start it twice
+        if self
+            .pending_commands
+            .read()
+            .await
+            .iter()
+            .any(|c| c.command_id == command.command_id)
+        {
+            println!("  Command already executing");
+            return self.create_ack(
+                header.sequence_id,
+                command.command_id,
+                AckStatus::AckAccepted,
+                "Command already executing",
+                0,
+            );
+        }
+
+        // An onboard update is running; only safety and read-only commands get through
+        let current_state = self.get_state().await;
+        if current_state == DroneState::DroneMaintenance && !allowed_while_busy(cmd_type) {
+            println!("  Command rejected: {}", DEVICE_BUSY_REASON);
+            return self.create_ack(
+                header.sequence_id,
+                command.command_id,
+                AckStatus::AckRejected,
+                DEVICE_BUSY_REASON,
+                0,
+            );
+        }
+
+        // Parameters must match the command registry before any handler runs
+        if let Err(e) = command.validate_params() {
+            println!("  Command rejected: {}", e);
+            return self.create_ack(
+                header.sequence_id,
+                command.command_id,
+                AckStatus::AckRejected,
+                &e.to_string(),
+                0,
+            );
+        }
+
         // Create handler context
         let ctx = HandlerContext {
             device_id: self.device_id.clone(),
-            current_state: self.get_state().await,
+            current_state,
             command_id: command.command_id,
+            telemetry: self.telemetry.clone(),
+            fc: self.fc.clone(),
+            mission_uploads: self.mission_uploads.clone(),
+            geofence: self.geofence.clone(),
+            video: self.video.clone(),
         };
 
         // Dispatch to appropriate handler
@@ -105,6 +426,9 @@ impl CommandExecutor {
             CommandType::CmdMissionAbort => {
                 handlers::handle_mission_abort(&ctx, command).await
             }
+            CommandType::CmdMissionUpload => {
+                handlers::handle_mission_upload(&ctx, command).await
+            }
             CommandType::CmdRth => {
                 handlers::handle_rth(&ctx, command).await
             }
@@ -114,6 +438,39 @@ impl CommandExecutor {
             CommandType::CmdEmergencyStop => {
                 handlers::handle_emergency_stop(&ctx, command).await
             }
+            CommandType::CmdGetFaults => {
+                handlers::handle_get_faults(&ctx, command).await
+            }
+            CommandType::CmdClearFaults => {
+                handlers::handle_clear_faults(&ctx, command).await
+            }
+            CommandType::CmdSkipWaypoint => {
+                handlers::handle_skip_waypoint(&ctx, command).await
+            }
+            CommandType::CmdDownloadLog if self.uplink.is_none() => {
+                CommandResult::Failed {
+                    message: "No uplink for log transfer".into(),
+                }
+            }
+            CommandType::CmdDownloadLog => {
+                handlers::handle_download_log(&ctx, command).await
+            }
+            CommandType::CmdCapabilities => {
+                handlers::handle_capabilities(&ctx, command).await
+            }
+            CommandType::CmdCancel => self.check_cancel(command).await,
+            CommandType::CmdParamGet => {
+                handlers::handle_param_get(&ctx, command).await
+            }
+            CommandType::CmdParamSet => {
+                handlers::handle_param_set(&ctx, command).await
+            }
+            CommandType::CmdVideoStart => {
+                handlers::handle_video_start(&ctx, command).await
+            }
+            CommandType::CmdVideoStop => {
+                handlers::handle_video_stop(&ctx, command).await
+            }
             CommandType::CmdUnknown => {
                 CommandResult::Rejected {
                     message: "Unknown command type".into(),
@@ -124,7 +481,7 @@ impl CommandExecutor {
         let processing_time = now_ms() - start_time;
 
         // Convert result to ACK
-        match result {
+        let ack = match result {
             CommandResult::Completed { message } => {
                 println!("  Command completed: {}", message);
                 self.create_ack(
@@ -155,6 +512,76 @@ impl CommandExecutor {
                     processing_time,
                 )
             }
+            CommandResult::Faults { message, faults } => {
+                println!("  Command completed: {}", message);
+                let mut envelope = self.create_ack(
+                    header.sequence_id,
+                    command.command_id,
+                    AckStatus::AckCompleted,
+                    &message,
+                    processing_time,
+                );
+                if let Some(resqterra_shared::envelope::Payload::Ack(ack)) = &mut envelope.payload {
+                    ack.faults = faults;
+                }
+                envelope
+            }
+            CommandResult::Waypoint { message, current_waypoint } => {
+                println!("  Command completed: {}", message);
+                let mut envelope = self.create_ack(
+                    header.sequence_id,
+                    command.command_id,
+                    AckStatus::AckCompleted,
+                    &message,
+                    processing_time,
+                );
+                if let Some(resqterra_shared::envelope::Payload::Ack(ack)) = &mut envelope.payload {
+                    ack.current_waypoint = Some(current_waypoint);
+                }
+                envelope
+            }
+            CommandResult::Capabilities { message, capabilities } => {
+                println!("  Command completed: {}", message);
+                let mut envelope = self.create_ack(
+                    header.sequence_id,
+                    command.command_id,
+                    AckStatus::AckCompleted,
+                    &message,
+                    processing_time,
+                );
+                if let Some(resqterra_shared::envelope::Payload::Ack(ack)) = &mut envelope.payload {
+                    ack.capabilities = capabilities;
+                }
+                envelope
+            }
+            CommandResult::Params { message, params } => {
+                println!("  Command completed: {}", message);
+                let mut envelope = self.create_ack(
+                    header.sequence_id,
+                    command.command_id,
+                    AckStatus::AckCompleted,
+                    &message,
+                    processing_time,
+                );
+                if let Some(resqterra_shared::envelope::Payload::Ack(ack)) = &mut envelope.payload {
+                    ack.fc_params = params;
+                }
+                envelope
+            }
+            CommandResult::Video { message, stream } => {
+                println!("  Command completed: {}", message);
+                let mut envelope = self.create_ack(
+                    header.sequence_id,
+                    command.command_id,
+                    AckStatus::AckCompleted,
+                    &message,
+                    processing_time,
+                );
+                if let Some(resqterra_shared::envelope::Payload::Ack(ack)) = &mut envelope.payload {
+                    ack.video_stream = Some(stream);
+                }
+                envelope
+            }
             CommandResult::Pending => {
                 // Add to pending commands
                 let pending = PendingCommand {
@@ -162,9 +589,27 @@ impl CommandExecutor {
                     sequence_id: header.sequence_id,
                     cmd_type,
                     started_at: start_time,
+                    priority: command.effective_priority(),
                 };
                 self.pending_commands.write().await.push(pending);
 
+                if let (CommandType::CmdDownloadLog, Some(fc), Some(uplink)) =
+                    (cmd_type, &self.fc, &self.uplink)
+                {
+                    let request = match &command.params {
+                        Some(command::Params::DownloadLog(request)) => *request,
+                        _ => Default::default(),
+                    };
+                    tokio::spawn(log_transfer::run(
+                        self.clone(),
+                        fc.clone(),
+                        uplink.clone(),
+                        request,
+                        command.command_id,
+                        header.sequence_id,
+                    ));
+                }
+
                 println!("  Command accepted, executing asynchronously");
                 self.create_ack(
                     header.sequence_id,
@@ -174,7 +619,80 @@ impl CommandExecutor {
                     processing_time,
                 )
             }
+        };
+
+        if let Some(payload) = ack_payload(&ack) {
+            self.dedup.write().await.record(
+                &header.device_id,
+                command.command_id,
+                payload.clone(),
+                now_ms(),
+            );
+        }
+        ack
+    }
+
+    /// Device ID used in outgoing headers
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// Next outgoing sequence ID
+    pub fn next_sequence_id(&self) -> u64 {
+        self.sequence_id.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Whether `command_id` is still executing (not finished or cancelled)
+    pub async fn is_pending(&self, command_id: u64) -> bool {
+        self.pending_commands
+            .read()
+            .await
+            .iter()
+            .any(|c| c.command_id == command_id)
+    }
+
+    /// ACK reporting how far a pending command's transfer has got
+    pub fn progress_ack(
+        &self,
+        ack_sequence_id: u64,
+        command_id: u64,
+        progress: TransferProgress,
+    ) -> Envelope {
+        let mut envelope = self.create_ack(
+            ack_sequence_id,
+            command_id,
+            AckStatus::AckAccepted,
+            &format!("Chunk {}/{}", progress.chunks_sent, progress.total_chunks),
+            0,
+        );
+        if let Some(resqterra_shared::envelope::Payload::Ack(ack)) = &mut envelope.payload {
+            ack.progress = Some(progress);
+        }
+        envelope
+    }
+
+    /// Finish a pending command and build its final ACK
+    ///
+    /// `None` if it is no longer pending (already NAKed as cancelled).
+    pub async fn finish_pending(
+        &self,
+        command_id: u64,
+        status: AckStatus,
+        message: &str,
+    ) -> Option<Envelope> {
+        let pending = self.complete_pending(command_id).await?;
+        println!("  Command {} finished: {}", command_id, message);
+        let ack = self.create_ack(
+            pending.sequence_id,
+            command_id,
+            status,
+            message,
+            now_ms().saturating_sub(pending.started_at),
+        );
+        if let Some(payload) = ack_payload(&ack) {
+            self.dedup.write().await.complete(command_id, payload, now_ms());
         }
+        Some(with_priority(ack, pending.priority))
     }
 
     /// Create an ACK envelope
@@ -186,7 +704,7 @@ impl CommandExecutor {
         message: &str,
         processing_time_ms: u64,
     ) -> Envelope {
-        let seq = self.sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let seq = self.next_sequence_id();
 
         Envelope {
             header: Some(Header::new(&self.device_id, MessageType::MsgAck, seq)),
@@ -196,10 +714,153 @@ impl CommandExecutor {
                 status: status.into(),
                 message: message.into(),
                 processing_time_ms,
+                faults: Vec::new(),
+                current_waypoint: None,
+                progress: None,
+                capabilities: Vec::new(),
+                video_stream: None,
+                fc_params: Vec::new(),
             })),
+            signature: Vec::new(),
         }
     }
 
+    /// Cancel all pending non-emergency commands after an emergency transition
+    ///
+    /// Returns one NAK envelope per cancelled command so the server learns
+    /// they will not run. Pending emergency stops are kept.
+    pub async fn cancel_pending_for_emergency(&self) -> Vec<Envelope> {
+        let cancelled: Vec<PendingCommand> = {
+            let mut pending = self.pending_commands.write().await;
+            let (cancelled, kept) = pending
+                .drain(..)
+                .partition(|c| c.cmd_type != CommandType::CmdEmergencyStop);
+            *pending = kept;
+            cancelled
+        };
+
+        self.nak_cancelled(
+            &cancelled,
+            self.emergency_policy.nak_status(),
+            EMERGENCY_SUPERSEDED_REASON,
+        )
+        .await
+    }
+
+    /// Cancel pending commands of lower priority than an accepted `command`
+    ///
+    /// Only `priority::HIGH` commands preempt (emergency stops go through
+    /// `cancel_pending_for_emergency`). Returns one NAK envelope per
+    /// cancelled command.
+    pub async fn preempt_pending(&self, command: &Command) -> Vec<Envelope> {
+        let priority = command.effective_priority();
+        if priority != priority::HIGH {
+            return Vec::new();
+        }
+
+        let preempted: Vec<PendingCommand> = {
+            let mut pending = self.pending_commands.write().await;
+            let (preempted, kept) = pending.drain(..).partition(|c| {
+                c.priority < priority && c.command_id != command.command_id
+            });
+            *pending = kept;
+            preempted
+        };
+
+        self.nak_cancelled(&preempted, AckStatus::AckFailed, PREEMPTED_REASON)
+            .await
+    }
+
+    /// Whether a `CMD_CANCEL` can abort its target
+    ///
+    /// The target is aborted by `cancel_pending` once the cancel is accepted.
+    async fn check_cancel(&self, command: &Command) -> CommandResult {
+        let target = cancel_target(command);
+        let pending = self.pending_commands.read().await;
+        match pending.iter().find(|c| c.command_id == target) {
+            None => CommandResult::Rejected {
+                message: format!("Command {} is not pending", target),
+            },
+            Some(c) if c.cmd_type == CommandType::CmdEmergencyStop => CommandResult::Rejected {
+                message: "Emergency stops can't be cancelled".into(),
+            },
+            Some(_) => CommandResult::Completed {
+                message: format!("Cancelling command {}", target),
+            },
+        }
+    }
+
+    /// Abort the pending command an accepted `CMD_CANCEL` targets
+    ///
+    /// Returns the `AckCancelled` NAK for the aborted command. Background work
+    /// (log transfers) notices through `is_pending` and stops.
+    pub async fn cancel_pending(&self, command: &Command) -> Option<Envelope> {
+        if command.cmd_type != CommandType::CmdCancel as i32 {
+            return None;
+        }
+        let cancelled = self.complete_pending(cancel_target(command)).await?;
+        self.nak_cancelled(&[cancelled], AckStatus::AckCancelled, CANCELLED_REASON)
+            .await
+            .pop()
+    }
+
+    /// Record an accepted mission start, superseding the mission in progress
+    ///
+    /// Returns a NAK for the mission-start command of the superseded mission
+    /// when the drone was flying one (the handler only accepts that with
+    /// `MissionStart.replace`).
+    pub async fn supersede_mission(&self, command: &Command, header: &Header) -> Option<Envelope> {
+        if command.cmd_type != CommandType::CmdMissionStart as i32 {
+            return None;
+        }
+
+        let in_mission = self.get_state().await == DroneState::DroneInMission;
+        let previous = self.active_mission.write().await.replace(PendingCommand {
+            command_id: command.command_id,
+            sequence_id: header.sequence_id,
+            cmd_type: CommandType::CmdMissionStart,
+            started_at: now_ms(),
+            priority: command.effective_priority(),
+        });
+
+        let superseded = previous.filter(|p| in_mission && p.command_id != command.command_id)?;
+        self.nak_cancelled(&[superseded], AckStatus::AckFailed, MISSION_SUPERSEDED_REASON)
+            .await
+            .pop()
+    }
+
+    /// Build NAKs for cancelled pending commands
+    ///
+    /// Retries of a cancelled command get its NAK rather than running it.
+    async fn nak_cancelled(
+        &self,
+        cancelled: &[PendingCommand],
+        status: AckStatus,
+        reason: &str,
+    ) -> Vec<Envelope> {
+        let mut dedup = self.dedup.write().await;
+        cancelled
+            .iter()
+            .map(|c| {
+                println!(
+                    "  Cancelling pending command id={} type={:?}: {}",
+                    c.command_id, c.cmd_type, reason
+                );
+                let nak = self.create_ack(
+                    c.sequence_id,
+                    c.command_id,
+                    status,
+                    reason,
+                    now_ms().saturating_sub(c.started_at),
+                );
+                if let Some(payload) = ack_payload(&nak) {
+                    dedup.complete(c.command_id, payload, now_ms());
+                }
+                with_priority(nak, c.priority)
+            })
+            .collect()
+    }
+
     /// Mark a pending command as completed
     pub async fn complete_pending(&self, command_id: u64) -> Option<PendingCommand> {
         let mut pending = self.pending_commands.write().await;
@@ -210,3 +871,336 @@ impl CommandExecutor {
         }
     }
 }
+
+/// Heartbeats report the executor's view: state, commands in flight, and the
+/// FC's battery and GPS fix when telemetry is attached
+#[async_trait]
+impl HeartbeatProvider for CommandExecutor {
+    async fn heartbeat(&self, uptime_ms: u64) -> Heartbeat {
+        let state = self.get_state().await;
+        let healthy = state != DroneState::DroneEmergency;
+        let heartbeat = Heartbeat::new(uptime_ms, state, self.pending_count().await, healthy);
+
+        let Some(telemetry) = &self.telemetry else {
+            return heartbeat;
+        };
+        let heartbeat = heartbeat.with_gps_fix(telemetry.has_gps_lock().await);
+        match telemetry.get_battery().await {
+            Some(battery) => heartbeat.with_battery(battery),
+            None => heartbeat,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(command_id: u64, cmd_type: CommandType) -> PendingCommand {
+        PendingCommand {
+            command_id,
+            sequence_id: command_id + 100,
+            cmd_type,
+            started_at: now_ms(),
+            priority: priority::NORMAL,
+        }
+    }
+
+    fn ack_of(envelope: &Envelope) -> &Ack {
+        match &envelope.payload {
+            Some(resqterra_shared::envelope::Payload::Ack(ack)) => ack,
+            other => panic!("expected ack, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_deduplicator_window_slides() {
+        let mut dedup = CommandDeduplicator::new(Duration::from_secs(10));
+        let accepted = Ack {
+            command_id: 7,
+            status: AckStatus::AckAccepted.into(),
+            ..Default::default()
+        };
+        dedup.record("server", 7, accepted, 1_000);
+        assert!(dedup.lookup("server", 7, 2_000).is_some());
+        assert!(dedup.lookup("other-server", 7, 2_000).is_none());
+
+        // Finishing refreshes the entry and replaces the ACK
+        let completed = Ack {
+            command_id: 7,
+            status: AckStatus::AckCompleted.into(),
+            ..Default::default()
+        };
+        dedup.complete(7, &completed, 9_000);
+        let ack = dedup.lookup("server", 7, 15_000).unwrap();
+        assert_eq!(ack.status, AckStatus::AckCompleted as i32);
+
+        assert!(dedup.lookup("server", 7, 19_001).is_none());
+        assert!(dedup.seen.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replayed_command_acknowledged_not_rerun() {
+        let executor = CommandExecutor::new("edge-001".into(), Arc::new(AtomicU64::new(0)));
+        let stop = Command {
+            command_id: 9,
+            cmd_type: CommandType::CmdEmergencyStop.into(),
+            ..Default::default()
+        };
+        let first = executor
+            .execute(&stop, &Header::new("server", MessageType::MsgCommand, 1))
+            .await;
+        assert_eq!(ack_of(&first).status, AckStatus::AckCompleted as i32);
+
+        // Resent over the other transport after a switch
+        let retry = executor
+            .execute(&stop, &Header::new("server", MessageType::MsgCommand, 2))
+            .await;
+        assert_eq!(ack_of(&retry).status, AckStatus::AckCompleted as i32);
+        assert_eq!(ack_of(&retry).message, ack_of(&first).message);
+        assert_eq!(ack_of(&retry).ack_sequence_id, 2);
+        assert_ne!(retry.header.as_ref().unwrap().sequence_id, first.header.as_ref().unwrap().sequence_id);
+        assert_eq!(retry.priority(), priority::EMERGENCY);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_aborts_pending_command() {
+        let executor = CommandExecutor::new("edge-001".into(), Arc::new(AtomicU64::new(0)));
+        executor
+            .pending_commands
+            .write()
+            .await
+            .push(pending(1, CommandType::CmdDownloadLog));
+        let header = Header::new("server", MessageType::MsgCommand, 5);
+        let cancel = |command_id, target_command_id| Command {
+            command_id,
+            cmd_type: CommandType::CmdCancel.into(),
+            params: Some(command::Params::Cancel(resqterra_shared::CancelCommand {
+                target_command_id,
+            })),
+            ..Default::default()
+        };
+
+        let ack = executor.execute(&cancel(2, 9), &header).await;
+        assert_eq!(ack_of(&ack).status, AckStatus::AckRejected as i32);
+
+        let ack = executor.execute(&cancel(3, 1), &header).await;
+        assert_eq!(ack_of(&ack).status, AckStatus::AckCompleted as i32);
+        let nak = executor.cancel_pending(&cancel(3, 1)).await.unwrap();
+        assert_eq!(ack_of(&nak).command_id, 1);
+        assert_eq!(ack_of(&nak).ack_sequence_id, 101);
+        assert_eq!(ack_of(&nak).status, AckStatus::AckCancelled as i32);
+        assert!(!executor.is_pending(1).await);
+    }
+
+    #[tokio::test]
+    async fn test_pending_commands_naked_on_emergency() {
+        let executor = CommandExecutor::new("edge-001".into(), Arc::new(AtomicU64::new(0)));
+        {
+            let mut pending_commands = executor.pending_commands.write().await;
+            pending_commands.push(pending(1, CommandType::CmdMissionStart));
+            pending_commands.push(pending(2, CommandType::CmdEmergencyStop));
+            pending_commands.push(pending(3, CommandType::CmdRth));
+        }
+
+        let naks = executor.cancel_pending_for_emergency().await;
+
+        assert_eq!(naks.len(), 2);
+        for (nak, command_id) in naks.iter().zip([1, 3]) {
+            let ack = ack_of(nak);
+            assert_eq!(ack.command_id, command_id);
+            assert_eq!(ack.ack_sequence_id, command_id + 100);
+            assert_eq!(ack.status, AckStatus::AckFailed as i32);
+            assert_eq!(ack.message, EMERGENCY_SUPERSEDED_REASON);
+        }
+
+        // The emergency stop itself keeps running
+        assert_eq!(executor.pending_count().await, 1);
+        assert!(executor.complete_pending(2).await.is_some());
+    }
+
+    #[test]
+    fn test_emergency_policy_names() {
+        assert_eq!(EmergencyPolicy::parse("failed").unwrap(), EmergencyPolicy::FailPending);
+        assert_eq!(EmergencyPolicy::parse("rejected").unwrap(), EmergencyPolicy::RejectPending);
+        assert!(EmergencyPolicy::parse("ignore").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_emergency_policy_reject() {
+        let executor = CommandExecutor::new("edge-001".into(), Arc::new(AtomicU64::new(0)))
+            .with_emergency_policy(EmergencyPolicy::RejectPending);
+        executor
+            .pending_commands
+            .write()
+            .await
+            .push(pending(7, CommandType::CmdMissionStart));
+
+        let naks = executor.cancel_pending_for_emergency().await;
+        assert_eq!(ack_of(&naks[0]).status, AckStatus::AckRejected as i32);
+        assert!(executor.cancel_pending_for_emergency().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_reports_executor_and_fc_status() {
+        use mavlink::ardupilotmega::{GpsFixType, MavMessage, GPS_RAW_INT_DATA, SYS_STATUS_DATA};
+
+        let telemetry = Arc::new(TelemetryReader::new());
+        let executor = CommandExecutor::new("edge-001".into(), Arc::new(AtomicU64::new(0)))
+            .with_telemetry(telemetry.clone());
+        executor
+            .pending_commands
+            .write()
+            .await
+            .push(pending(1, CommandType::CmdDownloadLog));
+
+        let hb = executor.heartbeat(5_000).await;
+        assert_eq!(hb.uptime_ms, 5_000);
+        assert_eq!(hb.pending_commands, 1);
+        assert_eq!(hb.battery_percent(), None);
+        assert!(!hb.gps_fix);
+
+        telemetry
+            .process_message(&MavMessage::SYS_STATUS(SYS_STATUS_DATA {
+                battery_remaining: 64,
+                ..Default::default()
+            }))
+            .await;
+        telemetry
+            .process_message(&MavMessage::GPS_RAW_INT(GPS_RAW_INT_DATA {
+                fix_type: GpsFixType::GPS_FIX_TYPE_3D_FIX,
+                ..Default::default()
+            }))
+            .await;
+        executor.set_state(DroneState::DroneEmergency).await;
+
+        let hb = executor.heartbeat(6_000).await;
+        assert_eq!(hb.state(), DroneState::DroneEmergency);
+        assert!(!hb.healthy);
+        assert_eq!(hb.battery_percent(), Some(64));
+        assert!(hb.gps_fix);
+    }
+
+    #[tokio::test]
+    async fn test_high_priority_command_preempts_pending() {
+        let executor = CommandExecutor::new("edge-001".into(), Arc::new(AtomicU64::new(0)));
+        {
+            let mut pending_commands = executor.pending_commands.write().await;
+            pending_commands.push(PendingCommand {
+                priority: priority::LOW,
+                ..pending(1, CommandType::CmdMissionStart)
+            });
+            pending_commands.push(PendingCommand {
+                priority: priority::HIGH,
+                ..pending(2, CommandType::CmdRth)
+            });
+        }
+
+        let normal = Command {
+            command_id: 3,
+            cmd_type: CommandType::CmdMissionStart.into(),
+            priority: priority::NORMAL,
+            ..Default::default()
+        };
+        assert!(executor.preempt_pending(&normal).await.is_empty());
+
+        let manual = Command {
+            command_id: 4,
+            priority: priority::HIGH,
+            ..normal
+        };
+        let naks = executor.preempt_pending(&manual).await;
+        assert_eq!(naks.len(), 1);
+        assert_eq!(ack_of(&naks[0]).command_id, 1);
+        assert_eq!(ack_of(&naks[0]).message, PREEMPTED_REASON);
+        assert_eq!(executor.pending_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_commands_rejected_while_busy() {
+        let executor = CommandExecutor::new("edge-001".into(), Arc::new(AtomicU64::new(0)));
+        executor.set_state(DroneState::DroneMaintenance).await;
+        let header = Header::new("server", MessageType::MsgCommand, 1);
+
+        for (id, cmd_type) in [(1, CommandType::CmdMissionStart), (2, CommandType::CmdRth), (3, CommandType::CmdConfigUpdate)] {
+            let command = Command {
+                command_id: id,
+                cmd_type: cmd_type.into(),
+                ..Default::default()
+            };
+            let ack = executor.execute(&command, &header).await;
+            assert_eq!(ack_of(&ack).status, AckStatus::AckRejected as i32);
+            assert_eq!(ack_of(&ack).message, DEVICE_BUSY_REASON);
+        }
+
+        // Emergency stop is always honored
+        let stop = Command {
+            command_id: 4,
+            cmd_type: CommandType::CmdEmergencyStop.into(),
+            ..Default::default()
+        };
+        let ack = executor.execute(&stop, &header).await;
+        assert_eq!(ack_of(&ack).status, AckStatus::AckCompleted as i32);
+        assert_eq!(ack.priority(), priority::EMERGENCY);
+
+        // Leaving maintenance lifts the restriction
+        executor.set_state(DroneState::DroneIdle).await;
+        let rth = Command {
+            command_id: 5,
+            cmd_type: CommandType::CmdRth.into(),
+            ..Default::default()
+        };
+        let ack = executor.execute(&rth, &header).await;
+        assert_ne!(ack_of(&ack).message, DEVICE_BUSY_REASON);
+    }
+
+    fn mission_start(command_id: u64, mission_id: &str, replace: bool) -> Command {
+        Command {
+            command_id,
+            cmd_type: CommandType::CmdMissionStart.into(),
+            params: Some(command::Params::MissionStart(resqterra_shared::MissionStart {
+                mission_id: mission_id.into(),
+                replace,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mission_start_in_mission_rejected_without_replace() {
+        let executor = CommandExecutor::new("edge-001".into(), Arc::new(AtomicU64::new(0)));
+        let header = Header::new("server", MessageType::MsgCommand, 1);
+        let first = mission_start(1, "survey-1", false);
+        executor.execute(&first, &header).await;
+        assert!(executor.supersede_mission(&first, &header).await.is_none());
+        executor.set_state(DroneState::DroneInMission).await;
+
+        let ack = executor.execute(&mission_start(2, "survey-2", false), &header).await;
+        assert_eq!(ack_of(&ack).status, AckStatus::AckRejected as i32);
+        assert_eq!(ack_of(&ack).message, "Already in mission");
+    }
+
+    #[tokio::test]
+    async fn test_mission_start_with_replace_supersedes_current() {
+        let executor = CommandExecutor::new("edge-001".into(), Arc::new(AtomicU64::new(0)));
+        let first = mission_start(1, "survey-1", false);
+        let first_header = Header::new("server", MessageType::MsgCommand, 10);
+        let ack = executor.execute(&first, &first_header).await;
+        assert_eq!(ack_of(&ack).status, AckStatus::AckCompleted as i32);
+        assert!(executor.supersede_mission(&first, &first_header).await.is_none());
+        executor.set_state(DroneState::DroneInMission).await;
+
+        let second = mission_start(2, "survey-2", true);
+        let header = Header::new("server", MessageType::MsgCommand, 11);
+        let ack = executor.execute(&second, &header).await;
+        assert_eq!(ack_of(&ack).status, AckStatus::AckCompleted as i32);
+
+        // The mission start of the superseded mission is NAKed
+        let nak = executor.supersede_mission(&second, &header).await.expect("NAK");
+        assert_eq!(ack_of(&nak).command_id, 1);
+        assert_eq!(ack_of(&nak).ack_sequence_id, 10);
+        assert_eq!(ack_of(&nak).status, AckStatus::AckFailed as i32);
+        assert_eq!(ack_of(&nak).message, MISSION_SUPERSEDED_REASON);
+    }
+}
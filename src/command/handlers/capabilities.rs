@@ -0,0 +1,23 @@
+//! Command capabilities handler
+
+use super::HandlerContext;
+use crate::command::CommandResult;
+use resqterra_shared::{command_spec, Command};
+
+/// Handle CAPABILITIES command
+///
+/// Lists every command this build supports with the parameters it takes, from
+/// the same registry incoming commands are validated against.
+pub async fn handle_capabilities(ctx: &HandlerContext, _command: &Command) -> CommandResult {
+    let capabilities = command_spec::capabilities();
+    println!(
+        "  [CAPABILITIES] {} supports {} command(s)",
+        ctx.device_id,
+        capabilities.len()
+    );
+
+    CommandResult::Capabilities {
+        message: format!("{} command(s) supported", capabilities.len()),
+        capabilities,
+    }
+}
@@ -2,6 +2,7 @@
 
 use super::HandlerContext;
 use crate::command::CommandResult;
+use crate::safety::Geofence;
 use resqterra_shared::{Command, command};
 
 /// Handle CONFIG_UPDATE command
@@ -18,9 +19,28 @@ pub async fn handle_config_update(ctx: &HandlerContext, command: &Command) -> Co
 
     println!("  [CONFIG_UPDATE] Received {} config entries", config.config.len());
 
+    // Geofence limits are validated and applied together, or not at all
+    if config.config.keys().any(|key| Geofence::is_config_key(key)) {
+        let Some(ref geofence) = ctx.geofence else {
+            return CommandResult::Rejected {
+                message: "Geofence not available".into(),
+            };
+        };
+        let mut geofence = geofence.write().await;
+        if let Err(e) = geofence.apply_config(&config.config) {
+            return CommandResult::Rejected {
+                message: e.to_string(),
+            };
+        }
+        println!(
+            "  [CONFIG_UPDATE] Geofence {}",
+            if geofence.is_active() { "active" } else { "off" }
+        );
+    }
+
     for (key, value) in &config.config {
         println!("    {} = {}", key, value);
-        // TODO: Actually apply configuration changes
+        // TODO: Actually apply configuration changes other than the geofence
     }
 
     CommandResult::Completed {
@@ -25,6 +25,11 @@ pub async fn handle_emergency_stop(ctx: &HandlerContext, _command: &Command) ->
     // Warning: This will cause the drone to fall!
     // Only use in actual emergency situations
 
+    // Alert if the FC heartbeat never shows the motors disarmed
+    if let Some(telemetry) = &ctx.telemetry {
+        telemetry.expect_armed(false).await;
+    }
+
     CommandResult::Completed {
         message: "EMERGENCY STOP EXECUTED - Motors killed".into(),
     }
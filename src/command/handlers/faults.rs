@@ -0,0 +1,132 @@
+//! Fault query/clear command handlers
+
+use super::HandlerContext;
+use crate::command::CommandResult;
+use resqterra_shared::{Command, command};
+
+/// Handle GET_FAULTS command
+pub async fn handle_get_faults(ctx: &HandlerContext, _command: &Command) -> CommandResult {
+    let Some(telemetry) = &ctx.telemetry else {
+        return CommandResult::Failed {
+            message: "Flight controller telemetry unavailable".into(),
+        };
+    };
+
+    let faults = telemetry.get_faults().await;
+    println!("  [GET_FAULTS] {} fault(s)", faults.len());
+
+    CommandResult::Faults {
+        message: format!("{} fault(s)", faults.len()),
+        faults,
+    }
+}
+
+/// Handle CLEAR_FAULTS command
+///
+/// Only clears faults the FC no longer reports; active faults stay listed so
+/// an acknowledgement can never hide a live problem.
+pub async fn handle_clear_faults(ctx: &HandlerContext, command: &Command) -> CommandResult {
+    let Some(telemetry) = &ctx.telemetry else {
+        return CommandResult::Failed {
+            message: "Flight controller telemetry unavailable".into(),
+        };
+    };
+
+    // No parameters means "clear everything that is resolved"
+    let requested = match &command.params {
+        Some(command::Params::ClearFaults(c)) => c.faults.clone(),
+        _ => Vec::new(),
+    };
+
+    let kept_active = telemetry.clear_resolved_faults(&requested).await;
+    let faults = telemetry.get_faults().await;
+
+    let message = if kept_active.is_empty() {
+        format!("Cleared resolved faults, {} remaining", faults.len())
+    } else {
+        println!("  [CLEAR_FAULTS] Kept active: {}", kept_active.join(", "));
+        format!("Kept {} active fault(s): {}", kept_active.len(), kept_active.join(", "))
+    };
+
+    CommandResult::Faults { message, faults }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::TelemetryReader;
+    use resqterra_shared::{ClearFaults, CommandType, DroneState};
+    use std::sync::Arc;
+
+    fn context(telemetry: &Arc<TelemetryReader>) -> HandlerContext {
+        HandlerContext {
+            device_id: "edge-001".into(),
+            current_state: DroneState::DroneIdle,
+            command_id: 1,
+            telemetry: Some(telemetry.clone()),
+            fc: None,
+            mission_uploads: Default::default(),
+            geofence: None,
+            video: None,
+        }
+    }
+
+    fn clear_command(faults: Vec<String>) -> Command {
+        Command {
+            command_id: 1,
+            cmd_type: CommandType::CmdClearFaults.into(),
+            params: Some(command::Params::ClearFaults(ClearFaults { faults })),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_faults_returns_typed_list() {
+        let telemetry = Arc::new(TelemetryReader::new());
+        telemetry.add_fault("Failsafe param FS_GCS_ENABLE could not be verified").await;
+        telemetry.add_fault("Failsafe param FS_GCS_ENABLE could not be verified").await;
+
+        match handle_get_faults(&context(&telemetry), &Command::default()).await {
+            CommandResult::Faults { faults, .. } => {
+                assert_eq!(faults.len(), 1);
+                assert_eq!(faults[0].count, 2);
+                assert_eq!(faults[0].severity, 3);
+                assert!(faults[0].active);
+            }
+            other => panic!("expected faults, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clear_faults_keeps_active() {
+        let telemetry = Arc::new(TelemetryReader::new());
+        telemetry.add_fault("Compass error").await;
+
+        // Just reported, so it is still active and must survive the clear
+        match handle_clear_faults(&context(&telemetry), &clear_command(vec![])).await {
+            CommandResult::Faults { message, faults } => {
+                assert!(message.contains("Compass error"));
+                assert_eq!(faults.len(), 1);
+            }
+            other => panic!("expected faults, got {:?}", other),
+        }
+
+        // Clearing an unknown fault leaves the list untouched
+        let result =
+            handle_clear_faults(&context(&telemetry), &clear_command(vec!["GPS glitch".into()]))
+                .await;
+        assert!(matches!(result, CommandResult::Faults { ref faults, .. } if faults.len() == 1));
+    }
+
+    #[tokio::test]
+    async fn test_fault_commands_without_fc() {
+        let ctx = HandlerContext {
+            telemetry: None,
+            ..context(&Arc::new(TelemetryReader::new()))
+        };
+        assert!(matches!(
+            handle_get_faults(&ctx, &Command::default()).await,
+            CommandResult::Failed { .. }
+        ));
+    }
+}
@@ -0,0 +1,31 @@
+//! FC onboard log download command handler
+
+use super::HandlerContext;
+use crate::command::CommandResult;
+use resqterra_shared::{Command, DroneState};
+
+/// Handle DOWNLOAD_LOG command
+///
+/// Only validates the request; the executor streams the log in the background
+/// and sends the final ACK once the last chunk is out.
+pub async fn handle_download_log(ctx: &HandlerContext, _command: &Command) -> CommandResult {
+    // The FC only serves logs while disarmed, and the transfer would compete
+    // with flight traffic for the link
+    if ctx.current_state != DroneState::DroneIdle {
+        return CommandResult::Rejected {
+            message: format!(
+                "Logs can only be downloaded on the ground (state: {:?})",
+                ctx.current_state
+            ),
+        };
+    }
+
+    if ctx.fc.is_none() {
+        return CommandResult::Failed {
+            message: "Flight controller unavailable".into(),
+        };
+    }
+
+    println!("  [DOWNLOAD_LOG] Starting log transfer for {}", ctx.device_id);
+    CommandResult::Pending
+}
@@ -1,16 +1,26 @@
-//! Mission command handlers (start, abort)
+//! Mission command handlers (start, abort, chunked upload)
 
 use super::HandlerContext;
+use crate::command::mission_upload::ChunkOutcome;
 use crate::command::CommandResult;
-use resqterra_shared::{Command, DroneState, command};
+use resqterra_shared::{Command, DroneState, ScanPattern, command};
 
 /// Handle MISSION_START command
 pub async fn handle_mission_start(ctx: &HandlerContext, command: &Command) -> CommandResult {
-    // Validate state - can only start mission when ARMED or IDLE
+    let replace = matches!(
+        &command.params,
+        Some(command::Params::MissionStart(m)) if m.replace
+    );
+
+    // Validate state - can only start mission when ARMED or IDLE, or
+    // IN_MISSION when the new mission supersedes the current one
     match ctx.current_state {
         DroneState::DroneArmed | DroneState::DroneIdle => {
             // Valid state to start mission
         }
+        DroneState::DroneInMission if replace => {
+            // The current mission is aborted below
+        }
         DroneState::DroneInMission => {
             return CommandResult::Rejected {
                 message: "Already in mission".into(),
@@ -43,9 +53,65 @@ pub async fn handle_mission_start(ctx: &HandlerContext, command: &Command) -> Co
         }
     };
 
+    // Waypoints streamed beforehand must all have arrived intact
+    let uploaded = if mission.uploaded_waypoints > 0 {
+        let waypoints = ctx.mission_uploads.write().await.take(&mission.mission_id);
+        match waypoints {
+            Some(w) if w.len() == mission.uploaded_waypoints as usize => Some(w),
+            _ => {
+                return CommandResult::Rejected {
+                    message: format!("Mission {} waypoints not uploaded", mission.mission_id),
+                };
+            }
+        }
+    } else {
+        None
+    };
+    if uploaded.is_none() && mission.scan_pattern == ScanPattern::PatternCustom as i32 {
+        return CommandResult::Rejected {
+            message: "Custom pattern needs uploaded waypoints".into(),
+        };
+    }
+
+    // The mission's geofence replaces the current one; a bad one fails the start
+    if let Some(ref fence) = mission.geofence {
+        let Some(ref geofence) = ctx.geofence else {
+            return CommandResult::Rejected {
+                message: "Geofence not available".into(),
+            };
+        };
+        if let Err(e) = geofence.write().await.apply_mission(fence) {
+            return CommandResult::Rejected {
+                message: e.to_string(),
+            };
+        }
+    }
+
+    // The FC must stop flying the current mission before it is given the new one
+    if ctx.current_state == DroneState::DroneInMission {
+        println!("  [MISSION_START] Superseding the current mission");
+        if let Some(ref fc) = ctx.fc {
+            if let Err(e) = fc.commands.abort_mission(&fc.controller).await {
+                return CommandResult::Failed {
+                    message: format!("Current mission not aborted: {}", e),
+                };
+            }
+        }
+    }
+
     println!("  [MISSION_START] Mission ID: {}", mission.mission_id);
     println!("    Altitude: {}m, Speed: {}m/s", mission.altitude_m, mission.speed_mps);
     println!("    Pattern: {:?}", resqterra_shared::ScanPattern::try_from(mission.scan_pattern).unwrap_or(resqterra_shared::ScanPattern::PatternUnknown));
+    if let Some(ref waypoints) = uploaded {
+        println!("    Uploaded waypoints: {}", waypoints.len());
+    }
+
+    if mission.geofence.is_some() {
+        println!("    Geofence: set by the mission");
+    }
+    if let Some(ref rth) = mission.rth {
+        println!("    RTH: altitude {}m, speed {}m/s (0 = default)", rth.altitude_m, rth.speed_mps);
+    }
 
     if let Some(ref area) = mission.survey_area {
         println!("    Survey area: {} boundary points", area.boundary.len());
@@ -54,14 +120,58 @@ pub async fn handle_mission_start(ctx: &HandlerContext, command: &Command) -> Co
         }
     }
 
-    // TODO: In Phase 5, this will send mission to MAVLink bridge
-    // For now, simulate accepting the mission
+    // The FC must hold the plan as sent before the start counts as done
+    if let Some(ref fc) = ctx.fc {
+        if let Err(e) = fc.commands.load_mission(&fc.controller, mission, ctx.current_state).await {
+            return CommandResult::Failed {
+                message: format!("Mission {} not loaded on FC: {}", mission.mission_id, e),
+            };
+        }
+    }
+
+    // Group the telemetry of this flight under the mission, and track progress
+    // along the items the FC is given
+    if let Some(ref telemetry) = ctx.telemetry {
+        telemetry.start_mission(&mission.mission_id).await;
+        if let Some(ref fc) = ctx.fc {
+            let path = fc.commands.mission_path(mission, ctx.current_state);
+            telemetry.set_mission_path(path).await;
+        }
+    }
 
     CommandResult::Completed {
         message: format!("Mission {} accepted", mission.mission_id),
     }
 }
 
+/// Handle MISSION_UPLOAD command (one chunk of a mission's waypoints)
+pub async fn handle_mission_upload(ctx: &HandlerContext, command: &Command) -> CommandResult {
+    let Some(command::Params::MissionUpload(chunk)) = &command.params else {
+        return CommandResult::Rejected {
+            message: "Missing mission upload parameters".into(),
+        };
+    };
+
+    let outcome = ctx.mission_uploads.write().await.add(chunk);
+    match outcome {
+        Ok(ChunkOutcome::Stored { received, total }) => CommandResult::Completed {
+            message: format!("Chunk {}/{} of mission {}", received, total, chunk.mission_id),
+        },
+        Ok(ChunkOutcome::Complete { waypoints }) => {
+            println!(
+                "  [MISSION_UPLOAD] Mission {} complete: {} waypoints, checksum ok",
+                chunk.mission_id, waypoints
+            );
+            CommandResult::Completed {
+                message: format!("Mission {} uploaded: {} waypoints", chunk.mission_id, waypoints),
+            }
+        }
+        Err(e) => CommandResult::Rejected {
+            message: e.to_string(),
+        },
+    }
+}
+
 /// Handle MISSION_ABORT command
 pub async fn handle_mission_abort(ctx: &HandlerContext, command: &Command) -> CommandResult {
     // Can only abort if in mission
@@ -93,3 +203,86 @@ pub async fn handle_mission_abort(ctx: &HandlerContext, command: &Command) -> Co
         message: format!("Mission aborted: {}", abort.reason),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::handlers::FcLink;
+    use crate::mavlink::{FcConfig, FlightController, MavCommandSender};
+    use mavlink::ardupilotmega::{
+        MavAutopilot, MavCmd, MavMessage, MavResult, COMMAND_ACK_DATA, HEARTBEAT_DATA,
+    };
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    /// In-mission context whose simulated FC confirms mode changes, or
+    /// denies them unless `accept_mode`, plus the commands it received
+    fn context(accept_mode: bool) -> (HandlerContext, mpsc::UnboundedReceiver<MavCmd>) {
+        let (controller, mut outbound, inject) = FlightController::test_link(FcConfig::default());
+        let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(msg) = outbound.recv().await {
+                let MavMessage::COMMAND_LONG(cmd) = msg else {
+                    continue;
+                };
+                let _ = commands_tx.send(cmd.command);
+                let _ = if accept_mode {
+                    inject.send(MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+                        custom_mode: cmd.param2 as u32,
+                        autopilot: MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+                        ..Default::default()
+                    }))
+                } else {
+                    inject.send(MavMessage::COMMAND_ACK(COMMAND_ACK_DATA {
+                        command: cmd.command,
+                        result: MavResult::MAV_RESULT_DENIED,
+                        ..Default::default()
+                    }))
+                };
+            }
+        });
+
+        let ctx = HandlerContext {
+            device_id: "edge-001".into(),
+            current_state: DroneState::DroneInMission,
+            command_id: 1,
+            telemetry: None,
+            fc: Some(FcLink {
+                controller: Arc::new(controller),
+                commands: Arc::new(MavCommandSender::new(1, 1)),
+            }),
+            mission_uploads: Default::default(),
+            geofence: None,
+            video: None,
+        };
+        (ctx, commands_rx)
+    }
+
+    fn replacing_mission() -> Command {
+        Command {
+            params: Some(command::Params::MissionStart(resqterra_shared::MissionStart {
+                mission_id: "survey-2".into(),
+                replace: true,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_superseding_aborts_current_mission_on_fc() {
+        let (ctx, mut commands) = context(true);
+        let result = handle_mission_start(&ctx, &replacing_mission()).await;
+        assert!(matches!(result, CommandResult::Completed { .. }), "got {:?}", result);
+        assert_eq!(commands.recv().await, Some(MavCmd::MAV_CMD_DO_SET_MODE));
+
+        // The new mission doesn't start while the FC still flies the old one
+        let (ctx, _commands) = context(false);
+        let result = handle_mission_start(&ctx, &replacing_mission()).await;
+        assert!(
+            matches!(&result, CommandResult::Failed { message } if message.contains("not aborted")),
+            "got {:?}",
+            result
+        );
+    }
+}
@@ -1,18 +1,36 @@
 //! Command handlers for different command types
 
+mod capabilities;
 mod mission;
 mod rth;
 mod status;
 mod config;
 mod emergency;
+mod faults;
+mod flight_log;
+mod params;
+mod video;
+mod waypoint;
 
-pub use mission::{handle_mission_start, handle_mission_abort};
+pub use capabilities::handle_capabilities;
+pub use mission::{handle_mission_abort, handle_mission_start, handle_mission_upload};
 pub use rth::handle_rth;
 pub use status::handle_status_request;
 pub use config::handle_config_update;
 pub use emergency::handle_emergency_stop;
+pub use faults::{handle_clear_faults, handle_get_faults};
+pub use flight_log::handle_download_log;
+pub use params::{handle_param_get, handle_param_set};
+pub use video::{handle_video_start, handle_video_stop};
+pub use waypoint::handle_skip_waypoint;
 
+use super::mission_upload::MissionUploads;
+use crate::mavlink::{FlightController, MavCommandSender, TelemetryReader};
+use crate::safety::Geofence;
+use crate::video::VideoStreamer;
 use resqterra_shared::DroneState;
+use std::sync::Arc;
+use tokio::sync::RwLock;
 
 /// Context passed to command handlers
 #[derive(Debug, Clone)]
@@ -20,4 +38,27 @@ pub struct HandlerContext {
     pub device_id: String,
     pub current_state: DroneState,
     pub command_id: u64,
+    /// FC telemetry, when a flight controller is attached
+    pub telemetry: Option<Arc<TelemetryReader>>,
+    /// FC link for handlers that command the flight controller directly
+    pub fc: Option<FcLink>,
+    /// Missions streamed in chunks, shared across commands
+    pub mission_uploads: Arc<RwLock<MissionUploads>>,
+    /// Geofence the safety monitor enforces, set by config updates
+    pub geofence: Option<Arc<RwLock<Geofence>>>,
+    /// Video stream pipeline, when one is configured
+    pub video: Option<Arc<VideoStreamer>>,
+}
+
+/// Flight controller connection plus the sender that targets it
+#[derive(Clone)]
+pub struct FcLink {
+    pub controller: Arc<FlightController>,
+    pub commands: Arc<MavCommandSender>,
+}
+
+impl std::fmt::Debug for FcLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FcLink").finish_non_exhaustive()
+    }
 }
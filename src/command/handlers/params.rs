@@ -0,0 +1,95 @@
+//! FC parameter command handlers
+
+use super::HandlerContext;
+use crate::command::CommandResult;
+use crate::mavlink::MavParamClient;
+use resqterra_shared::{command::Params, Command, FcParam};
+
+/// Most parameters read by one PARAM_GET (each takes a round trip to the FC)
+const MAX_PARAM_GET: usize = 32;
+
+/// Handle PARAM_GET command
+///
+/// Reads every requested parameter from the FC; the values go back in the ACK.
+pub async fn handle_param_get(ctx: &HandlerContext, command: &Command) -> CommandResult {
+    let Some(Params::ParamGet(request)) = &command.params else {
+        return CommandResult::Rejected {
+            message: "Missing param_get parameters".into(),
+        };
+    };
+    let count = request.names.len();
+    if count > MAX_PARAM_GET {
+        return CommandResult::Rejected {
+            message: format!("Too many parameters ({}, max {})", count, MAX_PARAM_GET),
+        };
+    }
+    let Some(fc) = &ctx.fc else {
+        return CommandResult::Failed {
+            message: "Flight controller unavailable".into(),
+        };
+    };
+
+    let client = MavParamClient::for_controller(&fc.controller);
+    let mut params = Vec::with_capacity(request.names.len());
+    for name in &request.names {
+        match client.get(&fc.controller, name).await {
+            Ok(value) => params.push(FcParam {
+                name: name.clone(),
+                value,
+            }),
+            Err(e) => {
+                return CommandResult::Failed {
+                    message: e.to_string(),
+                }
+            }
+        }
+    }
+    println!("  [PARAM_GET] {}", describe(&params));
+    CommandResult::Params {
+        message: describe(&params),
+        params,
+    }
+}
+
+/// Handle PARAM_SET command
+///
+/// Completes once the FC echoes the new value; a value it clamps or refuses
+/// fails the command.
+pub async fn handle_param_set(ctx: &HandlerContext, command: &Command) -> CommandResult {
+    let Some(Params::ParamSet(request)) = &command.params else {
+        return CommandResult::Rejected {
+            message: "Missing param_set parameters".into(),
+        };
+    };
+    let Some(fc) = &ctx.fc else {
+        return CommandResult::Failed {
+            message: "Flight controller unavailable".into(),
+        };
+    };
+
+    let client = MavParamClient::for_controller(&fc.controller);
+    match client.set(&fc.controller, &request.name, request.value).await {
+        Ok(value) => {
+            let params = vec![FcParam {
+                name: request.name.clone(),
+                value,
+            }];
+            CommandResult::Params {
+                message: describe(&params),
+                params,
+            }
+        }
+        Err(e) => CommandResult::Failed {
+            message: e.to_string(),
+        },
+    }
+}
+
+/// `RTL_ALT = 1500, WPNAV_SPEED = 500`, for the ACK message
+fn describe(params: &[FcParam]) -> String {
+    params
+        .iter()
+        .map(|p| format!("{} = {}", p.name, p.value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -2,7 +2,8 @@
 
 use super::HandlerContext;
 use crate::command::CommandResult;
-use resqterra_shared::{Command, DroneState, command};
+use crate::mavlink::RthAction;
+use resqterra_shared::{Command, DroneState, ReturnToHome, command};
 
 /// Handle RTH (Return-to-Home) command
 ///
@@ -31,27 +32,183 @@ pub async fn handle_rth(ctx: &HandlerContext, command: &Command) -> CommandResul
 
     // Extract RTH parameters
     let rth = match &command.params {
-        Some(command::Params::Rth(r)) => r,
-        _ => {
-            // RTH can work without explicit parameters (use defaults)
-            println!("  [RTH] Using default parameters");
-            return CommandResult::Completed {
-                message: "RTH initiated with defaults".into(),
-            };
+        Some(command::Params::Rth(r)) => Some(r),
+        _ => None,
+    };
+
+    match rth {
+        Some(rth) => {
+            println!("  [RTH] Return-to-Home initiated");
+            if rth.altitude_m > 0.0 {
+                println!("    RTH altitude: {}m", rth.altitude_m);
+            }
+            if rth.speed_mps > 0.0 {
+                println!("    RTH speed: {}m/s", rth.speed_mps);
+            }
+        }
+        // RTH can work without explicit parameters (use defaults)
+        None => println!("  [RTH] Using default parameters"),
+    }
+
+    let message = match rth {
+        Some(_) => "RTH initiated",
+        None => "RTH initiated with defaults",
+    };
+
+    let Some(fc) = &ctx.fc else {
+        // No FC attached (bench testing): accept without flying anything
+        return CommandResult::Completed {
+            message: message.into(),
+        };
+    };
+
+    // Near home, land in place rather than climbing out and back; with the
+    // position or home unknown, RTL is the safe default
+    let action = match &ctx.telemetry {
+        Some(telemetry) => match (telemetry.get_position().await, telemetry.get_home().await) {
+            (Some(position), Some(home)) => fc.commands.rth_action(&position, &home),
+            _ => RthAction::ReturnToLaunch,
+        },
+        None => RthAction::ReturnToLaunch,
+    };
+
+    let sent = match action {
+        RthAction::Land { distance_m } => {
+            println!("  [RTH] {:.1}m from home, landing in place", distance_m);
+            fc.commands
+                .land(&fc.controller)
+                .await
+                .map(|()| format!("Near home ({:.1}m), landing in place", distance_m))
+        }
+        RthAction::ReturnToLaunch => {
+            let defaults = ReturnToHome::default();
+            fc.commands
+                .return_to_home(&fc.controller, rth.unwrap_or(&defaults))
+                .await
+                .map(|()| message.to_string())
         }
     };
 
-    println!("  [RTH] Return-to-Home initiated");
-    if rth.altitude_m > 0.0 {
-        println!("    RTH altitude: {}m", rth.altitude_m);
+    match sent {
+        Ok(message) => CommandResult::Completed { message },
+        Err(e) => CommandResult::from_fc_error("RTH failed", &e),
     }
-    if rth.speed_mps > 0.0 {
-        println!("    RTH speed: {}m/s", rth.speed_mps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::handlers::FcLink;
+    use crate::mavlink::{FcConfig, FlightController, MavCommandSender, TelemetryReader};
+    use mavlink::ardupilotmega::{
+        MavAutopilot, MavCmd, MavMessage, MavResult, COMMAND_ACK_DATA, GLOBAL_POSITION_INT_DATA,
+        HEARTBEAT_DATA, HOME_POSITION_DATA,
+    };
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    /// In-mission context with home at 47.5N 8.5E and the drone at `lon`,
+    /// plus the commands the simulated FC received (it confirms mode changes
+    /// and answers other commands with `result`)
+    async fn context(
+        lon: f64,
+        result: MavResult,
+    ) -> (HandlerContext, mpsc::UnboundedReceiver<MavCmd>) {
+        let telemetry = Arc::new(TelemetryReader::new());
+        telemetry
+            .process_message(&MavMessage::HOME_POSITION(HOME_POSITION_DATA {
+                latitude: 475_000_000,
+                longitude: 85_000_000,
+                ..Default::default()
+            }))
+            .await;
+        telemetry
+            .process_message(&MavMessage::GLOBAL_POSITION_INT(GLOBAL_POSITION_INT_DATA {
+                lat: 475_000_000,
+                lon: (lon * 1e7) as i32,
+                alt: 30_000,
+                ..Default::default()
+            }))
+            .await;
+
+        let (controller, mut outbound, inject) = FlightController::test_link(FcConfig::default());
+        let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(msg) = outbound.recv().await {
+                let MavMessage::COMMAND_LONG(cmd) = msg else {
+                    continue;
+                };
+                let _ = commands_tx.send(cmd.command);
+                if cmd.command == MavCmd::MAV_CMD_DO_SET_MODE {
+                    let _ = inject.send(MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+                        custom_mode: cmd.param2 as u32,
+                        autopilot: MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+                        ..Default::default()
+                    }));
+                } else {
+                    let _ = inject.send(MavMessage::COMMAND_ACK(COMMAND_ACK_DATA {
+                        command: cmd.command,
+                        result,
+                        ..Default::default()
+                    }));
+                }
+            }
+        });
+
+        let ctx = HandlerContext {
+            device_id: "edge-001".into(),
+            current_state: DroneState::DroneInMission,
+            command_id: 1,
+            telemetry: Some(telemetry),
+            fc: Some(FcLink {
+                controller: Arc::new(controller),
+                commands: Arc::new(MavCommandSender::new(1, 1)),
+            }),
+            mission_uploads: Default::default(),
+            geofence: None,
+            video: None,
+        };
+        (ctx, commands_rx)
     }
 
-    // TODO: In Phase 5, this will trigger RTL mode via MAVLink
+    #[tokio::test]
+    async fn test_rth_near_home_lands_in_place() {
+        // ~4 m east of home
+        let (ctx, mut commands) = context(8.500_05, MavResult::MAV_RESULT_ACCEPTED).await;
 
-    CommandResult::Completed {
-        message: "RTH initiated".into(),
+        let result = handle_rth(&ctx, &Command::default()).await;
+        assert!(
+            matches!(&result, CommandResult::Completed { message } if message.starts_with("Near home")),
+            "got {:?}",
+            result
+        );
+        assert_eq!(commands.recv().await, Some(MavCmd::MAV_CMD_NAV_LAND));
+
+        // The FC's answer goes back to the server
+        let (ctx, _commands) = context(8.500_05, MavResult::MAV_RESULT_DENIED).await;
+        let result = handle_rth(&ctx, &Command::default()).await;
+        assert!(
+            matches!(&result, CommandResult::Rejected { message } if message.ends_with("DENIED")),
+            "got {:?}",
+            result
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rth_far_from_home_flies_rtl() {
+        // ~750 m east of home
+        let (ctx, mut commands) = context(8.51, MavResult::MAV_RESULT_ACCEPTED).await;
+
+        let result = handle_rth(&ctx, &Command::default()).await;
+        assert!(matches!(result, CommandResult::Completed { .. }));
+        assert_eq!(commands.recv().await, Some(MavCmd::MAV_CMD_DO_SET_MODE));
+
+        // A zero radius never lands in place
+        let (mut ctx, mut commands) = context(8.500_05, MavResult::MAV_RESULT_ACCEPTED).await;
+        if let Some(fc) = &mut ctx.fc {
+            fc.commands = Arc::new(MavCommandSender::new(1, 1).with_rth_land_radius(0.0));
+        }
+        handle_rth(&ctx, &Command::default()).await;
+        assert_eq!(commands.recv().await, Some(MavCmd::MAV_CMD_DO_SET_MODE));
     }
 }
@@ -0,0 +1,63 @@
+//! Video stream command handlers
+
+use super::HandlerContext;
+use crate::command::CommandResult;
+use resqterra_shared::{command::Params, Command};
+
+const NOT_CONFIGURED: &str = "Video streaming not configured (RESQTERRA_VIDEO_PIPELINE)";
+
+/// Handle VIDEO_START command
+///
+/// Negotiates the stream from the request and (re)starts the pipeline; the
+/// stream the drone settled on goes back in the ACK.
+pub async fn handle_video_start(ctx: &HandlerContext, command: &Command) -> CommandResult {
+    let Some(video) = &ctx.video else {
+        return CommandResult::Failed {
+            message: NOT_CONFIGURED.into(),
+        };
+    };
+    let request = match &command.params {
+        Some(Params::VideoStart(request)) => request.clone(),
+        _ => Default::default(),
+    };
+
+    let stream = match video.negotiate(&request) {
+        Ok(stream) => stream,
+        Err(e) => {
+            return CommandResult::Rejected {
+                message: e.to_string(),
+            }
+        }
+    };
+    match video.start(stream).await {
+        Ok(stream) => {
+            println!(
+                "  [VIDEO] {} {} {}x{}@{} at {}",
+                stream.camera, stream.codec, stream.width, stream.height, stream.framerate,
+                stream.url
+            );
+            CommandResult::Video {
+                message: format!("Streaming at {}", stream.url),
+                stream,
+            }
+        }
+        Err(e) => CommandResult::Failed {
+            message: e.to_string(),
+        },
+    }
+}
+
+/// Handle VIDEO_STOP command
+pub async fn handle_video_stop(ctx: &HandlerContext, _command: &Command) -> CommandResult {
+    let Some(video) = &ctx.video else {
+        return CommandResult::Failed {
+            message: NOT_CONFIGURED.into(),
+        };
+    };
+    let message = match video.stop().await {
+        Some(stream) => format!("Stopped stream {}", stream.stream_id),
+        None => "No video stream running".into(),
+    };
+    println!("  [VIDEO] {}", message);
+    CommandResult::Completed { message }
+}
@@ -0,0 +1,162 @@
+//! Mid-mission waypoint control
+
+use super::HandlerContext;
+use crate::command::CommandResult;
+use resqterra_shared::{command, Command, DroneState};
+
+/// Handle SKIP_WAYPOINT command
+///
+/// Advances the FC's current mission item (obstacle, area already covered).
+/// The target must lie ahead of the current item and within the mission.
+pub async fn handle_skip_waypoint(ctx: &HandlerContext, command: &Command) -> CommandResult {
+    if ctx.current_state != DroneState::DroneInMission {
+        return CommandResult::Rejected {
+            message: format!("Not in mission (state: {:?})", ctx.current_state),
+        };
+    }
+
+    let (Some(telemetry), Some(fc)) = (&ctx.telemetry, &ctx.fc) else {
+        return CommandResult::Failed {
+            message: "Flight controller unavailable".into(),
+        };
+    };
+
+    let Some(progress) = telemetry.get_mission_current().await else {
+        return CommandResult::Rejected {
+            message: "Current waypoint not reported by FC yet".into(),
+        };
+    };
+
+    // No target means "the next one"
+    let target = match &command.params {
+        Some(command::Params::SkipWaypoint(skip)) => skip.target_index,
+        _ => None,
+    }
+    .unwrap_or(progress.current as u32 + 1);
+
+    if target >= progress.total as u32 {
+        return CommandResult::Rejected {
+            message: format!(
+                "Waypoint {} out of range (mission has {} items)",
+                target, progress.total
+            ),
+        };
+    }
+    if target <= progress.current as u32 {
+        return CommandResult::Rejected {
+            message: format!(
+                "Waypoint {} is not ahead of current waypoint {}",
+                target, progress.current
+            ),
+        };
+    }
+
+    println!(
+        "  [SKIP_WAYPOINT] {} -> {} (of {})",
+        progress.current, target, progress.total
+    );
+
+    match fc
+        .commands
+        .set_current_waypoint(&fc.controller, target as u16)
+        .await
+    {
+        Ok(current) => CommandResult::Waypoint {
+            message: format!("Skipped to waypoint {}", current),
+            current_waypoint: current as u32,
+        },
+        Err(e) => CommandResult::Failed {
+            message: format!("Waypoint skip failed: {}", e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::handlers::FcLink;
+    use crate::mavlink::{FcConfig, FlightController, MavCommandSender, TelemetryReader};
+    use mavlink::ardupilotmega::{MavMessage, MISSION_CURRENT_DATA};
+    use resqterra_shared::SkipWaypoint;
+    use std::sync::Arc;
+
+    fn mission_current(seq: u16, total: u16) -> MavMessage {
+        MavMessage::MISSION_CURRENT(MISSION_CURRENT_DATA {
+            seq,
+            total,
+            ..Default::default()
+        })
+    }
+
+    /// In-mission context on waypoint 2 of 6, with a simulated FC that
+    /// confirms every MISSION_SET_CURRENT it receives
+    async fn context() -> HandlerContext {
+        let telemetry = Arc::new(TelemetryReader::new());
+        telemetry.process_message(&mission_current(2, 6)).await;
+
+        let (controller, mut outbound, inject) = FlightController::test_link(FcConfig::default());
+        tokio::spawn(async move {
+            while let Some(msg) = outbound.recv().await {
+                if let MavMessage::MISSION_SET_CURRENT(set) = msg {
+                    let _ = inject.send(mission_current(set.seq, 6));
+                }
+            }
+        });
+
+        HandlerContext {
+            device_id: "edge-001".into(),
+            current_state: DroneState::DroneInMission,
+            command_id: 1,
+            telemetry: Some(telemetry),
+            fc: Some(FcLink {
+                controller: Arc::new(controller),
+                commands: Arc::new(MavCommandSender::new(1, 1)),
+            }),
+            mission_uploads: Default::default(),
+            geofence: None,
+            video: None,
+        }
+    }
+
+    fn skip(target_index: Option<u32>) -> Command {
+        Command {
+            params: Some(command::Params::SkipWaypoint(SkipWaypoint { target_index })),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skip_to_next_and_explicit_waypoint() {
+        let ctx = context().await;
+
+        match handle_skip_waypoint(&ctx, &skip(None)).await {
+            CommandResult::Waypoint { current_waypoint, .. } => assert_eq!(current_waypoint, 3),
+            other => panic!("expected Waypoint, got {:?}", other),
+        }
+        match handle_skip_waypoint(&ctx, &skip(Some(5))).await {
+            CommandResult::Waypoint { current_waypoint, .. } => assert_eq!(current_waypoint, 5),
+            other => panic!("expected Waypoint, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skip_rejected_out_of_range_or_not_in_mission() {
+        let ctx = context().await;
+
+        for target in [6, 2, 1] {
+            assert!(matches!(
+                handle_skip_waypoint(&ctx, &skip(Some(target))).await,
+                CommandResult::Rejected { .. }
+            ));
+        }
+
+        let idle = HandlerContext {
+            current_state: DroneState::DroneIdle,
+            ..ctx
+        };
+        assert!(matches!(
+            handle_skip_waypoint(&idle, &skip(None)).await,
+            CommandResult::Rejected { .. }
+        ));
+    }
+}
@@ -0,0 +1,135 @@
+//! Background streaming of FC onboard logs to the server
+//!
+//! Each chunk goes up as a `SensorData` frame tagged with the command ID,
+//! followed by a progress ACK; the final ACK comes once the log is complete.
+
+use super::executor::CommandExecutor;
+use super::handlers::FcLink;
+use crate::connection::OutboundSender;
+use crate::mavlink::{LogChunk, LogEntry, LOG_CHUNK_SIZE};
+use anyhow::{anyhow, Result};
+use resqterra_shared::{
+    envelope, AckStatus, DownloadLog, Envelope, Header, MessageType, SensorData,
+    TransferProgress,
+};
+use tokio::sync::mpsc;
+
+/// `SensorData.sensor_type` of log chunks
+const LOG_SENSOR_TYPE: &str = "FC_LOG";
+
+/// `SensorData.format` of log chunks (ArduPilot `.bin`)
+const LOG_FORMAT: &str = "dataflash";
+
+/// Chunks reassembled ahead of the uplink
+const CHUNK_BUFFER: usize = 4;
+
+/// Download the requested log and stream it, then send the final ACK
+pub(super) async fn run(
+    executor: CommandExecutor,
+    fc: FcLink,
+    uplink: OutboundSender,
+    request: DownloadLog,
+    command_id: u64,
+    ack_sequence_id: u64,
+) {
+    let (status, message) =
+        match transfer(&executor, &fc, &uplink, request, command_id, ack_sequence_id).await {
+            Ok(message) => (AckStatus::AckCompleted, message),
+            Err(e) => (AckStatus::AckFailed, format!("Log download failed: {}", e)),
+        };
+
+    if let Some(ack) = executor.finish_pending(command_id, status, &message).await {
+        if let Err(e) = uplink.send(ack).await {
+            eprintln!("Failed to send log transfer ACK: {}", e);
+        }
+    }
+}
+
+async fn transfer(
+    executor: &CommandExecutor,
+    fc: &FcLink,
+    uplink: &OutboundSender,
+    request: DownloadLog,
+    command_id: u64,
+    ack_sequence_id: u64,
+) -> Result<String> {
+    let logs = fc.commands.list_logs(&fc.controller).await?;
+    let entry = match request.log_id {
+        Some(id) => logs.iter().find(|e| e.id as u32 == id),
+        None => logs.iter().max_by_key(|e| e.id),
+    }
+    .copied()
+    .ok_or_else(|| match request.log_id {
+        Some(id) => anyhow!("FC has no log {}", id),
+        None => anyhow!("FC has no logs"),
+    })?;
+
+    let (chunk_tx, mut chunk_rx) = mpsc::channel(CHUNK_BUFFER);
+    let download = fc.commands.download_log(
+        &fc.controller,
+        entry,
+        request.resume_from_chunk,
+        chunk_tx,
+    );
+
+    // Dropping the receiver on cancel or uplink loss stops the download
+    let forward = async move {
+        while let Some(chunk) = chunk_rx.recv().await {
+            if !executor.is_pending(command_id).await {
+                return Err(anyhow!("cancelled"));
+            }
+            let sent = progress(&entry, &chunk);
+            for envelope in [
+                chunk_envelope(executor, command_id, &entry, chunk),
+                executor.progress_ack(ack_sequence_id, command_id, sent),
+            ] {
+                uplink
+                    .send(envelope)
+                    .await
+                    .map_err(|_| anyhow!("uplink closed"))?;
+            }
+        }
+        Ok(())
+    };
+
+    let (downloaded, forwarded) = tokio::join!(download, forward);
+    forwarded?;
+    downloaded?;
+    Ok(format!("Log {} sent ({} bytes)", entry.id, entry.size))
+}
+
+/// Wrap a log chunk as a `SensorData` frame
+fn chunk_envelope(
+    executor: &CommandExecutor,
+    command_id: u64,
+    entry: &LogEntry,
+    chunk: LogChunk,
+) -> Envelope {
+    let seq = executor.next_sequence_id();
+    Envelope {
+        header: Some(Header::new(executor.device_id(), MessageType::MsgSensorData, seq)),
+        payload: Some(envelope::Payload::SensorData(SensorData {
+            sensor_type: LOG_SENSOR_TYPE.into(),
+            mission_id: format!("log-{}", entry.id),
+            capture_timestamp_ms: entry.time_utc as u64 * 1000,
+            capture_position: None,
+            data: chunk.data,
+            format: LOG_FORMAT.into(),
+            chunk_index: chunk.index,
+            total_chunks: chunk.total_chunks,
+            command_id,
+        })),
+        signature: Vec::new(),
+    }
+}
+
+/// Progress after `chunk` has been sent
+fn progress(entry: &LogEntry, chunk: &LogChunk) -> TransferProgress {
+    let chunks_sent = chunk.index + 1;
+    TransferProgress {
+        chunks_sent,
+        total_chunks: chunk.total_chunks,
+        bytes_sent: (chunks_sent as u64 * LOG_CHUNK_SIZE as u64).min(entry.size as u64),
+        total_bytes: entry.size as u64,
+    }
+}
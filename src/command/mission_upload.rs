@@ -0,0 +1,207 @@
+//! Reassembly of missions streamed in `CMD_MISSION_UPLOAD` chunks
+//!
+//! Chunks arrive in order, one command each (the server queue sends the next
+//! once the previous is ACKed). The last chunk is checked against the total
+//! waypoint count and checksum every chunk carries; the verified waypoints
+//! are kept until a `CMD_MISSION_START` for the mission takes them.
+
+use resqterra_shared::mission_upload::waypoint_checksum;
+use resqterra_shared::{GpsCoordinate, MissionUploadChunk, MAX_MISSION_WAYPOINTS};
+use std::fmt;
+
+/// Why a chunk was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UploadError {
+    /// Chunk out of sequence (a chunk was lost, or the upload never started)
+    OutOfOrder { expected: u32, got: u32 },
+    /// Chunk disagrees with the upload's totals or checksum
+    Mismatch,
+    /// More waypoints than the edge reassembles
+    TooLarge(u32),
+    /// Reassembled mission failed verification; the upload is discarded
+    Corrupt { waypoints: usize, expected: u32 },
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::OutOfOrder { expected, got } => {
+                write!(f, "Expected chunk {}, got {}", expected, got)
+            }
+            UploadError::Mismatch => write!(f, "Chunk doesn't match the upload in progress"),
+            UploadError::TooLarge(count) => write!(
+                f,
+                "Mission has {} waypoints (max {})",
+                count, MAX_MISSION_WAYPOINTS
+            ),
+            UploadError::Corrupt { waypoints, expected } => write!(
+                f,
+                "Checksum mismatch ({} of {} waypoints), upload discarded",
+                waypoints, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+/// Progress after a stored chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkOutcome {
+    /// More chunks to come
+    Stored { received: u32, total: u32 },
+    /// The mission is complete and verified
+    Complete { waypoints: usize },
+}
+
+/// An upload in progress
+#[derive(Debug)]
+struct Upload {
+    mission_id: String,
+    total_chunks: u32,
+    total_waypoints: u32,
+    checksum: u32,
+    /// Chunks stored so far
+    received: u32,
+    waypoints: Vec<GpsCoordinate>,
+}
+
+impl Upload {
+    fn matches(&self, chunk: &MissionUploadChunk) -> bool {
+        self.mission_id == chunk.mission_id
+            && self.total_chunks == chunk.total_chunks
+            && self.total_waypoints == chunk.total_waypoints
+            && self.checksum == chunk.checksum
+    }
+}
+
+/// The upload in progress and the last verified mission
+#[derive(Debug, Default)]
+pub struct MissionUploads {
+    in_progress: Option<Upload>,
+    /// Verified mission waiting for its mission start
+    completed: Option<(String, Vec<GpsCoordinate>)>,
+}
+
+impl MissionUploads {
+    /// Store a chunk
+    ///
+    /// Chunk 0 (re)starts an upload, replacing any other in progress.
+    pub fn add(&mut self, chunk: &MissionUploadChunk) -> Result<ChunkOutcome, UploadError> {
+        if chunk.total_waypoints as usize > MAX_MISSION_WAYPOINTS {
+            return Err(UploadError::TooLarge(chunk.total_waypoints));
+        }
+        if chunk.chunk_index == 0 {
+            self.in_progress = Some(Upload {
+                mission_id: chunk.mission_id.clone(),
+                total_chunks: chunk.total_chunks,
+                total_waypoints: chunk.total_waypoints,
+                checksum: chunk.checksum,
+                received: 0,
+                waypoints: Vec::with_capacity(chunk.total_waypoints as usize),
+            });
+        }
+
+        let upload = self.in_progress.as_mut().ok_or(UploadError::OutOfOrder {
+            expected: 0,
+            got: chunk.chunk_index,
+        })?;
+        if !upload.matches(chunk) {
+            return Err(UploadError::Mismatch);
+        }
+        if chunk.chunk_index != upload.received {
+            return Err(UploadError::OutOfOrder {
+                expected: upload.received,
+                got: chunk.chunk_index,
+            });
+        }
+        if upload.waypoints.len() + chunk.waypoints.len() > upload.total_waypoints as usize {
+            return Err(UploadError::Mismatch);
+        }
+        upload.waypoints.extend_from_slice(&chunk.waypoints);
+        upload.received += 1;
+        if upload.received < upload.total_chunks {
+            return Ok(ChunkOutcome::Stored {
+                received: upload.received,
+                total: upload.total_chunks,
+            });
+        }
+
+        let upload = self.in_progress.take().expect("upload in progress");
+        if upload.waypoints.len() != upload.total_waypoints as usize
+            || waypoint_checksum(&upload.waypoints) != upload.checksum
+        {
+            return Err(UploadError::Corrupt {
+                waypoints: upload.waypoints.len(),
+                expected: upload.total_waypoints,
+            });
+        }
+        let waypoints = upload.waypoints.len();
+        self.completed = Some((upload.mission_id, upload.waypoints));
+        Ok(ChunkOutcome::Complete { waypoints })
+    }
+
+    /// Take the verified waypoints of `mission_id`
+    pub fn take(&mut self, mission_id: &str) -> Option<Vec<GpsCoordinate>> {
+        match &self.completed {
+            Some((id, _)) if id == mission_id => self.completed.take().map(|(_, w)| w),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::mission_upload::chunk_mission;
+    use resqterra_shared::MISSION_CHUNK_WAYPOINTS;
+
+    fn mission(count: usize) -> Vec<GpsCoordinate> {
+        (0..count)
+            .map(|i| GpsCoordinate {
+                latitude: 47.0 + i as f64 * 1e-5,
+                longitude: 8.5,
+                altitude_m: 40.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reassembles_large_mission() {
+        let waypoints = mission(25 * MISSION_CHUNK_WAYPOINTS + 7);
+        let chunks = chunk_mission("survey-1", &waypoints);
+        let mut uploads = MissionUploads::default();
+
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(matches!(uploads.add(chunk), Ok(ChunkOutcome::Stored { .. })));
+        }
+        assert_eq!(
+            uploads.add(chunks.last().unwrap()),
+            Ok(ChunkOutcome::Complete { waypoints: waypoints.len() })
+        );
+        assert_eq!(uploads.take("survey-2"), None);
+        assert_eq!(uploads.take("survey-1"), Some(waypoints));
+        assert_eq!(uploads.take("survey-1"), None);
+    }
+
+    #[test]
+    fn test_rejects_gaps_and_corruption() {
+        let chunks = chunk_mission("survey-1", &mission(3 * MISSION_CHUNK_WAYPOINTS));
+        let mut uploads = MissionUploads::default();
+
+        assert_eq!(
+            uploads.add(&chunks[1]),
+            Err(UploadError::OutOfOrder { expected: 0, got: 1 })
+        );
+        uploads.add(&chunks[0]).unwrap();
+        assert_eq!(
+            uploads.add(&chunks[2]),
+            Err(UploadError::OutOfOrder { expected: 1, got: 2 })
+        );
+        let mut damaged = chunks[1].clone();
+        damaged.waypoints[0].latitude += 1e-3;
+        uploads.add(&damaged).unwrap();
+        assert!(matches!(uploads.add(&chunks[2]), Err(UploadError::Corrupt { .. })));
+        assert_eq!(uploads.take("survey-1"), None);
+    }
+}
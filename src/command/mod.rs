@@ -5,8 +5,12 @@
 //! - Dispatching to appropriate command handlers
 //! - Generating ACK responses
 //! - Tracking command execution state
+//! - Reassembling missions uploaded in chunks
+//! - Answering retried or replayed commands without running them twice
 
 mod executor;
 pub mod handlers;
+mod log_transfer;
+mod mission_upload;
 
-pub use executor::{CommandExecutor, CommandResult};
+pub use executor::{CommandExecutor, CommandResult, EmergencyPolicy};
@@ -0,0 +1,23 @@
+//! Heartbeat contents
+//!
+//! The connection task sends a heartbeat every `HEARTBEAT_INTERVAL_MS`; what
+//! it reports comes from a `HeartbeatProvider` set on the connection manager.
+
+use async_trait::async_trait;
+use resqterra_shared::{DroneState, Heartbeat};
+use tokio::sync::RwLock;
+
+/// Source of the status reported in heartbeats
+#[async_trait]
+pub trait HeartbeatProvider: Send + Sync {
+    /// The heartbeat to send, `uptime_ms` into the connection
+    async fn heartbeat(&self, uptime_ms: u64) -> Heartbeat;
+}
+
+/// Bare state only, until a richer provider is set
+#[async_trait]
+impl HeartbeatProvider for RwLock<DroneState> {
+    async fn heartbeat(&self, uptime_ms: u64) -> Heartbeat {
+        Heartbeat::new(uptime_ms, *self.read().await, 0, true)
+    }
+}
@@ -1,20 +1,35 @@
 //! Connection manager with persistent connections and automatic reconnection
 
+use super::heartbeat::HeartbeatProvider;
+use super::outbound::{self, OutboundReceiver, OutboundSender};
+use super::outbox::{Outbox, OutboxConfig};
+use super::selector::{DecisionReason, SelectorConfig, TransportDecision, TransportSelector};
+use super::telemetry_policy::TelemetryPolicies;
+use crate::safety::SafetyMonitor;
+use crate::transport::bt_discovery::AdapterError;
+use crate::transport::{
+    LoraConfig, LoraConnector, QuicConfig, QuicConnector, RfcommConnector, RfcommConfig,
+    SerialConfig, SerialConnector, TcpConnector, Transport, TransportConnector, TransportStream,
+    UdpConnector, WebSocketConnector,
+};
 use anyhow::{anyhow, Result};
-use bluer::rfcomm::{SocketAddr as RfcommAddr, Stream as RfcommStream};
 use bluer::Address as BtAddress;
+use futures::stream::FuturesUnordered;
+use futures::{SinkExt, StreamExt};
 use resqterra_shared::{
-    codec::{self, FrameDecoder},
-    safety, DroneState, Envelope, Header, Heartbeat, MessageType,
+    codec::EnvelopeCodec,
+    compression,
+    noise::{NoiseConfig, NoiseStream}, now_ms, safety, Compression, ConnectionQuality, DeviceId, DroneState, Envelope,
+    EnvelopeSigner, Header, Hello, LinkStats, MessageType, QualityHistory, SafetyConfig,
+    SequenceEvent, SequenceTracker, QUALITY_HISTORY_LEN,
 };
-use std::pin::Pin;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, RwLock};
 use tokio::time::{interval, timeout, Instant};
+use tokio_util::codec::{FramedRead, FramedWrite};
 
 /// Events emitted by the connection manager
 #[derive(Debug, Clone)]
@@ -24,29 +39,50 @@ pub enum ConnectionEvent {
     /// Disconnected from server
     Disconnected { reason: String },
     /// Received an envelope from server
-    Received(Envelope),
+    Received(Box<Envelope>),
     /// Failed to connect after all retries
     ConnectionFailed { reason: String },
     /// Transport switched (e.g., 5G -> Bluetooth)
     TransportSwitched { from: Transport, to: Transport },
+    /// Transport chosen for the next connection attempt, and why
+    TransportSelected(TransportDecision),
 }
 
-/// Available transport types
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Transport {
-    FiveG,
-    Bluetooth,
+/// How a connection is established
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectMode {
+    /// Try the selected transport, then the others if it fails
+    #[default]
+    Sequential,
+    /// Try every available transport at once and keep whichever is up first
+    Race,
 }
 
-impl std::fmt::Display for Transport {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Transport::FiveG => write!(f, "5G"),
-            Transport::Bluetooth => write!(f, "Bluetooth"),
+impl std::str::FromStr for ConnectMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "sequential" => Ok(ConnectMode::Sequential),
+            "race" => Ok(ConnectMode::Race),
+            other => Err(anyhow!("Unknown connect mode: {}", other)),
         }
     }
 }
 
+/// How the 5G connector reaches the server
+#[derive(Debug, Clone, Default)]
+pub enum FiveGLink {
+    #[default]
+    Tcp,
+    /// UDP with retransmission for everything but telemetry
+    Udp,
+    /// QUIC: TLS, telemetry on its own streams, 0-RTT reconnects
+    Quic(QuicConfig),
+    /// Bare envelopes over a WebSocket to this URL; no Noise
+    WebSocket(String),
+}
+
 /// Bluetooth transport mode
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum BluetoothMode {
@@ -68,6 +104,8 @@ pub struct BluetoothConfig {
     pub channel: u8,
     /// TCP simulation address (when mode is TcpSimulation)
     pub tcp_address: String,
+    /// Frame compression offered over the relay link
+    pub compression: Vec<Compression>,
 }
 
 impl Default for BluetoothConfig {
@@ -77,6 +115,7 @@ impl Default for BluetoothConfig {
             relay_address: None,
             channel: 1,
             tcp_address: "127.0.0.1:9000".into(),
+            compression: compression::supported(),
         }
     }
 }
@@ -85,10 +124,14 @@ impl Default for BluetoothConfig {
 #[derive(Debug, Clone)]
 pub struct ConnectionConfig {
     /// Device ID for this edge device
-    pub device_id: String,
-    /// 5G server address
+    pub device_id: DeviceId,
+    /// 5G server address (for the default connectors)
     pub server_5g: String,
-    /// Bluetooth configuration
+    /// How the 5G server is reached (TCP, UDP or QUIC)
+    pub link_5g: FiveGLink,
+    /// Frame compression offered over 5G and transports other than Bluetooth
+    pub compression_5g: Vec<Compression>,
+    /// Bluetooth configuration (for the default connectors)
     pub bluetooth: BluetoothConfig,
     /// Reconnection delay (initial)
     pub reconnect_delay: Duration,
@@ -98,70 +141,51 @@ pub struct ConnectionConfig {
     pub connect_timeout: Duration,
     /// Read timeout (should be > heartbeat interval)
     pub read_timeout: Duration,
+    /// Failsafe configuration reported to the server in the hello
+    pub safety_config: SafetyConfig,
+    /// Link quality samples kept for trend analysis (one per heartbeat reply)
+    pub quality_history_len: usize,
+    /// Signs outgoing and verifies incoming envelopes (None = signing off)
+    pub signer: Option<EnvelopeSigner>,
+    /// Encrypts every connection end to end, through relays too (None = plaintext)
+    pub noise: Option<NoiseConfig>,
+    /// Holds envelopes queued while no transport is up
+    pub outbox: OutboxConfig,
+    /// How the transport for each connection attempt is chosen
+    pub selector: SelectorConfig,
+    /// Whether transports are tried one after the other or raced
+    pub connect_mode: ConnectMode,
+    /// LoRa modem tried after 5G and Bluetooth (None = no LoRa)
+    pub lora: Option<LoraConfig>,
+    /// Tethered ground station on a serial port; when set it is the only
+    /// transport, for bench testing (None = radio links)
+    pub serial: Option<SerialConfig>,
+    /// Telemetry rate and detail on each transport
+    pub telemetry: TelemetryPolicies,
 }
 
 impl Default for ConnectionConfig {
     fn default() -> Self {
         Self {
-            device_id: "edge-001".into(),
+            device_id: "edge-001".parse().expect("valid default device ID"),
             server_5g: "127.0.0.1:8080".into(),
+            link_5g: FiveGLink::default(),
+            compression_5g: compression::supported(),
             bluetooth: BluetoothConfig::default(),
             reconnect_delay: Duration::from_secs(1),
             max_reconnect_delay: Duration::from_secs(30),
             connect_timeout: Duration::from_secs(5),
             read_timeout: Duration::from_secs(15), // > heartbeat timeout
-        }
-    }
-}
-
-/// A unified stream that can be either TCP or RFCOMM
-enum ConnectionStream {
-    Tcp(TcpStream),
-    Rfcomm(RfcommStream),
-}
-
-impl ConnectionStream {
-    /// Split the stream into read and write halves
-    fn into_split(self) -> (ConnectionReader, ConnectionWriter) {
-        match self {
-            ConnectionStream::Tcp(stream) => {
-                let (r, w) = stream.into_split();
-                (ConnectionReader::Tcp(r), ConnectionWriter::Tcp(w))
-            }
-            ConnectionStream::Rfcomm(stream) => {
-                let (r, w) = stream.into_split();
-                (ConnectionReader::Rfcomm(r), ConnectionWriter::Rfcomm(w))
-            }
-        }
-    }
-}
-
-/// Read half of a connection
-enum ConnectionReader {
-    Tcp(tokio::net::tcp::OwnedReadHalf),
-    Rfcomm(bluer::rfcomm::stream::OwnedReadHalf),
-}
-
-impl ConnectionReader {
-    async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        match self {
-            ConnectionReader::Tcp(r) => r.read(buf).await,
-            ConnectionReader::Rfcomm(r) => r.read(buf).await,
-        }
-    }
-}
-
-/// Write half of a connection
-enum ConnectionWriter {
-    Tcp(tokio::net::tcp::OwnedWriteHalf),
-    Rfcomm(bluer::rfcomm::stream::OwnedWriteHalf),
-}
-
-impl ConnectionWriter {
-    async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
-        match self {
-            ConnectionWriter::Tcp(w) => w.write_all(buf).await,
-            ConnectionWriter::Rfcomm(w) => w.write_all(buf).await,
+            safety_config: SafetyConfig::from_defaults(),
+            quality_history_len: QUALITY_HISTORY_LEN,
+            signer: None,
+            noise: None,
+            outbox: OutboxConfig::default(),
+            selector: SelectorConfig::default(),
+            connect_mode: ConnectMode::default(),
+            lora: None,
+            serial: None,
+            telemetry: TelemetryPolicies::default(),
         }
     }
 }
@@ -170,29 +194,73 @@ impl ConnectionWriter {
 pub struct ConnectionManager {
     config: ConnectionConfig,
     sequence_id: Arc<AtomicU64>,
-    /// Channel to send envelopes to the server
-    outbound_tx: mpsc::Sender<Envelope>,
+    /// Drone state reported in heartbeats until a provider is set
+    drone_state: Arc<RwLock<DroneState>>,
+    /// What heartbeats report
+    heartbeat: Arc<RwLock<Arc<dyn HeartbeatProvider>>>,
+    /// Told each time the server acknowledges a heartbeat
+    safety: Arc<RwLock<Option<Arc<SafetyMonitor>>>>,
+    /// Recent link quality samples
+    quality: Arc<RwLock<QualityHistory>>,
+    /// Chooses the transport for each connection attempt
+    selector: Arc<RwLock<TransportSelector>>,
+    /// Priority queue of envelopes for the server
+    outbound_tx: OutboundSender,
     /// Channel to receive connection events
     event_rx: mpsc::Receiver<ConnectionEvent>,
 }
 
 impl ConnectionManager {
-    /// Create a new connection manager and start the connection loop
+    /// Create a new connection manager over 5G and Bluetooth and start the
+    /// connection loop
     pub fn new(config: ConnectionConfig) -> Self {
-        let (outbound_tx, outbound_rx) = mpsc::channel::<Envelope>(100);
+        let connectors = default_connectors(&config);
+        Self::with_connectors(config, connectors)
+    }
+
+    /// Create a connection manager failing over across `connectors` and start
+    /// the connection loop
+    ///
+    /// `connectors` are in order of preference, which breaks ties between
+    /// equally scored transports; each should open a different `Transport`.
+    pub fn with_connectors(
+        config: ConnectionConfig,
+        connectors: Vec<Box<dyn TransportConnector>>,
+    ) -> Self {
+        assert!(!connectors.is_empty(), "at least one transport connector");
+        let (outbound_tx, outbound_rx) = outbound::channel(100);
         let (event_tx, event_rx) = mpsc::channel::<ConnectionEvent>(100);
         let sequence_id = Arc::new(AtomicU64::new(0));
+        let drone_state = Arc::new(RwLock::new(DroneState::DroneIdle));
+        let heartbeat: Arc<RwLock<Arc<dyn HeartbeatProvider>>> =
+            Arc::new(RwLock::new(drone_state.clone()));
+        let safety = Arc::new(RwLock::new(None));
+        let selector = Arc::new(RwLock::new(TransportSelector::new(config.selector.clone())));
+        let quality = Arc::new(RwLock::new(QualityHistory::new(config.quality_history_len)));
 
         // Spawn the connection loop
         let config_clone = config.clone();
-        let seq_clone = sequence_id.clone();
+        let link = LinkState {
+            sequence_id: sequence_id.clone(),
+            heartbeat: heartbeat.clone(),
+            safety: safety.clone(),
+            quality: quality.clone(),
+            stats: Arc::new(RwLock::new(HashMap::new())),
+            selector: selector.clone(),
+            session_token: RwLock::new(String::new()),
+        };
         tokio::spawn(async move {
-            connection_loop(config_clone, seq_clone, outbound_rx, event_tx).await;
+            connection_loop(config_clone, connectors, link, outbound_rx, event_tx).await;
         });
 
         Self {
             config,
             sequence_id,
+            drone_state,
+            heartbeat,
+            safety,
+            quality,
+            selector,
             outbound_tx,
             event_rx,
         }
@@ -203,7 +271,19 @@ impl ConnectionManager {
         self.sequence_id.fetch_add(1, Ordering::SeqCst) + 1
     }
 
+    /// Counter behind `next_sequence_id`, for other senders on this uplink
+    ///
+    /// Everything the drone sends should number from it, so the server sees
+    /// one sequence and can spot gaps and duplicates.
+    pub fn sequence_ids(&self) -> Arc<AtomicU64> {
+        self.sequence_id.clone()
+    }
+
     /// Send an envelope to the server
+    ///
+    /// All outbound traffic (including heartbeats) is written by the single
+    /// connection task. Envelopes of the same header priority reach the wire
+    /// in the order they were queued; higher priorities go first.
     pub async fn send(&self, envelope: Envelope) -> Result<()> {
         self.outbound_tx
             .send(envelope)
@@ -217,42 +297,126 @@ impl ConnectionManager {
     }
 
     /// Get the device ID
-    pub fn device_id(&self) -> &str {
+    pub fn device_id(&self) -> &DeviceId {
         &self.config.device_id
     }
 
+    /// Shared drone state reported to the server in heartbeats
+    pub fn drone_state(&self) -> Arc<RwLock<DroneState>> {
+        self.drone_state.clone()
+    }
+
+    /// Report what `provider` gives in heartbeats instead of the bare state
+    pub async fn set_heartbeat_provider(&self, provider: Arc<dyn HeartbeatProvider>) {
+        *self.heartbeat.write().await = provider;
+    }
+
+    /// Keep `monitor` informed that the server is alive
+    ///
+    /// Each `HeartbeatAck` resets its server heartbeat timer straight from the
+    /// connection task, so a lost server triggers RTH after
+    /// `HEARTBEAT_TIMEOUT_MS` even while the connection stays open.
+    pub async fn set_safety_monitor(&self, monitor: Arc<SafetyMonitor>) {
+        *self.safety.write().await = Some(monitor);
+    }
+
+    /// Snapshot of recent link quality samples (for plotting and trends)
+    pub async fn quality_history(&self) -> QualityHistory {
+        self.quality.read().await.clone()
+    }
+
+    /// Recent transport choices, oldest first
+    pub async fn transport_decisions(&self) -> Vec<TransportDecision> {
+        self.selector.read().await.decisions()
+    }
+
+    /// Latest measured link quality, if any heartbeat reply arrived yet
+    pub async fn latest_quality(&self) -> Option<ConnectionQuality> {
+        self.quality.read().await.latest().map(|s| s.quality)
+    }
+
     /// Get a clone of the sender for outbound messages
-    pub fn get_sender(&self) -> mpsc::Sender<Envelope> {
+    pub fn get_sender(&self) -> OutboundSender {
         self.outbound_tx.clone()
     }
 }
 
-/// Connect via Bluetooth (either RFCOMM or TCP simulation)
-async fn connect_bluetooth(config: &BluetoothConfig) -> Result<ConnectionStream> {
-    match config.mode {
+/// Connectors for the 5G server, the Bluetooth relay and any LoRa modem in
+/// `config`, in that order, or just the serial tether if one is set
+pub fn default_connectors(config: &ConnectionConfig) -> Vec<Box<dyn TransportConnector>> {
+    if let Some(serial) = &config.serial {
+        return vec![Box::new(SerialConnector::new(serial.clone()))];
+    }
+    let bluetooth: Box<dyn TransportConnector> = match config.bluetooth.mode {
         BluetoothMode::TcpSimulation => {
-            let stream = TcpStream::connect(&config.tcp_address).await?;
-            Ok(ConnectionStream::Tcp(stream))
+            Box::new(TcpConnector::new_relay(config.bluetooth.tcp_address.clone()))
         }
         BluetoothMode::Rfcomm => {
-            let addr = config
-                .relay_address
-                .as_ref()
-                .ok_or_else(|| anyhow!("No relay address configured for RFCOMM mode"))?;
+            let relay_address = config.bluetooth.relay_address.as_ref().and_then(|addr| {
+                let parsed = addr.parse::<BtAddress>().ok();
+                if parsed.is_none() {
+                    eprintln!("[BT] Invalid Bluetooth address {}, discovering relays instead", addr);
+                }
+                parsed
+            });
+            Box::new(RfcommConnector::new(RfcommConfig {
+                relay_address,
+                channel: config.bluetooth.channel,
+                ..Default::default()
+            }))
+        }
+    };
+    let five_g: Box<dyn TransportConnector> = match &config.link_5g {
+        FiveGLink::Tcp => Box::new(TcpConnector::new_5g(config.server_5g.clone())),
+        FiveGLink::Udp => Box::new(UdpConnector::new_5g(config.server_5g.clone())),
+        FiveGLink::Quic(quic) => Box::new(QuicConnector::new(quic.clone())),
+        FiveGLink::WebSocket(url) => Box::new(WebSocketConnector::new_5g(url.clone())),
+    };
+    let mut connectors = vec![five_g, bluetooth];
+    if let Some(lora) = &config.lora {
+        connectors.push(Box::new(LoraConnector::new(lora.clone())));
+    }
+    connectors
+}
 
-            let bt_addr: BtAddress = addr
-                .parse()
-                .map_err(|_| anyhow!("Invalid Bluetooth address: {}", addr))?;
+/// State shared between the manager and its connection task
+struct LinkState {
+    sequence_id: Arc<AtomicU64>,
+    /// What heartbeats report
+    heartbeat: Arc<RwLock<Arc<dyn HeartbeatProvider>>>,
+    /// Told each time the server acknowledges a heartbeat
+    safety: Arc<RwLock<Option<Arc<SafetyMonitor>>>>,
+    /// Link quality measured from heartbeat round trips
+    quality: Arc<RwLock<QualityHistory>>,
+    /// Round trip, jitter and loss per transport, kept across reconnects
+    stats: Arc<RwLock<HashMap<Transport, LinkStats>>>,
+    /// Chooses the transport for each connection attempt
+    selector: Arc<RwLock<TransportSelector>>,
+    /// Token of the server session, sent in the hello to resume it after a
+    /// reconnect or transport switch
+    session_token: RwLock<String>,
+}
 
-            let socket_addr = RfcommAddr::new(bt_addr, config.channel);
-            println!("[BT] Connecting via RFCOMM to {} channel {}", bt_addr, config.channel);
+impl LinkState {
+    /// Record a heartbeat sent over `transport`, for loss accounting
+    async fn heartbeat_sent(&self, transport: Transport, sequence_id: u64) {
+        self.stats.write().await.entry(transport).or_default().sent(sequence_id);
+    }
 
-            let stream = RfcommStream::connect(socket_addr)
-                .await
-                .map_err(|e| anyhow!("RFCOMM connect failed: {}", e))?;
+    /// Record the ack of a heartbeat and take a link quality sample
+    async fn heartbeat_acked(&self, transport: Transport, sequence_id: u64, rtt_ms: Option<u32>) {
+        let mut stats = self.stats.write().await;
+        let stats = stats.entry(transport).or_default();
+        if stats.acked(sequence_id, rtt_ms) {
+            let quality = stats.quality(transport.into());
+            self.quality.write().await.push(now_ms(), quality);
+        }
+    }
 
-            println!("[BT] Connected via RFCOMM to {}", bt_addr);
-            Ok(ConnectionStream::Rfcomm(stream))
+    /// The server acknowledged a heartbeat: it is still there
+    async fn server_alive(&self) {
+        if let Some(monitor) = &*self.safety.read().await {
+            monitor.update_server_heartbeat().await;
         }
     }
 }
@@ -260,36 +424,72 @@ async fn connect_bluetooth(config: &BluetoothConfig) -> Result<ConnectionStream>
 /// Main connection loop with reconnection logic
 async fn connection_loop(
     config: ConnectionConfig,
-    sequence_id: Arc<AtomicU64>,
-    mut outbound_rx: mpsc::Receiver<Envelope>,
+    connectors: Vec<Box<dyn TransportConnector>>,
+    link: LinkState,
+    mut outbound_rx: OutboundReceiver,
     event_tx: mpsc::Sender<ConnectionEvent>,
 ) {
-    let mut current_transport = Transport::FiveG;
     let mut reconnect_delay = config.reconnect_delay;
+    let mut previous_transport: Option<Transport> = None;
+    // Transports that failed this cycle; the rest are tried before backing off
+    let mut failed: Vec<Transport> = Vec::new();
+    let mut outbox = Outbox::open(config.outbox.clone()).unwrap_or_else(|e| {
+        eprintln!("[OUTBOX] Can't open journal ({}), keeping envelopes in memory only", e);
+        Outbox::open(OutboxConfig {
+            path: None,
+            ..config.outbox.clone()
+        })
+        .expect("in-memory outbox opens")
+    });
 
     loop {
+        let candidates: Vec<Transport> = connectors
+            .iter()
+            .map(|c| c.transport())
+            .filter(|t| !failed.contains(t))
+            .collect();
+        let decision = {
+            let stats = link.stats.read().await;
+            link.selector.write().await.select(&candidates, &stats, now_ms())
+        };
+        // Only race when the selector had a free choice
+        let race = config.connect_mode == ConnectMode::Race
+            && decision.reason == DecisionReason::BestScore;
+        let selected = decision.transport;
+        let contenders: Vec<&dyn TransportConnector> = connectors
+            .iter()
+            .map(|c| c.as_ref())
+            .filter(|c| match race {
+                true => decision.scores.iter().any(|(t, _)| *t == c.transport()),
+                false => c.transport() == selected,
+            })
+            .collect();
+        let _ = event_tx.send(ConnectionEvent::TransportSelected(decision)).await;
+        if let Some(from) = previous_transport.filter(|&from| !race && from != selected) {
+            let _ = event_tx
+                .send(ConnectionEvent::TransportSwitched { from, to: selected })
+                .await;
+        }
+
         // Try to connect
-        let connect_result: Result<ConnectionStream> = match current_transport {
-            Transport::FiveG => {
-                match timeout(config.connect_timeout, TcpStream::connect(&config.server_5g)).await {
-                    Ok(Ok(stream)) => Ok(ConnectionStream::Tcp(stream)),
-                    Ok(Err(e)) => Err(anyhow!("5G connection failed: {}", e)),
-                    Err(_) => Err(anyhow!("5G connection timeout")),
-                }
-            }
-            Transport::Bluetooth => {
-                match timeout(config.connect_timeout, connect_bluetooth(&config.bluetooth)).await {
-                    Ok(Ok(stream)) => Ok(stream),
-                    Ok(Err(e)) => Err(anyhow!("Bluetooth connection failed: {}", e)),
-                    Err(_) => Err(anyhow!("Bluetooth connection timeout")),
-                }
+        let (current_transport, connect_result) = if race {
+            let (winner, result) = race_connect(&contenders, &config, &link).await;
+            if let Some(from) = previous_transport.filter(|&from| result.is_ok() && from != winner) {
+                let _ = event_tx
+                    .send(ConnectionEvent::TransportSwitched { from, to: winner })
+                    .await;
             }
+            (winner, result)
+        } else {
+            (selected, connect(contenders[0], &config, &link).await)
         };
+        previous_transport = Some(current_transport);
 
         match connect_result {
             Ok(stream) => {
                 // Connected successfully
                 reconnect_delay = config.reconnect_delay; // Reset delay
+                failed.clear();
 
                 let _ = event_tx
                     .send(ConnectionEvent::Connected {
@@ -298,15 +498,18 @@ async fn connection_loop(
                     .await;
 
                 // Run the connection handler
-                if let Err(reason) = handle_connection(
+                let result = handle_connection(
                     stream,
+                    current_transport,
                     &config,
-                    &sequence_id,
+                    &link,
                     &mut outbound_rx,
+                    &mut outbox,
                     &event_tx,
                 )
-                .await
-                {
+                .await;
+                link.selector.write().await.record_drop(current_transport, now_ms());
+                if let Err(reason) = result {
                     let _ = event_tx
                         .send(ConnectionEvent::Disconnected {
                             reason: reason.to_string(),
@@ -315,54 +518,206 @@ async fn connection_loop(
                 }
             }
             Err(e) => {
-                // Connection failed, try fallback
-                if current_transport == Transport::FiveG {
-                    let _ = event_tx
-                        .send(ConnectionEvent::TransportSwitched {
-                            from: Transport::FiveG,
-                            to: Transport::Bluetooth,
-                        })
-                        .await;
-                    current_transport = Transport::Bluetooth;
-                    continue; // Try Bluetooth immediately
-                } else {
-                    // Both transports failed
-                    let _ = event_tx
-                        .send(ConnectionEvent::ConnectionFailed {
-                            reason: format!("All transports failed: {}", e),
-                        })
-                        .await;
+                // Connection failed: try the remaining transports before backing off
+                failed.extend(contenders.iter().map(|c| c.transport()));
+                let selector = link.selector.read().await;
+                let untried = connectors
+                    .iter()
+                    .map(|c| c.transport())
+                    .any(|t| !failed.contains(&t) && selector.is_available(t));
+                drop(selector);
+                if untried {
+                    continue; // Try the next transport immediately
                 }
+                failed.clear();
+                let _ = event_tx
+                    .send(ConnectionEvent::ConnectionFailed {
+                        reason: format!("All transports failed: {}", e),
+                    })
+                    .await;
             }
         }
 
-        // Wait before reconnecting
-        tokio::time::sleep(reconnect_delay).await;
+        // Wait before reconnecting, moving anything queued meanwhile into the outbox
+        let wait = tokio::time::sleep(reconnect_delay);
+        tokio::pin!(wait);
+        loop {
+            tokio::select! {
+                _ = &mut wait => break,
+                Some(envelope) = outbound_rx.recv() => store(&mut outbox, envelope),
+            }
+        }
 
         // Exponential backoff
         reconnect_delay = std::cmp::min(reconnect_delay * 2, config.max_reconnect_delay);
+    }
+}
+
+/// Connect with `connector`, including the Noise handshake if configured
+///
+/// The outcome counts towards the transport's success rate; a connect
+/// dropped before it finishes (a lost race) does not.
+async fn connect(
+    connector: &dyn TransportConnector,
+    config: &ConnectionConfig,
+    link: &LinkState,
+) -> Result<Box<dyn TransportStream>> {
+    let transport = connector.transport();
+    let result = match timeout(config.connect_timeout, connector.connect()).await {
+        Ok(Ok(stream)) => Ok(stream),
+        Ok(Err(e)) => {
+            if matches!(e.downcast_ref(), Some(AdapterError::NoAdapter)) {
+                println!("[CONN] No {} adapter present, not trying it again", transport);
+                link.selector.write().await.mark_unavailable(transport);
+            }
+            Err(anyhow!("{} connection failed: {}", transport, e))
+        }
+        Err(_) => Err(anyhow!("{} connection timeout", transport)),
+    };
+
+    // A failed handshake counts as a failed connection, so the next transport is tried
+    let result = match (result, &config.noise) {
+        (Ok(stream), Some(noise)) => {
+            match timeout(config.connect_timeout, NoiseStream::initiate(stream, noise)).await {
+                Ok(Ok(stream)) => {
+                    println!("[CONN] Link encrypted end to end with {}", stream.peer());
+                    Ok(Box::new(stream) as Box<dyn TransportStream>)
+                }
+                Ok(Err(e)) => Err(anyhow!("{} handshake failed: {}", transport, e)),
+                Err(_) => Err(anyhow!("{} handshake timeout", transport)),
+            }
+        }
+        (result, _) => result,
+    };
+
+    link.selector.write().await.record_attempt(transport, result.is_ok());
+    result
+}
 
-        // Reset to primary transport for next attempt
-        current_transport = Transport::FiveG;
+/// Connect with all `connectors` at once and keep whichever is up first
+///
+/// The losers are dropped wherever they got to, which closes their sockets.
+/// A failure leaves the race to the others; when all fail the error names
+/// each. Returns the transport the result belongs to.
+async fn race_connect(
+    connectors: &[&dyn TransportConnector],
+    config: &ConnectionConfig,
+    link: &LinkState,
+) -> (Transport, Result<Box<dyn TransportStream>>) {
+    let mut attempts: FuturesUnordered<_> = connectors
+        .iter()
+        .map(|&connector| async move {
+            (connector.transport(), connect(connector, config, link).await)
+        })
+        .collect();
+
+    let mut errors = Vec::new();
+    let mut last = connectors[0].transport();
+    while let Some((transport, result)) = attempts.next().await {
+        match result {
+            Ok(stream) => {
+                println!("[CONN] {} won the connect race", transport);
+                return (transport, Ok(stream));
+            }
+            Err(e) => {
+                errors.push(e.to_string());
+                last = transport;
+            }
+        }
+    }
+    (last, Err(anyhow!("{}", errors.join("; "))))
+}
+
+/// Sign an outgoing envelope if signing is on and the policy covers it
+fn sign(config: &ConnectionConfig, envelope: &mut Envelope) {
+    if let Some(signer) = &config.signer {
+        signer.sign(envelope);
+    }
+}
+
+/// Park an envelope in the outbox until a transport is back
+fn store(outbox: &mut Outbox, envelope: Envelope) {
+    if let Err(e) = outbox.push(envelope) {
+        eprintln!("[OUTBOX] Failed to journal envelope: {}", e);
     }
 }
 
 /// Handle an active connection
 async fn handle_connection(
-    stream: ConnectionStream,
+    stream: Box<dyn TransportStream>,
+    transport: Transport,
     config: &ConnectionConfig,
-    sequence_id: &Arc<AtomicU64>,
-    outbound_rx: &mut mpsc::Receiver<Envelope>,
+    link: &LinkState,
+    outbound_rx: &mut OutboundReceiver,
+    outbox: &mut Outbox,
     event_tx: &mpsc::Sender<ConnectionEvent>,
 ) -> Result<()> {
-    let (mut reader, mut writer) = stream.into_split();
-
-    let mut decoder = FrameDecoder::new();
-    let mut read_buf = vec![0u8; 4096];
+    let (reader, writer) = tokio::io::split(stream);
+    let mut reader = FramedRead::new(reader, EnvelopeCodec::new());
+    let mut writer = FramedWrite::new(writer, EnvelopeCodec::new());
+    let sequence_id = &link.sequence_id;
+
+    let mut unknown_reported = false;
+    // The server numbers every session from one counter, so gaps are normal
+    // here; only duplicates matter
+    let mut server_sequence = SequenceTracker::new();
+
+    // Frames go out uncompressed until the server's hello tells us what it supports
+    let offered = match transport {
+        Transport::Bluetooth => &config.bluetooth.compression,
+        _ => &config.compression_5g,
+    };
+
+    // Introduce ourselves before anything else so the server knows our
+    // failsafes, and can pick up where the previous connection left off
+    let seq = sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
+    let token = link.session_token.read().await.clone();
+    let mut hello = Envelope {
+        header: Some(Header::new(&config.device_id, MessageType::MsgHello, seq)),
+        payload: Some(resqterra_shared::envelope::Payload::Hello(
+            Hello::new(config.safety_config)
+                .with_compression(offered)
+                .with_session_token(token),
+        )),
+        signature: Vec::new(),
+    };
+    sign(config, &mut hello);
+    writer.send(hello).await?;
+
+    // Replay what queued up while offline, oldest first. Each gets a fresh
+    // sequence ID since the server checks for duplicates per session; ACKs
+    // name their command in the payload, so nothing else refers to the old one
+    let expired = outbox.expire(now_ms());
+    if expired > 0 {
+        println!("[OUTBOX] Dropped {} envelopes too old to replay", expired);
+    }
+    let pending = outbox.len();
+    let mut replayed = Ok(());
+    while let Some(envelope) = outbox.front() {
+        let mut envelope = envelope.clone();
+        if let Some(header) = &mut envelope.header {
+            header.sequence_id = sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
+        }
+        sign(config, &mut envelope);
+        if let Err(e) = writer.send(envelope).await {
+            replayed = Err(e);
+            break;
+        }
+        outbox.pop_front();
+    }
+    if let Err(e) = outbox.commit() {
+        eprintln!("[OUTBOX] Failed to update journal: {}", e);
+    }
+    replayed?;
+    if pending > 0 {
+        println!("[OUTBOX] Replayed {} envelopes over {}", pending, transport);
+    }
 
     // Heartbeat interval
     let mut heartbeat_interval = interval(Duration::from_millis(safety::HEARTBEAT_INTERVAL_MS));
     let start_time = Instant::now();
+    // Servers before `HeartbeatAck` answer with a bare heartbeat, timed from here
+    let mut heartbeat_sent_at: Option<(u64, Instant)> = None;
 
     loop {
         tokio::select! {
@@ -370,39 +725,93 @@ async fn handle_connection(
             _ = heartbeat_interval.tick() => {
                 let seq = sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
                 let uptime_ms = start_time.elapsed().as_millis() as u64;
+                let provider = link.heartbeat.read().await.clone();
 
-                let envelope = Envelope {
+                let mut envelope = Envelope {
                     header: Some(Header::new(&config.device_id, MessageType::MsgHeartbeat, seq)),
                     payload: Some(resqterra_shared::envelope::Payload::Heartbeat(
-                        Heartbeat::new(uptime_ms, DroneState::DroneIdle, 0, true),
+                        provider.heartbeat(uptime_ms).await,
                     )),
+                    signature: Vec::new(),
                 };
+                sign(config, &mut envelope);
 
-                let encoded = codec::encode(&envelope)?;
-                writer.write_all(&encoded).await?;
+                writer.send(envelope).await?;
+                heartbeat_sent_at = Some((seq, Instant::now()));
+                link.heartbeat_sent(transport, seq).await;
             }
 
             // Send outbound messages
             Some(envelope) = outbound_rx.recv() => {
-                let encoded = codec::encode(&envelope)?;
-                writer.write_all(&encoded).await?;
+                let mut signed = envelope.clone();
+                sign(config, &mut signed);
+                if let Err(e) = writer.send(signed).await {
+                    store(outbox, envelope);
+                    return Err(e.into());
+                }
             }
 
             // Read incoming messages
-            result = timeout(config.read_timeout, reader.read(&mut read_buf)) => {
+            result = timeout(config.read_timeout, reader.next()) => {
                 match result {
-                    Ok(Ok(0)) => {
+                    Ok(None) => {
                         return Err(anyhow!("Server closed connection"));
                     }
-                    Ok(Ok(n)) => {
-                        decoder.extend(&read_buf[..n]);
-
-                        // Process all complete frames
-                        while let Ok(Some(envelope)) = decoder.decode_next() {
-                            let _ = event_tx.send(ConnectionEvent::Received(envelope)).await;
+                    Ok(Some(Ok(envelope))) => {
+                        if let Err(e) = envelope.validate() {
+                            eprintln!("[CONN] Rejected invalid envelope from server: {}", e);
+                            continue;
+                        }
+                        if let Some(signer) = &config.signer {
+                            let encoded = reader.decoder().last_envelope();
+                            if let Err(e) = signer.verify_encoded(&envelope, encoded) {
+                                eprintln!("[CONN] Rejected message from server: {}", e);
+                                continue;
+                            }
+                        }
+                        let seq = envelope.header.as_ref().map_or(0, |h| h.sequence_id);
+                        if server_sequence.observe(seq) == SequenceEvent::Duplicate {
+                            println!("[CONN] Dropped duplicate sequence {} from server", seq);
+                            continue;
+                        }
+                        if !unknown_reported && reader.decoder().unknown_bytes() > 0 {
+                            unknown_reported = true;
+                            println!("[CONN] Server sent fields we don't know (newer protocol?), ignoring them");
+                        }
+                        if let Some(resqterra_shared::envelope::Payload::Hello(hello)) = &envelope.payload {
+                            let compression = compression::negotiate(offered, &hello.compression);
+                            writer.encoder_mut().set_compression(compression);
+                            println!(
+                                "[CONN] Server protocol v{}, frame compression over {}: {:?}",
+                                hello.protocol_version, transport, compression
+                            );
+                            if hello.session_resumed {
+                                println!("[CONN] Session resumed over {}", transport);
+                            }
+                            // Servers before session resumption send no token
+                            if !hello.session_token.is_empty() {
+                                *link.session_token.write().await = hello.session_token.clone();
+                            }
+                            continue;
                         }
+                        if let Some(resqterra_shared::envelope::Payload::HeartbeatAck(ack)) = &envelope.payload {
+                            // The ack echoes the heartbeat's timestamp: the round trip needs no lookup
+                            let rtt_ms = (ack.heartbeat_timestamp_ms > 0)
+                                .then(|| now_ms().saturating_sub(ack.heartbeat_timestamp_ms) as u32);
+                            link.heartbeat_acked(transport, ack.heartbeat_sequence_id, rtt_ms).await;
+                            link.server_alive().await;
+                            continue;
+                        }
+                        // Servers before `HeartbeatAck` answer with a heartbeat
+                        if matches!(envelope.payload, Some(resqterra_shared::envelope::Payload::Heartbeat(_))) {
+                            if let Some((seq, sent_at)) = heartbeat_sent_at.take() {
+                                let rtt_ms = sent_at.elapsed().as_millis() as u32;
+                                link.heartbeat_acked(transport, seq, Some(rtt_ms)).await;
+                            }
+                        }
+                        let _ = event_tx.send(ConnectionEvent::Received(Box::new(envelope))).await;
                     }
-                    Ok(Err(e)) => {
+                    Ok(Some(Err(e))) => {
                         return Err(anyhow!("Read error: {}", e));
                     }
                     Err(_) => {
@@ -414,3 +823,122 @@ async fn handle_connection(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn link_state() -> LinkState {
+        LinkState {
+            sequence_id: Arc::new(AtomicU64::new(0)),
+            heartbeat: Arc::new(RwLock::new(Arc::new(RwLock::new(DroneState::DroneIdle)))),
+            safety: Arc::new(RwLock::new(None)),
+            quality: Arc::new(RwLock::new(QualityHistory::new(QUALITY_HISTORY_LEN))),
+            stats: Arc::new(RwLock::new(HashMap::new())),
+            selector: Arc::new(RwLock::new(TransportSelector::new(SelectorConfig::default()))),
+            session_token: RwLock::new(String::new()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_race_connect_takes_first_transport_up() {
+        let relay = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // Nothing listens on the 5G address once the probe listener is gone
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_5g = closed.local_addr().unwrap().to_string();
+        drop(closed);
+
+        let config = ConnectionConfig {
+            server_5g,
+            bluetooth: BluetoothConfig {
+                tcp_address: relay.local_addr().unwrap().to_string(),
+                ..Default::default()
+            },
+            connect_timeout: Duration::from_secs(2),
+            connect_mode: ConnectMode::Race,
+            ..Default::default()
+        };
+        let link = link_state();
+        let connectors = default_connectors(&config);
+        let contenders: Vec<&dyn TransportConnector> = connectors.iter().map(|c| c.as_ref()).collect();
+
+        let (transport, result) = race_connect(&contenders, &config, &link).await;
+        assert_eq!(transport, Transport::Bluetooth);
+        assert!(result.is_ok());
+        let selector = link.selector.read().await;
+        assert_eq!(selector.success_rate(Transport::FiveG), 0.0);
+        assert_eq!(selector.success_rate(Transport::Bluetooth), 1.0);
+        drop(selector);
+
+        // Both down: the error names both
+        drop(relay);
+        let config = ConnectionConfig {
+            bluetooth: BluetoothConfig {
+                tcp_address: config.server_5g.clone(),
+                ..Default::default()
+            },
+            ..config
+        };
+        let connectors = default_connectors(&config);
+        let contenders: Vec<&dyn TransportConnector> = connectors.iter().map(|c| c.as_ref()).collect();
+        let (_, result) = race_connect(&contenders, &config, &link).await;
+        let error = result.err().unwrap().to_string();
+        assert!(error.contains("5G") && error.contains("Bluetooth"), "{}", error);
+    }
+
+    /// A link the manager knows nothing about, over loopback TCP
+    struct SatelliteModem(String);
+
+    #[async_trait::async_trait]
+    impl TransportConnector for SatelliteModem {
+        async fn connect(&self) -> Result<Box<dyn TransportStream>> {
+            let stream = tokio::net::TcpStream::connect(&self.0).await?;
+            Ok(Box::new(crate::transport::TcpTransportStream::new(stream)))
+        }
+
+        fn name(&self) -> &'static str {
+            "Satellite"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_to_plugged_in_transport() {
+        let modem = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_5g = closed.local_addr().unwrap().to_string();
+        drop(closed);
+
+        let connectors: Vec<Box<dyn TransportConnector>> = vec![
+            Box::new(TcpConnector::new_5g(server_5g)),
+            Box::new(SatelliteModem(modem.local_addr().unwrap().to_string())),
+        ];
+        let config = ConnectionConfig {
+            connect_timeout: Duration::from_secs(2),
+            ..Default::default()
+        };
+        let mut manager = ConnectionManager::with_connectors(config, connectors);
+
+        let connected = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(ConnectionEvent::Connected { transport }) = manager.recv().await {
+                    return transport;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(connected, Transport::Other("Satellite"));
+        assert_eq!(connected.to_string(), "Satellite");
+        let decisions = manager.transport_decisions().await;
+        assert_eq!(decisions[0].transport, Transport::FiveG);
+        assert_eq!(decisions[1].transport, Transport::Other("Satellite"));
+    }
+
+    #[test]
+    fn test_connect_mode_from_str() {
+        assert_eq!("race".parse::<ConnectMode>().unwrap(), ConnectMode::Race);
+        assert_eq!(" Sequential".parse::<ConnectMode>().unwrap(), ConnectMode::Sequential);
+        assert!("parallel".parse::<ConnectMode>().is_err());
+    }
+}
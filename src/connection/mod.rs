@@ -2,13 +2,30 @@
 //!
 //! This module handles:
 //! - Persistent TCP connections with automatic reconnection
-//! - Transport failover (5G primary, Bluetooth fallback)
+//! - Failover across pluggable transports (`TransportConnector`), selected
+//!   by link quality (5G preferred, Bluetooth fallback)
+//! - Optional racing connect over all transports at once
 //! - Bidirectional message streaming
 //! - Heartbeat management
+//! - Priority lanes for outbound envelopes
+//! - Store-and-forward outbox for offline periods
+//! - Telemetry rate and detail per transport
 
+mod heartbeat;
 mod manager;
+mod outbound;
+mod outbox;
+mod selector;
+mod telemetry_policy;
 
+pub use heartbeat::HeartbeatProvider;
 pub use manager::{
-    BluetoothConfig, BluetoothMode, ConnectionConfig, ConnectionEvent, ConnectionManager,
-    Transport,
+    BluetoothConfig, BluetoothMode, ConnectMode, ConnectionConfig, ConnectionEvent,
+    ConnectionManager, FiveGLink,
 };
+pub use crate::transport::Transport;
+pub use outbound::OutboundSender;
+#[cfg(test)]
+pub use outbound::channel as outbound_channel;
+pub use outbox::OutboxConfig;
+pub use telemetry_policy::{TelemetryPolicies, TelemetryPolicy};
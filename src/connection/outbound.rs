@@ -0,0 +1,102 @@
+//! Priority lanes for envelopes queued to the server
+//!
+//! Each `priority` level gets its own bounded lane and the connection task
+//! always takes from the most urgent non-empty one, so an emergency stop ACK
+//! doesn't wait behind telemetry on a slow Bluetooth link. Envelopes of the
+//! same priority keep their order.
+
+use resqterra_shared::{priority, Envelope};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::SendError;
+
+/// One lane per level, `priority::LOW` through `priority::EMERGENCY`
+const LANES: usize = priority::EMERGENCY as usize + 1;
+
+/// Create a prioritized outbound queue holding up to `capacity` envelopes per lane
+pub fn channel(capacity: usize) -> (OutboundSender, OutboundReceiver) {
+    let mut receivers = Vec::with_capacity(LANES);
+    let lanes = std::array::from_fn(|_| {
+        let (tx, rx) = mpsc::channel(capacity);
+        receivers.push(rx);
+        tx
+    });
+    let receivers = receivers.try_into().expect("one receiver per lane");
+    (OutboundSender { lanes }, OutboundReceiver { lanes: receivers })
+}
+
+/// Queues envelopes for the server by their header priority
+#[derive(Debug, Clone)]
+pub struct OutboundSender {
+    lanes: [mpsc::Sender<Envelope>; LANES],
+}
+
+impl OutboundSender {
+    /// Queue an envelope, waiting while its lane is full
+    pub async fn send(&self, envelope: Envelope) -> Result<(), SendError<Envelope>> {
+        self.lanes[envelope.priority() as usize].send(envelope).await
+    }
+}
+
+/// Hands out queued envelopes, most urgent first
+#[derive(Debug)]
+pub struct OutboundReceiver {
+    lanes: [mpsc::Receiver<Envelope>; LANES],
+}
+
+impl OutboundReceiver {
+    /// Next envelope from the highest-priority non-empty lane
+    ///
+    /// Returns `None` once every sender is dropped and the lanes are drained.
+    /// Cancel safe, so it can sit in a `select!`.
+    pub async fn recv(&mut self) -> Option<Envelope> {
+        let [low, normal, high, emergency] = &mut self.lanes;
+        tokio::select! {
+            biased;
+            Some(envelope) = emergency.recv() => Some(envelope),
+            Some(envelope) = high.recv() => Some(envelope),
+            Some(envelope) = normal.recv() => Some(envelope),
+            Some(envelope) = low.recv() => Some(envelope),
+            else => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::{Header, MessageType};
+
+    fn envelope(sequence_id: u64, priority: u32) -> Envelope {
+        Envelope {
+            header: Some(
+                Header::new("edge-001", MessageType::MsgTelemetry, sequence_id)
+                    .with_priority(priority),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn sequence_id(envelope: Option<Envelope>) -> u64 {
+        envelope.unwrap().header.unwrap().sequence_id
+    }
+
+    #[tokio::test]
+    async fn test_urgent_envelopes_jump_queued_telemetry() {
+        let (tx, mut rx) = channel(8);
+        for seq in 1..=3 {
+            tx.send(envelope(seq, priority::LOW)).await.unwrap();
+        }
+        tx.send(envelope(4, priority::NORMAL)).await.unwrap();
+        tx.send(envelope(5, priority::EMERGENCY)).await.unwrap();
+        tx.send(envelope(6, priority::HIGH)).await.unwrap();
+
+        let mut order = Vec::new();
+        for _ in 0..6 {
+            order.push(sequence_id(rx.recv().await));
+        }
+        assert_eq!(order, vec![5, 6, 4, 1, 2, 3]);
+
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,296 @@
+//! Store-and-forward outbox for envelopes queued while offline
+//!
+//! When neither 5G nor the relay is reachable the connection task moves
+//! everything queued for the server into the outbox instead of letting the
+//! priority lanes fill up and stall senders. On reconnect the outbox is
+//! replayed oldest first, right after the hello.
+//!
+//! With a `path` set, envelopes are journaled to disk as CRC-checked frames so
+//! they survive a restart; a torn or corrupt tail is dropped on load. The
+//! outbox is bounded by `max_bytes` (the oldest envelope of the lowest
+//! priority goes first, so command ACKs outlive telemetry) and `max_age`
+//! (judged by each header's `timestamp_ms`).
+
+use anyhow::Result;
+use resqterra_shared::codec::{self, FrameDecoder};
+use resqterra_shared::{now_ms, Compression, Envelope};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Outbox limits and where to journal it
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+    /// Journal file (None = kept in memory only, lost on restart)
+    pub path: Option<PathBuf>,
+    /// Encoded bytes kept before evicting
+    pub max_bytes: usize,
+    /// Envelopes older than this are dropped instead of replayed
+    pub max_age: Duration,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_bytes: 1024 * 1024,
+            max_age: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// Envelopes waiting for a link, oldest first
+#[derive(Debug)]
+pub struct Outbox {
+    config: OutboxConfig,
+    /// Queued envelopes and their journaled frame sizes
+    entries: VecDeque<(Envelope, usize)>,
+    /// Total of the frame sizes
+    bytes: usize,
+    /// Open journal, appended to on push
+    journal: Option<File>,
+    /// Entries were removed since the journal was last rewritten
+    dirty: bool,
+}
+
+impl Outbox {
+    /// Open the outbox, reloading envelopes journaled before a restart
+    pub fn open(config: OutboxConfig) -> Result<Self> {
+        let mut outbox = Self {
+            config,
+            entries: VecDeque::new(),
+            bytes: 0,
+            journal: None,
+            dirty: false,
+        };
+        let Some(path) = outbox.config.path.clone() else {
+            return Ok(outbox);
+        };
+
+        if let Ok(data) = fs::read(&path) {
+            let mut decoder = FrameDecoder::new();
+            decoder.extend(&data);
+            // Stop at the first frame that doesn't decode: a write torn by power loss
+            let mut remaining = decoder.buffer_len();
+            while let Ok(Some(envelope)) = decoder.decode_next() {
+                let size = remaining - decoder.buffer_len();
+                remaining = decoder.buffer_len();
+                outbox.bytes += size;
+                outbox.entries.push_back((envelope, size));
+            }
+            if decoder.buffer_len() > 0 || decoder.corrupt_frames() > 0 {
+                println!("[OUTBOX] Dropped a damaged tail from {}", path.display());
+            }
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        outbox.expire(now_ms());
+        outbox.rewrite()?;
+        if !outbox.is_empty() {
+            println!("[OUTBOX] {} envelopes waiting from before restart", outbox.len());
+        }
+        Ok(outbox)
+    }
+
+    /// Queue an envelope, evicting others if the outbox is over its limits
+    pub fn push(&mut self, envelope: Envelope) -> Result<()> {
+        let frame = codec::encode_frame(&envelope, Compression::None, true)?;
+        self.bytes += frame.len();
+        self.entries.push_back((envelope, frame.len()));
+
+        let evicted = self.evict();
+        if evicted > 0 {
+            println!("[OUTBOX] Full, dropped {} lowest-priority envelopes", evicted);
+        }
+        if self.dirty {
+            return self.rewrite();
+        }
+        if let Some(journal) = &mut self.journal {
+            if let Err(e) = journal.write_all(&frame) {
+                // Kept in memory; the next commit retries the whole journal
+                self.dirty = true;
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Oldest envelope, still queued
+    pub fn front(&self) -> Option<&Envelope> {
+        self.entries.front().map(|(envelope, _)| envelope)
+    }
+
+    /// Remove the oldest envelope once it has been sent
+    ///
+    /// The journal catches up on the next `commit`.
+    pub fn pop_front(&mut self) -> Option<Envelope> {
+        let (envelope, size) = self.entries.pop_front()?;
+        self.bytes -= size;
+        self.dirty = true;
+        Some(envelope)
+    }
+
+    /// Drop envelopes older than `max_age`, returning how many went
+    pub fn expire(&mut self, now_ms: u64) -> usize {
+        let max_age_ms = self.config.max_age.as_millis() as u64;
+        let before = self.entries.len();
+        self.entries.retain(|(e, _)| {
+            let sent_at = e.header.as_ref().map_or(0, |h| h.timestamp_ms);
+            now_ms.saturating_sub(sent_at) <= max_age_ms
+        });
+        let expired = before - self.entries.len();
+        if expired > 0 {
+            self.bytes = self.entries.iter().map(|(_, size)| size).sum();
+            self.dirty = true;
+        }
+        expired
+    }
+
+    /// Bring the journal in line with the queue after removals
+    pub fn commit(&mut self) -> Result<()> {
+        if self.dirty {
+            self.rewrite()?;
+        }
+        Ok(())
+    }
+
+    /// Envelopes waiting
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether nothing is waiting
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Evict until within `max_bytes`, returning how many went
+    fn evict(&mut self) -> usize {
+        let mut evicted = 0;
+        while self.bytes > self.config.max_bytes {
+            let Some(lowest) = self.entries.iter().map(|(e, _)| e.priority()).min() else {
+                break;
+            };
+            let index = self
+                .entries
+                .iter()
+                .position(|(e, _)| e.priority() == lowest)
+                .expect("lowest priority is present");
+            let (_, size) = self.entries.remove(index).expect("index in range");
+            self.bytes -= size;
+            self.dirty = true;
+            evicted += 1;
+        }
+        evicted
+    }
+
+    /// Replace the journal with the current queue
+    fn rewrite(&mut self) -> Result<()> {
+        self.dirty = false;
+        let Some(path) = &self.config.path else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        for (envelope, _) in &self.entries {
+            file.write_all(&codec::encode_frame(envelope, Compression::None, true)?)?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        self.journal = Some(OpenOptions::new().append(true).open(path)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::{priority, Ack, Header, MessageType, Telemetry};
+
+    fn telemetry(sequence_id: u64, timestamp_ms: u64) -> Envelope {
+        Envelope {
+            header: Some(Header::with_timestamp(
+                "edge-001",
+                MessageType::MsgTelemetry,
+                sequence_id,
+                timestamp_ms,
+            )),
+            payload: Some(resqterra_shared::envelope::Payload::Telemetry(Telemetry::default())),
+            signature: Vec::new(),
+        }
+    }
+
+    fn ack(sequence_id: u64, timestamp_ms: u64) -> Envelope {
+        Envelope {
+            header: Some(
+                Header::with_timestamp("edge-001", MessageType::MsgAck, sequence_id, timestamp_ms)
+                    .with_priority(priority::HIGH),
+            ),
+            payload: Some(resqterra_shared::envelope::Payload::Ack(Ack::default())),
+            signature: Vec::new(),
+        }
+    }
+
+    fn sequence_ids(outbox: &Outbox) -> Vec<u64> {
+        outbox.entries.iter().map(|(e, _)| e.header.as_ref().unwrap().sequence_id).collect()
+    }
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("resqterra-outbox-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_survives_restart_and_drops_torn_tail() {
+        let path = journal_path("restart");
+        let config = OutboxConfig {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+        let now = now_ms();
+
+        let mut outbox = Outbox::open(config.clone()).unwrap();
+        for seq in 1..=3 {
+            outbox.push(telemetry(seq, now)).unwrap();
+        }
+        outbox.pop_front();
+        outbox.commit().unwrap();
+        drop(outbox);
+
+        // Power lost halfway through appending another frame
+        let frame = codec::encode_frame(&telemetry(4, now), Compression::None, true).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&frame[..frame.len() / 2]).unwrap();
+        drop(file);
+
+        let outbox = Outbox::open(config).unwrap();
+        assert_eq!(sequence_ids(&outbox), vec![2, 3]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_eviction_keeps_acks_and_expires_old_entries() {
+        let size = |e: &Envelope| codec::encode_frame(e, Compression::None, true).unwrap().len();
+        let mut outbox = Outbox::open(OutboxConfig {
+            path: None,
+            max_bytes: size(&ack(1, 1_000)) + 2 * size(&telemetry(2, 2_000)),
+            max_age: Duration::from_secs(60),
+        })
+        .unwrap();
+
+        outbox.push(ack(1, 1_000)).unwrap();
+        for seq in 2..=5 {
+            outbox.push(telemetry(seq, 1_000 * seq)).unwrap();
+        }
+        // Over the limit: the oldest telemetry went, the older ACK stayed
+        assert_eq!(sequence_ids(&outbox), vec![1, 4, 5]);
+
+        assert_eq!(outbox.expire(62_000), 1);
+        assert_eq!(sequence_ids(&outbox), vec![4, 5]);
+        assert_eq!(outbox.front().unwrap().header.as_ref().unwrap().sequence_id, 4);
+    }
+}
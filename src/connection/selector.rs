@@ -0,0 +1,298 @@
+//! Transport selection
+//!
+//! Picks the transport for each connection attempt by score: configured
+//! preference, times the share of recent attempts that connected, times a
+//! round-trip factor. When 5G keeps dropping, the selector sticks to the
+//! other links for a while instead of flapping back on every reconnect.
+
+use crate::transport::Transport;
+use resqterra_shared::LinkStats;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+/// Decisions kept for `TransportSelector::decisions`
+const DECISION_HISTORY_LEN: usize = 50;
+
+/// Round trip at which a transport's score is halved
+const RTT_HALF_SCORE_MS: f64 = 500.0;
+
+/// How the selector weighs transports
+#[derive(Debug, Clone)]
+pub struct SelectorConfig {
+    /// Preference weight of 5G (0.0 - 1.0)
+    pub preference_5g: f64,
+    /// Preference weight of Bluetooth (0.0 - 1.0)
+    pub preference_bluetooth: f64,
+    /// Preference weight of any other transport (0.0 - 1.0)
+    pub preference_other: f64,
+    /// Connection attempts per transport the success rate is taken over
+    pub attempt_history_len: usize,
+    /// 5G drops within `flap_window` that count as flapping
+    pub flap_threshold: usize,
+    /// Window 5G drops are counted over
+    pub flap_window: Duration,
+    /// How long to stay off 5G once it flaps
+    pub sticky_fallback: Duration,
+}
+
+impl Default for SelectorConfig {
+    fn default() -> Self {
+        Self {
+            preference_5g: 1.0,
+            preference_bluetooth: 0.6,
+            preference_other: 0.3,
+            attempt_history_len: 10,
+            flap_threshold: 3,
+            flap_window: Duration::from_secs(60),
+            sticky_fallback: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Why a transport was chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecisionReason {
+    /// Highest score
+    BestScore,
+    /// 5G flapped recently; holding on to another link
+    StickyFallback,
+    /// No other transport is available
+    OnlyAvailable,
+}
+
+impl fmt::Display for DecisionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecisionReason::BestScore => write!(f, "best score"),
+            DecisionReason::StickyFallback => write!(f, "sticky fallback after 5G flapped"),
+            DecisionReason::OnlyAvailable => write!(f, "only transport available"),
+        }
+    }
+}
+
+/// One transport choice
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransportDecision {
+    /// Milliseconds since Unix epoch
+    pub at_ms: u64,
+    pub transport: Transport,
+    pub reason: DecisionReason,
+    /// Score of each available transport
+    pub scores: Vec<(Transport, f64)>,
+}
+
+/// Chooses the transport for each connection attempt
+#[derive(Debug)]
+pub struct TransportSelector {
+    config: SelectorConfig,
+    /// Outcome of recent connection attempts, oldest first
+    attempts: HashMap<Transport, VecDeque<bool>>,
+    /// When 5G connections dropped, oldest first
+    drops_5g: VecDeque<u64>,
+    /// 5G is avoided until then (ms since Unix epoch)
+    sticky_until_ms: Option<u64>,
+    /// Transports with no hardware on this drone
+    unavailable: HashSet<Transport>,
+    decisions: VecDeque<TransportDecision>,
+}
+
+impl TransportSelector {
+    pub fn new(config: SelectorConfig) -> Self {
+        Self {
+            config,
+            attempts: HashMap::new(),
+            drops_5g: VecDeque::new(),
+            sticky_until_ms: None,
+            unavailable: HashSet::new(),
+            decisions: VecDeque::new(),
+        }
+    }
+
+    /// Stop considering `transport` (e.g. no Bluetooth adapter on this hardware)
+    pub fn mark_unavailable(&mut self, transport: Transport) {
+        self.unavailable.insert(transport);
+    }
+
+    /// Whether `transport` may still be tried
+    pub fn is_available(&self, transport: Transport) -> bool {
+        !self.unavailable.contains(&transport)
+    }
+
+    /// Record whether a connection attempt over `transport` succeeded
+    pub fn record_attempt(&mut self, transport: Transport, connected: bool) {
+        let attempts = self.attempts.entry(transport).or_default();
+        if attempts.len() == self.config.attempt_history_len.max(1) {
+            attempts.pop_front();
+        }
+        attempts.push_back(connected);
+    }
+
+    /// Record an established connection dropping
+    ///
+    /// Enough 5G drops within the flap window start the sticky fallback.
+    pub fn record_drop(&mut self, transport: Transport, now_ms: u64) {
+        if transport != Transport::FiveG {
+            return;
+        }
+        let window_ms = self.config.flap_window.as_millis() as u64;
+        self.drops_5g.push_back(now_ms);
+        while self.drops_5g.front().is_some_and(|&at| now_ms.saturating_sub(at) > window_ms) {
+            self.drops_5g.pop_front();
+        }
+        if self.drops_5g.len() >= self.config.flap_threshold.max(1) {
+            self.sticky_until_ms = Some(now_ms + self.config.sticky_fallback.as_millis() as u64);
+            self.drops_5g.clear();
+        }
+    }
+
+    /// Share of recent attempts over `transport` that connected (1.0 untried)
+    pub fn success_rate(&self, transport: Transport) -> f64 {
+        match self.attempts.get(&transport) {
+            Some(attempts) if !attempts.is_empty() => {
+                attempts.iter().filter(|&&ok| ok).count() as f64 / attempts.len() as f64
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Score of `transport`, given the round trip last measured over it
+    pub fn score(&self, transport: Transport, rtt_ms: Option<u32>) -> f64 {
+        let preference = match transport {
+            Transport::FiveG => self.config.preference_5g,
+            Transport::Bluetooth => self.config.preference_bluetooth,
+            Transport::Other(_) => self.config.preference_other,
+        };
+        let rtt_factor = rtt_ms.map_or(1.0, |rtt| 1.0 / (1.0 + rtt as f64 / RTT_HALF_SCORE_MS));
+        preference * self.success_rate(transport) * rtt_factor
+    }
+
+    /// Choose the transport for the next connection attempt
+    ///
+    /// `candidates` (not empty) are in order of preference, which breaks ties.
+    /// Unavailable ones are skipped unless nothing else is left.
+    pub fn select(
+        &mut self,
+        candidates: &[Transport],
+        stats: &HashMap<Transport, LinkStats>,
+        now_ms: u64,
+    ) -> TransportDecision {
+        let rtt = |transport| stats.get(&transport).and_then(LinkStats::rtt_ms);
+        let mut available: Vec<Transport> =
+            candidates.iter().copied().filter(|&t| self.is_available(t)).collect();
+        if available.is_empty() {
+            available = candidates.to_vec();
+        }
+        let scores: Vec<(Transport, f64)> = available
+            .iter()
+            .map(|&transport| (transport, self.score(transport, rtt(transport))))
+            .collect();
+        let best = |scores: &[(Transport, f64)]| {
+            scores
+                .iter()
+                .fold(scores[0], |best, &candidate| if candidate.1 > best.1 { candidate } else { best })
+                .0
+        };
+
+        let sticky = self.sticky_until_ms.is_some_and(|until| now_ms < until);
+        if !sticky {
+            self.sticky_until_ms = None;
+        }
+        let fallbacks: Vec<(Transport, f64)> =
+            scores.iter().copied().filter(|(t, _)| *t != Transport::FiveG).collect();
+        let (transport, reason) = if scores.len() == 1 {
+            (scores[0].0, DecisionReason::OnlyAvailable)
+        } else if sticky && !fallbacks.is_empty() && fallbacks.len() < scores.len() {
+            (best(&fallbacks), DecisionReason::StickyFallback)
+        } else {
+            (best(&scores), DecisionReason::BestScore)
+        };
+
+        let decision = TransportDecision {
+            at_ms: now_ms,
+            transport,
+            reason,
+            scores,
+        };
+        if self.decisions.len() == DECISION_HISTORY_LEN {
+            self.decisions.pop_front();
+        }
+        self.decisions.push_back(decision.clone());
+        decision
+    }
+
+    /// Recent decisions, oldest first
+    pub fn decisions(&self) -> Vec<TransportDecision> {
+        self.decisions.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_with_rtt(rtts: &[(Transport, u32)]) -> HashMap<Transport, LinkStats> {
+        rtts.iter()
+            .map(|&(transport, rtt_ms)| {
+                let mut stats = LinkStats::default();
+                stats.sent(1);
+                stats.acked(1, Some(rtt_ms));
+                (transport, stats)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_unreliable_5g_loses_to_bluetooth() {
+        let mut selector = TransportSelector::new(SelectorConfig::default());
+        let stats = stats_with_rtt(&[(Transport::FiveG, 60), (Transport::Bluetooth, 150)]);
+
+        let candidates = [Transport::FiveG, Transport::Bluetooth];
+        let decision = selector.select(&candidates, &stats, 1_000);
+        assert_eq!(decision.transport, Transport::FiveG);
+        assert_eq!(decision.reason, DecisionReason::BestScore);
+
+        // 5G connects on only 3 of 10 attempts
+        for i in 0..10 {
+            selector.record_attempt(Transport::FiveG, i % 3 == 0 && i > 0);
+        }
+        selector.record_attempt(Transport::Bluetooth, true);
+        let decision = selector.select(&candidates, &stats, 2_000);
+        assert_eq!(decision.transport, Transport::Bluetooth);
+        assert_eq!(selector.decisions().len(), 2);
+
+        // Without an adapter 5G is the only choice
+        selector.mark_unavailable(Transport::Bluetooth);
+        let decision = selector.select(&candidates, &stats, 3_000);
+        assert_eq!(decision.transport, Transport::FiveG);
+        assert_eq!(decision.reason, DecisionReason::OnlyAvailable);
+    }
+
+    #[test]
+    fn test_sticky_fallback_after_5g_flaps() {
+        let config = SelectorConfig::default();
+        let sticky_ms = config.sticky_fallback.as_millis() as u64;
+        let mut selector = TransportSelector::new(config);
+        let stats = HashMap::new();
+        let satellite = Transport::Other("Satellite");
+        let candidates = [Transport::FiveG, Transport::Bluetooth, satellite];
+
+        // Two drops a minute apart are not flapping
+        selector.record_drop(Transport::FiveG, 0);
+        selector.record_drop(Transport::FiveG, 61_000);
+        selector.record_drop(Transport::Bluetooth, 62_000);
+        assert_eq!(selector.select(&candidates, &stats, 63_000).transport, Transport::FiveG);
+
+        selector.record_drop(Transport::FiveG, 70_000);
+        selector.record_drop(Transport::FiveG, 80_000);
+        let decision = selector.select(&candidates, &stats, 81_000);
+        assert_eq!(decision.transport, Transport::Bluetooth);
+        assert_eq!(decision.reason, DecisionReason::StickyFallback);
+
+        // Bluetooth failed too: the next link still isn't 5G
+        let decision = selector.select(&[Transport::FiveG, satellite], &stats, 82_000);
+        assert_eq!(decision.transport, satellite);
+
+        assert_eq!(selector.select(&candidates, &stats, 80_000 + sticky_ms).transport, Transport::FiveG);
+    }
+}
@@ -0,0 +1,154 @@
+//! Telemetry policy per transport
+//!
+//! How often telemetry is sampled, whether samples are batched, and how much
+//! of each sample is sent depends on the link the edge is on: full rate and
+//! detail on 5G, fewer and smaller samples in batches on Bluetooth and the
+//! slower links. The telemetry pump follows the table as the link changes.
+
+use crate::transport::Transport;
+use anyhow::{anyhow, Result};
+use resqterra_shared::Telemetry;
+use std::time::Duration;
+
+/// Fastest rate accepted from the environment
+const MAX_TELEMETRY_HZ: f64 = 50.0;
+
+/// How much of each sample is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryDetail {
+    /// Everything the telemetry reader has
+    Full,
+    /// Leaves out payload values and video stream health
+    Reduced,
+}
+
+impl TelemetryDetail {
+    /// Strip what this level leaves out from `telemetry`
+    pub fn apply(self, telemetry: &mut Telemetry) {
+        if self == TelemetryDetail::Reduced {
+            telemetry.payload_values.clear();
+            telemetry.video = None;
+        }
+    }
+}
+
+/// How telemetry is sent over one kind of link
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TelemetryPolicy {
+    /// Between samples
+    pub interval: Duration,
+    /// Batch samples, sending the batch at least this often (None = each
+    /// sample on its own, as a keyframe or delta)
+    pub batch_flush: Option<Duration>,
+    pub detail: TelemetryDetail,
+}
+
+/// Telemetry policy for each transport
+#[derive(Debug, Clone)]
+pub struct TelemetryPolicies {
+    pub five_g: TelemetryPolicy,
+    pub bluetooth: TelemetryPolicy,
+    /// Any other link (LoRa, satellite, serial)
+    pub other: TelemetryPolicy,
+}
+
+impl Default for TelemetryPolicies {
+    fn default() -> Self {
+        Self {
+            five_g: TelemetryPolicy {
+                interval: Duration::from_secs(1),
+                batch_flush: None,
+                detail: TelemetryDetail::Full,
+            },
+            bluetooth: TelemetryPolicy {
+                interval: Duration::from_secs(2),
+                batch_flush: Some(Duration::from_secs(5)),
+                detail: TelemetryDetail::Reduced,
+            },
+            other: TelemetryPolicy {
+                interval: Duration::from_secs(5),
+                batch_flush: Some(Duration::from_secs(30)),
+                detail: TelemetryDetail::Reduced,
+            },
+        }
+    }
+}
+
+impl TelemetryPolicies {
+    /// Defaults with the rate on 5G from `RESQTERRA_TELEMETRY_HZ` and of
+    /// batches on Bluetooth from `RESQTERRA_TELEMETRY_BATCH_HZ`
+    pub fn from_env() -> Result<Self> {
+        let mut policies = Self::default();
+        if let Ok(hz) = std::env::var("RESQTERRA_TELEMETRY_HZ") {
+            policies.five_g.interval = parse_rate(&hz)?;
+        }
+        if let Ok(hz) = std::env::var("RESQTERRA_TELEMETRY_BATCH_HZ") {
+            policies.bluetooth.batch_flush = Some(parse_rate(&hz)?);
+        }
+        Ok(policies)
+    }
+
+    /// The policy for `transport`
+    pub fn get(&self, transport: Transport) -> &TelemetryPolicy {
+        match transport {
+            Transport::FiveG => &self.five_g,
+            Transport::Bluetooth => &self.bluetooth,
+            Transport::Other(_) => &self.other,
+        }
+    }
+}
+
+/// Interval for a rate given in Hz
+fn parse_rate(hz: &str) -> Result<Duration> {
+    match hz.trim().parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate <= MAX_TELEMETRY_HZ => {
+            Ok(Duration::from_secs_f64(1.0 / rate))
+        }
+        _ => Err(anyhow!(
+            "Invalid telemetry rate {:?} (0-{} Hz)",
+            hz,
+            MAX_TELEMETRY_HZ
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::VideoStream;
+
+    #[test]
+    fn test_policy_per_transport() {
+        let policies = TelemetryPolicies::default();
+        let five_g = policies.get(Transport::FiveG);
+        assert_eq!(five_g.batch_flush, None);
+        assert_eq!(five_g.detail, TelemetryDetail::Full);
+        let bluetooth = policies.get(Transport::Bluetooth);
+        assert!(bluetooth.interval > five_g.interval);
+        assert_eq!(bluetooth.detail, TelemetryDetail::Reduced);
+        assert_eq!(policies.get(Transport::Other("LoRa")), &policies.other);
+
+        assert_eq!(parse_rate("4").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_rate("0.1").unwrap(), Duration::from_secs(10));
+        for hz in ["0", "-1", "NaN", "100", "fast"] {
+            assert!(parse_rate(hz).is_err(), "{}", hz);
+        }
+    }
+
+    #[test]
+    fn test_reduced_detail() {
+        let mut telemetry = Telemetry {
+            uptime_seconds: 42,
+            payload_values: [("gas_ppm".to_string(), 3.5)].into(),
+            video: Some(VideoStream::default()),
+            ..Default::default()
+        };
+        TelemetryDetail::Full.apply(&mut telemetry);
+        assert_eq!(telemetry.payload_values.len(), 1);
+
+        TelemetryDetail::Reduced.apply(&mut telemetry);
+        assert!(telemetry.payload_values.is_empty());
+        assert_eq!(telemetry.video, None);
+        assert_eq!(telemetry.uptime_seconds, 42);
+    }
+}
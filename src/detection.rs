@@ -0,0 +1,181 @@
+//! Detection reports from onboard perception
+//!
+//! Perception code (person/vehicle detectors on the thermal or RGB camera)
+//! hands each point of interest to a `DetectionReporter`, in process through
+//! `submit` or from another process as a protobuf-encoded `DetectionReport`
+//! in a UDP datagram to `RESQTERRA_DETECTION_LISTEN` (loopback by default).
+//! The reporter fills in what the detector left empty (ID, time, mission,
+//! the drone's position) and queues the report at `HIGH` priority, so it
+//! overtakes telemetry on a slow link and is kept in the outbox while
+//! offline.
+
+use crate::connection::OutboundSender;
+use crate::mavlink::TelemetryReader;
+use anyhow::{anyhow, Result};
+use prost::Message;
+use resqterra_shared::{envelope, now_ms, priority, DetectionReport, Envelope, Header, MessageType};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+/// Where perception processes send reports unless `RESQTERRA_DETECTION_LISTEN` is set
+pub const DEFAULT_DETECTION_LISTEN: &str = "127.0.0.1:14650";
+
+/// Address to receive reports on; `off` in `RESQTERRA_DETECTION_LISTEN` disables it
+pub fn listen_addr_from_env() -> Option<String> {
+    match std::env::var("RESQTERRA_DETECTION_LISTEN") {
+        Ok(v) if v.eq_ignore_ascii_case("off") => None,
+        Ok(v) => Some(v),
+        Err(_) => Some(DEFAULT_DETECTION_LISTEN.into()),
+    }
+}
+
+/// Largest report datagram accepted (a report is a few hundred bytes)
+const MAX_DATAGRAM_LEN: usize = 2048;
+
+/// Sends detections to the server
+pub struct DetectionReporter {
+    device_id: String,
+    sequence_id: Arc<AtomicU64>,
+    uplink: OutboundSender,
+    telemetry: Option<Arc<TelemetryReader>>,
+    /// Reports numbered since start, for IDs the detector didn't set
+    submitted: AtomicU64,
+}
+
+impl DetectionReporter {
+    /// Create a reporter sending over `uplink`, numbered with the connection's `sequence_id`
+    pub fn new(device_id: String, sequence_id: Arc<AtomicU64>, uplink: OutboundSender) -> Self {
+        Self {
+            device_id,
+            sequence_id,
+            uplink,
+            telemetry: None,
+            submitted: AtomicU64::new(0),
+        }
+    }
+
+    /// Tag reports with the mission being flown and, when they have none, the
+    /// drone's position
+    pub fn with_telemetry(mut self, telemetry: Arc<TelemetryReader>) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Queue a detection for the server
+    ///
+    /// Fails for a confidence outside 0-1 or once the connection is gone.
+    pub async fn submit(&self, report: DetectionReport) -> Result<()> {
+        let envelope = self.envelope(report).await?;
+        self.uplink
+            .send(envelope)
+            .await
+            .map_err(|_| anyhow!("Connection closed"))
+    }
+
+    async fn envelope(&self, mut report: DetectionReport) -> Result<Envelope> {
+        if !(0.0..=1.0).contains(&report.confidence) {
+            return Err(anyhow!("Confidence {} out of range 0-1", report.confidence));
+        }
+
+        let n = self.submitted.fetch_add(1, Ordering::SeqCst) + 1;
+        if report.detected_at_ms == 0 {
+            report.detected_at_ms = now_ms();
+        }
+        // Unique across restarts: the count alone starts over
+        if report.detection_id.is_empty() {
+            report.detection_id = format!("{}-{}-{}", self.device_id, report.detected_at_ms, n);
+        }
+        if let Some(telemetry) = &self.telemetry {
+            if report.mission_id.is_empty() {
+                report.mission_id = telemetry.get_mission_id().await;
+            }
+            if report.position.is_none() {
+                report.position = telemetry.get_position().await;
+            }
+        }
+        println!(
+            "[DETECT] {} {} ({:.0}%, {})",
+            report.detection_id,
+            report.label,
+            report.confidence * 100.0,
+            report.sensor_type
+        );
+
+        let seq = self.sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
+        Ok(Envelope {
+            header: Some(
+                Header::new(&self.device_id, MessageType::MsgDetectionReport, seq)
+                    .with_priority(priority::HIGH),
+            ),
+            payload: Some(envelope::Payload::DetectionReport(report)),
+            signature: Vec::new(),
+        })
+    }
+
+    /// Submit every report datagram arriving on `listen` until the socket fails
+    pub async fn listen(self: Arc<Self>, listen: &str) -> Result<()> {
+        let socket = UdpSocket::bind(listen).await?;
+        let mut buf = vec![0u8; MAX_DATAGRAM_LEN];
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await?;
+            let report = match DetectionReport::decode(&buf[..len]) {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("[DETECT] Bad report from {}: {}", from, e);
+                    continue;
+                }
+            };
+            if let Err(e) = self.submit(report).await {
+                eprintln!("[DETECT] Report from {} not sent: {}", from, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::outbound_channel;
+
+    #[tokio::test]
+    async fn test_detection_queued_at_high_priority() {
+        let (uplink, mut rx) = outbound_channel(8);
+        let sequence_id = Arc::new(AtomicU64::new(41));
+        let reporter = DetectionReporter::new("edge-001".into(), sequence_id, uplink);
+        let report = DetectionReport {
+            label: "person".into(),
+            confidence: 0.92,
+            sensor_type: "THERMAL".into(),
+            ..Default::default()
+        };
+        reporter.submit(report.clone()).await.unwrap();
+
+        let envelope = rx.recv().await.unwrap();
+        assert_eq!(envelope.priority(), priority::HIGH);
+        assert_eq!(envelope.header.as_ref().unwrap().sequence_id, 42);
+        let Some(envelope::Payload::DetectionReport(sent)) = envelope.payload else {
+            panic!("expected detection report");
+        };
+        assert_eq!(sent.label, "person");
+        assert!(sent.detected_at_ms > 0);
+        assert!(sent.detection_id.starts_with("edge-001-"));
+
+        // The detector's own ID is kept; nonsense confidences are refused
+        let named = DetectionReport {
+            detection_id: "cam0-17".into(),
+            ..report.clone()
+        };
+        reporter.submit(named).await.unwrap();
+        let Some(envelope::Payload::DetectionReport(sent)) = rx.recv().await.unwrap().payload
+        else {
+            panic!("expected detection report");
+        };
+        assert_eq!(sent.detection_id, "cam0-17");
+        let bad = DetectionReport {
+            confidence: 1.5,
+            ..report
+        };
+        assert!(reporter.submit(bad).await.is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//! Geodesy helpers for GPS positions
+
+/// Mean Earth radius (m)
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Great-circle distance in meters between two points given in degrees
+///
+/// Haversine on a spherical Earth; accurate to well under a meter at the
+/// distances a drone flies from home.
+pub fn distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = (lat2 - lat1).to_radians();
+    let d_lambda = (lon2 - lon1).to_radians();
+
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Flat east/north frame (m) around an origin, for planning over a survey area
+///
+/// Equirectangular; the error stays negligible over the few kilometres a
+/// survey spans.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalFrame {
+    lat0: f64,
+    lon0: f64,
+    cos_lat0: f64,
+}
+
+impl LocalFrame {
+    /// Frame centred on a point given in degrees
+    pub fn new(lat0: f64, lon0: f64) -> Self {
+        Self {
+            lat0,
+            lon0,
+            cos_lat0: lat0.to_radians().cos(),
+        }
+    }
+
+    /// Metres east and north of the origin
+    pub fn to_local(self, lat: f64, lon: f64) -> (f64, f64) {
+        (
+            (lon - self.lon0).to_radians() * EARTH_RADIUS_M * self.cos_lat0,
+            (lat - self.lat0).to_radians() * EARTH_RADIUS_M,
+        )
+    }
+
+    /// Latitude and longitude in degrees of a point east/north of the origin
+    pub fn to_global(self, east: f64, north: f64) -> (f64, f64) {
+        (
+            self.lat0 + (north / EARTH_RADIUS_M).to_degrees(),
+            self.lon0 + (east / (EARTH_RADIUS_M * self.cos_lat0)).to_degrees(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance_m(47.5, 8.5, 47.5, 8.5), 0.0);
+
+        // One degree of latitude is ~111.2 km anywhere
+        let degree = distance_m(47.0, 8.5, 48.0, 8.5);
+        assert!((degree - 111_195.0).abs() < 1.0, "got {}", degree);
+
+        // ~10 m east at 47.5N, either way round
+        let east = distance_m(47.5, 8.5, 47.5, 8.500_133);
+        assert!((east - 10.0).abs() < 0.1, "got {}", east);
+        assert_eq!(east, distance_m(47.5, 8.500_133, 47.5, 8.5));
+    }
+
+    #[test]
+    fn test_local_frame_roundtrip() {
+        let frame = LocalFrame::new(47.5, 8.5);
+        let (east, north) = frame.to_local(47.501, 8.502);
+        let distance = distance_m(47.5, 8.5, 47.501, 8.502);
+        assert!((east.hypot(north) - distance).abs() < 0.1, "got {}, {}", east, north);
+
+        let (lat, lon) = frame.to_global(east, north);
+        assert!((lat - 47.501).abs() < 1e-9 && (lon - 8.502).abs() < 1e-9);
+    }
+}
@@ -1,37 +1,117 @@
 mod command;
 mod connection;
+mod detection;
+mod geo;
 mod mavlink;
+mod mission;
 mod protocol;
 mod safety;
+mod state;
+mod telemetry_pump;
+mod transfer;
 mod transport;
+mod video;
 
-use command::CommandExecutor;
-use connection::{ConnectionConfig, ConnectionEvent, ConnectionManager};
-use mavlink::{FcConfig, FcConnectionType, FcEvent, FlightController, MavCommandSender, TelemetryReader};
+use command::{CommandExecutor, EmergencyPolicy};
+use connection::{
+    ConnectMode, ConnectionConfig, ConnectionEvent, ConnectionManager, FiveGLink, OutboxConfig,
+    TelemetryPolicies, Transport,
+};
+use detection::DetectionReporter;
+use mavlink::{
+    FailsafeParams, FcConfig, FcConnectionType, FcEvent, FcSigning, FlightController,
+    MavCommandSender, TelemetryReader,
+};
 use protocol::*;
-use safety::{SafetyAction, SafetyMonitor};
+use resqterra_shared::noise::NoiseConfig;
+use ::mavlink::ardupilotmega::{MavAutopilot, MavMessage};
+use safety::{SafetyActuator, SafetyMonitor};
+use telemetry_pump::TelemetryPump;
+use transfer::{BlobEvent, BlobUploader};
+use transport::{LoraConfig, LoraModem, QuicConfig, SerialConfig};
+use video::{VideoConfig, VideoStreamer};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Created by the onboard updater for the duration of a software/firmware update
+const MAINTENANCE_FLAG_PATH: &str = "/run/resqterra/maintenance";
+
+/// Journal for envelopes queued while offline, kept across restarts
+const OUTBOX_PATH: &str = "/var/lib/resqterra/outbox";
 
 #[tokio::main]
 async fn main() {
     let config = ConnectionConfig {
-        device_id: "edge-001".into(),
+        device_id: "edge-001".parse().expect("valid device ID"),
         server_5g: "127.0.0.1:8080".into(),
+        link_5g: five_g_link_from_env(),
+        signer: EnvelopeSigner::from_env().expect("valid RESQTERRA_SIGNED_TYPES"),
+        noise: NoiseConfig::from_env().expect("valid RESQTERRA_NOISE_KEY / RESQTERRA_NOISE_PEERS"),
+        outbox: OutboxConfig {
+            path: Some(OUTBOX_PATH.into()),
+            ..Default::default()
+        },
+        connect_mode: match std::env::var("RESQTERRA_CONNECT_MODE") {
+            Ok(mode) => mode.parse().expect("valid RESQTERRA_CONNECT_MODE"),
+            Err(_) => ConnectMode::default(),
+        },
+        lora: std::env::var("RESQTERRA_LORA_PORT").ok().map(|port| {
+            let modem: LoraModem = match std::env::var("RESQTERRA_LORA_MODEM") {
+                Ok(modem) => modem.parse().expect("valid RESQTERRA_LORA_MODEM"),
+                Err(_) => LoraModem::default(),
+            };
+            LoraConfig {
+                port,
+                baud_rate: if modem == LoraModem::E22 { 9600 } else { 57600 },
+                modem,
+                mtu: modem.max_packet_len(),
+            }
+        }),
+        serial: std::env::var("RESQTERRA_SERIAL_PORT").ok().map(|port| SerialConfig {
+            port,
+            baud_rate: match std::env::var("RESQTERRA_SERIAL_BAUD") {
+                Ok(baud) => baud.parse().expect("valid RESQTERRA_SERIAL_BAUD"),
+                Err(_) => SerialConfig::default().baud_rate,
+            },
+        }),
+        telemetry: TelemetryPolicies::from_env()
+            .expect("valid RESQTERRA_TELEMETRY_HZ / RESQTERRA_TELEMETRY_BATCH_HZ"),
         ..Default::default()
     };
+    // WebSocket carries bare envelopes, so it can't be Noise-encrypted
+    assert!(
+        config.noise.is_none() || !matches!(config.link_5g, FiveGLink::WebSocket(_)),
+        "RESQTERRA_5G_WS can't be combined with RESQTERRA_NOISE_KEY"
+    );
 
     println!("Edge device starting: {}", config.device_id);
-    println!("  5G server: {}", config.server_5g);
+    match &config.link_5g {
+        FiveGLink::Tcp => println!("  5G server: {} (TCP)", config.server_5g),
+        FiveGLink::Udp => println!("  5G server: {} (UDP)", config.server_5g),
+        FiveGLink::Quic(quic) => println!("  5G server: {} (QUIC, {})", quic.server, quic.server_name),
+        FiveGLink::WebSocket(url) => println!("  5G server: {} (WebSocket)", url),
+    }
     println!("  BT relay:  {} (mode: {:?})", config.bluetooth.tcp_address, config.bluetooth.mode);
+    println!("  Connect:   {:?}", config.connect_mode);
+    match &config.lora {
+        Some(lora) => println!("  LoRa:      {:?} on {}", lora.modem, lora.port),
+        None => println!("  LoRa:      off"),
+    }
+    if let Some(serial) = &config.serial {
+        println!("  Serial:    {} at {} baud (tethered, radio links off)", serial.port, serial.baud_rate);
+    }
+    match &config.signer {
+        Some(signer) => println!("  Signing:   {:?}", signer.policy()),
+        None => println!("  Signing:   off"),
+    }
+    match &config.noise {
+        Some(noise) => println!("  Noise:     public key {}", noise.public_key_hex()),
+        None => println!("  Noise:     off (plaintext links)"),
+    }
 
     let mut conn = ConnectionManager::new(config.clone());
 
-    // Create command executor (shares sequence_id with connection manager internally)
-    let cmd_executor = Arc::new(CommandExecutor::new(
-        config.device_id.clone(),
-        Arc::new(std::sync::atomic::AtomicU64::new(1000)), // Start from 1000 to avoid conflicts
-    ));
-
     // Create safety monitor
     let safety_monitor = Arc::new(SafetyMonitor::new());
     let _safety_handle = safety_monitor.start_monitoring().await;
@@ -42,47 +122,176 @@ async fn main() {
         connection: FcConnectionType::Udp {
             address: "127.0.0.1:14550".into(), // SITL default
         },
+        signing: FcSigning::from_env().expect("valid RESQTERRA_FC_SIGNING_KEY"),
         ..Default::default()
     };
-    let mut flight_controller = FlightController::new(fc_config.clone());
+    let flight_controller = Arc::new(FlightController::new(fc_config.clone()));
     let mav_cmd_sender = Arc::new(MavCommandSender::new(
         fc_config.target_system,
         fc_config.target_component,
     ));
-    let telemetry_reader = Arc::new(TelemetryReader::new());
+    // FC heartbeats feed the state the safety monitor keeps
+    let telemetry_reader =
+        Arc::new(TelemetryReader::new().with_state_store(safety_monitor.state_store()));
     println!("Flight controller bridge initialized (UDP:14550)");
+    match &fc_config.signing {
+        Some(signing) => println!("  FC signing: link {}", signing.link_id),
+        None => println!("  FC signing: off"),
+    }
+
+    // Video is streamed out of band; video commands negotiate it
+    let video = VideoConfig::from_env().map(|video_config| {
+        println!("Video pipeline: {}", video_config.pipeline);
+        Arc::new(VideoStreamer::new(config.device_id.to_string(), video_config))
+    });
+
+    // Create command executor (shares sequence_id with connection manager)
+    // Pending commands are NAKed when the drone enters emergency, as failed
+    // unless RESQTERRA_EMERGENCY_NAK says otherwise
+    let emergency_policy = EmergencyPolicy::from_env().expect("valid RESQTERRA_EMERGENCY_NAK");
+    let mut cmd_executor = CommandExecutor::new(config.device_id.to_string(), conn.sequence_ids())
+        .with_emergency_policy(emergency_policy)
+        .with_telemetry(telemetry_reader.clone())
+        .with_flight_controller(flight_controller.clone(), mav_cmd_sender.clone())
+        .with_geofence(safety_monitor.geofence())
+        .with_state_store(safety_monitor.state_store())
+        .with_uplink(conn.get_sender());
+    if let Some(video) = &video {
+        cmd_executor = cmd_executor.with_video(video.clone());
+    }
+    let cmd_executor = Arc::new(cmd_executor);
+    // Heartbeats report the executor's state and pending commands, battery and GPS fix
+    conn.set_heartbeat_provider(cmd_executor.clone()).await;
+    // Heartbeat acks from the server keep the safety monitor's link timer alive
+    conn.set_safety_monitor(safety_monitor.clone()).await;
 
     // Spawn flight controller event handler
+    let fc_clone = flight_controller.clone();
+    let mav_clone = mav_cmd_sender.clone();
     let telemetry_clone = telemetry_reader.clone();
     let safety_clone = safety_monitor.clone();
     tokio::spawn(async move {
-        handle_fc_events(&mut flight_controller, telemetry_clone, safety_clone).await;
+        handle_fc_events(fc_clone, mav_clone, telemetry_clone, safety_clone).await;
     });
 
-    // Spawn safety action handler with MAVLink integration
+    // Spawn the safety actuator: failsafes are flown via MAVLink and reported
+    let safety_actuator = SafetyActuator::new(
+        safety_monitor.clone(),
+        config.device_id.to_string(),
+        conn.sequence_ids(),
+        conn.get_sender(),
+    )
+    .with_flight_controller(flight_controller.clone(), mav_cmd_sender.clone())
+    .with_telemetry(telemetry_reader.clone())
+    .with_executor(cmd_executor.clone(), conn.drone_state());
+    tokio::spawn(safety_actuator.run());
+
+    // Onboard perception submits detections over loopback UDP
+    if let Some(listen) = detection::listen_addr_from_env() {
+        let device_id = config.device_id.to_string();
+        let reporter = Arc::new(
+            DetectionReporter::new(device_id, conn.sequence_ids(), conn.get_sender())
+                .with_telemetry(telemetry_reader.clone()),
+        );
+        println!("Detection reports accepted on UDP {}", listen);
+        tokio::spawn(async move {
+            if let Err(e) = reporter.listen(&listen).await {
+                eprintln!("[DETECT] Listener stopped: {}", e);
+            }
+        });
+    }
+
+    // Spawn maintenance flag watcher (busy while an onboard update runs)
     let safety_clone = safety_monitor.clone();
-    let conn_clone = conn.get_sender();
     tokio::spawn(async move {
-        handle_safety_actions(safety_clone, conn_clone).await;
+        watch_maintenance_flag(safety_clone).await;
+    });
+
+    // Captured images and map tiles go up over 5G, paused on other links
+    let blob_events = transfer::spool_from_env().map(|spool| {
+        println!("Blob uploads from {}", spool.display());
+        let device_id = config.device_id.to_string();
+        let (uploader, events) =
+            BlobUploader::new(device_id, conn.sequence_ids(), conn.get_sender(), spool);
+        tokio::spawn(async move {
+            if let Err(e) = uploader.run().await {
+                eprintln!("[BLOB] Uploader stopped: {}", e);
+            }
+        });
+        events
     });
 
+    // Telemetry is pushed as periodic keyframes with deltas in between; over
+    // Bluetooth it is slower, smaller and batched instead
+    let mut telemetry_pump = TelemetryPump::new(telemetry_reader.clone(), config.telemetry.clone());
+    if let Some(video) = &video {
+        telemetry_pump = telemetry_pump.with_video(video.clone());
+    }
+
     // Main event loop
     loop {
-        match conn.recv().await {
+        let event = tokio::select! {
+            event = conn.recv() => event,
+            tick = telemetry_pump.tick() => {
+                telemetry_pump.publish(tick, &conn).await;
+                continue;
+            }
+        };
+
+        match event {
             Some(ConnectionEvent::Connected { transport }) => {
                 println!("Connected via {}", transport);
+                safety_monitor.link_restored().await;
+                report_link(blob_events.as_ref(), Some(transport)).await;
+                telemetry_pump.link_changed(transport, &conn).await;
             }
             Some(ConnectionEvent::Disconnected { reason }) => {
                 println!("Disconnected: {}", reason);
+                // The safety monitor keeps running; RTH follows if the link stays down
+                safety_monitor.link_lost().await;
+                report_link(blob_events.as_ref(), None).await;
+                let history = conn.quality_history().await;
+                if let Some(trend) = history.trend(|q| q.latency_ms as f64) {
+                    println!(
+                        "  Latency trend before disconnect: {:+.1}ms/s over {} samples",
+                        trend,
+                        history.len()
+                    );
+                }
             }
             Some(ConnectionEvent::TransportSwitched { from, to }) => {
                 println!("Transport switched: {} -> {}", from, to);
+                telemetry_pump.follow_policy(to);
+            }
+            Some(ConnectionEvent::TransportSelected(decision)) => {
+                let scores: Vec<String> = decision
+                    .scores
+                    .iter()
+                    .map(|(transport, score)| format!("{}={:.2}", transport, score))
+                    .collect();
+                println!(
+                    "Transport selected: {} ({}; {})",
+                    decision.transport,
+                    decision.reason,
+                    scores.join(" ")
+                );
             }
             Some(ConnectionEvent::ConnectionFailed { reason }) => {
                 eprintln!("Connection failed: {}", reason);
+                safety_monitor.link_lost().await;
+                if let Some(last) = conn.transport_decisions().await.last() {
+                    eprintln!("  Last transport choice: {} ({})", last.transport, last.reason);
+                }
             }
             Some(ConnectionEvent::Received(envelope)) => {
-                handle_server_message(&envelope, &conn, &cmd_executor, &safety_monitor).await;
+                handle_server_message(
+                    &envelope,
+                    &conn,
+                    &cmd_executor,
+                    &safety_monitor,
+                    blob_events.as_ref(),
+                )
+                .await;
             }
             None => {
                 eprintln!("Connection manager closed");
@@ -92,11 +301,32 @@ async fn main() {
     }
 }
 
+/// 5G link from `RESQTERRA_QUIC_CA` (QUIC), `RESQTERRA_5G_WS` (WebSocket URL)
+/// or `RESQTERRA_5G_UDP`, TCP otherwise
+fn five_g_link_from_env() -> FiveGLink {
+    if let Ok(ca_cert) = std::env::var("RESQTERRA_QUIC_CA") {
+        let defaults = QuicConfig::default();
+        return FiveGLink::Quic(QuicConfig {
+            server: std::env::var("RESQTERRA_QUIC_SERVER").unwrap_or(defaults.server),
+            server_name: std::env::var("RESQTERRA_QUIC_SERVER_NAME").unwrap_or(defaults.server_name),
+            ca_cert: ca_cert.into(),
+        });
+    }
+    if let Ok(url) = std::env::var("RESQTERRA_5G_WS") {
+        return FiveGLink::WebSocket(url);
+    }
+    match std::env::var("RESQTERRA_5G_UDP") {
+        Ok(v) if v == "1" || v.eq_ignore_ascii_case("true") => FiveGLink::Udp,
+        _ => FiveGLink::Tcp,
+    }
+}
+
 async fn handle_server_message(
     envelope: &Envelope,
     conn: &ConnectionManager,
     cmd_executor: &CommandExecutor,
     safety_monitor: &SafetyMonitor,
+    blob_events: Option<&mpsc::Sender<BlobEvent>>,
 ) {
     let header = match &envelope.header {
         Some(h) => h,
@@ -117,11 +347,47 @@ async fn handle_server_message(
         Some(envelope::Payload::Command(cmd)) => {
             // Execute command and get ACK response
             let ack_envelope = cmd_executor.execute(cmd, header).await;
+            let accepted = matches!(
+                &ack_envelope.payload,
+                Some(envelope::Payload::Ack(ack))
+                    if ack.status == AckStatus::AckCompleted as i32
+                        || ack.status == AckStatus::AckAccepted as i32
+            );
 
             // Send ACK back to server
             if let Err(e) = conn.send(ack_envelope).await {
                 eprintln!("Failed to send ACK: {}", e);
             }
+
+            // An emergency stop supersedes everything still in flight
+            if cmd.cmd_type == CommandType::CmdEmergencyStop as i32 {
+                for nak in cmd_executor.cancel_pending_for_emergency().await {
+                    if let Err(e) = conn.send(nak).await {
+                        eprintln!("Failed to send emergency NAK: {}", e);
+                    }
+                }
+            } else if accepted {
+                // A replacing mission start NAKs the mission it supersedes
+                if let Some(nak) = cmd_executor.supersede_mission(cmd, header).await {
+                    if let Err(e) = conn.send(nak).await {
+                        eprintln!("Failed to send supersession NAK: {}", e);
+                    }
+                }
+
+                // A cancel aborts its target, which is NAKed as cancelled
+                if let Some(nak) = cmd_executor.cancel_pending(cmd).await {
+                    if let Err(e) = conn.send(nak).await {
+                        eprintln!("Failed to send cancellation NAK: {}", e);
+                    }
+                }
+
+                // High-priority commands preempt pending lower-priority work
+                for nak in cmd_executor.preempt_pending(cmd).await {
+                    if let Err(e) = conn.send(nak).await {
+                        eprintln!("Failed to send preemption NAK: {}", e);
+                    }
+                }
+            }
         }
         Some(envelope::Payload::Heartbeat(hb)) => {
             // Update safety monitor with server heartbeat
@@ -135,51 +401,66 @@ async fn handle_server_message(
                 ack.ack_sequence_id, status
             );
         }
+        Some(envelope::Payload::BlobStatus(status)) => {
+            if let Some(events) = blob_events {
+                let _ = events.send(BlobEvent::Status(status.clone())).await;
+            }
+        }
         _ => {
             println!("  Unhandled payload type");
         }
     }
 }
 
-/// Handle safety actions triggered by the monitor
-async fn handle_safety_actions(
-    safety_monitor: Arc<SafetyMonitor>,
-    sender: tokio::sync::mpsc::Sender<Envelope>,
-) {
+/// Tell the blob uploader which link is up, if uploads are on
+async fn report_link(blob_events: Option<&mpsc::Sender<BlobEvent>>, transport: Option<Transport>) {
+    if let Some(events) = blob_events {
+        let _ = events.send(BlobEvent::LinkChanged(transport)).await;
+    }
+}
+
+/// Enter maintenance while the updater's flag file exists and leave it once removed
+///
+/// Entering only succeeds while idle; the flag is re-checked every second, so
+/// an update started mid-flight takes effect after landing.
+async fn watch_maintenance_flag(safety_monitor: Arc<SafetyMonitor>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
     loop {
-        match safety_monitor.recv_action().await {
-            Some(SafetyAction::ReturnToHome { reason }) => {
-                println!("[MAIN] Safety RTH triggered: {}", reason);
-                // TODO: Send RTH command to flight controller via MAVLink
-                // For now, just log it
-            }
-            Some(SafetyAction::EmergencyStop { reason }) => {
-                println!("[MAIN] EMERGENCY STOP: {}", reason);
-                // TODO: Send emergency stop to flight controller
-            }
-            Some(SafetyAction::StateChanged { from, to }) => {
-                println!("[MAIN] State changed: {:?} -> {:?}", from, to);
-            }
-            Some(SafetyAction::None) => {}
-            None => {
-                eprintln!("[MAIN] Safety monitor channel closed");
-                break;
-            }
+        ticker.tick().await;
+        let flagged = tokio::fs::try_exists(MAINTENANCE_FLAG_PATH)
+            .await
+            .unwrap_or(false);
+        let state = safety_monitor.state().await;
+
+        if flagged && state == DroneState::DroneIdle {
+            println!("[MAIN] Onboard update started, entering maintenance");
+            safety_monitor.enter_maintenance().await;
+        } else if !flagged && state == DroneState::DroneMaintenance {
+            println!("[MAIN] Onboard update finished, leaving maintenance");
+            safety_monitor.exit_maintenance().await;
         }
     }
-    let _ = sender; // Keep sender alive for potential future use
 }
 
 /// Handle events from the flight controller
 async fn handle_fc_events(
-    fc: &mut FlightController,
+    fc: Arc<FlightController>,
+    mav_cmd_sender: Arc<MavCommandSender>,
     telemetry: Arc<TelemetryReader>,
-    _safety: Arc<SafetyMonitor>,
+    safety: Arc<SafetyMonitor>,
 ) {
     loop {
         match fc.recv().await {
             Some(FcEvent::Connected) => {
                 println!("[FC] Connected to flight controller");
+
+                // Configure FC failsafes off the event loop (read-back needs it running)
+                let fc = fc.clone();
+                let mav_cmd_sender = mav_cmd_sender.clone();
+                let telemetry = telemetry.clone();
+                tokio::spawn(async move {
+                    configure_fc_failsafes(&fc, &mav_cmd_sender, &telemetry).await;
+                });
             }
             Some(FcEvent::Disconnected { reason }) => {
                 println!("[FC] Disconnected: {}", reason);
@@ -190,15 +471,41 @@ async fn handle_fc_events(
                 system_status,
                 base_mode,
                 custom_mode,
+                dropped_frames,
             }) => {
                 println!(
-                    "[FC] Heartbeat: type={} autopilot={} status={} mode={} custom={}",
-                    mav_type, autopilot, system_status, base_mode, custom_mode
+                    "[FC] Heartbeat: type={} autopilot={} status={} mode={} custom={} dropped={}",
+                    mav_type, autopilot, system_status, base_mode, custom_mode, dropped_frames
                 );
             }
             Some(FcEvent::Message(msg)) => {
                 // Process telemetry messages
                 telemetry.process_message(&msg).await;
+
+                // New positions are checked against the geofence, battery
+                // levels against the staged battery policy, and the safety
+                // state follows what the FC reports in its heartbeat
+                match msg {
+                    MavMessage::HEARTBEAT(hb) => {
+                        // Other components on the link don't keep it alive
+                        if hb.autopilot != MavAutopilot::MAV_AUTOPILOT_INVALID {
+                            safety.update_fc_heartbeat().await;
+                        }
+                        safety.sync_with_fc().await;
+                    }
+                    MavMessage::GLOBAL_POSITION_INT(_) => {
+                        if let Some(position) = telemetry.get_position().await {
+                            let home = telemetry.get_home().await;
+                            safety.check_position(&position, home.as_ref()).await;
+                        }
+                    }
+                    MavMessage::BATTERY_STATUS(_) => {
+                        if let Some(battery) = telemetry.get_battery().await {
+                            safety.update_battery(battery.remaining_percent).await;
+                        }
+                    }
+                    _ => {}
+                }
             }
             None => {
                 eprintln!("[FC] Flight controller channel closed");
@@ -207,3 +514,38 @@ async fn handle_fc_events(
         }
     }
 }
+
+/// Push the failsafe configuration to the FC and raise a fault on any mismatch
+async fn configure_fc_failsafes(
+    fc: &FlightController,
+    mav_cmd_sender: &MavCommandSender,
+    telemetry: &TelemetryReader,
+) {
+    match mav_cmd_sender
+        .configure_failsafes(fc, &FailsafeParams::default())
+        .await
+    {
+        Ok(report) if report.is_verified() => {
+            println!("[FC] Failsafe parameters verified");
+        }
+        Ok(report) => {
+            for mismatch in report.mismatches {
+                let fault = match mismatch.actual {
+                    Some(actual) => format!(
+                        "Failsafe param {} is {} (expected {})",
+                        mismatch.name, actual, mismatch.expected
+                    ),
+                    None => format!("Failsafe param {} could not be verified", mismatch.name),
+                };
+                eprintln!("[FC] ALERT: {}", fault);
+                telemetry.add_fault(fault).await;
+            }
+        }
+        Err(e) => {
+            eprintln!("[FC] ALERT: Failed to configure failsafes: {}", e);
+            telemetry
+                .add_fault(format!("Failsafe configuration failed: {}", e))
+                .await;
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! COMMAND_LONG acknowledgement
+//!
+//! The FC answers every COMMAND_LONG with a COMMAND_ACK carrying a
+//! MAV_RESULT. `CommandAckTracker` sends a command and waits for the ACK of
+//! that command: one the FC can't take right now (TEMPORARILY_REJECTED) or
+//! doesn't answer in time is sent again with `confirmation` counting up, and
+//! one it refuses fails with a [`CommandAckError`] saying what the FC did.
+
+use anyhow::{anyhow, Result};
+use mavlink::ardupilotmega::{MavCmd, MavMessage, MavResult, COMMAND_LONG_DATA};
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+
+use super::connection::FlightController;
+
+/// How long each attempt waits for the COMMAND_ACK
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Attempts per command before giving up
+const ACK_ATTEMPTS: u32 = 3;
+
+/// A command the FC did not carry out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandAckError {
+    pub command: MavCmd,
+    /// What the FC answered; None if it never did
+    pub result: Option<MavResult>,
+}
+
+impl CommandAckError {
+    /// Whether the FC refused the command outright (denied or unsupported),
+    /// rather than failing to carry it out
+    pub fn is_refusal(&self) -> bool {
+        matches!(
+            self.result,
+            Some(MavResult::MAV_RESULT_DENIED | MavResult::MAV_RESULT_UNSUPPORTED)
+        )
+    }
+}
+
+impl fmt::Display for CommandAckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let command = format!("{:?}", self.command);
+        let command = command.trim_start_matches("MAV_CMD_");
+        match self.result {
+            Some(result) => {
+                let result = format!("{:?}", result);
+                let result = result.trim_start_matches("MAV_RESULT_");
+                write!(f, "FC answered {} with {}", command, result)
+            }
+            None => write!(f, "FC did not acknowledge {}", command),
+        }
+    }
+}
+
+impl std::error::Error for CommandAckError {}
+
+/// Sends COMMAND_LONGs and waits for the FC to accept them
+#[derive(Debug, Clone)]
+pub struct CommandAckTracker {
+    attempts: u32,
+    timeout: Duration,
+}
+
+impl Default for CommandAckTracker {
+    fn default() -> Self {
+        Self::new(ACK_ATTEMPTS, ACK_TIMEOUT)
+    }
+}
+
+impl CommandAckTracker {
+    /// Send each command up to `attempts` times, waiting `timeout` for its ACK
+    /// (also the pause before retrying a temporarily rejected one)
+    pub fn new(attempts: u32, timeout: Duration) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            timeout,
+        }
+    }
+
+    /// Send `command` and wait until the FC accepts it
+    ///
+    /// IN_PROGRESS counts as accepted: the FC took the command and is
+    /// carrying it out.
+    pub async fn send(&self, fc: &FlightController, mut command: COMMAND_LONG_DATA) -> Result<()> {
+        // Subscribe before sending so the COMMAND_ACK can't slip past us
+        let mut messages = fc.subscribe();
+        let mut last = None;
+
+        for attempt in 1..=self.attempts {
+            // Lets the FC tell a retransmission from a new command
+            command.confirmation = (attempt - 1).min(u8::MAX as u32) as u8;
+            fc.send(MavMessage::COMMAND_LONG(command.clone())).await?;
+
+            let cmd = command.command;
+            let answer = async {
+                loop {
+                    match messages.recv().await {
+                        Ok(MavMessage::COMMAND_ACK(ack)) if ack.command == cmd => {
+                            return Ok(ack.result);
+                        }
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => {
+                            return Err(anyhow!("FC connection closed"));
+                        }
+                    }
+                }
+            };
+
+            match timeout(self.timeout, answer).await {
+                Ok(Ok(MavResult::MAV_RESULT_ACCEPTED | MavResult::MAV_RESULT_IN_PROGRESS)) => {
+                    return Ok(());
+                }
+                Ok(Ok(MavResult::MAV_RESULT_TEMPORARILY_REJECTED)) => {
+                    eprintln!(
+                        "[MAVLink] FC temporarily rejected {:?} (attempt {}/{})",
+                        cmd, attempt, self.attempts
+                    );
+                    last = Some(MavResult::MAV_RESULT_TEMPORARILY_REJECTED);
+                    if attempt < self.attempts {
+                        tokio::time::sleep(self.timeout).await;
+                    }
+                }
+                Ok(Ok(result)) => {
+                    return Err(CommandAckError {
+                        command: cmd,
+                        result: Some(result),
+                    }
+                    .into());
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_) => eprintln!(
+                    "[MAVLink] No ACK for {:?} (attempt {}/{})",
+                    cmd, attempt, self.attempts
+                ),
+            }
+        }
+
+        Err(CommandAckError {
+            command: command.command,
+            result: last,
+        }
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::FcConfig;
+    use mavlink::ardupilotmega::COMMAND_ACK_DATA;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+
+    /// Simulated FC answering successive commands with `results` (None: no
+    /// answer). Returns the `confirmation` of every command it received.
+    fn spawn_fc(
+        mut outbound: mpsc::Receiver<MavMessage>,
+        inject: broadcast::Sender<MavMessage>,
+        results: Vec<Option<MavResult>>,
+    ) -> Arc<Mutex<Vec<u8>>> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+
+        tokio::spawn(async move {
+            let mut results = results.into_iter();
+            while let Some(msg) = outbound.recv().await {
+                let MavMessage::COMMAND_LONG(cmd) = msg else {
+                    continue;
+                };
+                log.lock().unwrap().push(cmd.confirmation);
+                if let Some(Some(result)) = results.next() {
+                    let _ = inject.send(MavMessage::COMMAND_ACK(COMMAND_ACK_DATA {
+                        command: cmd.command,
+                        result,
+                        ..Default::default()
+                    }));
+                }
+            }
+        });
+
+        received
+    }
+
+    fn land() -> COMMAND_LONG_DATA {
+        COMMAND_LONG_DATA {
+            command: MavCmd::MAV_CMD_NAV_LAND,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_command_retried_until_accepted() {
+        let (fc, outbound, inject) = FlightController::test_link(FcConfig::default());
+        let results = vec![
+            None,
+            Some(MavResult::MAV_RESULT_TEMPORARILY_REJECTED),
+            Some(MavResult::MAV_RESULT_ACCEPTED),
+        ];
+        let received = spawn_fc(outbound, inject, results);
+
+        let tracker = CommandAckTracker::new(3, Duration::from_millis(50));
+        tracker.send(&fc, land()).await.unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_command_failure_reported() {
+        let (fc, outbound, inject) = FlightController::test_link(FcConfig::default());
+        spawn_fc(outbound, inject, vec![Some(MavResult::MAV_RESULT_DENIED)]);
+        let tracker = CommandAckTracker::new(3, Duration::from_millis(50));
+
+        // Refused: not sent again
+        let err = tracker.send(&fc, land()).await.unwrap_err();
+        let err = err.downcast::<CommandAckError>().unwrap();
+        assert!(err.is_refusal());
+        assert_eq!(err.to_string(), "FC answered NAV_LAND with DENIED");
+
+        // Never answered
+        let err = tracker.send(&fc, land()).await.unwrap_err();
+        let err = err.downcast::<CommandAckError>().unwrap();
+        assert_eq!(err.result, None);
+        assert!(!err.is_refusal());
+    }
+}
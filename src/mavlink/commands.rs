@@ -2,19 +2,87 @@
 //!
 //! Translates ResQTerra commands to MAVLink commands for flight controller.
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use mavlink::ardupilotmega::{
-    MavCmd, MavFrame, MavMessage,
-    COMMAND_LONG_DATA, MISSION_ITEM_INT_DATA,
+    MavAutopilot, MavCmd, MavFrame, MavMessage, MavMissionType, MavModeFlag, MavResult,
+    PositionTargetTypemask, COMMAND_LONG_DATA, LOG_REQUEST_DATA_DATA, LOG_REQUEST_END_DATA,
+    LOG_REQUEST_LIST_DATA, MISSION_ITEM_INT_DATA, MISSION_SET_CURRENT_DATA,
+    SET_POSITION_TARGET_GLOBAL_INT_DATA, SET_POSITION_TARGET_LOCAL_NED_DATA,
 };
-use resqterra_shared::{Command, CommandType, MissionStart, ReturnToHome};
+use resqterra_shared::{
+    Command, CommandType, DroneState, GpsCoordinate, GpsPosition, MissionStart, ReturnToHome,
+    ScanPattern,
+};
+use std::time::Duration;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::time::{timeout, timeout_at, Instant};
 
+use super::command_ack::{CommandAckError, CommandAckTracker};
 use super::connection::FlightController;
+use super::mission_transfer::MavMissionClient;
+use super::params::{MavParamClient, PARAM_TOLERANCE};
+use crate::geo;
+use crate::mission::{self, CameraFootprint};
+use super::log_download::{LogAssembler, LogChunk, LogEntry, LOG_CHUNK_SIZE};
+
+/// How long to wait for the FC to report a new current mission item
+const MISSION_CURRENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait for the FC heartbeat to report a commanded mode
+const MODE_CONFIRM_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Mode-set attempts before giving up
+const MODE_SET_ATTEMPTS: u32 = 3;
+
+/// How long to wait for the FC to list its onboard logs
+const LOG_LIST_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Silence after which missing LOG_DATA is requested again
+const LOG_DATA_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Re-requests of a chunk that bring no new data before the download fails
+const LOG_DATA_RETRIES: u32 = 5;
+
+/// ArduCopter parameters holding the RTL altitude (cm) and speed (cm/s)
+const RTL_ALT: &str = "RTL_ALT";
+const RTL_SPEED: &str = "RTL_SPEED";
+
+/// Distance from home within which RTH lands in place instead (m)
+pub const DEFAULT_RTH_LAND_RADIUS_M: f64 = 10.0;
+
+/// How an RTH request is flown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RthAction {
+    /// Climb to the RTL altitude and fly home (FC RTL mode)
+    ReturnToLaunch,
+    /// Already over home: descend straight down
+    Land { distance_m: f64 },
+}
 
 /// Sends commands to the flight controller via MAVLink
 pub struct MavCommandSender {
     target_system: u8,
     target_component: u8,
+    /// Prepend a takeoff to missions started on the ground
+    takeoff_climb: bool,
+    /// RTH within this distance of home lands in place (m)
+    rth_land_radius_m: f64,
+    /// Mode-set attempts before giving up
+    mode_attempts: u32,
+    /// Wait for the heartbeat to confirm each mode-set attempt
+    mode_timeout: Duration,
+    /// Footprint survey passes are spaced for
+    camera: CameraFootprint,
+    /// Reads and writes the RTL parameters
+    params: MavParamClient,
+    /// FC values of RTL parameters an RTH replaced, put back once it's over
+    rtl_saved: Arc<Mutex<Vec<(&'static str, f32)>>>,
+    /// Waits for the FC to accept each COMMAND_LONG
+    acks: CommandAckTracker,
+    /// Uploads missions through the mission protocol handshake
+    missions: MavMissionClient,
 }
 
 impl MavCommandSender {
@@ -23,11 +91,83 @@ impl MavCommandSender {
         Self {
             target_system,
             target_component,
+            takeoff_climb: true,
+            rth_land_radius_m: DEFAULT_RTH_LAND_RADIUS_M,
+            mode_attempts: MODE_SET_ATTEMPTS,
+            mode_timeout: MODE_CONFIRM_TIMEOUT,
+            camera: CameraFootprint::default(),
+            params: MavParamClient::new(target_system, target_component),
+            rtl_saved: Arc::new(Mutex::new(Vec::new())),
+            acks: CommandAckTracker::default(),
+            missions: MavMissionClient::new(target_system, target_component),
+        }
+    }
+
+    /// Enable or disable the takeoff climb before the first waypoint (default: on)
+    ///
+    /// With it, a mission started on the ground climbs to the first
+    /// waypoint's altitude before transiting, instead of heading off low.
+    pub fn with_takeoff_climb(mut self, enabled: bool) -> Self {
+        self.takeoff_climb = enabled;
+        self
+    }
+
+    /// Set the distance from home within which RTH lands in place
+    /// (default: [`DEFAULT_RTH_LAND_RADIUS_M`]; 0 always flies RTL)
+    pub fn with_rth_land_radius(mut self, radius_m: f64) -> Self {
+        self.rth_land_radius_m = radius_m;
+        self
+    }
+
+    /// Set how often a mode change is sent, and how long each attempt waits
+    /// for the FC heartbeat to confirm it (default: 3 attempts of 2s)
+    pub fn with_mode_retry(mut self, attempts: u32, timeout: Duration) -> Self {
+        self.mode_attempts = attempts.max(1);
+        self.mode_timeout = timeout;
+        self
+    }
+
+    /// Set how often a command is sent, and how long each attempt waits for
+    /// the FC's COMMAND_ACK (default: 3 attempts of 1s)
+    pub fn with_ack_retry(mut self, attempts: u32, timeout: Duration) -> Self {
+        self.acks = CommandAckTracker::new(attempts, timeout);
+        self
+    }
+
+    /// Set the camera footprint survey passes are spaced for
+    /// (default: 70° field of view, 30% sidelap)
+    pub fn with_camera_footprint(mut self, camera: CameraFootprint) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    /// How to return home from `position`
+    ///
+    /// Close to home an RTL climb-and-return only wastes battery and time, so
+    /// the drone lands where it is instead.
+    pub fn rth_action(&self, position: &GpsPosition, home: &GpsCoordinate) -> RthAction {
+        let distance_m = geo::distance_m(
+            position.latitude,
+            position.longitude,
+            home.latitude,
+            home.longitude,
+        );
+        if distance_m <= self.rth_land_radius_m {
+            RthAction::Land { distance_m }
+        } else {
+            RthAction::ReturnToLaunch
         }
     }
 
     /// Translate and send a ResQTerra command to the flight controller
-    pub async fn send_command(&self, fc: &FlightController, command: &Command) -> Result<()> {
+    ///
+    /// `state` is the current drone state; a mission start depends on it.
+    pub async fn send_command(
+        &self,
+        fc: &FlightController,
+        command: &Command,
+        state: DroneState,
+    ) -> Result<()> {
         let cmd_type = CommandType::try_from(command.cmd_type).unwrap_or(CommandType::CmdUnknown);
 
         match cmd_type {
@@ -35,7 +175,7 @@ impl MavCommandSender {
                 if let Some(resqterra_shared::command::Params::MissionStart(mission)) =
                     &command.params
                 {
-                    self.start_mission(fc, mission).await?;
+                    self.start_mission(fc, mission, is_grounded(state)).await?;
                 }
             }
             CommandType::CmdMissionAbort => {
@@ -70,7 +210,7 @@ impl MavCommandSender {
     pub async fn arm(&self, fc: &FlightController) -> Result<()> {
         println!("[MAVLink] Sending ARM command");
 
-        let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+        let command = COMMAND_LONG_DATA {
             target_system: self.target_system,
             target_component: self.target_component,
             command: MavCmd::MAV_CMD_COMPONENT_ARM_DISARM,
@@ -82,16 +222,16 @@ impl MavCommandSender {
             param5: 0.0,
             param6: 0.0,
             param7: 0.0,
-        });
+        };
 
-        fc.send(msg).await
+        self.acks.send(fc, command).await
     }
 
     /// Disarm the drone
     pub async fn disarm(&self, fc: &FlightController) -> Result<()> {
         println!("[MAVLink] Sending DISARM command");
 
-        let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+        let command = COMMAND_LONG_DATA {
             target_system: self.target_system,
             target_component: self.target_component,
             command: MavCmd::MAV_CMD_COMPONENT_ARM_DISARM,
@@ -103,16 +243,16 @@ impl MavCommandSender {
             param5: 0.0,
             param6: 0.0,
             param7: 0.0,
-        });
+        };
 
-        fc.send(msg).await
+        self.acks.send(fc, command).await
     }
 
     /// Take off to specified altitude
     pub async fn takeoff(&self, fc: &FlightController, altitude_m: f32) -> Result<()> {
         println!("[MAVLink] Sending TAKEOFF to {}m", altitude_m);
 
-        let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+        let command = COMMAND_LONG_DATA {
             target_system: self.target_system,
             target_component: self.target_component,
             command: MavCmd::MAV_CMD_NAV_TAKEOFF,
@@ -124,16 +264,16 @@ impl MavCommandSender {
             param5: f32::NAN,   // Latitude (NAN = current)
             param6: f32::NAN,   // Longitude (NAN = current)
             param7: altitude_m, // Altitude
-        });
+        };
 
-        fc.send(msg).await
+        self.acks.send(fc, command).await
     }
 
     /// Land at current position
     pub async fn land(&self, fc: &FlightController) -> Result<()> {
         println!("[MAVLink] Sending LAND command");
 
-        let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+        let command = COMMAND_LONG_DATA {
             target_system: self.target_system,
             target_component: self.target_component,
             command: MavCmd::MAV_CMD_NAV_LAND,
@@ -145,53 +285,82 @@ impl MavCommandSender {
             param5: f32::NAN, // Latitude
             param6: f32::NAN, // Longitude
             param7: 0.0,      // Altitude
-        });
+        };
 
-        fc.send(msg).await
+        self.acks.send(fc, command).await
     }
 
     /// Return to home/launch position
+    ///
+    /// A non-zero `rth.altitude_m` or `rth.speed_mps` is written to `RTL_ALT` /
+    /// `RTL_SPEED` before switching to RTL, and the FC's own values are put
+    /// back once the drone disarms or leaves RTL. If the FC doesn't take them,
+    /// it returns with its own values rather than not at all.
     pub async fn return_to_home(&self, fc: &FlightController, rth: &ReturnToHome) -> Result<()> {
         println!("[MAVLink] Sending RTL command");
 
-        // Use COMMAND_LONG to set RTL mode
-        let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
-            target_system: self.target_system,
-            target_component: self.target_component,
-            command: MavCmd::MAV_CMD_DO_SET_MODE,
-            confirmation: 0,
-            param1: 1.0, // MAV_MODE_FLAG_CUSTOM_MODE_ENABLED
-            param2: 6.0, // RTL mode for ArduPilot (mode number 6)
-            param3: 0.0,
-            param4: 0.0,
-            param5: 0.0,
-            param6: 0.0,
-            param7: 0.0,
-        });
-
-        fc.send(msg).await?;
+        if let Err(e) = self.apply_rtl_params(fc, rth).await {
+            eprintln!("[MAVLink] RTL altitude/speed not applied, using FC values: {}", e);
+        }
+        let result = self.set_mode(fc, ArduPilotMode::Rtl).await;
 
-        // Optionally set RTL altitude if specified
-        if rth.altitude_m > 0.0 {
-            // This would require setting the RTL_ALT parameter
-            // For now, we just use the default RTL altitude
-            println!("[MAVLink] RTL altitude: {}m (using default)", rth.altitude_m);
+        // Also when RTL wasn't confirmed: the values then go back right away
+        if !self.rtl_saved.lock().await.is_empty() {
+            tokio::spawn(restore_after_rtl(
+                fc.clone(),
+                self.params.clone(),
+                self.rtl_saved.clone(),
+            ));
         }
+        result
+    }
 
+    /// Write the RTH's altitude and speed over the FC's RTL parameters,
+    /// remembering the values they replace
+    async fn apply_rtl_params(&self, fc: &FlightController, rth: &ReturnToHome) -> Result<()> {
+        let wanted = [
+            (RTL_ALT, rth.altitude_m),
+            (RTL_SPEED, rth.speed_mps),
+        ];
+        let mut saved = self.rtl_saved.lock().await;
+        for (name, value_m) in wanted {
+            let original = saved.iter().find(|(n, _)| *n == name).map(|(_, v)| *v);
+            if value_m <= 0.0 {
+                // An earlier RTH's value this one doesn't repeat goes back
+                if let Some(original) = original {
+                    self.params.set(fc, name, original).await?;
+                    saved.retain(|(n, _)| *n != name);
+                }
+                continue;
+            }
+            if original.is_none() {
+                let original = self.params.get(fc, name).await?;
+                saved.push((name, original));
+            }
+            self.params.set(fc, name, value_m * 100.0).await?;
+        }
         Ok(())
     }
 
     /// Start a mission
-    pub async fn start_mission(&self, fc: &FlightController, mission: &MissionStart) -> Result<()> {
+    ///
+    /// `grounded` says whether the drone is still on the ground, in which case
+    /// the mission starts with a takeoff (see [`Self::with_takeoff_climb`]).
+    pub async fn start_mission(
+        &self,
+        fc: &FlightController,
+        mission: &MissionStart,
+        grounded: bool,
+    ) -> Result<()> {
         println!("[MAVLink] Starting mission: {}", mission.mission_id);
 
         // First, upload mission waypoints
         if let Some(ref area) = mission.survey_area {
-            self.upload_mission_waypoints(fc, mission, area).await?;
+            self.upload_mission_waypoints(fc, mission, area, grounded).await?;
         }
 
         // Then start the mission
-        let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+        let command = COMMAND_LONG_DATA {
             target_system: self.target_system,
             target_component: self.target_component,
             command: MavCmd::MAV_CMD_MISSION_START,
@@ -203,71 +372,164 @@ impl MavCommandSender {
             param5: 0.0,
             param6: 0.0,
             param7: 0.0,
-        });
+        };
 
-        fc.send(msg).await
+        self.acks.send(fc, command).await
     }
 
-    /// Upload mission waypoints to flight controller
+    /// Load the mission's items onto the FC without starting it
+    ///
+    /// Completes once the FC's mission reads back as uploaded (see
+    /// [`MavMissionClient::verify`]); nothing is uploaded when the mission has
+    /// no survey area.
+    pub async fn load_mission(
+        &self,
+        fc: &FlightController,
+        mission: &MissionStart,
+        state: DroneState,
+    ) -> Result<()> {
+        let Some(ref area) = mission.survey_area else {
+            return Ok(());
+        };
+        self.upload_mission_waypoints(fc, mission, area, is_grounded(state)).await
+    }
+
+    /// Upload mission waypoints to flight controller and verify them
     async fn upload_mission_waypoints(
         &self,
         fc: &FlightController,
         mission: &MissionStart,
         area: &resqterra_shared::SurveyArea,
+        grounded: bool,
     ) -> Result<()> {
-        println!("[MAVLink] Uploading {} waypoints", area.boundary.len());
+        let items = self.mission_items(mission, area, grounded);
+        println!("[MAVLink] Uploading {} mission items", items.len());
 
-        // For a lawnmower pattern, we'd generate waypoints here
-        // For now, just upload the boundary points as a simple mission
+        self.missions.upload(fc, &items).await?;
+        self.missions.verify(fc, &items).await
+    }
 
-        for (i, point) in area.boundary.iter().enumerate() {
-            let msg = MavMessage::MISSION_ITEM_INT(MISSION_ITEM_INT_DATA {
-                target_system: self.target_system,
-                target_component: self.target_component,
-                seq: i as u16,
-                frame: MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT_INT,
-                command: MavCmd::MAV_CMD_NAV_WAYPOINT,
-                current: if i == 0 { 1 } else { 0 },
-                autocontinue: 1,
-                param1: 0.0,  // Hold time
-                param2: 2.0,  // Acceptance radius
-                param3: 0.0,  // Pass through
-                param4: 0.0,  // Yaw
-                x: (point.latitude * 1e7) as i32,
-                y: (point.longitude * 1e7) as i32,
-                z: if point.altitude_m > 0.0 {
-                    point.altitude_m
-                } else {
-                    mission.altitude_m
-                },
-            });
+    /// Mission items for a survey area, with a leading takeoff when grounded
+    ///
+    /// The area is covered with the requested scan pattern; an unknown or
+    /// custom pattern flies the boundary as given. A survey speed is set
+    /// before the first waypoint.
+    fn mission_items(
+        &self,
+        mission: &MissionStart,
+        area: &resqterra_shared::SurveyArea,
+        grounded: bool,
+    ) -> Vec<MISSION_ITEM_INT_DATA> {
+        let altitude = |point: &resqterra_shared::GpsCoordinate| {
+            if point.altitude_m > 0.0 {
+                point.altitude_m
+            } else {
+                mission.altitude_m
+            }
+        };
 
-            fc.send(msg).await?;
+        let pattern = ScanPattern::try_from(mission.scan_pattern).unwrap_or(ScanPattern::PatternUnknown);
+        let spacing_m = self.camera.line_spacing_m(mission.altitude_m);
+        let path = mission::generate_pattern(pattern, &area.boundary, mission.altitude_m, spacing_m)
+            .unwrap_or_else(|| area.boundary.clone());
+
+        // Climb in place to the first waypoint's altitude before transiting
+        let climb = match path.first() {
+            Some(first) if grounded && self.takeoff_climb => Some(altitude(first)),
+            _ => None,
+        };
+        let takeoff = climb.map(|z| MISSION_ITEM_INT_DATA {
+            target_system: self.target_system,
+            target_component: self.target_component,
+            seq: 0,
+            frame: MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT_INT,
+            command: MavCmd::MAV_CMD_NAV_TAKEOFF,
+            current: 0,
+            autocontinue: 1,
+            mission_type: MavMissionType::MAV_MISSION_TYPE_MISSION,
+            param1: 0.0,      // Minimum pitch
+            param2: 0.0,      // Empty
+            param3: 0.0,      // Empty
+            param4: f32::NAN, // Yaw angle (NAN = current)
+            x: 0,             // Current position
+            y: 0,
+            z,
+        });
+
+        let speed = (mission.speed_mps > 0.0).then(|| MISSION_ITEM_INT_DATA {
+            target_system: self.target_system,
+            target_component: self.target_component,
+            seq: 0,
+            frame: MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT_INT,
+            command: MavCmd::MAV_CMD_DO_CHANGE_SPEED,
+            current: 0,
+            autocontinue: 1,
+            mission_type: MavMissionType::MAV_MISSION_TYPE_MISSION,
+            param1: 1.0,               // Ground speed
+            param2: mission.speed_mps, // Speed (m/s)
+            param3: -1.0,              // Throttle (-1 = no change)
+            param4: 0.0,
+            x: 0,
+            y: 0,
+            z: 0.0,
+        });
+
+        let waypoints = path.iter().map(|point| MISSION_ITEM_INT_DATA {
+            target_system: self.target_system,
+            target_component: self.target_component,
+            seq: 0,
+            frame: MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT_INT,
+            command: MavCmd::MAV_CMD_NAV_WAYPOINT,
+            current: 0,
+            autocontinue: 1,
+            mission_type: MavMissionType::MAV_MISSION_TYPE_MISSION,
+            param1: 0.0,  // Hold time
+            param2: 2.0,  // Acceptance radius
+            param3: 0.0,  // Pass through
+            param4: 0.0,  // Yaw
+            x: (point.latitude * 1e7) as i32,
+            y: (point.longitude * 1e7) as i32,
+            z: altitude(point),
+        });
+
+        let mut items: Vec<_> = takeoff.into_iter().chain(speed).chain(waypoints).collect();
+        for (i, item) in items.iter_mut().enumerate() {
+            item.seq = i as u16;
+            item.current = if i == 0 { 1 } else { 0 };
         }
+        items
+    }
 
-        Ok(())
+    /// Location of each mission item [`Self::start_mission`] uploads, for progress
+    ///
+    /// Navigation waypoints map to their position; takeoff and speed items
+    /// to `None`. Empty when the mission has no survey area.
+    pub fn mission_path(
+        &self,
+        mission: &MissionStart,
+        state: DroneState,
+    ) -> Vec<Option<GpsCoordinate>> {
+        let Some(ref area) = mission.survey_area else {
+            return Vec::new();
+        };
+        self.mission_items(mission, area, is_grounded(state))
+            .iter()
+            .map(|item| {
+                (item.command == MavCmd::MAV_CMD_NAV_WAYPOINT).then(|| GpsCoordinate {
+                    latitude: item.x as f64 / 1e7,
+                    longitude: item.y as f64 / 1e7,
+                    altitude_m: item.z,
+                })
+            })
+            .collect()
     }
 
     /// Abort current mission
     pub async fn abort_mission(&self, fc: &FlightController) -> Result<()> {
         println!("[MAVLink] Aborting mission - switching to LOITER");
 
-        // Switch to LOITER mode (hold position) using COMMAND_LONG
-        let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
-            target_system: self.target_system,
-            target_component: self.target_component,
-            command: MavCmd::MAV_CMD_DO_SET_MODE,
-            confirmation: 0,
-            param1: 1.0, // MAV_MODE_FLAG_CUSTOM_MODE_ENABLED
-            param2: 5.0, // LOITER mode for ArduPilot
-            param3: 0.0,
-            param4: 0.0,
-            param5: 0.0,
-            param6: 0.0,
-            param7: 0.0,
-        });
-
-        fc.send(msg).await
+        // Switch to LOITER mode (hold position)
+        self.set_mode(fc, ArduPilotMode::Loiter).await
     }
 
     /// Emergency stop - kills motors immediately
@@ -275,7 +537,7 @@ impl MavCommandSender {
         println!("[MAVLink] EMERGENCY STOP - killing motors!");
 
         // Force disarm (even while flying - DANGEROUS!)
-        let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+        let command = COMMAND_LONG_DATA {
             target_system: self.target_system,
             target_component: self.target_component,
             command: MavCmd::MAV_CMD_COMPONENT_ARM_DISARM,
@@ -287,9 +549,9 @@ impl MavCommandSender {
             param5: 0.0,
             param6: 0.0,
             param7: 0.0,
-        });
+        };
 
-        fc.send(msg).await
+        self.acks.send(fc, command).await
     }
 
     /// Request status/data streams from FC
@@ -297,7 +559,7 @@ impl MavCommandSender {
         println!("[MAVLink] Requesting data streams");
 
         // Request all data streams
-        let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+        let command = COMMAND_LONG_DATA {
             target_system: self.target_system,
             target_component: self.target_component,
             command: MavCmd::MAV_CMD_SET_MESSAGE_INTERVAL,
@@ -309,30 +571,298 @@ impl MavCommandSender {
             param5: 0.0,
             param6: 0.0,
             param7: 0.0,
-        });
+        };
 
-        fc.send(msg).await
+        self.acks.send(fc, command).await
     }
 
-    /// Set flight mode
+    /// Set flight mode and wait for the FC heartbeat to report it
+    ///
+    /// The FC can ignore a mode change without saying so, so the command is
+    /// resent until the heartbeat's `custom_mode` matches or the attempts run
+    /// out (see [`Self::with_mode_retry`]). A COMMAND_ACK refusing the mode
+    /// fails it right away.
     pub async fn set_mode(&self, fc: &FlightController, mode: ArduPilotMode) -> Result<()> {
         println!("[MAVLink] Setting mode to {:?}", mode);
 
-        let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+        // Subscribe before sending so the confirming HEARTBEAT can't slip past us
+        let mut messages = fc.subscribe();
+
+        for attempt in 1..=self.mode_attempts {
+            let msg = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+                target_system: self.target_system,
+                target_component: self.target_component,
+                command: MavCmd::MAV_CMD_DO_SET_MODE,
+                confirmation: (attempt - 1).min(u8::MAX as u32) as u8,
+                param1: 1.0, // MAV_MODE_FLAG_CUSTOM_MODE_ENABLED
+                param2: mode as u32 as f32,
+                param3: 0.0,
+                param4: 0.0,
+                param5: 0.0,
+                param6: 0.0,
+                param7: 0.0,
+            });
+            fc.send(msg).await?;
+
+            let confirmed = async {
+                loop {
+                    match messages.recv().await {
+                        // GCS heartbeats carry no flight mode
+                        Ok(MavMessage::HEARTBEAT(hb))
+                            if hb.autopilot != MavAutopilot::MAV_AUTOPILOT_INVALID
+                                && hb.custom_mode == mode as u32 =>
+                        {
+                            return Ok(());
+                        }
+                        // Temporarily rejected: sent again after the timeout
+                        Ok(MavMessage::COMMAND_ACK(ack))
+                            if ack.command == MavCmd::MAV_CMD_DO_SET_MODE
+                                && !matches!(
+                                    ack.result,
+                                    MavResult::MAV_RESULT_ACCEPTED
+                                        | MavResult::MAV_RESULT_IN_PROGRESS
+                                        | MavResult::MAV_RESULT_TEMPORARILY_REJECTED
+                                ) =>
+                        {
+                            return Err(CommandAckError {
+                                command: ack.command,
+                                result: Some(ack.result),
+                            }
+                            .into());
+                        }
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => {
+                            return Err(anyhow!("FC connection closed"));
+                        }
+                    }
+                }
+            };
+
+            match timeout(self.mode_timeout, confirmed).await {
+                Ok(result) => return result,
+                Err(_) => eprintln!(
+                    "[MAVLink] FC not in {:?} yet (attempt {}/{})",
+                    mode, attempt, self.mode_attempts
+                ),
+            }
+        }
+
+        Err(anyhow!(
+            "FC did not switch to {:?} after {} attempts",
+            mode,
+            self.mode_attempts
+        ))
+    }
+
+    /// Write a parameter on the flight controller (PARAM_SET)
+    ///
+    /// Returns the value the FC echoed back, which may differ from `value`
+    /// if it clamped or refused the write.
+    pub async fn set_param(&self, fc: &FlightController, name: &str, value: f32) -> Result<f32> {
+        self.params.write(fc, name, value).await
+    }
+
+    /// Make `seq` the current mission item and wait for the FC to confirm it
+    pub async fn set_current_waypoint(&self, fc: &FlightController, seq: u16) -> Result<u16> {
+        println!("[MAVLink] Setting current mission item to {}", seq);
+
+        // Subscribe before requesting so the MISSION_CURRENT can't slip past us
+        let mut messages = fc.subscribe();
+
+        let msg = MavMessage::MISSION_SET_CURRENT(MISSION_SET_CURRENT_DATA {
+            seq,
             target_system: self.target_system,
             target_component: self.target_component,
-            command: MavCmd::MAV_CMD_DO_SET_MODE,
-            confirmation: 0,
-            param1: 1.0, // MAV_MODE_FLAG_CUSTOM_MODE_ENABLED
-            param2: mode as u32 as f32,
-            param3: 0.0,
-            param4: 0.0,
-            param5: 0.0,
-            param6: 0.0,
-            param7: 0.0,
         });
+        fc.send(msg).await?;
 
-        fc.send(msg).await
+        let wait = async {
+            loop {
+                match messages.recv().await {
+                    Ok(MavMessage::MISSION_CURRENT(current)) if current.seq == seq => {
+                        return Ok(current.seq);
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Err(anyhow!("FC connection closed"));
+                    }
+                }
+            }
+        };
+
+        timeout(MISSION_CURRENT_TIMEOUT, wait)
+            .await
+            .map_err(|_| anyhow!("FC did not confirm mission item {}", seq))?
+    }
+
+    /// List the onboard logs stored on the FC (LOG_REQUEST_LIST)
+    pub async fn list_logs(&self, fc: &FlightController) -> Result<Vec<LogEntry>> {
+        // Subscribe before requesting so no LOG_ENTRY can slip past us
+        let mut messages = fc.subscribe();
+
+        let msg = MavMessage::LOG_REQUEST_LIST(LOG_REQUEST_LIST_DATA {
+            start: 0,
+            end: u16::MAX,
+            target_system: self.target_system,
+            target_component: self.target_component,
+        });
+        fc.send(msg).await?;
+
+        let mut entries = BTreeMap::new();
+        let collect = async {
+            loop {
+                match messages.recv().await {
+                    Ok(MavMessage::LOG_ENTRY(entry)) => {
+                        // An FC without logs answers with a single empty entry
+                        if entry.num_logs == 0 {
+                            return Ok(());
+                        }
+                        entries.insert(
+                            entry.id,
+                            LogEntry {
+                                id: entry.id,
+                                size: entry.size,
+                                time_utc: entry.time_utc,
+                            },
+                        );
+                        if entries.len() >= entry.num_logs as usize {
+                            return Ok(());
+                        }
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Err(anyhow!("FC connection closed"));
+                    }
+                }
+            }
+        };
+
+        timeout(LOG_LIST_TIMEOUT, collect)
+            .await
+            .map_err(|_| anyhow!("FC did not list its logs"))??;
+        Ok(entries.into_values().collect())
+    }
+
+    /// Download a log (LOG_REQUEST_DATA), sending each chunk as it completes
+    ///
+    /// Starts at chunk `first_chunk` so an interrupted transfer can resume.
+    /// Ranges the FC (or a lagging subscriber) dropped are requested again.
+    /// Stops early, without error, once `chunks` is closed.
+    pub async fn download_log(
+        &self,
+        fc: &FlightController,
+        entry: LogEntry,
+        first_chunk: u32,
+        chunks: mpsc::Sender<LogChunk>,
+    ) -> Result<()> {
+        println!(
+            "[MAVLink] Downloading log {} ({} bytes) from chunk {}",
+            entry.id, entry.size, first_chunk
+        );
+
+        let mut messages = fc.subscribe();
+        let mut assembler = LogAssembler::new(entry, LOG_CHUNK_SIZE, first_chunk);
+
+        let result = async {
+            while !assembler.is_complete() {
+                let mut stalled = 0;
+                loop {
+                    let gaps = assembler.gaps();
+                    if gaps.is_empty() {
+                        break;
+                    }
+                    if stalled == LOG_DATA_RETRIES {
+                        return Err(anyhow!("Log {} stalled at offset {}", entry.id, gaps[0].0));
+                    }
+
+                    for (ofs, count) in gaps {
+                        fc.send(MavMessage::LOG_REQUEST_DATA(LOG_REQUEST_DATA_DATA {
+                            ofs,
+                            count,
+                            id: entry.id,
+                            target_system: self.target_system,
+                            target_component: self.target_component,
+                        }))
+                        .await?;
+                    }
+
+                    // Collect until the chunk is whole or no new LOG_DATA for a while
+                    let mut progressed = false;
+                    let mut deadline = Instant::now() + LOG_DATA_TIMEOUT;
+                    loop {
+                        match timeout_at(deadline, messages.recv()).await {
+                            Ok(Ok(MavMessage::LOG_DATA(data))) => {
+                                if assembler.insert(&data) {
+                                    progressed = true;
+                                    deadline = Instant::now() + LOG_DATA_TIMEOUT;
+                                    if assembler.gaps().is_empty() {
+                                        break;
+                                    }
+                                }
+                            }
+                            Ok(Ok(_)) | Ok(Err(broadcast::error::RecvError::Lagged(_))) => {}
+                            Ok(Err(broadcast::error::RecvError::Closed)) => {
+                                return Err(anyhow!("FC connection closed"));
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    stalled = if progressed { 0 } else { stalled + 1 };
+                }
+
+                if let Some(chunk) = assembler.take_chunk() {
+                    if chunks.send(chunk).await.is_err() {
+                        println!("[MAVLink] Log {} download cancelled", entry.id);
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        // Let the FC resume normal logging whatever happened
+        let end = MavMessage::LOG_REQUEST_END(LOG_REQUEST_END_DATA {
+            target_system: self.target_system,
+            target_component: self.target_component,
+        });
+        fc.send(end).await?;
+        result
+    }
+
+    /// Write the FC-side failsafe parameters and verify the values the FC
+    /// echoes back
+    ///
+    /// The FC then handles link loss and low battery on its own, even if the
+    /// companion computer dies. Any parameter the FC didn't keep as written
+    /// is reported in the returned [`FailsafeReport`].
+    pub async fn configure_failsafes(
+        &self,
+        fc: &FlightController,
+        params: &FailsafeParams,
+    ) -> Result<FailsafeReport> {
+        println!("[MAVLink] Configuring FC failsafes");
+
+        let mut report = FailsafeReport::default();
+        for (name, expected) in params.to_params() {
+            let actual = match self.set_param(fc, name, expected).await {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    eprintln!("[MAVLink] Read-back of {} failed: {}", name, e);
+                    None
+                }
+            };
+
+            if actual.is_none_or(|v| (v - expected).abs() > PARAM_TOLERANCE) {
+                report.mismatches.push(ParamMismatch {
+                    name: name.to_string(),
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        Ok(report)
     }
 
     /// Go to a specific GPS position
@@ -356,6 +886,7 @@ impl MavCommandSender {
             command: MavCmd::MAV_CMD_NAV_WAYPOINT,
             current: 2, // Guided mode waypoint
             autocontinue: 0,
+            mission_type: MavMissionType::MAV_MISSION_TYPE_MISSION,
             param1: 0.0,
             param2: 0.0,
             param3: 0.0,
@@ -367,6 +898,230 @@ impl MavCommandSender {
 
         fc.send(msg).await
     }
+
+    /// Steer at a velocity in the local NED frame (m/s, down positive),
+    /// optionally turning at `yaw_rate` (rad/s)
+    ///
+    /// Only honored in GUIDED mode (see [`Self::set_mode`]). ArduCopter stops
+    /// the drone when no setpoint arrives for 3s, so callers steering
+    /// continuously resend it well within that.
+    pub async fn set_velocity_ned(
+        &self,
+        fc: &FlightController,
+        north: f32,
+        east: f32,
+        down: f32,
+        yaw_rate: Option<f32>,
+    ) -> Result<()> {
+        if ![north, east, down, yaw_rate.unwrap_or(0.0)].iter().all(|v| v.is_finite()) {
+            return Err(anyhow!("Invalid velocity setpoint ({}, {}, {})", north, east, down));
+        }
+
+        let mut type_mask = PositionTargetTypemask::POSITION_TARGET_TYPEMASK_X_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_Y_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_Z_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_AX_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_AY_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_AZ_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_YAW_IGNORE;
+        if yaw_rate.is_none() {
+            type_mask |= PositionTargetTypemask::POSITION_TARGET_TYPEMASK_YAW_RATE_IGNORE;
+        }
+        let msg = MavMessage::SET_POSITION_TARGET_LOCAL_NED(SET_POSITION_TARGET_LOCAL_NED_DATA {
+            time_boot_ms: 0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            vx: north,
+            vy: east,
+            vz: down,
+            afx: 0.0,
+            afy: 0.0,
+            afz: 0.0,
+            yaw: 0.0,
+            yaw_rate: yaw_rate.unwrap_or(0.0),
+            type_mask,
+            target_system: self.target_system,
+            target_component: self.target_component,
+            coordinate_frame: MavFrame::MAV_FRAME_LOCAL_NED,
+        });
+
+        fc.send(msg).await
+    }
+
+    /// Fly to `target` (altitude above home) and hold there, optionally
+    /// facing `yaw_deg` (0 = north, clockwise)
+    ///
+    /// Only honored in GUIDED mode (see [`Self::set_mode`]); a new target
+    /// replaces the previous one.
+    pub async fn set_position_target(
+        &self,
+        fc: &FlightController,
+        target: &GpsCoordinate,
+        yaw_deg: Option<f32>,
+    ) -> Result<()> {
+        let valid = (-90.0..=90.0).contains(&target.latitude)
+            && (-180.0..=180.0).contains(&target.longitude)
+            && target.altitude_m.is_finite()
+            && yaw_deg.unwrap_or(0.0).is_finite();
+        if !valid {
+            return Err(anyhow!(
+                "Invalid position target ({}, {}, {}m)",
+                target.latitude,
+                target.longitude,
+                target.altitude_m
+            ));
+        }
+
+        let mut type_mask = PositionTargetTypemask::POSITION_TARGET_TYPEMASK_VX_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_VY_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_VZ_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_AX_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_AY_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_AZ_IGNORE
+            | PositionTargetTypemask::POSITION_TARGET_TYPEMASK_YAW_RATE_IGNORE;
+        if yaw_deg.is_none() {
+            type_mask |= PositionTargetTypemask::POSITION_TARGET_TYPEMASK_YAW_IGNORE;
+        }
+        let msg = MavMessage::SET_POSITION_TARGET_GLOBAL_INT(SET_POSITION_TARGET_GLOBAL_INT_DATA {
+            time_boot_ms: 0,
+            lat_int: (target.latitude * 1e7) as i32,
+            lon_int: (target.longitude * 1e7) as i32,
+            alt: target.altitude_m,
+            vx: 0.0,
+            vy: 0.0,
+            vz: 0.0,
+            afx: 0.0,
+            afy: 0.0,
+            afz: 0.0,
+            yaw: yaw_deg.unwrap_or(0.0).to_radians(),
+            yaw_rate: 0.0,
+            type_mask,
+            target_system: self.target_system,
+            target_component: self.target_component,
+            coordinate_frame: MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT_INT,
+        });
+
+        fc.send(msg).await
+    }
+}
+
+/// Whether the drone is on the ground in `state`
+fn is_grounded(state: DroneState) -> bool {
+    matches!(
+        state,
+        DroneState::DroneIdle | DroneState::DronePreflight | DroneState::DroneArmed
+    )
+}
+
+/// Put back the RTL parameters in `saved` once the drone disarms or leaves RTL
+async fn restore_after_rtl(
+    fc: FlightController,
+    params: MavParamClient,
+    saved: Arc<Mutex<Vec<(&'static str, f32)>>>,
+) {
+    let mut messages = fc.subscribe();
+    loop {
+        match messages.recv().await {
+            // GCS heartbeats carry no flight mode
+            Ok(MavMessage::HEARTBEAT(hb))
+                if hb.autopilot != MavAutopilot::MAV_AUTOPILOT_INVALID =>
+            {
+                let armed = hb.base_mode.contains(MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED);
+                if !armed || hb.custom_mode != ArduPilotMode::Rtl as u32 {
+                    break;
+                }
+            }
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+
+    // Another watcher (from a repeated RTH) may have restored them already
+    let mut saved = saved.lock().await;
+    for (name, value) in saved.drain(..) {
+        match params.set(&fc, name, value).await {
+            Ok(_) => println!("[MAVLink] {} restored to {}", name, value),
+            Err(e) => eprintln!("[MAVLink] Can't restore {}: {}", name, e),
+        }
+    }
+}
+
+/// Encode a parameter name into the fixed, NUL-padded MAVLink param_id field
+pub(super) fn param_id(name: &str) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    let bytes = name.as_bytes();
+    let len = bytes.len().min(id.len());
+    id[..len].copy_from_slice(&bytes[..len]);
+    id
+}
+
+/// Decode a MAVLink param_id field into a parameter name
+pub(super) fn param_name(id: &[u8; 16]) -> String {
+    let end = id.iter().position(|&b| b == 0).unwrap_or(id.len());
+    String::from_utf8_lossy(&id[..end]).to_string()
+}
+
+/// Flight controller failsafe configuration (ArduCopter parameter names)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailsafeParams {
+    /// FS_GCS_ENABLE - action on GCS link loss (1 = RTL)
+    pub gcs_enable: u8,
+    /// FS_GCS_TIMEOUT - seconds without GCS heartbeat before failsafe
+    pub gcs_timeout_s: f32,
+    /// FS_OPTIONS - failsafe option bitmask
+    pub options: u32,
+    /// BATT_LOW_VOLT - low battery voltage threshold
+    pub batt_low_volt: f32,
+    /// BATT_FS_LOW_ACT - action on low battery (2 = RTL)
+    pub batt_low_action: u8,
+}
+
+impl Default for FailsafeParams {
+    fn default() -> Self {
+        Self {
+            gcs_enable: 1,
+            gcs_timeout_s: 5.0,
+            options: 0,
+            batt_low_volt: 10.5,
+            batt_low_action: 2,
+        }
+    }
+}
+
+impl FailsafeParams {
+    /// Parameter writes in the order they are applied
+    pub fn to_params(&self) -> Vec<(&'static str, f32)> {
+        vec![
+            ("FS_GCS_ENABLE", self.gcs_enable as f32),
+            ("FS_GCS_TIMEOUT", self.gcs_timeout_s),
+            ("FS_OPTIONS", self.options as f32),
+            ("BATT_LOW_VOLT", self.batt_low_volt),
+            ("BATT_FS_LOW_ACT", self.batt_low_action as f32),
+        ]
+    }
+}
+
+/// A failsafe parameter that didn't read back as written
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamMismatch {
+    pub name: String,
+    pub expected: f32,
+    /// Value reported by the FC (None if it never answered)
+    pub actual: Option<f32>,
+}
+
+/// Outcome of [`MavCommandSender::configure_failsafes`]
+#[derive(Debug, Clone, Default)]
+pub struct FailsafeReport {
+    pub mismatches: Vec<ParamMismatch>,
+}
+
+impl FailsafeReport {
+    /// True if every parameter was verified
+    pub fn is_verified(&self) -> bool {
+        self.mismatches.is_empty()
+    }
 }
 
 /// ArduPilot Copter flight modes
@@ -403,6 +1158,11 @@ pub enum ArduPilotMode {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mavlink::FcConfig;
+    use mavlink::ardupilotmega::{MavParamType, HEARTBEAT_DATA, LOG_DATA_DATA, PARAM_VALUE_DATA};
+    use resqterra_shared::{GpsCoordinate, SurveyArea};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn test_ardupilot_modes() {
@@ -410,4 +1170,397 @@ mod tests {
         assert_eq!(ArduPilotMode::Rtl as u32, 6);
         assert_eq!(ArduPilotMode::Land as u32, 9);
     }
+
+    #[test]
+    fn test_takeoff_prepended_only_when_grounded() {
+        let mission = MissionStart {
+            mission_id: "survey-1".into(),
+            altitude_m: 40.0,
+            survey_area: Some(SurveyArea {
+                boundary: vec![
+                    GpsCoordinate {
+                        latitude: 47.1,
+                        longitude: 8.5,
+                        altitude_m: 0.0, // Mission altitude
+                    },
+                    GpsCoordinate {
+                        latitude: 47.2,
+                        longitude: 8.5,
+                        altitude_m: 60.0,
+                    },
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let area = mission.survey_area.as_ref().unwrap();
+        let sender = MavCommandSender::new(1, 1);
+
+        let grounded = sender.mission_items(&mission, area, is_grounded(DroneState::DroneArmed));
+        assert_eq!(grounded.len(), 3);
+        assert_eq!(grounded[0].command, MavCmd::MAV_CMD_NAV_TAKEOFF);
+        assert_eq!((grounded[0].seq, grounded[0].current, grounded[0].z), (0, 1, 40.0));
+        assert!(grounded[1..]
+            .iter()
+            .all(|item| item.command == MavCmd::MAV_CMD_NAV_WAYPOINT && item.current == 0));
+        assert_eq!(grounded.iter().map(|item| item.seq).collect::<Vec<_>>(), [0, 1, 2]);
+
+        // Already airborne: straight to the first waypoint
+        let airborne = sender.mission_items(&mission, area, is_grounded(DroneState::DroneInMission));
+        assert_eq!(airborne.len(), 2);
+        assert_eq!(airborne[0].command, MavCmd::MAV_CMD_NAV_WAYPOINT);
+        assert_eq!((airborne[0].seq, airborne[0].current), (0, 1));
+
+        // Progress is tracked along the same items
+        let path = sender.mission_path(&mission, DroneState::DroneArmed);
+        assert_eq!(path.len(), 3);
+        assert!(path[0].is_none());
+        let last = path[2].unwrap();
+        assert!((last.latitude - 47.2).abs() < 1e-6);
+        assert_eq!(last.altitude_m, 60.0);
+
+        // Climb disabled
+        let sender = MavCommandSender::new(1, 1).with_takeoff_climb(false);
+        assert_eq!(sender.mission_items(&mission, area, true).len(), 2);
+    }
+
+    #[test]
+    fn test_survey_pattern_flown_at_mission_speed() {
+        // ~370 m x 220 m, swept every ~39 m at 40 m
+        let corner = |latitude, longitude| GpsCoordinate {
+            latitude,
+            longitude,
+            altitude_m: 0.0,
+        };
+        let mission = MissionStart {
+            mission_id: "survey-1".into(),
+            altitude_m: 40.0,
+            speed_mps: 5.5,
+            scan_pattern: ScanPattern::PatternLawnmower.into(),
+            survey_area: Some(SurveyArea {
+                boundary: vec![
+                    corner(47.500, 8.500),
+                    corner(47.500, 8.505),
+                    corner(47.502, 8.505),
+                    corner(47.502, 8.500),
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let area = mission.survey_area.as_ref().unwrap();
+        let items = MavCommandSender::new(1, 1).mission_items(&mission, area, false);
+
+        assert_eq!(items[0].command, MavCmd::MAV_CMD_DO_CHANGE_SPEED);
+        assert_eq!(items[0].param2, 5.5);
+        let waypoints = &items[1..];
+        assert_eq!(waypoints.len(), 2 * 6);
+        assert!(waypoints
+            .iter()
+            .all(|item| item.command == MavCmd::MAV_CMD_NAV_WAYPOINT && item.z == 40.0));
+    }
+
+    #[test]
+    fn test_param_id_roundtrip() {
+        assert_eq!(param_name(&param_id("FS_GCS_ENABLE")), "FS_GCS_ENABLE");
+        // Names are truncated to the 16-byte field
+        assert_eq!(param_name(&param_id("A_VERY_LONG_PARAMETER")), "A_VERY_LONG_PARA");
+    }
+
+    /// Simulated FC that stores PARAM_SET writes (optionally clamping some)
+    /// and answers PARAM_REQUEST_READ. Returns the log of writes it received.
+    fn spawn_param_fc(
+        mut outbound: tokio::sync::mpsc::Receiver<MavMessage>,
+        inject: broadcast::Sender<MavMessage>,
+        clamp: HashMap<&'static str, f32>,
+    ) -> Arc<Mutex<Vec<(String, f32)>>> {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let log = writes.clone();
+
+        tokio::spawn(async move {
+            let mut params: HashMap<String, f32> = HashMap::new();
+            while let Some(msg) = outbound.recv().await {
+                match msg {
+                    // The FC echoes the value it kept
+                    MavMessage::PARAM_SET(set) => {
+                        let name = param_name(&set.param_id);
+                        log.lock().unwrap().push((name.clone(), set.param_value));
+                        let stored = clamp.get(name.as_str()).copied().unwrap_or(set.param_value);
+                        params.insert(name, stored);
+                        let _ = inject.send(MavMessage::PARAM_VALUE(PARAM_VALUE_DATA {
+                            param_value: stored,
+                            param_count: params.len() as u16,
+                            param_index: 0,
+                            param_id: set.param_id,
+                            param_type: MavParamType::MAV_PARAM_TYPE_REAL32,
+                        }));
+                    }
+                    MavMessage::PARAM_REQUEST_READ(req) => {
+                        let name = param_name(&req.param_id);
+                        if let Some(&value) = params.get(&name) {
+                            let _ = inject.send(MavMessage::PARAM_VALUE(PARAM_VALUE_DATA {
+                                param_value: value,
+                                param_count: params.len() as u16,
+                                param_index: 0,
+                                param_id: req.param_id,
+                                param_type: MavParamType::MAV_PARAM_TYPE_REAL32,
+                            }));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        writes
+    }
+
+    /// Simulated FC that ignores its first `ignore` mode changes and reports
+    /// the mode in a heartbeat once it takes one. Returns the mode-set count.
+    fn spawn_mode_fc(
+        mut outbound: tokio::sync::mpsc::Receiver<MavMessage>,
+        inject: broadcast::Sender<MavMessage>,
+        ignore: u32,
+    ) -> Arc<Mutex<u32>> {
+        let attempts = Arc::new(Mutex::new(0));
+        let count = attempts.clone();
+
+        tokio::spawn(async move {
+            while let Some(msg) = outbound.recv().await {
+                let MavMessage::COMMAND_LONG(cmd) = msg else {
+                    continue;
+                };
+                if cmd.command != MavCmd::MAV_CMD_DO_SET_MODE {
+                    continue;
+                }
+                let attempt = {
+                    let mut count = count.lock().unwrap();
+                    *count += 1;
+                    *count
+                };
+                if attempt > ignore {
+                    let _ = inject.send(MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+                        custom_mode: cmd.param2 as u32,
+                        autopilot: MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+                        ..Default::default()
+                    }));
+                }
+            }
+        });
+
+        attempts
+    }
+
+    #[tokio::test]
+    async fn test_set_mode_retried_until_confirmed() {
+        let (fc, outbound, inject) = FlightController::test_link(FcConfig::default());
+        let attempts = spawn_mode_fc(outbound, inject, 1);
+
+        let sender = MavCommandSender::new(1, 1).with_mode_retry(3, Duration::from_millis(100));
+        sender.set_mode(&fc, ArduPilotMode::Rtl).await.unwrap();
+        assert_eq!(*attempts.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_set_mode_fails_when_never_confirmed() {
+        let (fc, outbound, inject) = FlightController::test_link(FcConfig::default());
+        let attempts = spawn_mode_fc(outbound, inject, u32::MAX);
+
+        let sender = MavCommandSender::new(1, 1).with_mode_retry(2, Duration::from_millis(50));
+        assert!(sender.set_mode(&fc, ArduPilotMode::Loiter).await.is_err());
+        assert_eq!(*attempts.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_configure_failsafes_dry_run() {
+        let (fc, outbound, inject) = FlightController::test_link(FcConfig::default());
+        let writes = spawn_param_fc(outbound, inject, HashMap::new());
+
+        let sender = MavCommandSender::new(1, 1);
+        let params = FailsafeParams::default();
+        let report = sender.configure_failsafes(&fc, &params).await.unwrap();
+
+        assert!(report.is_verified(), "unexpected mismatches: {:?}", report.mismatches);
+        let written: Vec<(String, f32)> = params
+            .to_params()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        assert_eq!(*writes.lock().unwrap(), written);
+    }
+
+    #[tokio::test]
+    async fn test_configure_failsafes_reports_mismatch() {
+        let (fc, outbound, inject) = FlightController::test_link(FcConfig::default());
+        // FC refuses the voltage threshold and keeps its own value
+        let clamp = HashMap::from([("BATT_LOW_VOLT", 9.6)]);
+        spawn_param_fc(outbound, inject, clamp);
+
+        let sender = MavCommandSender::new(1, 1);
+        let report = sender
+            .configure_failsafes(&fc, &FailsafeParams::default())
+            .await
+            .unwrap();
+
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].name, "BATT_LOW_VOLT");
+        assert_eq!(report.mismatches[0].actual, Some(9.6));
+    }
+
+    #[tokio::test]
+    async fn test_download_log_rerequests_dropped_data() {
+        let (fc, mut outbound, inject) = FlightController::test_link(FcConfig::default());
+        let log: Vec<u8> = (0..LOG_CHUNK_SIZE + 500).map(|i| (i % 251) as u8).collect();
+        let entry = LogEntry {
+            id: 1,
+            size: log.len() as u32,
+            time_utc: 0,
+        };
+
+        // Simulated FC that loses the LOG_DATA at offset 900 the first time
+        let data = log.clone();
+        tokio::spawn(async move {
+            let mut dropped = false;
+            while let Some(msg) = outbound.recv().await {
+                let MavMessage::LOG_REQUEST_DATA(req) = msg else {
+                    continue;
+                };
+                let end = (req.ofs + req.count).min(data.len() as u32);
+                for ofs in (req.ofs..end).step_by(90) {
+                    if ofs == 900 && !dropped {
+                        dropped = true;
+                        continue;
+                    }
+                    let count = (end - ofs).min(90);
+                    let mut block = [0u8; 90];
+                    block[..count as usize].copy_from_slice(&data[ofs as usize..(ofs + count) as usize]);
+                    let _ = inject.send(MavMessage::LOG_DATA(LOG_DATA_DATA {
+                        ofs,
+                        id: req.id,
+                        count: count as u8,
+                        data: block,
+                    }));
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+
+        let (tx, mut rx) = mpsc::channel(4);
+        let sender = MavCommandSender::new(1, 1);
+        sender.download_log(&fc, entry, 0, tx).await.unwrap();
+
+        let mut received = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            assert_eq!((chunk.index, chunk.total_chunks), (received.len() as u32 / LOG_CHUNK_SIZE, 2));
+            received.extend(chunk.data);
+        }
+        assert_eq!(received, log);
+    }
+
+    /// Simulated FC holding `params` that echoes PARAM_SET and answers
+    /// PARAM_REQUEST_READ, and heartbeats armed in any mode it's set to
+    fn spawn_rtl_fc(
+        mut outbound: tokio::sync::mpsc::Receiver<MavMessage>,
+        inject: broadcast::Sender<MavMessage>,
+        mut params: HashMap<String, f32>,
+    ) {
+        tokio::spawn(async move {
+            while let Some(msg) = outbound.recv().await {
+                let id = match msg {
+                    MavMessage::PARAM_SET(set) => {
+                        params.insert(param_name(&set.param_id), set.param_value);
+                        set.param_id
+                    }
+                    MavMessage::PARAM_REQUEST_READ(req) => req.param_id,
+                    MavMessage::COMMAND_LONG(cmd) if cmd.command == MavCmd::MAV_CMD_DO_SET_MODE => {
+                        let _ = inject.send(MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+                            custom_mode: cmd.param2 as u32,
+                            autopilot: MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+                            base_mode: MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED,
+                            ..Default::default()
+                        }));
+                        continue;
+                    }
+                    _ => continue,
+                };
+                if let Some(&value) = params.get(&param_name(&id)) {
+                    let _ = inject.send(MavMessage::PARAM_VALUE(PARAM_VALUE_DATA {
+                        param_value: value,
+                        param_count: params.len() as u16,
+                        param_index: 0,
+                        param_id: id,
+                        param_type: MavParamType::MAV_PARAM_TYPE_REAL32,
+                    }));
+                }
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_rth_altitude_and_speed_applied_then_restored() {
+        let (fc, outbound, inject) = FlightController::test_link(FcConfig::default());
+        let params = HashMap::from([
+            ("RTL_ALT".to_string(), 1500.0),
+            ("RTL_SPEED".to_string(), 0.0),
+        ]);
+        spawn_rtl_fc(outbound, inject.clone(), params);
+        let sender = MavCommandSender::new(1, 1);
+        let client = MavParamClient::for_controller(&fc);
+
+        let rth = ReturnToHome { altitude_m: 50.0, speed_mps: 3.0 };
+        sender.return_to_home(&fc, &rth).await.unwrap();
+        assert_eq!(client.get(&fc, "RTL_ALT").await.unwrap(), 5000.0);
+        assert_eq!(client.get(&fc, "RTL_SPEED").await.unwrap(), 300.0);
+
+        // Still returning: a repeated RTH keeps the FC's values to restore
+        let rth = ReturnToHome { altitude_m: 40.0, speed_mps: 0.0 };
+        sender.return_to_home(&fc, &rth).await.unwrap();
+        assert_eq!(client.get(&fc, "RTL_ALT").await.unwrap(), 4000.0);
+        assert_eq!(client.get(&fc, "RTL_SPEED").await.unwrap(), 0.0);
+
+        // Landed and disarmed
+        let _ = inject.send(MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+            custom_mode: ArduPilotMode::Rtl as u32,
+            autopilot: MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+            ..Default::default()
+        }));
+        for _ in 0..50 {
+            if sender.rtl_saved.lock().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(client.get(&fc, "RTL_ALT").await.unwrap(), 1500.0);
+        assert_eq!(client.get(&fc, "RTL_SPEED").await.unwrap(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_guided_setpoints() {
+        let (fc, mut outbound, _inject) = FlightController::test_link(FcConfig::default());
+        let sender = MavCommandSender::new(1, 1);
+
+        sender.set_velocity_ned(&fc, 2.0, -1.0, 0.5, None).await.unwrap();
+        let Ok(MavMessage::SET_POSITION_TARGET_LOCAL_NED(target)) = outbound.try_recv() else {
+            panic!("expected a local NED setpoint");
+        };
+        assert_eq!((target.vx, target.vy, target.vz), (2.0, -1.0, 0.5));
+        assert_eq!(target.coordinate_frame, MavFrame::MAV_FRAME_LOCAL_NED);
+        // Only the velocity is used
+        assert_eq!(target.type_mask.bits(), 0b1101_1100_0111);
+
+        let target = GpsCoordinate { latitude: 47.5, longitude: 8.5, altitude_m: 30.0 };
+        sender.set_position_target(&fc, &target, Some(90.0)).await.unwrap();
+        let Ok(MavMessage::SET_POSITION_TARGET_GLOBAL_INT(target)) = outbound.try_recv() else {
+            panic!("expected a global setpoint");
+        };
+        assert_eq!((target.lat_int, target.lon_int, target.alt), (475_000_000, 85_000_000, 30.0));
+        assert!((target.yaw - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+        // Position and yaw are used
+        assert_eq!(target.type_mask.bits(), 0b1001_1111_1000);
+
+        assert!(sender.set_velocity_ned(&fc, f32::NAN, 0.0, 0.0, None).await.is_err());
+        let nowhere = GpsCoordinate { latitude: 91.0, longitude: 0.0, altitude_m: 10.0 };
+        assert!(sender.set_position_target(&fc, &nowhere, None).await.is_err());
+        assert!(outbound.try_recv().is_err());
+    }
 }
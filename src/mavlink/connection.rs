@@ -3,10 +3,24 @@
 //! Manages connection to ArduPilot/PX4 flight controllers via serial or UDP.
 
 use anyhow::{anyhow, Result};
-use mavlink::ardupilotmega::MavMessage;
-use mavlink::{MavConnection, MavHeader};
+use mavlink::ardupilotmega::{
+    MavAutopilot, MavMessage, MavModeFlag, MavState, MavType, HEARTBEAT_DATA,
+};
+use mavlink::{MavConnection, MavHeader, SigningConfig};
+use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
+
+/// Buffered inbound MAVLink messages per subscriber before it starts lagging
+const MESSAGE_SUBSCRIPTION_CAPACITY: usize = 256;
+
+/// Inbound MAVLink messages buffered between the reader thread and dispatch
+const INBOUND_CAPACITY: usize = 100;
+
+/// How often we send our own HEARTBEAT (the FC's GCS failsafe watches for it)
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Connection type for flight controller
 #[derive(Debug, Clone)]
@@ -28,6 +42,80 @@ impl Default for FcConnectionType {
     }
 }
 
+/// Length of a MAVLink 2 signing secret key
+pub const SIGNING_KEY_LEN: usize = 32;
+
+/// MAVLink 2 message signing on the FC link
+///
+/// Our frames are signed with the secret key and a timestamp taken from the
+/// system clock; inbound frames without a valid signature, or replaying an
+/// old timestamp, are dropped by the link before they reach us.
+#[derive(Clone)]
+pub struct FcSigning {
+    /// Secret key shared with the FC
+    pub secret_key: [u8; SIGNING_KEY_LEN],
+    /// Link ID stamped on our frames
+    pub link_id: u8,
+    /// Also accept unsigned frames (while the FC is being set up)
+    pub allow_unsigned: bool,
+}
+
+impl fmt::Debug for FcSigning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FcSigning")
+            .field("secret_key", &"<redacted>")
+            .field("link_id", &self.link_id)
+            .field("allow_unsigned", &self.allow_unsigned)
+            .finish()
+    }
+}
+
+impl FcSigning {
+    /// Signing from `RESQTERRA_FC_SIGNING_KEY` (hex secret key),
+    /// `RESQTERRA_FC_SIGNING_LINK_ID` and `RESQTERRA_FC_ALLOW_UNSIGNED=1`
+    ///
+    /// Returns `None` when no key is set, i.e. the FC link stays unsigned.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(key) = std::env::var("RESQTERRA_FC_SIGNING_KEY")
+            .ok()
+            .filter(|k| !k.is_empty())
+        else {
+            return Ok(None);
+        };
+        let link_id = match std::env::var("RESQTERRA_FC_SIGNING_LINK_ID") {
+            Ok(id) => id
+                .parse()
+                .map_err(|_| anyhow!("Invalid RESQTERRA_FC_SIGNING_LINK_ID {:?}", id))?,
+            Err(_) => 0,
+        };
+        Ok(Some(Self {
+            secret_key: parse_signing_key(&key)?,
+            link_id,
+            allow_unsigned: std::env::var("RESQTERRA_FC_ALLOW_UNSIGNED").is_ok_and(|v| v == "1"),
+        }))
+    }
+
+    fn config(&self) -> SigningConfig {
+        SigningConfig::new(self.secret_key, self.link_id, true, self.allow_unsigned)
+    }
+}
+
+/// Parse a signing key given as hex
+fn parse_signing_key(hex: &str) -> Result<[u8; SIGNING_KEY_LEN]> {
+    if hex.len() != SIGNING_KEY_LEN * 2 || !hex.is_ascii() {
+        return Err(anyhow!(
+            "Signing key must be {} hex digits",
+            SIGNING_KEY_LEN * 2
+        ));
+    }
+    let mut key = [0u8; SIGNING_KEY_LEN];
+    for (byte, pair) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(pair).expect("ASCII");
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| anyhow!("Signing key is not hex"))?;
+    }
+    Ok(key)
+}
+
 /// Configuration for flight controller connection
 #[derive(Debug, Clone)]
 pub struct FcConfig {
@@ -41,6 +129,8 @@ pub struct FcConfig {
     pub target_system: u8,
     /// Target component ID (autopilot)
     pub target_component: u8,
+    /// MAVLink 2 signing (None: unsigned link)
+    pub signing: Option<FcSigning>,
 }
 
 impl Default for FcConfig {
@@ -51,6 +141,7 @@ impl Default for FcConfig {
             component_id: 190,   // MAV_COMP_ID_ONBOARD_COMPUTER
             target_system: 1,    // Autopilot
             target_component: 1, // MAV_COMP_ID_AUTOPILOT1
+            signing: None,
         }
     }
 }
@@ -71,18 +162,27 @@ pub enum FcEvent {
         system_status: u8,
         base_mode: u8,
         custom_mode: u32,
+        /// Frames from the FC missing so far on this connection (see
+        /// [`DroppedFrames`])
+        dropped_frames: u64,
     },
 }
 
+/// An open MAVLink link, shared by its reader thread and writer task
+type SharedConnection = Arc<dyn MavConnection<MavMessage> + Send + Sync>;
+
 /// Flight controller connection manager
+///
+/// Clones share the connection.
+#[derive(Clone)]
 pub struct FlightController {
     config: FcConfig,
-    /// Connection handle (wrapped for thread safety)
-    connection: Arc<RwLock<Option<Box<dyn MavConnection<MavMessage> + Send + Sync>>>>,
     /// Channel for outgoing messages
     outbound_tx: mpsc::Sender<MavMessage>,
     /// Channel for incoming events
-    event_rx: mpsc::Receiver<FcEvent>,
+    event_rx: Arc<RwLock<mpsc::Receiver<FcEvent>>>,
+    /// Fan-out of every inbound MAVLink message (for request/response flows)
+    message_tx: broadcast::Sender<MavMessage>,
     /// Flag indicating if connected
     connected: Arc<RwLock<bool>>,
 }
@@ -92,21 +192,20 @@ impl FlightController {
     pub fn new(config: FcConfig) -> Self {
         let (outbound_tx, outbound_rx) = mpsc::channel::<MavMessage>(100);
         let (event_tx, event_rx) = mpsc::channel::<FcEvent>(100);
+        let (message_tx, _) = broadcast::channel::<MavMessage>(MESSAGE_SUBSCRIPTION_CAPACITY);
         let connected = Arc::new(RwLock::new(false));
 
         let fc = Self {
             config: config.clone(),
-            connection: Arc::new(RwLock::new(None)),
             outbound_tx,
-            event_rx,
+            event_rx: Arc::new(RwLock::new(event_rx)),
+            message_tx: message_tx.clone(),
             connected: connected.clone(),
         };
 
         // Spawn the connection handler
-        let conn_arc = fc.connection.clone();
-        let connected_clone = connected;
         tokio::spawn(async move {
-            connection_loop(config, conn_arc, outbound_rx, event_tx, connected_clone).await;
+            connection_loop(config, outbound_rx, event_tx, message_tx, connected).await;
         });
 
         fc
@@ -126,8 +225,15 @@ impl FlightController {
     }
 
     /// Receive the next event from the flight controller
-    pub async fn recv(&mut self) -> Option<FcEvent> {
-        self.event_rx.recv().await
+    pub async fn recv(&self) -> Option<FcEvent> {
+        self.event_rx.write().await.recv().await
+    }
+
+    /// Subscribe to all MAVLink messages received from the flight controller
+    ///
+    /// Subscribe *before* sending a request so the response can't be missed.
+    pub fn subscribe(&self) -> broadcast::Receiver<MavMessage> {
+        self.message_tx.subscribe()
     }
 
     /// Get the configuration
@@ -148,9 +254,9 @@ impl FlightController {
 /// Main connection loop
 async fn connection_loop(
     config: FcConfig,
-    connection: Arc<RwLock<Option<Box<dyn MavConnection<MavMessage> + Send + Sync>>>>,
     mut outbound_rx: mpsc::Receiver<MavMessage>,
     event_tx: mpsc::Sender<FcEvent>,
+    message_tx: broadcast::Sender<MavMessage>,
     connected: Arc<RwLock<bool>>,
 ) {
     loop {
@@ -173,21 +279,31 @@ async fn connection_loop(
         };
 
         match conn_result {
-            Ok(conn) => {
-                println!("[MAVLink] Connected to flight controller");
+            Ok(mut conn) => {
+                match &config.signing {
+                    Some(signing) => {
+                        conn.setup_signing(Some(signing.config()));
+                        println!(
+                            "[MAVLink] Connected to flight controller (signed, link {})",
+                            signing.link_id
+                        );
+                    }
+                    None => println!("[MAVLink] Connected to flight controller"),
+                }
                 *connected.write().await = true;
                 let _ = event_tx.send(FcEvent::Connected).await;
 
-                // Store connection
-                *connection.write().await = Some(conn);
-
-                // Handle connection
-                if let Err(e) = handle_connection(
-                    &connection,
+                // Handle connection; the outbound queue comes back for the next one
+                let (result, rx) = handle_connection(
+                    Arc::from(conn),
                     &config,
-                    &mut outbound_rx,
+                    outbound_rx,
                     &event_tx,
-                ).await {
+                    &message_tx,
+                )
+                .await;
+                outbound_rx = rx;
+                if let Err(e) = result {
                     eprintln!("[MAVLink] Connection error: {}", e);
                     let _ = event_tx
                         .send(FcEvent::Disconnected {
@@ -197,7 +313,6 @@ async fn connection_loop(
                 }
 
                 *connected.write().await = false;
-                *connection.write().await = None;
             }
             Err(e) => {
                 eprintln!("[MAVLink] Failed to connect: {}", e);
@@ -209,63 +324,205 @@ async fn connection_loop(
     }
 }
 
-/// Handle an active connection
+/// Handle an active connection until it fails
+///
+/// Blocking reads run on their own thread and writes in their own task, so
+/// neither waits on the other. Returns the outbound queue along with the
+/// error that ended the connection.
 async fn handle_connection(
-    connection: &Arc<RwLock<Option<Box<dyn MavConnection<MavMessage> + Send + Sync>>>>,
+    conn: SharedConnection,
     config: &FcConfig,
-    outbound_rx: &mut mpsc::Receiver<MavMessage>,
+    outbound_rx: mpsc::Receiver<MavMessage>,
     event_tx: &mpsc::Sender<FcEvent>,
-) -> Result<()> {
+    message_tx: &broadcast::Sender<MavMessage>,
+) -> (Result<()>, mpsc::Receiver<MavMessage>) {
     let header = MavHeader {
         system_id: config.system_id,
         component_id: config.component_id,
         sequence: 0,
     };
 
-    loop {
+    let mut dropped = DroppedFrames::new(config.target_system);
+    let (inbound_tx, mut inbound_rx) = mpsc::channel(INBOUND_CAPACITY);
+    let reader = conn.clone();
+    tokio::task::spawn_blocking(move || read_loop(reader, inbound_tx));
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let mut writer = tokio::spawn(write_loop(conn, header, outbound_rx, stop_rx));
+
+    let result = loop {
         tokio::select! {
-            // Send outbound messages
-            Some(msg) = outbound_rx.recv() => {
-                let conn_guard = connection.read().await;
-                if let Some(ref conn) = *conn_guard {
-                    conn.send(&header, &msg)?;
+            inbound = inbound_rx.recv() => match inbound {
+                Some(Ok((header, msg))) => {
+                    dropped.record(&header);
+                    dispatch(msg, dropped.count, event_tx, message_tx).await;
                 }
+                Some(Err(e)) => break Err(e),
+                None => break Err(anyhow!("MAVLink reader stopped")),
+            },
+
+            // The writer only finishes on its own when a send fails
+            written = &mut writer => {
+                return written.expect("MAVLink writer panicked");
             }
+        }
+    };
 
-            // Read incoming messages (with timeout)
-            _ = tokio::time::sleep(tokio::time::Duration::from_millis(10)) => {
-                let conn_guard = connection.read().await;
-                if let Some(ref conn) = *conn_guard {
-                    // Try to receive without blocking
-                    match conn.recv() {
-                        Ok((_header, msg)) => {
-                            // Handle heartbeat specially
-                            if let MavMessage::HEARTBEAT(hb) = &msg {
-                                let _ = event_tx.send(FcEvent::Heartbeat {
-                                    autopilot: hb.autopilot as u8,
-                                    mav_type: hb.mavtype as u8,
-                                    system_status: hb.system_status as u8,
-                                    base_mode: hb.base_mode.bits(),
-                                    custom_mode: hb.custom_mode,
-                                }).await;
-                            }
-
-                            let _ = event_tx.send(FcEvent::Message(msg)).await;
-                        }
-                        Err(mavlink::error::MessageReadError::Io(ref e))
-                            if e.kind() == std::io::ErrorKind::WouldBlock => {
-                            // No data available, continue
-                        }
-                        Err(e) => {
-                            return Err(anyhow!("Read error: {}", e));
-                        }
-                    }
-                }
+    let _ = stop_tx.send(());
+    let (_, outbound_rx) = writer.await.expect("MAVLink writer panicked");
+    (result, outbound_rx)
+}
+
+/// Receive messages until the link fails, on a blocking thread
+///
+/// Also ends when the next message arrives after the connection was dropped
+/// on our side.
+fn read_loop(
+    conn: SharedConnection,
+    inbound_tx: mpsc::Sender<Result<(MavHeader, MavMessage)>>,
+) {
+    loop {
+        let inbound = match conn.recv() {
+            Ok(received) => Ok(received),
+            // Blocking links only report these on a read timeout
+            Err(mavlink::error::MessageReadError::Io(ref e))
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
             }
+            Err(e) => Err(anyhow!("Read error: {}", e)),
+        };
+        let failed = inbound.is_err();
+        if inbound_tx.blocking_send(inbound).is_err() || failed {
+            return;
         }
     }
 }
 
+/// Send queued messages and our heartbeat until a send fails or `stop` fires
+async fn write_loop(
+    conn: SharedConnection,
+    header: MavHeader,
+    mut outbound_rx: mpsc::Receiver<MavMessage>,
+    mut stop: oneshot::Receiver<()>,
+) -> (Result<()>, mpsc::Receiver<MavMessage>) {
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    let result = loop {
+        let msg = tokio::select! {
+            _ = &mut stop => break Ok(()),
+            // Announce ourselves to the FC
+            _ = heartbeat.tick() => companion_heartbeat(),
+            Some(msg) = outbound_rx.recv() => msg,
+        };
+        if let Err(e) = conn.send(&header, &msg) {
+            break Err(anyhow!("Write error: {}", e));
+        }
+    };
+
+    (result, outbound_rx)
+}
+
+/// Pass an inbound message on to the event loop and subscribers
+async fn dispatch(
+    msg: MavMessage,
+    dropped_frames: u64,
+    event_tx: &mpsc::Sender<FcEvent>,
+    message_tx: &broadcast::Sender<MavMessage>,
+) {
+    // Handle heartbeat specially
+    if let MavMessage::HEARTBEAT(hb) = &msg {
+        let _ = event_tx
+            .send(FcEvent::Heartbeat {
+                autopilot: hb.autopilot as u8,
+                mav_type: hb.mavtype as u8,
+                system_status: hb.system_status as u8,
+                base_mode: hb.base_mode.bits(),
+                custom_mode: hb.custom_mode,
+                dropped_frames,
+            })
+            .await;
+    }
+
+    let _ = message_tx.send(msg.clone());
+    let _ = event_tx.send(FcEvent::Message(msg)).await;
+}
+
+/// Counts frames from the FC missing from its sequence numbers
+///
+/// The mavlink crate discards frames with a bad CRC, signature or timestamp
+/// (or unsigned ones on a signed link) without telling us, so these show up
+/// only as gaps, along with frames lost on the way. An FC whose frames are all
+/// dropped is caught by the heartbeat timeout instead. Each component numbers
+/// its own frames.
+#[derive(Debug)]
+struct DroppedFrames {
+    system_id: u8,
+    last_sequence: HashMap<u8, u8>,
+    count: u64,
+}
+
+impl DroppedFrames {
+    fn new(system_id: u8) -> Self {
+        Self {
+            system_id,
+            last_sequence: HashMap::new(),
+            count: 0,
+        }
+    }
+
+    /// Note a frame received with `header`
+    fn record(&mut self, header: &MavHeader) {
+        if header.system_id != self.system_id {
+            return;
+        }
+        if let Some(last) = self.last_sequence.insert(header.component_id, header.sequence) {
+            // A repeated frame skips nothing
+            let step = header.sequence.wrapping_sub(last);
+            self.count += u64::from(step.saturating_sub(1));
+        }
+    }
+}
+
+/// The HEARTBEAT identifying us as the onboard companion computer
+fn companion_heartbeat() -> MavMessage {
+    MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+        custom_mode: 0,
+        mavtype: MavType::MAV_TYPE_ONBOARD_CONTROLLER,
+        autopilot: MavAutopilot::MAV_AUTOPILOT_INVALID,
+        base_mode: MavModeFlag::empty(),
+        system_status: MavState::MAV_STATE_ACTIVE,
+        mavlink_version: 3,
+    })
+}
+
+#[cfg(test)]
+impl FlightController {
+    /// Create a controller with no real link behind it
+    ///
+    /// Returns the controller, the queue of messages it sends to the FC, and a
+    /// sender for injecting messages as if they came from the FC.
+    pub(crate) fn test_link(
+        config: FcConfig,
+    ) -> (Self, mpsc::Receiver<MavMessage>, broadcast::Sender<MavMessage>) {
+        let (outbound_tx, outbound_rx) = mpsc::channel::<MavMessage>(100);
+        let (_event_tx, event_rx) = mpsc::channel::<FcEvent>(100);
+        let (message_tx, _) = broadcast::channel::<MavMessage>(MESSAGE_SUBSCRIPTION_CAPACITY);
+
+        let fc = Self {
+            config,
+            outbound_tx,
+            event_rx: Arc::new(RwLock::new(event_rx)),
+            message_tx: message_tx.clone(),
+            connected: Arc::new(RwLock::new(true)),
+        };
+
+        (fc, outbound_rx, message_tx)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -290,4 +547,56 @@ mod tests {
         };
         assert!(matches!(udp, FcConnectionType::Udp { .. }));
     }
+
+    #[test]
+    fn test_companion_heartbeat() {
+        // Never counted as an autopilot heartbeat by the other side
+        let MavMessage::HEARTBEAT(hb) = companion_heartbeat() else {
+            panic!("not a heartbeat");
+        };
+        assert_eq!(hb.autopilot, MavAutopilot::MAV_AUTOPILOT_INVALID);
+        assert_eq!(hb.mavtype, MavType::MAV_TYPE_ONBOARD_CONTROLLER);
+    }
+
+    #[test]
+    fn test_dropped_frames() {
+        let mut dropped = DroppedFrames::new(1);
+        let mut receive = |system_id, component_id, sequence| {
+            dropped.record(&MavHeader {
+                system_id,
+                component_id,
+                sequence,
+            });
+        };
+        receive(1, 1, 10);
+        receive(1, 1, 11);
+        // 12 and 13 never arrived
+        receive(1, 1, 14);
+        // Another component of the FC numbers its own frames: 255 and 0
+        // dropped across the wrap, the repeat of 1 skips nothing
+        receive(1, 100, 254);
+        receive(1, 100, 1);
+        receive(1, 100, 1);
+        // Other systems on the link don't count
+        receive(255, 190, 0);
+        receive(255, 190, 100);
+        assert_eq!(dropped.count, 4);
+    }
+
+    #[test]
+    fn test_signing_key() {
+        let hex = "00ff".repeat(SIGNING_KEY_LEN / 2);
+        let key = parse_signing_key(&hex).unwrap();
+        assert_eq!(key[..2], [0x00, 0xff]);
+        assert!(parse_signing_key("00ff").is_err());
+        assert!(parse_signing_key(&"zz".repeat(SIGNING_KEY_LEN)).is_err());
+
+        // The key never shows up in logs
+        let signing = FcSigning {
+            secret_key: key,
+            link_id: 3,
+            allow_unsigned: false,
+        };
+        assert!(!format!("{:?}", signing).contains("255"));
+    }
 }
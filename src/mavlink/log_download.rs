@@ -0,0 +1,253 @@
+//! FC onboard log reassembly
+//!
+//! The FC sends a log as `LOG_DATA` messages of up to 90 bytes each, in any
+//! order and with gaps when the link drops some. `LogAssembler` collects them
+//! one chunk at a time, reports the missing ranges to re-request, and hands
+//! out complete chunks in order so the whole log never sits in memory.
+
+use mavlink::ardupilotmega::LOG_DATA_DATA;
+use std::collections::BTreeMap;
+
+/// Payload bytes carried by one `LOG_DATA` message
+pub const LOG_DATA_LEN: u32 = 90;
+
+/// Bytes per chunk streamed to the server
+pub const LOG_CHUNK_SIZE: u32 = 16 * 1024;
+
+/// A log stored on the FC, as listed by `LOG_ENTRY`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogEntry {
+    pub id: u16,
+    /// Size in bytes
+    pub size: u32,
+    /// UTC timestamp of the log (0 if unknown)
+    pub time_utc: u32,
+}
+
+impl LogEntry {
+    /// Number of chunks of `chunk_size` bytes the log splits into
+    pub fn total_chunks(&self, chunk_size: u32) -> u32 {
+        self.size.div_ceil(chunk_size)
+    }
+}
+
+/// One complete, in-order piece of a log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogChunk {
+    pub index: u32,
+    pub total_chunks: u32,
+    pub data: Vec<u8>,
+}
+
+/// Reassembles `LOG_DATA` for one log, chunk by chunk
+#[derive(Debug)]
+pub struct LogAssembler {
+    entry: LogEntry,
+    chunk_size: u32,
+    /// Index of the next chunk to hand out
+    next_chunk: u32,
+    /// Received payloads from the next chunk onwards, by offset
+    blocks: BTreeMap<u32, Vec<u8>>,
+}
+
+impl LogAssembler {
+    /// Reassemble `entry` starting at chunk `first_chunk` (non-zero to resume)
+    pub fn new(entry: LogEntry, chunk_size: u32, first_chunk: u32) -> Self {
+        let chunk_size = chunk_size.max(1);
+        Self {
+            entry,
+            chunk_size,
+            next_chunk: first_chunk.min(entry.total_chunks(chunk_size)),
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    /// Total chunks in the log
+    pub fn total_chunks(&self) -> u32 {
+        self.entry.total_chunks(self.chunk_size)
+    }
+
+    /// Whether every chunk has been handed out
+    pub fn is_complete(&self) -> bool {
+        self.next_chunk >= self.total_chunks()
+    }
+
+    /// Byte range `[start, end)` of the chunk being assembled
+    pub fn pending_range(&self) -> Option<(u32, u32)> {
+        if self.is_complete() {
+            return None;
+        }
+        let start = self.next_chunk * self.chunk_size;
+        Some((start, (start + self.chunk_size).min(self.entry.size)))
+    }
+
+    /// Store one `LOG_DATA` message; returns whether it added anything
+    ///
+    /// Data for another log, before the current chunk or past the end of the
+    /// log is ignored, as are repeats of an offset already held.
+    pub fn insert(&mut self, data: &LOG_DATA_DATA) -> bool {
+        let Some((start, _)) = self.pending_range() else {
+            return false;
+        };
+        if data.id != self.entry.id || data.ofs >= self.entry.size {
+            return false;
+        }
+
+        let count = (data.count as u32).min(LOG_DATA_LEN).min(self.entry.size - data.ofs);
+        let end = data.ofs + count;
+        if end <= start {
+            return false;
+        }
+
+        // Keep only the part from the current chunk onwards
+        let ofs = data.ofs.max(start);
+        let bytes = &data.data[(ofs - data.ofs) as usize..count as usize];
+        if self.blocks.get(&ofs).is_some_and(|held| held.len() >= bytes.len()) {
+            return false;
+        }
+        self.blocks.insert(ofs, bytes.to_vec());
+        true
+    }
+
+    /// Missing `(offset, length)` ranges of the chunk being assembled
+    pub fn gaps(&self) -> Vec<(u32, u32)> {
+        let Some((start, end)) = self.pending_range() else {
+            return Vec::new();
+        };
+
+        let mut gaps = Vec::new();
+        let mut cursor = start;
+        for (&ofs, bytes) in self.blocks.range(..end) {
+            if ofs > cursor {
+                gaps.push((cursor, ofs - cursor));
+            }
+            cursor = cursor.max(ofs + bytes.len() as u32);
+        }
+        if cursor < end {
+            gaps.push((cursor, end - cursor));
+        }
+        gaps
+    }
+
+    /// Take the current chunk once it has no gaps
+    pub fn take_chunk(&mut self) -> Option<LogChunk> {
+        let (start, end) = self.pending_range()?;
+        if !self.gaps().is_empty() {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity((end - start) as usize);
+        let mut carry = None;
+        let mut cursor = start;
+        while let Some((ofs, bytes)) = self.blocks.pop_first() {
+            if ofs >= end {
+                self.blocks.insert(ofs, bytes);
+                break;
+            }
+            let block_end = ofs + bytes.len() as u32;
+            if block_end > cursor {
+                let from = (cursor - ofs) as usize;
+                let to = (block_end.min(end) - ofs) as usize;
+                data.extend_from_slice(&bytes[from..to]);
+                cursor = block_end.min(end);
+            }
+            // A block straddling the chunk boundary starts the next chunk
+            if block_end > end {
+                carry = Some(bytes[(end - ofs) as usize..].to_vec());
+            }
+        }
+        if let Some(rest) = carry {
+            if self.blocks.get(&end).is_none_or(|held| held.len() < rest.len()) {
+                self.blocks.insert(end, rest);
+            }
+        }
+
+        let chunk = LogChunk {
+            index: self.next_chunk,
+            total_chunks: self.total_chunks(),
+            data,
+        };
+        self.next_chunk += 1;
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: LogEntry = LogEntry {
+        id: 3,
+        size: 500,
+        time_utc: 0,
+    };
+
+    /// Byte `i` of the synthetic log
+    fn byte(i: u32) -> u8 {
+        (i % 251) as u8
+    }
+
+    /// A LOG_DATA message for log `id` as the FC would send it
+    fn log_data(id: u16, ofs: u32) -> LOG_DATA_DATA {
+        let count = LOG_DATA_LEN.min(ENTRY.size - ofs);
+        let mut data = [0u8; 90];
+        for i in 0..count {
+            data[i as usize] = byte(ofs + i);
+        }
+        LOG_DATA_DATA {
+            ofs,
+            id,
+            count: count as u8,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_chunks_reassembled_in_order_with_gaps_refilled() {
+        // 500 bytes in 200-byte chunks: LOG_DATA blocks straddle chunk boundaries
+        let mut assembler = LogAssembler::new(ENTRY, 200, 0);
+        assert_eq!(assembler.total_chunks(), 3);
+
+        // Out of order, one duplicate, one for another log, block at 90 lost
+        for ofs in [180, 0, 0, 270] {
+            assembler.insert(&log_data(ENTRY.id, ofs));
+        }
+        assert!(!assembler.insert(&log_data(9, 90)));
+        assert_eq!(assembler.gaps(), [(90, 90)]);
+        assert!(assembler.take_chunk().is_none());
+
+        // The re-requested block fills the gap
+        assert!(assembler.insert(&log_data(ENTRY.id, 90)));
+        let chunk = assembler.take_chunk().unwrap();
+        assert_eq!((chunk.index, chunk.total_chunks), (0, 3));
+        assert_eq!(chunk.data, (0..200).map(byte).collect::<Vec<_>>());
+
+        // The tail of the block at 180 already covers the start of chunk 1
+        assert_eq!(assembler.gaps(), [(360, 40)]);
+        for ofs in [360, 450] {
+            assembler.insert(&log_data(ENTRY.id, ofs));
+        }
+        assert_eq!(assembler.take_chunk().unwrap().data, (200..400).map(byte).collect::<Vec<_>>());
+
+        // The last chunk is short
+        let last = assembler.take_chunk().unwrap();
+        assert_eq!(last.data, (400..500).map(byte).collect::<Vec<_>>());
+        assert!(assembler.is_complete());
+        assert_eq!(assembler.pending_range(), None);
+    }
+
+    #[test]
+    fn test_resume_skips_delivered_chunks() {
+        let mut assembler = LogAssembler::new(ENTRY, 200, 2);
+        assert_eq!(assembler.pending_range(), Some((400, 500)));
+
+        // Data before the resume point is ignored
+        assert!(!assembler.insert(&log_data(ENTRY.id, 0)));
+        for ofs in [360, 450] {
+            assembler.insert(&log_data(ENTRY.id, ofs));
+        }
+        let chunk = assembler.take_chunk().unwrap();
+        assert_eq!(chunk.index, 2);
+        assert_eq!(chunk.data, (400..500).map(byte).collect::<Vec<_>>());
+    }
+}
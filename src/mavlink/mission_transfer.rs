@@ -0,0 +1,491 @@
+//! MAVLink mission upload and download
+//!
+//! The FC only takes a mission through the mission protocol handshake: the
+//! previous mission is cleared (MISSION_CLEAR_ALL, answered by a
+//! MISSION_ACK), the new one is announced with a MISSION_COUNT, the FC asks
+//! for each item by sequence number (MISSION_REQUEST_INT, or MISSION_REQUEST
+//! from older firmware) and confirms the whole mission with a MISSION_ACK.
+//! A download runs the other way round: MISSION_REQUEST_LIST is answered by
+//! a MISSION_COUNT, and each item is requested in turn. When the other side
+//! goes quiet, the last message is sent again.
+
+use anyhow::{anyhow, Result};
+use mavlink::ardupilotmega::{
+    MavMessage, MavMissionResult, MavMissionType, MISSION_ACK_DATA, MISSION_CLEAR_ALL_DATA,
+    MISSION_COUNT_DATA, MISSION_ITEM_INT_DATA, MISSION_REQUEST_INT_DATA,
+    MISSION_REQUEST_LIST_DATA,
+};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+
+use super::connection::FlightController;
+
+/// How long to wait for the FC's next request or ACK
+const MISSION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sends of one message without an answer before the upload fails
+const MISSION_ATTEMPTS: u32 = 5;
+
+/// Tolerance when comparing altitudes and parameters read back from the FC
+const ITEM_TOLERANCE: f32 = 1e-3;
+
+/// What the FC said next during an upload
+enum Reply {
+    /// Asks for the item with this sequence number
+    Request(u16),
+    Ack(MavMissionResult),
+}
+
+/// Uploads missions to the FC and reads them back
+#[derive(Debug, Clone)]
+pub struct MavMissionClient {
+    target_system: u8,
+    target_component: u8,
+    attempts: u32,
+    timeout: Duration,
+}
+
+impl MavMissionClient {
+    pub fn new(target_system: u8, target_component: u8) -> Self {
+        Self {
+            target_system,
+            target_component,
+            attempts: MISSION_ATTEMPTS,
+            timeout: MISSION_TIMEOUT,
+        }
+    }
+
+    /// Replace the FC's mission with `items`, numbered from 0
+    pub async fn upload(
+        &self,
+        fc: &FlightController,
+        items: &[MISSION_ITEM_INT_DATA],
+    ) -> Result<()> {
+        let count = u16::try_from(items.len())
+            .map_err(|_| anyhow!("Mission has {} items (max {})", items.len(), u16::MAX))?;
+        // Subscribe before sending so no answer can slip past us
+        let mut messages = fc.subscribe();
+
+        self.clear(fc, &mut messages).await?;
+        if items.is_empty() {
+            return Ok(());
+        }
+
+        let mut last = MavMessage::MISSION_COUNT(MISSION_COUNT_DATA {
+            count,
+            target_system: self.target_system,
+            target_component: self.target_component,
+            mission_type: MavMissionType::MAV_MISSION_TYPE_MISSION,
+        });
+        // Highest item the FC asked for so far
+        let mut requested: Option<u16> = None;
+        let mut unanswered = 0;
+        let mut resend = true;
+
+        loop {
+            if unanswered == self.attempts {
+                return Err(match requested {
+                    None => anyhow!("FC did not ask for the mission items"),
+                    Some(seq) => anyhow!("FC stopped after mission item {} of {}", seq + 1, count),
+                });
+            }
+            if resend {
+                fc.send(last.clone()).await?;
+            }
+            resend = true;
+
+            match timeout(self.timeout, next_reply(&mut messages)).await {
+                Err(_) => {
+                    unanswered += 1;
+                    eprintln!(
+                        "[MAVLink] No mission request from FC (attempt {}/{})",
+                        unanswered, self.attempts
+                    );
+                }
+                Ok(Err(e)) => return Err(e),
+                Ok(Ok(Reply::Request(seq))) => {
+                    let Some(item) = items.get(seq as usize) else {
+                        return Err(anyhow!("FC asked for item {} of {}", seq, count));
+                    };
+                    // A repeated request (our item got lost) doesn't count as progress
+                    if requested.is_none_or(|highest| seq > highest) {
+                        requested = Some(seq);
+                        unanswered = 0;
+                    }
+                    last = MavMessage::MISSION_ITEM_INT(MISSION_ITEM_INT_DATA {
+                        seq,
+                        ..item.clone()
+                    });
+                }
+                Ok(Ok(Reply::Ack(MavMissionResult::MAV_MISSION_ACCEPTED)))
+                    if requested == Some(count - 1) =>
+                {
+                    println!("[MAVLink] FC accepted {} mission items", count);
+                    return Ok(());
+                }
+                // A late answer to a repeated clear
+                Ok(Ok(Reply::Ack(MavMissionResult::MAV_MISSION_ACCEPTED))) => resend = false,
+                Ok(Ok(Reply::Ack(result))) => {
+                    return Err(anyhow!("FC rejected the mission: {:?}", result));
+                }
+            }
+        }
+    }
+
+    /// Read the mission stored on the FC
+    pub async fn download(&self, fc: &FlightController) -> Result<Vec<MISSION_ITEM_INT_DATA>> {
+        let mission = MavMissionType::MAV_MISSION_TYPE_MISSION;
+        // Subscribe before sending so no answer can slip past us
+        let mut messages = fc.subscribe();
+
+        let list = MavMessage::MISSION_REQUEST_LIST(MISSION_REQUEST_LIST_DATA {
+            target_system: self.target_system,
+            target_component: self.target_component,
+            mission_type: mission,
+        });
+        let count = self
+            .exchange(fc, &mut messages, list, "mission count", |msg| match msg {
+                MavMessage::MISSION_COUNT(c) if c.mission_type == mission => Some(c.count),
+                _ => None,
+            })
+            .await?;
+
+        let mut items = Vec::with_capacity(count as usize);
+        for seq in 0..count {
+            let request = MavMessage::MISSION_REQUEST_INT(MISSION_REQUEST_INT_DATA {
+                seq,
+                target_system: self.target_system,
+                target_component: self.target_component,
+                mission_type: mission,
+            });
+            let what = format!("mission item {}", seq);
+            let item = self
+                .exchange(fc, &mut messages, request, &what, |msg| match msg {
+                    MavMessage::MISSION_ITEM_INT(item)
+                        if item.mission_type == mission && item.seq == seq =>
+                    {
+                        Some(item)
+                    }
+                    _ => None,
+                })
+                .await?;
+            items.push(item);
+        }
+
+        // Tells the FC the download is over
+        fc.send(MavMessage::MISSION_ACK(MISSION_ACK_DATA {
+            target_system: self.target_system,
+            target_component: self.target_component,
+            mavtype: MavMissionResult::MAV_MISSION_ACCEPTED,
+            mission_type: mission,
+        }))
+        .await?;
+        Ok(items)
+    }
+
+    /// Read the FC's mission back and check it is `items`
+    ///
+    /// Item 0 is only checked to be there: ArduPilot keeps the home position
+    /// in it, whatever was uploaded.
+    pub async fn verify(
+        &self,
+        fc: &FlightController,
+        items: &[MISSION_ITEM_INT_DATA],
+    ) -> Result<()> {
+        let stored = self.download(fc).await?;
+        if stored.len() != items.len() {
+            return Err(anyhow!(
+                "FC has {} mission items, {} were uploaded",
+                stored.len(),
+                items.len()
+            ));
+        }
+        let mismatches: Vec<String> = items
+            .iter()
+            .zip(&stored)
+            .skip(1)
+            .filter_map(|(sent, held)| {
+                mismatch(sent, held).map(|field| format!("item {} {}", sent.seq, field))
+            })
+            .collect();
+        if !mismatches.is_empty() {
+            return Err(anyhow!("FC mission differs from the upload: {}", mismatches.join(", ")));
+        }
+        println!("[MAVLink] FC mission verified ({} items)", items.len());
+        Ok(())
+    }
+
+    /// Send `msg` until an answer `accept` takes comes back
+    async fn exchange<T>(
+        &self,
+        fc: &FlightController,
+        messages: &mut broadcast::Receiver<MavMessage>,
+        msg: MavMessage,
+        what: &str,
+        accept: impl Fn(MavMessage) -> Option<T>,
+    ) -> Result<T> {
+        for attempt in 1..=self.attempts {
+            fc.send(msg.clone()).await?;
+            let answer = async {
+                loop {
+                    match messages.recv().await {
+                        Ok(msg) => {
+                            if let Some(answer) = accept(msg) {
+                                return Ok(answer);
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => {
+                            return Err(anyhow!("FC connection closed"));
+                        }
+                    }
+                }
+            };
+            match timeout(self.timeout, answer).await {
+                Ok(result) => return result,
+                Err(_) => eprintln!(
+                    "[MAVLink] No {} from FC (attempt {}/{})",
+                    what, attempt, self.attempts
+                ),
+            }
+        }
+
+        Err(anyhow!("FC did not send the {} after {} attempts", what, self.attempts))
+    }
+
+    /// Clear the FC's mission
+    async fn clear(
+        &self,
+        fc: &FlightController,
+        messages: &mut broadcast::Receiver<MavMessage>,
+    ) -> Result<()> {
+        let msg = MavMessage::MISSION_CLEAR_ALL(MISSION_CLEAR_ALL_DATA {
+            target_system: self.target_system,
+            target_component: self.target_component,
+            mission_type: MavMissionType::MAV_MISSION_TYPE_MISSION,
+        });
+
+        for attempt in 1..=self.attempts {
+            fc.send(msg.clone()).await?;
+            let ack = async {
+                loop {
+                    // Requests left over from an earlier upload are ignored
+                    if let Reply::Ack(result) = next_reply(messages).await? {
+                        return Ok(result);
+                    }
+                }
+            };
+            match timeout(self.timeout, ack).await {
+                Ok(Ok(MavMissionResult::MAV_MISSION_ACCEPTED)) => return Ok(()),
+                Ok(Ok(result)) => {
+                    return Err(anyhow!("FC refused to clear the mission: {:?}", result));
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_) => eprintln!(
+                    "[MAVLink] Mission clear not acknowledged (attempt {}/{})",
+                    attempt, self.attempts
+                ),
+            }
+        }
+
+        Err(anyhow!("FC did not clear its mission after {} attempts", self.attempts))
+    }
+}
+
+/// What differs between an uploaded item and the one the FC holds
+fn mismatch(sent: &MISSION_ITEM_INT_DATA, held: &MISSION_ITEM_INT_DATA) -> Option<&'static str> {
+    // NaN (e.g. "current yaw") may come back as anything
+    let same = |a: f32, b: f32| a.is_nan() || (a - b).abs() <= ITEM_TOLERANCE;
+    if sent.command != held.command {
+        Some("command")
+    } else if sent.frame != held.frame {
+        Some("frame")
+    } else if sent.x != held.x || sent.y != held.y || !same(sent.z, held.z) {
+        Some("position")
+    } else if ![
+        (sent.param1, held.param1),
+        (sent.param2, held.param2),
+        (sent.param3, held.param3),
+        (sent.param4, held.param4),
+    ]
+    .iter()
+    .all(|&(a, b)| same(a, b))
+    {
+        Some("parameters")
+    } else {
+        None
+    }
+}
+
+/// Wait for the FC's next mission request or ACK
+async fn next_reply(messages: &mut broadcast::Receiver<MavMessage>) -> Result<Reply> {
+    let mission = MavMissionType::MAV_MISSION_TYPE_MISSION;
+    loop {
+        match messages.recv().await {
+            Ok(MavMessage::MISSION_REQUEST_INT(req)) if req.mission_type == mission => {
+                return Ok(Reply::Request(req.seq));
+            }
+            Ok(MavMessage::MISSION_REQUEST(req)) if req.mission_type == mission => {
+                return Ok(Reply::Request(req.seq));
+            }
+            Ok(MavMessage::MISSION_ACK(ack)) if ack.mission_type == mission => {
+                return Ok(Reply::Ack(ack.mavtype));
+            }
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return Err(anyhow!("FC connection closed")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::FcConfig;
+    use mavlink::ardupilotmega::MavCmd;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+
+    /// Simulated FC taking missions of up to `capacity` items, that ignores
+    /// the first item it's sent, and hands its mission out for download.
+    /// Returns the items it holds.
+    fn spawn_fc(
+        mut outbound: mpsc::Receiver<MavMessage>,
+        inject: broadcast::Sender<MavMessage>,
+        capacity: u16,
+    ) -> Arc<Mutex<Vec<MISSION_ITEM_INT_DATA>>> {
+        let mission = Arc::new(Mutex::new(Vec::new()));
+        let stored = mission.clone();
+
+        tokio::spawn(async move {
+            let ack = |mavtype| {
+                MavMessage::MISSION_ACK(MISSION_ACK_DATA {
+                    mavtype,
+                    ..Default::default()
+                })
+            };
+            let request = |seq| {
+                MavMessage::MISSION_REQUEST_INT(MISSION_REQUEST_INT_DATA {
+                    seq,
+                    ..Default::default()
+                })
+            };
+            let mut count = 0;
+            let mut dropped = false;
+            while let Some(msg) = outbound.recv().await {
+                let reply = match msg {
+                    MavMessage::MISSION_CLEAR_ALL(_) => {
+                        stored.lock().unwrap().clear();
+                        ack(MavMissionResult::MAV_MISSION_ACCEPTED)
+                    }
+                    MavMessage::MISSION_COUNT(c) if c.count > capacity => {
+                        ack(MavMissionResult::MAV_MISSION_NO_SPACE)
+                    }
+                    MavMessage::MISSION_COUNT(c) => {
+                        count = c.count;
+                        request(0)
+                    }
+                    MavMessage::MISSION_ITEM_INT(_) if !dropped => {
+                        dropped = true;
+                        continue;
+                    }
+                    MavMessage::MISSION_ITEM_INT(item) => {
+                        let mut stored = stored.lock().unwrap();
+                        if item.seq as usize == stored.len() {
+                            stored.push(item);
+                        }
+                        match stored.len() as u16 {
+                            n if n == count => ack(MavMissionResult::MAV_MISSION_ACCEPTED),
+                            n => request(n),
+                        }
+                    }
+                    MavMessage::MISSION_REQUEST_LIST(_) => {
+                        MavMessage::MISSION_COUNT(MISSION_COUNT_DATA {
+                            count: stored.lock().unwrap().len() as u16,
+                            ..Default::default()
+                        })
+                    }
+                    MavMessage::MISSION_REQUEST_INT(req) => {
+                        match stored.lock().unwrap().get(req.seq as usize) {
+                            Some(item) => MavMessage::MISSION_ITEM_INT(item.clone()),
+                            None => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+                let _ = inject.send(reply);
+            }
+        });
+
+        mission
+    }
+
+    fn client(attempts: u32, timeout: Duration) -> MavMissionClient {
+        MavMissionClient {
+            attempts,
+            timeout,
+            ..MavMissionClient::new(1, 1)
+        }
+    }
+
+    fn items(count: u16) -> Vec<MISSION_ITEM_INT_DATA> {
+        (0..count)
+            .map(|seq| MISSION_ITEM_INT_DATA {
+                seq,
+                command: MavCmd::MAV_CMD_NAV_WAYPOINT,
+                x: seq as i32,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_mission_uploaded_through_handshake() {
+        let (fc, outbound, inject) = FlightController::test_link(FcConfig::default());
+        let mission = spawn_fc(outbound, inject, 10);
+        let client = client(3, Duration::from_millis(50));
+
+        // The lost first item is sent again
+        client.upload(&fc, &items(4)).await.unwrap();
+        let stored: Vec<i32> = mission.lock().unwrap().iter().map(|item| item.x).collect();
+        assert_eq!(stored, vec![0, 1, 2, 3]);
+
+        // Too large: the FC refuses it, after clearing the old one
+        let err = client.upload(&fc, &items(11)).await.unwrap_err();
+        assert!(err.to_string().contains("NO_SPACE"), "{}", err);
+        assert!(mission.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_silent_fc_fails_upload() {
+        let (fc, _outbound, _inject) = FlightController::test_link(FcConfig::default());
+        let client = client(2, Duration::from_millis(20));
+        let err = client.upload(&fc, &items(2)).await.unwrap_err();
+        assert!(err.to_string().contains("did not clear"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_mission_read_back_and_verified() {
+        let (fc, outbound, inject) = FlightController::test_link(FcConfig::default());
+        let mission = spawn_fc(outbound, inject, 10);
+        let client = client(3, Duration::from_millis(50));
+        let sent = items(4);
+        client.upload(&fc, &sent).await.unwrap();
+
+        assert_eq!(client.download(&fc).await.unwrap(), sent);
+        client.verify(&fc, &sent).await.unwrap();
+
+        // Home replaces item 0 on ArduPilot
+        mission.lock().unwrap()[0].x = 475_000_000;
+        client.verify(&fc, &sent).await.unwrap();
+
+        mission.lock().unwrap()[2].z = 80.0;
+        mission.lock().unwrap()[3].command = MavCmd::MAV_CMD_NAV_LAND;
+        let err = client.verify(&fc, &sent).await.unwrap_err().to_string();
+        assert!(err.ends_with("item 2 position, item 3 command"), "{}", err);
+
+        mission.lock().unwrap().pop();
+        let err = client.verify(&fc, &sent).await.unwrap_err().to_string();
+        assert!(err.contains("FC has 3 mission items, 4 were uploaded"), "{}", err);
+    }
+}
@@ -3,10 +3,17 @@
 //! Provides integration with ArduPilot/PX4 flight controllers via MAVLink protocol.
 //! Supports both serial and UDP connections.
 
+mod command_ack;
 mod commands;
 mod connection;
+mod log_download;
+mod mission_transfer;
+mod params;
 mod telemetry;
 
-pub use commands::{ArduPilotMode, MavCommandSender};
-pub use connection::{FcConfig, FcConnectionType, FcEvent, FlightController};
+pub use command_ack::CommandAckError;
+pub use commands::{ArduPilotMode, FailsafeParams, MavCommandSender, RthAction};
+pub use connection::{FcConfig, FcConnectionType, FcEvent, FcSigning, FlightController};
+pub use log_download::{LogChunk, LogEntry, LOG_CHUNK_SIZE};
+pub use params::MavParamClient;
 pub use telemetry::TelemetryReader;
@@ -0,0 +1,224 @@
+//! Flight controller parameters
+//!
+//! `MavParamClient` reads (PARAM_REQUEST_READ) and writes (PARAM_SET) single
+//! FC parameters by name. The FC answers both with a PARAM_VALUE; a request
+//! it doesn't answer in time is sent again. `set` only succeeds once the
+//! echoed value matches, since the FC may clamp or refuse it; `write` returns
+//! whatever value the FC kept.
+
+use anyhow::{anyhow, Result};
+use mavlink::ardupilotmega::{MavMessage, MavParamType, PARAM_REQUEST_READ_DATA, PARAM_SET_DATA};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+
+use super::commands::{param_id, param_name};
+use super::connection::FlightController;
+
+/// How long each attempt waits for the PARAM_VALUE
+const PARAM_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Attempts per read or write before giving up
+const PARAM_ATTEMPTS: u32 = 3;
+
+/// Tolerance when comparing the value the FC echoes to the one written
+pub(super) const PARAM_TOLERANCE: f32 = 1e-3;
+
+/// Longest MAVLink parameter name
+const MAX_PARAM_NAME_LEN: usize = 16;
+
+/// Reads and writes FC parameters, with retry and timeout
+#[derive(Debug, Clone)]
+pub struct MavParamClient {
+    target_system: u8,
+    target_component: u8,
+    attempts: u32,
+    timeout: Duration,
+}
+
+impl MavParamClient {
+    pub fn new(target_system: u8, target_component: u8) -> Self {
+        Self {
+            target_system,
+            target_component,
+            attempts: PARAM_ATTEMPTS,
+            timeout: PARAM_TIMEOUT,
+        }
+    }
+
+    /// Client for the FC `fc` is connected to
+    pub fn for_controller(fc: &FlightController) -> Self {
+        Self::new(fc.config().target_system, fc.config().target_component)
+    }
+
+    /// Read parameter `name`
+    pub async fn get(&self, fc: &FlightController, name: &str) -> Result<f32> {
+        validate_name(name)?;
+        let msg = MavMessage::PARAM_REQUEST_READ(PARAM_REQUEST_READ_DATA {
+            target_system: self.target_system,
+            target_component: self.target_component,
+            param_id: param_id(name),
+            param_index: -1, // Look up by name
+        });
+        self.request(fc, name, msg).await
+    }
+
+    /// Write parameter `name` and return the value the FC confirmed
+    pub async fn set(&self, fc: &FlightController, name: &str, value: f32) -> Result<f32> {
+        let confirmed = self.write(fc, name, value).await?;
+        if (confirmed - value).abs() > PARAM_TOLERANCE {
+            return Err(anyhow!("FC kept {} at {} (requested {})", name, confirmed, value));
+        }
+        Ok(confirmed)
+    }
+
+    /// Write parameter `name` and return the value the FC echoed, which may
+    /// differ from `value` if the FC clamped or refused it
+    pub async fn write(&self, fc: &FlightController, name: &str, value: f32) -> Result<f32> {
+        validate_name(name)?;
+        if !value.is_finite() {
+            return Err(anyhow!("Invalid value {} for {}", value, name));
+        }
+        println!("[MAVLink] Setting param {} = {}", name, value);
+        let msg = MavMessage::PARAM_SET(PARAM_SET_DATA {
+            target_system: self.target_system,
+            target_component: self.target_component,
+            param_id: param_id(name),
+            param_value: value,
+            param_type: MavParamType::MAV_PARAM_TYPE_REAL32,
+        });
+        self.request(fc, name, msg).await
+    }
+
+    /// Send `msg` until the FC answers with `name`'s PARAM_VALUE
+    async fn request(&self, fc: &FlightController, name: &str, msg: MavMessage) -> Result<f32> {
+        // Subscribe before sending so the PARAM_VALUE can't slip past us
+        let mut messages = fc.subscribe();
+
+        for attempt in 1..=self.attempts {
+            fc.send(msg.clone()).await?;
+
+            let answer = async {
+                loop {
+                    match messages.recv().await {
+                        Ok(MavMessage::PARAM_VALUE(value))
+                            if param_name(&value.param_id) == name =>
+                        {
+                            return Ok(value.param_value);
+                        }
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => {
+                            return Err(anyhow!("FC connection closed"));
+                        }
+                    }
+                }
+            };
+
+            match timeout(self.timeout, answer).await {
+                Ok(result) => return result,
+                Err(_) => eprintln!(
+                    "[MAVLink] No answer for param {} (attempt {}/{})",
+                    name, attempt, self.attempts
+                ),
+            }
+        }
+
+        Err(anyhow!("FC did not answer for param {} after {} attempts", name, self.attempts))
+    }
+}
+
+/// Check a parameter name fits the MAVLink param_id field
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_PARAM_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_graphic());
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid parameter name {:?}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::FcConfig;
+    use mavlink::ardupilotmega::PARAM_VALUE_DATA;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+
+    /// Simulated FC holding `params` that ignores its first `drop` requests
+    /// and keeps RTL_ALT at least 300. Returns the number of requests seen.
+    fn spawn_fc(
+        mut outbound: mpsc::Receiver<MavMessage>,
+        inject: broadcast::Sender<MavMessage>,
+        mut params: HashMap<String, f32>,
+        drop: u32,
+    ) -> Arc<Mutex<u32>> {
+        let requests = Arc::new(Mutex::new(0));
+        let count = requests.clone();
+
+        tokio::spawn(async move {
+            while let Some(msg) = outbound.recv().await {
+                let id = match msg {
+                    MavMessage::PARAM_SET(set) => {
+                        let name = param_name(&set.param_id);
+                        let value = match name.as_str() {
+                            "RTL_ALT" => set.param_value.max(300.0),
+                            _ => set.param_value,
+                        };
+                        params.insert(name, value);
+                        set.param_id
+                    }
+                    MavMessage::PARAM_REQUEST_READ(req) => req.param_id,
+                    _ => continue,
+                };
+                let seen = {
+                    let mut count = count.lock().unwrap();
+                    *count += 1;
+                    *count
+                };
+                if seen <= drop {
+                    continue;
+                }
+                if let Some(&value) = params.get(&param_name(&id)) {
+                    let _ = inject.send(MavMessage::PARAM_VALUE(PARAM_VALUE_DATA {
+                        param_value: value,
+                        param_count: params.len() as u16,
+                        param_index: 0,
+                        param_id: id,
+                        param_type: MavParamType::MAV_PARAM_TYPE_REAL32,
+                    }));
+                }
+            }
+        });
+
+        requests
+    }
+
+    #[tokio::test]
+    async fn test_param_read_and_write_retried() {
+        let (fc, outbound, inject) = FlightController::test_link(FcConfig::default());
+        let params = HashMap::from([("RTL_ALT".to_string(), 1500.0)]);
+        let requests = spawn_fc(outbound, inject, params, 1);
+        let client = MavParamClient {
+            timeout: Duration::from_millis(100),
+            ..MavParamClient::for_controller(&fc)
+        };
+
+        // The first request is lost and sent again
+        assert_eq!(client.get(&fc, "RTL_ALT").await.unwrap(), 1500.0);
+        assert_eq!(*requests.lock().unwrap(), 2);
+        assert_eq!(client.set(&fc, "RTL_ALT", 2000.0).await.unwrap(), 2000.0);
+        assert_eq!(client.get(&fc, "RTL_ALT").await.unwrap(), 2000.0);
+
+        // Clamped by the FC
+        let err = client.set(&fc, "RTL_ALT", 100.0).await.unwrap_err();
+        assert!(err.to_string().contains("kept RTL_ALT at 300"), "{}", err);
+        // Unknown to the FC: never answered
+        assert!(client.get(&fc, "NO_SUCH_PARAM").await.is_err());
+        assert!(client.get(&fc, "FAR_TOO_LONG_PARAM_NAME").await.is_err());
+        assert!(client.set(&fc, "RTL_ALT", f32::NAN).await.is_err());
+    }
+}
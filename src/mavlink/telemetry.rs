@@ -2,24 +2,242 @@
 //!
 //! Reads telemetry from flight controller and converts to ResQTerra format.
 
+use crate::mission;
+use crate::state::{DroneStateStore, FcState};
 use mavlink::ardupilotmega::MavMessage;
 use resqterra_shared::{
-    BatteryStatus, ConnectionQuality, DroneState, FlightControllerStatus, GpsPosition, Telemetry,
-    Transport,
+    BatteryStatus, ConnectionQuality, DroneState, Fault, FlightControllerStatus, GpsCoordinate,
+    GpsPosition, MissionProgress, Telemetry, Transport,
 };
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
+/// Maximum number of distinct payload values tracked (NAMED_VALUE_*)
+const MAX_PAYLOAD_VALUES: usize = 32;
+
+/// Most recent distinct faults kept in the fault list
+const MAX_ACTIVE_FAULTS: usize = 10;
+
+/// Distinct STATUSTEXT lines remembered for deduplication
+const MAX_RECENT_STATUS_TEXTS: usize = 32;
+
+/// Distinct STATUSTEXT lines printed per window before suppressing
+const STATUS_TEXT_LOG_BURST: u32 = 5;
+
+/// STATUSTEXT log rate-limit window
+const STATUS_TEXT_LOG_WINDOW: Duration = Duration::from_secs(1);
+
+/// A fault not re-reported for this long is considered resolved
+const FAULT_RESOLVED_AFTER: Duration = Duration::from_secs(10);
+
+/// Critical faults (CRITICAL or worse) must stay quiet longer before clearing
+const CRITICAL_FAULT_RESOLVED_AFTER: Duration = Duration::from_secs(60);
+
+/// How long the FC has to reflect a commanded arm/disarm before we alert
+const ARM_STATE_GRACE: Duration = Duration::from_secs(3);
+
+/// MAV_SEVERITY_ERROR - companion faults are reported at this level
+const SEVERITY_ERROR: u8 = 3;
+
+/// MAV_SEVERITY_CRITICAL - this level and worse are critical
+const SEVERITY_CRITICAL: u8 = 2;
+
+/// A deduplicated status message with repeat count
+#[derive(Debug, Clone)]
+pub struct StatusTextEntry {
+    pub text: String,
+    /// Worst MAVLink severity seen for this text (lower = more severe)
+    pub severity: u8,
+    pub count: u32,
+    pub last_seen: Instant,
+}
+
+impl StatusTextEntry {
+    /// Whether the fault is still being reported at `now`
+    fn is_active(&self, now: Instant) -> bool {
+        let quiet_for = if self.severity <= SEVERITY_CRITICAL {
+            CRITICAL_FAULT_RESOLVED_AFTER
+        } else {
+            FAULT_RESOLVED_AFTER
+        };
+        now.saturating_duration_since(self.last_seen) < quiet_for
+    }
+
+    fn to_fault(&self, now: Instant) -> Fault {
+        Fault {
+            text: self.text.clone(),
+            severity: self.severity as u32,
+            count: self.count,
+            last_seen_age_ms: now.saturating_duration_since(self.last_seen).as_millis() as u64,
+            active: self.is_active(now),
+        }
+    }
+
+    /// Render for the fault list, e.g. "EKF variance (x12)"
+    fn summary(&self) -> String {
+        if self.count > 1 {
+            format!("{} (x{})", self.text, self.count)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+/// Bounded list of distinct messages, most recently seen last
+#[derive(Debug)]
+struct StatusTextLog {
+    entries: VecDeque<StatusTextEntry>,
+    capacity: usize,
+}
+
+impl StatusTextLog {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Record an occurrence and return how many times it has been seen
+    fn record(&mut self, text: &str, severity: u8, now: Instant) -> u32 {
+        let mut entry = match self.entries.iter().position(|e| e.text == text) {
+            Some(idx) => self.entries.remove(idx).unwrap(),
+            None => StatusTextEntry {
+                text: text.to_string(),
+                severity,
+                count: 0,
+                last_seen: now,
+            },
+        };
+        entry.severity = entry.severity.min(severity);
+        entry.count += 1;
+        entry.last_seen = now;
+        let count = entry.count;
+
+        self.entries.push_back(entry);
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+        count
+    }
+
+    fn summaries(&self) -> Vec<String> {
+        self.entries.iter().map(StatusTextEntry::summary).collect()
+    }
+
+    /// Remove resolved entries matching `texts` (all resolved when empty)
+    ///
+    /// Returns the texts of matching entries that are still active and were kept.
+    fn clear_resolved(&mut self, texts: &[String], now: Instant) -> Vec<String> {
+        let mut kept_active = Vec::new();
+        self.entries.retain(|e| {
+            if !texts.is_empty() && !texts.contains(&e.text) {
+                return true;
+            }
+            if e.is_active(now) {
+                kept_active.push(e.text.clone());
+                return true;
+            }
+            false
+        });
+        kept_active
+    }
+}
+
+/// STATUSTEXT deduplication and log rate limiting
+#[derive(Debug)]
+struct StatusTextFilter {
+    /// All recent distinct messages (any severity)
+    recent: StatusTextLog,
+    /// Recent distinct faults (severity ERROR or worse, plus companion faults)
+    faults: StatusTextLog,
+    window_start: Instant,
+    logged_in_window: u32,
+    suppressed: u32,
+}
+
+impl StatusTextFilter {
+    fn new() -> Self {
+        Self {
+            recent: StatusTextLog::new(MAX_RECENT_STATUS_TEXTS),
+            faults: StatusTextLog::new(MAX_ACTIVE_FAULTS),
+            window_start: Instant::now(),
+            logged_in_window: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Decide whether a message should be printed (first sighting, within budget)
+    fn should_log(&mut self, text: &str, severity: u8, now: Instant) -> bool {
+        if now.duration_since(self.window_start) >= STATUS_TEXT_LOG_WINDOW {
+            if self.suppressed > 0 {
+                println!("[FC] Suppressed {} repeated/excess status messages", self.suppressed);
+            }
+            self.window_start = now;
+            self.logged_in_window = 0;
+            self.suppressed = 0;
+        }
+
+        let first_sighting = self.recent.record(text, severity, now) == 1;
+        if first_sighting && self.logged_in_window < STATUS_TEXT_LOG_BURST {
+            self.logged_in_window += 1;
+            true
+        } else {
+            self.suppressed += 1;
+            false
+        }
+    }
+}
+
+/// Mission item the FC is flying (MISSION_CURRENT)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissionCurrent {
+    /// Index of the mission item being flown
+    pub current: u16,
+    /// Number of mission items (0 = no mission loaded)
+    pub total: u16,
+}
+
+/// Arm state we commanded, awaiting confirmation in the FC heartbeat
+#[derive(Debug, Clone, Copy)]
+struct ArmIntent {
+    armed: bool,
+    commanded_at: Instant,
+}
+
 /// Reads and converts MAVLink telemetry to ResQTerra format
+#[derive(Debug)]
 pub struct TelemetryReader {
     /// Latest GPS position
     position: Arc<RwLock<Option<GpsPosition>>>,
+    /// Home position, once the FC reports it
+    home: Arc<RwLock<Option<GpsCoordinate>>>,
     /// Latest battery status
     battery: Arc<RwLock<Option<BatteryStatus>>>,
     /// Latest FC status
     fc_status: Arc<RwLock<FlightControllerStatus>>,
-    /// Current drone state
-    state: Arc<RwLock<DroneState>>,
+    /// Drone state, fed the FC's arm state and mode
+    state: Arc<DroneStateStore>,
+    /// Latest custom payload values, keyed by NAMED_VALUE name
+    payload_values: Arc<RwLock<BTreeMap<String, f32>>>,
+    /// Names ignored because the payload table was full, each logged once
+    dropped_payload_names: Arc<RwLock<BTreeSet<String>>>,
+    /// STATUSTEXT dedup/rate-limit state
+    status_texts: Arc<RwLock<StatusTextFilter>>,
+    /// Commanded arm state not yet confirmed by the FC
+    arm_intent: Arc<RwLock<Option<ArmIntent>>>,
+    /// Link the companion is currently connected over
+    active_transport: Arc<RwLock<Transport>>,
+    /// Current mission item, once the FC reports one
+    mission_current: Arc<RwLock<Option<MissionCurrent>>>,
+    /// Last mission item the FC reached (MISSION_ITEM_REACHED)
+    mission_reached: Arc<RwLock<Option<u16>>>,
+    /// Location of each uploaded mission item, for the progress estimate
+    mission_path: Arc<RwLock<Vec<Option<GpsCoordinate>>>>,
+    /// ID of the mission being flown, empty outside a mission
+    mission_id: Arc<RwLock<String>>,
     /// Uptime in seconds
     uptime_seconds: Arc<RwLock<u64>>,
     /// Start time for calculating uptime
@@ -31,6 +249,7 @@ impl TelemetryReader {
     pub fn new() -> Self {
         Self {
             position: Arc::new(RwLock::new(None)),
+            home: Arc::new(RwLock::new(None)),
             battery: Arc::new(RwLock::new(None)),
             fc_status: Arc::new(RwLock::new(FlightControllerStatus {
                 armed: false,
@@ -39,12 +258,27 @@ impl TelemetryReader {
                 error_count: 0,
                 active_faults: vec![],
             })),
-            state: Arc::new(RwLock::new(DroneState::DroneIdle)),
+            state: Arc::new(DroneStateStore::new()),
+            payload_values: Arc::new(RwLock::new(BTreeMap::new())),
+            dropped_payload_names: Arc::new(RwLock::new(BTreeSet::new())),
+            status_texts: Arc::new(RwLock::new(StatusTextFilter::new())),
+            arm_intent: Arc::new(RwLock::new(None)),
+            active_transport: Arc::new(RwLock::new(Transport::Transport5g)),
+            mission_current: Arc::new(RwLock::new(None)),
+            mission_reached: Arc::new(RwLock::new(None)),
+            mission_path: Arc::new(RwLock::new(Vec::new())),
+            mission_id: Arc::new(RwLock::new(String::new())),
             uptime_seconds: Arc::new(RwLock::new(0)),
             start_time: std::time::Instant::now(),
         }
     }
 
+    /// Report into the drone state shared with the safety monitor
+    pub fn with_state_store(mut self, state: Arc<DroneStateStore>) -> Self {
+        self.state = state;
+        self
+    }
+
     /// Process a MAVLink message and update telemetry
     pub async fn process_message(&self, msg: &MavMessage) {
         match msg {
@@ -61,6 +295,14 @@ impl TelemetryReader {
                 *self.position.write().await = Some(gps);
             }
 
+            MavMessage::HOME_POSITION(home) => {
+                *self.home.write().await = Some(GpsCoordinate {
+                    latitude: home.latitude as f64 / 1e7,
+                    longitude: home.longitude as f64 / 1e7,
+                    altitude_m: home.altitude as f32 / 1000.0, // mm to m
+                });
+            }
+
             MavMessage::GPS_RAW_INT(gps) => {
                 // Update satellite count and HDOP
                 if let Some(ref mut pos) = *self.position.write().await {
@@ -108,31 +350,48 @@ impl TelemetryReader {
                 let armed = (hb.base_mode.bits() & 0x80) != 0; // MAV_MODE_FLAG_SAFETY_ARMED
 
                 let mut fc = self.fc_status.write().await;
-                fc.armed = armed;
+                let was_armed = std::mem::replace(&mut fc.armed, armed);
                 fc.mode = mode_to_string(hb.custom_mode);
 
                 // Update drone state based on mode
                 drop(fc);
                 self.update_state_from_mode(hb.custom_mode, armed).await;
+
+                // The mission, including the flight home, ends when the drone disarms
+                if was_armed && !armed {
+                    let mut mission_id = self.mission_id.write().await;
+                    if !mission_id.is_empty() {
+                        println!("[FC] Mission {} complete", mission_id);
+                        mission_id.clear();
+                        *self.mission_reached.write().await = None;
+                        self.mission_path.write().await.clear();
+                    }
+                }
+
+                if let Some(alert) = self.check_arm_intent(armed, Instant::now()).await {
+                    eprintln!("[FC] ALERT: {}", alert);
+                }
             }
 
             MavMessage::STATUSTEXT(text) => {
-                // Log status text and check for faults
                 let text_str = String::from_utf8_lossy(&text.text).to_string();
                 let text_str = text_str.trim_end_matches('\0');
+                let severity = text.severity as u8;
 
-                if text.severity as u8 <= 3 {
-                    // EMERGENCY, ALERT, CRITICAL, ERROR
-                    let mut fc = self.fc_status.write().await;
-                    fc.active_faults.push(text_str.to_string());
+                let should_log = self
+                    .status_texts
+                    .write()
+                    .await
+                    .should_log(text_str, severity, Instant::now());
 
-                    // Keep only last 10 faults
-                    if fc.active_faults.len() > 10 {
-                        fc.active_faults.remove(0);
-                    }
+                if severity <= SEVERITY_ERROR {
+                    // EMERGENCY, ALERT, CRITICAL, ERROR
+                    self.record_fault(text_str, severity).await;
                 }
 
-                println!("[FC] {}: {}", severity_to_string(text.severity as u8), text_str);
+                if should_log {
+                    println!("[FC] {}: {}", severity_to_string(severity), text_str);
+                }
             }
 
             MavMessage::VFR_HUD(hud) => {
@@ -143,15 +402,55 @@ impl TelemetryReader {
                 }
             }
 
+            MavMessage::MISSION_CURRENT(current) => {
+                *self.mission_current.write().await = Some(MissionCurrent {
+                    current: current.seq,
+                    total: current.total,
+                });
+            }
+
+            MavMessage::MISSION_ITEM_REACHED(reached) => {
+                *self.mission_reached.write().await = Some(reached.seq);
+            }
+
+            MavMessage::NAMED_VALUE_FLOAT(named) => {
+                self.record_payload_value(&named.name, named.value).await;
+            }
+
+            MavMessage::NAMED_VALUE_INT(named) => {
+                self.record_payload_value(&named.name, named.value as f32).await;
+            }
+
             _ => {
                 // Other messages we don't process
             }
         }
     }
 
-    /// Update drone state based on flight mode
+    /// Store a named payload value, ignoring new names once the map is full
+    ///
+    /// An ignored name is logged the first time only (up to
+    /// `MAX_PAYLOAD_VALUES` names), not on every value the payload sends.
+    async fn record_payload_value(&self, name: &[u8; 10], value: f32) {
+        let Some(name) = named_value_name(name) else {
+            return;
+        };
+
+        let mut values = self.payload_values.write().await;
+        if values.len() >= MAX_PAYLOAD_VALUES && !values.contains_key(&name) {
+            drop(values);
+            let mut dropped = self.dropped_payload_names.write().await;
+            if dropped.len() < MAX_PAYLOAD_VALUES && dropped.insert(name.clone()) {
+                eprintln!("[FC] Payload value table full, ignoring '{}'", name);
+            }
+            return;
+        }
+        values.insert(name, value);
+    }
+
+    /// Record the FC's arm state and the drone state its flight mode implies
     async fn update_state_from_mode(&self, custom_mode: u32, armed: bool) {
-        let new_state = match custom_mode {
+        let mode = match custom_mode {
             6 => DroneState::DroneReturningHome, // RTL
             9 => DroneState::DroneLanding,       // LAND
             3 => DroneState::DroneInMission,     // AUTO
@@ -160,7 +459,7 @@ impl TelemetryReader {
             _ => DroneState::DroneArmed,
         };
 
-        *self.state.write().await = new_state;
+        self.state.update_fc(FcState { armed, mode }).await;
     }
 
     /// Get current telemetry as ResQTerra Telemetry message
@@ -168,28 +467,63 @@ impl TelemetryReader {
         *self.uptime_seconds.write().await = self.start_time.elapsed().as_secs();
 
         Telemetry {
-            position: self.position.read().await.clone(),
-            battery: self.battery.read().await.clone(),
-            state: (*self.state.read().await).into(),
+            position: *self.position.read().await,
+            battery: *self.battery.read().await,
+            state: self.state.state().await.into(),
             fc_status: Some(self.fc_status.read().await.clone()),
             uptime_seconds: *self.uptime_seconds.read().await,
             conn_quality: Some(ConnectionQuality {
-                active_transport: Transport::Transport5g.into(),
+                active_transport: (*self.active_transport.read().await).into(),
                 rssi_dbm: 0,
                 latency_ms: 0,
                 packet_loss_percent: 0.0,
+                jitter_ms: 0,
             }),
+            payload_values: self.payload_values.read().await.clone(),
+            keyframe_id: 0,
+            mission_id: self.mission_id.read().await.clone(),
+            video: None,
         }
     }
 
+    /// Tag telemetry with `mission_id` until the drone next disarms
+    ///
+    /// Progress restarts; the FC re-reports its current item shortly after.
+    pub async fn start_mission(&self, mission_id: &str) {
+        *self.mission_id.write().await = mission_id.to_string();
+        *self.mission_current.write().await = None;
+        *self.mission_reached.write().await = None;
+        self.mission_path.write().await.clear();
+    }
+
+    /// Record where each uploaded mission item lies (see `MavCommandSender::mission_path`)
+    pub async fn set_mission_path(&self, path: Vec<Option<GpsCoordinate>>) {
+        *self.mission_path.write().await = path;
+    }
+
+    /// Record the link telemetry is reported over (the server sizes uploads by it)
+    pub async fn set_active_transport(&self, transport: Transport) {
+        *self.active_transport.write().await = transport;
+    }
+
+    /// Get the mission being flown (empty outside a mission)
+    pub async fn get_mission_id(&self) -> String {
+        self.mission_id.read().await.clone()
+    }
+
     /// Get current drone state
     pub async fn get_state(&self) -> DroneState {
-        *self.state.read().await
+        self.state.state().await
     }
 
     /// Get current GPS position
     pub async fn get_position(&self) -> Option<GpsPosition> {
-        self.position.read().await.clone()
+        *self.position.read().await
+    }
+
+    /// Get the home position reported by the FC
+    pub async fn get_home(&self) -> Option<GpsCoordinate> {
+        *self.home.read().await
     }
 
     /// Get current battery status
@@ -197,11 +531,84 @@ impl TelemetryReader {
         self.battery.read().await.clone()
     }
 
+    /// Get latest custom payload values (gas, radiation, ...)
+    pub async fn get_payload_values(&self) -> BTreeMap<String, f32> {
+        self.payload_values.read().await.clone()
+    }
+
+    /// Get the FC's current mission item and mission length
+    pub async fn get_mission_current(&self) -> Option<MissionCurrent> {
+        *self.mission_current.read().await
+    }
+
+    /// Progress of the mission being flown, once the FC reports a loaded mission
+    pub async fn get_mission_progress(&self) -> Option<MissionProgress> {
+        let mission_id = self.mission_id.read().await.clone();
+        let current = (*self.mission_current.read().await)?;
+        if mission_id.is_empty() || current.total == 0 {
+            return None;
+        }
+
+        let position = self.position.read().await.clone();
+        let estimate = mission::estimate_progress(
+            &self.mission_path.read().await,
+            current.current as usize,
+            current.total as usize,
+            position.as_ref(),
+        );
+        Some(MissionProgress {
+            mission_id,
+            current_waypoint: current.current as u32,
+            total_waypoints: current.total as u32,
+            last_reached: self.mission_reached.read().await.map(u32::from),
+            percent_complete: estimate.percent_complete,
+            distance_remaining_m: estimate.distance_remaining_m.map(|d| d as f32),
+            eta_seconds: estimate.eta_seconds,
+        })
+    }
+
     /// Check if drone is armed
     pub async fn is_armed(&self) -> bool {
         self.fc_status.read().await.armed
     }
 
+    /// Record that we commanded the FC to arm or disarm
+    ///
+    /// If the heartbeat still disagrees after `ARM_STATE_GRACE` (e.g. a disarm
+    /// rejected because the drone is flying), a fault is raised.
+    pub async fn expect_armed(&self, armed: bool) {
+        *self.arm_intent.write().await = Some(ArmIntent {
+            armed,
+            commanded_at: Instant::now(),
+        });
+    }
+
+    /// Compare the commanded arm state with the one the FC reports
+    ///
+    /// Returns the alert raised when the FC still disagrees after the grace
+    /// window; the intent is dropped once confirmed or alerted.
+    async fn check_arm_intent(&self, reported_armed: bool, now: Instant) -> Option<String> {
+        let mut intent = self.arm_intent.write().await;
+        let pending = (*intent)?;
+        if pending.armed == reported_armed {
+            *intent = None;
+            return None;
+        }
+        if now.saturating_duration_since(pending.commanded_at) < ARM_STATE_GRACE {
+            return None;
+        }
+        *intent = None;
+        drop(intent);
+
+        let alert = if pending.armed {
+            "Commanded arm not reflected by FC (still disarmed)"
+        } else {
+            "Commanded disarm not reflected by FC (still armed)"
+        };
+        self.add_fault(alert).await;
+        Some(alert.to_string())
+    }
+
     /// Check if we have GPS lock
     pub async fn has_gps_lock(&self) -> bool {
         self.fc_status.read().await.gps_lock
@@ -211,6 +618,42 @@ impl TelemetryReader {
     pub async fn get_mode(&self) -> String {
         self.fc_status.read().await.mode.clone()
     }
+
+    /// Record a fault raised by the companion (reported alongside FC faults)
+    ///
+    /// Repeats of a fault collapse into a single entry with a count.
+    pub async fn add_fault(&self, fault: impl AsRef<str>) {
+        self.record_fault(fault.as_ref(), SEVERITY_ERROR).await;
+    }
+
+    async fn record_fault(&self, text: &str, severity: u8) {
+        let mut filter = self.status_texts.write().await;
+        filter.faults.record(text, severity, Instant::now());
+        self.fc_status.write().await.active_faults = filter.faults.summaries();
+    }
+
+    /// Get the deduplicated fault history (oldest first)
+    pub async fn get_fault_entries(&self) -> Vec<StatusTextEntry> {
+        self.status_texts.read().await.faults.entries.iter().cloned().collect()
+    }
+
+    /// Get the current fault list (oldest first)
+    pub async fn get_faults(&self) -> Vec<Fault> {
+        let now = Instant::now();
+        let filter = self.status_texts.read().await;
+        filter.faults.entries.iter().map(|e| e.to_fault(now)).collect()
+    }
+
+    /// Clear faults the FC no longer reports
+    ///
+    /// Only resolved faults are removed; faults matching `texts` (or any
+    /// fault, when empty) that are still active are kept and returned.
+    pub async fn clear_resolved_faults(&self, texts: &[String]) -> Vec<String> {
+        let mut filter = self.status_texts.write().await;
+        let kept_active = filter.faults.clear_resolved(texts, Instant::now());
+        self.fc_status.write().await.active_faults = filter.faults.summaries();
+        kept_active
+    }
 }
 
 impl Default for TelemetryReader {
@@ -245,6 +688,13 @@ fn mode_to_string(mode: u32) -> String {
     }
 }
 
+/// Decode a NAMED_VALUE name (up to 10 chars, NUL-terminated only when shorter)
+fn named_value_name(raw: &[u8; 10]) -> Option<String> {
+    let len = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    let name = String::from_utf8_lossy(&raw[..len]).trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
+
 /// Convert MAVLink severity to string
 fn severity_to_string(severity: u8) -> &'static str {
     match severity {
@@ -263,6 +713,26 @@ fn severity_to_string(severity: u8) -> &'static str {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use mavlink::ardupilotmega::{
+        MavModeFlag, MavSeverity, HEARTBEAT_DATA, MISSION_CURRENT_DATA, MISSION_ITEM_REACHED_DATA,
+        NAMED_VALUE_FLOAT_DATA, NAMED_VALUE_INT_DATA, STATUSTEXT_DATA,
+    };
+
+    fn status_text(severity: MavSeverity, text: &str) -> MavMessage {
+        let mut raw = [0u8; 50];
+        raw[..text.len()].copy_from_slice(text.as_bytes());
+        MavMessage::STATUSTEXT(STATUSTEXT_DATA {
+            severity,
+            text: raw,
+            ..Default::default()
+        })
+    }
+
+    fn name10(name: &str) -> [u8; 10] {
+        let mut raw = [0u8; 10];
+        raw[..name.len()].copy_from_slice(name.as_bytes());
+        raw
+    }
 
     #[tokio::test]
     async fn test_telemetry_reader_creation() {
@@ -277,4 +747,238 @@ mod tests {
         assert_eq!(mode_to_string(4), "GUIDED");
         assert_eq!(mode_to_string(6), "RTL");
     }
+
+    #[tokio::test]
+    async fn test_named_values_captured() {
+        let reader = TelemetryReader::new();
+
+        reader
+            .process_message(&MavMessage::NAMED_VALUE_FLOAT(NAMED_VALUE_FLOAT_DATA {
+                name: name10("CO_PPM"),
+                value: 12.5,
+                ..Default::default()
+            }))
+            .await;
+        // Full 10-char name arrives without a NUL terminator
+        reader
+            .process_message(&MavMessage::NAMED_VALUE_INT(NAMED_VALUE_INT_DATA {
+                name: name10("RAD_CPM_HI"),
+                value: 340,
+                ..Default::default()
+            }))
+            .await;
+        reader
+            .process_message(&MavMessage::NAMED_VALUE_FLOAT(NAMED_VALUE_FLOAT_DATA {
+                name: name10("CO_PPM"),
+                value: 14.0,
+                ..Default::default()
+            }))
+            .await;
+
+        let values = reader.get_payload_values().await;
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["CO_PPM"], 14.0);
+        assert_eq!(values["RAD_CPM_HI"], 340.0);
+        assert_eq!(reader.get_telemetry().await.payload_values, values);
+    }
+
+    #[tokio::test]
+    async fn test_named_values_bounded() {
+        let reader = TelemetryReader::new();
+        for _ in 0..2 {
+            for i in 0..MAX_PAYLOAD_VALUES + 5 {
+                reader
+                    .record_payload_value(&name10(&format!("S{}", i)), i as f32)
+                    .await;
+            }
+        }
+        // Existing names still update once the table is full
+        reader.record_payload_value(&name10("S0"), 99.0).await;
+        reader.record_payload_value(&[0u8; 10], 1.0).await;
+
+        let values = reader.get_payload_values().await;
+        assert_eq!(values.len(), MAX_PAYLOAD_VALUES);
+        assert_eq!(values["S0"], 99.0);
+        assert!(!values.contains_key(&format!("S{}", MAX_PAYLOAD_VALUES)));
+        // Each ignored name is only logged once
+        assert_eq!(reader.dropped_payload_names.read().await.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_status_text_storm_deduplicated() {
+        let reader = TelemetryReader::new();
+        for _ in 0..100 {
+            reader
+                .process_message(&status_text(
+                    MavSeverity::MAV_SEVERITY_CRITICAL,
+                    "EKF variance",
+                ))
+                .await;
+        }
+
+        let entries = reader.get_fault_entries().await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].text, "EKF variance");
+        assert_eq!(entries[0].count, 100);
+
+        let telemetry = reader.get_telemetry().await;
+        assert_eq!(
+            telemetry.fc_status.unwrap().active_faults,
+            vec!["EKF variance (x100)".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_text_faults_bounded() {
+        let reader = TelemetryReader::new();
+        for i in 0..MAX_ACTIVE_FAULTS + 3 {
+            reader
+                .process_message(&status_text(MavSeverity::MAV_SEVERITY_ERROR, &format!("fault {}", i)))
+                .await;
+        }
+        // Info messages never enter the fault list
+        reader
+            .process_message(&status_text(MavSeverity::MAV_SEVERITY_INFO, "armed"))
+            .await;
+
+        let entries = reader.get_fault_entries().await;
+        assert_eq!(entries.len(), MAX_ACTIVE_FAULTS);
+        assert_eq!(entries[0].text, "fault 3");
+    }
+
+    #[test]
+    fn test_clear_resolved_keeps_active_faults() {
+        let start = Instant::now();
+        let now = start + FAULT_RESOLVED_AFTER;
+        let mut log = StatusTextLog::new(MAX_ACTIVE_FAULTS);
+        log.record("GPS glitch", 3, start);
+        log.record("EKF variance", 2, start);
+        log.record("Compass error", 3, now);
+
+        let kept = log.clear_resolved(&[], now);
+
+        // Resolved ERROR cleared; recent ERROR and quiet-but-critical kept
+        assert_eq!(kept, vec!["EKF variance".to_string(), "Compass error".to_string()]);
+        assert_eq!(log.summaries(), vec!["EKF variance", "Compass error"]);
+
+        let later = now + CRITICAL_FAULT_RESOLVED_AFTER;
+        assert!(log.clear_resolved(&["EKF variance".to_string()], later).is_empty());
+        assert_eq!(log.summaries(), vec!["Compass error"]);
+    }
+
+    #[test]
+    fn test_status_text_log_rate_limited() {
+        let mut filter = StatusTextFilter::new();
+        let now = Instant::now();
+
+        assert!(filter.should_log("PreArm: GPS", 4, now));
+        assert!(!filter.should_log("PreArm: GPS", 4, now), "repeats are not logged");
+
+        let logged = (0..20)
+            .filter(|i| filter.should_log(&format!("msg {}", i), 6, now))
+            .count();
+        assert_eq!(logged as u32, STATUS_TEXT_LOG_BURST - 1);
+
+        // New window resets the budget
+        assert!(filter.should_log("fresh", 6, now + STATUS_TEXT_LOG_WINDOW));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_disarm_raises_alert() {
+        let reader = TelemetryReader::new();
+        reader.expect_armed(false).await;
+        let start = Instant::now();
+
+        // FC rejects the disarm (flying) and keeps reporting armed
+        reader
+            .process_message(&MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+                base_mode: MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED,
+                ..Default::default()
+            }))
+            .await;
+        assert!(reader.is_armed().await);
+        assert!(reader.get_faults().await.is_empty(), "still within grace window");
+
+        let alert = reader.check_arm_intent(true, start + ARM_STATE_GRACE).await;
+        assert_eq!(
+            alert.as_deref(),
+            Some("Commanded disarm not reflected by FC (still armed)")
+        );
+        assert_eq!(reader.get_fault_entries().await[0].text, alert.unwrap());
+
+        // Alerted once; the intent is dropped
+        assert!(reader.check_arm_intent(true, start + ARM_STATE_GRACE * 2).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_confirmed_arm_state_clears_intent() {
+        let reader = TelemetryReader::new();
+        reader.expect_armed(false).await;
+        let start = Instant::now();
+
+        assert!(reader.check_arm_intent(false, start).await.is_none());
+        assert!(reader.check_arm_intent(true, start + ARM_STATE_GRACE).await.is_none());
+        assert!(reader.get_faults().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_telemetry_in_mission_carries_mission_id() {
+        let reader = TelemetryReader::new();
+        let heartbeat = |custom_mode, armed: bool| {
+            MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+                custom_mode,
+                base_mode: if armed {
+                    MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED
+                } else {
+                    MavModeFlag::empty()
+                },
+                ..Default::default()
+            })
+        };
+        assert_eq!(reader.get_telemetry().await.mission_id, "");
+
+        // Started on the ground: the ID holds through arming, AUTO and RTL
+        reader.start_mission("survey-7").await;
+        for (mode, armed) in [(0, false), (0, true), (3, true), (6, true)] {
+            reader.process_message(&heartbeat(mode, armed)).await;
+            assert_eq!(reader.get_telemetry().await.mission_id, "survey-7");
+        }
+
+        // Landed and disarmed: telemetry is no longer part of the mission
+        reader.process_message(&heartbeat(6, false)).await;
+        assert_eq!(reader.get_telemetry().await.mission_id, "");
+    }
+
+    #[tokio::test]
+    async fn test_mission_progress_reported_in_mission() {
+        let reader = TelemetryReader::new();
+        let current = |seq, total| {
+            MavMessage::MISSION_CURRENT(MISSION_CURRENT_DATA {
+                seq,
+                total,
+                ..Default::default()
+            })
+        };
+
+        // The FC's mission item alone is not a mission of ours
+        reader.process_message(&current(1, 4)).await;
+        assert!(reader.get_mission_progress().await.is_none());
+
+        reader.start_mission("survey-7").await;
+        assert!(reader.get_mission_progress().await.is_none());
+        reader.process_message(&current(2, 4)).await;
+        reader
+            .process_message(&MavMessage::MISSION_ITEM_REACHED(MISSION_ITEM_REACHED_DATA {
+                seq: 1,
+            }))
+            .await;
+
+        // No path uploaded: progress by item index
+        let progress = reader.get_mission_progress().await.unwrap();
+        assert_eq!(progress.mission_id, "survey-7");
+        assert_eq!((progress.current_waypoint, progress.total_waypoints), (2, 4));
+        assert_eq!(progress.last_reached, Some(1));
+        assert_eq!(progress.percent_complete, 50.0);
+        assert_eq!(progress.distance_remaining_m, None);
+    }
 }
@@ -0,0 +1,10 @@
+//! Mission Planning Module
+//!
+//! Turns a mission start into the waypoints the flight controller flies, and
+//! estimates how far along them the drone is.
+
+mod pattern;
+mod progress;
+
+pub use pattern::{generate_pattern, CameraFootprint};
+pub use progress::estimate_progress;
@@ -0,0 +1,328 @@
+//! Survey pattern generation
+//!
+//! Turns a `SurveyArea` boundary into the waypoints that cover it: lawnmower
+//! lines, a cross-hatch grid, an inward spiral or a single perimeter lap.
+//! Passes are spaced by the camera footprint at the survey altitude, so
+//! neighbouring passes overlap by the configured sidelap. Polygons are
+//! expected to be convex; a concave boundary is still covered, but the
+//! spiral and perimeter may cut across its notches.
+
+use crate::geo::LocalFrame;
+use resqterra_shared::{GpsCoordinate, ScanPattern};
+
+/// Horizontal field of view of the survey camera (degrees)
+pub const DEFAULT_CAMERA_HFOV_DEG: f64 = 70.0;
+
+/// Fraction of the footprint neighbouring passes overlap by
+pub const DEFAULT_SIDELAP: f64 = 0.3;
+
+/// Closest passes are ever spaced (m), whatever the footprint
+const MIN_SPACING_M: f64 = 1.0;
+
+/// Ground footprint of the survey camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraFootprint {
+    /// Horizontal field of view (degrees)
+    pub hfov_deg: f64,
+    /// Overlap between neighbouring passes (0..1)
+    pub sidelap: f64,
+}
+
+impl Default for CameraFootprint {
+    fn default() -> Self {
+        Self {
+            hfov_deg: DEFAULT_CAMERA_HFOV_DEG,
+            sidelap: DEFAULT_SIDELAP,
+        }
+    }
+}
+
+impl CameraFootprint {
+    /// Distance between passes flown at `altitude_m` (m)
+    pub fn line_spacing_m(&self, altitude_m: f32) -> f64 {
+        let width = 2.0 * altitude_m as f64 * (self.hfov_deg.to_radians() / 2.0).tan();
+        (width * (1.0 - self.sidelap.clamp(0.0, 0.95))).max(MIN_SPACING_M)
+    }
+}
+
+/// Waypoints covering `boundary` with `pattern`, at `altitude_m`
+///
+/// `None` when the pattern is not generated here (unknown or custom) or the
+/// boundary has fewer than three vertices; the boundary is then flown as given.
+pub fn generate_pattern(
+    pattern: ScanPattern,
+    boundary: &[GpsCoordinate],
+    altitude_m: f32,
+    spacing_m: f64,
+) -> Option<Vec<GpsCoordinate>> {
+    if boundary.len() < 3 {
+        return None;
+    }
+    let spacing_m = spacing_m.max(MIN_SPACING_M);
+    let polygon = Polygon::new(boundary);
+
+    let points = match pattern {
+        ScanPattern::PatternLawnmower => polygon.lawnmower(polygon.sweep_angle(), spacing_m),
+        ScanPattern::PatternGrid => {
+            let angle = polygon.sweep_angle();
+            let mut points = polygon.lawnmower(angle, spacing_m);
+            points.extend(polygon.lawnmower(angle + std::f64::consts::FRAC_PI_2, spacing_m));
+            points
+        }
+        ScanPattern::PatternSpiral => polygon.spiral(spacing_m),
+        ScanPattern::PatternPerimeter => polygon.perimeter(spacing_m),
+        ScanPattern::PatternUnknown | ScanPattern::PatternCustom => return None,
+    };
+
+    Some(
+        points
+            .into_iter()
+            .map(|(east, north)| {
+                let (latitude, longitude) = polygon.frame.to_global(east, north);
+                GpsCoordinate {
+                    latitude,
+                    longitude,
+                    altitude_m,
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Survey boundary in a local east/north frame (m)
+struct Polygon {
+    frame: LocalFrame,
+    vertices: Vec<(f64, f64)>,
+}
+
+impl Polygon {
+    fn new(boundary: &[GpsCoordinate]) -> Self {
+        let n = boundary.len() as f64;
+        let lat0 = boundary.iter().map(|p| p.latitude).sum::<f64>() / n;
+        let lon0 = boundary.iter().map(|p| p.longitude).sum::<f64>() / n;
+        let frame = LocalFrame::new(lat0, lon0);
+        let vertices = boundary
+            .iter()
+            .map(|p| frame.to_local(p.latitude, p.longitude))
+            .collect();
+        Self { frame, vertices }
+    }
+
+    fn edges(&self) -> impl Iterator<Item = ((f64, f64), (f64, f64))> + '_ {
+        let next = self.vertices.iter().cycle().skip(1);
+        self.vertices.iter().copied().zip(next.copied())
+    }
+
+    /// Direction of the longest edge; sweeping along it needs the fewest turns
+    fn sweep_angle(&self) -> f64 {
+        self.edges()
+            .max_by(|(a1, b1), (a2, b2)| {
+                let l1 = (b1.0 - a1.0).hypot(b1.1 - a1.1);
+                let l2 = (b2.0 - a2.0).hypot(b2.1 - a2.1);
+                l1.total_cmp(&l2)
+            })
+            .map(|(a, b)| (b.1 - a.1).atan2(b.0 - a.0).rem_euclid(std::f64::consts::PI))
+            .unwrap_or(0.0)
+    }
+
+    /// Parallel passes along `angle`, flown back and forth
+    fn lawnmower(&self, angle: f64, spacing: f64) -> Vec<(f64, f64)> {
+        let (sin, cos) = angle.sin_cos();
+        // Rotate so the passes run along the x axis
+        let rotated: Vec<_> = self
+            .vertices
+            .iter()
+            .map(|&(x, y)| (x * cos + y * sin, -x * sin + y * cos))
+            .collect();
+        let y_min = rotated.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+        let y_max = rotated.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+
+        // Half a spacing in from each side; an area narrower than one pass
+        // gets a single pass down the middle
+        let mut lines = Vec::new();
+        let mut y = y_min + spacing / 2.0;
+        while y < y_max {
+            lines.push(y);
+            y += spacing;
+        }
+        if lines.is_empty() {
+            lines.push((y_min + y_max) / 2.0);
+        }
+
+        let mut points = Vec::new();
+        for (i, y) in lines.into_iter().enumerate() {
+            let mut xs: Vec<f64> = rotated
+                .iter()
+                .zip(rotated.iter().cycle().skip(1))
+                .filter(|(a, b)| (a.1 <= y) != (b.1 <= y))
+                .map(|(a, b)| a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1))
+                .collect();
+            xs.sort_by(f64::total_cmp);
+            // Every other line runs the other way
+            if i % 2 == 1 {
+                xs.reverse();
+            }
+            for x in xs.chunks_exact(2).flatten() {
+                points.push((x * cos - y * sin, x * sin + y * cos));
+            }
+        }
+        points
+    }
+
+    /// Rings shrinking towards the centre, one spacing apart, ending there
+    fn spiral(&self, spacing: f64) -> Vec<(f64, f64)> {
+        let (centre, inradius) = self.centre();
+        let mut points = Vec::new();
+        let mut inset = spacing / 2.0;
+        while inset < inradius {
+            points.extend(self.ring(centre, inradius, inset));
+            inset += spacing;
+        }
+        points.push(centre);
+        points
+    }
+
+    /// One closed lap half a spacing inside the boundary
+    fn perimeter(&self, spacing: f64) -> Vec<(f64, f64)> {
+        let (centre, inradius) = self.centre();
+        let inset = (spacing / 2.0).min(inradius / 2.0);
+        let mut points = self.ring(centre, inradius, inset);
+        points.extend(points.first().copied());
+        points
+    }
+
+    /// The boundary scaled about `centre`, moving its nearest edge in by `inset`
+    ///
+    /// Farther edges move in by more, so rings never leave a gap.
+    fn ring(&self, centre: (f64, f64), inradius: f64, inset: f64) -> Vec<(f64, f64)> {
+        if inradius <= 0.0 {
+            return self.vertices.clone();
+        }
+        let scale = 1.0 - inset / inradius;
+        self.vertices
+            .iter()
+            .map(|&(x, y)| {
+                (
+                    centre.0 + (x - centre.0) * scale,
+                    centre.1 + (y - centre.1) * scale,
+                )
+            })
+            .collect()
+    }
+
+    /// Vertex centroid and its distance to the nearest edge
+    fn centre(&self) -> ((f64, f64), f64) {
+        let n = self.vertices.len() as f64;
+        let centre = (
+            self.vertices.iter().map(|p| p.0).sum::<f64>() / n,
+            self.vertices.iter().map(|p| p.1).sum::<f64>() / n,
+        );
+        let inradius = self
+            .edges()
+            .map(|(a, b)| distance_to_segment(centre, a, b))
+            .fold(f64::INFINITY, f64::min);
+        (centre, inradius)
+    }
+}
+
+fn distance_to_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_sq = dx * dx + dy * dy;
+    let t = if length_sq > 0.0 {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (p.0 - a.0 - t * dx).hypot(p.1 - a.1 - t * dy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 100 m (east) x 60 m (north) rectangle at 47.5N
+    fn rectangle() -> Vec<GpsCoordinate> {
+        let frame = LocalFrame::new(47.5, 8.5);
+        [(0.0, 0.0), (100.0, 0.0), (100.0, 60.0), (0.0, 60.0)]
+            .iter()
+            .map(|&(east, north)| {
+                let (latitude, longitude) = frame.to_global(east, north);
+                GpsCoordinate {
+                    latitude,
+                    longitude,
+                    altitude_m: 0.0,
+                }
+            })
+            .collect()
+    }
+
+    fn local(points: &[GpsCoordinate]) -> Vec<(f64, f64)> {
+        let frame = LocalFrame::new(47.5, 8.5);
+        points
+            .iter()
+            .map(|p| frame.to_local(p.latitude, p.longitude))
+            .collect()
+    }
+
+    #[test]
+    fn test_footprint_spacing() {
+        let camera = CameraFootprint {
+            hfov_deg: 90.0,
+            sidelap: 0.5,
+        };
+        // 80 m wide at 40 m, half of it overlapping
+        assert!((camera.line_spacing_m(40.0) - 40.0).abs() < 1e-9);
+        assert_eq!(camera.line_spacing_m(0.0), MIN_SPACING_M);
+    }
+
+    #[test]
+    fn test_lawnmower_covers_rectangle_along_long_side() {
+        let waypoints = generate_pattern(ScanPattern::PatternLawnmower, &rectangle(), 40.0, 20.0).unwrap();
+        let points = local(&waypoints);
+
+        // Passes at 10, 30 and 50 m north, two waypoints each, alternating
+        assert_eq!(points.len(), 6);
+        for (i, pair) in points.chunks(2).enumerate() {
+            let north = 10.0 + 20.0 * i as f64;
+            assert!(pair.iter().all(|p| (p.1 - north).abs() < 0.01), "got {:?}", pair);
+            let (start, end) = if i % 2 == 0 { (0.0, 100.0) } else { (100.0, 0.0) };
+            assert!((pair[0].0 - start).abs() < 0.01 && (pair[1].0 - end).abs() < 0.01);
+        }
+        assert!(waypoints.iter().all(|p| p.altitude_m == 40.0));
+
+        // Cross-hatch adds passes the other way
+        let grid = generate_pattern(ScanPattern::PatternGrid, &rectangle(), 40.0, 20.0).unwrap();
+        assert_eq!(grid.len(), 6 + 10);
+    }
+
+    #[test]
+    fn test_spiral_and_perimeter_stay_inside() {
+        let inside = |points: &[(f64, f64)]| {
+            points
+                .iter()
+                .all(|p| (0.0..=100.0).contains(&p.0) && (0.0..=60.0).contains(&p.1))
+        };
+
+        let spiral = local(&generate_pattern(ScanPattern::PatternSpiral, &rectangle(), 40.0, 25.0).unwrap());
+        // The second ring, 37.5 m in, would pass the centre 30 m from the
+        // long sides, so one ring and then the centre
+        assert_eq!(spiral.len(), 4 + 1);
+        assert!(inside(&spiral));
+        let centre = spiral.last().unwrap();
+        assert!((centre.0 - 50.0).abs() < 0.01 && (centre.1 - 30.0).abs() < 0.01);
+
+        let perimeter =
+            local(&generate_pattern(ScanPattern::PatternPerimeter, &rectangle(), 40.0, 20.0).unwrap());
+        assert_eq!(perimeter.len(), 5);
+        assert_eq!(perimeter.first(), perimeter.last());
+        assert!(inside(&perimeter));
+        assert!((perimeter[0].1 - 10.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_boundary_flown_as_given_otherwise() {
+        assert!(generate_pattern(ScanPattern::PatternUnknown, &rectangle(), 40.0, 20.0).is_none());
+        assert!(generate_pattern(ScanPattern::PatternCustom, &rectangle(), 40.0, 20.0).is_none());
+        assert!(generate_pattern(ScanPattern::PatternLawnmower, &rectangle()[..2], 40.0, 20.0).is_none());
+    }
+}
@@ -0,0 +1,152 @@
+//! Mission progress estimate
+//!
+//! Combines the FC's current mission item with the path uploaded for the
+//! mission: the distance left is the straight line to the next waypoint plus
+//! the legs after it. Without the path (or a position) progress falls back
+//! to the item index.
+
+use crate::geo::distance_m;
+use resqterra_shared::{GpsCoordinate, GpsPosition};
+
+/// Slowest ground speed an ETA is estimated at (m/s); below it we are hovering
+const MIN_ETA_SPEED_MPS: f32 = 1.0;
+
+/// How far a mission has come, as reported to the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProgressEstimate {
+    /// 0-100
+    pub percent_complete: f32,
+    /// Along the remaining legs (m); `None` without the path or a position
+    pub distance_remaining_m: Option<f64>,
+    /// At the current ground speed; `None` while hovering
+    pub eta_seconds: Option<u32>,
+}
+
+/// Estimate progress while the FC flies item `current` of `total`
+///
+/// `path` holds one entry per FC mission item, the location of each
+/// navigation waypoint and `None` for the rest (takeoff, speed changes). A
+/// path that does not match `total` is ignored.
+pub fn estimate_progress(
+    path: &[Option<GpsCoordinate>],
+    current: usize,
+    total: usize,
+    position: Option<&GpsPosition>,
+) -> ProgressEstimate {
+    let by_index = ProgressEstimate {
+        percent_complete: percent(current as f64, total as f64),
+        distance_remaining_m: None,
+        eta_seconds: None,
+    };
+    let Some(position) = position.filter(|_| path.len() == total) else {
+        return by_index;
+    };
+
+    let waypoints: Vec<(usize, &GpsCoordinate)> = path
+        .iter()
+        .enumerate()
+        .filter_map(|(i, point)| point.as_ref().map(|p| (i, p)))
+        .collect();
+    let legs_after = |start: usize| -> f64 {
+        waypoints[start..]
+            .windows(2)
+            .map(|w| {
+                let (from, to) = (w[0].1, w[1].1);
+                distance_m(from.latitude, from.longitude, to.latitude, to.longitude)
+            })
+            .sum()
+    };
+    let length = legs_after(0);
+    if length <= 0.0 {
+        return by_index;
+    }
+
+    // Past the last waypoint (flying home or landing) nothing is left
+    let remaining = match waypoints.iter().position(|&(i, _)| i >= current) {
+        Some(next) => {
+            let target = waypoints[next].1;
+            distance_m(position.latitude, position.longitude, target.latitude, target.longitude)
+                + legs_after(next)
+        }
+        None => 0.0,
+    };
+    let eta_seconds = (position.ground_speed_mps >= MIN_ETA_SPEED_MPS)
+        .then(|| (remaining / position.ground_speed_mps as f64).round() as u32);
+
+    ProgressEstimate {
+        percent_complete: percent(length - remaining, length),
+        distance_remaining_m: Some(remaining),
+        eta_seconds,
+    }
+}
+
+/// `done` of `total` as a percentage, clamped to 0-100
+fn percent(done: f64, total: f64) -> f32 {
+    if total <= 0.0 {
+        return 0.0;
+    }
+    (done / total * 100.0).clamp(0.0, 100.0) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::LocalFrame;
+
+    fn at(east: f64, north: f64) -> GpsCoordinate {
+        let (latitude, longitude) = LocalFrame::new(47.5, 8.5).to_global(east, north);
+        GpsCoordinate {
+            latitude,
+            longitude,
+            altitude_m: 30.0,
+        }
+    }
+
+    fn position(east: f64, north: f64, ground_speed_mps: f32) -> GpsPosition {
+        let point = at(east, north);
+        GpsPosition {
+            latitude: point.latitude,
+            longitude: point.longitude,
+            altitude_m: 30.0,
+            ground_speed_mps,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_progress_along_path() {
+        // Takeoff, then three waypoints 100 m apart: 200 m of legs
+        let path = [None, Some(at(0.0, 0.0)), Some(at(100.0, 0.0)), Some(at(100.0, 100.0))];
+
+        // Halfway along the first leg, heading for item 2
+        let halfway = estimate_progress(&path, 2, 4, Some(&position(50.0, 0.0, 5.0)));
+        let remaining = halfway.distance_remaining_m.unwrap();
+        assert!((remaining - 150.0).abs() < 1.0, "{remaining}");
+        assert!((halfway.percent_complete - 25.0).abs() < 0.5);
+        assert_eq!(halfway.eta_seconds, Some(30));
+
+        // Still climbing out: the legs are all ahead, and no ETA while hovering
+        let takeoff = estimate_progress(&path, 0, 4, Some(&position(0.0, 0.0, 0.0)));
+        assert_eq!(takeoff.percent_complete, 0.0);
+        assert_eq!(takeoff.eta_seconds, None);
+
+        // Past the last waypoint (RTL)
+        let done = estimate_progress(&path, 4, 4, Some(&position(0.0, 0.0, 8.0)));
+        assert_eq!(done.percent_complete, 100.0);
+        assert_eq!(done.distance_remaining_m, Some(0.0));
+    }
+
+    #[test]
+    fn test_progress_by_index_without_path() {
+        let estimate = estimate_progress(&[], 3, 12, Some(&position(0.0, 0.0, 5.0)));
+        assert_eq!(estimate.percent_complete, 25.0);
+        assert_eq!(estimate.distance_remaining_m, None);
+        assert_eq!(estimate.eta_seconds, None);
+
+        // A path for a different mission length is not trusted
+        let path = [Some(at(0.0, 0.0)), Some(at(100.0, 0.0))];
+        let estimate = estimate_progress(&path, 1, 4, None);
+        assert_eq!(estimate.percent_complete, 25.0);
+        assert_eq!(estimate.distance_remaining_m, None);
+    }
+}
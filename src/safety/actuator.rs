@@ -0,0 +1,250 @@
+//! Safety Actuator
+//!
+//! Carries out the safety monitor's actions: flies RTL, lands or
+//! force-disarms via MAVLink, and reports each autonomous action to the
+//! server in a `StatusUpdate`.
+
+use super::monitor::{SafetyAction, SafetyMonitor};
+use crate::command::CommandExecutor;
+use crate::connection::OutboundSender;
+use crate::mavlink::{FlightController, MavCommandSender, TelemetryReader};
+use resqterra_shared::{
+    envelope, priority, AutonomousAction, DroneState, Envelope, Header, MessageType,
+    ReturnToHome, StatusUpdate,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Drives the flight controller from safety actions
+pub struct SafetyActuator {
+    monitor: Arc<SafetyMonitor>,
+    device_id: String,
+    sequence_id: Arc<AtomicU64>,
+    uplink: OutboundSender,
+    fc: Option<(Arc<FlightController>, Arc<MavCommandSender>)>,
+    telemetry: Option<Arc<TelemetryReader>>,
+    /// Executor whose pending commands an emergency cancels, and the
+    /// connection's heartbeat state kept in step with the state machine
+    executor: Option<(Arc<CommandExecutor>, Arc<RwLock<DroneState>>)>,
+}
+
+impl SafetyActuator {
+    /// Create an actuator reporting to the server over `uplink`
+    ///
+    /// `sequence_id` is the connection's counter, so status updates number
+    /// in line with everything else the drone sends.
+    pub fn new(
+        monitor: Arc<SafetyMonitor>,
+        device_id: String,
+        sequence_id: Arc<AtomicU64>,
+        uplink: OutboundSender,
+    ) -> Self {
+        Self {
+            monitor,
+            device_id,
+            sequence_id,
+            uplink,
+            fc: None,
+            telemetry: None,
+            executor: None,
+        }
+    }
+
+    /// Command this flight controller (without it actions are only reported)
+    pub fn with_flight_controller(
+        mut self,
+        controller: Arc<FlightController>,
+        commands: Arc<MavCommandSender>,
+    ) -> Self {
+        self.fc = Some((controller, commands));
+        self
+    }
+
+    /// Include the drone's position in status updates
+    pub fn with_telemetry(mut self, telemetry: Arc<TelemetryReader>) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// NAK the executor's pending commands on emergency, and keep the
+    /// connection's heartbeat state in step
+    pub fn with_executor(
+        mut self,
+        executor: Arc<CommandExecutor>,
+        drone_state: Arc<RwLock<DroneState>>,
+    ) -> Self {
+        self.executor = Some((executor, drone_state));
+        self
+    }
+
+    /// Consume safety actions until the monitor's channel closes
+    pub async fn run(self) {
+        while let Some(action) = self.monitor.recv_action().await {
+            if let SafetyAction::StateChanged { from, to } = action {
+                println!("[SAFETY] State changed: {:?} -> {:?}", from, to);
+                self.follow_state(to).await;
+                continue;
+            }
+            if let Some(update) = self.execute(&action).await {
+                if let Err(e) = self.uplink.send(update).await {
+                    eprintln!("[SAFETY] Failed to send status update: {}", e);
+                }
+            }
+            if matches!(action, SafetyAction::EmergencyStop { .. }) {
+                self.cancel_pending().await;
+            }
+        }
+        eprintln!("[SAFETY] Safety monitor channel closed");
+    }
+
+    /// Carry out an autonomous action on the FC
+    ///
+    /// Returns the `StatusUpdate` envelope reporting it; `None` for actions
+    /// that are not autonomous (state changes).
+    pub async fn execute(&self, action: &SafetyAction) -> Option<Envelope> {
+        let (kind, reason) = match action {
+            SafetyAction::Warning { reason } => (AutonomousAction::AutonomousWarning, reason),
+            SafetyAction::ReturnToHome { reason } => (AutonomousAction::AutonomousRth, reason),
+            SafetyAction::Land { reason } => (AutonomousAction::AutonomousLand, reason),
+            SafetyAction::EmergencyStop { reason } => (AutonomousAction::AutonomousDisarm, reason),
+            SafetyAction::StateChanged { .. } | SafetyAction::None => return None,
+        };
+        println!("[SAFETY] Autonomous {:?}: {}", kind, reason);
+
+        let result = match (&self.fc, kind) {
+            (_, AutonomousAction::AutonomousWarning) => Ok(()),
+            (None, _) => Err("no flight controller".to_string()),
+            (Some((fc, commands)), AutonomousAction::AutonomousRth) => commands
+                .return_to_home(fc, &ReturnToHome::default())
+                .await
+                .map_err(|e| e.to_string()),
+            (Some((fc, commands)), AutonomousAction::AutonomousLand) => {
+                commands.land(fc).await.map_err(|e| e.to_string())
+            }
+            (Some((fc, commands)), _) => {
+                commands.emergency_stop(fc).await.map_err(|e| e.to_string())
+            }
+        };
+        let error = result.err().unwrap_or_default();
+        if !error.is_empty() {
+            eprintln!("[SAFETY] ALERT: {:?} not carried out: {}", kind, error);
+        }
+
+        let position = match &self.telemetry {
+            Some(telemetry) => telemetry.get_position().await,
+            None => None,
+        };
+        let update = StatusUpdate {
+            action: kind.into(),
+            reason: reason.clone(),
+            state: self.monitor.state().await.into(),
+            position,
+            error,
+        };
+        let seq = self.sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
+        Some(Envelope {
+            header: Some(
+                Header::new(&self.device_id, MessageType::MsgStatusUpdate, seq)
+                    .with_priority(priority::HIGH),
+            ),
+            payload: Some(envelope::Payload::StatusUpdate(update)),
+            signature: Vec::new(),
+        })
+    }
+
+    async fn follow_state(&self, to: DroneState) {
+        if let Some((_, drone_state)) = &self.executor {
+            *drone_state.write().await = to;
+        }
+        if to == DroneState::DroneEmergency {
+            self.cancel_pending().await;
+        }
+    }
+
+    async fn cancel_pending(&self) {
+        let Some((executor, _)) = &self.executor else {
+            return;
+        };
+        for nak in executor.cancel_pending_for_emergency().await {
+            if let Err(e) = self.uplink.send(nak).await {
+                eprintln!("[SAFETY] Failed to send emergency NAK: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::outbound_channel;
+    use crate::mavlink::FcConfig;
+    use mavlink::ardupilotmega::{MavCmd, MavMessage, MavResult, COMMAND_ACK_DATA};
+    use resqterra_shared::state_machine::SafetyEvent;
+
+    fn status_update(envelope: Envelope) -> StatusUpdate {
+        assert_eq!(envelope.priority(), priority::HIGH);
+        match envelope.payload {
+            Some(envelope::Payload::StatusUpdate(update)) => update,
+            other => panic!("expected status update, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forced_landing_commanded_and_reported() {
+        let monitor = Arc::new(SafetyMonitor::new());
+        monitor.process_event(SafetyEvent::PreflightComplete).await;
+        monitor.process_event(SafetyEvent::Armed).await;
+        let (uplink, _rx) = outbound_channel(8);
+        let (controller, mut outbound, inject) = FlightController::test_link(FcConfig::default());
+        // The FC accepts every command
+        let (commands_tx, mut commands) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(MavMessage::COMMAND_LONG(cmd)) = outbound.recv().await {
+                let _ = commands_tx.send(cmd.command);
+                let _ = inject.send(MavMessage::COMMAND_ACK(COMMAND_ACK_DATA {
+                    command: cmd.command,
+                    result: MavResult::MAV_RESULT_ACCEPTED,
+                    ..Default::default()
+                }));
+            }
+        });
+        let actuator = SafetyActuator::new(
+            monitor.clone(),
+            "edge-001".into(),
+            Arc::new(AtomicU64::new(0)),
+            uplink,
+        )
+        .with_flight_controller(Arc::new(controller), Arc::new(MavCommandSender::new(1, 1)));
+
+        let action = monitor.process_event(SafetyEvent::BatteryDepleted).await;
+        let update = status_update(actuator.execute(&action).await.unwrap());
+        assert_eq!(commands.try_recv(), Ok(MavCmd::MAV_CMD_NAV_LAND));
+        assert_eq!(update.action(), AutonomousAction::AutonomousLand);
+        assert_eq!(update.reason, "Battery depleted");
+        assert_eq!(update.state(), DroneState::DroneLanding);
+        assert!(update.error.is_empty());
+
+        // State changes are not autonomous actions
+        let changed = SafetyAction::StateChanged {
+            from: DroneState::DroneIdle,
+            to: DroneState::DronePreflight,
+        };
+        assert!(actuator.execute(&changed).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_action_without_fc_reported_as_failed() {
+        let monitor = Arc::new(SafetyMonitor::new());
+        let (uplink, _rx) = outbound_channel(8);
+        let actuator =
+            SafetyActuator::new(monitor, "edge-001".into(), Arc::new(AtomicU64::new(0)), uplink);
+
+        let action = SafetyAction::ReturnToHome {
+            reason: "Geofence breach".into(),
+        };
+        let update = status_update(actuator.execute(&action).await.unwrap());
+        assert_eq!(update.action(), AutonomousAction::AutonomousRth);
+        assert_eq!(update.error, "no flight controller");
+    }
+}
@@ -0,0 +1,356 @@
+//! Geofence
+//!
+//! Limits on where the drone may fly: an allowed polygon, a ceiling above
+//! home and a radius around home. Set from `ConfigUpdate` keys or by a
+//! mission start that carries one, and checked against each GPS position
+//! from the FC; a breach triggers RTH.
+
+use crate::geo;
+use resqterra_shared::{GpsCoordinate, GpsPosition, MissionGeofence};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Config key for the allowed polygon: `lat,lon;lat,lon;...` (empty clears it)
+pub const POLYGON_KEY: &str = "geofence.polygon";
+
+/// Config key for the ceiling above home in meters (empty clears it)
+pub const MAX_ALTITUDE_KEY: &str = "geofence.max_altitude_m";
+
+/// Config key for the radius around home in meters (empty clears it)
+pub const MAX_DISTANCE_KEY: &str = "geofence.max_distance_m";
+
+/// Prefix shared by all geofence config keys
+const KEY_PREFIX: &str = "geofence.";
+
+/// Where the drone may fly; an unset limit is not enforced
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Geofence {
+    /// Allowed area (at least three vertices)
+    polygon: Option<Vec<GpsCoordinate>>,
+    /// Ceiling above home (m)
+    max_altitude_m: Option<f32>,
+    /// Radius around home (m)
+    max_distance_m: Option<f64>,
+}
+
+/// A limit the drone is outside of
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breach {
+    /// Outside the allowed polygon
+    OutsidePolygon,
+    /// Above the ceiling
+    TooHigh { altitude_m: f32, limit_m: f32 },
+    /// Farther from home than allowed
+    TooFar { distance_m: f64, limit_m: f64 },
+}
+
+impl fmt::Display for Breach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breach::OutsidePolygon => write!(f, "outside the geofence polygon"),
+            Breach::TooHigh { altitude_m, limit_m } => {
+                write!(f, "{:.0}m above home (limit {:.0}m)", altitude_m, limit_m)
+            }
+            Breach::TooFar { distance_m, limit_m } => {
+                write!(f, "{:.0}m from home (limit {:.0}m)", distance_m, limit_m)
+            }
+        }
+    }
+}
+
+/// A geofence config entry that could not be applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeofenceError {
+    pub key: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for GeofenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {}: {}", self.key, self.reason)
+    }
+}
+
+impl std::error::Error for GeofenceError {}
+
+impl Geofence {
+    /// Whether `key` is a geofence config key
+    pub fn is_config_key(key: &str) -> bool {
+        key.starts_with(KEY_PREFIX)
+    }
+
+    /// Whether any limit is set
+    pub fn is_active(&self) -> bool {
+        self.polygon.is_some() || self.max_altitude_m.is_some() || self.max_distance_m.is_some()
+    }
+
+    /// Apply the geofence entries of a `ConfigUpdate`, returning how many
+    ///
+    /// All entries are validated first; on an error nothing is changed.
+    pub fn apply_config(&mut self, config: &BTreeMap<String, String>) -> Result<usize, GeofenceError> {
+        let mut updated = self.clone();
+        let mut applied = 0;
+
+        for (key, value) in config.iter().filter(|(key, _)| Self::is_config_key(key)) {
+            let value = value.trim();
+            let error = |reason| GeofenceError {
+                key: key.clone(),
+                reason,
+            };
+            match key.as_str() {
+                POLYGON_KEY => {
+                    updated.polygon = if value.is_empty() {
+                        None
+                    } else {
+                        Some(parse_polygon(value).map_err(error)?)
+                    };
+                }
+                MAX_ALTITUDE_KEY => {
+                    updated.max_altitude_m = parse_limit(value).map_err(error)?;
+                }
+                MAX_DISTANCE_KEY => {
+                    updated.max_distance_m = parse_limit(value).map_err(error)?;
+                }
+                _ => return Err(error("unknown geofence key")),
+            }
+            applied += 1;
+        }
+
+        *self = updated;
+        Ok(applied)
+    }
+
+    /// Replace all limits with a mission's geofence
+    ///
+    /// An empty polygon and zero limits are not enforced. On an error nothing
+    /// is changed.
+    pub fn apply_mission(&mut self, fence: &MissionGeofence) -> Result<(), GeofenceError> {
+        let error = |key: &str, reason| GeofenceError {
+            key: key.to_string(),
+            reason,
+        };
+        let polygon = match fence.polygon.len() {
+            0 => None,
+            1 | 2 => return Err(error(POLYGON_KEY, "polygon needs at least 3 vertices")),
+            _ => Some(fence.polygon.clone()),
+        };
+        let in_range = |v: &GpsCoordinate| {
+            (-90.0..=90.0).contains(&v.latitude) && (-180.0..=180.0).contains(&v.longitude)
+        };
+        if !fence.polygon.iter().all(in_range) {
+            return Err(error(POLYGON_KEY, "coordinate out of range"));
+        }
+        if !(0.0..).contains(&fence.max_altitude_m) {
+            return Err(error(MAX_ALTITUDE_KEY, "expected a positive number"));
+        }
+        if !(0.0..).contains(&fence.max_distance_m) {
+            return Err(error(MAX_DISTANCE_KEY, "expected a positive number"));
+        }
+
+        *self = Self {
+            polygon,
+            max_altitude_m: Some(fence.max_altitude_m).filter(|limit| *limit > 0.0),
+            max_distance_m: Some(fence.max_distance_m).filter(|limit| *limit > 0.0),
+        };
+        Ok(())
+    }
+
+    /// Check a position against the limits
+    ///
+    /// The ceiling and radius are relative to `home`, so they are only
+    /// enforced once the FC has reported it.
+    pub fn check(&self, position: &GpsPosition, home: Option<&GpsCoordinate>) -> Option<Breach> {
+        if let Some(ref polygon) = self.polygon {
+            if !contains(polygon, position.latitude, position.longitude) {
+                return Some(Breach::OutsidePolygon);
+            }
+        }
+
+        let home = home?;
+        if let Some(limit_m) = self.max_altitude_m {
+            let altitude_m = position.altitude_m - home.altitude_m;
+            if altitude_m > limit_m {
+                return Some(Breach::TooHigh { altitude_m, limit_m });
+            }
+        }
+        if let Some(limit_m) = self.max_distance_m {
+            let distance_m = geo::distance_m(
+                position.latitude,
+                position.longitude,
+                home.latitude,
+                home.longitude,
+            );
+            if distance_m > limit_m {
+                return Some(Breach::TooFar { distance_m, limit_m });
+            }
+        }
+
+        None
+    }
+}
+
+fn parse_polygon(value: &str) -> Result<Vec<GpsCoordinate>, &'static str> {
+    let polygon = value
+        .split(';')
+        .map(|vertex| {
+            let (lat, lon) = vertex.split_once(',').ok_or("expected lat,lon pairs")?;
+            let latitude: f64 = lat.trim().parse().map_err(|_| "bad latitude")?;
+            let longitude: f64 = lon.trim().parse().map_err(|_| "bad longitude")?;
+            if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                return Err("coordinate out of range");
+            }
+            Ok(GpsCoordinate {
+                latitude,
+                longitude,
+                altitude_m: 0.0,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if polygon.len() < 3 {
+        return Err("polygon needs at least 3 vertices");
+    }
+    Ok(polygon)
+}
+
+fn parse_limit<T: std::str::FromStr + PartialOrd + Default>(value: &str) -> Result<Option<T>, &'static str> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match value.parse::<T>() {
+        Ok(limit) if limit > T::default() => Ok(Some(limit)),
+        _ => Err("expected a positive number"),
+    }
+}
+
+/// Point-in-polygon by ray casting, treating degrees as planar
+fn contains(polygon: &[GpsCoordinate], lat: f64, lon: f64) -> bool {
+    let mut inside = false;
+    let next = polygon.iter().cycle().skip(1);
+    for (a, b) in polygon.iter().zip(next) {
+        if (a.latitude > lat) != (b.latitude > lat) {
+            let crossing = a.longitude
+                + (lat - a.latitude) * (b.longitude - a.longitude) / (b.latitude - a.latitude);
+            if lon < crossing {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(latitude: f64, longitude: f64, altitude_m: f32) -> GpsPosition {
+        GpsPosition {
+            latitude,
+            longitude,
+            altitude_m,
+            ..Default::default()
+        }
+    }
+
+    fn config(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_limits_checked_against_home() {
+        let mut fence = Geofence::default();
+        let applied = fence
+            .apply_config(&config(&[
+                (POLYGON_KEY, "47.50,8.50; 47.50,8.52; 47.52,8.52; 47.52,8.50"),
+                (MAX_ALTITUDE_KEY, "120"),
+                (MAX_DISTANCE_KEY, "1000"),
+                ("telemetry.rate_hz", "2"),
+            ]))
+            .unwrap();
+        assert_eq!(applied, 3);
+        assert!(fence.is_active());
+
+        let home = GpsCoordinate {
+            latitude: 47.505,
+            longitude: 8.505,
+            altitude_m: 400.0,
+        };
+        assert_eq!(fence.check(&position(47.506, 8.506, 450.0), Some(&home)), None);
+        assert_eq!(
+            fence.check(&position(47.49, 8.51, 450.0), Some(&home)),
+            Some(Breach::OutsidePolygon)
+        );
+        assert!(matches!(
+            fence.check(&position(47.506, 8.506, 530.0), Some(&home)),
+            Some(Breach::TooHigh { .. })
+        ));
+        assert!(matches!(
+            fence.check(&position(47.519, 8.519, 450.0), Some(&home)),
+            Some(Breach::TooFar { .. })
+        ));
+
+        // Without home only the polygon applies
+        assert_eq!(fence.check(&position(47.519, 8.519, 900.0), None), None);
+
+        // Empty values clear a limit
+        fence
+            .apply_config(&config(&[(POLYGON_KEY, ""), (MAX_DISTANCE_KEY, "")]))
+            .unwrap();
+        assert_eq!(fence.check(&position(47.49, 8.51, 450.0), Some(&home)), None);
+    }
+
+    #[test]
+    fn test_invalid_config_changes_nothing() {
+        let mut fence = Geofence::default();
+        let err = fence
+            .apply_config(&config(&[
+                (MAX_ALTITUDE_KEY, "120"),
+                (POLYGON_KEY, "47.50,8.50; 47.50,8.52"),
+            ]))
+            .unwrap_err();
+        assert_eq!(err.key, POLYGON_KEY);
+        assert!(!fence.is_active());
+
+        assert!(fence.apply_config(&config(&[(MAX_DISTANCE_KEY, "-5")])).is_err());
+        assert!(fence.apply_config(&config(&[("geofence.floor_m", "5")])).is_err());
+        assert_eq!(fence, Geofence::default());
+    }
+
+    #[test]
+    fn test_mission_geofence_replaces_limits() {
+        let mut fence = Geofence::default();
+        fence.apply_config(&config(&[(MAX_DISTANCE_KEY, "1000")])).unwrap();
+        let vertex = |latitude, longitude| GpsCoordinate {
+            latitude,
+            longitude,
+            altitude_m: 0.0,
+        };
+        let mission = MissionGeofence {
+            polygon: vec![vertex(47.50, 8.50), vertex(47.50, 8.52), vertex(47.52, 8.51)],
+            max_altitude_m: 100.0,
+            max_distance_m: 0.0,
+        };
+        fence.apply_mission(&mission).unwrap();
+        let home = GpsCoordinate {
+            altitude_m: 400.0,
+            ..vertex(47.505, 8.51)
+        };
+        // The radius from the config update is gone with the mission's fence
+        assert_eq!(fence.check(&position(47.519, 8.51, 450.0), Some(&home)), None);
+        assert!(matches!(
+            fence.check(&position(47.51, 8.51, 520.0), Some(&home)),
+            Some(Breach::TooHigh { .. })
+        ));
+
+        let before = fence.clone();
+        let invalid = MissionGeofence {
+            polygon: vec![vertex(47.50, 8.50), vertex(47.50, 8.52)],
+            ..mission
+        };
+        assert_eq!(fence.apply_mission(&invalid).unwrap_err().key, POLYGON_KEY);
+        assert_eq!(fence, before);
+    }
+}
@@ -1,8 +1,13 @@
 //! Safety Module
 //!
 //! Monitors safety conditions and triggers automatic responses
-//! such as Return-to-Home on connection loss.
+//! such as Return-to-Home on connection loss or a geofence breach, and
+//! carries them out on the flight controller.
 
+mod actuator;
+mod geofence;
 mod monitor;
 
-pub use monitor::{SafetyMonitor, SafetyAction};
+pub use actuator::SafetyActuator;
+pub use geofence::Geofence;
+pub use monitor::SafetyMonitor;
@@ -3,11 +3,14 @@
 //! Runs a background task that monitors safety conditions and triggers
 //! appropriate responses when thresholds are exceeded.
 
+use super::geofence::Geofence;
+use crate::state::DroneStateStore;
 use resqterra_shared::{
     now_ms, safety,
     state_machine::{SafetyEvent, SafetyStateMachine, TransitionResult},
-    DroneState,
+    DroneState, GpsCoordinate, GpsPosition,
 };
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::sync::{mpsc, RwLock};
 use tokio::time::{interval, Duration};
@@ -15,8 +18,12 @@ use tokio::time::{interval, Duration};
 /// Actions that the safety monitor can trigger
 #[derive(Debug, Clone)]
 pub enum SafetyAction {
+    /// Warn the operator; nothing to do yet
+    Warning { reason: String },
     /// Trigger Return-to-Home
     ReturnToHome { reason: String },
+    /// Land in place (battery too low to make it home)
+    Land { reason: String },
     /// Trigger emergency stop
     EmergencyStop { reason: String },
     /// State changed
@@ -29,12 +36,18 @@ pub enum SafetyAction {
 pub struct SafetyMonitor {
     /// The state machine
     fsm: Arc<RwLock<SafetyStateMachine>>,
+    /// Shared drone state, mirroring the state machine and the FC
+    store: Arc<DroneStateStore>,
     /// Channel to send safety actions
     action_tx: mpsc::UnboundedSender<SafetyAction>,
     /// Channel to receive safety actions
     action_rx: Arc<RwLock<mpsc::UnboundedReceiver<SafetyAction>>>,
     /// Flag to track if monitoring is active
     monitoring_active: Arc<RwLock<bool>>,
+    /// Flight limits, updated from `ConfigUpdate`
+    geofence: Arc<RwLock<Geofence>>,
+    /// Outside the geofence at the last check (a breach is raised once)
+    geofence_breached: AtomicBool,
 }
 
 impl SafetyMonitor {
@@ -44,12 +57,25 @@ impl SafetyMonitor {
 
         Self {
             fsm: Arc::new(RwLock::new(SafetyStateMachine::new())),
+            store: Arc::new(DroneStateStore::new()),
             action_tx,
             action_rx: Arc::new(RwLock::new(action_rx)),
             monitoring_active: Arc::new(RwLock::new(false)),
+            geofence: Arc::new(RwLock::new(Geofence::default())),
+            geofence_breached: AtomicBool::new(false),
         }
     }
 
+    /// The geofence checked by [`Self::check_position`], for config updates
+    pub fn geofence(&self) -> Arc<RwLock<Geofence>> {
+        self.geofence.clone()
+    }
+
+    /// The drone state store, for the executor and telemetry to share
+    pub fn state_store(&self) -> Arc<DroneStateStore> {
+        self.store.clone()
+    }
+
     /// Get the current drone state
     pub async fn state(&self) -> DroneState {
         self.fsm.read().await.state()
@@ -60,23 +86,88 @@ impl SafetyMonitor {
         self.fsm.write().await.update_heartbeat(now_ms());
     }
 
+    /// Update the FC heartbeat timestamp (call on each autopilot heartbeat)
+    ///
+    /// Without one for `FC_HEARTBEAT_TIMEOUT_MS` the monitoring task raises
+    /// `FcLinkLost`, warning once per outage.
+    pub async fn update_fc_heartbeat(&self) {
+        self.fsm.write().await.update_fc_heartbeat(now_ms());
+    }
+
+    /// Mark the server link as down
+    ///
+    /// If it stays down for `HEARTBEAT_TIMEOUT_MS` the monitoring task raises a
+    /// heartbeat timeout, even when no heartbeat was ever received.
+    pub async fn link_lost(&self) {
+        self.fsm.write().await.link_lost(now_ms());
+    }
+
+    /// Mark the server link as up again
+    pub async fn link_restored(&self) {
+        self.fsm.write().await.link_restored();
+    }
+
     /// Update battery level
+    ///
+    /// Below each threshold the drone warns, returns home, then lands in place.
     pub async fn update_battery(&self, percent: u32) {
         let mut fsm = self.fsm.write().await;
         fsm.update_battery(percent);
 
         // Check if this triggers a safety event
-        if fsm.is_battery_critical() {
+        if let Some(event) = fsm.battery_event() {
             drop(fsm); // Release lock before processing
-            let _ = self.process_event(SafetyEvent::BatteryCritical).await;
+            let _ = self.process_event(event).await;
+        }
+    }
+
+    /// Check a GPS position from the FC against the geofence
+    ///
+    /// Raises `GeofenceBreach` when the drone leaves the fence; it is raised
+    /// again only after the drone has been back inside.
+    pub async fn check_position(
+        &self,
+        position: &GpsPosition,
+        home: Option<&GpsCoordinate>,
+    ) -> SafetyAction {
+        let breach = self.geofence.read().await.check(position, home);
+        let Some(breach) = breach else {
+            self.geofence_breached.store(false, Ordering::Relaxed);
+            return SafetyAction::None;
+        };
+        if self.geofence_breached.swap(true, Ordering::Relaxed) {
+            return SafetyAction::None;
+        }
+
+        println!("[SAFETY] Geofence breach: {}", breach);
+        self.process_event(SafetyEvent::GeofenceBreach).await
+    }
+
+    /// Bring the state machine in line with the FC's last heartbeat
+    ///
+    /// Follows what the FC did on its own: arming, flying AUTO, disarming,
+    /// or switching to RTL or LAND. This is what moves the drone out of Idle
+    /// in flight, so the autonomous failsafes have a flight state to act on.
+    pub async fn sync_with_fc(&self) -> SafetyAction {
+        let events = self.store.fc_events().await;
+        if events.is_empty() {
+            return SafetyAction::None;
         }
+        self.process_events(&events).await
     }
 
     /// Process a safety event and return the resulting action
+    ///
+    /// A transition the FC contradicts is refused and leaves the state as is.
     pub async fn process_event(&self, event: SafetyEvent) -> SafetyAction {
-        let mut fsm = self.fsm.write().await;
-        let from_state = fsm.state();
-        let result = fsm.process_event(event);
+        self.process_events(&[event]).await
+    }
+
+    /// Process `events` in order as one transition
+    async fn process_events(&self, events: &[SafetyEvent]) -> SafetyAction {
+        let Some((from_state, result)) = transition(&self.fsm, &self.store, events).await else {
+            return SafetyAction::None;
+        };
 
         let action = match result {
             TransitionResult::Success(to_state) => {
@@ -100,10 +191,18 @@ impl SafetyMonitor {
                 );
                 SafetyAction::None
             }
+            TransitionResult::Warning { reason } => {
+                println!("[SAFETY] WARNING: {}", reason);
+                SafetyAction::Warning { reason }
+            }
             TransitionResult::EmergencyRth { reason } => {
                 println!("[SAFETY] EMERGENCY RTH: {}", reason);
                 SafetyAction::ReturnToHome { reason }
             }
+            TransitionResult::ForcedLand { reason } => {
+                println!("[SAFETY] FORCED LANDING: {}", reason);
+                SafetyAction::Land { reason }
+            }
             TransitionResult::EmergencyStop { reason } => {
                 println!("[SAFETY] EMERGENCY STOP: {}", reason);
                 SafetyAction::EmergencyStop { reason }
@@ -138,6 +237,16 @@ impl SafetyMonitor {
         self.process_event(SafetyEvent::MissionComplete).await
     }
 
+    /// Enter maintenance (onboard update); only valid while idle
+    pub async fn enter_maintenance(&self) -> SafetyAction {
+        self.process_event(SafetyEvent::MaintenanceStarted).await
+    }
+
+    /// Leave maintenance once the update is done
+    pub async fn exit_maintenance(&self) -> SafetyAction {
+        self.process_event(SafetyEvent::MaintenanceComplete).await
+    }
+
     /// Receive the next safety action (blocks until available)
     pub async fn recv_action(&self) -> Option<SafetyAction> {
         self.action_rx.write().await.recv().await
@@ -159,6 +268,7 @@ impl SafetyMonitor {
         drop(active);
 
         let fsm = self.fsm.clone();
+        let store = self.store.clone();
         let action_tx = self.action_tx.clone();
         let monitoring_active = self.monitoring_active.clone();
 
@@ -183,10 +293,10 @@ impl SafetyMonitor {
 
                 // Process any safety events
                 for event in events {
-                    let mut fsm_guard = fsm.write().await;
-                    let from_state = fsm_guard.state();
-                    let result = fsm_guard.process_event(event.clone());
-                    drop(fsm_guard);
+                    let Some((from_state, result)) = transition(&fsm, &store, &[event]).await
+                    else {
+                        continue;
+                    };
 
                     let action = match result {
                         TransitionResult::Success(to_state) if from_state != to_state => {
@@ -199,10 +309,18 @@ impl SafetyMonitor {
                                 to: to_state,
                             }
                         }
+                        TransitionResult::Warning { reason } => {
+                            println!("[SAFETY] AUTO-WARNING: {}", reason);
+                            SafetyAction::Warning { reason }
+                        }
                         TransitionResult::EmergencyRth { reason } => {
                             println!("[SAFETY] AUTO-RTH TRIGGERED: {}", reason);
                             SafetyAction::ReturnToHome { reason }
                         }
+                        TransitionResult::ForcedLand { reason } => {
+                            println!("[SAFETY] AUTO-LAND TRIGGERED: {}", reason);
+                            SafetyAction::Land { reason }
+                        }
                         TransitionResult::EmergencyStop { reason } => {
                             println!("[SAFETY] AUTO-EMERGENCY TRIGGERED: {}", reason);
                             SafetyAction::EmergencyStop { reason }
@@ -224,6 +342,40 @@ impl SafetyMonitor {
     }
 }
 
+/// Run `events` through the state machine and mirror the result in `store`
+///
+/// The transition is worked out on a copy and only committed if the FC agrees
+/// with the state it leads to; states passed on the way aren't checked. An
+/// invalid event stops the run with nothing committed. Returns the state
+/// before and the last result, or `None` if refused.
+async fn transition(
+    fsm: &RwLock<SafetyStateMachine>,
+    store: &DroneStateStore,
+    events: &[SafetyEvent],
+) -> Option<(DroneState, TransitionResult)> {
+    let mut fsm = fsm.write().await;
+    let from_state = fsm.state();
+    let mut next = fsm.clone();
+    let mut result = TransitionResult::Success(from_state);
+    for event in events {
+        result = next.process_event(event.clone());
+        if matches!(result, TransitionResult::Invalid { .. }) {
+            return Some((from_state, result));
+        }
+    }
+
+    if let Err(conflict) = store.check_transition(next.state()).await {
+        eprintln!(
+            "[SAFETY] Refused {:?} from {:?}: {}",
+            events, from_state, conflict
+        );
+        return None;
+    }
+    store.set_state(next.state()).await;
+    *fsm = next;
+    Some((from_state, result))
+}
+
 impl Default for SafetyMonitor {
     fn default() -> Self {
         Self::new()
@@ -246,6 +398,9 @@ impl SafetyMonitorHandle {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mavlink::TelemetryReader;
+    use crate::state::FcState;
+    use mavlink::ardupilotmega::{MavMessage, MavModeFlag, HEARTBEAT_DATA};
 
     #[tokio::test]
     async fn test_safety_monitor_creation() {
@@ -275,6 +430,170 @@ mod tests {
         assert!(matches!(action, SafetyAction::StateChanged { to: DroneState::DroneReturningHome, .. }));
     }
 
+    #[tokio::test]
+    async fn test_disconnected_edge_triggers_rth() {
+        let monitor = SafetyMonitor::new();
+        monitor.process_event(SafetyEvent::PreflightComplete).await;
+        monitor.process_event(SafetyEvent::Armed).await;
+        monitor.process_event(SafetyEvent::TakeoffStarted).await;
+        monitor.process_event(SafetyEvent::MissionStarted).await;
+        while monitor.try_recv_action().await.is_some() {}
+
+        // Never heard from the server, link down for longer than the grace window
+        monitor
+            .fsm
+            .write()
+            .await
+            .link_lost(now_ms() - safety::HEARTBEAT_TIMEOUT_MS - 1);
+        let handle = monitor.start_monitoring().await;
+
+        let action = tokio::time::timeout(Duration::from_secs(2), monitor.recv_action())
+            .await
+            .expect("monitor should act without the server");
+        handle.stop().await;
+        assert!(matches!(action, Some(SafetyAction::ReturnToHome { .. })));
+        assert_eq!(monitor.state().await, DroneState::DroneReturningHome);
+    }
+
+    #[tokio::test]
+    async fn test_silent_fc_warns() {
+        let monitor = SafetyMonitor::new();
+        monitor.process_event(SafetyEvent::PreflightComplete).await;
+        monitor.process_event(SafetyEvent::Armed).await;
+        while monitor.try_recv_action().await.is_some() {}
+
+        monitor.update_server_heartbeat().await;
+        monitor
+            .fsm
+            .write()
+            .await
+            .update_fc_heartbeat(now_ms() - safety::FC_HEARTBEAT_TIMEOUT_MS - 1);
+        let handle = monitor.start_monitoring().await;
+
+        let action = tokio::time::timeout(Duration::from_secs(2), monitor.recv_action())
+            .await
+            .expect("monitor should warn about the silent FC");
+        handle.stop().await;
+        assert!(matches!(action, Some(SafetyAction::Warning { .. })));
+        assert_eq!(monitor.state().await, DroneState::DroneArmed);
+    }
+
+    #[tokio::test]
+    async fn test_geofence_breach_triggers_rth_once() {
+        let monitor = SafetyMonitor::new();
+        monitor.process_event(SafetyEvent::PreflightComplete).await;
+        monitor.process_event(SafetyEvent::Armed).await;
+        monitor.process_event(SafetyEvent::TakeoffStarted).await;
+        monitor.process_event(SafetyEvent::MissionStarted).await;
+
+        let config = [("geofence.max_distance_m".to_string(), "100".to_string())].into();
+        monitor.geofence().write().await.apply_config(&config).unwrap();
+        let home = GpsCoordinate {
+            latitude: 47.5,
+            longitude: 8.5,
+            altitude_m: 0.0,
+        };
+        let at = |latitude| GpsPosition {
+            latitude,
+            longitude: 8.5,
+            ..Default::default()
+        };
+
+        let action = monitor.check_position(&at(47.5005), Some(&home)).await;
+        assert!(matches!(action, SafetyAction::None));
+
+        let action = monitor.check_position(&at(47.502), Some(&home)).await;
+        assert!(matches!(action, SafetyAction::ReturnToHome { .. }));
+        assert_eq!(monitor.state().await, DroneState::DroneReturningHome);
+
+        // Still outside: not raised again
+        let action = monitor.check_position(&at(47.503), Some(&home)).await;
+        assert!(matches!(action, SafetyAction::None));
+    }
+
+    #[tokio::test]
+    async fn test_battery_stages_warn_rth_then_land() {
+        let monitor = SafetyMonitor::new();
+        monitor.process_event(SafetyEvent::PreflightComplete).await;
+        monitor.process_event(SafetyEvent::Armed).await;
+        monitor.process_event(SafetyEvent::TakeoffStarted).await;
+        monitor.process_event(SafetyEvent::MissionStarted).await;
+        while monitor.try_recv_action().await.is_some() {}
+
+        monitor.update_battery(28).await;
+        assert!(matches!(monitor.try_recv_action().await, Some(SafetyAction::Warning { .. })));
+        monitor.update_battery(27).await;
+        assert!(monitor.try_recv_action().await.is_none());
+
+        monitor.update_battery(19).await;
+        assert!(matches!(monitor.try_recv_action().await, Some(SafetyAction::ReturnToHome { .. })));
+
+        monitor.update_battery(9).await;
+        assert!(matches!(monitor.try_recv_action().await, Some(SafetyAction::Land { .. })));
+        assert_eq!(monitor.state().await, DroneState::DroneLanding);
+    }
+
+    #[tokio::test]
+    async fn test_state_reconciled_with_fc() {
+        let monitor = SafetyMonitor::new();
+        let store = monitor.state_store();
+        monitor.process_event(SafetyEvent::PreflightComplete).await;
+        store
+            .update_fc(FcState {
+                armed: false,
+                mode: DroneState::DroneIdle,
+            })
+            .await;
+
+        // Battery RTH on the ground with the FC disarmed is refused
+        let action = monitor.process_event(SafetyEvent::BatteryCritical).await;
+        assert!(matches!(action, SafetyAction::None));
+        assert_eq!(monitor.state().await, DroneState::DronePreflight);
+
+        // The FC arms, then the pilot switches it to RTL
+        let fc = |mode| FcState { armed: true, mode };
+        store.update_fc(fc(DroneState::DroneArmed)).await;
+        monitor.sync_with_fc().await;
+        assert_eq!(store.state().await, DroneState::DroneArmed);
+        store.update_fc(fc(DroneState::DroneReturningHome)).await;
+        let action = monitor.sync_with_fc().await;
+        assert!(matches!(action, SafetyAction::StateChanged { to: DroneState::DroneReturningHome, .. }));
+        assert_eq!(store.state().await, DroneState::DroneReturningHome);
+    }
+
+    #[tokio::test]
+    async fn test_fc_heartbeats_alone_reach_flight_states() {
+        let monitor = SafetyMonitor::new();
+        let reader = TelemetryReader::new().with_state_store(monitor.state_store());
+        let heartbeat = |custom_mode, armed: bool| {
+            MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+                custom_mode,
+                base_mode: if armed {
+                    MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED
+                } else {
+                    MavModeFlag::empty()
+                },
+                ..Default::default()
+            })
+        };
+
+        // As main does for each heartbeat: disarmed, armed, then AUTO
+        for (mode, armed, state) in [
+            (0, false, DroneState::DroneIdle),
+            (0, true, DroneState::DroneArmed),
+            (3, true, DroneState::DroneInMission),
+        ] {
+            reader.process_message(&heartbeat(mode, armed)).await;
+            monitor.sync_with_fc().await;
+            assert_eq!(monitor.state().await, state);
+        }
+
+        // In flight, a failsafe now has something to act on
+        let action = monitor.process_event(SafetyEvent::BatteryCritical).await;
+        assert!(matches!(action, SafetyAction::ReturnToHome { .. }));
+        assert_eq!(monitor.state().await, DroneState::DroneReturningHome);
+    }
+
     #[tokio::test]
     async fn test_emergency_stop() {
         let monitor = SafetyMonitor::new();
@@ -0,0 +1,209 @@
+//! Drone state shared across the edge
+//!
+//! The safety state machine says what the drone should be doing; the FC
+//! heartbeat says what it is actually doing. The store keeps both, so the
+//! executor, telemetry and safety monitor agree on one state, and the safety
+//! monitor can refuse transitions the FC contradicts.
+
+use resqterra_shared::{state_machine::SafetyEvent, DroneState};
+use std::fmt;
+use tokio::sync::RwLock;
+
+/// What the FC last reported in its heartbeat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FcState {
+    pub armed: bool,
+    /// State implied by the flight mode (RTL, LAND, AUTO, ...)
+    pub mode: DroneState,
+}
+
+/// A transition the FC's reported state rules out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateConflict {
+    pub to: DroneState,
+    pub fc: FcState,
+}
+
+impl fmt::Display for StateConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arming = if self.fc.armed { "armed" } else { "disarmed" };
+        write!(f, "{:?} contradicts the FC ({}, {:?})", self.to, arming, self.fc.mode)
+    }
+}
+
+impl std::error::Error for StateConflict {}
+
+/// The drone state, reconciled with the flight controller
+#[derive(Debug)]
+pub struct DroneStateStore {
+    state: RwLock<DroneState>,
+    fc: RwLock<Option<FcState>>,
+}
+
+impl Default for DroneStateStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DroneStateStore {
+    /// Idle, with nothing heard from the FC yet
+    pub fn new() -> Self {
+        Self {
+            state: RwLock::new(DroneState::DroneIdle),
+            fc: RwLock::new(None),
+        }
+    }
+
+    /// The current drone state
+    pub async fn state(&self) -> DroneState {
+        *self.state.read().await
+    }
+
+    /// Record the state the safety state machine moved to
+    pub async fn set_state(&self, state: DroneState) {
+        *self.state.write().await = state;
+    }
+
+    /// What the FC last reported (`None` before its first heartbeat)
+    pub async fn fc(&self) -> Option<FcState> {
+        *self.fc.read().await
+    }
+
+    /// Record the FC's arm state and flight mode from a heartbeat
+    pub async fn update_fc(&self, fc: FcState) {
+        *self.fc.write().await = Some(fc);
+    }
+
+    /// Whether moving to `to` is consistent with the FC
+    ///
+    /// Flight states need the FC armed and ground-only states need it
+    /// disarmed. Anything goes until the FC has reported, and an emergency
+    /// is never refused.
+    pub async fn check_transition(&self, to: DroneState) -> Result<(), StateConflict> {
+        let Some(fc) = self.fc().await else {
+            return Ok(());
+        };
+        let contradicts = match to {
+            DroneState::DroneTakingOff
+            | DroneState::DroneInMission
+            | DroneState::DroneReturningHome => !fc.armed,
+            DroneState::DronePreflight | DroneState::DroneMaintenance => fc.armed,
+            _ => false,
+        };
+        if contradicts && to != self.state().await {
+            return Err(StateConflict { to, fc });
+        }
+        Ok(())
+    }
+
+    /// The events that bring the safety state machine in line with the FC
+    ///
+    /// Covers what happens outside our control: the FC arming (RC or GCS),
+    /// flying its mission in AUTO, disarming (landed or crashed), or
+    /// switching to RTL or LAND itself (RC override, its own failsafe).
+    /// Several events may be needed to catch up, e.g. Idle to InMission when
+    /// the first heartbeat we see is already armed in AUTO.
+    pub async fn fc_events(&self) -> Vec<SafetyEvent> {
+        let Some(fc) = self.fc().await else {
+            return Vec::new();
+        };
+        let mut state = self.state().await;
+        let mut events = Vec::new();
+        use DroneState::*;
+
+        loop {
+            let (event, next) = match (state, fc.armed, fc.mode) {
+                (DroneIdle, true, _) => (SafetyEvent::PreflightComplete, DronePreflight),
+                (DronePreflight, true, _) => (SafetyEvent::Armed, DroneArmed),
+                (DroneArmed, true, DroneInMission) => (SafetyEvent::TakeoffStarted, DroneTakingOff),
+                (DroneTakingOff, true, DroneInMission) => {
+                    (SafetyEvent::MissionStarted, DroneInMission)
+                }
+                (
+                    DroneArmed | DroneTakingOff | DroneInMission | DroneReturningHome
+                    | DroneLanding,
+                    false,
+                    _,
+                ) => (SafetyEvent::Landed, DroneIdle),
+                (DroneArmed | DroneTakingOff | DroneInMission, true, DroneReturningHome) => {
+                    (SafetyEvent::RthTriggered, DroneReturningHome)
+                }
+                (DroneInMission | DroneReturningHome, true, DroneLanding) => {
+                    (SafetyEvent::LandingStarted, DroneLanding)
+                }
+                _ => return events,
+            };
+            events.push(event);
+            state = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_transitions_checked_against_fc() {
+        let store = DroneStateStore::new();
+        // Nothing from the FC yet
+        assert!(store.check_transition(DroneState::DroneInMission).await.is_ok());
+
+        store
+            .update_fc(FcState {
+                armed: false,
+                mode: DroneState::DroneIdle,
+            })
+            .await;
+        let err = store
+            .check_transition(DroneState::DroneInMission)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to, DroneState::DroneInMission);
+        assert!(store.check_transition(DroneState::DroneMaintenance).await.is_ok());
+        assert!(store.check_transition(DroneState::DroneEmergency).await.is_ok());
+
+        store
+            .update_fc(FcState {
+                armed: true,
+                mode: DroneState::DroneInMission,
+            })
+            .await;
+        assert!(store.check_transition(DroneState::DroneInMission).await.is_ok());
+        assert!(store.check_transition(DroneState::DroneMaintenance).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fc_event_follows_fc() {
+        let store = DroneStateStore::new();
+        store.set_state(DroneState::DroneInMission).await;
+        assert!(store.fc_events().await.is_empty());
+
+        let fc = |armed, mode| FcState { armed, mode };
+        store.update_fc(fc(true, DroneState::DroneInMission)).await;
+        assert!(store.fc_events().await.is_empty());
+
+        // Pilot flipped to RTL, then LAND, then the drone disarmed
+        store.update_fc(fc(true, DroneState::DroneReturningHome)).await;
+        assert_eq!(store.fc_events().await, [SafetyEvent::RthTriggered]);
+        store.set_state(DroneState::DroneReturningHome).await;
+        store.update_fc(fc(true, DroneState::DroneLanding)).await;
+        assert_eq!(store.fc_events().await, [SafetyEvent::LandingStarted]);
+        store.update_fc(fc(false, DroneState::DroneIdle)).await;
+        assert_eq!(store.fc_events().await, [SafetyEvent::Landed]);
+
+        // Idle, then the first heartbeat is already armed and flying AUTO
+        store.set_state(DroneState::DroneIdle).await;
+        store.update_fc(fc(true, DroneState::DroneInMission)).await;
+        assert_eq!(
+            store.fc_events().await,
+            [
+                SafetyEvent::PreflightComplete,
+                SafetyEvent::Armed,
+                SafetyEvent::TakeoffStarted,
+                SafetyEvent::MissionStarted,
+            ]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Telemetry pump
+//!
+//! Publishes what the `TelemetryReader` gathered from the FC to the server
+//! through the `ConnectionManager`, following the link's `TelemetryPolicy`:
+//! on 5G each sample goes out on its own as a keyframe or delta, while on
+//! Bluetooth and other slow links fewer, smaller samples are batched and the
+//! batch goes out at the policy's rate (or sooner once full). Mission
+//! progress is reported alongside while a mission flies.
+
+use crate::connection::{ConnectionManager, TelemetryPolicies, TelemetryPolicy, Transport};
+use crate::mavlink::TelemetryReader;
+use crate::protocol::*;
+use crate::video::VideoStreamer;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{interval, Interval};
+
+/// How often mission progress is reported while a mission flies
+const MISSION_PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What is due when the pump wakes up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PumpTick {
+    Sample,
+    FlushBatch,
+    MissionProgress,
+}
+
+/// Samples telemetry and pushes it to the server as the current link's
+/// policy says
+pub struct TelemetryPump {
+    reader: Arc<TelemetryReader>,
+    video: Option<Arc<VideoStreamer>>,
+    policies: TelemetryPolicies,
+    /// Policy in force, 5G's until a link says otherwise
+    policy: TelemetryPolicy,
+    encoder: DeltaEncoder,
+    batcher: TelemetryBatcher,
+    sample_interval: Interval,
+    flush_interval: Interval,
+    progress_interval: Interval,
+}
+
+impl TelemetryPump {
+    pub fn new(reader: Arc<TelemetryReader>, policies: TelemetryPolicies) -> Self {
+        let policy = *policies.get(Transport::FiveG);
+        Self {
+            reader,
+            video: None,
+            policies,
+            policy,
+            encoder: DeltaEncoder::new(TELEMETRY_KEYFRAME_INTERVAL),
+            batcher: TelemetryBatcher::new(TELEMETRY_BATCH_LEN),
+            sample_interval: interval(policy.interval),
+            flush_interval: interval(policy.batch_flush.unwrap_or(policy.interval)),
+            progress_interval: interval(MISSION_PROGRESS_INTERVAL),
+        }
+    }
+
+    /// Report the health of the video stream in telemetry
+    pub fn with_video(mut self, video: Arc<VideoStreamer>) -> Self {
+        self.video = Some(video);
+        self
+    }
+
+    /// Wait until something is due
+    ///
+    /// Holds no borrow of the connection, so it can race its events; pass the
+    /// tick on to [`Self::publish`].
+    pub async fn tick(&mut self) -> PumpTick {
+        tokio::select! {
+            _ = self.sample_interval.tick() => PumpTick::Sample,
+            _ = self.flush_interval.tick() => PumpTick::FlushBatch,
+            _ = self.progress_interval.tick() => PumpTick::MissionProgress,
+        }
+    }
+
+    /// Send what `tick` found due
+    pub async fn publish(&mut self, tick: PumpTick, conn: &ConnectionManager) {
+        match tick {
+            PumpTick::Sample => {
+                let telemetry = self.sample(conn).await;
+                if self.policy.batch_flush.is_some() {
+                    if let Some(batch) = self.batcher.push(now_ms(), telemetry) {
+                        send_batch(conn, batch).await;
+                    }
+                } else {
+                    let payload = self.encoder.encode(telemetry);
+                    let msg_type = match payload {
+                        envelope::Payload::TelemetryDelta(_) => MessageType::MsgTelemetryDelta,
+                        _ => MessageType::MsgTelemetry,
+                    };
+                    send_payload(conn, msg_type, payload).await;
+                }
+            }
+            PumpTick::FlushBatch => {
+                if let Some(batch) = self.batcher.flush() {
+                    send_batch(conn, batch).await;
+                }
+            }
+            PumpTick::MissionProgress => {
+                if let Some(progress) = self.reader.get_mission_progress().await {
+                    let payload = envelope::Payload::MissionProgress(progress);
+                    send_payload(conn, MessageType::MsgMissionProgress, payload).await;
+                }
+            }
+        }
+    }
+
+    /// Follow a new link to the server
+    pub async fn link_changed(&mut self, transport: Transport, conn: &ConnectionManager) {
+        self.reader.set_active_transport(transport.into()).await;
+        // The server may have lost our keyframe along with the old link
+        self.encoder.force_keyframe();
+        self.follow_policy(transport);
+        // Batch only over the slow links; on a full-rate one send what's waiting now
+        if self.policy.batch_flush.is_none() {
+            if let Some(batch) = self.batcher.flush() {
+                send_batch(conn, batch).await;
+            }
+        }
+    }
+
+    /// Switch to the policy for `transport`
+    ///
+    /// Called as soon as the connection moves to another transport, before
+    /// it is up, so nothing is queued at the old link's rate meanwhile.
+    pub fn follow_policy(&mut self, transport: Transport) {
+        let policy = *self.policies.get(transport);
+        if policy == self.policy {
+            return;
+        }
+        match policy.batch_flush {
+            Some(flush) => println!(
+                "[TELEMETRY] {}: sample every {:?}, batched every {:?}, {:?} detail",
+                transport, policy.interval, flush, policy.detail
+            ),
+            None => println!(
+                "[TELEMETRY] {}: every {:?}, {:?} detail",
+                transport, policy.interval, policy.detail
+            ),
+        }
+        self.sample_interval = interval(policy.interval);
+        self.flush_interval = interval(policy.batch_flush.unwrap_or(policy.interval));
+        self.policy = policy;
+    }
+
+    /// Current telemetry with the latest link quality and video stream health
+    async fn sample(&self, conn: &ConnectionManager) -> Telemetry {
+        let mut telemetry = self.reader.get_telemetry().await;
+        if let Some(quality) = conn.latest_quality().await {
+            telemetry.conn_quality = Some(quality);
+        }
+        if let Some(video) = &self.video {
+            telemetry.video = video.health().await;
+        }
+        self.policy.detail.apply(&mut telemetry);
+        telemetry
+    }
+}
+
+/// Push batched samples to the server
+async fn send_batch(conn: &ConnectionManager, batch: TelemetryBatch) {
+    let payload = envelope::Payload::TelemetryBatch(Box::new(batch));
+    send_payload(conn, MessageType::MsgTelemetryBatch, payload).await;
+}
+
+async fn send_payload(conn: &ConnectionManager, msg_type: MessageType, payload: envelope::Payload) {
+    let envelope = Envelope {
+        header: Some(Header::new(
+            conn.device_id(),
+            msg_type,
+            conn.next_sequence_id(),
+        )),
+        payload: Some(payload),
+        signature: Vec::new(),
+    };
+
+    if let Err(e) = conn.send(envelope).await {
+        eprintln!("[TELEMETRY] Failed to send telemetry: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_policy_follows_transport() {
+        let policies = TelemetryPolicies::default();
+        let mut pump = TelemetryPump::new(Arc::new(TelemetryReader::new()), policies.clone());
+        assert_eq!(pump.policy, policies.five_g);
+
+        // Down to Bluetooth: slower, batched, reduced
+        pump.follow_policy(Transport::Bluetooth);
+        assert_eq!(pump.policy, policies.bluetooth);
+        assert_eq!(pump.sample_interval.period(), policies.bluetooth.interval);
+
+        // Back on 5G: full rate again
+        pump.follow_policy(Transport::FiveG);
+        assert_eq!(pump.sample_interval.period(), policies.five_g.interval);
+        assert_eq!(pump.policy.batch_flush, None);
+    }
+}
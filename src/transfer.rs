@@ -0,0 +1,411 @@
+//! Blob uploads: captured images and map tiles to the server
+//!
+//! Files dropped into the spool directory (`RESQTERRA_BLOB_SPOOL`) are sent
+//! one at a time as chunked blobs (see `resqterra_shared::blob`), named by
+//! their file name, and deleted once the server has stored them. Writers
+//! should create a file under a `.tmp` name and rename it when it is
+//! complete.
+//!
+//! Transfers only run over 5G. On Bluetooth (or with no link) the current one
+//! is suspended; when 5G is back its `BlobStart` is sent again and it resumes
+//! from what the server already holds.
+
+use crate::connection::OutboundSender;
+use crate::transport::Transport;
+use anyhow::{anyhow, Result};
+use resqterra_shared::blob::{self, chunk_blob};
+use resqterra_shared::{
+    envelope, priority, BlobChunk, BlobEnd, BlobStart, BlobState, BlobStatus, Envelope, Header,
+    MessageType, MAX_BLOB_LEN,
+};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{interval, sleep_until, Instant};
+
+/// Where images and tiles wait to be sent unless `RESQTERRA_BLOB_SPOOL` is set
+pub const DEFAULT_BLOB_SPOOL: &str = "/var/lib/resqterra/blobs-out";
+
+/// How often the spool is checked for new files while idle
+const SPOOL_SCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the server has to answer a `BlobStart` or `BlobEnd`
+const STATUS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tries per blob before it is set aside as `.failed`
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Spool directory; `off` in `RESQTERRA_BLOB_SPOOL` disables uploads
+pub fn spool_from_env() -> Option<PathBuf> {
+    match std::env::var("RESQTERRA_BLOB_SPOOL") {
+        Ok(v) if v.eq_ignore_ascii_case("off") => None,
+        Ok(v) => Some(v.into()),
+        Err(_) => Some(DEFAULT_BLOB_SPOOL.into()),
+    }
+}
+
+/// What the uploader is told by the main loop
+#[derive(Debug, Clone)]
+pub enum BlobEvent {
+    /// Connected over a transport, or disconnected (None)
+    LinkChanged(Option<Transport>),
+    /// The server's answer to a `BlobStart` or `BlobEnd`
+    Status(BlobStatus),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// `BlobStart` sent (or due once 5G is up), waiting for where to go on from
+    Starting,
+    /// Sending chunks
+    Sending,
+    /// `BlobEnd` sent, waiting for the verdict
+    Ending,
+}
+
+/// The blob being sent
+struct Transfer {
+    path: PathBuf,
+    start: BlobStart,
+    data: Vec<u8>,
+    /// Next byte to send
+    offset: u64,
+    phase: Phase,
+    attempts: u32,
+    /// When to give up waiting for a status and start again
+    deadline: Option<Instant>,
+}
+
+/// Sends spooled files to the server, one at a time
+pub struct BlobUploader {
+    device_id: String,
+    sequence_id: Arc<AtomicU64>,
+    uplink: OutboundSender,
+    spool: PathBuf,
+    events: mpsc::Receiver<BlobEvent>,
+}
+
+impl BlobUploader {
+    /// Create an uploader for the files in `spool`, and the sender the main
+    /// loop reports link changes and blob statuses on
+    pub fn new(
+        device_id: String,
+        sequence_id: Arc<AtomicU64>,
+        uplink: OutboundSender,
+        spool: PathBuf,
+    ) -> (Self, mpsc::Sender<BlobEvent>) {
+        let (tx, events) = mpsc::channel(16);
+        let uploader = Self {
+            device_id,
+            sequence_id,
+            uplink,
+            spool,
+            events,
+        };
+        (uploader, tx)
+    }
+
+    /// Upload spooled files until the event sender is dropped or the
+    /// connection closes
+    pub async fn run(mut self) -> Result<()> {
+        std::fs::create_dir_all(&self.spool)?;
+        let mut five_g = false;
+        let mut current: Option<Transfer> = None;
+        let mut scan = interval(SPOOL_SCAN_INTERVAL);
+        loop {
+            let sending = five_g && current.as_ref().is_some_and(|t| t.phase == Phase::Sending);
+            let deadline = current.as_ref().and_then(|t| t.deadline);
+            tokio::select! {
+                biased;
+                event = self.events.recv() => match event {
+                    Some(BlobEvent::LinkChanged(transport)) => {
+                        five_g = transport == Some(Transport::FiveG);
+                        if let Some(transfer) = &mut current {
+                            // Suspended until 5G is back; then resumed from the server's count
+                            transfer.phase = Phase::Starting;
+                            transfer.deadline = None;
+                            if five_g {
+                                self.send_start(transfer).await?;
+                            }
+                        }
+                    }
+                    Some(BlobEvent::Status(status)) => {
+                        let Some(transfer) = current.as_mut() else {
+                            continue;
+                        };
+                        if status.blob_id != transfer.start.blob_id {
+                            continue;
+                        }
+                        if self.apply_status(transfer, &status, five_g).await? {
+                            current = None;
+                        }
+                    }
+                    None => return Ok(()),
+                },
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    let transfer = current.as_mut().expect("a deadline belongs to a transfer");
+                    eprintln!("[BLOB] No answer for {}, starting again", transfer.start.blob_id);
+                    self.send_start(transfer).await?;
+                }
+                _ = std::future::ready(()), if sending => {
+                    let transfer = current.as_mut().expect("sending a transfer");
+                    self.send_next_chunk(transfer).await?;
+                }
+                _ = scan.tick(), if current.is_none() => {
+                    current = self.next_file();
+                    if let (Some(transfer), true) = (&mut current, five_g) {
+                        self.send_start(transfer).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Act on the server's status for the current blob; true once it is done with
+    async fn apply_status(
+        &self,
+        transfer: &mut Transfer,
+        status: &BlobStatus,
+        five_g: bool,
+    ) -> Result<bool> {
+        let blob_id = &transfer.start.blob_id;
+        match status.state() {
+            BlobState::BlobReceiving => {
+                transfer.offset = status.received_bytes.min(transfer.start.total_size);
+                transfer.phase = Phase::Sending;
+                transfer.deadline = None;
+                if transfer.offset > 0 {
+                    println!("[BLOB] Resuming {} at {} bytes", blob_id, transfer.offset);
+                }
+                Ok(false)
+            }
+            BlobState::BlobComplete => {
+                println!("[BLOB] {} stored by the server", blob_id);
+                if let Err(e) = std::fs::remove_file(&transfer.path) {
+                    eprintln!("[BLOB] Can't remove {}: {}", transfer.path.display(), e);
+                }
+                Ok(true)
+            }
+            BlobState::BlobFailed => {
+                transfer.attempts += 1;
+                eprintln!(
+                    "[BLOB] {} failed ({}), attempt {}/{}",
+                    blob_id, status.error, transfer.attempts, MAX_ATTEMPTS
+                );
+                if transfer.attempts >= MAX_ATTEMPTS {
+                    set_aside(&transfer.path);
+                    return Ok(true);
+                }
+                transfer.phase = Phase::Starting;
+                transfer.deadline = None;
+                if five_g {
+                    self.send_start(transfer).await?;
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// The oldest complete file in the spool, read for sending
+    fn next_file(&self) -> Option<Transfer> {
+        let entries = match std::fs::read_dir(&self.spool) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("[BLOB] Can't read {}: {}", self.spool.display(), e);
+                return None;
+            }
+        };
+        let mut files: Vec<(std::time::SystemTime, PathBuf)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                !name.ends_with(".tmp") && !name.ends_with(".failed")
+            })
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect();
+        files.sort();
+
+        for (_, path) in files {
+            match read_blob(&path) {
+                Ok((start, data)) => {
+                    println!("[BLOB] Queued {} ({} bytes)", start.blob_id, start.total_size);
+                    return Some(Transfer {
+                        path,
+                        start,
+                        data,
+                        offset: 0,
+                        phase: Phase::Starting,
+                        attempts: 0,
+                        deadline: None,
+                    });
+                }
+                Err(e) => {
+                    eprintln!("[BLOB] Can't send {}: {}", path.display(), e);
+                    set_aside(&path);
+                }
+            }
+        }
+        None
+    }
+
+    async fn send_start(&self, transfer: &mut Transfer) -> Result<()> {
+        transfer.phase = Phase::Starting;
+        transfer.deadline = Some(Instant::now() + STATUS_TIMEOUT);
+        let start = transfer.start.clone();
+        self.send(MessageType::MsgBlobStart, envelope::Payload::BlobStart(start)).await
+    }
+
+    /// Send the chunk at the transfer's offset, or the `BlobEnd` after the last
+    async fn send_next_chunk(&self, transfer: &mut Transfer) -> Result<()> {
+        let blob_id = transfer.start.blob_id.clone();
+        let chunk: Option<BlobChunk> = chunk_blob(&blob_id, &transfer.data, transfer.offset).next();
+        match chunk {
+            Some(chunk) => {
+                transfer.offset += chunk.data.len() as u64;
+                self.send(MessageType::MsgBlobChunk, envelope::Payload::BlobChunk(chunk)).await
+            }
+            None => {
+                transfer.phase = Phase::Ending;
+                transfer.deadline = Some(Instant::now() + STATUS_TIMEOUT);
+                let end = BlobEnd { blob_id };
+                self.send(MessageType::MsgBlobEnd, envelope::Payload::BlobEnd(end)).await
+            }
+        }
+    }
+
+    async fn send(&self, msg_type: MessageType, payload: envelope::Payload) -> Result<()> {
+        let seq = self.sequence_id.fetch_add(1, Ordering::SeqCst) + 1;
+        // Bulk data: it never gets ahead of telemetry or ACKs
+        let envelope = Envelope {
+            header: Some(Header::new(&self.device_id, msg_type, seq).with_priority(priority::LOW)),
+            payload: Some(payload),
+            signature: Vec::new(),
+        };
+        self.uplink
+            .send(envelope)
+            .await
+            .map_err(|_| anyhow!("Connection closed"))
+    }
+}
+
+/// Read a spooled file as a blob named after it
+fn read_blob(path: &Path) -> Result<(BlobStart, Vec<u8>)> {
+    let blob_id = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    blob::validate_blob_id(&blob_id)?;
+    let len = std::fs::metadata(path)?.len();
+    if len > MAX_BLOB_LEN {
+        return Err(blob::BlobError::TooLarge(len).into());
+    }
+    let data = std::fs::read(path)?;
+    let start = BlobStart {
+        blob_id,
+        content_type: content_type(path).into(),
+        total_size: data.len() as u64,
+        sha256: blob::blob_checksum(&data),
+        name: String::new(),
+    };
+    Ok((start, data))
+}
+
+/// MIME type by file extension
+fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("tif" | "tiff") => "image/tiff",
+        Some("webp") => "image/webp",
+        Some("pbf" | "mvt") => "application/vnd.mapbox-vector-tile",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Rename a file that can't be sent to `{name}.failed`, out of the queue
+fn set_aside(path: &Path) {
+    let mut failed = path.as_os_str().to_owned();
+    failed.push(".failed");
+    if let Err(e) = std::fs::rename(path, &failed) {
+        eprintln!("[BLOB] Can't set aside {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::outbound_channel;
+    use resqterra_shared::BLOB_CHUNK_LEN;
+
+    fn spool(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("resqterra-spool-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn status(blob_id: &str, state: BlobState, received_bytes: u64) -> BlobEvent {
+        BlobEvent::Status(BlobStatus {
+            blob_id: blob_id.into(),
+            state: state.into(),
+            received_bytes,
+            error: String::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_spooled_file_resumed_over_5g_only() {
+        let dir = spool("resume");
+        let data: Vec<u8> = (0..BLOB_CHUNK_LEN * 2 + 10).map(|i| i as u8).collect();
+        std::fs::write(dir.join("frame-1.jpg"), &data).unwrap();
+        std::fs::write(dir.join("frame-2.jpg.tmp"), b"still being written").unwrap();
+
+        let (uplink, mut rx) = outbound_channel(8);
+        let (uploader, events) =
+            BlobUploader::new("edge-001".into(), Arc::new(AtomicU64::new(0)), uplink, dir.clone());
+        tokio::spawn(uploader.run());
+
+        // Nothing goes out over Bluetooth
+        events.send(BlobEvent::LinkChanged(Some(Transport::Bluetooth))).await.unwrap();
+        let quiet = tokio::time::timeout(Duration::from_millis(50), rx.recv()).await;
+        assert!(quiet.is_err());
+
+        events.send(BlobEvent::LinkChanged(Some(Transport::FiveG))).await.unwrap();
+        let envelope = rx.recv().await.unwrap();
+        assert_eq!(envelope.priority(), priority::LOW);
+        let Some(envelope::Payload::BlobStart(start)) = envelope.payload else {
+            panic!("expected blob start");
+        };
+        assert_eq!(start.blob_id, "frame-1.jpg");
+        assert_eq!(start.content_type, "image/jpeg");
+        assert_eq!(start.total_size, data.len() as u64);
+
+        // The server already holds the first chunk from before a link drop
+        let resume = status("frame-1.jpg", BlobState::BlobReceiving, BLOB_CHUNK_LEN as u64);
+        events.send(resume).await.unwrap();
+        let mut offsets = Vec::new();
+        loop {
+            match rx.recv().await.unwrap().payload {
+                Some(envelope::Payload::BlobChunk(chunk)) => offsets.push(chunk.offset),
+                Some(envelope::Payload::BlobEnd(end)) => {
+                    assert_eq!(end.blob_id, "frame-1.jpg");
+                    break;
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(offsets, [BLOB_CHUNK_LEN as u64, 2 * BLOB_CHUNK_LEN as u64]);
+
+        let stored = status("frame-1.jpg", BlobState::BlobComplete, data.len() as u64);
+        events.send(stored).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!dir.join("frame-1.jpg").exists());
+        assert!(dir.join("frame-2.jpg.tmp").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
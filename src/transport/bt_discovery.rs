@@ -1,14 +1,93 @@
 //! Bluetooth device discovery for finding relay nodes
 
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use bluer::{Adapter, Address, Device};
 use std::collections::HashSet;
+use std::fmt;
 use std::time::Duration;
 use tokio::time::timeout;
 
 /// UUID for ResQTerra relay service (SPP-like custom UUID)
 pub const RESQTERRA_SERVICE_UUID: bluer::Uuid =
-    bluer::Uuid::from_u128(0x00001101_0000_1000_8000_00805F9B34FB);
+    bluer::Uuid::from_u128(resqterra_shared::BT_SERVICE_UUID);
+
+/// How long BlueZ gets for each step of handing over the adapter
+pub const ADAPTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why no usable Bluetooth adapter could be acquired
+#[derive(Debug)]
+pub enum AdapterError {
+    /// The hardware has no Bluetooth adapter; Bluetooth can be skipped entirely
+    NoAdapter,
+    /// BlueZ didn't answer in time (DBus stall, wedged bluetoothd)
+    Timeout { step: &'static str, after: Duration },
+    /// BlueZ reported an error
+    Bluez(bluer::Error),
+}
+
+impl fmt::Display for AdapterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdapterError::NoAdapter => write!(f, "No Bluetooth adapter present"),
+            AdapterError::Timeout { step, after } => {
+                write!(f, "Bluetooth {} timed out after {:?}", step, after)
+            }
+            AdapterError::Bluez(e) => write!(f, "BlueZ error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AdapterError {}
+
+/// Source of the Bluetooth adapter (BlueZ, or a fake in tests)
+#[async_trait]
+pub trait AdapterProvider: Send + Sync {
+    type Adapter: Send + Sync;
+
+    /// The system's default adapter
+    async fn default_adapter(&self) -> bluer::Result<Self::Adapter>;
+
+    /// Power the adapter on
+    async fn power_on(&self, adapter: &Self::Adapter) -> bluer::Result<()>;
+}
+
+/// Adapters managed by the system BlueZ daemon
+pub struct BluezAdapters;
+
+#[async_trait]
+impl AdapterProvider for BluezAdapters {
+    type Adapter = Adapter;
+
+    async fn default_adapter(&self) -> bluer::Result<Adapter> {
+        bluer::Session::new().await?.default_adapter().await
+    }
+
+    async fn power_on(&self, adapter: &Adapter) -> bluer::Result<()> {
+        adapter.set_powered(true).await
+    }
+}
+
+/// Acquire and power on the default adapter, giving each step `limit`
+pub async fn acquire_adapter<P: AdapterProvider>(
+    provider: &P,
+    limit: Duration,
+) -> Result<P::Adapter, AdapterError> {
+    let timed_out = |step| AdapterError::Timeout { step, after: limit };
+
+    let adapter = match timeout(limit, provider.default_adapter()).await {
+        Ok(Ok(adapter)) => adapter,
+        Ok(Err(e)) if e.kind == bluer::ErrorKind::NotFound => return Err(AdapterError::NoAdapter),
+        Ok(Err(e)) => return Err(AdapterError::Bluez(e)),
+        Err(_) => return Err(timed_out("adapter lookup")),
+    };
+
+    match timeout(limit, provider.power_on(&adapter)).await {
+        Ok(Ok(())) => Ok(adapter),
+        Ok(Err(e)) => Err(AdapterError::Bluez(e)),
+        Err(_) => Err(timed_out("power on")),
+    }
+}
 
 /// Configuration for Bluetooth discovery
 #[derive(Debug, Clone)]
@@ -19,6 +98,8 @@ pub struct BtDiscoveryConfig {
     pub known_relays: Vec<Address>,
     /// Device name prefix to match
     pub name_prefix: Option<String>,
+    /// Limit for each step of acquiring the adapter
+    pub adapter_timeout: Duration,
 }
 
 impl Default for BtDiscoveryConfig {
@@ -27,6 +108,7 @@ impl Default for BtDiscoveryConfig {
             scan_duration: Duration::from_secs(10),
             known_relays: Vec::new(),
             name_prefix: Some("ResQTerra-Relay".into()),
+            adapter_timeout: ADAPTER_TIMEOUT,
         }
     }
 }
@@ -53,12 +135,11 @@ impl BtDiscovery {
         Self { config }
     }
 
-    /// Get the default Bluetooth adapter
-    pub async fn get_adapter() -> Result<Adapter> {
-        let session = bluer::Session::new().await?;
-        let adapter = session.default_adapter().await?;
-        adapter.set_powered(true).await?;
-        Ok(adapter)
+    /// Get the default Bluetooth adapter, powered on
+    ///
+    /// Fails with an [`AdapterError`] instead of hanging when BlueZ stalls.
+    pub async fn get_adapter(&self) -> Result<Adapter> {
+        Ok(acquire_adapter(&BluezAdapters, self.config.adapter_timeout).await?)
     }
 
     /// Discover relay devices
@@ -164,4 +245,60 @@ mod tests {
         assert!(config.known_relays.is_empty());
         assert_eq!(config.name_prefix, Some("ResQTerra-Relay".into()));
     }
+
+    /// Adapter provider that answers after `delay`, or reports no adapter
+    struct FakeAdapters {
+        delay: Duration,
+        present: bool,
+    }
+
+    #[async_trait]
+    impl AdapterProvider for FakeAdapters {
+        type Adapter = &'static str;
+
+        async fn default_adapter(&self) -> bluer::Result<&'static str> {
+            tokio::time::sleep(self.delay).await;
+            if !self.present {
+                return Err(bluer::Error {
+                    kind: bluer::ErrorKind::NotFound,
+                    message: "No Bluetooth adapter present".into(),
+                });
+            }
+            Ok("hci0")
+        }
+
+        async fn power_on(&self, _adapter: &&'static str) -> bluer::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_bluez_times_out() {
+        let limit = Duration::from_millis(50);
+        let stalled = FakeAdapters {
+            delay: Duration::from_secs(3600),
+            present: true,
+        };
+        let result = acquire_adapter(&stalled, limit).await;
+        assert!(matches!(
+            result,
+            Err(AdapterError::Timeout { step: "adapter lookup", after }) if after == limit
+        ));
+
+        let healthy = FakeAdapters {
+            delay: Duration::from_millis(5),
+            present: true,
+        };
+        assert_eq!(acquire_adapter(&healthy, limit).await.unwrap(), "hci0");
+    }
+
+    #[tokio::test]
+    async fn test_missing_adapter_is_distinct() {
+        let absent = FakeAdapters {
+            delay: Duration::ZERO,
+            present: false,
+        };
+        let err = anyhow::Error::from(acquire_adapter(&absent, ADAPTER_TIMEOUT).await.unwrap_err());
+        assert!(matches!(err.downcast_ref(), Some(AdapterError::NoAdapter)));
+    }
 }
@@ -0,0 +1,612 @@
+//! LoRa transport for long-range, low-rate fallback
+//!
+//! Talks to a serial-attached LoRa modem: a Microchip RN2903 through its
+//! `radio` command set, or an Ebyte E22 in transparent mode. A LoRa gateway
+//! on the ground forwards to the server, as the relay node does for Bluetooth.
+//!
+//! Radio packets are small (255 bytes on the RN2903, 240 on the E22), so
+//! whatever is written between two flushes is sent as one message, cut into
+//! MTU-sized fragments and put back together on the other side. Callers
+//! flush after whole frames (`FramedWrite` does), so a lost fragment loses
+//! those frames and nothing else: the byte stream stays in step.
+
+use crate::transport::traits::{TransportConnector, TransportStream};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf, WriteHalf};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_serial::SerialPortBuilderExt;
+use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite, LinesCodec};
+use tokio_util::sync::PollSender;
+
+/// Fragment header: message ID, fragment index, fragment count
+const FRAGMENT_HEADER_LEN: usize = 3;
+
+/// Start of a packet on the E22 serial line, followed by its length
+const E22_SYNC: u8 = 0xA5;
+
+/// Longest wait for the RN2903 to answer a command
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest a packet may take on air (slow spreading factors)
+const TX_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages buffered each way between the stream and the modem task
+const MESSAGE_QUEUE_LEN: usize = 8;
+
+/// Supported LoRa modems
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoraModem {
+    /// Microchip RN2903, driven with `radio tx` / `radio rx`
+    #[default]
+    Rn2903,
+    /// Ebyte E22 in transparent mode (M0 and M1 low)
+    E22,
+}
+
+impl LoraModem {
+    /// Largest radio packet the modem sends
+    pub fn max_packet_len(self) -> usize {
+        match self {
+            LoraModem::Rn2903 => 255,
+            LoraModem::E22 => 240,
+        }
+    }
+
+    /// Serial framing around each packet
+    fn framing_len(self) -> usize {
+        match self {
+            LoraModem::Rn2903 => 0,
+            LoraModem::E22 => 2,
+        }
+    }
+}
+
+impl FromStr for LoraModem {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "rn2903" => Ok(LoraModem::Rn2903),
+            "e22" => Ok(LoraModem::E22),
+            other => Err(anyhow!("Unknown LoRa modem: {}", other)),
+        }
+    }
+}
+
+/// Configuration for the LoRa connector
+#[derive(Debug, Clone)]
+pub struct LoraConfig {
+    /// Serial port the modem is attached to
+    pub port: String,
+    /// Serial baud rate (RN2903: 57600, E22: 9600 by default)
+    pub baud_rate: u32,
+    pub modem: LoraModem,
+    /// Largest radio packet to send, headers included (capped at the modem's)
+    pub mtu: usize,
+}
+
+impl Default for LoraConfig {
+    fn default() -> Self {
+        Self {
+            port: "/dev/ttyUSB0".into(),
+            baud_rate: 57600,
+            modem: LoraModem::Rn2903,
+            mtu: LoraModem::Rn2903.max_packet_len(),
+        }
+    }
+}
+
+impl LoraConfig {
+    /// Message bytes carried by each fragment
+    fn fragment_payload_len(&self) -> usize {
+        let mtu = self.mtu.min(self.modem.max_packet_len());
+        mtu.saturating_sub(FRAGMENT_HEADER_LEN + self.modem.framing_len()).max(1)
+    }
+}
+
+/// Cut `message` into packets of at most `payload_len` message bytes each
+///
+/// The message must fit in 255 fragments.
+pub fn fragment(message_id: u8, message: &[u8], payload_len: usize) -> Vec<Bytes> {
+    let chunks: Vec<&[u8]> = message.chunks(payload_len).collect();
+    let count = chunks.len() as u8;
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut packet = BytesMut::with_capacity(FRAGMENT_HEADER_LEN + chunk.len());
+            packet.put_u8(message_id);
+            packet.put_u8(index as u8);
+            packet.put_u8(count);
+            packet.put_slice(chunk);
+            packet.freeze()
+        })
+        .collect()
+}
+
+/// Puts fragmented messages back together
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    message_id: Option<u8>,
+    parts: Vec<Option<Bytes>>,
+}
+
+impl Reassembler {
+    /// Add a received packet; returns the message once all its fragments are in
+    ///
+    /// LoRa doesn't reorder, so a fragment of another message means the one
+    /// being assembled lost a fragment: it is dropped.
+    pub fn push(&mut self, packet: &[u8]) -> Option<Bytes> {
+        if packet.len() < FRAGMENT_HEADER_LEN {
+            return None;
+        }
+        let (message_id, index, count) = (packet[0], packet[1] as usize, packet[2] as usize);
+        if index >= count {
+            return None;
+        }
+        if self.message_id != Some(message_id) || self.parts.len() != count {
+            self.message_id = Some(message_id);
+            self.parts = vec![None; count];
+        }
+        self.parts[index] = Some(Bytes::copy_from_slice(&packet[FRAGMENT_HEADER_LEN..]));
+        if !self.parts.iter().all(Option::is_some) {
+            return None;
+        }
+
+        self.message_id = None;
+        let mut message = BytesMut::new();
+        for part in self.parts.drain(..).flatten() {
+            message.put_slice(&part);
+        }
+        Some(message.freeze())
+    }
+}
+
+/// Stream over a LoRa modem; a background task does the radio work
+pub struct LoraTransportStream {
+    /// Bytes written since the last flush
+    pending: BytesMut,
+    /// Longest message the fragment header can number
+    max_message_len: usize,
+    outgoing: PollSender<Bytes>,
+    incoming: mpsc::Receiver<Bytes>,
+    /// Rest of the message being read
+    read_buf: Bytes,
+}
+
+impl LoraTransportStream {
+    /// A stream, and the ends of its queues the modem task serves
+    fn new(payload_len: usize) -> (Self, mpsc::Receiver<Bytes>, mpsc::Sender<Bytes>) {
+        let (outgoing_tx, outgoing_rx) = mpsc::channel(MESSAGE_QUEUE_LEN);
+        let (incoming_tx, incoming_rx) = mpsc::channel(MESSAGE_QUEUE_LEN);
+        let stream = Self {
+            pending: BytesMut::new(),
+            max_message_len: payload_len * u8::MAX as usize,
+            outgoing: PollSender::new(outgoing_tx),
+            incoming: incoming_rx,
+            read_buf: Bytes::new(),
+        };
+        (stream, outgoing_rx, incoming_tx)
+    }
+}
+
+impl AsyncRead for LoraTransportStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.read_buf.is_empty() {
+            match ready!(this.incoming.poll_recv(cx)) {
+                Some(message) => this.read_buf = message,
+                // The modem task stopped
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = buf.remaining().min(this.read_buf.len());
+        buf.put_slice(&this.read_buf[..n]);
+        this.read_buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for LoraTransportStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().pending.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pending.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        let closed = |_| io::Error::new(io::ErrorKind::BrokenPipe, "LoRa modem stopped");
+        ready!(this.outgoing.poll_reserve(cx)).map_err(closed)?;
+
+        let message = this.pending.split().freeze();
+        if message.len() > this.max_message_len {
+            // Lost like a dropped fragment would be
+            eprintln!(
+                "[LORA] Dropping {}-byte message, over the {}-byte limit",
+                message.len(),
+                this.max_message_len
+            );
+            this.outgoing.abort_send();
+            return Poll::Ready(Ok(()));
+        }
+        this.outgoing.send_item(message).map_err(closed)?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.get_mut().outgoing.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[async_trait]
+impl TransportStream for LoraTransportStream {
+    async fn shutdown(&mut self) -> Result<()> {
+        AsyncWriteExt::shutdown(self).await?;
+        Ok(())
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Bytes> {
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .map(Bytes::from)
+}
+
+/// An RN2903 driven through its `radio` command set
+struct Rn2903<M> {
+    lines: FramedRead<tokio::io::ReadHalf<M>, LinesCodec>,
+    writer: WriteHalf<M>,
+    /// Packets heard while waiting for a reply
+    received: Vec<Bytes>,
+}
+
+impl<M: AsyncRead + AsyncWrite + Send> Rn2903<M> {
+    fn new(modem: M) -> Self {
+        let (reader, writer) = tokio::io::split(modem);
+        Self {
+            lines: FramedRead::new(reader, LinesCodec::new()),
+            writer,
+            received: Vec::new(),
+        }
+    }
+
+    /// Next line from the modem, stashing received packets on the way
+    async fn reply(&mut self, wait: Duration) -> Result<String> {
+        loop {
+            let line = timeout(wait, self.lines.next())
+                .await
+                .map_err(|_| anyhow!("RN2903 didn't answer within {:?}", wait))?
+                .ok_or_else(|| anyhow!("RN2903 serial port closed"))??;
+            match line.trim().strip_prefix("radio_rx") {
+                Some(hex) => self.received.extend(decode_hex(hex.trim())),
+                None => return Ok(line.trim().to_string()),
+            }
+        }
+    }
+
+    async fn command(&mut self, command: &str) -> Result<String> {
+        self.writer.write_all(format!("{}\r\n", command).as_bytes()).await?;
+        self.reply(COMMAND_TIMEOUT).await
+    }
+
+    async fn expect_ok(&mut self, command: &str) -> Result<()> {
+        match self.command(command).await?.as_str() {
+            "ok" => Ok(()),
+            other => Err(anyhow!("RN2903 answered {:?} to {:?}", other, command)),
+        }
+    }
+
+    /// Take the radio out of LoRaWAN into raw packet mode
+    async fn init(&mut self) -> Result<()> {
+        let version = self.command("sys get ver").await?;
+        println!("[LORA] Modem: {}", version);
+        // Answers with how long LoRaWAN is paused for
+        self.command("mac pause").await?;
+        // Listen until a packet arrives instead of timing out
+        self.expect_ok("radio set wdt 0").await
+    }
+
+    async fn transmit(&mut self, packet: &[u8]) -> Result<()> {
+        self.expect_ok(&format!("radio tx {}", encode_hex(packet))).await?;
+        match self.reply(TX_TIMEOUT).await?.as_str() {
+            "radio_tx_ok" => Ok(()),
+            other => Err(anyhow!("RN2903 transmit failed: {}", other)),
+        }
+    }
+
+    /// Listen, stopping to send each outgoing message, until the stream closes
+    async fn run(
+        mut self,
+        payload_len: usize,
+        mut outgoing: mpsc::Receiver<Bytes>,
+        incoming: mpsc::Sender<Bytes>,
+    ) -> Result<()> {
+        let mut reassembler = Reassembler::default();
+        let mut message_id: u8 = 0;
+        loop {
+            // Half duplex: the receiver is re-armed after every packet and send
+            self.expect_ok("radio rx 0").await?;
+            tokio::select! {
+                line = self.lines.next() => {
+                    let line = line.ok_or_else(|| anyhow!("RN2903 serial port closed"))??;
+                    match line.trim().strip_prefix("radio_rx") {
+                        Some(hex) => self.received.extend(decode_hex(hex.trim())),
+                        None if line.trim() == "radio_err" => {}
+                        None => println!("[LORA] Unexpected from modem: {}", line.trim()),
+                    }
+                }
+                message = outgoing.recv() => {
+                    let Some(message) = message else {
+                        return Ok(());
+                    };
+                    // Stops listening; a packet that just arrived is stashed
+                    self.command("radio rxstop").await?;
+                    for packet in fragment(message_id, &message, payload_len) {
+                        if let Err(e) = self.transmit(&packet).await {
+                            eprintln!("[LORA] {}", e);
+                            break;
+                        }
+                    }
+                    message_id = message_id.wrapping_add(1);
+                }
+            }
+            for packet in std::mem::take(&mut self.received) {
+                if let Some(message) = reassembler.push(&packet) {
+                    if incoming.send(message).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Packets on an E22's serial line: sync byte, length, packet
+struct E22Codec;
+
+impl Decoder for E22Codec {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Bytes>> {
+        loop {
+            // Skip line noise up to the next sync byte
+            match src.iter().position(|&b| b == E22_SYNC) {
+                Some(start) => src.advance(start),
+                None => {
+                    src.clear();
+                    return Ok(None);
+                }
+            }
+            if src.len() < 2 {
+                return Ok(None);
+            }
+            let len = src[1] as usize;
+            if len == 0 {
+                src.advance(1);
+                continue;
+            }
+            if src.len() < 2 + len {
+                return Ok(None);
+            }
+            src.advance(2);
+            return Ok(Some(src.split_to(len).freeze()));
+        }
+    }
+}
+
+impl Encoder<Bytes> for E22Codec {
+    type Error = io::Error;
+
+    fn encode(&mut self, packet: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+        dst.put_u8(E22_SYNC);
+        dst.put_u8(packet.len() as u8);
+        dst.put_slice(&packet);
+        Ok(())
+    }
+}
+
+/// Pass packets to and from an E22 until the stream closes
+async fn run_e22<M: AsyncRead + AsyncWrite + Send>(
+    modem: M,
+    payload_len: usize,
+    mut outgoing: mpsc::Receiver<Bytes>,
+    incoming: mpsc::Sender<Bytes>,
+) -> Result<()> {
+    let (reader, writer) = tokio::io::split(modem);
+    let mut reader = FramedRead::new(reader, E22Codec);
+    let mut writer = FramedWrite::new(writer, E22Codec);
+    let mut reassembler = Reassembler::default();
+    let mut message_id: u8 = 0;
+    loop {
+        tokio::select! {
+            packet = reader.next() => {
+                let packet = packet.ok_or_else(|| anyhow!("E22 serial port closed"))??;
+                if let Some(message) = reassembler.push(&packet) {
+                    if incoming.send(message).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            message = outgoing.recv() => {
+                let Some(message) = message else {
+                    return Ok(());
+                };
+                for packet in fragment(message_id, &message, payload_len) {
+                    writer.send(packet).await?;
+                }
+                message_id = message_id.wrapping_add(1);
+            }
+        }
+    }
+}
+
+/// Connector for a serial-attached LoRa modem
+pub struct LoraConnector {
+    config: LoraConfig,
+}
+
+impl LoraConnector {
+    pub fn new(config: LoraConfig) -> Self {
+        Self { config }
+    }
+
+    /// Bring up `modem` and hand its traffic to a new stream
+    async fn start<M>(modem: M, config: &LoraConfig) -> Result<LoraTransportStream>
+    where
+        M: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let payload_len = config.fragment_payload_len();
+        let (stream, outgoing, incoming) = LoraTransportStream::new(payload_len);
+        match config.modem {
+            LoraModem::Rn2903 => {
+                let mut modem = Rn2903::new(modem);
+                modem.init().await?;
+                tokio::spawn(async move {
+                    if let Err(e) = modem.run(payload_len, outgoing, incoming).await {
+                        eprintln!("[LORA] Modem stopped: {}", e);
+                    }
+                });
+            }
+            LoraModem::E22 => {
+                tokio::spawn(async move {
+                    if let Err(e) = run_e22(modem, payload_len, outgoing, incoming).await {
+                        eprintln!("[LORA] Modem stopped: {}", e);
+                    }
+                });
+            }
+        }
+        Ok(stream)
+    }
+}
+
+#[async_trait]
+impl TransportConnector for LoraConnector {
+    async fn connect(&self) -> Result<Box<dyn TransportStream>> {
+        let serial = tokio_serial::new(&self.config.port, self.config.baud_rate)
+            .open_native_async()
+            .map_err(|e| anyhow!("Can't open LoRa modem on {}: {}", self.config.port, e))?;
+        println!("[LORA] Opened {:?} modem on {}", self.config.modem, self.config.port);
+        Ok(Box::new(Self::start(serial, &self.config).await?))
+    }
+
+    fn name(&self) -> &'static str {
+        "LoRa"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+    #[test]
+    fn test_fragments_reassembled_and_incomplete_dropped() {
+        let message: Vec<u8> = (0..600u32).map(|i| i as u8).collect();
+        let packets = fragment(7, &message, 100);
+        assert_eq!(packets.len(), 6);
+        assert!(packets.iter().all(|p| p.len() <= 100 + FRAGMENT_HEADER_LEN));
+
+        let mut reassembler = Reassembler::default();
+        let delivered: Vec<Bytes> = packets.iter().filter_map(|p| reassembler.push(p)).collect();
+        assert_eq!(delivered, [Bytes::from(message.clone())]);
+
+        // One fragment lost: that message is gone, the next still arrives
+        for packet in fragment(8, &message, 100).iter().skip(1) {
+            assert!(reassembler.push(packet).is_none());
+        }
+        let next = fragment(9, b"heartbeat", 100);
+        assert_eq!(reassembler.push(&next[0]).as_deref(), Some(&b"heartbeat"[..]));
+    }
+
+    #[test]
+    fn test_e22_codec_resyncs_after_noise() {
+        let mut buf = BytesMut::new();
+        E22Codec.encode(Bytes::from_static(b"one"), &mut buf).unwrap();
+        let mut line = BytesMut::from(&[0x00, 0x13][..]);
+        line.extend_from_slice(&buf);
+        E22Codec.encode(Bytes::from_static(b"two"), &mut line).unwrap();
+
+        assert_eq!(E22Codec.decode(&mut line).unwrap().as_deref(), Some(&b"one"[..]));
+        assert_eq!(E22Codec.decode(&mut line).unwrap().as_deref(), Some(&b"two"[..]));
+        assert!(E22Codec.decode(&mut line).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rn2903_sends_and_receives_fragments() {
+        let (modem, radio) = tokio::io::duplex(4096);
+        let (transmitted_tx, mut transmitted) = mpsc::unbounded_channel();
+
+        // A fake RN2903 that hears one packet once listening
+        tokio::spawn(async move {
+            let (reader, mut writer) = tokio::io::split(radio);
+            let mut lines = BufReader::new(reader).lines();
+            let mut heard = false;
+            while let Ok(Some(line)) = lines.next_line().await {
+                let reply = match line.as_str() {
+                    "sys get ver" => "RN2903 1.0.5".to_string(),
+                    "mac pause" => "4294967245".to_string(),
+                    "radio rx 0" if !heard => {
+                        heard = true;
+                        let packet = fragment(0, b"from server", 64).remove(0);
+                        format!("ok\r\nradio_rx  {}", encode_hex(&packet))
+                    }
+                    tx if tx.starts_with("radio tx ") => {
+                        transmitted_tx.send(tx["radio tx ".len()..].to_string()).unwrap();
+                        "ok\r\nradio_tx_ok".to_string()
+                    }
+                    _ => "ok".to_string(),
+                };
+                writer.write_all(format!("{}\r\n", reply).as_bytes()).await.unwrap();
+            }
+        });
+
+        let config = LoraConfig {
+            mtu: 8,
+            ..Default::default()
+        };
+        let mut stream = LoraConnector::start(modem, &config).await.unwrap();
+
+        let mut received = [0u8; 11];
+        stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"from server");
+
+        // 8-byte packets leave 5 bytes of message each
+        stream.write_all(b"hello lora").await.unwrap();
+        stream.flush().await.unwrap();
+        let packets = [transmitted.recv().await.unwrap(), transmitted.recv().await.unwrap()];
+        assert_eq!(packets[0], encode_hex(b"\x00\x00\x02hello"));
+        assert_eq!(packets[1], encode_hex(b"\x00\x01\x02 lora"));
+    }
+}
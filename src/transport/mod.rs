@@ -1,11 +1,22 @@
 pub mod bluetooth;
 pub mod bt_discovery;
 pub mod five_g;
+pub mod lora;
+pub mod noise;
+pub mod quic;
 pub mod rfcomm;
+pub mod serial;
 pub mod tcp;
 pub mod traits;
+pub mod udp;
+pub mod websocket;
 
 pub use bt_discovery::{BtDiscovery, BtDiscoveryConfig, RelayDevice, RESQTERRA_SERVICE_UUID};
+pub use lora::{LoraConfig, LoraConnector, LoraModem};
+pub use quic::{QuicConfig, QuicConnector};
 pub use rfcomm::{RfcommConfig, RfcommConnector, RfcommTransportStream, DEFAULT_RFCOMM_CHANNEL};
+pub use serial::{SerialConfig, SerialConnector};
 pub use tcp::{TcpConnector, TcpTransportStream};
-pub use traits::{TransportConnector, TransportStream};
+pub use traits::{Transport, TransportConnector, TransportStream};
+pub use udp::UdpConnector;
+pub use websocket::WebSocketConnector;
@@ -0,0 +1,19 @@
+//! Noise encryption over any transport
+//!
+//! The shared `NoiseStream` over a [`TransportStream`] is itself one, so
+//! traffic is encrypted end to end between the drone and the server and a
+//! relay node in between only forwards ciphertext. The connection manager
+//! runs the handshake once a transport connects.
+
+use crate::transport::traits::TransportStream;
+use anyhow::Result;
+use async_trait::async_trait;
+use resqterra_shared::noise::NoiseStream;
+
+#[async_trait]
+impl<S: TransportStream> TransportStream for NoiseStream<S> {
+    async fn shutdown(&mut self) -> Result<()> {
+        tokio::io::AsyncWriteExt::shutdown(self).await?;
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 //! RFCOMM transport implementation for Bluetooth connections
 
-use crate::transport::bt_discovery::{BtDiscovery, BtDiscoveryConfig, RelayDevice};
-use crate::transport::traits::{TransportConnector, TransportStream};
+use crate::transport::bt_discovery::{
+    acquire_adapter, BluezAdapters, BtDiscovery, BtDiscoveryConfig, RelayDevice,
+};
+use crate::transport::traits::{Transport, TransportConnector, TransportStream};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use bluer::rfcomm::{SocketAddr as RfcommAddr, Stream as RfcommStream};
@@ -122,8 +124,8 @@ impl RfcommConnector {
 
     /// Discover and cache a relay device
     async fn discover_relay(&mut self) -> Result<RelayDevice> {
-        let adapter = BtDiscovery::get_adapter().await?;
         let discovery = BtDiscovery::new(self.config.discovery.clone());
+        let adapter = discovery.get_adapter().await?;
         let relay = discovery.find_best_relay(&adapter).await?;
         self.cached_relay = Some(relay.clone());
         Ok(relay)
@@ -132,18 +134,18 @@ impl RfcommConnector {
 
 #[async_trait]
 impl TransportConnector for RfcommConnector {
-    type Stream = RfcommTransportStream;
-
-    async fn connect(&self) -> Result<Self::Stream> {
+    async fn connect(&self) -> Result<Box<dyn TransportStream>> {
         // Determine target address
         let target_addr = if let Some(addr) = self.config.relay_address {
+            // Fails fast (and distinctly without an adapter) when BlueZ misbehaves
+            acquire_adapter(&BluezAdapters, self.config.discovery.adapter_timeout).await?;
             addr
         } else if let Some(ref relay) = self.cached_relay {
             relay.address
         } else {
             // Need to discover
-            let adapter = BtDiscovery::get_adapter().await?;
             let discovery = BtDiscovery::new(self.config.discovery.clone());
+            let adapter = discovery.get_adapter().await?;
             let relay = discovery.find_best_relay(&adapter).await?;
             relay.address
         };
@@ -157,12 +159,16 @@ impl TransportConnector for RfcommConnector {
             .map_err(|e| anyhow!("RFCOMM connect failed: {}", e))?;
 
         println!("[BT] Connected to {}", target_addr);
-        Ok(RfcommTransportStream::new(stream, target_addr))
+        Ok(Box::new(RfcommTransportStream::new(stream, target_addr)))
     }
 
     fn name(&self) -> &'static str {
         "Bluetooth"
     }
+
+    fn transport(&self) -> Transport {
+        Transport::Bluetooth
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,120 @@
+//! Serial/UART transport for tethered bench testing
+//!
+//! Carries the same framed envelopes over a USB serial cable to a ground
+//! station, with no radio in the loop.
+
+use crate::transport::traits::{TransportConnector, TransportStream};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+/// Configuration for the serial connector
+#[derive(Debug, Clone)]
+pub struct SerialConfig {
+    /// Serial device, e.g. `/dev/ttyUSB0` or `/dev/ttyACM0`
+    pub port: String,
+    pub baud_rate: u32,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            port: "/dev/ttyUSB0".into(),
+            baud_rate: 115_200,
+        }
+    }
+}
+
+/// Serial port wrapper implementing TransportStream
+pub struct SerialTransportStream {
+    inner: SerialStream,
+}
+
+impl SerialTransportStream {
+    pub fn new(stream: SerialStream) -> Self {
+        Self { inner: stream }
+    }
+}
+
+impl AsyncRead for SerialTransportStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SerialTransportStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl TransportStream for SerialTransportStream {
+    async fn shutdown(&mut self) -> Result<()> {
+        tokio::io::AsyncWriteExt::shutdown(&mut self.inner).await?;
+        Ok(())
+    }
+}
+
+/// Connector for a ground station on a serial port
+pub struct SerialConnector {
+    config: SerialConfig,
+}
+
+impl SerialConnector {
+    pub fn new(config: SerialConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl TransportConnector for SerialConnector {
+    async fn connect(&self) -> Result<Box<dyn TransportStream>> {
+        let stream = tokio_serial::new(&self.config.port, self.config.baud_rate)
+            .open_native_async()
+            .map_err(|e| anyhow!("Can't open serial port {}: {}", self.config.port, e))?;
+        Ok(Box::new(SerialTransportStream::new(stream)))
+    }
+
+    fn name(&self) -> &'static str {
+        "Serial"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Transport;
+
+    #[tokio::test]
+    async fn test_serial_connector_reports_missing_port() {
+        let connector = SerialConnector::new(SerialConfig {
+            port: "/dev/resqterra-no-such-tty".into(),
+            ..Default::default()
+        });
+        assert_eq!(connector.transport(), Transport::Other("Serial"));
+
+        let err = connector.connect().await.err().expect("no such port");
+        assert!(err.to_string().contains("/dev/resqterra-no-such-tty"));
+    }
+}
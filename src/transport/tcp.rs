@@ -1,6 +1,6 @@
 //! TCP transport implementation for 5G and relay connections
 
-use crate::transport::traits::{TransportConnector, TransportStream};
+use crate::transport::traits::{Transport, TransportConnector, TransportStream};
 use anyhow::Result;
 use async_trait::async_trait;
 use std::io;
@@ -60,6 +60,7 @@ impl TransportStream for TcpTransportStream {
 pub struct TcpConnector {
     address: String,
     name: &'static str,
+    transport: Transport,
 }
 
 impl TcpConnector {
@@ -68,30 +69,36 @@ impl TcpConnector {
         Self {
             address,
             name: "5G",
+            transport: Transport::FiveG,
         }
     }
 
     /// Create a new TCP connector for relay transport
+    ///
+    /// Stands in for the Bluetooth relay link in development (TCP simulation).
     pub fn new_relay(address: String) -> Self {
         Self {
             address,
             name: "Relay",
+            transport: Transport::Bluetooth,
         }
     }
 }
 
 #[async_trait]
 impl TransportConnector for TcpConnector {
-    type Stream = TcpTransportStream;
-
-    async fn connect(&self) -> Result<Self::Stream> {
+    async fn connect(&self) -> Result<Box<dyn TransportStream>> {
         let stream = TcpStream::connect(&self.address).await?;
-        Ok(TcpTransportStream::new(stream))
+        Ok(Box::new(TcpTransportStream::new(stream)))
     }
 
     fn name(&self) -> &'static str {
         self.name
     }
+
+    fn transport(&self) -> Transport {
+        self.transport
+    }
 }
 
 #[cfg(test)]
@@ -102,8 +109,10 @@ mod tests {
     fn test_tcp_connector_names() {
         let five_g = TcpConnector::new_5g("127.0.0.1:8080".into());
         assert_eq!(five_g.name(), "5G");
+        assert_eq!(five_g.transport(), Transport::FiveG);
 
         let relay = TcpConnector::new_relay("127.0.0.1:9000".into());
         assert_eq!(relay.name(), "Relay");
+        assert_eq!(relay.transport(), Transport::Bluetooth);
     }
 }
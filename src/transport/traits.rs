@@ -2,8 +2,39 @@
 
 use anyhow::Result;
 use async_trait::async_trait;
+use resqterra_shared::message_link::MessageLink;
+use std::fmt;
 use tokio::io::{AsyncRead, AsyncWrite};
 
+/// Kind of link a connector opens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    FiveG,
+    Bluetooth,
+    /// Any other link (satellite modem, LoRa, serial), by connector name
+    Other(&'static str),
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::FiveG => write!(f, "5G"),
+            Transport::Bluetooth => write!(f, "Bluetooth"),
+            Transport::Other(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl From<Transport> for resqterra_shared::Transport {
+    fn from(transport: Transport) -> Self {
+        match transport {
+            Transport::FiveG => resqterra_shared::Transport::Transport5g,
+            Transport::Bluetooth => resqterra_shared::Transport::Bluetooth,
+            Transport::Other(_) => resqterra_shared::Transport::Unknown,
+        }
+    }
+}
+
 /// A transport stream that can read and write bytes
 #[async_trait]
 pub trait TransportStream: AsyncRead + AsyncWrite + Send + Unpin + 'static {
@@ -11,15 +42,37 @@ pub trait TransportStream: AsyncRead + AsyncWrite + Send + Unpin + 'static {
     async fn shutdown(&mut self) -> Result<()>;
 }
 
+#[async_trait]
+impl TransportStream for Box<dyn TransportStream> {
+    async fn shutdown(&mut self) -> Result<()> {
+        (**self).shutdown().await
+    }
+}
+
+/// UDP and QUIC links
+#[async_trait]
+impl TransportStream for MessageLink {
+    async fn shutdown(&mut self) -> Result<()> {
+        tokio::io::AsyncWriteExt::shutdown(self).await?;
+        Ok(())
+    }
+}
+
 /// Factory for creating transport connections
+///
+/// The connection manager takes a list of these in order of preference, so
+/// new links plug in without touching it.
 #[async_trait]
 pub trait TransportConnector: Send + Sync {
-    /// The stream type this connector produces
-    type Stream: TransportStream;
-
     /// Attempt to connect, returning a stream on success
-    async fn connect(&self) -> Result<Self::Stream>;
+    async fn connect(&self) -> Result<Box<dyn TransportStream>>;
 
     /// Human-readable name for this transport
     fn name(&self) -> &'static str;
+
+    /// The link this connector opens, which decides how it is scored and
+    /// reported to the server
+    fn transport(&self) -> Transport {
+        Transport::Other(self.name())
+    }
 }
@@ -0,0 +1,59 @@
+//! UDP transport for 5G, without TCP's head-of-line blocking
+//!
+//! Telemetry frames go fire-and-forget; everything else is retransmitted
+//! selectively (see `resqterra_shared::udp`).
+
+use crate::transport::traits::{Transport, TransportConnector, TransportStream};
+use anyhow::Result;
+use async_trait::async_trait;
+use resqterra_shared::udp;
+
+/// UDP connector for the 5G server
+pub struct UdpConnector {
+    address: String,
+}
+
+impl UdpConnector {
+    pub fn new_5g(address: String) -> Self {
+        Self { address }
+    }
+}
+
+#[async_trait]
+impl TransportConnector for UdpConnector {
+    async fn connect(&self) -> Result<Box<dyn TransportStream>> {
+        Ok(Box::new(udp::connect(&self.address).await?))
+    }
+
+    fn name(&self) -> &'static str {
+        "5G/UDP"
+    }
+
+    fn transport(&self) -> Transport {
+        Transport::FiveG
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::udp::UdpListener;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_udp_connector_reaches_listener() {
+        let mut listener = UdpListener::bind("127.0.0.1:0").await.unwrap();
+        let connector = UdpConnector::new_5g(listener.local_addr().to_string());
+        assert_eq!(connector.transport(), Transport::FiveG);
+
+        let (connected, accepted) = tokio::join!(connector.connect(), listener.accept());
+        let mut stream = connected.unwrap();
+        let (mut server, _) = accepted.unwrap();
+
+        stream.write_all(b"hello").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut received = [0; 5];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"hello");
+    }
+}
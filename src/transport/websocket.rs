@@ -0,0 +1,67 @@
+//! WebSocket transport for 5G, for networks that only pass HTTP
+//!
+//! Each envelope travels bare in one binary message (see
+//! `resqterra_shared::websocket`), so it can't carry Noise traffic.
+
+use crate::transport::traits::{Transport, TransportConnector, TransportStream};
+use anyhow::Result;
+use async_trait::async_trait;
+use resqterra_shared::websocket;
+
+/// WebSocket connector for the 5G server
+pub struct WebSocketConnector {
+    url: String,
+}
+
+impl WebSocketConnector {
+    /// `url` is the server's WebSocket endpoint, e.g. `ws://127.0.0.1:8081/`
+    pub fn new_5g(url: String) -> Self {
+        Self { url }
+    }
+}
+
+#[async_trait]
+impl TransportConnector for WebSocketConnector {
+    async fn connect(&self) -> Result<Box<dyn TransportStream>> {
+        Ok(Box::new(websocket::connect(&self.url).await?))
+    }
+
+    fn name(&self) -> &'static str {
+        "5G/WebSocket"
+    }
+
+    fn transport(&self) -> Transport {
+        Transport::FiveG
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resqterra_shared::websocket::WebSocketListener;
+    use resqterra_shared::{codec, Envelope, Header, MessageType};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_websocket_connector_reaches_listener() {
+        let mut listener = WebSocketListener::bind("127.0.0.1:0").await.unwrap();
+        let connector = WebSocketConnector::new_5g(format!("ws://{}/", listener.local_addr()));
+        assert_eq!(connector.transport(), Transport::FiveG);
+
+        let (connected, accepted) = tokio::join!(connector.connect(), listener.accept());
+        let mut stream = connected.unwrap();
+        let (mut server, _) = accepted.unwrap();
+
+        let frame = codec::encode(&Envelope {
+            header: Some(Header::new("edge-001", MessageType::MsgHeartbeat, 1)),
+            payload: None,
+            signature: Vec::new(),
+        })
+        .unwrap();
+        stream.write_all(&frame).await.unwrap();
+        stream.flush().await.unwrap();
+        let mut received = vec![0; frame.len()];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, frame);
+    }
+}
@@ -0,0 +1,278 @@
+//! Live video streaming
+//!
+//! Video never goes over the command link. A pipeline command
+//! (`RESQTERRA_VIDEO_PIPELINE`, e.g. GStreamer) encodes the camera and
+//! publishes it over RTSP to a media server on the drone such as MediaMTX,
+//! which serves viewers over RTSP (port 8554) and WebRTC/WHEP (port 8889).
+//! `CMD_VIDEO_START` negotiates the stream: what the request leaves open is
+//! filled in with defaults, the pipeline is (re)started, and the stream goes
+//! back in the ACK. Its health is reported in telemetry until
+//! `CMD_VIDEO_STOP`.
+
+use anyhow::{anyhow, Result};
+use resqterra_shared::{now_ms, VideoProtocol, VideoStart, VideoStream, VideoStreamState};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+
+/// Codecs pipelines are expected to handle
+pub const VIDEO_CODECS: [&str; 2] = ["h264", "h265"];
+
+/// Stream settings used where the request leaves them open
+const DEFAULT_CAMERA: &str = "rgb";
+const DEFAULT_RESOLUTION: (u32, u32) = (1280, 720);
+const DEFAULT_FRAMERATE: u32 = 30;
+const DEFAULT_BITRATE_KBPS: u32 = 2500;
+
+/// Largest stream accepted
+const MAX_RESOLUTION: (u32, u32) = (3840, 2160);
+const MAX_FRAMERATE: u32 = 120;
+
+/// How long the pipeline must stay up before the stream counts as live
+const LIVE_AFTER: Duration = Duration::from_secs(2);
+
+/// Ports of the drone's media server
+const RTSP_PORT: u16 = 8554;
+const WEBRTC_PORT: u16 = 8889;
+
+/// How to run the video pipeline
+#[derive(Debug, Clone)]
+pub struct VideoConfig {
+    /// Shell command publishing the stream to `{publish_url}`; `{camera}`,
+    /// `{codec}`, `{width}`, `{height}`, `{framerate}` and `{bitrate_kbps}`
+    /// are replaced with the negotiated values
+    pub pipeline: String,
+    /// Address viewers reach the drone's media server at
+    pub host: String,
+}
+
+impl VideoConfig {
+    /// Read `RESQTERRA_VIDEO_PIPELINE` and `RESQTERRA_VIDEO_HOST`; None
+    /// without a pipeline (or with `off`)
+    pub fn from_env() -> Option<Self> {
+        let pipeline = std::env::var("RESQTERRA_VIDEO_PIPELINE").ok()?;
+        if pipeline.is_empty() || pipeline.eq_ignore_ascii_case("off") {
+            return None;
+        }
+        let host = std::env::var("RESQTERRA_VIDEO_HOST").unwrap_or_else(|_| "127.0.0.1".into());
+        Some(Self { pipeline, host })
+    }
+}
+
+/// The running stream and its pipeline
+#[derive(Debug)]
+struct Running {
+    stream: VideoStream,
+    /// None once the pipeline exited
+    pipeline: Option<Child>,
+    started: Instant,
+}
+
+/// Negotiates the video stream and supervises its pipeline
+#[derive(Debug)]
+pub struct VideoStreamer {
+    device_id: String,
+    config: VideoConfig,
+    running: Mutex<Option<Running>>,
+}
+
+impl VideoStreamer {
+    pub fn new(device_id: String, config: VideoConfig) -> Self {
+        Self {
+            device_id,
+            config,
+            running: Mutex::new(None),
+        }
+    }
+
+    /// The stream this drone would run for `request`, or why it can't
+    pub fn negotiate(&self, request: &VideoStart) -> Result<VideoStream> {
+        let camera = match request.camera.as_str() {
+            "" => DEFAULT_CAMERA.to_string(),
+            camera => camera.to_ascii_lowercase(),
+        };
+        // The camera name ends up in a shell command
+        if !camera.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(anyhow!("Invalid camera name {:?}", request.camera));
+        }
+        let codec = match request.codec.as_str() {
+            "" => VIDEO_CODECS[0].to_string(),
+            codec => codec.to_ascii_lowercase(),
+        };
+        if !VIDEO_CODECS.contains(&codec.as_str()) {
+            return Err(anyhow!("Unsupported codec {:?} (supported: h264, h265)", request.codec));
+        }
+        let (width, height) = match (request.width, request.height) {
+            (0, 0) => DEFAULT_RESOLUTION,
+            (0, _) | (_, 0) => return Err(anyhow!("Set both width and height, or neither")),
+            (width, height) if width > MAX_RESOLUTION.0 || height > MAX_RESOLUTION.1 => {
+                return Err(anyhow!("Resolution {}x{} above 3840x2160", width, height));
+            }
+            resolution => resolution,
+        };
+        let framerate = match request.framerate {
+            0 => DEFAULT_FRAMERATE,
+            fps => fps.min(MAX_FRAMERATE),
+        };
+        let bitrate_kbps = match request.bitrate_kbps {
+            0 => DEFAULT_BITRATE_KBPS,
+            kbps => kbps,
+        };
+
+        let stream_id = self.device_id.clone();
+        let host = &self.config.host;
+        let url = match request.protocol() {
+            VideoProtocol::VideoRtsp => format!("rtsp://{}:{}/{}", host, RTSP_PORT, stream_id),
+            VideoProtocol::VideoWebrtc => {
+                format!("http://{}:{}/{}/whep", host, WEBRTC_PORT, stream_id)
+            }
+        };
+        Ok(VideoStream {
+            stream_id,
+            protocol: request.protocol,
+            url,
+            camera,
+            codec,
+            width,
+            height,
+            framerate,
+            bitrate_kbps,
+            state: VideoStreamState::VideoStarting.into(),
+            started_at_ms: 0,
+            error: String::new(),
+        })
+    }
+
+    /// Run the pipeline for `stream`, replacing any running one
+    pub async fn start(&self, mut stream: VideoStream) -> Result<VideoStream> {
+        let mut running = self.running.lock().await;
+        if let Some(previous) = running.take() {
+            stop_pipeline(previous).await;
+        }
+
+        let publish_url = format!("rtsp://127.0.0.1:{}/{}", RTSP_PORT, stream.stream_id);
+        let command = self
+            .config
+            .pipeline
+            .replace("{publish_url}", &publish_url)
+            .replace("{camera}", &stream.camera)
+            .replace("{codec}", &stream.codec)
+            .replace("{width}", &stream.width.to_string())
+            .replace("{height}", &stream.height.to_string())
+            .replace("{framerate}", &stream.framerate.to_string())
+            .replace("{bitrate_kbps}", &stream.bitrate_kbps.to_string());
+        let pipeline = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("Can't start video pipeline: {}", e))?;
+
+        stream.started_at_ms = now_ms();
+        *running = Some(Running {
+            stream: stream.clone(),
+            pipeline: Some(pipeline),
+            started: Instant::now(),
+        });
+        Ok(stream)
+    }
+
+    /// Stop the stream; None when none was running
+    pub async fn stop(&self) -> Option<VideoStream> {
+        let running = self.running.lock().await.take()?;
+        let stream = running.stream.clone();
+        stop_pipeline(running).await;
+        Some(stream)
+    }
+
+    /// The stream with its current health, for telemetry
+    pub async fn health(&self) -> Option<VideoStream> {
+        let mut running = self.running.lock().await;
+        let running = running.as_mut()?;
+        if let Some(pipeline) = &mut running.pipeline {
+            match pipeline.try_wait() {
+                Ok(None) if running.started.elapsed() >= LIVE_AFTER => {
+                    running.stream.set_state(VideoStreamState::VideoLive);
+                }
+                Ok(None) => {}
+                Ok(Some(status)) => {
+                    eprintln!("[VIDEO] Pipeline exited ({})", status);
+                    running.stream.set_state(VideoStreamState::VideoFailed);
+                    running.stream.error = format!("Pipeline exited ({})", status);
+                    running.pipeline = None;
+                }
+                Err(e) => {
+                    running.stream.set_state(VideoStreamState::VideoFailed);
+                    running.stream.error = e.to_string();
+                    running.pipeline = None;
+                }
+            }
+        }
+        Some(running.stream.clone())
+    }
+}
+
+async fn stop_pipeline(running: Running) {
+    if let Some(mut pipeline) = running.pipeline {
+        if let Err(e) = pipeline.kill().await {
+            eprintln!("[VIDEO] Can't stop pipeline: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn streamer(pipeline: &str) -> VideoStreamer {
+        let config = VideoConfig {
+            pipeline: pipeline.into(),
+            host: "10.0.0.7".into(),
+        };
+        VideoStreamer::new("edge-001".into(), config)
+    }
+
+    #[tokio::test]
+    async fn test_stream_negotiated_started_and_stopped() {
+        let video = streamer("sleep 30");
+        let request = VideoStart {
+            protocol: VideoProtocol::VideoWebrtc.into(),
+            codec: "H265".into(),
+            ..Default::default()
+        };
+        let stream = video.negotiate(&request).unwrap();
+        assert_eq!(stream.url, "http://10.0.0.7:8889/edge-001/whep");
+        assert_eq!((stream.codec.as_str(), stream.width, stream.height), ("h265", 1280, 720));
+        assert_eq!(stream.camera, "rgb");
+
+        let started = video.start(stream).await.unwrap();
+        assert!(started.started_at_ms > 0);
+        assert_eq!(video.health().await.unwrap().state(), VideoStreamState::VideoStarting);
+        assert_eq!(video.stop().await.unwrap().stream_id, "edge-001");
+        assert!(video.health().await.is_none());
+        assert!(video.stop().await.is_none());
+
+        for bad in [
+            VideoStart { codec: "vp8".into(), ..Default::default() },
+            VideoStart { width: 640, ..Default::default() },
+            VideoStart { width: 7680, height: 4320, ..Default::default() },
+            VideoStart { camera: "rgb; reboot".into(), ..Default::default() },
+        ] {
+            assert!(video.negotiate(&bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_exit_reported() {
+        let video = streamer("exit 3");
+        let stream = video.negotiate(&VideoStart::default()).unwrap();
+        assert!(stream.url.starts_with("rtsp://10.0.0.7:8554/"));
+        video.start(stream).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let health = video.health().await.unwrap();
+        assert_eq!(health.state(), VideoStreamState::VideoFailed);
+        assert!(health.error.contains('3'), "{}", health.error);
+    }
+}